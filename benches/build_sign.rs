@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types as json_types;
+use ckb_mock_tx_types::MockInput;
+use ckb_sdk::{
+    constants::{MULTISIG_TYPE_HASH, ONE_CKB, SIGHASH_TYPE_HASH},
+    test_util::{Context, LiveCellsContext},
+    traits::{CellCollector, CellQueryOptions, SecpCkbRawKeySigner},
+    tx_builder::{balance_tx_capacity, CapacityBalancer},
+    unlock::{
+        generate_message, MultisigConfig, ScriptSigner, SecpMultisigScriptSigner,
+        SecpSighashScriptSigner, SighashSkeletonSigner, WitnessSkeleton,
+    },
+    ScriptGroup, ScriptGroupType,
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockView, ScriptHashType, TransactionBuilder},
+    packed::{CellInput, CellOutput, OutPoint, Script, WitnessArgs},
+    prelude::*,
+    H160,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+const GENESIS_JSON: &str = include_str!("../src/test-data/genesis_block.json");
+
+fn out_point(idx: u32) -> OutPoint {
+    OutPoint::new(blake2b_256(idx.to_le_bytes()).pack(), 0)
+}
+
+fn secret_key(idx: u8) -> secp256k1::SecretKey {
+    secp256k1::SecretKey::from_slice(&[idx + 1; 32]).unwrap()
+}
+
+fn sighash_script(arg: &[u8]) -> Script {
+    Script::new_builder()
+        .code_hash(SIGHASH_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(arg.to_vec()).pack())
+        .build()
+}
+
+fn sighash_placeholder() -> WitnessArgs {
+    WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build()
+}
+
+/// generate_message over transactions with many large witnesses
+fn bench_generate_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_message");
+    for (input_count, witness_size) in [(100u32, 1024usize), (1000, 1024), (100, 64 * 1024)] {
+        let witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .input_type(Some(Bytes::from(vec![1u8; witness_size])).pack())
+            .build();
+        let tx = TransactionBuilder::default()
+            .inputs((0..input_count).map(|idx| CellInput::new(out_point(idx), 0)))
+            .witnesses((0..input_count).map(|_| witness.as_bytes().pack()))
+            .build();
+        let mut script_group = ScriptGroup::new(&Script::default(), ScriptGroupType::Lock);
+        script_group.input_indices = (0..input_count as usize).collect();
+        let id = format!("{}x{}", input_count, witness_size);
+        group.bench_function(BenchmarkId::from_parameter(id), |b| {
+            b.iter(|| generate_message(&tx, &script_group, Bytes::from(vec![0u8; 65])).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Sign a 15-of-20 multisig transaction with all the 15 keys
+fn bench_multisig_sign(c: &mut Criterion) {
+    let keys = (0..20).map(secret_key).collect::<Vec<_>>();
+    let addresses = keys
+        .iter()
+        .map(|key| {
+            let pubkey = secp256k1::PublicKey::from_secret_key(&ckb_sdk::SECP256K1, key);
+            H160::from_slice(&blake2b_256(pubkey.serialize())[0..20]).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let config = MultisigConfig::new_with(addresses, 0, 15).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys[0..15].to_vec());
+    let script_signer = SecpMultisigScriptSigner::new(Box::new(signer), config.clone());
+
+    let lock_script = Script::new_builder()
+        .code_hash(MULTISIG_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(config.hash160().as_bytes().to_vec()).pack())
+        .build();
+    let input_count = 10;
+    let tx = TransactionBuilder::default()
+        .inputs((0..input_count).map(|idx| CellInput::new(out_point(idx), 0)))
+        .witnesses((0..input_count).map(|_| config.placeholder_witness().as_bytes().pack()))
+        .build();
+    let mut script_group = ScriptGroup::new(&lock_script, ScriptGroupType::Lock);
+    script_group.input_indices = (0..input_count as usize).collect();
+
+    c.bench_function("multisig_sign_15_of_20", |b| {
+        b.iter(|| script_signer.sign_tx(&tx, &script_group).unwrap())
+    });
+}
+
+/// Sign a 2 inputs sighash transaction, with the sighash signer and with a
+/// precomputed witness skeleton
+fn bench_sighash_sign(c: &mut Criterion) {
+    let key = secret_key(0);
+    let skeleton_signer = SighashSkeletonSigner::new(&key, WitnessSkeleton::new(None, None));
+    let script_signer =
+        SecpSighashScriptSigner::new(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+            key,
+        ])));
+    let lock_script = sighash_script(skeleton_signer.lock_arg().as_bytes());
+    let output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(sighash_script(&[2u8; 20]))
+        .build();
+    let tx = TransactionBuilder::default()
+        .inputs((0..2).map(|idx| CellInput::new(out_point(idx), 0)))
+        .outputs(vec![output.clone(), output])
+        .outputs_data(vec![Bytes::new().pack(), Bytes::new().pack()])
+        .witnesses(vec![
+            sighash_placeholder().as_bytes().pack(),
+            Bytes::new().pack(),
+        ])
+        .build();
+    let mut script_group = ScriptGroup::new(&lock_script, ScriptGroupType::Lock);
+    script_group.input_indices = vec![0, 1];
+
+    let mut group = c.benchmark_group("sighash_sign");
+    group.bench_function("script_signer", |b| {
+        b.iter(|| script_signer.sign_tx(&tx, &script_group).unwrap())
+    });
+    group.bench_function("witness_skeleton", |b| {
+        b.iter(|| skeleton_signer.sign(&tx, &script_group).unwrap())
+    });
+    group.finish();
+}
+
+/// Collect cells from a 100k live cells fixture
+fn bench_cell_collection(c: &mut Criterion) {
+    let cell_count = 100_000u32;
+    let target = sighash_script(&[1u8; 20]);
+    let other = sighash_script(&[2u8; 20]);
+    let inputs = (0..cell_count)
+        .map(|idx| {
+            // one target cell every 1000 cells
+            let lock = if idx % 1000 == 999 {
+                target.clone()
+            } else {
+                other.clone()
+            };
+            let output = CellOutput::new_builder()
+                .capacity((100 * ONE_CKB).pack())
+                .lock(lock)
+                .build();
+            MockInput {
+                input: CellInput::new(out_point(idx), 0),
+                output,
+                data: Bytes::new(),
+                header: None,
+            }
+        })
+        .collect::<Vec<_>>();
+    let collector = LiveCellsContext {
+        inputs,
+        header_deps: Vec::new(),
+        used_inputs: HashSet::new(),
+    };
+
+    let mut group = c.benchmark_group("collect_live_cells_100k");
+    for capacity in [ONE_CKB, 1000 * ONE_CKB, u64::MAX] {
+        let mut query = CellQueryOptions::new_lock(target.clone());
+        query.min_total_capacity = capacity;
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity / ONE_CKB),
+            &query,
+            |b, query| {
+                b.iter_batched(
+                    || collector.clone(),
+                    |mut collector| collector.collect_live_cells(query, true).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Balance a transaction which needs all the `cell_count` provider cells as
+/// inputs, the number of balancing iterations grows with the inputs added.
+fn bench_balancing(c: &mut Criterion) {
+    let genesis_block: json_types::BlockView = serde_json::from_str(GENESIS_JSON).unwrap();
+    let genesis_block: BlockView = genesis_block.into();
+    let sender = sighash_script(&[1u8; 20]);
+    let receiver = sighash_script(&[2u8; 20]);
+
+    let mut group = c.benchmark_group("balance_tx_capacity");
+    for cell_count in [1u32, 10, 100] {
+        let mut ctx = Context::new(&genesis_block, Vec::new());
+        for idx in 0..cell_count {
+            ctx.add_simple_live_cell(out_point(idx), sender.clone(), Some(1000 * ONE_CKB));
+        }
+        // leave enough capacity for the change cell
+        let output = CellOutput::new_builder()
+            .capacity((u64::from(cell_count) * 1000 * ONE_CKB - 100 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build();
+        let tx = TransactionBuilder::default()
+            .output(output)
+            .output_data(Bytes::new().pack())
+            .build();
+        let balancer = CapacityBalancer::new_simple(sender.clone(), sighash_placeholder(), 1000);
+        group.bench_with_input(BenchmarkId::from_parameter(cell_count), &tx, |b, tx| {
+            b.iter_batched(
+                || ctx.to_live_cells_context(),
+                |mut collector| {
+                    balance_tx_capacity(tx, &balancer, &mut collector, &ctx, &ctx, &ctx).unwrap()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_generate_message,
+    bench_multisig_sign,
+    bench_sighash_sign,
+    bench_cell_collection,
+    bench_balancing
+);
+criterion_main!(benches);
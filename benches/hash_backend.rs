@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use ckb_sdk::{
+    hash::{Blake2bBackend, HashBackend},
+    unlock::generate_message_with_backend,
+    ScriptGroup, ScriptGroupType,
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionBuilder,
+    packed::{CellInput, OutPoint, Script, WitnessArgs},
+    prelude::*,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn backends() -> Vec<Arc<dyn HashBackend>> {
+    #[allow(unused_mut)]
+    let mut backends: Vec<Arc<dyn HashBackend>> = vec![Arc::new(Blake2bBackend::default())];
+    #[cfg(feature = "blake2b-simd")]
+    backends.push(Arc::new(ckb_sdk::hash::Blake2bSimdBackend::default()));
+    backends
+}
+
+fn bench_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash");
+    for size in [32usize, 1024, 64 * 1024] {
+        let data = vec![7u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        for backend in backends() {
+            group.bench_with_input(BenchmarkId::new(backend.name(), size), &data, |b, data| {
+                b.iter(|| backend.hash(data))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_sighash_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sighash_message");
+    let input_count = 1000;
+    let witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let tx = TransactionBuilder::default()
+        .inputs(
+            (0..input_count).map(|idx| CellInput::new(OutPoint::new(Default::default(), idx), 0)),
+        )
+        .witnesses((0..input_count).map(|_| witness.as_bytes().pack()))
+        .build();
+    let mut script_group = ScriptGroup::new(&Script::default(), ScriptGroupType::Lock);
+    script_group.input_indices = (0..input_count as usize).collect();
+
+    for backend in backends() {
+        group.bench_function(BenchmarkId::new(backend.name(), input_count), |b| {
+            b.iter(|| {
+                generate_message_with_backend(
+                    &tx,
+                    &script_group,
+                    Bytes::from(vec![0u8; 65]),
+                    backend.as_ref(),
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_hash, bench_sighash_message);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+use ckb_sdk::test_vectors::SighashVectorSet;
+use clap::{Parser, Subcommand};
+use std::{error::Error as StdErr, fs, path::PathBuf};
+
+/// Export the sighash message test vectors, or check this SDK against the
+/// vectors exported by another one
+/// # Example:
+///     ./target/debug/examples/sighash_test_vectors export --output vectors.json
+///
+///     ./target/debug/examples/sighash_test_vectors check --input vectors.json
+///
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[clap(propagate_version = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Write the vectors of this SDK
+    Export {
+        /// The output file (.json), print to stdout if not set
+        #[clap(long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Check the vectors of another SDK
+    Check {
+        /// The vectors file (.json)
+        #[clap(long, value_name = "PATH")]
+        input: PathBuf,
+    },
+}
+
+fn main() -> Result<(), Box<dyn StdErr>> {
+    let cli = Cli::parse();
+    match cli.command {
+        Commands::Export { output } => {
+            let json = SighashVectorSet::generate()?.to_json()?;
+            match output {
+                Some(path) => fs::write(path, json)?,
+                None => println!("{}", json),
+            }
+        }
+        Commands::Check { input } => {
+            let set = SighashVectorSet::from_json(&fs::read_to_string(input)?)?;
+            set.check()?;
+            println!(">>> {} vectors passed <<<", set.vectors.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use ckb_sdk::Address;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        if let Ok(address) = Address::from_str(input) {
+            let _ = address.to_string();
+        }
+    }
+});
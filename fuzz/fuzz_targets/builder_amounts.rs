@@ -0,0 +1,358 @@
+#![no_main]
+
+use ckb_sdk::{
+    cell_snapshot::SnapshotCellCollector,
+    traits::{
+        dummy_impls::{DummyCellCollector, DummyHeaderDepResolver},
+        CellDepResolver, LiveCell, OffchainTransactionDependencyProvider,
+    },
+    tx_builder::{
+        cheque::ChequeClaimBuilder,
+        transfer::CapacityTransferBuilder,
+        tx_fee,
+        udt::{UdtTargetReceiver, UdtTransferBuilder},
+        TransactionFeeError, TransferAction, TxBuilder, TxBuilderError,
+    },
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+    H256,
+};
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryInto;
+
+/// Resolves any script to the same cell dep
+struct AnyCellDepResolver;
+
+impl CellDepResolver for AnyCellDepResolver {
+    fn resolve(&self, _script: &Script) -> Option<CellDep> {
+        Some(CellDep::default())
+    }
+}
+
+/// Reads the values of a case, biased to the bounds of the integer types
+struct Values<'a>(&'a [u8]);
+
+impl Values<'_> {
+    fn take(&mut self, len: usize) -> [u8; 16] {
+        let len = len.min(self.0.len());
+        let mut bytes = [0u8; 16];
+        bytes[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u64(&mut self) -> u64 {
+        let tag = self.u8();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.take(8)[..8]);
+        let raw = u64::from_le_bytes(bytes);
+        match tag % 4 {
+            0 => raw,
+            1 => u64::MAX - raw % 1024,
+            2 => u64::MAX / 2 + raw % 1024,
+            _ => raw % 1024,
+        }
+    }
+
+    fn u128(&mut self) -> u128 {
+        let tag = self.u8();
+        let raw = u128::from_le_bytes(self.take(16));
+        match tag % 4 {
+            0 => raw,
+            1 => u128::MAX - raw % 1024,
+            2 => u128::MAX / 2 + raw % 1024,
+            _ => raw % 1024,
+        }
+    }
+}
+
+/// The exact sum of `values`, as the number of u128 overflows and the rest
+fn wide_sum<T: Into<u128>>(values: impl IntoIterator<Item = T>) -> (u32, u128) {
+    values.into_iter().fold((0, 0), |(carries, total), value| {
+        let (total, overflow) = total.overflowing_add(value.into());
+        (carries + overflow as u32, total)
+    })
+}
+
+fn checked_sum<T: Into<u128>>(values: impl IntoIterator<Item = T>) -> Option<u128> {
+    match wide_sum(values) {
+        (0, total) => Some(total),
+        _ => None,
+    }
+}
+
+fn lock_script(arg: u8) -> Script {
+    Script::new_builder()
+        .code_hash(H256([1; 32]).pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![arg; 20]).pack())
+        .build()
+}
+
+fn udt_script() -> Script {
+    Script::new_builder()
+        .code_hash(H256([2; 32]).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9; 32]).pack())
+        .build()
+}
+
+fn cheque_script(sender: &Script, receiver: &Script) -> Script {
+    let mut args = vec![0u8; 40];
+    args[0..20].copy_from_slice(&receiver.calc_script_hash().as_slice()[0..20]);
+    args[20..40].copy_from_slice(&sender.calc_script_hash().as_slice()[0..20]);
+    Script::new_builder()
+        .code_hash(H256([3; 32]).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(args).pack())
+        .build()
+}
+
+struct Cells {
+    provider: OffchainTransactionDependencyProvider,
+    live_cells: Vec<LiveCell>,
+}
+
+impl Cells {
+    fn new() -> Cells {
+        Cells {
+            provider: OffchainTransactionDependencyProvider::default(),
+            live_cells: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, output: CellOutput, data: Bytes) -> OutPoint {
+        let tx_hash = H256([self.live_cells.len() as u8 + 1; 32]);
+        let out_point = OutPoint::new(tx_hash.pack(), 0);
+        self.provider
+            .cells
+            .insert((tx_hash, 0), (output.clone(), data.clone()));
+        self.live_cells.push(LiveCell {
+            output,
+            output_data: data,
+            out_point: out_point.clone(),
+            block_number: 0,
+            tx_index: 1,
+        });
+        out_point
+    }
+
+    fn udt_amount(&self, out_point: &OutPoint) -> u128 {
+        let tx_hash: H256 = out_point.tx_hash().unpack();
+        let data = &self.provider.cells[&(tx_hash, 0)].1;
+        u128::from_le_bytes(data[0..16].try_into().unwrap())
+    }
+}
+
+fn udt_cell(lock: &Script, capacity: u64) -> CellOutput {
+    CellOutput::new_builder()
+        .capacity(capacity.pack())
+        .lock(lock.clone())
+        .type_(Some(udt_script()).pack())
+        .build()
+}
+
+fn amount_data(amount: u128) -> Bytes {
+    Bytes::from(amount.to_le_bytes().to_vec())
+}
+
+fn output_amounts(tx: &TransactionView) -> Vec<u128> {
+    tx.outputs_data()
+        .into_iter()
+        .filter(|data| data.len() >= 16)
+        .map(|data| u128::from_le_bytes(data.raw_data()[0..16].try_into().unwrap()))
+        .collect()
+}
+
+fn fuzz_cheque_claim(values: &mut Values) {
+    let sender = lock_script(1);
+    let receiver = lock_script(2);
+    let cheque_lock = cheque_script(&sender, &receiver);
+    let mut cells = Cells::new();
+    let receiver_amount = values.u128();
+    let receiver_out_point = cells.add(
+        udt_cell(&receiver, values.u64()),
+        amount_data(receiver_amount),
+    );
+    let mut capacities = Vec::new();
+    let mut amounts = Vec::new();
+    let mut inputs = Vec::new();
+    for _ in 0..values.u8() % 3 + 1 {
+        let (capacity, amount) = (values.u64(), values.u128());
+        let out_point = cells.add(udt_cell(&cheque_lock, capacity), amount_data(amount));
+        capacities.push(capacity);
+        amounts.push(amount);
+        inputs.push(CellInput::new(out_point, 0));
+    }
+
+    let builder = ChequeClaimBuilder::new(inputs, CellInput::new(receiver_out_point, 0), sender);
+    let mut cell_collector = SnapshotCellCollector::new(cells.live_cells.clone(), 0);
+    let result = builder.build_base(
+        &mut cell_collector,
+        &AnyCellDepResolver,
+        &DummyHeaderDepResolver,
+        &cells.provider,
+    );
+    let total_capacity = checked_sum(capacities).filter(|total| *total <= u64::MAX as u128);
+    let total_amount = checked_sum(amounts.into_iter().chain(Some(receiver_amount)));
+    match result {
+        Ok(tx) => {
+            let sender_capacity: u64 = tx.outputs().get(1).unwrap().capacity().unpack();
+            assert_eq!(Some(sender_capacity as u128), total_capacity);
+            assert_eq!(Some(output_amounts(&tx)[0]), total_amount);
+        }
+        Err(TxBuilderError::AmountOverflow(a, b)) => {
+            assert!(a.checked_add(b).is_none());
+            assert!(total_amount.is_none());
+        }
+        Err(TxBuilderError::Capacity(_)) => assert!(total_capacity.is_none()),
+        Err(err) => panic!("unexpected cheque claim error: {}", err),
+    }
+}
+
+fn fuzz_udt_transfer(values: &mut Values) {
+    let sender = lock_script(1);
+    let receiver = lock_script(2);
+    let flags = values.u8();
+    let mut cells = Cells::new();
+    for _ in 0..values.u8() % 4 + 1 {
+        cells.add(udt_cell(&sender, values.u64()), amount_data(values.u128()));
+    }
+    let mut receivers = Vec::new();
+    for _ in 0..values.u8() % 3 + 1 {
+        let amount = values.u128();
+        receivers.push(UdtTargetReceiver::new(
+            TransferAction::Create,
+            receiver.clone(),
+            amount,
+        ));
+    }
+    if flags & 1 != 0 {
+        cells.add(
+            udt_cell(&receiver, values.u64()),
+            amount_data(values.u128()),
+        );
+        let amount = values.u128();
+        receivers.push(UdtTargetReceiver::new(
+            TransferAction::Update,
+            receiver.clone(),
+            amount,
+        ));
+    }
+
+    let mut builder = UdtTransferBuilder {
+        type_script: udt_script(),
+        sender: sender.clone(),
+        receivers,
+        change_lock: None,
+    };
+    if flags & 2 != 0 {
+        builder = builder.with_udt_change(sender);
+    }
+    let mut cell_collector = SnapshotCellCollector::new(cells.live_cells.clone(), 0);
+    let result = builder.build_base(
+        &mut cell_collector,
+        &AnyCellDepResolver,
+        &DummyHeaderDepResolver,
+        &cells.provider,
+    );
+    match result {
+        Ok(tx) => {
+            // the udt is neither minted nor burned
+            let input_total = wide_sum(
+                tx.inputs()
+                    .into_iter()
+                    .map(|input| cells.udt_amount(&input.previous_output())),
+            );
+            assert_eq!(input_total, wide_sum(output_amounts(&tx)));
+        }
+        Err(TxBuilderError::AmountOverflow(a, b)) => assert!(a.checked_add(b).is_none()),
+        // not enough udt in the sender cells
+        Err(TxBuilderError::Other(_)) => {}
+        Err(err) => panic!("unexpected udt transfer error: {}", err),
+    }
+}
+
+fn fuzz_capacity_transfer(values: &mut Values) {
+    let flags = values.u8();
+    let outputs = (0..values.u8() % 3 + 1)
+        .map(|_| {
+            let output = CellOutput::new_builder()
+                .capacity(values.u64().pack())
+                .lock(lock_script(2))
+                .build();
+            (output, Bytes::new())
+        })
+        .collect::<Vec<_>>();
+    let mut builder = CapacityTransferBuilder::new(outputs);
+    if flags & 1 != 0 {
+        builder = builder.with_min_capacity_top_up();
+    }
+    let base_tx = builder
+        .build_base(
+            &mut DummyCellCollector,
+            &AnyCellDepResolver,
+            &DummyHeaderDepResolver,
+            &OffchainTransactionDependencyProvider::default(),
+        )
+        .expect("build capacity transfer");
+
+    let mut cells = Cells::new();
+    let mut input_capacities = Vec::new();
+    for _ in 0..values.u8() % 3 + 1 {
+        let capacity = values.u64();
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock_script(1))
+            .build();
+        cells.add(output, Bytes::new());
+        input_capacities.push(capacity);
+    }
+    let tx = base_tx
+        .as_advanced_builder()
+        .inputs(
+            cells
+                .live_cells
+                .iter()
+                .map(|cell| CellInput::new(cell.out_point.clone(), 0)),
+        )
+        .build();
+    let output_capacities = tx
+        .outputs()
+        .into_iter()
+        .map(|output| -> u64 { output.capacity().unpack() })
+        .collect::<Vec<_>>();
+    let limit = |total: Option<u128>| total.filter(|total| *total <= u64::MAX as u128);
+    let input_total = limit(checked_sum(input_capacities));
+    let output_total = limit(checked_sum(output_capacities));
+    match tx_fee(tx, &cells.provider, &DummyHeaderDepResolver) {
+        Ok(fee) => {
+            assert_eq!(Some(fee as u128 + output_total.unwrap()), input_total);
+        }
+        Err(TransactionFeeError::CapacityError(_)) => {
+            assert!(input_total.is_none() || output_total.is_none());
+        }
+        Err(TransactionFeeError::CapacityOverflow(delta)) => {
+            assert_eq!(Some(delta as u128 + input_total.unwrap()), output_total);
+        }
+        Err(err) => panic!("unexpected transaction fee error: {}", err),
+    }
+}
+
+// The cheque claim, the udt transfer and the capacity transfer with the
+// capacities and amounts from the input: the builders either keep the totals
+// or fail with a typed overflow error, they never panic.
+fuzz_target!(|data: &[u8]| {
+    let mut values = Values(data);
+    fuzz_cheque_claim(&mut values);
+    fuzz_udt_transfer(&mut values);
+    fuzz_capacity_transfer(&mut values);
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+use ckb_sdk::{
+    tx_builder::escrow::{EscrowScript, EscrowTimeout},
+    types::{HtlcArgs, ScriptId},
+    util::{parse_dao_deposit_number, parse_udt_amount},
+};
+use ckb_types::{bytes::Bytes, packed::Script, prelude::*, H256};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(amount) = parse_udt_amount(data) {
+        assert_eq!(&amount.to_le_bytes()[..], &data[0..16]);
+    }
+    let _ = parse_dao_deposit_number(data);
+
+    if let Some(args) = HtlcArgs::from_slice(data) {
+        assert_eq!(args.to_bytes().as_ref(), data);
+    }
+
+    let script_id = ScriptId::new_type(H256::default());
+    let escrow = EscrowScript::new(script_id.clone(), 20, EscrowTimeout::InArgs);
+    let lock_script = Script::new_builder()
+        .code_hash(script_id.code_hash.pack())
+        .hash_type(script_id.hash_type.into())
+        .args(Bytes::from(data.to_vec()).pack())
+        .build();
+    let _ = escrow.parse_args(&lock_script);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use ckb_sdk::tx_stream::read_molecule_tx;
+use ckb_types::prelude::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    if let Ok(tx) = read_molecule_tx(&mut reader, 1024 * 1024) {
+        assert!(data.starts_with(tx.data().as_slice()));
+    }
+});
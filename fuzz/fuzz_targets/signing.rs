@@ -0,0 +1,87 @@
+#![no_main]
+
+use ckb_sdk::{
+    traits::{Signer, SignerError},
+    types::ScriptGroup,
+    unlock::{
+        fill_signature_slot, generate_message, MultisigConfig, ScriptSigner,
+        SecpMultisigScriptSigner, SecpSighashScriptSigner,
+    },
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{Script, Transaction, WitnessArgs},
+    prelude::*,
+    H160,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Signs everything with the first 65 bytes of the message, repeated
+struct EchoSigner;
+
+impl Signer for EchoSigner {
+    fn match_id(&self, _id: &[u8]) -> bool {
+        true
+    }
+
+    fn sign(
+        &self,
+        _id: &[u8],
+        message: &[u8],
+        _recoverable: bool,
+        _tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        Ok(message.iter().cycle().take(65).copied().collect())
+    }
+}
+
+// The first 4 bytes pick the script group and the multisig config, the rest
+// is a molecule transaction with arbitrary (partially signed) witnesses.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let (params, tx_data) = data.split_at(4);
+    let tx = match Transaction::from_slice(tx_data) {
+        Ok(tx) => tx.into_view(),
+        Err(_) => return,
+    };
+    for witness in tx.witnesses() {
+        if let Ok(witness_args) = WitnessArgs::from_slice(&witness.raw_data()) {
+            let _ = witness_args.lock().to_opt();
+        }
+    }
+
+    // the indices may be out of the inputs and the witnesses, or none at all
+    let mut script_group = ScriptGroup::from_lock_script(&Script::default());
+    script_group.input_indices = (0..params[0] as usize % 5)
+        .map(|i| (params[1] as usize + i * 7) % (tx.inputs().len() + 3))
+        .collect();
+    let _ = generate_message(&tx, &script_group, Bytes::from(vec![0u8; 65]));
+
+    let sighash_signer = SecpSighashScriptSigner::new(Box::new(EchoSigner));
+    let _ = sighash_signer.sign_tx(&tx, &script_group);
+
+    let addresses = (0..params[2] % 4 + 1)
+        .map(|i| H160([i; 20]))
+        .collect::<Vec<_>>();
+    let threshold = params[3] % (addresses.len() as u8 + 1);
+    let require_first_n = params[3] >> 4;
+    if let Ok(config) = MultisigConfig::new_with(addresses, require_first_n, threshold) {
+        let multisig_signer = SecpMultisigScriptSigner::new(Box::new(EchoSigner), config);
+        let _ = multisig_signer.sign_tx(&tx, &script_group);
+    }
+
+    // the signature slots of a lock field from the witness
+    if let Some(witness) = tx.witnesses().get(0) {
+        let mut lock_field = witness.raw_data().to_vec();
+        let offset = params[1] as usize;
+        let signature = [params[2]; 65];
+        if fill_signature_slot(&mut lock_field, offset, &signature).is_ok() {
+            assert!(lock_field[offset..]
+                .chunks_exact(65)
+                .any(|slot| slot == &signature[..]));
+        }
+    }
+});
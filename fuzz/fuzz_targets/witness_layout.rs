@@ -0,0 +1,21 @@
+#![no_main]
+
+use ckb_sdk::unlock::{detect_witness_layout, CobuildSighashWitness, WitnessLayoutKind};
+use ckb_types::{packed::WitnessArgs, prelude::*};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let kind = detect_witness_layout(data);
+    if let Ok(witness) = WitnessArgs::from_slice(data) {
+        assert_eq!(kind, WitnessLayoutKind::WitnessArgs);
+        let _ = witness.lock().to_opt();
+        let _ = witness.input_type().to_opt();
+        let _ = witness.output_type().to_opt();
+    }
+    if let Some(witness) = CobuildSighashWitness::parse(data) {
+        assert_eq!(
+            CobuildSighashWitness::parse(&witness.to_bytes()),
+            Some(witness)
+        );
+    }
+});
@@ -0,0 +1,150 @@
+//! Rotate the change addresses of an HD wallet account.
+//!
+//! By default the change of a transaction goes back to the sender lock
+//! script, which links all the payments of a wallet together. In the privacy
+//! mode of an [`HdAccount`] every balancer sends the change to a freshly
+//! derived lock script of the change chain instead. The account keeps all
+//! its lock scripts, receiving and change ones, so its capacity provider and
+//! its balance cover the whole rotation set.
+//!
+//! A change lock script is registered as soon as it is handed out, a
+//! transaction that is never sent leaves an unused change address behind.
+//! Keep the unused ones below the gap limit of [`scan_used_addresses`] so a
+//! wallet restored from the mnemonic still finds all of them.
+//!
+//! [`scan_used_addresses`]: crate::address_scan::scan_used_addresses
+
+use ckb_types::packed::{Script, WitnessArgs};
+
+use crate::address_scan::{AddressScanError, HdWallet, KeyChain, UsedAddress, UsedAddresses};
+use crate::traits::{CellCollector, CellCollectorError, CellQueryOptions, MaturityOption};
+use crate::tx_builder::{CapacityBalancer, CapacityProvider};
+
+/// The lock scripts of an HD wallet account, see the
+/// [module documentation](self).
+pub struct HdAccount<W> {
+    wallet: W,
+    placeholder_witness: WitnessArgs,
+    locks: Vec<UsedAddress>,
+    next_external_index: u32,
+    next_change_index: u32,
+    privacy_mode: bool,
+}
+
+impl<W: HdWallet> HdAccount<W> {
+    /// An account without any lock script yet, the lock scripts share
+    /// `placeholder_witness` (e.g. 65 zero bytes for sighash)
+    pub fn new(wallet: W, placeholder_witness: WitnessArgs) -> HdAccount<W> {
+        HdAccount::from_used_addresses(wallet, placeholder_witness, UsedAddresses::default())
+    }
+
+    /// Restore an account from the result of
+    /// [`scan_used_addresses`](crate::address_scan::scan_used_addresses)
+    pub fn from_used_addresses(
+        wallet: W,
+        placeholder_witness: WitnessArgs,
+        used: UsedAddresses,
+    ) -> HdAccount<W> {
+        HdAccount {
+            wallet,
+            placeholder_witness,
+            locks: used.used,
+            next_external_index: used.next_external_index,
+            next_change_index: used.next_change_index,
+            privacy_mode: false,
+        }
+    }
+
+    /// Send the change of every balancer to a fresh change address
+    pub fn with_privacy_mode(mut self, enabled: bool) -> Self {
+        self.privacy_mode = enabled;
+        self
+    }
+
+    pub fn privacy_mode(&self) -> bool {
+        self.privacy_mode
+    }
+
+    /// All the lock scripts of the account, in the order they were handed out
+    pub fn locks(&self) -> &[UsedAddress] {
+        &self.locks
+    }
+
+    pub fn lock_scripts(&self) -> Vec<Script> {
+        self.locks.iter().map(|used| used.lock.clone()).collect()
+    }
+
+    pub fn contains(&self, lock: &Script) -> bool {
+        self.locks.iter().any(|used| &used.lock == lock)
+    }
+
+    /// Derive and register the next receiving lock script
+    pub fn next_receiving_lock(&mut self) -> Result<Script, AddressScanError> {
+        self.derive_next(KeyChain::External)
+    }
+
+    /// Derive and register the next change lock script
+    pub fn next_change_lock(&mut self) -> Result<Script, AddressScanError> {
+        self.derive_next(KeyChain::Change)
+    }
+
+    fn derive_next(&mut self, chain: KeyChain) -> Result<Script, AddressScanError> {
+        let index = match chain {
+            KeyChain::External => &mut self.next_external_index,
+            KeyChain::Change => &mut self.next_change_index,
+        };
+        let lock = self.wallet.derive_lock(chain, *index)?;
+        self.locks.push(UsedAddress {
+            chain,
+            index: *index,
+            lock: lock.clone(),
+        });
+        *index += 1;
+        Ok(lock)
+    }
+
+    /// Collect the capacity of all the lock scripts of the account
+    pub fn capacity_provider(&self) -> CapacityProvider {
+        CapacityProvider::new_simple(
+            self.locks
+                .iter()
+                .map(|used| (used.lock.clone(), self.placeholder_witness.clone()))
+                .collect(),
+        )
+    }
+
+    /// A balancer collecting the capacity of the account. In the privacy
+    /// mode its change goes to a fresh change lock script, otherwise to the
+    /// first lock script of the account, the first receiving one is derived
+    /// if there is none yet.
+    pub fn balancer(&mut self, fee_rate: u64) -> Result<CapacityBalancer, AddressScanError> {
+        if self.locks.is_empty() {
+            self.next_receiving_lock()?;
+        }
+        let change_lock = if self.privacy_mode {
+            Some(self.next_change_lock()?)
+        } else {
+            None
+        };
+        let mut balancer = CapacityBalancer::new_with_provider(fee_rate, self.capacity_provider());
+        balancer.change_lock_script = change_lock;
+        Ok(balancer)
+    }
+
+    /// The total capacity of the live cells of all the lock scripts, the
+    /// immature ones included
+    pub fn balance(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<u64, CellCollectorError> {
+        let mut total = 0u64;
+        for used in &self.locks {
+            let mut query = CellQueryOptions::new_lock(used.lock.clone());
+            query.maturity = MaturityOption::Both;
+            query.min_total_capacity = u64::MAX;
+            let (_, capacity) = cell_collector.collect_live_cells(&query, false)?;
+            total = total.saturating_add(capacity);
+        }
+        Ok(total)
+    }
+}
@@ -0,0 +1,217 @@
+//! Recover the used addresses of an HD wallet.
+//!
+//! After importing a wallet from its mnemonic the addresses it used are
+//! unknown. [`scan_used_addresses`] derives the lock scripts of the external
+//! and change chains in order and asks the indexer whether each of them has a
+//! transaction history, the scan of a chain stops after `gap_limit`
+//! consecutive unused addresses (BIP44 uses 20).
+
+use ckb_types::packed::{Script, WitnessArgs};
+use thiserror::Error;
+
+#[cfg(feature = "indexer")]
+use crate::rpc::ckb_indexer::{Order, SearchKey, SearchMode};
+#[cfg(feature = "indexer")]
+use crate::traits::CellQueryOptions;
+use crate::tx_builder::CapacityProvider;
+#[cfg(feature = "indexer")]
+use crate::IndexerRpcClient;
+use crate::RpcError;
+
+/// The gap limit of BIP44
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// BIP32 non-hardened indexes are below 2^31
+const MAX_INDEX: u32 = 0x8000_0000;
+
+#[derive(Error, Debug)]
+pub enum AddressScanError {
+    #[error("derive lock script error: `{0}`")]
+    Derive(String),
+
+    #[error("gap limit must be greater than 0")]
+    InvalidGapLimit,
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+}
+
+/// The chain of a derivation path `m/44'/309'/account'/chain/index`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum KeyChain {
+    /// Chain 0, the receiving addresses
+    External,
+    /// Chain 1, the change addresses
+    Change,
+}
+
+/// The lock scripts of an HD wallet account
+pub trait HdWallet {
+    fn derive_lock(&self, chain: KeyChain, index: u32) -> Result<Script, AddressScanError>;
+}
+
+impl<F> HdWallet for F
+where
+    F: Fn(KeyChain, u32) -> Result<Script, AddressScanError>,
+{
+    fn derive_lock(&self, chain: KeyChain, index: u32) -> Result<Script, AddressScanError> {
+        self(chain, index)
+    }
+}
+
+/// Tell whether a lock script was ever used on chain
+pub trait LockHistory {
+    fn has_history(&self, lock: &Script) -> Result<bool, AddressScanError>;
+}
+
+#[cfg(feature = "indexer")]
+impl LockHistory for IndexerRpcClient {
+    fn has_history(&self, lock: &Script) -> Result<bool, AddressScanError> {
+        let mut query = CellQueryOptions::new_lock(lock.clone());
+        query.script_search_mode = Some(SearchMode::Exact);
+        let txs = self.get_transactions(SearchKey::from(query), Order::Asc, 1.into(), None)?;
+        Ok(!txs.objects.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UsedAddress {
+    pub chain: KeyChain,
+    pub index: u32,
+    pub lock: Script,
+}
+
+/// The result of [`scan_used_addresses`]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UsedAddresses {
+    pub used: Vec<UsedAddress>,
+    /// The index of the next receiving address to hand out
+    pub next_external_index: u32,
+    /// The index of the next change address
+    pub next_change_index: u32,
+}
+
+impl UsedAddresses {
+    pub fn lock_scripts(&self) -> Vec<Script> {
+        self.used.iter().map(|used| used.lock.clone()).collect()
+    }
+
+    /// Collect the capacity of all the used lock scripts, they share
+    /// `placeholder_witness` (e.g. 65 zero bytes for sighash)
+    pub fn capacity_provider(&self, placeholder_witness: WitnessArgs) -> CapacityProvider {
+        CapacityProvider::new_simple(
+            self.used
+                .iter()
+                .map(|used| (used.lock.clone(), placeholder_witness.clone()))
+                .collect(),
+        )
+    }
+}
+
+/// Find the used addresses of `wallet` on both chains with the gap limit
+/// scanning, see the [module documentation](self).
+pub fn scan_used_addresses(
+    wallet: &dyn HdWallet,
+    indexer: &dyn LockHistory,
+    gap_limit: u32,
+) -> Result<UsedAddresses, AddressScanError> {
+    if gap_limit == 0 {
+        return Err(AddressScanError::InvalidGapLimit);
+    }
+    let mut result = UsedAddresses::default();
+    for chain in [KeyChain::External, KeyChain::Change] {
+        let mut next_index = 0;
+        let mut index = 0;
+        while index < MAX_INDEX && index - next_index < gap_limit {
+            let lock = wallet.derive_lock(chain, index)?;
+            if indexer.has_history(&lock)? {
+                result.used.push(UsedAddress { chain, index, lock });
+                next_index = index + 1;
+            }
+            index += 1;
+        }
+        match chain {
+            KeyChain::External => result.next_external_index = next_index,
+            KeyChain::Change => result.next_change_index = next_index,
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, core::ScriptHashType, prelude::*};
+    use std::cell::RefCell;
+
+    fn derive(chain: KeyChain, index: u32) -> Result<Script, AddressScanError> {
+        let mut args = vec![(chain == KeyChain::Change) as u8];
+        args.extend_from_slice(&index.to_le_bytes());
+        Ok(Script::new_builder()
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(args).pack())
+            .build())
+    }
+
+    struct MockIndexer {
+        used: Vec<Script>,
+        queries: RefCell<usize>,
+    }
+
+    impl LockHistory for MockIndexer {
+        fn has_history(&self, lock: &Script) -> Result<bool, AddressScanError> {
+            *self.queries.borrow_mut() += 1;
+            Ok(self.used.contains(lock))
+        }
+    }
+
+    #[test]
+    fn test_scan_used_addresses() {
+        let used = [
+            (KeyChain::External, 0),
+            (KeyChain::External, 3),
+            (KeyChain::External, 7),
+            // beyond the gap limit after index 7
+            (KeyChain::External, 13),
+            (KeyChain::Change, 1),
+        ];
+        let indexer = MockIndexer {
+            used: used
+                .iter()
+                .map(|(chain, index)| derive(*chain, *index).unwrap())
+                .collect(),
+            queries: RefCell::new(0),
+        };
+        assert!(matches!(
+            scan_used_addresses(&derive, &indexer, 0),
+            Err(AddressScanError::InvalidGapLimit)
+        ));
+
+        let result = scan_used_addresses(&derive, &indexer, 5).unwrap();
+        let found = result
+            .used
+            .iter()
+            .map(|used| (used.chain, used.index))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            used[0..3]
+                .iter()
+                .chain(&used[4..])
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(result.next_external_index, 8);
+        assert_eq!(result.next_change_index, 2);
+        // indexes 0..=12 of the external chain and 0..=6 of the change chain
+        assert_eq!(*indexer.queries.borrow(), 13 + 7);
+        assert_eq!(
+            result.lock_scripts()[1],
+            derive(KeyChain::External, 3).unwrap()
+        );
+
+        let result = scan_used_addresses(&derive, &indexer, DEFAULT_GAP_LIMIT).unwrap();
+        assert_eq!(result.used.len(), 5);
+        assert_eq!(result.next_external_index, 14);
+    }
+}
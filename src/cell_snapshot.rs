@@ -0,0 +1,257 @@
+//! Export the full cell set of a wallet and build with it offline.
+//!
+//! A [`CellSnapshot`] records all the live cells of some lock scripts at a
+//! tip block, e.g. the wallet of a user reporting a "cannot balance" or a
+//! "fee too low" error. Imported on another machine, its
+//! [`cell_collector`](CellSnapshot::cell_collector) and
+//! [`tx_dep_provider`](CellSnapshot::tx_dep_provider) run the same builder
+//! against the same cells without a node.
+//!
+//! The snapshot types are serde types, [`CellSnapshot::to_json`] and
+//! [`CellSnapshot::from_json`] are the JSON encoding, any other serde format
+//! (e.g. CBOR) works as well, check the version with
+//! [`CellSnapshot::check_version`] after decoding.
+
+use std::collections::HashSet;
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    packed::{OutPoint, Script, Transaction},
+    prelude::*,
+    H256,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::traits::{
+    CellCollector, CellCollectorError, CellQueryOptions, LiveCell, MaturityOption,
+    OffchainTransactionDependencyProvider,
+};
+use crate::tx_builder::trace::TracedCell;
+#[cfg(feature = "indexer")]
+use crate::{traits::DefaultCellCollector, util::get_max_mature_number, CkbRpcClient};
+
+/// The version of the snapshot format
+pub const CELL_SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum CellSnapshotError {
+    #[error("unsupported snapshot version: `{0}`")]
+    UnsupportedVersion(u32),
+
+    #[error("collect cells error: `{0}`")]
+    CellCollector(#[from] CellCollectorError),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(String),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// The live cells of `lock_scripts` at block `tip_block_number`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CellSnapshot {
+    pub version: u32,
+    pub tip_block_number: u64,
+    /// The cellbase cells up to this block are mature
+    pub max_mature_number: u64,
+    pub lock_scripts: Vec<json_types::Script>,
+    pub cells: Vec<TracedCell>,
+}
+
+impl CellSnapshot {
+    /// An empty snapshot
+    pub fn new(tip_block_number: u64, max_mature_number: u64) -> CellSnapshot {
+        CellSnapshot {
+            version: CELL_SNAPSHOT_VERSION,
+            tip_block_number,
+            max_mature_number,
+            lock_scripts: Vec::new(),
+            cells: Vec::new(),
+        }
+    }
+
+    pub fn check_version(&self) -> Result<(), CellSnapshotError> {
+        if self.version != CELL_SNAPSHOT_VERSION {
+            return Err(CellSnapshotError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, CellSnapshotError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<CellSnapshot, CellSnapshotError> {
+        let snapshot: CellSnapshot = serde_json::from_str(json)?;
+        snapshot.check_version()?;
+        Ok(snapshot)
+    }
+
+    /// The total capacity of the cells
+    pub fn total_capacity(&self) -> u64 {
+        self.cells
+            .iter()
+            .map(|cell| cell.output.capacity.value())
+            .fold(0u64, u64::saturating_add)
+    }
+
+    pub fn live_cells(&self) -> Vec<LiveCell> {
+        self.cells.iter().cloned().map(LiveCell::from).collect()
+    }
+
+    /// A cell collector serving the cells of the snapshot
+    pub fn cell_collector(&self) -> SnapshotCellCollector {
+        SnapshotCellCollector::new(self.live_cells(), self.max_mature_number)
+    }
+
+    /// A transaction dependency provider knowing the cells of the snapshot,
+    /// the cell deps and the headers are not in the snapshot.
+    pub fn tx_dep_provider(&self) -> OffchainTransactionDependencyProvider {
+        let mut provider = OffchainTransactionDependencyProvider::default();
+        for cell in self.live_cells() {
+            provider.cells.insert(
+                (
+                    cell.out_point.tx_hash().unpack(),
+                    cell.out_point.index().unpack(),
+                ),
+                (cell.output, cell.output_data),
+            );
+        }
+        provider
+    }
+}
+
+/// Export all the live cells of `lock_scripts`, mature or not, from
+/// `cell_collector`
+pub fn export_cells(
+    cell_collector: &mut dyn CellCollector,
+    lock_scripts: &[Script],
+    tip_block_number: u64,
+    max_mature_number: u64,
+) -> Result<CellSnapshot, CellSnapshotError> {
+    let mut snapshot = CellSnapshot::new(tip_block_number, max_mature_number);
+    let mut seen = HashSet::new();
+    for lock_script in lock_scripts {
+        if !seen.insert(lock_script.clone()) {
+            continue;
+        }
+        snapshot.lock_scripts.push(lock_script.clone().into());
+        let mut query = CellQueryOptions::new_lock(lock_script.clone());
+        query.maturity = MaturityOption::Both;
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+        snapshot.cells.extend(cells.iter().map(TracedCell::from));
+    }
+    Ok(snapshot)
+}
+
+/// Export all the live cells of `lock_scripts` at the current tip of the
+/// node at `ckb_rpc`
+#[cfg(feature = "indexer")]
+pub fn export_cells_from_node(
+    ckb_rpc: &str,
+    lock_scripts: &[Script],
+) -> Result<CellSnapshot, CellSnapshotError> {
+    let ckb_client = CkbRpcClient::new(ckb_rpc);
+    let tip_block_number = ckb_client
+        .get_tip_block_number()
+        .map_err(|err| CellSnapshotError::Rpc(err.to_string()))?
+        .value();
+    let max_mature_number = get_max_mature_number(&ckb_client).map_err(CellSnapshotError::Rpc)?;
+    let mut cell_collector = DefaultCellCollector::new(ckb_rpc);
+    export_cells(
+        &mut cell_collector,
+        lock_scripts,
+        tip_block_number,
+        max_mature_number,
+    )
+}
+
+/// A cell collector over a fixed cell set, see [`CellSnapshot::cell_collector`]
+#[derive(Debug, Clone)]
+pub struct SnapshotCellCollector {
+    cells: Vec<LiveCell>,
+    snapshot_len: usize,
+    used: HashSet<(H256, u32)>,
+    max_mature_number: u64,
+}
+
+impl SnapshotCellCollector {
+    pub fn new(cells: Vec<LiveCell>, max_mature_number: u64) -> SnapshotCellCollector {
+        SnapshotCellCollector {
+            snapshot_len: cells.len(),
+            cells,
+            used: HashSet::new(),
+            max_mature_number,
+        }
+    }
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+impl CellCollector for SnapshotCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let mut total_capacity = 0u64;
+        let mut cells = Vec::new();
+        for cell in &self.cells {
+            if total_capacity >= query.min_total_capacity {
+                break;
+            }
+            let key = out_point_key(&cell.out_point);
+            if self.used.contains(&key) || !query.match_cell(cell, self.max_mature_number) {
+                continue;
+            }
+            let capacity: u64 = cell.output.capacity().unpack();
+            total_capacity = total_capacity.saturating_add(capacity);
+            cells.push(cell.clone());
+            if apply_changes {
+                self.used.insert(key);
+            }
+        }
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.used.insert(out_point_key(&out_point));
+        Ok(())
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        let tx_view = tx.into_view();
+        for out_point in tx_view.input_pts_iter() {
+            self.lock_cell(out_point, tip_block_number)?;
+        }
+        for (index, (output, output_data)) in tx_view.outputs_with_data_iter().enumerate() {
+            self.cells.push(LiveCell {
+                output,
+                output_data,
+                out_point: OutPoint::new(tx_view.hash(), index as u32),
+                block_number: 0,
+                tx_index: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Back to the cells of the snapshot
+    fn reset(&mut self) {
+        self.cells.truncate(self.snapshot_len);
+        self.used.clear();
+    }
+}
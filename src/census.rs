@@ -0,0 +1,317 @@
+//! Count the live cells of a lock script before a sweep or a migration.
+//!
+//! [`take_cell_census`] pages through the live cells of a lock script, or of
+//! every lock script of a script id, and reports how many cells there are,
+//! their capacity, how many carry data or a type script and how old they are.
+
+use std::collections::HashSet;
+
+use ckb_jsonrpc_types::JsonBytes;
+use ckb_types::{packed::Script, prelude::*, H256};
+use thiserror::Error;
+
+#[cfg(feature = "indexer")]
+use crate::rpc::ckb_indexer::Order;
+use crate::rpc::ckb_indexer::{ScriptType, SearchKey, SearchMode};
+use crate::traits::LiveCell;
+use crate::types::ScriptId;
+#[cfg(feature = "indexer")]
+use crate::IndexerRpcClient;
+use crate::RpcError;
+
+/// The default age boundaries in blocks: about a day, a week, a month and a
+/// year at 8 seconds per block
+pub const DEFAULT_AGE_BOUNDARIES: [u64; 4] = [10_800, 75_600, 324_000, 3_942_000];
+
+/// The default number of cells of an indexer page
+pub const DEFAULT_PAGE_SIZE: u32 = 1000;
+
+#[derive(Error, Debug)]
+pub enum CensusError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("page size must be greater than 0")]
+    InvalidPageSize,
+}
+
+/// The cells to count
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CensusTarget {
+    /// The cells of exactly this lock script
+    Lock(Script),
+    /// The cells of every lock script of the script id, whatever the args
+    ScriptId(ScriptId),
+}
+
+impl CensusTarget {
+    pub fn search_key(&self) -> SearchKey {
+        let (script, mode) = match self {
+            CensusTarget::Lock(lock) => (lock.clone(), SearchMode::Exact),
+            CensusTarget::ScriptId(script_id) => (
+                Script::new_builder()
+                    .code_hash(script_id.code_hash.pack())
+                    .hash_type(script_id.hash_type.into())
+                    .build(),
+                SearchMode::Prefix,
+            ),
+        };
+        SearchKey {
+            script: script.into(),
+            script_type: ScriptType::Lock,
+            script_search_mode: Some(mode),
+            filter: None,
+            with_data: Some(true),
+            group_by_transaction: None,
+        }
+    }
+}
+
+/// A source of live cells read page by page
+pub trait CellPages {
+    /// The cells after `cursor` and the cursor of the next page, an empty
+    /// page ends the scan
+    fn cells_page(
+        &self,
+        search_key: &SearchKey,
+        limit: u32,
+        cursor: Option<JsonBytes>,
+    ) -> Result<(Vec<LiveCell>, JsonBytes), CensusError>;
+}
+
+#[cfg(feature = "indexer")]
+impl CellPages for IndexerRpcClient {
+    fn cells_page(
+        &self,
+        search_key: &SearchKey,
+        limit: u32,
+        cursor: Option<JsonBytes>,
+    ) -> Result<(Vec<LiveCell>, JsonBytes), CensusError> {
+        let page = self.get_cells(search_key.clone(), Order::Asc, limit.into(), cursor)?;
+        let cells = page.objects.into_iter().map(LiveCell::from).collect();
+        Ok((cells, page.last_cursor))
+    }
+}
+
+/// The cells whose age falls in a range of blocks
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct AgeBucket {
+    /// The cells younger than this many blocks and not in a previous
+    /// bucket, `None` for the oldest cells
+    pub max_age: Option<u64>,
+    pub cell_count: usize,
+    pub capacity: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CellCensus {
+    /// The block the ages are counted from
+    pub tip_block_number: u64,
+    pub cell_count: usize,
+    pub total_capacity: u64,
+    /// The cells with non-empty data
+    pub data_cell_count: usize,
+    /// The cells with a type script
+    pub typed_cell_count: usize,
+    /// The cells without data nor type script, what a capacity sweep collects
+    pub plain_cell_count: usize,
+    pub plain_capacity: u64,
+    /// The block number of the oldest cell
+    pub oldest_block_number: Option<u64>,
+    pub age_buckets: Vec<AgeBucket>,
+    lock_hashes: HashSet<H256>,
+}
+
+impl CellCensus {
+    /// An empty census, `age_boundaries` (in blocks, ascending) split the
+    /// cells into `age_boundaries.len() + 1` buckets.
+    pub fn new(tip_block_number: u64, age_boundaries: &[u64]) -> CellCensus {
+        let age_buckets = age_boundaries
+            .iter()
+            .map(|max_age| Some(*max_age))
+            .chain(std::iter::once(None))
+            .map(|max_age| AgeBucket {
+                max_age,
+                ..Default::default()
+            })
+            .collect();
+        CellCensus {
+            tip_block_number,
+            age_buckets,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, cell: &LiveCell) {
+        let capacity: u64 = cell.output.capacity().unpack();
+        let has_data = !cell.output_data.is_empty();
+        let has_type = cell.output.type_().is_some();
+        self.cell_count += 1;
+        self.total_capacity = self.total_capacity.saturating_add(capacity);
+        if has_data {
+            self.data_cell_count += 1;
+        }
+        if has_type {
+            self.typed_cell_count += 1;
+        }
+        if !has_data && !has_type {
+            self.plain_cell_count += 1;
+            self.plain_capacity = self.plain_capacity.saturating_add(capacity);
+        }
+        self.oldest_block_number = Some(
+            self.oldest_block_number
+                .map_or(cell.block_number, |oldest| oldest.min(cell.block_number)),
+        );
+        let age = self.tip_block_number.saturating_sub(cell.block_number);
+        if let Some(bucket) = self
+            .age_buckets
+            .iter_mut()
+            .find(|bucket| bucket.max_age.map_or(true, |max_age| age < max_age))
+        {
+            bucket.cell_count += 1;
+            bucket.capacity = bucket.capacity.saturating_add(capacity);
+        }
+        self.lock_hashes
+            .insert(cell.output.lock().calc_script_hash().unpack());
+    }
+
+    /// The number of distinct lock scripts, 1 for a [`CensusTarget::Lock`]
+    pub fn lock_count(&self) -> usize {
+        self.lock_hashes.len()
+    }
+}
+
+/// Count the live cells of `target`, see the [module documentation](self).
+pub fn take_cell_census(
+    pages: &dyn CellPages,
+    target: &CensusTarget,
+    tip_block_number: u64,
+    page_size: u32,
+    age_boundaries: &[u64],
+) -> Result<CellCensus, CensusError> {
+    if page_size == 0 {
+        return Err(CensusError::InvalidPageSize);
+    }
+    let search_key = target.search_key();
+    let mut census = CellCensus::new(tip_block_number, age_boundaries);
+    let mut cursor = None;
+    loop {
+        let (cells, next_cursor) = pages.cells_page(&search_key, page_size, cursor)?;
+        if cells.is_empty() {
+            break;
+        }
+        for cell in &cells {
+            census.add(cell);
+        }
+        cursor = Some(next_cursor);
+    }
+    Ok(census)
+}
+
+impl From<ScriptId> for CensusTarget {
+    fn from(script_id: ScriptId) -> CensusTarget {
+        CensusTarget::ScriptId(script_id)
+    }
+}
+
+impl From<Script> for CensusTarget {
+    fn from(lock: Script) -> CensusTarget {
+        CensusTarget::Lock(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::ScriptHashType,
+        h256,
+        packed::{CellOutput, OutPoint},
+    };
+    use std::cell::RefCell;
+
+    struct MockPages {
+        cells: Vec<LiveCell>,
+        queries: RefCell<Vec<SearchKey>>,
+    }
+
+    impl CellPages for MockPages {
+        fn cells_page(
+            &self,
+            search_key: &SearchKey,
+            limit: u32,
+            cursor: Option<JsonBytes>,
+        ) -> Result<(Vec<LiveCell>, JsonBytes), CensusError> {
+            self.queries.borrow_mut().push(search_key.clone());
+            let start = cursor.map_or(0, |cursor| cursor.as_bytes()[0] as usize);
+            let end = (start + limit as usize).min(self.cells.len());
+            let cells = self.cells[start.min(end)..end].to_vec();
+            Ok((cells, JsonBytes::from_vec(vec![end as u8])))
+        }
+    }
+
+    fn cell(args: u8, capacity: u64, block_number: u64, data: &[u8], typed: bool) -> LiveCell {
+        let script = |args: u8| {
+            Script::new_builder()
+                .code_hash(h256!("0x1234").pack())
+                .hash_type(ScriptHashType::Type.into())
+                .args(Bytes::from(vec![args]).pack())
+                .build()
+        };
+        LiveCell {
+            output: CellOutput::new_builder()
+                .capacity(capacity.pack())
+                .lock(script(args))
+                .type_(Some(script(0xff)).filter(|_| typed).pack())
+                .build(),
+            output_data: Bytes::from(data.to_vec()),
+            out_point: OutPoint::default(),
+            block_number,
+            tx_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_take_cell_census() {
+        let pages = MockPages {
+            cells: vec![
+                cell(1, 100, 990, &[], false),
+                cell(1, 200, 900, &[1, 2], false),
+                cell(2, 300, 500, &[], true),
+                cell(2, 400, 10, &[], false),
+                cell(3, 500, 995, &[3], true),
+            ],
+            queries: RefCell::new(Vec::new()),
+        };
+        let target = CensusTarget::from(ScriptId::new_type(h256!("0x1234")));
+        assert!(matches!(
+            take_cell_census(&pages, &target, 1000, 0, &[50, 200]),
+            Err(CensusError::InvalidPageSize)
+        ));
+
+        let census = take_cell_census(&pages, &target, 1000, 2, &[50, 200]).unwrap();
+        // 3 pages of cells and the empty last one
+        assert_eq!(pages.queries.borrow().len(), 4);
+        assert_eq!(
+            pages.queries.borrow()[0].script_search_mode,
+            Some(SearchMode::Prefix)
+        );
+        assert_eq!(census.cell_count, 5);
+        assert_eq!(census.total_capacity, 1500);
+        assert_eq!(census.data_cell_count, 2);
+        assert_eq!(census.typed_cell_count, 2);
+        assert_eq!(census.plain_cell_count, 2);
+        assert_eq!(census.plain_capacity, 500);
+        assert_eq!(census.oldest_block_number, Some(10));
+        assert_eq!(census.lock_count(), 3);
+        assert_eq!(
+            census
+                .age_buckets
+                .iter()
+                .map(|bucket| (bucket.max_age, bucket.cell_count, bucket.capacity))
+                .collect::<Vec<_>>(),
+            vec![(Some(50), 2, 600), (Some(200), 1, 200), (None, 2, 700),]
+        );
+    }
+}
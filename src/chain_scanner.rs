@@ -0,0 +1,597 @@
+//! Iterate the blocks of the chain and scan their transactions.
+//!
+//! [`BlockIterator`] fetches the blocks of a number range in batches and
+//! checks every block is the child of the previous one. [`ChainScanner`]
+//! follows the chain tip, passes every block and transaction to a
+//! [`ScanHandler`] and rolls the handler back when the chain reorganizes, see
+//! [`crate::deposit::DepositWatcher`] for an example.
+//!
+//! The blocks of [`CkbRpcClient`] are fetched in the packed molecule format,
+//! which is a fraction of the size of the json format. A block of thousands
+//! of transactions can still be large, the buffered blocks of
+//! [`BlockIterator`] are bounded with
+//! [`BlockIterator::with_max_buffer_size`].
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+#[cfg(feature = "rpc")]
+use anyhow::anyhow;
+#[cfg(feature = "rpc")]
+use ckb_jsonrpc_types::JsonBytes;
+use ckb_types::{
+    core::{BlockNumber, BlockView, TransactionView},
+    packed::Byte32,
+};
+#[cfg(feature = "rpc")]
+use ckb_types::{packed::Block, prelude::*};
+use thiserror::Error;
+
+#[cfg(feature = "rpc")]
+use crate::rpc::CkbRpcClient;
+use crate::rpc::RpcError;
+
+/// The default number of blocks fetched at a time
+pub const DEFAULT_BATCH_SIZE: u64 = 20;
+/// The default number of recent blocks kept to find the fork point of a reorg
+pub const DEFAULT_MAX_REORG_DEPTH: usize = 100;
+
+#[derive(Error, Debug)]
+pub enum ChainScannerError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("block not found: `{0}`")]
+    BlockNotFound(String),
+
+    #[error("block `{0}` is not the child of the previous block")]
+    Reorg(BlockNumber),
+
+    #[error("the chain reorganized deeper than `{0}` blocks")]
+    ReorgTooDeep(usize),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Where the scanned blocks come from
+pub trait BlockSource {
+    fn tip_block_number(&self) -> Result<BlockNumber, ChainScannerError>;
+
+    fn block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, ChainScannerError>;
+
+    fn block_by_hash(&self, hash: &Byte32) -> Result<Option<BlockView>, ChainScannerError>;
+
+    /// Fetch a batch of blocks, stop at the first block not found.
+    fn blocks_by_number(
+        &self,
+        numbers: Range<BlockNumber>,
+    ) -> Result<Vec<BlockView>, ChainScannerError> {
+        let mut blocks = Vec::new();
+        for number in numbers {
+            match self.block_by_number(number)? {
+                Some(block) => blocks.push(block),
+                None => break,
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(feature = "rpc")]
+impl BlockSource for CkbRpcClient {
+    fn tip_block_number(&self) -> Result<BlockNumber, ChainScannerError> {
+        Ok(self.get_tip_block_number()?.value())
+    }
+
+    fn block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, ChainScannerError> {
+        self.get_packed_block_by_number(number.into())?
+            .map(|block| decode_packed_block(&block))
+            .transpose()
+    }
+
+    fn block_by_hash(&self, hash: &Byte32) -> Result<Option<BlockView>, ChainScannerError> {
+        self.get_packed_block(hash.unpack())?
+            .map(|block| decode_packed_block(&block))
+            .transpose()
+    }
+}
+
+/// Decode a block returned in the packed format, the block extension is kept
+#[cfg(feature = "rpc")]
+fn decode_packed_block(block: &JsonBytes) -> Result<BlockView, ChainScannerError> {
+    let block = Block::from_compatible_slice(block.as_bytes())
+        .map_err(|err| anyhow!("invalid packed block: {}", err))?;
+    Ok(block.into_view())
+}
+
+/// Iterate the blocks of a number range, `batch_size` blocks are fetched at a
+/// time.
+///
+/// The iteration stops at the end of the range or at the first block not
+/// found. When a block is not the child of the previous one the chain has
+/// reorganized, [`ChainScannerError::Reorg`] is returned and the iteration
+/// stops.
+pub struct BlockIterator<'a> {
+    source: &'a dyn BlockSource,
+    numbers: Range<BlockNumber>,
+    batch_size: u64,
+    /// The size of the next batch, smaller than `batch_size` when the blocks
+    /// are too large for `max_buffer_size`
+    next_batch_size: u64,
+    max_buffer_size: Option<usize>,
+    buffer: VecDeque<BlockView>,
+    parent_hash: Option<Byte32>,
+    finished: bool,
+}
+
+impl<'a> BlockIterator<'a> {
+    pub fn new(source: &'a dyn BlockSource, numbers: Range<BlockNumber>) -> BlockIterator<'a> {
+        BlockIterator {
+            source,
+            numbers,
+            batch_size: DEFAULT_BATCH_SIZE,
+            next_batch_size: DEFAULT_BATCH_SIZE,
+            max_buffer_size: None,
+            buffer: VecDeque::new(),
+            parent_hash: None,
+            finished: false,
+        }
+    }
+
+    /// Iterate from the block of `hash` to the current tip
+    pub fn from_hash(
+        source: &'a dyn BlockSource,
+        hash: &Byte32,
+    ) -> Result<BlockIterator<'a>, ChainScannerError> {
+        let block = source
+            .block_by_hash(hash)?
+            .ok_or_else(|| ChainScannerError::BlockNotFound(format!("{:#x}", hash)))?;
+        let tip_number = source.tip_block_number()?;
+        let mut iter = BlockIterator::new(source, block.number() + 1..tip_number + 1);
+        iter.buffer.push_back(block);
+        Ok(iter)
+    }
+
+    pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self.next_batch_size = match self.max_buffer_size {
+            Some(_) => 1,
+            None => self.batch_size,
+        };
+        self
+    }
+
+    /// Keep the serialized size of the buffered blocks around
+    /// `max_buffer_size` bytes.
+    ///
+    /// The iteration starts with one block per batch, the batch grows up to
+    /// `batch_size` blocks as long as the blocks of the previous batch fit
+    /// in the buffer. A single block larger than the buffer is still
+    /// fetched.
+    pub fn with_max_buffer_size(mut self, max_buffer_size: usize) -> Self {
+        self.max_buffer_size = Some(max_buffer_size);
+        self.next_batch_size = 1;
+        self
+    }
+
+    /// The first block must be the child of `parent_hash`
+    pub fn with_parent_hash(mut self, parent_hash: Byte32) -> Self {
+        self.parent_hash = Some(parent_hash);
+        self
+    }
+
+    fn fetch_batch(&mut self) -> Result<(), ChainScannerError> {
+        let end = self
+            .numbers
+            .end
+            .min(self.numbers.start.saturating_add(self.next_batch_size));
+        let blocks = self.source.blocks_by_number(self.numbers.start..end)?;
+        self.numbers.start += blocks.len() as u64;
+        if let (Some(max_buffer_size), false) = (self.max_buffer_size, blocks.is_empty()) {
+            let total_size: usize = blocks.iter().map(|block| block.data().total_size()).sum();
+            let block_size = (total_size / blocks.len()).max(1);
+            self.next_batch_size =
+                ((max_buffer_size / block_size) as u64).clamp(1, self.batch_size);
+        }
+        self.buffer.extend(blocks);
+        Ok(())
+    }
+}
+
+impl Iterator for BlockIterator<'_> {
+    type Item = Result<BlockView, ChainScannerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if self.buffer.is_empty() && !self.numbers.is_empty() {
+            if let Err(err) = self.fetch_batch() {
+                self.finished = true;
+                return Some(Err(err));
+            }
+        }
+        let block = match self.buffer.pop_front() {
+            Some(block) => block,
+            None => {
+                self.finished = true;
+                return None;
+            }
+        };
+        if let Some(parent_hash) = self.parent_hash.as_ref() {
+            if &block.parent_hash() != parent_hash {
+                self.finished = true;
+                return Some(Err(ChainScannerError::Reorg(block.number())));
+            }
+        }
+        self.parent_hash = Some(block.hash());
+        Some(Ok(block))
+    }
+}
+
+/// Callbacks of [`ChainScanner`]
+pub trait ScanHandler {
+    fn on_block(&mut self, _block: &BlockView) -> Result<(), ChainScannerError> {
+        Ok(())
+    }
+
+    /// Called for every transaction of the block after [`ScanHandler::on_block`]
+    fn on_transaction(
+        &mut self,
+        block: &BlockView,
+        tx_index: usize,
+        tx: &TransactionView,
+    ) -> Result<(), ChainScannerError>;
+
+    /// The scanned block is no longer in the main chain, called from the
+    /// highest block down to the fork point.
+    fn on_rollback(
+        &mut self,
+        _number: BlockNumber,
+        _hash: &Byte32,
+    ) -> Result<(), ChainScannerError> {
+        Ok(())
+    }
+}
+
+/// Follow the main chain from a block number
+pub struct ChainScanner {
+    next_number: BlockNumber,
+    /// The number and hash of the recently scanned blocks
+    recent_blocks: VecDeque<(BlockNumber, Byte32)>,
+    pub batch_size: u64,
+    /// See [`BlockIterator::with_max_buffer_size`]
+    pub max_buffer_size: Option<usize>,
+    pub max_reorg_depth: usize,
+}
+
+impl ChainScanner {
+    pub fn new(start_number: BlockNumber) -> ChainScanner {
+        ChainScanner {
+            next_number: start_number,
+            recent_blocks: VecDeque::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_buffer_size: None,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+        }
+    }
+
+    /// The number of the next block to scan
+    pub fn next_number(&self) -> BlockNumber {
+        self.next_number
+    }
+
+    /// Scan the blocks up to the current tip, return the number of blocks
+    /// passed to `handler`.
+    pub fn scan(
+        &mut self,
+        source: &dyn BlockSource,
+        handler: &mut dyn ScanHandler,
+    ) -> Result<usize, ChainScannerError> {
+        let mut scanned = 0;
+        loop {
+            let tip_number = source.tip_block_number()?;
+            if self.next_number > tip_number {
+                // the main chain may get shorter after a reorg
+                if self.rollback(source, handler)? {
+                    continue;
+                }
+                return Ok(scanned);
+            }
+            let mut blocks = BlockIterator::new(source, self.next_number..tip_number + 1)
+                .with_batch_size(self.batch_size);
+            if let Some(max_buffer_size) = self.max_buffer_size {
+                blocks = blocks.with_max_buffer_size(max_buffer_size);
+            }
+            if let Some((_, hash)) = self.recent_blocks.back() {
+                blocks = blocks.with_parent_hash(hash.clone());
+            }
+            let mut reorganized = false;
+            for block in blocks {
+                match block {
+                    Ok(block) => {
+                        self.scan_block(&block, handler)?;
+                        scanned += 1;
+                    }
+                    Err(ChainScannerError::Reorg(_)) => {
+                        reorganized = self.rollback(source, handler)?;
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            if !reorganized {
+                return Ok(scanned);
+            }
+        }
+    }
+
+    fn scan_block(
+        &mut self,
+        block: &BlockView,
+        handler: &mut dyn ScanHandler,
+    ) -> Result<(), ChainScannerError> {
+        handler.on_block(block)?;
+        for (tx_index, tx) in block.transactions().iter().enumerate() {
+            handler.on_transaction(block, tx_index, tx)?;
+        }
+        self.recent_blocks.push_back((block.number(), block.hash()));
+        while self.recent_blocks.len() > self.max_reorg_depth {
+            self.recent_blocks.pop_front();
+        }
+        self.next_number = block.number() + 1;
+        Ok(())
+    }
+
+    /// Roll back the scanned blocks not in the main chain anymore, return
+    /// false if the last scanned block is still in the main chain.
+    fn rollback(
+        &mut self,
+        source: &dyn BlockSource,
+        handler: &mut dyn ScanHandler,
+    ) -> Result<bool, ChainScannerError> {
+        let mut rolled_back = false;
+        while let Some((number, hash)) = self.recent_blocks.back().cloned() {
+            let main_chain_hash = source.block_by_number(number)?.map(|block| block.hash());
+            if main_chain_hash.as_ref() == Some(&hash) {
+                return Ok(rolled_back);
+            }
+            handler.on_rollback(number, &hash)?;
+            self.recent_blocks.pop_back();
+            self.next_number = number;
+            rolled_back = true;
+        }
+        if rolled_back {
+            Err(ChainScannerError::ReorgTooDeep(self.max_reorg_depth))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::{BlockBuilder, TransactionBuilder},
+        packed::CellOutput,
+        prelude::*,
+    };
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct MockBlockSource {
+        blocks: RefCell<Vec<BlockView>>,
+        /// The number of blocks requested by every batch
+        batches: RefCell<Vec<u64>>,
+    }
+
+    impl MockBlockSource {
+        /// Replace the blocks from `number` with `count` new blocks
+        fn fork(&self, number: BlockNumber, count: u64, salt: u64) {
+            let mut blocks = self.blocks.borrow_mut();
+            blocks.truncate(number as usize);
+            for _ in 0..count {
+                let number = blocks.len() as u64;
+                let parent_hash = blocks.last().map(|block| block.hash()).unwrap_or_default();
+                let tx = TransactionBuilder::default()
+                    .output(CellOutput::new_builder().capacity(number.pack()).build())
+                    .output_data(Bytes::new().pack())
+                    .build();
+                blocks.push(
+                    BlockBuilder::default()
+                        .number(number.pack())
+                        .parent_hash(parent_hash)
+                        .timestamp(salt.pack())
+                        .transaction(tx)
+                        .build(),
+                );
+            }
+        }
+    }
+
+    impl BlockSource for MockBlockSource {
+        fn tip_block_number(&self) -> Result<BlockNumber, ChainScannerError> {
+            Ok(self.blocks.borrow().len() as u64 - 1)
+        }
+
+        fn block_by_number(
+            &self,
+            number: BlockNumber,
+        ) -> Result<Option<BlockView>, ChainScannerError> {
+            Ok(self.blocks.borrow().get(number as usize).cloned())
+        }
+
+        fn block_by_hash(&self, hash: &Byte32) -> Result<Option<BlockView>, ChainScannerError> {
+            Ok(self
+                .blocks
+                .borrow()
+                .iter()
+                .find(|block| &block.hash() == hash)
+                .cloned())
+        }
+
+        fn blocks_by_number(
+            &self,
+            numbers: Range<BlockNumber>,
+        ) -> Result<Vec<BlockView>, ChainScannerError> {
+            self.batches.borrow_mut().push(numbers.end - numbers.start);
+            Ok(numbers
+                .map_while(|number| self.block_by_number(number).unwrap())
+                .collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordHandler {
+        txs: Vec<(BlockNumber, Byte32)>,
+        rollbacks: Vec<BlockNumber>,
+    }
+
+    impl ScanHandler for RecordHandler {
+        fn on_transaction(
+            &mut self,
+            block: &BlockView,
+            _tx_index: usize,
+            tx: &TransactionView,
+        ) -> Result<(), ChainScannerError> {
+            self.txs.push((block.number(), tx.hash()));
+            Ok(())
+        }
+
+        fn on_rollback(
+            &mut self,
+            number: BlockNumber,
+            _hash: &Byte32,
+        ) -> Result<(), ChainScannerError> {
+            self.rollbacks.push(number);
+            self.txs.retain(|(block_number, _)| *block_number != number);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_block_iterator() {
+        let source = MockBlockSource::default();
+        source.fork(0, 10, 0);
+        let numbers = BlockIterator::new(&source, 2..20)
+            .with_batch_size(3)
+            .map(|block| block.unwrap().number())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, (2..10).collect::<Vec<_>>());
+
+        let hash = source.block_by_number(7).unwrap().unwrap().hash();
+        let numbers = BlockIterator::from_hash(&source, &hash)
+            .unwrap()
+            .map(|block| block.unwrap().number())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![7, 8, 9]);
+
+        let other_parent = source.block_by_number(5).unwrap().unwrap().hash();
+        let mut blocks = BlockIterator::new(&source, 7..10).with_parent_hash(other_parent);
+        assert!(matches!(
+            blocks.next(),
+            Some(Err(ChainScannerError::Reorg(7)))
+        ));
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn test_block_iterator_max_buffer_size() {
+        let source = MockBlockSource::default();
+        source.fork(0, 10, 0);
+        let block_size = source
+            .block_by_number(0)
+            .unwrap()
+            .unwrap()
+            .data()
+            .total_size();
+        let numbers = BlockIterator::new(&source, 0..10)
+            .with_batch_size(4)
+            .with_max_buffer_size(block_size * 3)
+            .map(|block| block.unwrap().number())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, (0..10).collect::<Vec<_>>());
+        assert_eq!(*source.batches.borrow(), vec![1, 3, 3, 3]);
+
+        // a block larger than the buffer is fetched alone
+        source.batches.borrow_mut().clear();
+        let mut scanner = ChainScanner::new(0);
+        scanner.max_buffer_size = Some(1);
+        let mut handler = RecordHandler::default();
+        assert_eq!(scanner.scan(&source, &mut handler).unwrap(), 10);
+        assert!(source.batches.borrow().iter().all(|batch| *batch == 1));
+    }
+
+    #[cfg(feature = "rpc")]
+    #[test]
+    fn test_rpc_block_source() {
+        use crate::test_util::MockRpcResult;
+        use httpmock::prelude::*;
+
+        let source = MockBlockSource::default();
+        source.fork(0, 1, 0);
+        let block = source.block_by_number(0).unwrap().unwrap();
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .body_contains("get_block_by_number");
+            then.status(200)
+                .body(MockRpcResult::new(JsonBytes::from_bytes(block.data().as_bytes())).to_json());
+        });
+
+        let client = CkbRpcClient::new(server.base_url().as_str());
+        let fetched = client.block_by_number(0).unwrap().unwrap();
+        assert_eq!(fetched.hash(), block.hash());
+
+        let client = client.with_max_response_size(block.data().total_size());
+        assert!(matches!(
+            client.block_by_number(0),
+            Err(ChainScannerError::Rpc(RpcError::ResponseTooLarge(_)))
+        ));
+    }
+
+    #[test]
+    fn test_chain_scanner_reorg() {
+        let source = MockBlockSource::default();
+        source.fork(0, 10, 0);
+        let mut scanner = ChainScanner::new(0);
+        scanner.batch_size = 4;
+        let mut handler = RecordHandler::default();
+        assert_eq!(scanner.scan(&source, &mut handler).unwrap(), 10);
+        assert_eq!(scanner.next_number(), 10);
+        assert_eq!(scanner.scan(&source, &mut handler).unwrap(), 0);
+
+        // blocks 7..10 are replaced by 7..12
+        source.fork(7, 5, 1);
+        assert_eq!(scanner.scan(&source, &mut handler).unwrap(), 5);
+        assert_eq!(handler.rollbacks, vec![9, 8, 7]);
+        assert_eq!(scanner.next_number(), 12);
+        let expected = (0..12)
+            .map(|number| {
+                let block = source.block_by_number(number).unwrap().unwrap();
+                (number, block.transactions()[0].hash())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(handler.txs, expected);
+
+        // the main chain gets shorter
+        source.fork(10, 1, 3);
+        assert_eq!(scanner.scan(&source, &mut handler).unwrap(), 1);
+        assert_eq!(handler.rollbacks, vec![9, 8, 7, 11, 10]);
+        assert_eq!(scanner.next_number(), 11);
+
+        // the fork point is older than the recent blocks kept
+        scanner.max_reorg_depth = 2;
+        source.fork(11, 1, 1);
+        scanner.scan(&source, &mut handler).unwrap();
+        source.fork(5, 10, 2);
+        assert!(matches!(
+            scanner.scan(&source, &mut handler),
+            Err(ChainScannerError::ReorgTooDeep(2))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+//! Adapters of the `ckb-types`, `ckb-script` and `ckb-chain-spec` APIs which
+//! change between node versions.
+//!
+//! The rest of the crate resolves and verifies transactions through these
+//! functions only, so upgrading the node crates (or pinning an older version
+//! of them) means adapting this module instead of every call site.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use ckb_chain_spec::consensus::{Consensus, ConsensusBuilder};
+use ckb_script::{TransactionScriptsVerifier, TxVerifyEnv};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_types::core::{
+    cell::{resolve_transaction, CellProvider, HeaderChecker, ResolvedTransaction},
+    hardfork::{HardForks, CKB2021, CKB2023},
+    Cycle, HeaderView, TransactionView,
+};
+
+/// A consensus with all the hardfork features activated from genesis
+pub(crate) fn dev_consensus() -> Consensus {
+    ConsensusBuilder::default()
+        .hardfork_switch(HardForks {
+            ckb2021: CKB2021::new_dev_default(),
+            ckb2023: CKB2023::new_dev_default(),
+        })
+        .build()
+}
+
+/// Resolve the input cells, cell deps and header deps of `tx`
+pub(crate) fn resolve_tx<CP: CellProvider, HC: HeaderChecker>(
+    tx: TransactionView,
+    cell_provider: &CP,
+    header_checker: &HC,
+) -> Result<ResolvedTransaction, String> {
+    resolve_transaction(tx, &mut HashSet::new(), cell_provider, header_checker)
+        .map_err(|err| format!("Resolve transaction error: {:?}", err))
+}
+
+/// Run all the scripts of a resolved transaction, the script debug messages
+/// are printed when `print_debug` is set.
+pub(crate) fn verify_tx_scripts<DL>(
+    rtx: ResolvedTransaction,
+    data_loader: DL,
+    consensus: Arc<Consensus>,
+    tip: &HeaderView,
+    max_cycles: Cycle,
+    print_debug: bool,
+) -> Result<Cycle, String>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let mut verifier = TransactionScriptsVerifier::new(
+        Arc::new(rtx),
+        data_loader,
+        consensus,
+        Arc::new(TxVerifyEnv::new_submit(tip)),
+    );
+    if print_debug {
+        verifier.set_debug_printer(|script_hash, message| {
+            println!("script: {:x}, debug: {}", script_hash, message);
+        });
+    }
+    verifier
+        .verify(max_cycles)
+        .map_err(|err| format!("Verify script error: {:?}", err))
+}
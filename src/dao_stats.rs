@@ -0,0 +1,122 @@
+//! The Nervos DAO statistics in the `dao` field of the block headers.
+//!
+//! Every header carries the total issuance `C`, the accumulated rate `AR`,
+//! the unissued secondary issuance `S` and the occupied capacity `U` at the
+//! end of the block. A deposit grows with `AR`: a capacity counted at the
+//! deposit block is worth `capacity * AR_withdraw / AR_deposit` later.
+
+use ckb_dao_utils::{extract_dao_data, pack_dao_data};
+use ckb_types::{
+    core::{Capacity, HeaderView},
+    packed::Byte32,
+};
+
+/// The accumulated rate of the genesis block
+pub const GENESIS_ACCUMULATED_RATE: u64 = 10_000_000_000_000_000;
+
+const MILLISECONDS_IN_YEAR: u64 = 365 * 24 * 60 * 60 * 1000;
+
+/// The parsed `dao` field of a header, the capacities are in shannons
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DaoStats {
+    /// `C`, the total issuance
+    pub total_issuance: u64,
+    /// `AR`, the accumulated rate
+    pub accumulated_rate: u64,
+    /// `S`, the secondary issuance not issued to the DAO depositors
+    pub secondary_unissued: u64,
+    /// `U`, the total occupied capacity
+    pub occupied_capacity: u64,
+}
+
+impl DaoStats {
+    pub fn from_dao_field(dao: &Byte32) -> DaoStats {
+        let (accumulated_rate, total_issuance, secondary_unissued, occupied_capacity) =
+            extract_dao_data(dao.clone());
+        DaoStats {
+            total_issuance: total_issuance.as_u64(),
+            accumulated_rate,
+            secondary_unissued: secondary_unissued.as_u64(),
+            occupied_capacity: occupied_capacity.as_u64(),
+        }
+    }
+
+    pub fn from_header(header: &HeaderView) -> DaoStats {
+        DaoStats::from_dao_field(&header.dao())
+    }
+
+    pub fn to_dao_field(&self) -> Byte32 {
+        pack_dao_data(
+            self.accumulated_rate,
+            Capacity::shannons(self.total_issuance),
+            Capacity::shannons(self.secondary_unissued),
+            Capacity::shannons(self.occupied_capacity),
+        )
+    }
+
+    /// The value at `later` of `counted_capacity` deposited at `self`, the
+    /// occupied capacity of the deposit cell does not grow
+    pub fn grow(&self, later: &DaoStats, counted_capacity: u64) -> u64 {
+        (u128::from(counted_capacity) * u128::from(later.accumulated_rate)
+            / u128::from(self.accumulated_rate)) as u64
+    }
+
+    /// The growth ratio of a deposit from `self` to `later`, e.g. 1.02
+    pub fn rate_growth(&self, later: &DaoStats) -> f64 {
+        later.accumulated_rate as f64 / self.accumulated_rate as f64
+    }
+}
+
+/// The growth ratio of a deposit from the block of `from` to the block of
+/// `to`
+pub fn accumulated_rate_growth(from: &HeaderView, to: &HeaderView) -> f64 {
+    DaoStats::from_header(from).rate_growth(&DaoStats::from_header(to))
+}
+
+/// The annual percentage compensation of the deposits between the blocks of
+/// `from` and `to`, extrapolated from their timestamps, `None` when `to` is
+/// not later than `from`
+pub fn estimate_apc(from: &HeaderView, to: &HeaderView) -> Option<f64> {
+    let elapsed = to.timestamp().checked_sub(from.timestamp())?;
+    if elapsed == 0 {
+        return None;
+    }
+    let growth = accumulated_rate_growth(from, to) - 1.0;
+    Some(growth * MILLISECONDS_IN_YEAR as f64 / elapsed as f64 * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::HeaderBuilder, prelude::*};
+
+    #[test]
+    fn test_dao_stats() {
+        let stats = DaoStats {
+            total_issuance: 3_360_000_000 * 100_000_000,
+            accumulated_rate: GENESIS_ACCUMULATED_RATE,
+            secondary_unissued: 7,
+            occupied_capacity: 42,
+        };
+        let header = HeaderBuilder::default()
+            .dao(stats.to_dao_field())
+            .timestamp(0u64.pack())
+            .build();
+        assert_eq!(DaoStats::from_header(&header), stats);
+
+        // 2% after half a year
+        let later = DaoStats {
+            accumulated_rate: GENESIS_ACCUMULATED_RATE / 100 * 102,
+            ..stats
+        };
+        let later_header = HeaderBuilder::default()
+            .dao(later.to_dao_field())
+            .timestamp((MILLISECONDS_IN_YEAR / 2).pack())
+            .build();
+        assert_eq!(stats.grow(&later, 1_000_000), 1_020_000);
+        assert!((accumulated_rate_growth(&header, &later_header) - 1.02).abs() < 1e-12);
+        assert!((estimate_apc(&header, &later_header).unwrap() - 4.0).abs() < 1e-9);
+        assert_eq!(estimate_apc(&later_header, &header), None);
+        assert_eq!(estimate_apc(&header, &header), None);
+    }
+}
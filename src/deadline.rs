@@ -0,0 +1,209 @@
+//! Bound the time spent building a transaction.
+//!
+//! A web request handler creates a [`Deadline`] for the request and passes it
+//! to the RPC clients ([`CkbRpcClient::with_deadline`](crate::CkbRpcClient::with_deadline)),
+//! so a slow node can't block a call past the deadline, and wraps the
+//! providers given to the builders with [`Deadline::cell_collector`] and
+//! [`Deadline::tx_dep_provider`], so the build stops with `DeadlineExceeded`
+//! once the deadline is reached.
+
+use std::time::{Duration, Instant};
+
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{Byte32, CellOutput, OutPoint, Transaction},
+};
+use thiserror::Error;
+
+use crate::traits::{
+    CellCollector, CellCollectorError, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyError, TransactionDependencyProvider,
+};
+
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+#[error("deadline exceeded")]
+pub struct DeadlineExceeded;
+
+/// A point in time after which the work is abandoned
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Deadline {
+    instant: Instant,
+}
+
+impl Deadline {
+    pub fn at(instant: Instant) -> Deadline {
+        Deadline { instant }
+    }
+
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline::at(Instant::now() + timeout)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// The time left, `None` when the deadline is reached
+    pub fn remaining(&self) -> Option<Duration> {
+        self.instant
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining().is_none()
+    }
+
+    /// The time left or [`DeadlineExceeded`]
+    pub fn check(&self) -> Result<Duration, DeadlineExceeded> {
+        self.remaining().ok_or(DeadlineExceeded)
+    }
+
+    pub fn cell_collector(&self, inner: Box<dyn CellCollector>) -> DeadlineCellCollector {
+        DeadlineCellCollector {
+            inner,
+            deadline: *self,
+        }
+    }
+
+    pub fn tx_dep_provider<'a>(
+        &self,
+        inner: &'a dyn TransactionDependencyProvider,
+    ) -> DeadlineTxDepProvider<'a> {
+        DeadlineTxDepProvider {
+            inner,
+            deadline: *self,
+        }
+    }
+
+    pub fn header_dep_resolver<'a>(
+        &self,
+        inner: &'a dyn HeaderDepResolver,
+    ) -> DeadlineHeaderDepResolver<'a> {
+        DeadlineHeaderDepResolver {
+            inner,
+            deadline: *self,
+        }
+    }
+
+    /// Run `call` if the deadline is not reached, a failure after the
+    /// deadline (e.g. an RPC timeout) is reported as [`DeadlineExceeded`].
+    fn guard<T, E, F>(&self, call: F) -> Result<T, E>
+    where
+        E: From<DeadlineExceeded>,
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.check()?;
+        match call() {
+            Err(_) if self.is_exceeded() => Err(DeadlineExceeded.into()),
+            result => result,
+        }
+    }
+}
+
+/// A cell collector failing with [`DeadlineExceeded`] after the deadline
+#[derive(Clone)]
+pub struct DeadlineCellCollector {
+    inner: Box<dyn CellCollector>,
+    deadline: Deadline,
+}
+
+impl DeadlineCellCollector {
+    /// The wrapped cell collector
+    pub fn into_inner(self) -> Box<dyn CellCollector> {
+        self.inner
+    }
+}
+
+impl CellCollector for DeadlineCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let inner = &mut self.inner;
+        self.deadline
+            .guard(|| inner.collect_live_cells(query, apply_changes))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+/// A transaction dependency provider failing with [`DeadlineExceeded`]
+/// after the deadline
+pub struct DeadlineTxDepProvider<'a> {
+    inner: &'a dyn TransactionDependencyProvider,
+    deadline: Deadline,
+}
+
+impl<'a> TransactionDependencyProvider for DeadlineTxDepProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        self.deadline.guard(|| self.inner.get_transaction(tx_hash))
+    }
+
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.deadline.guard(|| self.inner.get_cell(out_point))
+    }
+
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.deadline.guard(|| self.inner.get_cell_data(out_point))
+    }
+
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.deadline.guard(|| self.inner.get_header(block_hash))
+    }
+
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        self.deadline
+            .guard(|| self.inner.get_block_extension(block_hash))
+    }
+
+    fn get_cells_with_data(
+        &self,
+        out_points: &[OutPoint],
+    ) -> Result<Vec<(CellOutput, Bytes)>, TransactionDependencyError> {
+        self.deadline
+            .guard(|| self.inner.get_cells_with_data(out_points))
+    }
+}
+
+/// A header dep resolver failing with [`DeadlineExceeded`] after the deadline
+pub struct DeadlineHeaderDepResolver<'a> {
+    inner: &'a dyn HeaderDepResolver,
+    deadline: Deadline,
+}
+
+impl<'a> HeaderDepResolver for DeadlineHeaderDepResolver<'a> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.deadline.guard(|| self.inner.resolve_by_tx(tx_hash))
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.deadline.guard(|| self.inner.resolve_by_number(number))
+    }
+}
@@ -0,0 +1,407 @@
+//! Per-invoice deposit addresses.
+//!
+//! Merchants usually give every invoice its own deposit address, so incoming
+//! payments can be matched to invoices by lock script. [`DepositKeyDeriver`]
+//! derives a key pair from an account key and the invoice id, and
+//! [`DepositRegistry`] keeps track of the derived lock scripts, scans their
+//! cells and sweeps them to a treasury lock script. [`DepositWatcher`] finds
+//! the deposits in new blocks instead, driven by a
+//! [`ChainScanner`](crate::chain_scanner::ChainScanner).
+//!
+//! The derivation is `child_key = account_key + blake2b(account_pubkey || invoice_id)`,
+//! so a watch-only service holding only the account public key can derive the
+//! deposit addresses. It is not compatible with BIP32 wallets.
+
+use std::collections::HashMap;
+
+use ckb_hash::blake2b_256;
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockNumber, BlockView, ScriptHashType, TransactionView},
+    packed::{Byte32, OutPoint, Script, WitnessArgs},
+    prelude::*,
+    H160,
+};
+use secp256k1::{PublicKey, Scalar, SecretKey};
+use thiserror::Error;
+
+use crate::chain_scanner::{ChainScannerError, ScanHandler};
+use crate::constants::SIGHASH_TYPE_HASH;
+use crate::traits::{
+    CellCollector, CellCollectorError, CellQueryOptions, LiveCell, SecpCkbRawKeySigner,
+    ValueRangeOption,
+};
+use crate::tx_builder::{sweep::SweepBuilder, CapacityBalancer, CapacityProvider};
+use crate::types::ScriptId;
+use crate::unlock::{omni_lock::ConfigError, OmniLockConfig, OmniUnlockMode};
+use crate::util::blake160;
+use crate::SECP256K1;
+
+#[derive(Error, Debug)]
+pub enum DepositError {
+    #[error("invalid derivation tweak for invoice `{0}`")]
+    InvalidTweak(String),
+
+    #[error("the account secret key is required, the deriver is watch-only")]
+    WatchOnly,
+
+    #[error("unknown invoice: `{0}`")]
+    UnknownInvoice(String),
+
+    #[error("omni lock config error: `{0}`")]
+    OmniLockConfig(#[from] ConfigError),
+
+    #[error(transparent)]
+    CellCollector(#[from] CellCollectorError),
+}
+
+/// Derive per-invoice keys from an account key
+#[derive(Clone)]
+pub struct DepositKeyDeriver {
+    account_pubkey: PublicKey,
+    account_key: Option<SecretKey>,
+}
+
+impl DepositKeyDeriver {
+    pub fn new(account_key: SecretKey) -> DepositKeyDeriver {
+        DepositKeyDeriver {
+            account_pubkey: PublicKey::from_secret_key(&SECP256K1, &account_key),
+            account_key: Some(account_key),
+        }
+    }
+
+    /// A deriver which can derive lock scripts but not secret keys
+    pub fn watch_only(account_pubkey: PublicKey) -> DepositKeyDeriver {
+        DepositKeyDeriver {
+            account_pubkey,
+            account_key: None,
+        }
+    }
+
+    pub fn account_pubkey(&self) -> &PublicKey {
+        &self.account_pubkey
+    }
+
+    fn tweak(&self, invoice_id: &str) -> Result<Scalar, DepositError> {
+        let mut message = self.account_pubkey.serialize().to_vec();
+        message.extend_from_slice(invoice_id.as_bytes());
+        Scalar::from_be_bytes(blake2b_256(message))
+            .map_err(|_| DepositError::InvalidTweak(invoice_id.to_string()))
+    }
+
+    pub fn derive_pubkey(&self, invoice_id: &str) -> Result<PublicKey, DepositError> {
+        self.account_pubkey
+            .add_exp_tweak(&SECP256K1, &self.tweak(invoice_id)?)
+            .map_err(|_| DepositError::InvalidTweak(invoice_id.to_string()))
+    }
+
+    pub fn derive_secret_key(&self, invoice_id: &str) -> Result<SecretKey, DepositError> {
+        let account_key = self.account_key.ok_or(DepositError::WatchOnly)?;
+        account_key
+            .add_tweak(&self.tweak(invoice_id)?)
+            .map_err(|_| DepositError::InvalidTweak(invoice_id.to_string()))
+    }
+
+    /// The blake160 hash of the derived public key
+    pub fn derive_pubkey_hash(&self, invoice_id: &str) -> Result<H160, DepositError> {
+        Ok(blake160(&self.derive_pubkey(invoice_id)?.serialize()))
+    }
+}
+
+/// The lock script type of the deposit addresses
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum DepositLockKind {
+    Sighash,
+    /// Omnilock in pubkey hash mode, the value is the omnilock script id
+    Omnilock(ScriptId),
+}
+
+impl DepositLockKind {
+    fn build(&self, pubkey_hash: H160) -> Result<(Script, WitnessArgs), DepositError> {
+        match self {
+            DepositLockKind::Sighash => {
+                let script = Script::new_builder()
+                    .code_hash(SIGHASH_TYPE_HASH.pack())
+                    .hash_type(ScriptHashType::Type.into())
+                    .args(Bytes::from(pubkey_hash.as_bytes().to_vec()).pack())
+                    .build();
+                let placeholder_witness = WitnessArgs::new_builder()
+                    .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+                    .build();
+                Ok((script, placeholder_witness))
+            }
+            DepositLockKind::Omnilock(script_id) => {
+                let config = OmniLockConfig::new_pubkey_hash(pubkey_hash);
+                let script = Script::new_builder()
+                    .code_hash(script_id.code_hash.pack())
+                    .hash_type(script_id.hash_type.into())
+                    .args(config.build_args().pack())
+                    .build();
+                let placeholder_witness = config.placeholder_witness(OmniUnlockMode::Normal)?;
+                Ok((script, placeholder_witness))
+            }
+        }
+    }
+}
+
+/// A cell paid to a registered invoice
+#[derive(Debug, Clone)]
+pub struct Deposit {
+    pub invoice_id: String,
+    pub cell: LiveCell,
+}
+
+struct InvoiceLock {
+    lock_script: Script,
+    placeholder_witness: WitnessArgs,
+}
+
+/// Track the deposit lock scripts of invoices
+pub struct DepositRegistry {
+    deriver: DepositKeyDeriver,
+    lock_kind: DepositLockKind,
+    invoices: HashMap<String, InvoiceLock>,
+}
+
+impl DepositRegistry {
+    pub fn new(deriver: DepositKeyDeriver, lock_kind: DepositLockKind) -> DepositRegistry {
+        DepositRegistry {
+            deriver,
+            lock_kind,
+            invoices: HashMap::default(),
+        }
+    }
+
+    pub fn deriver(&self) -> &DepositKeyDeriver {
+        &self.deriver
+    }
+
+    /// Derive the deposit lock script of the invoice and start tracking it.
+    /// Registering the same invoice again returns the same lock script.
+    pub fn register(&mut self, invoice_id: &str) -> Result<Script, DepositError> {
+        if let Some(invoice_lock) = self.invoices.get(invoice_id) {
+            return Ok(invoice_lock.lock_script.clone());
+        }
+        let pubkey_hash = self.deriver.derive_pubkey_hash(invoice_id)?;
+        let (lock_script, placeholder_witness) = self.lock_kind.build(pubkey_hash)?;
+        self.invoices.insert(
+            invoice_id.to_string(),
+            InvoiceLock {
+                lock_script: lock_script.clone(),
+                placeholder_witness,
+            },
+        );
+        Ok(lock_script)
+    }
+
+    /// Stop tracking the invoice, e.g. after it is swept
+    pub fn unregister(&mut self, invoice_id: &str) -> Option<Script> {
+        self.invoices
+            .remove(invoice_id)
+            .map(|invoice_lock| invoice_lock.lock_script)
+    }
+
+    pub fn lock_script(&self, invoice_id: &str) -> Option<&Script> {
+        self.invoices
+            .get(invoice_id)
+            .map(|invoice_lock| &invoice_lock.lock_script)
+    }
+
+    /// Find the invoice paid by a cell with this lock script
+    pub fn invoice_of(&self, lock_script: &Script) -> Option<&str> {
+        self.invoices
+            .iter()
+            .find(|(_, invoice_lock)| &invoice_lock.lock_script == lock_script)
+            .map(|(invoice_id, _)| invoice_id.as_str())
+    }
+
+    /// Collect the plain cells (no type script and empty data) paid to the
+    /// registered invoices, the cells are not locked in the cell collector.
+    pub fn scan(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<Vec<Deposit>, DepositError> {
+        let mut deposits = Vec::new();
+        for (invoice_id, invoice_lock) in &self.invoices {
+            let mut query = CellQueryOptions::new_lock(invoice_lock.lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+            deposits.extend(cells.into_iter().map(|cell| Deposit {
+                invoice_id: invoice_id.clone(),
+                cell,
+            }));
+        }
+        deposits.sort_by(|a, b| a.invoice_id.cmp(&b.invoice_id));
+        Ok(deposits)
+    }
+
+    /// Build a sweep transaction builder and its balancer which move all the
+    /// deposits of `invoice_ids` to `treasury_lock`.
+    pub fn sweep(
+        &self,
+        invoice_ids: &[&str],
+        treasury_lock: Script,
+        fee_rate: u64,
+    ) -> Result<(SweepBuilder, CapacityBalancer), DepositError> {
+        let lock_scripts = invoice_ids
+            .iter()
+            .map(|invoice_id| {
+                self.invoices
+                    .get(*invoice_id)
+                    .map(|invoice_lock| {
+                        (
+                            invoice_lock.lock_script.clone(),
+                            invoice_lock.placeholder_witness.clone(),
+                        )
+                    })
+                    .ok_or_else(|| DepositError::UnknownInvoice(invoice_id.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut balancer = CapacityBalancer::new_with_provider(
+            fee_rate,
+            CapacityProvider::new_simple(lock_scripts.clone()),
+        );
+        balancer.change_lock_script = Some(treasury_lock);
+        Ok((SweepBuilder::new(lock_scripts), balancer))
+    }
+
+    /// The signer holding the derived secret keys of `invoice_ids`
+    pub fn signer(&self, invoice_ids: &[&str]) -> Result<SecpCkbRawKeySigner, DepositError> {
+        let keys = invoice_ids
+            .iter()
+            .map(|invoice_id| self.deriver.derive_secret_key(invoice_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SecpCkbRawKeySigner::new_with_secret_keys(keys))
+    }
+}
+
+/// Collect the plain cells paid to the invoices of a [`DepositRegistry`] from
+/// the scanned blocks, the deposits of the blocks rolled back are removed.
+/// Spending the deposits is not tracked.
+pub struct DepositWatcher<'a> {
+    registry: &'a DepositRegistry,
+    pub deposits: Vec<Deposit>,
+}
+
+impl<'a> DepositWatcher<'a> {
+    pub fn new(registry: &'a DepositRegistry) -> DepositWatcher<'a> {
+        DepositWatcher {
+            registry,
+            deposits: Vec::new(),
+        }
+    }
+}
+
+impl ScanHandler for DepositWatcher<'_> {
+    fn on_transaction(
+        &mut self,
+        block: &BlockView,
+        tx_index: usize,
+        tx: &TransactionView,
+    ) -> Result<(), ChainScannerError> {
+        for (index, (output, output_data)) in tx.outputs_with_data_iter().enumerate() {
+            if output.type_().is_some() || !output_data.is_empty() {
+                continue;
+            }
+            if let Some(invoice_id) = self.registry.invoice_of(&output.lock()) {
+                self.deposits.push(Deposit {
+                    invoice_id: invoice_id.to_string(),
+                    cell: LiveCell {
+                        output,
+                        output_data,
+                        out_point: OutPoint::new(tx.hash(), index as u32),
+                        block_number: block.number(),
+                        tx_index: tx_index as u32,
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn on_rollback(
+        &mut self,
+        number: BlockNumber,
+        _hash: &Byte32,
+    ) -> Result<(), ChainScannerError> {
+        self.deposits
+            .retain(|deposit| deposit.cell.block_number != number);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_deposit_keys() {
+        let account_key = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let deriver = DepositKeyDeriver::new(account_key);
+        let watch_only = DepositKeyDeriver::watch_only(*deriver.account_pubkey());
+
+        let key1 = deriver.derive_secret_key("invoice-1").unwrap();
+        assert_eq!(
+            PublicKey::from_secret_key(&SECP256K1, &key1),
+            watch_only.derive_pubkey("invoice-1").unwrap()
+        );
+        assert_eq!(deriver.derive_secret_key("invoice-1").unwrap(), key1);
+        assert_ne!(deriver.derive_secret_key("invoice-2").unwrap(), key1);
+        assert!(matches!(
+            watch_only.derive_secret_key("invoice-1"),
+            Err(DepositError::WatchOnly)
+        ));
+
+        let mut registry = DepositRegistry::new(watch_only, DepositLockKind::Sighash);
+        let lock_script = registry.register("invoice-1").unwrap();
+        assert_eq!(registry.register("invoice-1").unwrap(), lock_script);
+        assert_eq!(registry.invoice_of(&lock_script), Some("invoice-1"));
+        assert_eq!(
+            lock_script.args().raw_data().as_ref(),
+            deriver.derive_pubkey_hash("invoice-1").unwrap().as_bytes()
+        );
+        assert!(matches!(
+            registry.sweep(&["invoice-3"], lock_script, 1000),
+            Err(DepositError::UnknownInvoice(_))
+        ));
+    }
+    #[test]
+    fn test_deposit_watcher() {
+        use ckb_types::{
+            core::{BlockBuilder, TransactionBuilder},
+            packed::CellOutput,
+        };
+
+        let deriver = DepositKeyDeriver::new(SecretKey::from_slice(&[1u8; 32]).unwrap());
+        let mut registry = DepositRegistry::new(deriver, DepositLockKind::Sighash);
+        let lock_script = registry.register("invoice-1").unwrap();
+        let tx = TransactionBuilder::default()
+            .output(
+                CellOutput::new_builder()
+                    .capacity(100u64.pack())
+                    .lock(lock_script)
+                    .build(),
+            )
+            .output_data(Bytes::new().pack())
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Bytes::new().pack())
+            .build();
+        let block = BlockBuilder::default()
+            .number(5u64.pack())
+            .transaction(tx.clone())
+            .build();
+
+        let mut watcher = DepositWatcher::new(&registry);
+        watcher.on_transaction(&block, 0, &tx).unwrap();
+        assert_eq!(watcher.deposits.len(), 1);
+        assert_eq!(watcher.deposits[0].invoice_id, "invoice-1");
+        assert_eq!(
+            watcher.deposits[0].cell.out_point,
+            OutPoint::new(tx.hash(), 0)
+        );
+        watcher.on_rollback(5, &block.hash()).unwrap();
+        assert!(watcher.deposits.is_empty());
+    }
+}
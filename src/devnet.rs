@@ -0,0 +1,718 @@
+//! Bootstrap the script environment of a local devnet.
+//!
+//! Devnets usually deploy the contracts they need (omnilock, sUDT, ...) as
+//! genesis system cells. [`bootstrap_dev_env`] downloads the genesis block,
+//! resolves the system scripts like [`DefaultCellDepResolver::from_genesis`]
+//! and registers the cell deps of the [`DevContract`]s found in it.
+//!
+//! ```no_run
+//! # fn run() -> Result<(), ckb_sdk::devnet::DevEnvError> {
+//! let scripts = ckb_sdk::devnet::bootstrap_dev_env("http://localhost:8114")?;
+//! if let Some(omnilock) = scripts.contract("omnilock") {
+//!     println!("omnilock deployed at {}", omnilock.script_id);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`GenesisFunder`] funds the test accounts from the genesis issued cells of
+//! the [`DEV_GENESIS_KEYS`] without an indexer, [`DevnetHarness`] funds them
+//! from any cell of its funder through the indexer.
+
+#[cfg(feature = "indexer")]
+use std::collections::HashMap;
+#[cfg(feature = "indexer")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "indexer")]
+use std::thread;
+#[cfg(feature = "indexer")]
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "indexer")]
+use ckb_hash::blake2b_256;
+#[cfg(feature = "indexer")]
+use ckb_jsonrpc_types as json_types;
+#[cfg(feature = "indexer")]
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionView},
+    packed::{Script, WitnessArgs},
+};
+use ckb_types::{
+    core::{BlockView, DepType},
+    h256,
+    packed::{CellDep, CellOutput, OutPoint},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+#[cfg(feature = "indexer")]
+use crate::cell_snapshot::SnapshotCellCollector;
+#[cfg(feature = "indexer")]
+use crate::constants::{MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH};
+use crate::traits::{default_impls::ParseGenesisInfoError, DefaultCellDepResolver};
+#[cfg(feature = "indexer")]
+use crate::traits::{
+    dummy_impls::DummyHeaderDepResolver, CellCollector, DefaultCellCollector,
+    DefaultHeaderDepResolver, DefaultTransactionDependencyProvider, LiveCell,
+    OffchainTransactionDependencyProvider, SecpCkbRawKeySigner,
+};
+#[cfg(feature = "indexer")]
+use crate::tx_builder::{
+    transfer::CapacityTransferBuilder, CapacityBalancer, CapacityProvider, TxBuilder,
+    TxBuilderError,
+};
+use crate::types::{NetworkInfo, NetworkType, ScriptId};
+#[cfg(feature = "indexer")]
+use crate::unlock::{MultisigConfig, ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker};
+#[cfg(feature = "indexer")]
+use crate::SECP256K1;
+use crate::{CkbRpcClient, RpcError};
+
+/// The private keys of the genesis issued cells of the dev chain spec
+/// (`ckb init --chain dev`), they are public and only fit a devnet
+pub const DEV_GENESIS_KEYS: [H256; 2] = [
+    h256!("0xd00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc"),
+    h256!("0x63d86723e08f0f813a36ce6aa123bb2289d90680ae1e99d4de8cdb334553f24d"),
+];
+
+/// The environment variable holding the rpc url of the devnet the
+/// integration tests attach to
+pub const DEVNET_RPC_URL_ENV: &str = "CKB_DEVNET_RPC_URL";
+
+#[derive(Error, Debug)]
+pub enum DevEnvError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("genesis block not found")]
+    GenesisNotFound,
+
+    #[error("parse genesis info error: `{0}`")]
+    ParseGenesisInfo(#[from] ParseGenesisInfoError),
+
+    #[cfg(feature = "indexer")]
+    #[error("build transaction error: `{0}`")]
+    Build(#[from] TxBuilderError),
+
+    #[error("invalid secret key: `{0}`")]
+    InvalidKey(String),
+
+    #[error("transaction `{0:#x}` rejected: `{1}`")]
+    Rejected(H256, String),
+
+    #[error("transaction `{0:#x}` not committed in time")]
+    Timeout(H256),
+
+    #[error("no genesis issued cell of the funding keys")]
+    NoIssuedCell,
+}
+
+/// A contract looked up in the genesis block by the hash of its binary
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DevContract {
+    pub name: String,
+    pub data_hash: H256,
+}
+
+impl DevContract {
+    pub fn new<S: Into<String>>(name: S, data_hash: H256) -> DevContract {
+        DevContract {
+            name: name.into(),
+            data_hash,
+        }
+    }
+
+    /// The omnilock and sUDT releases deployed by common devnet setups
+    pub fn defaults() -> Vec<DevContract> {
+        vec![
+            DevContract::new(
+                "omnilock",
+                h256!("0x635f78eba450cb2f73f113022ff62e4bbfb5a39b7368c375c6a731ba4c85c59e"),
+            ),
+            DevContract::new(
+                "sudt",
+                h256!("0xe1e354d6d643ad42724d40967e334984534e0367405c5ae42a9d7d63d77df419"),
+            ),
+        ]
+    }
+}
+
+/// A contract found in the genesis block
+#[derive(Debug, Clone)]
+pub struct DeployedContract {
+    pub name: String,
+    /// The type script hash of the code cell (e.g. type id) or the data hash
+    pub script_id: ScriptId,
+    pub cell_dep: CellDep,
+}
+
+/// The network info and the script cell deps of a devnet
+#[derive(Clone)]
+pub struct NetworkScripts {
+    pub network_info: NetworkInfo,
+    pub cell_dep_resolver: DefaultCellDepResolver,
+    pub contracts: Vec<DeployedContract>,
+}
+
+impl NetworkScripts {
+    /// Resolve the system scripts and `contracts` from a genesis block
+    pub fn from_genesis(
+        network_info: NetworkInfo,
+        genesis_block: &BlockView,
+        contracts: &[DevContract],
+    ) -> Result<NetworkScripts, DevEnvError> {
+        let mut cell_dep_resolver = DefaultCellDepResolver::from_genesis(genesis_block)?;
+        let contracts = detect_contracts(genesis_block, contracts);
+        for contract in &contracts {
+            cell_dep_resolver.insert(
+                contract.script_id.clone(),
+                contract.cell_dep.clone(),
+                contract.name.clone(),
+            );
+        }
+        Ok(NetworkScripts {
+            network_info,
+            cell_dep_resolver,
+            contracts,
+        })
+    }
+
+    pub fn contract(&self, name: &str) -> Option<&DeployedContract> {
+        self.contracts.iter().find(|contract| contract.name == name)
+    }
+}
+
+/// Find the code cells of `contracts` in the genesis block. A contract
+/// deployed with a type script is resolved by both its type hash and data
+/// hash, so there are two entries for it.
+pub fn detect_contracts(
+    genesis_block: &BlockView,
+    contracts: &[DevContract],
+) -> Vec<DeployedContract> {
+    let mut deployed = Vec::new();
+    for tx in genesis_block.transactions() {
+        for (index, (output, data)) in tx.outputs().into_iter().zip(tx.outputs_data()).enumerate() {
+            let data_hash: H256 = CellOutput::calc_data_hash(&data.raw_data()).unpack();
+            let contract = match contracts.iter().find(|c| c.data_hash == data_hash) {
+                Some(contract) => contract,
+                None => continue,
+            };
+            let cell_dep = CellDep::new_builder()
+                .out_point(OutPoint::new(tx.hash(), index as u32))
+                .dep_type(DepType::Code.into())
+                .build();
+            if let Some(type_script) = output.type_().to_opt() {
+                deployed.push(DeployedContract {
+                    name: contract.name.clone(),
+                    script_id: ScriptId::new_type(type_script.calc_script_hash().unpack()),
+                    cell_dep: cell_dep.clone(),
+                });
+            }
+            deployed.push(DeployedContract {
+                name: contract.name.clone(),
+                script_id: ScriptId::new_data1(data_hash),
+                cell_dep,
+            });
+        }
+    }
+    deployed
+}
+
+/// Build the [`NetworkScripts`] of the devnet node at `rpc_url` with the
+/// [`DevContract::defaults`]
+pub fn bootstrap_dev_env(rpc_url: &str) -> Result<NetworkScripts, DevEnvError> {
+    bootstrap_dev_env_with(rpc_url, &DevContract::defaults())
+}
+
+/// Build the [`NetworkScripts`] of the devnet node at `rpc_url`, looking for
+/// `contracts` in its genesis block
+pub fn bootstrap_dev_env_with(
+    rpc_url: &str,
+    contracts: &[DevContract],
+) -> Result<NetworkScripts, DevEnvError> {
+    let genesis_block = CkbRpcClient::new(rpc_url)
+        .get_block_by_number(0.into())?
+        .ok_or(DevEnvError::GenesisNotFound)?;
+    NetworkScripts::from_genesis(
+        NetworkInfo::new(NetworkType::Dev, rpc_url.to_string()),
+        &BlockView::from(genesis_block),
+        contracts,
+    )
+}
+
+/// Drive a devnet from the integration tests: fund accounts from a genesis
+/// issued cell, send transactions and generate the blocks committing them.
+///
+/// Generating blocks needs the `IntegrationTest` rpc module, the cell
+/// collector needs the `Indexer` module.
+#[cfg(feature = "indexer")]
+pub struct DevnetHarness {
+    pub rpc_url: String,
+    pub ckb_client: CkbRpcClient,
+    pub scripts: NetworkScripts,
+    funder: secp256k1::SecretKey,
+    generate_blocks: bool,
+    fee_rate: u64,
+}
+
+#[cfg(feature = "indexer")]
+impl DevnetHarness {
+    /// Attach to the devnet node at `rpc_url`, the first genesis issued
+    /// cell funds the accounts.
+    pub fn attach(rpc_url: &str) -> Result<DevnetHarness, DevEnvError> {
+        Ok(DevnetHarness {
+            rpc_url: rpc_url.to_string(),
+            ckb_client: CkbRpcClient::new(rpc_url),
+            scripts: bootstrap_dev_env(rpc_url)?,
+            funder: secret_key(&DEV_GENESIS_KEYS[0])?,
+            generate_blocks: true,
+            fee_rate: 1000,
+        })
+    }
+
+    /// Attach to the devnet at [`DEVNET_RPC_URL_ENV`], `None` when it is
+    /// not set
+    pub fn from_env() -> Option<Result<DevnetHarness, DevEnvError>> {
+        let rpc_url = std::env::var(DEVNET_RPC_URL_ENV).ok()?;
+        Some(DevnetHarness::attach(&rpc_url))
+    }
+
+    pub fn with_funder(mut self, funder: secp256k1::SecretKey) -> Self {
+        self.funder = funder;
+        self
+    }
+
+    /// Whether to generate blocks while waiting for a transaction, disable
+    /// it when the devnet runs a miner
+    pub fn with_block_generation(mut self, generate_blocks: bool) -> Self {
+        self.generate_blocks = generate_blocks;
+        self
+    }
+
+    pub fn fee_rate(&self) -> u64 {
+        self.fee_rate
+    }
+
+    pub fn cell_collector(&self) -> DefaultCellCollector {
+        DefaultCellCollector::new(&self.rpc_url)
+    }
+
+    pub fn tx_dep_provider(&self) -> DefaultTransactionDependencyProvider {
+        DefaultTransactionDependencyProvider::new(&self.rpc_url, 10)
+    }
+
+    pub fn header_dep_resolver(&self) -> DefaultHeaderDepResolver {
+        DefaultHeaderDepResolver::new(&self.rpc_url)
+    }
+
+    /// A balancer paying the fee from the sighash cells of `key`
+    pub fn balancer(&self, key: &secp256k1::SecretKey) -> CapacityBalancer {
+        let placeholder_witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        CapacityBalancer::new_simple(sighash_lock(key), placeholder_witness, self.fee_rate)
+    }
+
+    /// Build, sign and send a transfer of `capacity` shannons from the
+    /// funder to `lock`
+    pub fn fund(&self, lock: &Script, capacity: u64) -> Result<H256, DevEnvError> {
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock.clone())
+            .build();
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        let (tx, _) = builder.build_unlocked(
+            &mut self.cell_collector(),
+            &self.scripts.cell_dep_resolver,
+            &self.header_dep_resolver(),
+            &self.tx_dep_provider(),
+            &self.balancer(&self.funder),
+            &sighash_unlockers(vec![self.funder]),
+        )?;
+        self.send(&tx)
+    }
+
+    /// A new sighash account holding a cell of `capacity` shannons, the
+    /// funding is committed when it returns
+    pub fn new_account(
+        &self,
+        capacity: u64,
+        timeout: Duration,
+    ) -> Result<(secp256k1::SecretKey, Script), DevEnvError> {
+        let key = self.fresh_key()?;
+        let lock = sighash_lock(&key);
+        let tx_hash = self.fund(&lock, capacity)?;
+        self.wait_committed(&tx_hash, timeout)?;
+        Ok((key, lock))
+    }
+
+    pub fn send(&self, tx: &TransactionView) -> Result<H256, DevEnvError> {
+        let tx_hash = self.ckb_client.send_transaction(
+            json_types::TransactionView::from(tx.clone()).inner,
+            Some(json_types::OutputsValidator::Passthrough),
+        )?;
+        Ok(tx_hash)
+    }
+
+    /// Wait until the transaction is committed and indexed, generating the
+    /// blocks when enabled
+    pub fn wait_committed(&self, tx_hash: &H256, timeout: Duration) -> Result<(), DevEnvError> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            let tx_status = self
+                .ckb_client
+                .get_transaction(tx_hash.clone())?
+                .map(|tx| tx.tx_status);
+            match tx_status {
+                Some(status) if status.status == json_types::Status::Committed => {
+                    let committed = status.block_number.map(|number| number.value());
+                    let indexed = self
+                        .ckb_client
+                        .get_indexer_tip()?
+                        .map(|tip| tip.block_number.value());
+                    if indexed >= committed {
+                        return Ok(());
+                    }
+                }
+                Some(status) if status.status == json_types::Status::Rejected => {
+                    return Err(DevEnvError::Rejected(
+                        tx_hash.clone(),
+                        status.reason.unwrap_or_default(),
+                    ));
+                }
+                _ => {
+                    if self.generate_blocks {
+                        self.ckb_client.generate_block()?;
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Err(DevEnvError::Timeout(tx_hash.clone()))
+    }
+
+    /// A key unique to this harness run
+    fn fresh_key(&self) -> Result<secp256k1::SecretKey, DevEnvError> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let mut seed = nanos.to_le_bytes().to_vec();
+        seed.extend_from_slice(&COUNTER.fetch_add(1, Ordering::SeqCst).to_le_bytes());
+        seed.extend_from_slice(&std::process::id().to_le_bytes());
+        secret_key(&H256(blake2b_256(seed)))
+    }
+}
+
+/// Fund accounts from the genesis issued cells of some dev keys, without an
+/// indexer.
+///
+/// The change of every transfer funds the next one, the transfers are
+/// chained and can be sent without waiting for the previous ones to commit.
+/// The issued cells must still be live, i.e. the devnet is fresh or only
+/// funded by this funder.
+#[cfg(feature = "indexer")]
+pub struct GenesisFunder {
+    keys: Vec<secp256k1::SecretKey>,
+    cell_dep_resolver: DefaultCellDepResolver,
+    cell_collector: SnapshotCellCollector,
+    tx_dep_provider: OffchainTransactionDependencyProvider,
+    fee_rate: u64,
+}
+
+#[cfg(feature = "indexer")]
+impl GenesisFunder {
+    /// Spend the issued cells of `keys` found in the genesis block
+    pub fn new(
+        scripts: &NetworkScripts,
+        genesis_block: &BlockView,
+        keys: Vec<secp256k1::SecretKey>,
+    ) -> Result<GenesisFunder, DevEnvError> {
+        let locks = keys.iter().map(sighash_lock).collect::<Vec<_>>();
+        let cells = genesis_issued_cells(genesis_block, &locks);
+        GenesisFunder::from_cells(scripts.cell_dep_resolver.clone(), cells, keys)
+    }
+
+    /// Spend `cells` locked by the sighash locks of `keys`
+    pub fn from_cells(
+        cell_dep_resolver: DefaultCellDepResolver,
+        cells: Vec<LiveCell>,
+        keys: Vec<secp256k1::SecretKey>,
+    ) -> Result<GenesisFunder, DevEnvError> {
+        if cells.is_empty() {
+            return Err(DevEnvError::NoIssuedCell);
+        }
+        let mut tx_dep_provider = OffchainTransactionDependencyProvider::default();
+        for cell in &cells {
+            tx_dep_provider.cells.insert(
+                (
+                    cell.out_point.tx_hash().unpack(),
+                    cell.out_point.index().unpack(),
+                ),
+                (cell.output.clone(), cell.output_data.clone()),
+            );
+        }
+        Ok(GenesisFunder {
+            keys,
+            cell_dep_resolver,
+            cell_collector: SnapshotCellCollector::new(cells, 0),
+            tx_dep_provider,
+            fee_rate: 1000,
+        })
+    }
+
+    /// Fund from the issued cells of the [`DEV_GENESIS_KEYS`] of the devnet
+    /// node at `rpc_url`
+    pub fn attach(rpc_url: &str) -> Result<GenesisFunder, DevEnvError> {
+        let genesis_block = CkbRpcClient::new(rpc_url)
+            .get_block_by_number(0.into())?
+            .ok_or(DevEnvError::GenesisNotFound)?;
+        let genesis_block = BlockView::from(genesis_block);
+        let scripts = NetworkScripts::from_genesis(
+            NetworkInfo::new(NetworkType::Dev, rpc_url.to_string()),
+            &genesis_block,
+            &DevContract::defaults(),
+        )?;
+        let keys = DEV_GENESIS_KEYS
+            .iter()
+            .map(secret_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        GenesisFunder::new(&scripts, &genesis_block, keys)
+    }
+
+    pub fn with_fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Build and sign a transfer of `outputs`, the change goes back to the
+    /// first key. Send the returned transaction, e.g. with
+    /// [`DevnetHarness::send`].
+    pub fn transfer(
+        &mut self,
+        outputs: Vec<(CellOutput, Bytes)>,
+    ) -> Result<TransactionView, DevEnvError> {
+        let placeholder_witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build();
+        let locks = self
+            .keys
+            .iter()
+            .map(|key| (sighash_lock(key), placeholder_witness.clone()))
+            .collect::<Vec<_>>();
+        let change_lock = locks.first().map(|(lock, _)| lock.clone());
+        let mut balancer =
+            CapacityBalancer::new_with_provider(self.fee_rate, CapacityProvider::new_simple(locks));
+        balancer.change_lock_script = change_lock;
+        let (tx, _) = CapacityTransferBuilder::new(outputs).build_unlocked(
+            &mut self.cell_collector,
+            &self.cell_dep_resolver,
+            &DummyHeaderDepResolver,
+            &self.tx_dep_provider,
+            &balancer,
+            &sighash_unlockers(self.keys.clone()),
+        )?;
+        self.cell_collector
+            .apply_tx(tx.data(), 0)
+            .map_err(TxBuilderError::from)?;
+        for (index, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+            self.tx_dep_provider
+                .cells
+                .insert((tx.hash().unpack(), index as u32), (output, data));
+        }
+        Ok(tx)
+    }
+
+    /// Build and sign a transfer of `capacity` shannons to `lock`
+    pub fn fund(&mut self, lock: &Script, capacity: u64) -> Result<TransactionView, DevEnvError> {
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock.clone())
+            .build();
+        self.transfer(vec![(output, Bytes::default())])
+    }
+}
+
+/// The cells of the genesis block locked by `locks`, the issued cells of
+/// the chain spec are in the cellbase
+#[cfg(feature = "indexer")]
+pub fn genesis_issued_cells(genesis_block: &BlockView, locks: &[Script]) -> Vec<LiveCell> {
+    let mut cells = Vec::new();
+    for (tx_index, tx) in genesis_block.transactions().iter().enumerate() {
+        for (index, (output, output_data)) in tx.outputs_with_data_iter().enumerate() {
+            if locks.contains(&output.lock()) {
+                cells.push(LiveCell {
+                    output,
+                    output_data,
+                    out_point: OutPoint::new(tx.hash(), index as u32),
+                    block_number: 0,
+                    tx_index: tx_index as u32,
+                });
+            }
+        }
+    }
+    cells
+}
+
+#[cfg(feature = "indexer")]
+fn secret_key(key: &H256) -> Result<secp256k1::SecretKey, DevEnvError> {
+    secp256k1::SecretKey::from_slice(key.as_bytes())
+        .map_err(|err| DevEnvError::InvalidKey(err.to_string()))
+}
+
+/// The sighash lock script of `key`
+#[cfg(feature = "indexer")]
+pub fn sighash_lock(key: &secp256k1::SecretKey) -> Script {
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, key);
+    Script::new_builder()
+        .code_hash(SIGHASH_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(blake2b_256(pubkey.serialize())[0..20].to_vec()).pack())
+        .build()
+}
+
+/// The multisig lock script of `config`
+#[cfg(feature = "indexer")]
+pub fn multisig_lock(config: &MultisigConfig) -> Script {
+    Script::new_builder()
+        .code_hash(MULTISIG_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(config.hash160().0.to_vec()).pack())
+        .build()
+}
+
+#[cfg(feature = "indexer")]
+pub fn sighash_unlockers(
+    keys: Vec<secp256k1::SecretKey>,
+) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    unlockers
+}
+
+#[cfg(feature = "indexer")]
+pub fn multisig_unlockers(
+    keys: Vec<secp256k1::SecretKey>,
+    config: MultisigConfig,
+) -> HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(MULTISIG_TYPE_HASH.clone()),
+        Box::new(SecpMultisigUnlocker::from((
+            Box::new(signer) as Box<_>,
+            config,
+        ))),
+    );
+    unlockers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::CellDepResolver;
+    use ckb_jsonrpc_types as json_types;
+    use ckb_types::packed::Script;
+
+    const GENESIS_JSON: &str = include_str!("test-data/genesis_block.json");
+
+    #[test]
+    fn test_detect_genesis_contracts() {
+        let genesis_block: json_types::BlockView = serde_json::from_str(GENESIS_JSON).unwrap();
+        let genesis_block: BlockView = genesis_block.into();
+        // the sighash binary is the second output of the cellbase
+        let cellbase = genesis_block.transaction(0).unwrap();
+        let data_hash: H256 =
+            CellOutput::calc_data_hash(&cellbase.outputs_data().get(1).unwrap().raw_data())
+                .unpack();
+        let contracts = vec![
+            DevContract::new("sighash", data_hash.clone()),
+            DevContract::new("missing", H256::default()),
+        ];
+        let scripts =
+            NetworkScripts::from_genesis(NetworkInfo::devnet(), &genesis_block, &contracts)
+                .unwrap();
+        assert_eq!(scripts.contracts.len(), 2);
+        assert!(scripts.contract("missing").is_none());
+        let sighash = scripts.contract("sighash").unwrap();
+        assert_eq!(
+            sighash.cell_dep.out_point().as_slice(),
+            OutPoint::new(cellbase.hash(), 1).as_slice()
+        );
+
+        let script = Script::new_builder()
+            .code_hash(data_hash.pack())
+            .hash_type(ckb_types::core::ScriptHashType::Data1.into())
+            .build();
+        assert_eq!(
+            scripts
+                .cell_dep_resolver
+                .resolve(&script)
+                .map(|cell_dep| cell_dep.as_bytes()),
+            Some(sighash.cell_dep.as_bytes())
+        );
+    }
+
+    #[cfg(feature = "indexer")]
+    #[test]
+    fn test_genesis_funder() {
+        let genesis_block: json_types::BlockView = serde_json::from_str(GENESIS_JSON).unwrap();
+        let genesis_block: BlockView = genesis_block.into();
+        let scripts =
+            NetworkScripts::from_genesis(NetworkInfo::devnet(), &genesis_block, &[]).unwrap();
+        let key = secret_key(&DEV_GENESIS_KEYS[0]).unwrap();
+        let lock = sighash_lock(&key);
+        // the testnet genesis has no issued cell of the dev keys
+        assert!(genesis_issued_cells(&genesis_block, &[lock.clone()]).is_empty());
+        assert!(matches!(
+            GenesisFunder::new(&scripts, &genesis_block, vec![key]),
+            Err(DevEnvError::NoIssuedCell)
+        ));
+
+        let issued = LiveCell {
+            output: CellOutput::new_builder()
+                .capacity(100_000_000_000u64.pack())
+                .lock(lock.clone())
+                .build(),
+            output_data: Bytes::new(),
+            out_point: OutPoint::new(genesis_block.transaction(0).unwrap().hash(), 42),
+            block_number: 0,
+            tx_index: 0,
+        };
+        let mut funder = GenesisFunder::from_cells(
+            scripts.cell_dep_resolver.clone(),
+            vec![issued.clone()],
+            vec![key],
+        )
+        .unwrap();
+        let target = sighash_lock(&secret_key(&DEV_GENESIS_KEYS[1]).unwrap());
+        let first = funder.fund(&target, 10_000_000_000).unwrap();
+        assert_eq!(
+            first.input_pts_iter().collect::<Vec<_>>(),
+            vec![issued.out_point]
+        );
+        assert_eq!(first.output(0).unwrap().lock(), target);
+        assert_eq!(first.output(1).unwrap().lock(), lock);
+        let witness =
+            WitnessArgs::from_slice(&first.witnesses().get(0).unwrap().raw_data()).unwrap();
+        assert_ne!(
+            witness.lock().to_opt().unwrap().raw_data(),
+            Bytes::from(vec![0u8; 65])
+        );
+
+        // the change of the first transfer funds the second
+        let second = funder.fund(&target, 10_000_000_000).unwrap();
+        assert_eq!(
+            second.input_pts_iter().collect::<Vec<_>>(),
+            vec![OutPoint::new(first.hash(), 1)]
+        );
+        assert!(funder.fund(&target, 100_000_000_000).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+//! The cobuild witness layouts and signing messages.
+
+use super::{Experimental, Stability};
+
+pub use crate::unlock::{
+    cobuild_signing_message, detect_witness_layout, CobuildSighashWitness, WitnessLayoutKind,
+    COBUILD_SIGHASH_ALL_ONLY_PERSONALIZATION, COBUILD_SIGHASH_ALL_PERSONALIZATION, OTX_LAYOUT_ID,
+    OTX_START_LAYOUT_ID, SIGHASH_ALL_LAYOUT_ID, SIGHASH_ALL_ONLY_LAYOUT_ID,
+};
+
+/// The marker of the cobuild subsystem
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Cobuild;
+
+impl Experimental for Cobuild {
+    const NAME: &'static str = "cobuild";
+    const STABILITY: Stability = Stability::Preview;
+}
@@ -0,0 +1,37 @@
+//! Subsystems still being designed.
+//!
+//! Nothing under this module follows semver: a minor release may change or
+//! remove any item. The module needs the `experimental` feature, so the
+//! builders and signers of the crate never depend on it. A subsystem moves
+//! out of this module once its API is settled.
+//!
+//! Every subsystem has a marker type implementing [`Experimental`] which
+//! tells its [`Stability`].
+
+pub mod cobuild;
+pub mod otx;
+pub mod rgbpp;
+
+/// How close an experimental subsystem is to being stabilized
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Stability {
+    /// The API and the data formats may change in any release
+    Draft,
+    /// The data formats are settled, the API may still change
+    Preview,
+}
+
+/// The marker of an experimental subsystem, it is not covered by semver
+pub trait Experimental {
+    const NAME: &'static str;
+    const STABILITY: Stability;
+}
+
+/// The name and the stability of every experimental subsystem
+pub fn subsystems() -> Vec<(&'static str, Stability)> {
+    vec![
+        (cobuild::Cobuild::NAME, cobuild::Cobuild::STABILITY),
+        (otx::Otx::NAME, otx::Otx::STABILITY),
+        (rgbpp::Rgbpp::NAME, rgbpp::Rgbpp::STABILITY),
+    ]
+}
@@ -0,0 +1,16 @@
+//! The open transactions (OTX) waiting to be aggregated.
+
+use super::{Experimental, Stability};
+
+pub use crate::otx_book::{
+    LiveCellChecker, OpenTx, OtxBook, OtxBookError, StaleOtx, StaleOtxNotifier, StaleReason,
+};
+
+/// The marker of the open transaction subsystem
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Otx;
+
+impl Experimental for Otx {
+    const NAME: &'static str = "otx";
+    const STABILITY: Stability = Stability::Draft;
+}
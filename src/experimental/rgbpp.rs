@@ -0,0 +1,92 @@
+//! The RGB++ lock binding a CKB cell to a Bitcoin UTXO.
+//!
+//! The args of an RGB++ lock are the output index of the UTXO (`u32` little
+//! endian) followed by the Bitcoin transaction id in the byte order of the
+//! Bitcoin serialization, which is the reverse of the displayed txid.
+
+use ckb_types::{bytes::Bytes, H256};
+use thiserror::Error;
+
+use super::{Experimental, Stability};
+
+/// The length of the RGB++ lock args
+pub const RGBPP_LOCK_ARGS_LEN: usize = 4 + 32;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum RgbppError {
+    #[error("invalid RGB++ lock args length: `{0}`")]
+    InvalidArgsLength(usize),
+}
+
+/// The marker of the RGB++ subsystem
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Rgbpp;
+
+impl Experimental for Rgbpp {
+    const NAME: &'static str = "rgbpp";
+    const STABILITY: Stability = Stability::Draft;
+}
+
+/// The Bitcoin UTXO an RGB++ cell is bound to
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RgbppLockArgs {
+    pub out_index: u32,
+    /// The txid as displayed by the Bitcoin explorers
+    pub btc_txid: H256,
+}
+
+impl RgbppLockArgs {
+    pub fn new(out_index: u32, btc_txid: H256) -> RgbppLockArgs {
+        RgbppLockArgs {
+            out_index,
+            btc_txid,
+        }
+    }
+
+    pub fn from_slice(args: &[u8]) -> Result<RgbppLockArgs, RgbppError> {
+        if args.len() != RGBPP_LOCK_ARGS_LEN {
+            return Err(RgbppError::InvalidArgsLength(args.len()));
+        }
+        let mut out_index = [0u8; 4];
+        out_index.copy_from_slice(&args[..4]);
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&args[4..]);
+        txid.reverse();
+        Ok(RgbppLockArgs {
+            out_index: u32::from_le_bytes(out_index),
+            btc_txid: H256(txid),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut txid = self.btc_txid.0;
+        txid.reverse();
+        let mut args = Vec::with_capacity(RGBPP_LOCK_ARGS_LEN);
+        args.extend_from_slice(&self.out_index.to_le_bytes());
+        args.extend_from_slice(&txid);
+        Bytes::from(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h256;
+
+    #[test]
+    fn test_rgbpp_lock_args() {
+        let args = RgbppLockArgs::new(
+            2,
+            h256!("0x0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20"),
+        );
+        let bytes = args.to_bytes();
+        assert_eq!(&bytes[..4], &[2, 0, 0, 0]);
+        assert_eq!(bytes[4], 0x20);
+        assert_eq!(bytes[35], 0x01);
+        assert_eq!(RgbppLockArgs::from_slice(&bytes), Ok(args));
+        assert_eq!(
+            RgbppLockArgs::from_slice(&bytes[1..]),
+            Err(RgbppError::InvalidArgsLength(35))
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Claim testnet CKB from the public faucet.
+//!
+//! [`FaucetClient`] talks to the API behind <https://faucet.nervos.org>, the
+//! integration tests and the examples running against the testnet provision
+//! their accounts with it. The faucet rate limits the claims of an address
+//! and only accepts a few amounts, see [`FAUCET_AMOUNTS`]. On a devnet, fund
+//! the accounts from the genesis issued cells with
+//! [`GenesisFunder`](crate::devnet::GenesisFunder) instead.
+//!
+//! ```no_run
+//! # fn run(address: &ckb_sdk::Address) -> Result<(), ckb_sdk::faucet::FaucetError> {
+//! use std::time::Duration;
+//! use ckb_sdk::faucet::FaucetClient;
+//!
+//! let faucet = FaucetClient::testnet();
+//! faucet.claim(address, 10_000)?;
+//! let event = faucet.wait_processed(address, Duration::from_secs(300))?;
+//! println!("claimed in transaction {:?}", event.tx_hash);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ckb_types::H256;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::deadline::{Deadline, DeadlineExceeded};
+use crate::{Address, NetworkType};
+
+/// The faucet of the public testnet
+pub const TESTNET_FAUCET_URL: &str = "https://faucet-api.nervos.org";
+/// The amounts in CKB the faucet accepts
+pub const FAUCET_AMOUNTS: [u64; 3] = [10_000, 100_000, 300_000];
+
+#[derive(Error, Debug)]
+pub enum FaucetError {
+    #[error("http error: `{0}`")]
+    Http(#[from] reqwest::Error),
+
+    #[error("parse json error: `{0}`")]
+    Json(#[from] serde_json::Error),
+
+    #[error("the faucet only funds testnet addresses, got a `{0:?}` address")]
+    NotTestnet(NetworkType),
+
+    #[error("the faucet does not accept `{0}` CKB, the amounts are 10000, 100000 and 300000")]
+    InvalidAmount(u64),
+
+    #[error("the faucet rejected the claim with status `{status}`: `{message}`")]
+    Rejected { status: u16, message: String },
+
+    #[error("no processed claim in time")]
+    Timeout,
+
+    #[error(transparent)]
+    DeadlineExceeded(#[from] DeadlineExceeded),
+}
+
+/// A claim of an address, the transaction hash is set once the faucet sent
+/// the transfer
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClaimEvent {
+    pub address_hash: String,
+    /// The claimed capacity in shannons, as a decimal string
+    pub capacity: String,
+    /// `pending` or `processed`
+    pub status: String,
+    pub tx_hash: Option<H256>,
+    /// The status of the transfer in the chain, `pending` or `committed`
+    pub tx_status: Option<String>,
+    pub timestamp: Option<u64>,
+}
+
+impl ClaimEvent {
+    /// Whether the faucet sent the transfer of the claim
+    pub fn is_processed(&self) -> bool {
+        self.status == "processed" && self.tx_hash.is_some()
+    }
+}
+
+/// A JSON:API resource of the faucet
+#[derive(Deserialize)]
+struct Resource {
+    attributes: ClaimEvent,
+}
+
+#[derive(Deserialize)]
+struct Document<T> {
+    data: T,
+}
+
+/// A client of the testnet faucet API
+#[derive(Clone)]
+pub struct FaucetClient {
+    pub client: reqwest::blocking::Client,
+    pub url: reqwest::Url,
+    /// The calls fail with `FaucetError::DeadlineExceeded` after it
+    pub deadline: Option<Deadline>,
+}
+
+impl FaucetClient {
+    pub fn new(url: &str) -> FaucetClient {
+        let url =
+            reqwest::Url::parse(url).expect("faucet url, e.g. \"https://faucet-api.nervos.org\"");
+        FaucetClient {
+            client: reqwest::blocking::Client::new(),
+            url,
+            deadline: None,
+        }
+    }
+
+    /// The faucet of the public testnet
+    pub fn testnet() -> FaucetClient {
+        FaucetClient::new(TESTNET_FAUCET_URL)
+    }
+
+    /// Bound all the calls of the client by `deadline`
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Claim `amount` CKB to `address`, the transfer is sent later, see
+    /// [`FaucetClient::wait_processed`]
+    pub fn claim(&self, address: &Address, amount: u64) -> Result<ClaimEvent, FaucetError> {
+        check_address(address)?;
+        if !FAUCET_AMOUNTS.contains(&amount) {
+            return Err(FaucetError::InvalidAmount(amount));
+        }
+        let body = serde_json::json!({
+            "claim_event": {
+                "address_hash": address.to_string(),
+                "amount": amount.to_string(),
+            }
+        });
+        let request = self.client.post(self.endpoint()).json(&body);
+        let document: Document<Resource> = self.send(request)?;
+        Ok(document.data.attributes)
+    }
+
+    /// The recent claims of `address`, the latest first
+    pub fn claim_events(&self, address: &Address) -> Result<Vec<ClaimEvent>, FaucetError> {
+        check_address(address)?;
+        let request = self
+            .client
+            .get(self.endpoint())
+            .query(&[("address_hash", address.to_string())]);
+        let document: Document<Vec<Resource>> = self.send(request)?;
+        Ok(document
+            .data
+            .into_iter()
+            .map(|resource| resource.attributes)
+            .collect())
+    }
+
+    /// Poll the claims of `address` until the latest one is processed
+    pub fn wait_processed(
+        &self,
+        address: &Address,
+        timeout: Duration,
+    ) -> Result<ClaimEvent, FaucetError> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(event) = self.claim_events(address)?.into_iter().next() {
+                if event.is_processed() {
+                    return Ok(event);
+                }
+            }
+            thread::sleep(Duration::from_secs(5));
+        }
+        Err(FaucetError::Timeout)
+    }
+
+    fn endpoint(&self) -> reqwest::Url {
+        self.url
+            .join("claim_events")
+            .expect("claim_events is a valid relative url")
+    }
+
+    fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<T, FaucetError> {
+        let request = match self.deadline.as_ref() {
+            Some(deadline) => request.timeout(deadline.check()?),
+            None => request,
+        };
+        let response = request.header("Accept", "application/json").send()?;
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(FaucetError::Rejected {
+                status: status.as_u16(),
+                message: body,
+            });
+        }
+        serde_json::from_str(&body).map_err(Into::into)
+    }
+}
+
+fn check_address(address: &Address) -> Result<(), FaucetError> {
+    match address.network() {
+        NetworkType::Testnet => Ok(()),
+        network => Err(FaucetError::NotTestnet(network)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AddressPayload;
+    use ckb_types::{h256, H160};
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_faucet_claim() {
+        let payload = AddressPayload::from_pubkey_hash(H160([0x11; 20]));
+        let address = Address::new(NetworkType::Testnet, payload.clone(), true);
+        let tx_hash = h256!("0x01");
+        let server = MockServer::start();
+        let claim = server.mock(|when, then| {
+            when.method(POST)
+                .path("/claim_events")
+                .body_contains(address.to_string())
+                .body_contains("\"amount\":\"10000\"");
+            then.status(200).body(
+                serde_json::json!({
+                    "data": {
+                        "id": "1",
+                        "type": "claim_event",
+                        "attributes": {
+                            "addressHash": address.to_string(),
+                            "capacity": "1000000000000",
+                            "status": "pending",
+                            "txHash": null,
+                            "txStatus": "pending",
+                            "timestamp": 1700000000
+                        }
+                    }
+                })
+                .to_string(),
+            );
+        });
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/claim_events")
+                .query_param("address_hash", address.to_string());
+            then.status(200).body(
+                serde_json::json!({
+                    "data": [{
+                        "id": "1",
+                        "type": "claim_event",
+                        "attributes": {
+                            "addressHash": address.to_string(),
+                            "capacity": "1000000000000",
+                            "status": "processed",
+                            "txHash": tx_hash,
+                            "txStatus": "committed"
+                        }
+                    }]
+                })
+                .to_string(),
+            );
+        });
+
+        let faucet = FaucetClient::new(&server.base_url());
+        let event = faucet.claim(&address, 10_000).unwrap();
+        claim.assert();
+        assert_eq!(event.status, "pending");
+        assert!(!event.is_processed());
+        let event = faucet
+            .wait_processed(&address, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(event.tx_hash, Some(tx_hash));
+
+        assert!(matches!(
+            faucet.claim(&address, 1),
+            Err(FaucetError::InvalidAmount(1))
+        ));
+        let mainnet = Address::new(NetworkType::Mainnet, payload, true);
+        assert!(matches!(
+            faucet.claim(&mainnet, 10_000),
+            Err(FaucetError::NotTestnet(NetworkType::Mainnet))
+        ));
+    }
+}
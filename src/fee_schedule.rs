@@ -0,0 +1,332 @@
+//! Off-peak submission hints from the fee rate history.
+//!
+//! [`FeeRateHistory`] records the median pool fee rate over time and groups
+//! the samples by the UTC hour of the day. [`FeeRateHistory::suggest_submission_window`]
+//! then tells when the fee rate usually drops to a target, and
+//! [`SubmissionScheduler`] holds the low priority transactions until such a
+//! window (or their maximum delay) is reached.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ckb_types::core::TransactionView;
+use thiserror::Error;
+
+#[cfg(feature = "rpc")]
+use crate::rpc::CkbRpcClient;
+use crate::rpc::RpcError;
+
+const HOURS_PER_DAY: u64 = 24;
+const MS_PER_HOUR: u64 = 60 * 60 * 1000;
+
+/// The default number of samples kept, a week of samples taken every 10
+/// minutes
+pub const DEFAULT_MAX_SAMPLES: usize = 7 * 24 * 6;
+
+#[derive(Error, Debug)]
+pub enum FeeScheduleError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("the node returned no fee rate statistics")]
+    NoStatistics,
+}
+
+/// A time range in milliseconds since the unix epoch
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SubmissionWindow {
+    pub start: u64,
+    pub end: u64,
+    /// The highest hourly median fee rate of the window, in shannons/KB
+    pub expected_fee_rate: u64,
+}
+
+impl SubmissionWindow {
+    pub fn contains(&self, timestamp: u64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+}
+
+/// The recent median fee rates, the oldest samples are dropped once
+/// `max_samples` is reached.
+#[derive(Debug, Clone)]
+pub struct FeeRateHistory {
+    samples: VecDeque<(u64, u64)>,
+    max_samples: usize,
+}
+
+impl Default for FeeRateHistory {
+    fn default() -> Self {
+        FeeRateHistory::new(DEFAULT_MAX_SAMPLES)
+    }
+}
+
+impl FeeRateHistory {
+    pub fn new(max_samples: usize) -> FeeRateHistory {
+        FeeRateHistory {
+            samples: VecDeque::new(),
+            max_samples: max_samples.max(1),
+        }
+    }
+
+    /// Record the fee rate (shannons/KB) seen at `timestamp` (milliseconds
+    /// since the unix epoch, e.g. the tip block timestamp)
+    pub fn record(&mut self, timestamp: u64, fee_rate: u64) {
+        if self.samples.len() == self.max_samples {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((timestamp, fee_rate));
+    }
+
+    /// Record the median fee rate of the node statistics at the tip block
+    /// timestamp, returns the recorded fee rate.
+    #[cfg(feature = "rpc")]
+    pub fn record_from_rpc(&mut self, ckb_client: &CkbRpcClient) -> Result<u64, FeeScheduleError> {
+        let statistics = ckb_client
+            .get_fee_rate_statistics(None)?
+            .ok_or(FeeScheduleError::NoStatistics)?;
+        let timestamp = ckb_client.get_tip_header()?.inner.timestamp.value();
+        let fee_rate = statistics.median.value();
+        self.record(timestamp, fee_rate);
+        Ok(fee_rate)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The median fee rate of every UTC hour of the day, `None` for the hours
+    /// without samples
+    pub fn hourly_medians(&self) -> Vec<Option<u64>> {
+        let mut buckets = vec![Vec::new(); HOURS_PER_DAY as usize];
+        for (timestamp, fee_rate) in &self.samples {
+            buckets[hour_of_day(*timestamp) as usize].push(*fee_rate);
+        }
+        buckets
+            .into_iter()
+            .map(|mut fee_rates| {
+                if fee_rates.is_empty() {
+                    return None;
+                }
+                fee_rates.sort_unstable();
+                Some(fee_rates[fee_rates.len() / 2])
+            })
+            .collect()
+    }
+
+    /// The next window in which the fee rate is usually at most
+    /// `target_fee_rate`, see [`FeeRateHistory::suggest_submission_window_at`].
+    pub fn suggest_submission_window(&self, target_fee_rate: u64) -> Option<SubmissionWindow> {
+        self.suggest_submission_window_at(target_fee_rate, now_millis())
+    }
+
+    /// The next window starting within a day from `now` (milliseconds since
+    /// the unix epoch) made of the consecutive hours whose median fee rate
+    /// is at most `target_fee_rate`. The window starts at `now` when the
+    /// current hour qualifies. The hours without samples never qualify.
+    pub fn suggest_submission_window_at(
+        &self,
+        target_fee_rate: u64,
+        now: u64,
+    ) -> Option<SubmissionWindow> {
+        let medians = self.hourly_medians();
+        let median_at = |hour: u64| medians[((hour_of_day(now) + hour) % HOURS_PER_DAY) as usize];
+        let qualifies =
+            |hour: u64| matches!(median_at(hour), Some(rate) if rate <= target_fee_rate);
+        let first = (0..HOURS_PER_DAY).find(|hour| qualifies(*hour))?;
+        let last = (first..first + HOURS_PER_DAY)
+            .take_while(|hour| qualifies(*hour))
+            .last()
+            .unwrap_or(first);
+        let current_hour_start = now - now % MS_PER_HOUR;
+        let expected_fee_rate = (first..=last)
+            .filter_map(median_at)
+            .max()
+            .unwrap_or_default();
+        Some(SubmissionWindow {
+            start: (current_hour_start + first * MS_PER_HOUR).max(now),
+            end: current_hour_start + (last + 1) * MS_PER_HOUR,
+            expected_fee_rate,
+        })
+    }
+}
+
+fn hour_of_day(timestamp: u64) -> u64 {
+    timestamp / MS_PER_HOUR % HOURS_PER_DAY
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SubmissionPriority {
+    /// Submitted at once
+    High,
+    /// Deferred to a window where the fee rate is at most the target
+    Low { target_fee_rate: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduledTx {
+    pub tx: TransactionView,
+    pub priority: SubmissionPriority,
+    /// Milliseconds since the unix epoch
+    pub scheduled_at: u64,
+}
+
+/// A queue of the transactions waiting for submission.
+///
+/// A low priority transaction is due when its target window is open, or
+/// when it waited for `max_delay`. Without any fee rate history nothing is
+/// deferred.
+#[derive(Debug, Clone)]
+pub struct SubmissionScheduler {
+    pending: Vec<ScheduledTx>,
+    max_delay: Duration,
+}
+
+impl SubmissionScheduler {
+    pub fn new(max_delay: Duration) -> SubmissionScheduler {
+        SubmissionScheduler {
+            pending: Vec::new(),
+            max_delay,
+        }
+    }
+
+    pub fn schedule(&mut self, tx: TransactionView, priority: SubmissionPriority, now: u64) {
+        self.pending.push(ScheduledTx {
+            tx,
+            priority,
+            scheduled_at: now,
+        });
+    }
+
+    pub fn pending(&self) -> &[ScheduledTx] {
+        &self.pending
+    }
+
+    /// Remove and return the transactions due at `now` in the scheduling
+    /// order
+    pub fn take_due(&mut self, history: &FeeRateHistory, now: u64) -> Vec<TransactionView> {
+        let max_delay = self.max_delay.as_millis() as u64;
+        let is_due = |scheduled: &ScheduledTx| match scheduled.priority {
+            SubmissionPriority::High => true,
+            SubmissionPriority::Low { target_fee_rate } => {
+                history.is_empty()
+                    || now.saturating_sub(scheduled.scheduled_at) >= max_delay
+                    || history
+                        .suggest_submission_window_at(target_fee_rate, now)
+                        .map(|window| window.contains(now))
+                        .unwrap_or(false)
+            }
+        };
+        let (due, pending): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(is_due);
+        self.pending = pending;
+        due.into_iter().map(|scheduled| scheduled.tx).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, prelude::*};
+
+    const DAY: u64 = HOURS_PER_DAY * MS_PER_HOUR;
+
+    fn history() -> FeeRateHistory {
+        let mut history = FeeRateHistory::default();
+        for day in 0..3 {
+            for hour in 0..HOURS_PER_DAY {
+                // busy during the day, cheap between 2:00 and 5:00 UTC
+                let fee_rate = if (2..5).contains(&hour) {
+                    1000
+                } else {
+                    3000 + day
+                };
+                history.record(day * DAY + hour * MS_PER_HOUR + 1000, fee_rate);
+            }
+        }
+        history
+    }
+
+    #[test]
+    fn test_suggest_submission_window() {
+        let history = history();
+        let medians = history.hourly_medians();
+        assert_eq!(medians[3], Some(1000));
+        assert_eq!(medians[10], Some(3001));
+
+        let now = 10 * DAY + 20 * MS_PER_HOUR + 123;
+        let window = history.suggest_submission_window_at(1500, now).unwrap();
+        assert_eq!(window.start, 11 * DAY + 2 * MS_PER_HOUR);
+        assert_eq!(window.end, 11 * DAY + 5 * MS_PER_HOUR);
+        assert_eq!(window.expected_fee_rate, 1000);
+
+        let now = 10 * DAY + 3 * MS_PER_HOUR + 123;
+        let window = history.suggest_submission_window_at(1500, now).unwrap();
+        assert_eq!(window.start, now);
+        assert!(window.contains(now));
+        assert_eq!(window.end, 10 * DAY + 5 * MS_PER_HOUR);
+
+        assert!(history.suggest_submission_window_at(500, now).is_none());
+        assert!(FeeRateHistory::default()
+            .suggest_submission_window_at(1500, now)
+            .is_none());
+
+        let mut bounded = FeeRateHistory::new(2);
+        for fee_rate in [1, 2, 3] {
+            bounded.record(0, fee_rate);
+        }
+        assert_eq!(bounded.len(), 2);
+        assert_eq!(bounded.hourly_medians()[0], Some(3));
+    }
+
+    #[test]
+    fn test_submission_scheduler() {
+        let history = history();
+        let mut scheduler = SubmissionScheduler::new(Duration::from_secs(12 * 60 * 60));
+        let urgent = TransactionBuilder::default().version(1u32.pack()).build();
+        let deferred = TransactionBuilder::default().version(2u32.pack()).build();
+        let now = 10 * DAY + 20 * MS_PER_HOUR;
+        scheduler.schedule(urgent.clone(), SubmissionPriority::High, now);
+        scheduler.schedule(
+            deferred.clone(),
+            SubmissionPriority::Low {
+                target_fee_rate: 1500,
+            },
+            now,
+        );
+
+        assert_eq!(scheduler.take_due(&history, now), vec![urgent]);
+        assert_eq!(scheduler.pending().len(), 1);
+        assert!(scheduler
+            .take_due(&history, 11 * DAY + MS_PER_HOUR)
+            .is_empty());
+        assert_eq!(
+            scheduler.take_due(&history, 11 * DAY + 2 * MS_PER_HOUR),
+            vec![deferred.clone()]
+        );
+
+        // the maximum delay is reached before any cheap window
+        scheduler.schedule(
+            deferred.clone(),
+            SubmissionPriority::Low { target_fee_rate: 1 },
+            now,
+        );
+        assert!(scheduler
+            .take_due(&history, now + 11 * MS_PER_HOUR)
+            .is_empty());
+        assert_eq!(
+            scheduler.take_due(&history, now + 12 * MS_PER_HOUR),
+            vec![deferred]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Pluggable blake2b hashing backends.
+//!
+//! The default backend is the blake2b implementation from `ckb-hash`. Services
+//! computing a large amount of signing messages may plug in an accelerated
+//! implementation by implementing [`HashBackend`].
+
+use std::sync::Arc;
+
+use ckb_hash::{Blake2b, Blake2bBuilder, CKB_HASH_PERSONALIZATION};
+
+/// The length of the blake2b hash used by CKB
+pub const HASH_LEN: usize = 32;
+
+/// The length of the blake2b personalization
+pub const PERSONALIZATION_LEN: usize = 16;
+
+/// An incremental hasher
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> [u8; HASH_LEN];
+}
+
+/// Create hashers, the hashers created must produce 32 bytes blake2b hash with
+/// the personalization of the backend.
+pub trait HashBackend: Send + Sync {
+    /// The name of the backend, used in benchmarks and logs
+    fn name(&self) -> &str;
+
+    fn new_hasher(&self) -> Box<dyn Hasher>;
+
+    fn hash(&self, data: &[u8]) -> [u8; HASH_LEN] {
+        let mut hasher = self.new_hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// The blake2b implementation from `ckb-hash`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Blake2bBackend {
+    personalization: [u8; PERSONALIZATION_LEN],
+}
+
+impl Blake2bBackend {
+    pub fn new(personalization: [u8; PERSONALIZATION_LEN]) -> Blake2bBackend {
+        Blake2bBackend { personalization }
+    }
+
+    pub fn personalization(&self) -> &[u8; PERSONALIZATION_LEN] {
+        &self.personalization
+    }
+}
+
+impl Default for Blake2bBackend {
+    fn default() -> Blake2bBackend {
+        let mut personalization = [0u8; PERSONALIZATION_LEN];
+        personalization.copy_from_slice(CKB_HASH_PERSONALIZATION);
+        Blake2bBackend { personalization }
+    }
+}
+
+struct Blake2bHasher(Blake2b);
+
+impl Hasher for Blake2bHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finalize(self: Box<Self>) -> [u8; HASH_LEN] {
+        let mut result = [0u8; HASH_LEN];
+        self.0.finalize(&mut result);
+        result
+    }
+}
+
+impl HashBackend for Blake2bBackend {
+    fn name(&self) -> &str {
+        "ckb-hash"
+    }
+
+    fn new_hasher(&self) -> Box<dyn Hasher> {
+        let blake2b = Blake2bBuilder::new(HASH_LEN)
+            .personal(&self.personalization)
+            .build();
+        Box::new(Blake2bHasher(blake2b))
+    }
+}
+
+/// The SIMD accelerated blake2b implementation from `blake2b_simd`
+#[cfg(feature = "blake2b-simd")]
+#[derive(Debug, Clone)]
+pub struct Blake2bSimdBackend {
+    params: blake2b_simd::Params,
+}
+
+#[cfg(feature = "blake2b-simd")]
+impl Blake2bSimdBackend {
+    pub fn new(personalization: [u8; PERSONALIZATION_LEN]) -> Blake2bSimdBackend {
+        let mut params = blake2b_simd::Params::new();
+        params.hash_length(HASH_LEN).personal(&personalization);
+        Blake2bSimdBackend { params }
+    }
+}
+
+#[cfg(feature = "blake2b-simd")]
+impl Default for Blake2bSimdBackend {
+    fn default() -> Blake2bSimdBackend {
+        let mut personalization = [0u8; PERSONALIZATION_LEN];
+        personalization.copy_from_slice(CKB_HASH_PERSONALIZATION);
+        Blake2bSimdBackend::new(personalization)
+    }
+}
+
+#[cfg(feature = "blake2b-simd")]
+struct Blake2bSimdHasher(blake2b_simd::State);
+
+#[cfg(feature = "blake2b-simd")]
+impl Hasher for Blake2bSimdHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    fn finalize(self: Box<Self>) -> [u8; HASH_LEN] {
+        let mut result = [0u8; HASH_LEN];
+        result.copy_from_slice(self.0.finalize().as_bytes());
+        result
+    }
+}
+
+#[cfg(feature = "blake2b-simd")]
+impl HashBackend for Blake2bSimdBackend {
+    fn name(&self) -> &str {
+        "blake2b-simd"
+    }
+
+    fn new_hasher(&self) -> Box<dyn Hasher> {
+        Box::new(Blake2bSimdHasher(self.params.to_state()))
+    }
+}
+
+/// Mix a domain separator, usually the genesis hash of the chain, into every
+/// hash before the data.
+///
+/// Signing messages hashed with this backend are only valid on the chain of
+/// the domain, so a message built on testnet can not be replayed on mainnet.
+/// The lock script must hash its signing message the same way, the standard
+/// sighash and multisig locks do not.
+pub struct DomainSeparatedBackend {
+    inner: Arc<dyn HashBackend>,
+    domain: [u8; HASH_LEN],
+}
+
+impl DomainSeparatedBackend {
+    pub fn new(inner: Arc<dyn HashBackend>, domain: [u8; HASH_LEN]) -> DomainSeparatedBackend {
+        DomainSeparatedBackend { inner, domain }
+    }
+
+    /// Separate the default backend by `domain`
+    pub fn with_default(domain: [u8; HASH_LEN]) -> DomainSeparatedBackend {
+        DomainSeparatedBackend::new(default_backend(), domain)
+    }
+
+    pub fn domain(&self) -> &[u8; HASH_LEN] {
+        &self.domain
+    }
+}
+
+impl HashBackend for DomainSeparatedBackend {
+    fn name(&self) -> &str {
+        "domain-separated"
+    }
+
+    fn new_hasher(&self) -> Box<dyn Hasher> {
+        let mut hasher = self.inner.new_hasher();
+        hasher.update(&self.domain);
+        hasher
+    }
+}
+
+/// The backend used when no backend is specified
+pub fn default_backend() -> Arc<dyn HashBackend> {
+    Arc::new(Blake2bBackend::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_hash::blake2b_256;
+
+    #[test]
+    fn test_default_backend() {
+        let backend = default_backend();
+        let cases: [&[u8]; 3] = [b"", b"abc", &[7u8; 1000]];
+        for data in cases {
+            assert_eq!(backend.hash(data), blake2b_256(data));
+        }
+
+        let mut hasher = backend.new_hasher();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(hasher.finalize(), blake2b_256(b"abc"));
+    }
+
+    #[test]
+    fn test_personalization() {
+        let backend = Blake2bBackend::new(*b"ckb-custom-hash!");
+        assert_ne!(backend.hash(b"abc"), blake2b_256(b"abc"));
+    }
+
+    #[test]
+    fn test_domain_separated_backend() {
+        let mainnet = DomainSeparatedBackend::with_default([1u8; HASH_LEN]);
+        let testnet = DomainSeparatedBackend::with_default([2u8; HASH_LEN]);
+        assert_ne!(mainnet.hash(b"abc"), testnet.hash(b"abc"));
+        let mut data = vec![1u8; HASH_LEN];
+        data.extend_from_slice(b"abc");
+        assert_eq!(mainnet.hash(b"abc"), blake2b_256(&data));
+    }
+
+    #[cfg(feature = "blake2b-simd")]
+    #[test]
+    fn test_simd_backend() {
+        let backend = Blake2bSimdBackend::default();
+        let cases: [&[u8]; 3] = [b"", b"abc", &[7u8; 1000]];
+        for data in cases {
+            assert_eq!(backend.hash(data), blake2b_256(data));
+        }
+        let personalization = *b"ckb-custom-hash!";
+        assert_eq!(
+            Blake2bSimdBackend::new(personalization).hash(b"abc"),
+            Blake2bBackend::new(personalization).hash(b"abc")
+        );
+    }
+}
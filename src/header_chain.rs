@@ -0,0 +1,292 @@
+//! Sanity check the block headers fetched from an untrusted node.
+//!
+//! The light client backed providers rely on the headers for the since and
+//! DAO math. [`HeaderChainValidator`] checks their proof of work and that
+//! they link up: the parent hash, the block number, the epoch progress and
+//! the difficulty, which is the same in an epoch and changes at most by a
+//! factor of [`DIFFICULTY_ADJUSTMENT_FACTOR`] between two epochs.
+//! [`PowCheckedHeaderDepResolver`] checks the proof of work of every header
+//! resolved by another resolver.
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ckb_pow::{Pow, PowEngine};
+use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderView},
+    packed::Byte32,
+    prelude::*,
+    utilities::compact_to_difficulty,
+    H256, U256,
+};
+use thiserror::Error;
+
+use crate::traits::HeaderDepResolver;
+
+/// The maximum factor of the difficulty change between two epochs
+pub const DIFFICULTY_ADJUSTMENT_FACTOR: u32 = 2;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum HeaderChainError {
+    #[error("invalid proof of work of block `{0:#x}`")]
+    InvalidPow(H256),
+
+    #[error("block `{number}` has parent `{actual:#x}`, expected `{expected:#x}`")]
+    ParentMismatch {
+        number: u64,
+        expected: H256,
+        actual: H256,
+    },
+
+    #[error("block `{number}` does not follow block `{parent_number}`")]
+    NumberNotContinuous { parent_number: u64, number: u64 },
+
+    #[error(
+        "epoch `{epoch}` of block `{number}` does not follow the parent epoch `{parent_epoch}`"
+    )]
+    EpochNotContinuous {
+        number: u64,
+        parent_epoch: EpochNumberWithFraction,
+        epoch: EpochNumberWithFraction,
+    },
+
+    #[error("compact target of block `{number}` changed in the middle of an epoch")]
+    CompactTargetChanged { number: u64 },
+
+    #[error("difficulty of block `{number}` changed too much from the previous epoch")]
+    DifficultyOutOfBound { number: u64 },
+}
+
+/// Check headers one after another, see the [module documentation](self).
+pub struct HeaderChainValidator {
+    pow_engine: Arc<dyn PowEngine>,
+    tip: Option<HeaderView>,
+}
+
+impl HeaderChainValidator {
+    /// A validator with the proof of work of the chain, e.g.
+    /// [`Pow::Eaglesong`] for the mainnet and the testnet
+    pub fn new(pow: &Pow) -> HeaderChainValidator {
+        HeaderChainValidator {
+            pow_engine: pow.engine(),
+            tip: None,
+        }
+    }
+
+    /// Start from a trusted header, e.g. a checkpoint, its proof of work is
+    /// not checked
+    pub fn with_trusted_tip(mut self, tip: HeaderView) -> Self {
+        self.tip = Some(tip);
+        self
+    }
+
+    /// The last accepted header
+    pub fn tip(&self) -> Option<&HeaderView> {
+        self.tip.as_ref()
+    }
+
+    pub fn verify_pow(&self, header: &HeaderView) -> Result<(), HeaderChainError> {
+        if self.pow_engine.verify(&header.data()) {
+            Ok(())
+        } else {
+            Err(HeaderChainError::InvalidPow(header.hash().unpack()))
+        }
+    }
+
+    /// Check that `header` is the child of `parent`, the proof of work is
+    /// not checked
+    pub fn verify_link(parent: &HeaderView, header: &HeaderView) -> Result<(), HeaderChainError> {
+        let number = header.number();
+        if number != parent.number() + 1 {
+            return Err(HeaderChainError::NumberNotContinuous {
+                parent_number: parent.number(),
+                number,
+            });
+        }
+        if header.parent_hash() != parent.hash() {
+            return Err(HeaderChainError::ParentMismatch {
+                number,
+                expected: parent.hash().unpack(),
+                actual: header.parent_hash().unpack(),
+            });
+        }
+
+        let parent_epoch = parent.epoch();
+        let epoch = header.epoch();
+        let epoch_not_continuous = HeaderChainError::EpochNotContinuous {
+            number,
+            parent_epoch,
+            epoch,
+        };
+        if epoch.number() == parent_epoch.number() {
+            if epoch.index() != parent_epoch.index() + 1 || epoch.length() != parent_epoch.length()
+            {
+                return Err(epoch_not_continuous);
+            }
+            if header.compact_target() != parent.compact_target() {
+                return Err(HeaderChainError::CompactTargetChanged { number });
+            }
+        } else if epoch.number() == parent_epoch.number() + 1 {
+            if epoch.index() != 0 || parent_epoch.index() + 1 != parent_epoch.length() {
+                return Err(epoch_not_continuous);
+            }
+            let difficulty = compact_to_difficulty(header.compact_target());
+            let parent_difficulty = compact_to_difficulty(parent.compact_target());
+            let factor = U256::from(DIFFICULTY_ADJUSTMENT_FACTOR);
+            if difficulty > parent_difficulty.clone() * factor.clone()
+                || parent_difficulty > difficulty * factor
+            {
+                return Err(HeaderChainError::DifficultyOutOfBound { number });
+            }
+        } else {
+            return Err(epoch_not_continuous);
+        }
+        Ok(())
+    }
+
+    /// Check `header` against the tip and make it the new tip, the first
+    /// header without a tip only has its proof of work checked
+    pub fn push(&mut self, header: HeaderView) -> Result<(), HeaderChainError> {
+        self.verify_pow(&header)?;
+        if let Some(tip) = &self.tip {
+            Self::verify_link(tip, &header)?;
+        }
+        self.tip = Some(header);
+        Ok(())
+    }
+
+    /// Push the `headers` in ascending order, stop at the first invalid one
+    pub fn push_all<I: IntoIterator<Item = HeaderView>>(
+        &mut self,
+        headers: I,
+    ) -> Result<(), HeaderChainError> {
+        headers.into_iter().try_for_each(|header| self.push(header))
+    }
+}
+
+/// A [`HeaderDepResolver`] failing on the headers with an invalid proof of
+/// work
+pub struct PowCheckedHeaderDepResolver<T> {
+    inner: T,
+    pow_engine: Arc<dyn PowEngine>,
+}
+
+impl<T: HeaderDepResolver> PowCheckedHeaderDepResolver<T> {
+    pub fn new(inner: T, pow: &Pow) -> PowCheckedHeaderDepResolver<T> {
+        PowCheckedHeaderDepResolver {
+            inner,
+            pow_engine: pow.engine(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn check(&self, header: Option<HeaderView>) -> Result<Option<HeaderView>, anyhow::Error> {
+        match header {
+            Some(header) if !self.pow_engine.verify(&header.data()) => Err(anyhow!(
+                HeaderChainError::InvalidPow(header.hash().unpack())
+            )),
+            header => Ok(header),
+        }
+    }
+}
+
+impl<T: HeaderDepResolver> HeaderDepResolver for PowCheckedHeaderDepResolver<T> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.check(self.inner.resolve_by_tx(tx_hash)?)
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.check(self.inner.resolve_by_number(number)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::core::HeaderBuilder;
+
+    const COMPACT_TARGET: u32 = 0x1e08_3126;
+
+    fn child(
+        parent: &HeaderView,
+        epoch: EpochNumberWithFraction,
+        compact_target: u32,
+    ) -> HeaderView {
+        HeaderBuilder::default()
+            .number((parent.number() + 1).pack())
+            .parent_hash(parent.hash())
+            .epoch(epoch.full_value().pack())
+            .compact_target(compact_target.pack())
+            .build()
+    }
+
+    #[test]
+    fn test_header_chain_validator() {
+        let genesis = HeaderBuilder::default()
+            .epoch(EpochNumberWithFraction::new(0, 0, 2).full_value().pack())
+            .compact_target(COMPACT_TARGET.pack())
+            .build();
+        let block1 = child(
+            &genesis,
+            EpochNumberWithFraction::new(0, 1, 2),
+            COMPACT_TARGET,
+        );
+        // 1.5 times the difficulty in the next epoch
+        let block2 = child(&block1, EpochNumberWithFraction::new(1, 0, 3), 0x1e05_7619);
+        let mut validator = HeaderChainValidator::new(&Pow::Dummy);
+        assert!(validator
+            .push_all(vec![genesis.clone(), block1.clone(), block2.clone()])
+            .is_ok());
+        assert_eq!(validator.tip(), Some(&block2));
+
+        let orphan = child(
+            &genesis,
+            EpochNumberWithFraction::new(0, 1, 2),
+            COMPACT_TARGET,
+        );
+        let orphan = orphan
+            .as_advanced_builder()
+            .parent_hash(Byte32::default())
+            .build();
+        assert!(matches!(
+            HeaderChainValidator::verify_link(&genesis, &orphan),
+            Err(HeaderChainError::ParentMismatch { number: 1, .. })
+        ));
+        assert!(matches!(
+            HeaderChainValidator::verify_link(&genesis, &block2),
+            Err(HeaderChainError::NumberNotContinuous { .. })
+        ));
+        let skipped_index = child(
+            &genesis,
+            EpochNumberWithFraction::new(1, 0, 2),
+            COMPACT_TARGET,
+        );
+        assert!(matches!(
+            HeaderChainValidator::verify_link(&genesis, &skipped_index),
+            Err(HeaderChainError::EpochNotContinuous { .. })
+        ));
+        let retargeted = child(&genesis, EpochNumberWithFraction::new(0, 1, 2), 0x1e05_7619);
+        assert_eq!(
+            HeaderChainValidator::verify_link(&genesis, &retargeted),
+            Err(HeaderChainError::CompactTargetChanged { number: 1 })
+        );
+        // 4 times the difficulty
+        let too_hard = child(&block1, EpochNumberWithFraction::new(1, 0, 3), 0x1e02_0c49);
+        assert_eq!(
+            HeaderChainValidator::verify_link(&block1, &too_hard),
+            Err(HeaderChainError::DifficultyOutOfBound { number: 2 })
+        );
+
+        // the nonce 0 does not meet the target
+        let mut validator =
+            HeaderChainValidator::new(&Pow::Eaglesong).with_trusted_tip(genesis.clone());
+        assert_eq!(
+            validator.push(block1.clone()),
+            Err(HeaderChainError::InvalidPow(block1.hash().unpack()))
+        );
+        assert_eq!(validator.tip(), Some(&genesis));
+    }
+}
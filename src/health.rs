@@ -0,0 +1,232 @@
+//! Health checks of the rpc backed providers.
+//!
+//! A service embedding the SDK exposes its liveness and readiness from a
+//! [`HealthReport`]: the tip lag of the node and of the indexer, the tx pool
+//! status and the round-trip latency of every call. The default providers
+//! have a `health_check` method checking the endpoints they use.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ckb_types::core::HeaderView;
+
+use crate::rpc::{CkbRpcClient, RpcError};
+#[cfg(feature = "indexer")]
+use crate::IndexerRpcClient;
+
+/// The default maximum age of the tip block of the node
+pub const DEFAULT_MAX_NODE_TIP_LAG: Duration = Duration::from_secs(10 * 60);
+/// The default maximum blocks the indexer is behind the node
+pub const DEFAULT_MAX_INDEXER_TIP_LAG: u64 = 10;
+/// The default maximum round-trip latency of a call
+pub const DEFAULT_MAX_LATENCY: Duration = Duration::from_secs(5);
+
+/// The transactions in the tx pool of the node
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PoolStatus {
+    pub pending: u64,
+    pub proposed: u64,
+    pub orphan: u64,
+    pub total_tx_size: u64,
+}
+
+/// The limits of a ready [`HealthReport`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HealthThresholds {
+    pub max_node_tip_lag: Duration,
+    pub max_indexer_tip_lag: u64,
+    pub max_latency: Duration,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> HealthThresholds {
+        HealthThresholds {
+            max_node_tip_lag: DEFAULT_MAX_NODE_TIP_LAG,
+            max_indexer_tip_lag: DEFAULT_MAX_INDEXER_TIP_LAG,
+            max_latency: DEFAULT_MAX_LATENCY,
+        }
+    }
+}
+
+/// The result of a health check, the fields of a failed or skipped check
+/// are `None`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HealthReport {
+    /// The tip block number of the node
+    pub node_tip: Option<u64>,
+    /// How long ago the tip block of the node was mined, by its timestamp
+    pub node_tip_lag: Option<Duration>,
+    pub node_latency: Option<Duration>,
+    /// The tip block number of the indexer
+    pub indexer_tip: Option<u64>,
+    /// How many blocks the indexer is behind the node
+    pub indexer_tip_lag: Option<u64>,
+    pub indexer_latency: Option<Duration>,
+    pub pool: Option<PoolStatus>,
+    pub pool_latency: Option<Duration>,
+    /// The errors of the failed checks
+    pub errors: Vec<String>,
+}
+
+impl HealthReport {
+    /// The node answers
+    pub fn is_live(&self) -> bool {
+        self.node_tip.is_some()
+    }
+
+    /// All the checks passed within `thresholds`
+    pub fn is_ready(&self, thresholds: &HealthThresholds) -> bool {
+        let latencies = [self.node_latency, self.indexer_latency, self.pool_latency];
+        self.errors.is_empty()
+            && self.is_live()
+            && self
+                .node_tip_lag
+                .map_or(true, |lag| lag <= thresholds.max_node_tip_lag)
+            && self
+                .indexer_tip_lag
+                .map_or(true, |lag| lag <= thresholds.max_indexer_tip_lag)
+            && latencies
+                .iter()
+                .flatten()
+                .all(|latency| *latency <= thresholds.max_latency)
+    }
+}
+
+fn timed<T, F: FnOnce() -> Result<T, RpcError>>(call: F) -> (Result<T, RpcError>, Duration) {
+    let start = Instant::now();
+    let result = call();
+    (result, start.elapsed())
+}
+
+fn check_node(ckb_client: &CkbRpcClient, report: &mut HealthReport) {
+    let (tip, latency) = timed(|| ckb_client.get_tip_header());
+    match tip {
+        Ok(tip) => {
+            let tip: HeaderView = tip.into();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            report.node_tip = Some(tip.number());
+            report.node_tip_lag = Some(now.saturating_sub(Duration::from_millis(tip.timestamp())));
+            report.node_latency = Some(latency);
+        }
+        Err(err) => report.errors.push(format!("node: {}", err)),
+    }
+
+    let (pool, latency) = timed(|| ckb_client.tx_pool_info());
+    match pool {
+        Ok(pool) => {
+            report.pool = Some(PoolStatus {
+                pending: pool.pending.value(),
+                proposed: pool.proposed.value(),
+                orphan: pool.orphan.value(),
+                total_tx_size: pool.total_tx_size.value(),
+            });
+            report.pool_latency = Some(latency);
+        }
+        Err(err) => report.errors.push(format!("tx pool: {}", err)),
+    }
+}
+
+#[cfg(feature = "indexer")]
+fn check_indexer(indexer_client: &IndexerRpcClient, report: &mut HealthReport) {
+    let (tip, latency) = timed(|| indexer_client.get_indexer_tip());
+    match tip {
+        Ok(Some(tip)) => {
+            let tip = tip.block_number.value();
+            report.indexer_tip = Some(tip);
+            report.indexer_tip_lag = report.node_tip.map(|node_tip| node_tip.saturating_sub(tip));
+            report.indexer_latency = Some(latency);
+        }
+        Ok(None) => report.errors.push("indexer: no indexed block".to_string()),
+        Err(err) => report.errors.push(format!("indexer: {}", err)),
+    }
+}
+
+/// Check the node and its tx pool
+pub fn check_node_health(ckb_client: &CkbRpcClient) -> HealthReport {
+    let mut report = HealthReport::default();
+    check_node(ckb_client, &mut report);
+    report
+}
+
+/// Check the node, its tx pool and the indexer
+#[cfg(feature = "indexer")]
+pub fn check_health(ckb_client: &CkbRpcClient, indexer_client: &IndexerRpcClient) -> HealthReport {
+    let mut report = HealthReport::default();
+    check_node(ckb_client, &mut report);
+    check_indexer(indexer_client, &mut report);
+    report
+}
+
+#[cfg(all(test, feature = "indexer"))]
+mod tests {
+    use super::*;
+    use crate::rpc::ckb_indexer::Tip;
+    use crate::test_util::MockRpcResult;
+    use ckb_jsonrpc_types::HeaderView as JsonHeaderView;
+    use ckb_types::{core::HeaderBuilder, prelude::*, H256};
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_check_health() {
+        let server = MockServer::start();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let tip_header: JsonHeaderView = HeaderBuilder::default()
+            .number(100u64.pack())
+            .timestamp((now - 30_000).pack())
+            .build()
+            .into();
+        server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("get_tip_header");
+            then.status(200)
+                .body(MockRpcResult::new(tip_header).to_json());
+        });
+        let indexer_tip = Tip {
+            block_hash: H256::default(),
+            block_number: 97u64.into(),
+        };
+        server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("get_indexer_tip");
+            then.status(200)
+                .body(MockRpcResult::new(indexer_tip).to_json());
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("tx_pool_info");
+            then.status(200).body(
+                r#"{"id":42,"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"}}"#,
+            );
+        });
+
+        let ckb_client = CkbRpcClient::new(server.base_url().as_str());
+        let indexer_client = IndexerRpcClient::new(server.base_url().as_str());
+        let report = check_health(&ckb_client, &indexer_client);
+        assert!(report.is_live());
+        assert_eq!(report.node_tip, Some(100));
+        let node_tip_lag = report.node_tip_lag.unwrap();
+        assert!(node_tip_lag >= Duration::from_secs(30) && node_tip_lag < Duration::from_secs(90));
+        assert_eq!(report.indexer_tip, Some(97));
+        assert_eq!(report.indexer_tip_lag, Some(3));
+        assert!(report.indexer_latency.is_some());
+        assert_eq!(report.pool, None);
+        assert_eq!(report.errors.len(), 1);
+        assert!(!report.is_ready(&HealthThresholds::default()));
+
+        let report = HealthReport {
+            errors: Vec::new(),
+            ..report
+        };
+        assert!(report.is_ready(&HealthThresholds::default()));
+        assert!(!report.is_ready(&HealthThresholds {
+            max_indexer_tip_lag: 2,
+            ..Default::default()
+        }));
+        assert!(!report.is_ready(&HealthThresholds {
+            max_node_tip_lag: Duration::from_secs(10),
+            ..Default::default()
+        }));
+        assert!(!HealthReport::default().is_ready(&HealthThresholds::default()));
+    }
+}
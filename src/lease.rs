@@ -0,0 +1,562 @@
+//! Time-boxed exclusive building leases per account.
+//!
+//! The cell reservations keep two builds from spending the same cells, they
+//! don't keep two instances of a service from building, bumping the fee of
+//! or replacing the transactions of the same account at the same time. An
+//! [`AccountLease`] serializes the builds per lock script: a builder
+//! acquires the lease of the account first, and the lease expires after its
+//! ttl if the holder dies without releasing it.
+//!
+//! [`MemoryAccountLease`] serves the builds of one process. With the
+//! `redis-lease` feature, `RedisAccountLease` serves the builds of a fleet
+//! sharing a redis server.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use ckb_types::packed::Script;
+//! # fn run(lock: Script) -> Result<(), ckb_sdk::lease::LeaseError> {
+//! use ckb_sdk::lease::{lease_account, MemoryAccountLease};
+//!
+//! let leases = MemoryAccountLease::new();
+//! let _guard = lease_account(&leases, &lock, "instance-1", Duration::from_secs(30))?;
+//! // build, sign and send, the lease is released when the guard drops
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+#[cfg(feature = "redis-lease")]
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(feature = "redis-lease")]
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ckb_types::{packed::Script, prelude::*, H256};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::redact;
+
+/// The key prefix of the leases in redis
+#[cfg(feature = "redis-lease")]
+pub const DEFAULT_REDIS_KEY_PREFIX: &str = "ckb-sdk:lease:";
+
+#[derive(Error, Debug)]
+pub enum LeaseError {
+    #[error("account `{}` is leased by `{holder}`", redact::bytes(.lock_hash.as_bytes()))]
+    Held { lock_hash: H256, holder: String },
+
+    #[error("the lease of account `{}` expired or was taken over", redact::bytes(.0.as_bytes()))]
+    NotHolder(H256),
+
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("redis error: `{0}`")]
+    Redis(String),
+}
+
+/// A lease held on the account of `lock_hash`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Lease {
+    pub lock_hash: H256,
+    pub holder: String,
+    /// Unique per acquisition, only the acquisition can renew or release
+    pub token: String,
+    pub ttl: Duration,
+    /// When the lease was acquired or renewed
+    pub acquired_at: Instant,
+}
+
+impl Lease {
+    fn new(lock_hash: H256, holder: &str, ttl: Duration) -> Lease {
+        Lease {
+            lock_hash,
+            holder: holder.to_string(),
+            token: new_token(holder),
+            ttl,
+            acquired_at: Instant::now(),
+        }
+    }
+
+    /// The local estimate of the expiration, renew the lease before
+    pub fn expires_at(&self) -> Instant {
+        self.acquired_at + self.ttl
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at()
+    }
+}
+
+/// `holder/timestamp-counter`, the holder is kept in the token to report who
+/// holds a lease
+fn new_token(holder: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}/{:x}-{:x}", holder, nanos, count)
+}
+
+fn token_holder(token: &str) -> String {
+    token
+        .rsplit_once('/')
+        .map(|(holder, _)| holder.to_string())
+        .unwrap_or_default()
+}
+
+/// Exclusive leases of the accounts, keyed by the lock script hash
+pub trait AccountLease: Send + Sync {
+    /// Acquire the lease of `lock_script` for `ttl`, fails with
+    /// [`LeaseError::Held`] while another holder has it
+    fn acquire(
+        &self,
+        lock_script: &Script,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<Lease, LeaseError>;
+
+    /// Extend a held lease to `ttl` from now
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease, LeaseError>;
+
+    /// Release a held lease, fails with [`LeaseError::NotHolder`] if it
+    /// expired in the meantime
+    fn release(&self, lease: &Lease) -> Result<(), LeaseError>;
+}
+
+/// Releases the lease when dropped, see [`lease_account`]
+pub struct LeaseGuard<'a> {
+    leases: &'a dyn AccountLease,
+    lease: Lease,
+}
+
+impl LeaseGuard<'_> {
+    pub fn lease(&self) -> &Lease {
+        &self.lease
+    }
+
+    pub fn renew(&mut self, ttl: Duration) -> Result<(), LeaseError> {
+        self.lease = self.leases.renew(&self.lease, ttl)?;
+        Ok(())
+    }
+}
+
+impl Drop for LeaseGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.leases.release(&self.lease) {
+            log::debug!("release the account lease failed: {}", err);
+        }
+    }
+}
+
+/// Acquire the lease of `lock_script`, released when the guard drops
+pub fn lease_account<'a>(
+    leases: &'a dyn AccountLease,
+    lock_script: &Script,
+    holder: &str,
+    ttl: Duration,
+) -> Result<LeaseGuard<'a>, LeaseError> {
+    let lease = leases.acquire(lock_script, holder, ttl)?;
+    Ok(LeaseGuard { leases, lease })
+}
+
+/// The leases of the builds of one process
+#[derive(Default)]
+pub struct MemoryAccountLease {
+    // lock hash => (token, expiration)
+    leases: Mutex<HashMap<H256, (String, Instant)>>,
+}
+
+impl MemoryAccountLease {
+    pub fn new() -> MemoryAccountLease {
+        MemoryAccountLease::default()
+    }
+}
+
+impl AccountLease for MemoryAccountLease {
+    fn acquire(
+        &self,
+        lock_script: &Script,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<Lease, LeaseError> {
+        let lock_hash: H256 = lock_script.calc_script_hash().unpack();
+        let mut leases = self.leases.lock();
+        if let Some((token, expires_at)) = leases.get(&lock_hash) {
+            if Instant::now() < *expires_at {
+                return Err(LeaseError::Held {
+                    lock_hash,
+                    holder: token_holder(token),
+                });
+            }
+        }
+        let lease = Lease::new(lock_hash.clone(), holder, ttl);
+        leases.insert(lock_hash, (lease.token.clone(), lease.expires_at()));
+        Ok(lease)
+    }
+
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease, LeaseError> {
+        let mut leases = self.leases.lock();
+        match leases.get_mut(&lease.lock_hash) {
+            Some((token, expires_at)) if token == &lease.token && Instant::now() < *expires_at => {
+                let renewed = Lease {
+                    ttl,
+                    acquired_at: Instant::now(),
+                    ..lease.clone()
+                };
+                *expires_at = renewed.expires_at();
+                Ok(renewed)
+            }
+            _ => Err(LeaseError::NotHolder(lease.lock_hash.clone())),
+        }
+    }
+
+    fn release(&self, lease: &Lease) -> Result<(), LeaseError> {
+        let mut leases = self.leases.lock();
+        match leases.get(&lease.lock_hash) {
+            Some((token, expires_at)) if token == &lease.token => {
+                let expired = Instant::now() >= *expires_at;
+                leases.remove(&lease.lock_hash);
+                if expired {
+                    Err(LeaseError::NotHolder(lease.lock_hash.clone()))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Err(LeaseError::NotHolder(lease.lock_hash.clone())),
+        }
+    }
+}
+
+/// The largest bulk string and array length accepted in a reply, the replies
+/// of the lease commands are tiny
+#[cfg(feature = "redis-lease")]
+const MAX_REPLY_LEN: i64 = 64 * 1024;
+/// The deepest nesting of arrays accepted in a reply
+#[cfg(feature = "redis-lease")]
+const MAX_REPLY_DEPTH: usize = 4;
+
+#[cfg(feature = "redis-lease")]
+const RENEW_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('pexpire', KEYS[1], ARGV[2]) else return 0 end";
+#[cfg(feature = "redis-lease")]
+const RELEASE_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) else return 0 end";
+
+/// The leases of a fleet of instances sharing a redis server.
+///
+/// A lease is a key set with `SET NX PX`, renewed and released by lua
+/// scripts checking the token, so an expired holder never releases the lease
+/// of the next one. A connection is opened per call.
+#[cfg(feature = "redis-lease")]
+#[derive(Debug, Clone)]
+pub struct RedisAccountLease {
+    addr: String,
+    password: Option<String>,
+    key_prefix: String,
+    timeout: Duration,
+}
+
+/// A RESP reply
+#[cfg(feature = "redis-lease")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum RedisReply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<RedisReply>>),
+}
+
+#[cfg(feature = "redis-lease")]
+impl RedisAccountLease {
+    /// `addr` is the `host:port` of the redis server
+    pub fn new(addr: impl Into<String>) -> RedisAccountLease {
+        RedisAccountLease {
+            addr: addr.into(),
+            password: None,
+            key_prefix: DEFAULT_REDIS_KEY_PREFIX.to_string(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Authenticate with `AUTH password`
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// The connect, read and write timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn key(&self, lock_hash: &H256) -> String {
+        format!("{}{:x}", self.key_prefix, lock_hash)
+    }
+
+    fn connect(&self) -> Result<BufReader<TcpStream>, LeaseError> {
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(self.addr.as_str())?
+            .next()
+            .ok_or_else(|| LeaseError::Redis(format!("invalid address {}", self.addr)))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut conn = BufReader::new(stream);
+        if let Some(password) = self.password.as_ref() {
+            command(&mut conn, &["AUTH", password])?;
+        }
+        Ok(conn)
+    }
+}
+
+#[cfg(feature = "redis-lease")]
+fn command(conn: &mut BufReader<TcpStream>, args: &[&str]) -> Result<RedisReply, LeaseError> {
+    conn.get_mut().write_all(&encode_command(args))?;
+    read_reply(conn)
+}
+
+#[cfg(feature = "redis-lease")]
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+#[cfg(feature = "redis-lease")]
+fn read_reply<R: BufRead>(conn: &mut R) -> Result<RedisReply, LeaseError> {
+    read_reply_nested(conn, 0)
+}
+
+#[cfg(feature = "redis-lease")]
+fn read_reply_nested<R: BufRead>(conn: &mut R, depth: usize) -> Result<RedisReply, LeaseError> {
+    let mut line = String::new();
+    conn.read_line(&mut line)?;
+    let line = line.trim_end_matches("\r\n");
+    let invalid = || LeaseError::Redis(format!("invalid reply: {}", line));
+    let (kind, rest) = match line.as_bytes().first() {
+        Some(kind) if kind.is_ascii() => (*kind, &line[1..]),
+        _ => return Err(invalid()),
+    };
+    let parse_int = |rest: &str| rest.parse::<i64>().map_err(|_| invalid());
+    // -1 is the null bulk string or array
+    let parse_len = |rest: &str| match parse_int(rest)? {
+        -1 => Ok(None),
+        len if (0..=MAX_REPLY_LEN).contains(&len) => Ok(Some(len)),
+        _ => Err(invalid()),
+    };
+    match kind {
+        b'+' => Ok(RedisReply::Status(rest.to_string())),
+        b'-' => Err(LeaseError::Redis(rest.to_string())),
+        b':' => Ok(RedisReply::Integer(parse_int(rest)?)),
+        b'$' => {
+            let len = match parse_len(rest)? {
+                Some(len) => len,
+                None => return Ok(RedisReply::Bulk(None)),
+            };
+            let mut data = vec![0u8; len as usize + 2];
+            conn.read_exact(&mut data)?;
+            data.truncate(len as usize);
+            Ok(RedisReply::Bulk(Some(data)))
+        }
+        b'*' => {
+            let len = match parse_len(rest)? {
+                Some(len) => len,
+                None => return Ok(RedisReply::Array(None)),
+            };
+            if depth >= MAX_REPLY_DEPTH {
+                return Err(invalid());
+            }
+            let items = (0..len)
+                .map(|_| read_reply_nested(conn, depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(RedisReply::Array(Some(items)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(feature = "redis-lease")]
+impl AccountLease for RedisAccountLease {
+    fn acquire(
+        &self,
+        lock_script: &Script,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<Lease, LeaseError> {
+        let lock_hash: H256 = lock_script.calc_script_hash().unpack();
+        let key = self.key(&lock_hash);
+        let lease = Lease::new(lock_hash.clone(), holder, ttl);
+        let ttl_ms = ttl.as_millis().max(1).to_string();
+        let mut conn = self.connect()?;
+        match command(&mut conn, &["SET", &key, &lease.token, "NX", "PX", &ttl_ms])? {
+            RedisReply::Status(_) => Ok(lease),
+            RedisReply::Bulk(None) => {
+                let holder = match command(&mut conn, &["GET", &key])? {
+                    RedisReply::Bulk(Some(token)) => token_holder(&String::from_utf8_lossy(&token)),
+                    _ => String::new(),
+                };
+                Err(LeaseError::Held { lock_hash, holder })
+            }
+            reply => Err(LeaseError::Redis(format!("unexpected reply: {:?}", reply))),
+        }
+    }
+
+    fn renew(&self, lease: &Lease, ttl: Duration) -> Result<Lease, LeaseError> {
+        let key = self.key(&lease.lock_hash);
+        let ttl_ms = ttl.as_millis().max(1).to_string();
+        let acquired_at = Instant::now();
+        let mut conn = self.connect()?;
+        match command(
+            &mut conn,
+            &["EVAL", RENEW_SCRIPT, "1", &key, &lease.token, &ttl_ms],
+        )? {
+            RedisReply::Integer(1) => Ok(Lease {
+                ttl,
+                acquired_at,
+                ..lease.clone()
+            }),
+            _ => Err(LeaseError::NotHolder(lease.lock_hash.clone())),
+        }
+    }
+
+    fn release(&self, lease: &Lease) -> Result<(), LeaseError> {
+        let key = self.key(&lease.lock_hash);
+        let mut conn = self.connect()?;
+        match command(
+            &mut conn,
+            &["EVAL", RELEASE_SCRIPT, "1", &key, &lease.token],
+        )? {
+            RedisReply::Integer(1) => Ok(()),
+            _ => Err(LeaseError::NotHolder(lease.lock_hash.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::bytes::Bytes;
+
+    fn lock(arg: u8) -> Script {
+        Script::new_builder()
+            .args(Bytes::from(vec![arg; 20]).pack())
+            .build()
+    }
+
+    #[test]
+    fn test_memory_account_lease() {
+        let leases = MemoryAccountLease::new();
+        let ttl = Duration::from_secs(60);
+        let lease = leases.acquire(&lock(1), "a", ttl).unwrap();
+        assert_eq!(lease.holder, "a");
+        match leases.acquire(&lock(1), "b", ttl) {
+            Err(LeaseError::Held { holder, .. }) => assert_eq!(holder, "a"),
+            other => panic!("unexpected: {:?}", other),
+        }
+        // other accounts are not blocked
+        leases.acquire(&lock(2), "b", ttl).unwrap();
+
+        let renewed = leases.renew(&lease, ttl).unwrap();
+        assert_eq!(renewed.token, lease.token);
+        leases.release(&renewed).unwrap();
+        assert!(matches!(
+            leases.release(&renewed),
+            Err(LeaseError::NotHolder(_))
+        ));
+
+        // an expired lease is taken over, its holder can not release it
+        let expired = leases.acquire(&lock(1), "a", Duration::ZERO).unwrap();
+        let lease = leases.acquire(&lock(1), "b", ttl).unwrap();
+        assert!(leases.release(&expired).is_err());
+        assert!(leases.renew(&expired, ttl).is_err());
+
+        {
+            let guard = lease_account(&leases, &lock(3), "c", ttl).unwrap();
+            assert_eq!(guard.lease().holder, "c");
+            assert!(leases.acquire(&lock(3), "d", ttl).is_err());
+        }
+        leases.acquire(&lock(3), "d", ttl).unwrap();
+        leases.release(&lease).unwrap();
+    }
+
+    #[cfg(feature = "redis-lease")]
+    #[test]
+    fn test_redis_protocol() {
+        assert_eq!(
+            encode_command(&["GET", "key"]),
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec()
+        );
+        let mut data: &[u8] =
+            b"+OK\r\n:1\r\n$-1\r\n$5\r\na/b-c\r\n*2\r\n:2\r\n$0\r\n\r\n-ERR wrong\r\n";
+        assert_eq!(
+            read_reply(&mut data).unwrap(),
+            RedisReply::Status("OK".to_string())
+        );
+        assert_eq!(read_reply(&mut data).unwrap(), RedisReply::Integer(1));
+        assert_eq!(read_reply(&mut data).unwrap(), RedisReply::Bulk(None));
+        assert_eq!(
+            read_reply(&mut data).unwrap(),
+            RedisReply::Bulk(Some(b"a/b-c".to_vec()))
+        );
+        assert_eq!(
+            read_reply(&mut data).unwrap(),
+            RedisReply::Array(Some(vec![
+                RedisReply::Integer(2),
+                RedisReply::Bulk(Some(Vec::new()))
+            ]))
+        );
+        assert!(matches!(read_reply(&mut data), Err(LeaseError::Redis(err)) if err == "ERR wrong"));
+
+        // the lengths are bounded, only -1 is negative
+        for reply in [
+            &b"$-2\r\n"[..],
+            &b"*-2\r\n"[..],
+            &b"$9223372036854775807\r\n"[..],
+            &b"*100000000\r\n"[..],
+            &b"*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n:1\r\n"[..],
+        ] {
+            let mut data = reply;
+            assert!(matches!(read_reply(&mut data), Err(LeaseError::Redis(_))));
+        }
+    }
+
+    #[cfg(feature = "redis-lease")]
+    #[test]
+    fn test_redis_account_lease() {
+        use std::net::TcpListener;
+        use std::thread;
+
+        // a redis server holding the key already
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(stream);
+            for reply in [&b"$-1\r\n"[..], &b"$8\r\nother/01\r\n"[..]] {
+                match read_reply(&mut conn).unwrap() {
+                    RedisReply::Array(Some(args)) => assert!(!args.is_empty()),
+                    reply => panic!("unexpected command: {:?}", reply),
+                }
+                conn.get_mut().write_all(reply).unwrap();
+            }
+        });
+        let leases = RedisAccountLease::new(addr.to_string());
+        match leases.acquire(&lock(1), "a", Duration::from_secs(30)) {
+            Err(LeaseError::Held { holder, .. }) => assert_eq!(holder, "other"),
+            other => panic!("unexpected: {:?}", other),
+        }
+        server.join().unwrap();
+    }
+}
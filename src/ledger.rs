@@ -0,0 +1,349 @@
+//! Export the wallet history as ledger entries for accounting tools.
+//!
+//! [`classify_transaction`] turns a committed transaction into the
+//! [`WalletEvent`]s of a wallet: the net change of every asset held by the
+//! wallet lock scripts, the counterparty and the fee paid. The UDTs are the
+//! type scripts registered in a [`TokenRegistry`], which also gives their
+//! symbol and decimals. [`LedgerExporter`] writes the events as CSV or JSON
+//! ledger entries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::Write;
+
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    packed::{CellOutput, Script},
+    prelude::*,
+    H256,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::traits::{TransactionDependencyError, TransactionDependencyProvider};
+use crate::types::{Address, AddressPayload, NetworkType};
+use crate::util::{parse_udt_amount, CellDataError};
+
+/// The decimals of CKB, 1 CKB is 10^8 shannons
+pub const CKB_DECIMALS: u8 = 8;
+
+const CSV_HEADER: &str = "timestamp,asset,amount,counterparty,tx_hash,fee";
+
+#[derive(Error, Debug)]
+pub enum LedgerError {
+    #[error("transaction dependency provider error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("invalid udt cell data: `{0}`")]
+    CellData(#[from] CellDataError),
+
+    #[error("the amount change of `{0}` overflows")]
+    AmountOverflow(String),
+
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Asset {
+    Ckb,
+    /// A UDT identified by its type script hash
+    Udt(H256),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// The known UDTs, keyed by their type script hash
+#[derive(Debug, Clone, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<H256, TokenInfo>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> TokenRegistry {
+        TokenRegistry::default()
+    }
+
+    /// Register a UDT, replace the old info if any
+    pub fn register(&mut self, type_script: &Script, info: TokenInfo) {
+        self.tokens
+            .insert(type_script.calc_script_hash().unpack(), info);
+    }
+
+    pub fn get(&self, type_hash: &H256) -> Option<&TokenInfo> {
+        self.tokens.get(type_hash)
+    }
+
+    /// The UDT of a cell, `None` for a cell without a registered type script
+    pub fn udt_of(&self, output: &CellOutput) -> Option<H256> {
+        let type_hash: H256 = output.type_().to_opt()?.calc_script_hash().unpack();
+        self.tokens.contains_key(&type_hash).then_some(type_hash)
+    }
+}
+
+/// The change of an asset of the wallet in a transaction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WalletEvent {
+    pub tx_hash: H256,
+    /// Milliseconds since the unix epoch, e.g. the block timestamp
+    pub timestamp: u64,
+    pub asset: Asset,
+    /// The net change of the wallet balance, excluding the fee
+    pub amount: i128,
+    /// The first lock script not owned by the wallet which sent the asset
+    /// (for an incoming amount) or received it (for an outgoing amount)
+    pub counterparty_lock: Option<Script>,
+    /// The transaction fee in shannons when the wallet provided inputs,
+    /// only set on the CKB event
+    pub fee: u64,
+}
+
+/// Classify `tx` from the point of view of the wallet owning `wallet_locks`.
+///
+/// The wallet pays the whole fee when it provides any input. The events are
+/// ordered by asset, CKB first, assets whose balance is unchanged are
+/// skipped unless the wallet paid a fee.
+pub fn classify_transaction(
+    tx: &TransactionView,
+    timestamp: u64,
+    wallet_locks: &[Script],
+    registry: &TokenRegistry,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Vec<WalletEvent>, LedgerError> {
+    let out_points = tx.input_pts_iter().collect::<Vec<_>>();
+    let inputs = tx_dep_provider.get_cells_with_data(&out_points)?;
+    let outputs = tx.outputs_with_data_iter().collect::<Vec<_>>();
+
+    let mut flows: BTreeMap<Asset, AssetFlow> = BTreeMap::new();
+    let mut input_capacity = 0u64;
+    let mut output_capacity = 0u64;
+    let mut paid_fee = false;
+    for (is_input, (output, data)) in inputs
+        .iter()
+        .map(|cell| (true, cell))
+        .chain(outputs.iter().map(|cell| (false, cell)))
+    {
+        let capacity: Capacity = output.capacity().unpack();
+        if is_input {
+            input_capacity += capacity.as_u64();
+        } else {
+            output_capacity += capacity.as_u64();
+        }
+        let lock = output.lock();
+        let owned = wallet_locks.contains(&lock);
+        paid_fee |= is_input && owned;
+        let mut assets = vec![(Asset::Ckb, u128::from(capacity.as_u64()))];
+        if let Some(type_hash) = registry.udt_of(output) {
+            assets.push((Asset::Udt(type_hash), parse_udt_amount(data)?));
+        }
+        for (asset, amount) in assets {
+            flows
+                .entry(asset)
+                .or_default()
+                .add(is_input, owned, amount, &lock);
+        }
+    }
+
+    let fee = if paid_fee {
+        input_capacity.saturating_sub(output_capacity)
+    } else {
+        0
+    };
+    let tx_hash: H256 = tx.hash().unpack();
+    let mut events = Vec::new();
+    for (asset, flow) in flows {
+        let mut spent = flow.spent;
+        if asset == Asset::Ckb {
+            spent = spent.saturating_sub(u128::from(fee));
+        }
+        let overflow = || LedgerError::AmountOverflow(format!("{:?}", asset));
+        let received = i128::try_from(flow.received).map_err(|_| overflow())?;
+        let spent = i128::try_from(spent).map_err(|_| overflow())?;
+        let amount = received - spent;
+        let fee = if asset == Asset::Ckb { fee } else { 0 };
+        if amount == 0 && fee == 0 {
+            continue;
+        }
+        let counterparty_lock = if amount > 0 {
+            flow.sender
+        } else {
+            flow.recipient
+        };
+        events.push(WalletEvent {
+            tx_hash: tx_hash.clone(),
+            timestamp,
+            asset,
+            amount,
+            counterparty_lock,
+            fee,
+        });
+    }
+    Ok(events)
+}
+
+/// The amounts of an asset moved by a transaction
+#[derive(Default)]
+struct AssetFlow {
+    /// Spent from the wallet inputs
+    spent: u128,
+    /// Received by the wallet outputs
+    received: u128,
+    /// The first foreign input lock
+    sender: Option<Script>,
+    /// The first foreign output lock
+    recipient: Option<Script>,
+}
+
+impl AssetFlow {
+    fn add(&mut self, is_input: bool, owned: bool, amount: u128, lock: &Script) {
+        match (is_input, owned) {
+            (true, true) => self.spent = self.spent.saturating_add(amount),
+            (false, true) => self.received = self.received.saturating_add(amount),
+            (true, false) => {
+                self.sender.get_or_insert_with(|| lock.clone());
+            }
+            (false, false) => {
+                self.recipient.get_or_insert_with(|| lock.clone());
+            }
+        }
+    }
+}
+
+/// A row of the exported ledger
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// The token symbol, or the type script hash of an unknown UDT
+    pub asset: String,
+    /// The signed decimal amount, e.g. `-12.5`
+    pub amount: String,
+    /// The address of the counterparty lock, empty if unknown
+    pub counterparty: String,
+    pub tx_hash: H256,
+    /// The fee in CKB
+    pub fee: String,
+}
+
+/// Turn [`WalletEvent`]s into ledger entries
+pub struct LedgerExporter<'a> {
+    registry: &'a TokenRegistry,
+    network: NetworkType,
+}
+
+impl<'a> LedgerExporter<'a> {
+    pub fn new(registry: &'a TokenRegistry, network: NetworkType) -> LedgerExporter<'a> {
+        LedgerExporter { registry, network }
+    }
+
+    pub fn entry(&self, event: &WalletEvent) -> LedgerEntry {
+        let (asset, decimals) = match &event.asset {
+            Asset::Ckb => ("CKB".to_string(), CKB_DECIMALS),
+            Asset::Udt(type_hash) => match self.registry.get(type_hash) {
+                Some(info) => (info.symbol.clone(), info.decimals),
+                None => (format!("{:#x}", type_hash), 0),
+            },
+        };
+        let counterparty = event
+            .counterparty_lock
+            .clone()
+            .map(|lock| Address::new(self.network, AddressPayload::from(lock), true).to_string())
+            .unwrap_or_default();
+        LedgerEntry {
+            timestamp: event.timestamp,
+            asset,
+            amount: format_amount(event.amount, decimals),
+            counterparty,
+            tx_hash: event.tx_hash.clone(),
+            fee: format_amount(i128::from(event.fee), CKB_DECIMALS),
+        }
+    }
+
+    pub fn entries(&self, events: &[WalletEvent]) -> Vec<LedgerEntry> {
+        events.iter().map(|event| self.entry(event)).collect()
+    }
+
+    /// Write the entries as CSV with a header row
+    pub fn write_csv<W: Write>(
+        &self,
+        events: &[WalletEvent],
+        mut writer: W,
+    ) -> Result<(), LedgerError> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+        for entry in self.entries(events) {
+            writeln!(
+                writer,
+                "{},{},{},{},{:#x},{}",
+                entry.timestamp,
+                csv_field(&entry.asset),
+                entry.amount,
+                csv_field(&entry.counterparty),
+                entry.tx_hash,
+                entry.fee
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the entries as a JSON array
+    pub fn write_json<W: Write>(
+        &self,
+        events: &[WalletEvent],
+        writer: W,
+    ) -> Result<(), LedgerError> {
+        serde_json::to_writer_pretty(writer, &self.entries(events))?;
+        Ok(())
+    }
+}
+
+/// Format an integer amount with `decimals` decimal places, the trailing
+/// zeros of the fraction are removed.
+pub fn format_amount(amount: i128, decimals: u8) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let digits = amount.unsigned_abs().to_string();
+    let decimals = usize::from(decimals);
+    if decimals == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        format!("{}{}", sign, integer)
+    } else {
+        format!("{}{}.{}", sign, integer, fraction)
+    }
+}
+
+/// Quote a CSV field containing a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(0, 8), "0");
+        assert_eq!(format_amount(1_250_000_000, 8), "12.5");
+        assert_eq!(format_amount(-1, 8), "-0.00000001");
+        assert_eq!(format_amount(-42, 0), "-42");
+        assert_eq!(format_amount(i128::MIN, 2).chars().next(), Some('-'));
+        assert_eq!(csv_field("a,b\"c"), "\"a,b\"\"c\"");
+        assert_eq!(csv_field("USDT"), "USDT");
+    }
+}
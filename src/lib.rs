@@ -1,22 +1,78 @@
+#![cfg_attr(docsrs, feature(doc_cfg))]
+
+#[cfg(feature = "hd")]
+pub mod address_rotation;
+#[cfg(feature = "hd")]
+pub mod address_scan;
+pub mod cell_snapshot;
+pub mod census;
+pub mod chain_scanner;
+#[cfg(feature = "verify")]
+mod compat;
 pub mod constants;
 pub mod core;
+pub mod dao_stats;
+pub mod deadline;
+pub mod deposit;
+#[cfg(feature = "rpc")]
+pub mod devnet;
+#[cfg(feature = "experimental")]
+#[cfg_attr(docsrs, doc(cfg(feature = "experimental")))]
+pub mod experimental;
+#[cfg(feature = "rpc")]
+pub mod faucet;
+pub mod fee_schedule;
+pub mod hash;
+pub mod header_chain;
+#[cfg(feature = "rpc")]
+pub mod health;
+pub mod lease;
+pub mod ledger;
+pub mod mining;
+pub mod otx_book;
+#[cfg(feature = "rpc")]
+pub mod preflight;
+#[cfg(feature = "rpc")]
 pub mod pubsub;
+pub mod redact;
+pub mod registry;
 pub mod rpc;
+#[cfg(feature = "indexer")]
+pub mod script_cache;
+#[cfg(all(
+    feature = "indexer",
+    feature = "builders-dao",
+    feature = "builders-udt"
+))]
+pub mod sdk;
+pub mod secp;
+pub mod session;
+pub mod test_vectors;
 pub mod traits;
 pub mod transaction;
 pub mod tx_builder;
+pub mod tx_proof;
+pub mod tx_stream;
 pub mod types;
 pub mod unlock;
 pub mod util;
+pub mod watchtower;
 
 #[cfg(feature = "test")]
 pub mod test_util;
 
+#[cfg(feature = "signer-testkit")]
+pub mod signer_testkit;
+
 #[cfg(feature = "test")]
 #[cfg(test)]
 mod tests;
 
-pub use rpc::{CkbRpcClient, IndexerRpcClient, RpcError};
+#[cfg(feature = "rpc")]
+pub use rpc::CkbRpcClient;
+#[cfg(feature = "indexer")]
+pub use rpc::IndexerRpcClient;
+pub use rpc::RpcError;
 pub use types::{
     Address, AddressPayload, AddressType, CodeHashIndex, HumanCapacity, NetworkInfo, NetworkType,
     OldAddress, OldAddressFormat, ScriptGroup, ScriptGroupType, ScriptId, Since, SinceType,
@@ -0,0 +1,256 @@
+//! Block templates and cellbase transactions for mining tools.
+//!
+//! [`BlockTemplateView`] is the `get_block_template` result in the core
+//! types. A pool puts its own lock script in the cellbase witness with
+//! [`BlockTemplateView::with_cellbase_witness`], fills in the nonce found by
+//! the miners with [`BlockTemplateView::block`] and submits the block with
+//! [`submit_block`].
+
+use ckb_jsonrpc_types as json_types;
+#[cfg(feature = "rpc")]
+use ckb_types::H256;
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockBuilder, BlockView, EpochNumberWithFraction, TransactionView, UncleBlockView},
+    packed::{
+        Byte32, CellInput, CellOutput, CellbaseWitness, Header, ProposalShortId,
+        ProposalShortIdVec, Script, Transaction, UncleBlock,
+    },
+    prelude::*,
+};
+
+#[cfg(feature = "rpc")]
+use crate::rpc::{CkbRpcClient, RpcError};
+
+/// A block template in the core types
+#[derive(Debug, Clone)]
+pub struct BlockTemplateView {
+    /// The id to submit the block with
+    pub work_id: u64,
+    pub version: u32,
+    pub compact_target: u32,
+    pub current_time: u64,
+    pub number: u64,
+    pub epoch: EpochNumberWithFraction,
+    pub parent_hash: Byte32,
+    pub cycles_limit: u64,
+    pub bytes_limit: u64,
+    pub uncles_count_limit: u64,
+    pub uncles: Vec<UncleBlockView>,
+    pub cellbase: TransactionView,
+    /// The transactions after the cellbase
+    pub transactions: Vec<TransactionView>,
+    pub proposals: Vec<ProposalShortId>,
+    pub dao: Byte32,
+    pub extension: Option<Bytes>,
+}
+
+impl From<json_types::BlockTemplate> for BlockTemplateView {
+    fn from(template: json_types::BlockTemplate) -> BlockTemplateView {
+        let uncles = template
+            .uncles
+            .into_iter()
+            .map(|uncle| {
+                UncleBlock::new_builder()
+                    .header(Header::from(uncle.header))
+                    .proposals(
+                        ProposalShortIdVec::new_builder()
+                            .set(
+                                uncle
+                                    .proposals
+                                    .into_iter()
+                                    .map(ProposalShortId::from)
+                                    .collect(),
+                            )
+                            .build(),
+                    )
+                    .build()
+                    .into_view()
+            })
+            .collect();
+        BlockTemplateView {
+            work_id: template.work_id.value(),
+            version: template.version.value(),
+            compact_target: template.compact_target.value(),
+            current_time: template.current_time.value(),
+            number: template.number.value(),
+            epoch: EpochNumberWithFraction::from_full_value(template.epoch.value()),
+            parent_hash: template.parent_hash.pack(),
+            cycles_limit: template.cycles_limit.value(),
+            bytes_limit: template.bytes_limit.value(),
+            uncles_count_limit: template.uncles_count_limit.value(),
+            uncles,
+            cellbase: Transaction::from(template.cellbase.data).into_view(),
+            transactions: template
+                .transactions
+                .into_iter()
+                .map(|tx| Transaction::from(tx.data).into_view())
+                .collect(),
+            proposals: template
+                .proposals
+                .into_iter()
+                .map(ProposalShortId::from)
+                .collect(),
+            dao: template.dao.into(),
+            extension: template.extension.map(|data| data.into_bytes()),
+        }
+    }
+}
+
+impl BlockTemplateView {
+    /// Replace the cellbase witness, the block reward of this block goes to
+    /// `lock` after the finalization delay
+    pub fn with_cellbase_witness(mut self, lock: Script, message: Bytes) -> Self {
+        let witness = cellbase_witness(lock, message);
+        self.cellbase = self
+            .cellbase
+            .as_advanced_builder()
+            .set_witnesses(vec![witness.as_bytes().pack()])
+            .build();
+        self
+    }
+
+    /// The block with `nonce`, the roots of the header are computed from
+    /// the transactions, the proposals and the uncles.
+    pub fn block(&self, nonce: u128) -> BlockView {
+        BlockBuilder::default()
+            .version(self.version.pack())
+            .compact_target(self.compact_target.pack())
+            .timestamp(self.current_time.pack())
+            .number(self.number.pack())
+            .epoch(self.epoch.full_value().pack())
+            .parent_hash(self.parent_hash.clone())
+            .dao(self.dao.clone())
+            .nonce(nonce.pack())
+            .uncles(self.uncles.clone())
+            .transaction(self.cellbase.clone())
+            .transactions(self.transactions.clone())
+            .proposals(self.proposals.clone())
+            .extension(self.extension.as_ref().map(|data| data.pack()))
+            .build()
+    }
+}
+
+/// The cellbase witness of a block mined by `lock`
+pub fn cellbase_witness(lock: Script, message: Bytes) -> CellbaseWitness {
+    CellbaseWitness::new_builder()
+        .lock(lock)
+        .message(message.pack())
+        .build()
+}
+
+/// The cellbase witness of `cellbase`, `None` if it is not a cellbase
+pub fn parse_cellbase_witness(cellbase: &TransactionView) -> Option<CellbaseWitness> {
+    if !cellbase.is_cellbase() {
+        return None;
+    }
+    let witness = cellbase.witnesses().get(0)?;
+    CellbaseWitness::from_slice(&witness.raw_data()).ok()
+}
+
+/// A cellbase of block `block_number` paying `reward`, the reward of the
+/// block mined the finalization delay (11 blocks) earlier, there is no
+/// reward in the first blocks of the chain. The block reward of this block goes to the
+/// lock of `witness`.
+pub fn build_cellbase(
+    block_number: u64,
+    reward: Option<CellOutput>,
+    witness: CellbaseWitness,
+) -> TransactionView {
+    let mut builder = TransactionView::new_advanced_builder()
+        .input(CellInput::new_cellbase_input(block_number))
+        .witness(witness.as_bytes().pack());
+    if let Some(reward) = reward {
+        builder = builder.output(reward).output_data(Bytes::new().pack());
+    }
+    builder.build()
+}
+
+/// Get a block template, `None` limits are the limits of the node
+#[cfg(feature = "rpc")]
+pub fn get_block_template(
+    ckb_client: &CkbRpcClient,
+    bytes_limit: Option<u64>,
+    proposals_limit: Option<u64>,
+) -> Result<BlockTemplateView, RpcError> {
+    let template = ckb_client.get_block_template(
+        bytes_limit.map(Into::into),
+        proposals_limit.map(Into::into),
+        None,
+    )?;
+    Ok(template.into())
+}
+
+/// Submit a mined block of the template `work_id`, returns the block hash
+#[cfg(feature = "rpc")]
+pub fn submit_block(
+    ckb_client: &CkbRpcClient,
+    work_id: u64,
+    block: &BlockView,
+) -> Result<H256, RpcError> {
+    ckb_client.submit_block(work_id.to_string(), block.data().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::capacity_bytes, h256};
+
+    #[test]
+    fn test_block_template() {
+        let lock = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let reward = CellOutput::new_builder()
+            .capacity(capacity_bytes!(1000).pack())
+            .lock(lock.clone())
+            .build();
+        let cellbase = build_cellbase(
+            20,
+            Some(reward),
+            cellbase_witness(Script::default(), Bytes::new()),
+        );
+        assert!(cellbase.is_cellbase());
+        assert_eq!(cellbase.outputs_data().len(), 1);
+
+        let template = BlockTemplateView {
+            work_id: 7,
+            version: 0,
+            compact_target: 0x1e08_3126,
+            current_time: 1_700_000_000_000,
+            number: 20,
+            epoch: EpochNumberWithFraction::new(1, 10, 1800),
+            parent_hash: h256!("0x1").pack(),
+            cycles_limit: 100,
+            bytes_limit: 100,
+            uncles_count_limit: 2,
+            uncles: Vec::new(),
+            cellbase,
+            transactions: Vec::new(),
+            proposals: Vec::new(),
+            dao: Byte32::default(),
+            extension: None,
+        };
+        let pool_template = template
+            .clone()
+            .with_cellbase_witness(lock.clone(), Bytes::from("pool"));
+        let witness = parse_cellbase_witness(&pool_template.cellbase).unwrap();
+        assert_eq!(witness.lock(), lock);
+        assert_eq!(witness.message().raw_data(), Bytes::from("pool"));
+        assert_ne!(pool_template.cellbase.hash(), template.cellbase.hash());
+
+        let block = pool_template.block(42);
+        assert_eq!(block.number(), 20);
+        assert_eq!(block.nonce(), 42);
+        assert_eq!(block.parent_hash(), h256!("0x1").pack());
+        assert_eq!(
+            block.transactions()[0].hash(),
+            pool_template.cellbase.hash()
+        );
+        assert_ne!(
+            block.transactions_root(),
+            template.block(42).transactions_root()
+        );
+        assert!(parse_cellbase_witness(&TransactionView::new_advanced_builder().build()).is_none());
+    }
+}
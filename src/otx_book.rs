@@ -0,0 +1,368 @@
+//! Expire the open transactions whose inputs are spent elsewhere.
+//!
+//! An open transaction (OTX) stays in a broker's [`OtxBook`] until it is
+//! aggregated, but its originator may spend the same inputs in another
+//! transaction at any time, and an aggregate transaction including that OTX
+//! would be rejected as a whole. The book indexes the inputs of its OTXs and
+//! expires the stale ones when:
+//!
+//! - a committed transaction spends one of their inputs, see
+//!   [`OtxBook::apply_tx`], the book is also a [`ScanHandler`] so it can be
+//!   driven by a [`ChainScanner`](crate::chain_scanner::ChainScanner);
+//! - a [`LiveCellChecker`] reports one of their inputs is not live, see
+//!   [`OtxBook::expire_spent`].
+//!
+//! The originators are told through the [`StaleOtxNotifier`] registered with
+//! [`OtxBook::set_notifier`]. [`OtxBook::check_aggregate`] rejects the OTXs
+//! which are no longer in the book before they are assembled.
+
+use std::collections::{BTreeMap, HashMap};
+
+use ckb_types::{
+    core::{BlockView, TransactionView},
+    packed::{Byte32, OutPoint},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use crate::chain_scanner::{ChainScannerError, ScanHandler};
+#[cfg(feature = "rpc")]
+use crate::rpc::CkbRpcClient;
+use crate::rpc::RpcError;
+
+#[derive(Error, Debug)]
+pub enum OtxBookError {
+    #[error("the input `{out_point}` is already used by the open transaction `{otx_hash}`")]
+    InputConflict { out_point: String, otx_hash: String },
+
+    #[error("the open transaction `{0}` is not in the book, it may have expired")]
+    UnknownOtx(String),
+
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// An open transaction waiting in the book
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpenTx {
+    pub tx: TransactionView,
+    /// Who submitted the OTX, e.g. a peer id or a callback url
+    pub originator: String,
+}
+
+/// Why an open transaction expired
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StaleReason {
+    /// The inputs are spent by this committed transaction
+    SpentBy(Byte32),
+    /// The inputs are not live anymore
+    NotLive,
+}
+
+/// An open transaction removed from the book
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StaleOtx {
+    pub otx: OpenTx,
+    /// The inputs of the OTX which are spent
+    pub spent_inputs: Vec<OutPoint>,
+    pub reason: StaleReason,
+}
+
+/// Tell the originator an open transaction has expired
+pub trait StaleOtxNotifier {
+    fn notify(&mut self, stale: &StaleOtx);
+}
+
+impl<F> StaleOtxNotifier for F
+where
+    F: FnMut(&StaleOtx),
+{
+    fn notify(&mut self, stale: &StaleOtx) {
+        self(stale)
+    }
+}
+
+/// Tell whether a cell can still be spent
+pub trait LiveCellChecker {
+    fn is_live(&self, out_point: &OutPoint) -> Result<bool, OtxBookError>;
+}
+
+/// A cell spent by a transaction of the pool is not live
+#[cfg(feature = "rpc")]
+impl LiveCellChecker for CkbRpcClient {
+    fn is_live(&self, out_point: &OutPoint) -> Result<bool, OtxBookError> {
+        let cell_with_status =
+            self.get_live_cell_with_include_tx_pool(out_point.clone().into(), false, true)?;
+        Ok(cell_with_status.status == "live")
+    }
+}
+
+/// The open transactions of a broker, indexed by their inputs
+#[derive(Default)]
+pub struct OtxBook {
+    otxs: BTreeMap<H256, OpenTx>,
+    inputs: HashMap<OutPoint, H256>,
+    notifier: Option<Box<dyn StaleOtxNotifier>>,
+}
+
+impl OtxBook {
+    pub fn new() -> OtxBook {
+        OtxBook::default()
+    }
+
+    pub fn set_notifier(&mut self, notifier: Box<dyn StaleOtxNotifier>) {
+        self.notifier = Some(notifier);
+    }
+
+    /// Add an open transaction, it must not share an input with another OTX
+    /// of the book. Adding the same OTX again replaces its originator.
+    pub fn add(
+        &mut self,
+        tx: TransactionView,
+        originator: impl Into<String>,
+    ) -> Result<(), OtxBookError> {
+        let otx_hash: H256 = tx.hash().unpack();
+        for out_point in tx.input_pts_iter() {
+            match self.inputs.get(&out_point) {
+                Some(other) if other != &otx_hash => {
+                    return Err(OtxBookError::InputConflict {
+                        out_point: format!("{}", out_point),
+                        otx_hash: format!("{:#x}", other),
+                    });
+                }
+                _ => {}
+            }
+        }
+        for out_point in tx.input_pts_iter() {
+            self.inputs.insert(out_point, otx_hash.clone());
+        }
+        let originator = originator.into();
+        self.otxs.insert(otx_hash, OpenTx { tx, originator });
+        Ok(())
+    }
+
+    /// Remove an open transaction without notifying its originator, e.g.
+    /// after it is aggregated
+    pub fn remove(&mut self, otx_hash: &Byte32) -> Option<OpenTx> {
+        let otx = self.otxs.remove(&otx_hash.unpack())?;
+        for out_point in otx.tx.input_pts_iter() {
+            self.inputs.remove(&out_point);
+        }
+        Some(otx)
+    }
+
+    pub fn get(&self, otx_hash: &Byte32) -> Option<&OpenTx> {
+        self.otxs.get(&otx_hash.unpack())
+    }
+
+    /// The open transactions ordered by hash
+    pub fn otxs(&self) -> impl Iterator<Item = &OpenTx> {
+        self.otxs.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.otxs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.otxs.is_empty()
+    }
+
+    /// The open transactions to aggregate, fails if any of them is no longer
+    /// in the book.
+    pub fn check_aggregate(&self, otx_hashes: &[Byte32]) -> Result<Vec<&OpenTx>, OtxBookError> {
+        otx_hashes
+            .iter()
+            .map(|otx_hash| {
+                self.get(otx_hash)
+                    .ok_or_else(|| OtxBookError::UnknownOtx(format!("{:#x}", otx_hash)))
+            })
+            .collect()
+    }
+
+    /// Expire the open transactions spending the same inputs as the
+    /// committed `tx`. An aggregate transaction including OTXs of the book
+    /// expires nothing, the aggregated OTXs are removed silently.
+    pub fn apply_tx(&mut self, tx: &TransactionView) -> Vec<StaleOtx> {
+        let mut spent: BTreeMap<H256, Vec<OutPoint>> = BTreeMap::new();
+        for out_point in tx.input_pts_iter() {
+            if let Some(otx_hash) = self.inputs.get(&out_point) {
+                spent.entry(otx_hash.clone()).or_default().push(out_point);
+            }
+        }
+        let mut stale_otxs = Vec::new();
+        for (otx_hash, spent_inputs) in spent {
+            let otx_hash = otx_hash.pack();
+            let aggregated = self
+                .get(&otx_hash)
+                .map(|otx| is_aggregated(&otx.tx, tx))
+                .unwrap_or(false);
+            if aggregated {
+                self.remove(&otx_hash);
+            } else {
+                let reason = StaleReason::SpentBy(tx.hash());
+                stale_otxs.extend(self.expire(&otx_hash, spent_inputs, reason));
+            }
+        }
+        stale_otxs
+    }
+
+    /// Check the inputs of every open transaction and expire the OTXs with a
+    /// spent input.
+    pub fn expire_spent(
+        &mut self,
+        checker: &dyn LiveCellChecker,
+    ) -> Result<Vec<StaleOtx>, OtxBookError> {
+        let mut spent: BTreeMap<H256, Vec<OutPoint>> = BTreeMap::new();
+        for (otx_hash, otx) in &self.otxs {
+            for out_point in otx.tx.input_pts_iter() {
+                if !checker.is_live(&out_point)? {
+                    spent.entry(otx_hash.clone()).or_default().push(out_point);
+                }
+            }
+        }
+        let mut stale_otxs = Vec::new();
+        for (otx_hash, spent_inputs) in spent {
+            stale_otxs.extend(self.expire(&otx_hash.pack(), spent_inputs, StaleReason::NotLive));
+        }
+        Ok(stale_otxs)
+    }
+
+    fn expire(
+        &mut self,
+        otx_hash: &Byte32,
+        spent_inputs: Vec<OutPoint>,
+        reason: StaleReason,
+    ) -> Option<StaleOtx> {
+        let otx = self.remove(otx_hash)?;
+        let stale = StaleOtx {
+            otx,
+            spent_inputs,
+            reason,
+        };
+        if let Some(notifier) = self.notifier.as_mut() {
+            notifier.notify(&stale);
+        }
+        Some(stale)
+    }
+}
+
+/// Whether `tx` includes all the inputs and outputs of `otx`
+fn is_aggregated(otx: &TransactionView, tx: &TransactionView) -> bool {
+    let inputs = tx.input_pts_iter().collect::<Vec<_>>();
+    let outputs = tx.outputs_with_data_iter().collect::<Vec<_>>();
+    otx.input_pts_iter().all(|input| inputs.contains(&input))
+        && otx
+            .outputs_with_data_iter()
+            .all(|output| outputs.contains(&output))
+}
+
+/// The expired OTXs are not restored when a block is rolled back, their
+/// originators have been told to resubmit them.
+impl ScanHandler for OtxBook {
+    fn on_transaction(
+        &mut self,
+        _block: &BlockView,
+        _tx_index: usize,
+        tx: &TransactionView,
+    ) -> Result<(), ChainScannerError> {
+        self.apply_tx(tx);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::TransactionBuilder,
+        packed::{CellInput, CellOutput},
+        prelude::*,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn out_point(seed: u8) -> OutPoint {
+        OutPoint::new(Byte32::new([seed; 32]), 0)
+    }
+
+    fn otx(inputs: &[u8], capacity: u64) -> TransactionView {
+        TransactionBuilder::default()
+            .inputs(
+                inputs
+                    .iter()
+                    .map(|seed| CellInput::new(out_point(*seed), 0)),
+            )
+            .output(CellOutput::new_builder().capacity(capacity.pack()).build())
+            .output_data(Bytes::new().pack())
+            .build()
+    }
+
+    struct SpentCells(Vec<OutPoint>);
+
+    impl LiveCellChecker for SpentCells {
+        fn is_live(&self, out_point: &OutPoint) -> Result<bool, OtxBookError> {
+            Ok(!self.0.contains(out_point))
+        }
+    }
+
+    #[test]
+    fn test_expire_stale_otxs() {
+        let notified = Rc::new(RefCell::new(Vec::new()));
+        let notified_clone = notified.clone();
+        let mut book = OtxBook::new();
+        book.set_notifier(Box::new(move |stale: &StaleOtx| {
+            notified_clone
+                .borrow_mut()
+                .push(stale.otx.originator.clone());
+        }));
+        let alice = otx(&[1, 2], 100);
+        let bob = otx(&[3], 200);
+        let carol = otx(&[4], 300);
+        book.add(alice.clone(), "alice").unwrap();
+        book.add(bob.clone(), "bob").unwrap();
+        book.add(carol.clone(), "carol").unwrap();
+        assert!(matches!(
+            book.add(otx(&[2, 5], 400), "mallory"),
+            Err(OtxBookError::InputConflict { .. })
+        ));
+        assert_eq!(book.len(), 3);
+
+        // alice spends one of the inputs elsewhere
+        let double_spend = otx(&[2], 50);
+        let stale = book.apply_tx(&double_spend);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].otx.tx, alice);
+        assert_eq!(stale[0].spent_inputs, vec![out_point(2)]);
+        assert_eq!(stale[0].reason, StaleReason::SpentBy(double_spend.hash()));
+        assert!(matches!(
+            book.check_aggregate(&[alice.hash(), bob.hash()]),
+            Err(OtxBookError::UnknownOtx(_))
+        ));
+        // the input 1 of alice is free again
+        book.add(otx(&[1], 100), "alice").unwrap();
+
+        // bob's input is spent in the pool
+        let stale = book.expire_spent(&SpentCells(vec![out_point(3)])).unwrap();
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].reason, StaleReason::NotLive);
+        assert_eq!(*notified.borrow(), vec!["alice", "bob"]);
+
+        // an aggregate transaction including carol is not a double spend
+        let aggregate = TransactionBuilder::default()
+            .inputs(carol.inputs())
+            .input(CellInput::new(out_point(6), 0))
+            .outputs(carol.outputs())
+            .outputs_data(carol.outputs_data())
+            .build();
+        assert_eq!(book.check_aggregate(&[carol.hash()]).unwrap().len(), 1);
+        assert!(book.apply_tx(&aggregate).is_empty());
+        assert!(book.get(&carol.hash()).is_none());
+        assert_eq!(notified.borrow().len(), 2);
+        assert_eq!(book.len(), 1);
+    }
+}
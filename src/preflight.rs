@@ -0,0 +1,421 @@
+//! Check whether the tx pool is likely to accept a transaction before
+//! submitting it.
+//!
+//! The checks only use the information exposed by the `tx_pool_info` and
+//! `get_raw_tx_pool` rpc methods, they are not a replacement of the
+//! `test_tx_pool_accept` rpc which runs the scripts.
+
+use std::collections::{HashMap, HashSet};
+
+use ckb_jsonrpc_types::{RawTxPool, TxPoolEntries, TxPoolEntry, TxPoolInfo};
+use ckb_types::{
+    core::{FeeRate, TransactionView},
+    packed::OutPoint,
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use crate::rpc::{CkbRpcClient, RpcError};
+
+/// The default `max_ancestors_count` of the ckb tx pool config
+pub const DEFAULT_MAX_ANCESTORS_COUNT: u64 = 125;
+
+#[derive(Error, Debug)]
+pub enum PreflightError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("get_raw_tx_pool returned tx ids, expected verbose entries")]
+    UnexpectedRawTxPool,
+}
+
+/// Reasons the tx pool may reject (or evict) the transaction
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum PreflightWarning {
+    /// The fee rate (shannons/KB) is lower than the pool's `min_fee_rate`
+    FeeRateTooLow { fee_rate: u64, min_fee_rate: u64 },
+    /// The transaction is larger than the pool's `tx_size_limit`
+    TxSizeTooLarge { tx_size: u64, tx_size_limit: u64 },
+    /// The pool is full, low fee rate transactions will be evicted
+    PoolFull {
+        total_tx_size: u64,
+        max_tx_pool_size: u64,
+    },
+    /// Too many in-pool ancestors (including the transaction itself)
+    TooManyAncestors { ancestors_count: u64, limit: u64 },
+    /// The transaction spends the same inputs as pending transactions, but
+    /// the fee is not enough to replace them.
+    ReplaceFeeTooLow {
+        conflicts: Vec<H256>,
+        fee: u64,
+        min_replace_fee: u64,
+    },
+    /// The transaction conflicts with pending transactions and the node
+    /// disabled RBF (`min_rbf_rate <= min_fee_rate`).
+    ReplaceDisabled { conflicts: Vec<H256> },
+}
+
+/// The tx pool parameters used by preflight checks
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PoolLimits {
+    pub min_fee_rate: u64,
+    pub min_rbf_rate: u64,
+    pub tx_size_limit: u64,
+    pub max_tx_pool_size: u64,
+    pub total_tx_size: u64,
+    pub max_ancestors_count: u64,
+}
+
+impl From<&TxPoolInfo> for PoolLimits {
+    fn from(info: &TxPoolInfo) -> PoolLimits {
+        PoolLimits {
+            min_fee_rate: info.min_fee_rate.value(),
+            min_rbf_rate: info.min_rbf_rate.value(),
+            tx_size_limit: info.tx_size_limit.value(),
+            max_tx_pool_size: info.max_tx_pool_size.value(),
+            total_tx_size: info.total_tx_size.value(),
+            max_ancestors_count: DEFAULT_MAX_ANCESTORS_COUNT,
+        }
+    }
+}
+
+/// Check the transaction against the tx pool of the node.
+///
+/// Arguments:
+///   * `fee` is the transaction fee in shannons, see [`crate::tx_builder::tx_fee`]
+///   * `known_pending` are the pending transactions which may spend the same
+///     inputs (e.g. the transaction being replaced), they are used to check RBF
+///     rules.
+pub fn preflight(
+    rpc_client: &CkbRpcClient,
+    tx: &TransactionView,
+    fee: u64,
+    known_pending: &[TransactionView],
+) -> Result<Vec<PreflightWarning>, PreflightError> {
+    let pool_info = rpc_client.tx_pool_info()?;
+    let entries = match rpc_client.get_raw_tx_pool(Some(true))? {
+        RawTxPool::Verbose(entries) => entries,
+        RawTxPool::Ids(_) => return Err(PreflightError::UnexpectedRawTxPool),
+    };
+    Ok(check_tx(
+        tx,
+        fee,
+        &PoolLimits::from(&pool_info),
+        &entries,
+        known_pending,
+    ))
+}
+
+/// The checks of [`preflight`] without rpc calls
+pub fn check_tx(
+    tx: &TransactionView,
+    fee: u64,
+    limits: &PoolLimits,
+    entries: &TxPoolEntries,
+    known_pending: &[TransactionView],
+) -> Vec<PreflightWarning> {
+    let mut warnings = Vec::new();
+    let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+
+    let min_fee = FeeRate::from_u64(limits.min_fee_rate).fee(tx_size).as_u64();
+    if fee < min_fee {
+        warnings.push(PreflightWarning::FeeRateTooLow {
+            fee_rate: fee.saturating_mul(1000) / tx_size.max(1),
+            min_fee_rate: limits.min_fee_rate,
+        });
+    }
+    if tx_size > limits.tx_size_limit {
+        warnings.push(PreflightWarning::TxSizeTooLarge {
+            tx_size,
+            tx_size_limit: limits.tx_size_limit,
+        });
+    }
+    if limits.total_tx_size.saturating_add(tx_size) > limits.max_tx_pool_size {
+        warnings.push(PreflightWarning::PoolFull {
+            total_tx_size: limits.total_tx_size,
+            max_tx_pool_size: limits.max_tx_pool_size,
+        });
+    }
+
+    let pool_entries = entries
+        .pending
+        .iter()
+        .chain(entries.proposed.iter())
+        .collect::<HashMap<&H256, &TxPoolEntry>>();
+
+    // The ancestors of the parents may overlap, so this is an upper bound.
+    let parents = tx
+        .input_pts_iter()
+        .map(|out_point| out_point.tx_hash().unpack())
+        .collect::<HashSet<H256>>();
+    let ancestors_count = parents
+        .iter()
+        .filter_map(|parent| pool_entries.get(parent))
+        .map(|entry| entry.ancestors_count.value())
+        .sum::<u64>()
+        + 1;
+    if ancestors_count > limits.max_ancestors_count {
+        warnings.push(PreflightWarning::TooManyAncestors {
+            ancestors_count,
+            limit: limits.max_ancestors_count,
+        });
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    let inputs = tx.input_pts_iter().collect::<HashSet<OutPoint>>();
+    let conflicts = known_pending
+        .iter()
+        .filter(|pending| pending.hash() != tx.hash())
+        .filter(|pending| {
+            pending
+                .input_pts_iter()
+                .any(|input| inputs.contains(&input))
+        })
+        .filter_map(|pending| {
+            let tx_hash: H256 = pending.hash().unpack();
+            pool_entries
+                .get(&tx_hash)
+                .map(|entry| (tx_hash, entry.fee.value()))
+        })
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        let conflict_hashes = conflicts
+            .iter()
+            .map(|(tx_hash, _)| tx_hash.clone())
+            .collect::<Vec<_>>();
+        if limits.min_rbf_rate <= limits.min_fee_rate {
+            warnings.push(PreflightWarning::ReplaceDisabled {
+                conflicts: conflict_hashes,
+            });
+        } else {
+            let min_replace_fee = conflicts
+                .iter()
+                .map(|(_, fee)| *fee)
+                .sum::<u64>()
+                .saturating_add(FeeRate::from_u64(limits.min_rbf_rate).fee(tx_size).as_u64());
+            if fee < min_replace_fee {
+                warnings.push(PreflightWarning::ReplaceFeeTooLow {
+                    conflicts: conflict_hashes,
+                    fee,
+                    min_replace_fee,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// An out point consumed more than once in a batch of transactions
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputConflict {
+    pub out_point: OutPoint,
+    /// The indices of the transactions spending the out point in the batch, an
+    /// index appears twice when the transaction itself spends it twice.
+    pub tx_indices: Vec<usize>,
+}
+
+/// Find the out points consumed by more than one input of the prepared
+/// transactions, in the order of their first use. The node would reject all
+/// but the first transaction spending each of them.
+pub fn detect_conflicts(txs: &[TransactionView]) -> Vec<InputConflict> {
+    #[allow(clippy::mutable_key_type)]
+    let mut spenders: HashMap<OutPoint, usize> = HashMap::new();
+    let mut conflicts: Vec<InputConflict> = Vec::new();
+    for (tx_index, tx) in txs.iter().enumerate() {
+        for out_point in tx.input_pts_iter() {
+            if let Some(first_index) = spenders.get(&out_point) {
+                match conflicts
+                    .iter_mut()
+                    .find(|conflict| conflict.out_point == out_point)
+                {
+                    Some(conflict) => conflict.tx_indices.push(tx_index),
+                    None => conflicts.push(InputConflict {
+                        out_point,
+                        tx_indices: vec![*first_index, tx_index],
+                    }),
+                }
+            } else {
+                spenders.insert(out_point, tx_index);
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::TransactionBuilder,
+        packed::{CellInput, CellOutput},
+    };
+
+    fn limits() -> PoolLimits {
+        PoolLimits {
+            min_fee_rate: 1000,
+            min_rbf_rate: 1500,
+            tx_size_limit: 512_000,
+            max_tx_pool_size: 180_000_000,
+            total_tx_size: 0,
+            max_ancestors_count: DEFAULT_MAX_ANCESTORS_COUNT,
+        }
+    }
+
+    fn pool_entry(fee: u64, ancestors_count: u64) -> TxPoolEntry {
+        TxPoolEntry {
+            cycles: 0.into(),
+            size: 0.into(),
+            fee: fee.into(),
+            ancestors_size: 0.into(),
+            ancestors_cycles: 0.into(),
+            ancestors_count: ancestors_count.into(),
+            timestamp: 0.into(),
+        }
+    }
+
+    fn empty_entries() -> TxPoolEntries {
+        TxPoolEntries {
+            pending: HashMap::new(),
+            proposed: HashMap::new(),
+            conflicted: Vec::new(),
+        }
+    }
+
+    fn build_tx(inputs: &[OutPoint], output_capacity: u64) -> TransactionView {
+        TransactionBuilder::default()
+            .inputs(inputs.iter().map(|input| CellInput::new(input.clone(), 0)))
+            .output(
+                CellOutput::new_builder()
+                    .capacity(output_capacity.pack())
+                    .build(),
+            )
+            .output_data(Bytes::new().pack())
+            .build()
+    }
+
+    #[test]
+    fn test_check_fee_and_size() {
+        let tx = build_tx(&[OutPoint::new(H256([1u8; 32]).pack(), 0)], 100);
+        let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+        let min_fee = FeeRate::from_u64(1000).fee(tx_size).as_u64();
+        let entries = empty_entries();
+
+        assert!(check_tx(&tx, min_fee, &limits(), &entries, &[]).is_empty());
+        let warnings = check_tx(&tx, min_fee - 1, &limits(), &entries, &[]);
+        assert!(matches!(
+            warnings.as_slice(),
+            [PreflightWarning::FeeRateTooLow {
+                min_fee_rate: 1000,
+                ..
+            }]
+        ));
+
+        let mut small_pool = limits();
+        small_pool.tx_size_limit = tx_size - 1;
+        small_pool.max_tx_pool_size = 1000;
+        small_pool.total_tx_size = 1000;
+        let warnings = check_tx(&tx, min_fee, &small_pool, &entries, &[]);
+        assert_eq!(
+            warnings,
+            vec![
+                PreflightWarning::TxSizeTooLarge {
+                    tx_size,
+                    tx_size_limit: tx_size - 1
+                },
+                PreflightWarning::PoolFull {
+                    total_tx_size: 1000,
+                    max_tx_pool_size: 1000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_ancestors_and_rbf() {
+        let input = OutPoint::new(H256([1u8; 32]).pack(), 0);
+        let parent = OutPoint::new(H256([2u8; 32]).pack(), 0);
+        let pending = build_tx(&[input.clone()], 200);
+        let tx = build_tx(&[input, parent], 100);
+        let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+        let min_replace_fee = 5000 + FeeRate::from_u64(1500).fee(tx_size).as_u64();
+
+        let mut entries = empty_entries();
+        entries
+            .pending
+            .insert(pending.hash().unpack(), pool_entry(5000, 1));
+        entries.proposed.insert(
+            H256([2u8; 32]),
+            pool_entry(1000, DEFAULT_MAX_ANCESTORS_COUNT),
+        );
+
+        let pending_hash: H256 = pending.hash().unpack();
+        let warnings = check_tx(
+            &tx,
+            min_replace_fee - 1,
+            &limits(),
+            &entries,
+            &[pending.clone()],
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                PreflightWarning::TooManyAncestors {
+                    ancestors_count: DEFAULT_MAX_ANCESTORS_COUNT + 1,
+                    limit: DEFAULT_MAX_ANCESTORS_COUNT
+                },
+                PreflightWarning::ReplaceFeeTooLow {
+                    conflicts: vec![pending_hash.clone()],
+                    fee: min_replace_fee - 1,
+                    min_replace_fee,
+                },
+            ]
+        );
+
+        entries.proposed.clear();
+        assert!(check_tx(
+            &tx,
+            min_replace_fee,
+            &limits(),
+            &entries,
+            &[pending.clone()]
+        )
+        .is_empty());
+
+        let mut no_rbf = limits();
+        no_rbf.min_rbf_rate = no_rbf.min_fee_rate;
+        assert_eq!(
+            check_tx(&tx, min_replace_fee, &no_rbf, &entries, &[pending]),
+            vec![PreflightWarning::ReplaceDisabled {
+                conflicts: vec![pending_hash]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detect_conflicts() {
+        let a = OutPoint::new(H256([1u8; 32]).pack(), 0);
+        let b = OutPoint::new(H256([1u8; 32]).pack(), 1);
+        let c = OutPoint::new(H256([2u8; 32]).pack(), 0);
+        let txs = vec![
+            build_tx(&[a, b.clone()], 100),
+            build_tx(&[c.clone()], 100),
+            build_tx(&[b.clone(), c.clone(), c.clone()], 100),
+        ];
+        assert!(detect_conflicts(&txs[0..2]).is_empty());
+        assert_eq!(
+            detect_conflicts(&txs),
+            vec![
+                InputConflict {
+                    out_point: b,
+                    tx_indices: vec![0, 2],
+                },
+                InputConflict {
+                    out_point: c,
+                    tx_indices: vec![1, 2, 2],
+                },
+            ]
+        );
+    }
+}
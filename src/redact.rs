@@ -0,0 +1,221 @@
+//! Redact script args, hashes and amounts in logs and error messages.
+//!
+//! The errors and the logs of the SDK end up in shared log pipelines, the
+//! lock args of a wallet and the amounts it moves should not. The values are
+//! formatted through the wrappers of this module, which truncate the hashes
+//! and the args (`0x1234abcd..ef01`) and, in [`Redaction::Strict`] mode, hide
+//! the amounts. The full values are shown with [`Redaction::Off`] or when
+//! the `trace` log level is enabled.
+//!
+//! ```
+//! use ckb_sdk::redact::{self, Redaction};
+//!
+//! redact::set_redaction(Redaction::Strict);
+//! assert_eq!(redact::amount(100).to_string(), "<redacted>");
+//! # redact::set_redaction(Redaction::default());
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use ckb_types::{core::ScriptHashType, molecule::hex_string, packed::Script, prelude::*};
+
+/// The bytes shown at the head and the tail of a truncated value
+const HEAD_LEN: usize = 4;
+const TAIL_LEN: usize = 2;
+
+/// How much of the sensitive values is shown
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+#[repr(u8)]
+pub enum Redaction {
+    /// The full values
+    Off = 0,
+    /// Truncated hashes and args, full amounts
+    #[default]
+    Truncate = 1,
+    /// Truncated hashes and args, no amounts
+    Strict = 2,
+}
+
+static REDACTION: AtomicU8 = AtomicU8::new(Redaction::Truncate as u8);
+
+/// Set the process wide redaction mode, [`Redaction::Truncate`] by default
+pub fn set_redaction(redaction: Redaction) {
+    REDACTION.store(redaction as u8, Ordering::Relaxed);
+}
+
+/// The current redaction mode, [`Redaction::Off`] when the `trace` log
+/// level is enabled
+pub fn redaction() -> Redaction {
+    if log::max_level() >= log::LevelFilter::Trace {
+        return Redaction::Off;
+    }
+    match REDACTION.load(Ordering::Relaxed) {
+        0 => Redaction::Off,
+        2 => Redaction::Strict,
+        _ => Redaction::Truncate,
+    }
+}
+
+/// Bytes in hex, see [`bytes`]
+#[derive(Debug, Clone, Copy)]
+pub struct RedactedBytes<'a> {
+    data: &'a [u8],
+    redaction: Option<Redaction>,
+}
+
+impl RedactedBytes<'_> {
+    /// Format with `redaction` instead of the process wide mode
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+}
+
+impl fmt::Display for RedactedBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let data = self.data;
+        if self.redaction.unwrap_or_else(redaction) == Redaction::Off
+            || data.len() <= HEAD_LEN + TAIL_LEN
+        {
+            return write!(f, "0x{}", hex_string(data));
+        }
+        write!(
+            f,
+            "0x{}..{}",
+            hex_string(&data[..HEAD_LEN]),
+            hex_string(&data[data.len() - TAIL_LEN..])
+        )
+    }
+}
+
+/// A script with its code hash and args in hex, see [`script`]
+#[derive(Debug, Clone, Copy)]
+pub struct RedactedScript<'a> {
+    script: &'a Script,
+    redaction: Option<Redaction>,
+}
+
+impl RedactedScript<'_> {
+    /// Format with `redaction` instead of the process wide mode
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+}
+
+impl fmt::Display for RedactedScript<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash_type = ScriptHashType::try_from(self.script.hash_type())
+            .map(|hash_type| format!("{:?}", hash_type))
+            .unwrap_or_else(|_| self.script.hash_type().to_string());
+        let code_hash = self.script.code_hash();
+        let args = self.script.args().raw_data();
+        let redaction = self.redaction.unwrap_or_else(redaction);
+        write!(
+            f,
+            "Script {{ code_hash: {}, hash_type: {}, args: {} }}",
+            bytes(code_hash.as_slice()).with_redaction(redaction),
+            hash_type,
+            bytes(&args).with_redaction(redaction)
+        )
+    }
+}
+
+/// An amount, see [`amount`]
+#[derive(Debug, Clone, Copy)]
+pub struct RedactedAmount<T> {
+    amount: T,
+    redaction: Option<Redaction>,
+}
+
+impl<T> RedactedAmount<T> {
+    /// Format with `redaction` instead of the process wide mode
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for RedactedAmount<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.redaction.unwrap_or_else(redaction) == Redaction::Strict {
+            write!(f, "<redacted>")
+        } else {
+            self.amount.fmt(f)
+        }
+    }
+}
+
+/// Format a hash or args, truncated unless the redaction is off
+pub fn bytes(data: &[u8]) -> RedactedBytes<'_> {
+    RedactedBytes {
+        data,
+        redaction: None,
+    }
+}
+
+/// Format a script, its code hash and args are truncated unless the
+/// redaction is off
+pub fn script(script: &Script) -> RedactedScript<'_> {
+    RedactedScript {
+        script,
+        redaction: None,
+    }
+}
+
+/// Format a capacity or an udt amount, hidden in the strict mode
+pub fn amount<T: fmt::Display>(amount: T) -> RedactedAmount<T> {
+    RedactedAmount {
+        amount,
+        redaction: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, h256};
+
+    #[test]
+    fn test_redaction() {
+        let lock = Script::new_builder()
+            .code_hash(
+                h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8").pack(),
+            )
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(vec![0x11; 20]).pack())
+            .build();
+        // the process wide mode is left alone, the other tests format errors
+        // with it concurrently
+        let truncate = Redaction::Truncate;
+        assert_eq!(
+            script(&lock).with_redaction(truncate).to_string(),
+            "Script { code_hash: 0x9bd7e06f..cce8, hash_type: Type, args: 0x11111111..1111 }"
+        );
+        assert_eq!(
+            bytes(&[1, 2, 3]).with_redaction(truncate).to_string(),
+            "0x010203"
+        );
+        assert_eq!(amount(100u64).with_redaction(truncate).to_string(), "100");
+
+        assert_eq!(
+            amount(100u64).with_redaction(Redaction::Strict).to_string(),
+            "<redacted>"
+        );
+        assert_eq!(
+            bytes(&[0x22; 20])
+                .with_redaction(Redaction::Off)
+                .to_string(),
+            format!("0x{}", "22".repeat(20))
+        );
+        assert_eq!(
+            script(&lock).with_redaction(Redaction::Off).to_string(),
+            format!(
+                "Script {{ code_hash: 0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8, hash_type: Type, args: 0x{} }}",
+                "11".repeat(20)
+            )
+        );
+    }
+}
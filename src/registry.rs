@@ -0,0 +1,298 @@
+//! Registry of third-party scripts.
+//!
+//! Crates shipping a script register a [`ScriptPlugin`] under its `ScriptId`:
+//! the transaction builders it offers, how to unlock its cells and how to
+//! read its args. Plugins registered with [`register_script`] are picked up by
+//! [`CkbSdk`](crate::sdk::CkbSdk), so a new script works with the facade
+//! without a release of this crate.
+//!
+//! ```ignore
+//! ckb_sdk::registry::register_script(
+//!     my_script_id,
+//!     ScriptPlugin::new("my-lock")
+//!         .with_unlocker(placeholder_witness, |signer| Box::new(MyUnlocker::new(signer)))
+//!         .with_args_parser(|args| parse_my_args(args)),
+//! )?;
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ckb_types::packed::{Script, WitnessArgs};
+use lazy_static::lazy_static;
+use parking_lot::{RwLock, RwLockReadGuard};
+use thiserror::Error;
+
+use crate::traits::Signer;
+use crate::tx_builder::TxBuilder;
+use crate::types::ScriptId;
+use crate::unlock::ScriptUnlocker;
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("script already registered: `{0}`")]
+    AlreadyRegistered(ScriptId),
+
+    #[error("script not registered: `{0}`")]
+    NotRegistered(ScriptId),
+
+    #[error("builder `{builder}` not registered for script `{script_id}`")]
+    BuilderNotFound {
+        script_id: ScriptId,
+        builder: String,
+    },
+
+    #[error("invalid builder params: `{0}`")]
+    InvalidParams(String),
+}
+
+/// Create a transaction builder from JSON params
+pub type BuilderFactory =
+    Arc<dyn Fn(&serde_json::Value) -> Result<Box<dyn TxBuilder>, RegistryError> + Send + Sync>;
+/// Create the unlocker of the script from a key signer
+pub type UnlockerFactory = Arc<dyn Fn(Box<dyn Signer>) -> Box<dyn ScriptUnlocker> + Send + Sync>;
+/// Decode the script args into a human readable JSON value
+pub type ArgsParser = Arc<dyn Fn(&[u8]) -> Result<serde_json::Value, String> + Send + Sync>;
+
+/// What a third-party crate knows about its script
+#[derive(Clone)]
+pub struct ScriptPlugin {
+    pub name: String,
+    builders: HashMap<String, BuilderFactory>,
+    unlocker: Option<(WitnessArgs, UnlockerFactory)>,
+    args_parser: Option<ArgsParser>,
+}
+
+impl ScriptPlugin {
+    pub fn new<S: Into<String>>(name: S) -> ScriptPlugin {
+        ScriptPlugin {
+            name: name.into(),
+            builders: HashMap::default(),
+            unlocker: None,
+            args_parser: None,
+        }
+    }
+
+    pub fn with_builder<S, F>(mut self, name: S, factory: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&serde_json::Value) -> Result<Box<dyn TxBuilder>, RegistryError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.builders.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// The unlocker of a lock script, `placeholder_witness` is used to
+    /// estimate the fee of its inputs.
+    pub fn with_unlocker<F>(mut self, placeholder_witness: WitnessArgs, factory: F) -> Self
+    where
+        F: Fn(Box<dyn Signer>) -> Box<dyn ScriptUnlocker> + Send + Sync + 'static,
+    {
+        self.unlocker = Some((placeholder_witness, Arc::new(factory)));
+        self
+    }
+
+    pub fn with_args_parser<F>(mut self, parser: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<serde_json::Value, String> + Send + Sync + 'static,
+    {
+        self.args_parser = Some(Arc::new(parser));
+        self
+    }
+
+    /// Names of the registered builders, sorted
+    pub fn builder_names(&self) -> Vec<&str> {
+        let mut names = self.builders.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn placeholder_witness(&self) -> Option<&WitnessArgs> {
+        self.unlocker.as_ref().map(|(witness, _)| witness)
+    }
+}
+
+/// The description of a script found in the registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptDescription {
+    pub name: String,
+    /// `None` when the plugin has no args parser
+    pub args: Option<Result<serde_json::Value, String>>,
+}
+
+#[derive(Clone, Default)]
+pub struct ScriptRegistry {
+    plugins: HashMap<ScriptId, ScriptPlugin>,
+}
+
+impl ScriptRegistry {
+    pub fn new() -> ScriptRegistry {
+        ScriptRegistry::default()
+    }
+
+    /// Register a plugin, a script can only be registered once
+    pub fn register(
+        &mut self,
+        script_id: ScriptId,
+        plugin: ScriptPlugin,
+    ) -> Result<(), RegistryError> {
+        if self.plugins.contains_key(&script_id) {
+            return Err(RegistryError::AlreadyRegistered(script_id));
+        }
+        self.plugins.insert(script_id, plugin);
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, script_id: &ScriptId) -> Option<ScriptPlugin> {
+        self.plugins.remove(script_id)
+    }
+
+    pub fn get(&self, script_id: &ScriptId) -> Option<&ScriptPlugin> {
+        self.plugins.get(script_id)
+    }
+
+    pub fn script_ids(&self) -> impl Iterator<Item = &ScriptId> {
+        self.plugins.keys()
+    }
+
+    /// Create the builder `builder` of the script from JSON params
+    pub fn build_builder(
+        &self,
+        script_id: &ScriptId,
+        builder: &str,
+        params: &serde_json::Value,
+    ) -> Result<Box<dyn TxBuilder>, RegistryError> {
+        let plugin = self
+            .plugins
+            .get(script_id)
+            .ok_or_else(|| RegistryError::NotRegistered(script_id.clone()))?;
+        let factory =
+            plugin
+                .builders
+                .get(builder)
+                .ok_or_else(|| RegistryError::BuilderNotFound {
+                    script_id: script_id.clone(),
+                    builder: builder.to_string(),
+                })?;
+        factory(params)
+    }
+
+    /// The unlockers and placeholder witnesses of all registered lock scripts,
+    /// each unlocker gets its own signer from `new_signer`.
+    pub fn unlockers(
+        &self,
+        new_signer: &dyn Fn() -> Box<dyn Signer>,
+    ) -> Vec<(ScriptId, Box<dyn ScriptUnlocker>, WitnessArgs)> {
+        self.plugins
+            .iter()
+            .filter_map(|(script_id, plugin)| {
+                plugin.unlocker.as_ref().map(|(witness, factory)| {
+                    (script_id.clone(), factory(new_signer()), witness.clone())
+                })
+            })
+            .collect()
+    }
+
+    /// Describe a script with the registered name and args parser
+    pub fn describe(&self, script: &Script) -> Option<ScriptDescription> {
+        let plugin = self.plugins.get(&ScriptId::from(script))?;
+        let args = plugin
+            .args_parser
+            .as_ref()
+            .map(|parser| parser(&script.args().raw_data()));
+        Some(ScriptDescription {
+            name: plugin.name.clone(),
+            args,
+        })
+    }
+}
+
+lazy_static! {
+    static ref GLOBAL_REGISTRY: RwLock<ScriptRegistry> = RwLock::new(ScriptRegistry::new());
+}
+
+/// Register a plugin into the process wide registry
+pub fn register_script(script_id: ScriptId, plugin: ScriptPlugin) -> Result<(), RegistryError> {
+    GLOBAL_REGISTRY.write().register(script_id, plugin)
+}
+
+/// Remove a plugin from the process wide registry
+pub fn unregister_script(script_id: &ScriptId) -> Option<ScriptPlugin> {
+    GLOBAL_REGISTRY.write().unregister(script_id)
+}
+
+/// The process wide registry, do not hold the guard while registering
+pub fn global_registry() -> RwLockReadGuard<'static, ScriptRegistry> {
+    GLOBAL_REGISTRY.read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::SecpCkbRawKeySigner;
+    use crate::tx_builder::transfer::CapacityTransferBuilder;
+    use crate::unlock::SecpSighashUnlocker;
+    use ckb_types::{bytes::Bytes, h256, prelude::*};
+
+    fn test_plugin() -> ScriptPlugin {
+        ScriptPlugin::new("test-lock")
+            .with_builder("transfer", |params| {
+                if !params.is_object() {
+                    return Err(RegistryError::InvalidParams("expected an object".into()));
+                }
+                Ok(Box::new(CapacityTransferBuilder::new(vec![])) as Box<_>)
+            })
+            .with_unlocker(WitnessArgs::default(), |signer| {
+                Box::new(SecpSighashUnlocker::from(signer))
+            })
+            .with_args_parser(|args| Ok(serde_json::json!({ "len": args.len() })))
+    }
+
+    #[test]
+    fn test_script_registry() {
+        let script_id = ScriptId::new_data1(h256!("0x5678"));
+        let mut registry = ScriptRegistry::new();
+        registry.register(script_id.clone(), test_plugin()).unwrap();
+        assert!(matches!(
+            registry.register(script_id.clone(), test_plugin()),
+            Err(RegistryError::AlreadyRegistered(_))
+        ));
+        assert_eq!(
+            registry.get(&script_id).unwrap().builder_names(),
+            vec!["transfer"]
+        );
+
+        assert!(registry
+            .build_builder(&script_id, "transfer", &serde_json::json!({}))
+            .is_ok());
+        assert!(matches!(
+            registry.build_builder(&script_id, "transfer", &serde_json::json!(1)),
+            Err(RegistryError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            registry.build_builder(&script_id, "claim", &serde_json::json!({})),
+            Err(RegistryError::BuilderNotFound { .. })
+        ));
+
+        let unlockers = registry
+            .unlockers(&|| Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![])) as Box<_>);
+        assert_eq!(unlockers.len(), 1);
+        assert_eq!(unlockers[0].0, script_id);
+
+        let script = Script::new_builder()
+            .code_hash(script_id.code_hash.pack())
+            .hash_type(script_id.hash_type.into())
+            .args(Bytes::from(vec![0u8; 20]).pack())
+            .build();
+        let description = registry.describe(&script).unwrap();
+        assert_eq!(description.name, "test-lock");
+        assert_eq!(description.args, Some(Ok(serde_json::json!({ "len": 20 }))));
+        assert!(registry.describe(&Script::default()).is_none());
+
+        assert!(registry.unregister(&script_id).is_some());
+        assert!(registry.get(&script_id).is_none());
+    }
+}
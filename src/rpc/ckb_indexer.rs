@@ -54,14 +54,15 @@ impl From<CellQueryOptions> for SearchKey {
             && opts.data_len_range.is_none()
             && opts.capacity_range.is_none()
             && opts.block_range.is_none()
+            && opts.data_prefix.is_none()
         {
             None
         } else {
             Some(SearchKeyFilter {
                 script: opts.secondary_script.map(|v| v.into()),
                 script_len_range: opts.secondary_script_len_range.map(convert_range),
-                output_data: None,
-                output_data_filter_mode: None,
+                output_data: opts.data_prefix.clone().map(JsonBytes::from_bytes),
+                output_data_filter_mode: opts.data_prefix.map(|_| SearchMode::Prefix),
                 output_data_len_range: opts.data_len_range.map(convert_range),
                 output_capacity_range: opts.capacity_range.map(convert_range),
                 block_range: opts.block_range.map(convert_range),
@@ -78,6 +79,97 @@ impl From<CellQueryOptions> for SearchKey {
     }
 }
 
+/// The optional search key filters an indexer knows, an older indexer
+/// rejects a search key with the newer ones as invalid params.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct IndexerFilterSupport {
+    /// `filter.output_capacity_range`, since CKB v0.106.0
+    pub capacity_range: bool,
+    /// `filter.output_data` and `filter.output_data_filter_mode`, since CKB
+    /// v0.111.0
+    pub data_prefix: bool,
+}
+
+impl IndexerFilterSupport {
+    pub const ALL: IndexerFilterSupport = IndexerFilterSupport {
+        capacity_range: true,
+        data_prefix: true,
+    };
+    pub const NONE: IndexerFilterSupport = IndexerFilterSupport {
+        capacity_range: false,
+        data_prefix: false,
+    };
+
+    /// The filters of the indexer built in the node of `version`, e.g.
+    /// `0.110.2 (6d7d3a6 2023-09-20)`, all of them if the version can't be
+    /// parsed.
+    pub fn from_node_version(version: &str) -> IndexerFilterSupport {
+        let mut numbers = version
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| {
+                part.chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect::<String>()
+                    .parse::<u64>()
+                    .ok()
+            });
+        match (numbers.next().flatten(), numbers.next().flatten()) {
+            (Some(major), Some(minor)) => {
+                let version = (major, minor);
+                IndexerFilterSupport {
+                    capacity_range: version >= (0, 106),
+                    data_prefix: version >= (0, 111),
+                }
+            }
+            _ => IndexerFilterSupport::ALL,
+        }
+    }
+
+    /// Whether `search_key` only uses the supported filters
+    pub fn supports(&self, search_key: &SearchKey) -> bool {
+        match search_key.filter.as_ref() {
+            Some(filter) => {
+                (self.capacity_range || filter.output_capacity_range.is_none())
+                    && (self.data_prefix || filter.output_data.is_none())
+            }
+            None => true,
+        }
+    }
+
+    /// `search_key` without the unsupported filters, the returned cells must
+    /// be filtered by the client with [`CellQueryOptions::match_cell`]
+    /// instead. The cell data is always requested when the data filter is
+    /// removed.
+    pub fn degrade(&self, mut search_key: SearchKey) -> SearchKey {
+        if let Some(filter) = search_key.filter.as_mut() {
+            if !self.capacity_range {
+                filter.output_capacity_range = None;
+            }
+            if !self.data_prefix && filter.output_data.is_some() {
+                filter.output_data = None;
+                filter.output_data_filter_mode = None;
+                search_key.with_data = Some(true);
+            }
+        }
+        if let Some(filter) = search_key.filter.as_ref() {
+            if filter.script.is_none()
+                && filter.script_len_range.is_none()
+                && filter.output_data.is_none()
+                && filter.output_data_len_range.is_none()
+                && filter.output_capacity_range.is_none()
+                && filter.block_range.is_none()
+            {
+                search_key.filter = None;
+            }
+        }
+        search_key
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum ScriptType {
@@ -189,9 +281,69 @@ pub struct Pagination<T> {
     pub last_cursor: JsonBytes,
 }
 
+#[cfg(feature = "indexer")]
 crate::jsonrpc!(pub struct IndexerRpcClient {
     pub fn get_indexer_tip(&self) -> Option<Tip>;
     pub fn get_cells(&self, search_key: SearchKey, order: Order, limit: Uint32, after: Option<JsonBytes>) -> Pagination<Cell>;
     pub fn get_transactions(&self, search_key: SearchKey, order: Order, limit: Uint32, after: Option<JsonBytes>) -> Pagination<Tx>;
     pub fn get_cells_capacity(&self, search_key: SearchKey) -> Option<CellsCapacity>;
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, packed};
+
+    #[test]
+    fn test_indexer_filter_support() {
+        assert_eq!(
+            IndexerFilterSupport::from_node_version("0.119.0 (e5d7f7b 2024-10-22)"),
+            IndexerFilterSupport::ALL
+        );
+        assert_eq!(
+            IndexerFilterSupport::from_node_version("0.110.2 (6d7d3a6 2023-09-20)"),
+            IndexerFilterSupport {
+                capacity_range: true,
+                data_prefix: false,
+            }
+        );
+        assert_eq!(
+            IndexerFilterSupport::from_node_version("v0.105.1-rc1"),
+            IndexerFilterSupport::NONE
+        );
+        assert_eq!(
+            IndexerFilterSupport::from_node_version("unknown"),
+            IndexerFilterSupport::ALL
+        );
+
+        let mut query = CellQueryOptions::new_lock(packed::Script::default());
+        query.data_prefix = Some(Bytes::from(vec![1, 2]));
+        query.with_data = Some(false);
+        let search_key = SearchKey::from(query.clone());
+        let filter = search_key.filter.as_ref().unwrap();
+        assert_eq!(
+            filter.output_data.clone().map(JsonBytes::into_bytes),
+            Some(Bytes::from(vec![1, 2]))
+        );
+        assert!(!IndexerFilterSupport::NONE.supports(&search_key));
+        let degraded = IndexerFilterSupport::NONE.degrade(search_key.clone());
+        assert!(IndexerFilterSupport::NONE.supports(&degraded));
+        assert!(degraded.filter.is_none());
+        assert_eq!(degraded.with_data, Some(true));
+        let kept = IndexerFilterSupport::ALL.degrade(search_key);
+        assert!(kept.filter.unwrap().output_data.is_some());
+        assert_eq!(kept.with_data, Some(false));
+
+        // the removed filter is applied by the client
+        let cell = LiveCell {
+            output: packed::CellOutput::default(),
+            output_data: Bytes::from(vec![1, 2, 3]),
+            out_point: packed::OutPoint::default(),
+            block_number: 0,
+            tx_index: 0,
+        };
+        assert!(query.match_cell(&cell, 0));
+        query.data_prefix = Some(Bytes::from(vec![2]));
+        assert!(!query.match_cell(&cell, 0));
+    }
+}
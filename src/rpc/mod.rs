@@ -1,24 +1,50 @@
+//! The jsonrpc clients of the CKB node, the indexer and the light client.
+//!
+//! The clients need the `rpc` feature, and the `indexer` feature for
+//! [`IndexerRpcClient`]. The indexer query types of [`ckb_indexer`] are
+//! always available, they are used by the cell collectors.
+
+#[cfg(feature = "rpc")]
 mod ckb;
 pub mod ckb_indexer;
+#[cfg(feature = "rpc")]
 pub mod ckb_light_client;
 
 use anyhow::anyhow;
+#[cfg(feature = "rpc")]
 pub use ckb::CkbRpcClient;
+#[cfg(feature = "indexer")]
 pub use ckb_indexer::IndexerRpcClient;
 use ckb_jsonrpc_types::{JsonBytes, ResponseFormat};
+#[cfg(feature = "rpc")]
 pub use ckb_light_client::LightClientRpcClient;
 
 use thiserror::Error;
 
+#[cfg(feature = "rpc")]
+use crate::deadline::Deadline;
+use crate::deadline::DeadlineExceeded;
+#[cfg(feature = "rpc")]
+use std::io::Read;
+
+/// The default response size limit of the clients, a full block with
+/// thousands of transactions is a few tens of MiB in json.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 128 * 1024 * 1024;
+
 #[derive(Error, Debug)]
 pub enum RpcError {
     #[error("parse json error: `{0}`")]
     Json(#[from] serde_json::Error),
+    #[cfg(feature = "rpc")]
     #[error("http error: `{0}`")]
     Http(#[from] reqwest::Error),
     #[error("jsonrpc error: `{0}`")]
     Rpc(#[from] jsonrpc_core::Error),
     #[error(transparent)]
+    DeadlineExceeded(#[from] DeadlineExceeded),
+    #[error("response larger than `{0}` bytes")]
+    ResponseTooLarge(usize),
+    #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
@@ -37,18 +63,46 @@ macro_rules! jsonrpc {
             pub client: reqwest::blocking::Client,
             pub url: reqwest::Url,
             pub id: std::sync::atomic::AtomicU64,
+            /// The calls fail with `RpcError::DeadlineExceeded` after it
+            pub deadline: Option<$crate::deadline::Deadline>,
+            /// The calls fail with `RpcError::ResponseTooLarge` when the
+            /// response body is larger, `None` for no limit
+            pub max_response_size: Option<usize>,
         }
 
         impl Clone for $struct_name {
             fn clone(&self) -> Self {
-                Self::new(&self.url.to_string())
+                let mut client = Self::new(&self.url.to_string());
+                client.deadline = self.deadline;
+                client.max_response_size = self.max_response_size;
+                client
             }
         }
 
         impl $struct_name {
             pub fn new(uri: &str) -> Self {
                 let url = reqwest::Url::parse(uri).expect("ckb uri, e.g. \"http://127.0.0.1:8114\"");
-                $struct_name { url, id: 0.into(), client: reqwest::blocking::Client::new(), }
+                $struct_name { url, id: 0.into(), client: reqwest::blocking::Client::new(), deadline: None, max_response_size: Some($crate::rpc::DEFAULT_MAX_RESPONSE_SIZE), }
+            }
+
+            /// Bound all the calls of the client by `deadline`
+            pub fn with_deadline(mut self, deadline: $crate::deadline::Deadline) -> Self {
+                self.deadline = Some(deadline);
+                self
+            }
+
+            pub fn set_deadline(&mut self, deadline: Option<$crate::deadline::Deadline>) {
+                self.deadline = deadline;
+            }
+
+            /// Fail the calls whose response body is larger than `max_response_size` bytes
+            pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+                self.max_response_size = Some(max_response_size);
+                self
+            }
+
+            pub fn set_max_response_size(&mut self, max_response_size: Option<usize>) {
+                self.max_response_size = max_response_size;
             }
 
             pub fn post<PARAM, RET>(&self, method:&str, params: PARAM)->Result<RET, $crate::rpc::RpcError>
@@ -65,8 +119,11 @@ macro_rules! jsonrpc {
                 req_json.insert("method".to_owned(), serde_json::json!(method));
                 req_json.insert("params".to_owned(), params);
 
-                let resp = self.client.post(self.url.clone()).json(&req_json).send()?;
-                let output = resp.json::<jsonrpc_core::response::Output>()?;
+                let output = $crate::rpc::send_request(
+                    self.client.post(self.url.clone()).json(&req_json),
+                    self.deadline.as_ref(),
+                    self.max_response_size,
+                )?;
                 match output {
                     jsonrpc_core::response::Output::Success(success) => {
                         serde_json::from_value(success.result).map_err(Into::into)
@@ -91,8 +148,11 @@ macro_rules! jsonrpc {
                     req_json.insert("method".to_owned(), serde_json::json!(method));
                     req_json.insert("params".to_owned(), params);
 
-                    let resp = $selff.client.post($selff.url.clone()).json(&req_json).send()?;
-                    let output = resp.json::<jsonrpc_core::response::Output>()?;
+                    let output = $crate::rpc::send_request(
+                        $selff.client.post($selff.url.clone()).json(&req_json),
+                        $selff.deadline.as_ref(),
+                        $selff.max_response_size,
+                    )?;
                     match output {
                         jsonrpc_core::response::Output::Success(success) => {
                             serde_json::from_value(success.result).map_err(Into::into)
@@ -107,6 +167,53 @@ macro_rules! jsonrpc {
     )
 }
 
+/// Send a jsonrpc request, the request times out at `deadline` and fails when
+/// the response body is larger than `max_response_size` bytes.
+#[cfg(feature = "rpc")]
+#[doc(hidden)]
+pub fn send_request(
+    request: reqwest::blocking::RequestBuilder,
+    deadline: Option<&Deadline>,
+    max_response_size: Option<usize>,
+) -> Result<jsonrpc_core::response::Output, RpcError> {
+    let request = match deadline {
+        Some(deadline) => request.timeout(deadline.check()?),
+        None => request,
+    };
+    let map_err = |err: reqwest::Error| match deadline {
+        Some(deadline) if err.is_timeout() || deadline.is_exceeded() => {
+            RpcError::DeadlineExceeded(DeadlineExceeded)
+        }
+        _ => RpcError::Http(err),
+    };
+    let response = request.send().map_err(map_err)?;
+    let max_response_size = match max_response_size {
+        Some(max_response_size) => max_response_size,
+        None => return response.json().map_err(map_err),
+    };
+    if response
+        .content_length()
+        .map_or(false, |len| len > max_response_size as u64)
+    {
+        return Err(RpcError::ResponseTooLarge(max_response_size));
+    }
+    // the content length may be missing or wrong, read one more byte to tell
+    let mut body = Vec::new();
+    response
+        .take(max_response_size as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| match deadline {
+            Some(deadline) if deadline.is_exceeded() => {
+                RpcError::DeadlineExceeded(DeadlineExceeded)
+            }
+            _ => RpcError::Other(err.into()),
+        })?;
+    if body.len() > max_response_size {
+        return Err(RpcError::ResponseTooLarge(max_response_size));
+    }
+    serde_json::from_slice(&body).map_err(Into::into)
+}
+
 #[macro_export]
 macro_rules! serialize_parameters {
     () => ( serde_json::Value::Null );
@@ -0,0 +1,283 @@
+//! Locate and cache the code of third-party scripts.
+//!
+//! Only the code hash of a script is known from the cells using it. The code
+//! cell is found by looking at the transactions which ran the script: one of
+//! their cell deps holds the code. The code and the cell dep are then cached
+//! on disk, so they can be registered into a [`DefaultCellDepResolver`]
+//! without asking the node again.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{DepType, ScriptHashType},
+    packed::{CellDep, OutPointVec, Script},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::rpc::ckb_indexer::{CellType, Order, ScriptType, SearchKey, Tx};
+use crate::traits::{
+    DefaultCellDepResolver, TransactionDependencyError, TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+use crate::{CkbRpcClient, RpcError};
+
+#[derive(Error, Debug)]
+pub enum ScriptCacheError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("transaction dependency provider error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("script code not found: `{0}`")]
+    NotFound(ScriptId),
+
+    #[error("cached code of `{0}` does not match the code hash")]
+    CodeMismatch(ScriptId),
+}
+
+/// The code of a script and the cell dep to reference it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ScriptCode {
+    pub script_id: ScriptId,
+    /// The cell dep found in the transaction which ran the script, it's a dep
+    /// group when the transaction referenced the code through a dep group.
+    pub cell_dep: CellDep,
+    pub data: Bytes,
+}
+
+impl ScriptCode {
+    /// Check the code matches the code hash, only possible for data hash types
+    pub fn verify(&self) -> bool {
+        match self.script_id.hash_type {
+            ScriptHashType::Type => true,
+            _ => blake2b_256(&self.data) == self.script_id.code_hash.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCellDep {
+    cell_dep: json_types::CellDep,
+}
+
+/// Locate the code of `script` from the latest `max_txs` transactions which
+/// ran it, `script_type` tells whether it's used as a lock or a type script.
+pub fn locate_script_code(
+    script: &Script,
+    script_type: ScriptType,
+    ckb_client: &CkbRpcClient,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    max_txs: u32,
+) -> Result<Option<ScriptCode>, ScriptCacheError> {
+    let script_id = ScriptId::from(script);
+    let is_lock = matches!(script_type, ScriptType::Lock);
+    let search_key = SearchKey {
+        script: script.clone().into(),
+        script_type,
+        script_search_mode: None,
+        filter: None,
+        with_data: None,
+        group_by_transaction: Some(true),
+    };
+    let txs = ckb_client.get_transactions(search_key, Order::Desc, max_txs.into(), None)?;
+    for indexed_tx in txs.objects {
+        // a lock script only runs when its cell is spent
+        let ran_script = !is_lock
+            || match &indexed_tx {
+                Tx::Ungrouped(tx) => matches!(tx.io_type, CellType::Input),
+                Tx::Grouped(tx) => tx
+                    .cells
+                    .iter()
+                    .any(|(io_type, _)| matches!(io_type, CellType::Input)),
+            };
+        if !ran_script {
+            continue;
+        }
+        let tx = tx_dep_provider.get_transaction(&indexed_tx.tx_hash().pack())?;
+        for cell_dep in tx.cell_deps() {
+            let out_points = if cell_dep.dep_type() == DepType::DepGroup.into() {
+                let data = tx_dep_provider.get_cell_data(&cell_dep.out_point())?;
+                match OutPointVec::from_slice(&data) {
+                    Ok(out_points) => out_points.into_iter().collect(),
+                    Err(_) => continue,
+                }
+            } else {
+                vec![cell_dep.out_point()]
+            };
+            for out_point in out_points {
+                // the code cell may be consumed since, e.g. an upgraded type id script
+                let (output, data) = match tx_dep_provider.get_cells_with_data(&[out_point]) {
+                    Ok(mut cells) => cells.remove(0),
+                    Err(_) => continue,
+                };
+                let matched = match script_id.hash_type {
+                    ScriptHashType::Type => output
+                        .type_()
+                        .to_opt()
+                        .map(|type_script| {
+                            type_script.calc_script_hash().as_slice()
+                                == script_id.code_hash.as_bytes()
+                        })
+                        .unwrap_or(false),
+                    _ => blake2b_256(&data) == script_id.code_hash.0,
+                };
+                if matched {
+                    return Ok(Some(ScriptCode {
+                        script_id,
+                        cell_dep,
+                        data,
+                    }));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A directory caching script codes, a script is stored as
+/// `{code_hash}-{hash_type}.bin` and its cell dep as `{code_hash}-{hash_type}.json`.
+pub struct ScriptCodeCache {
+    dir: PathBuf,
+}
+
+impl ScriptCodeCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<ScriptCodeCache, ScriptCacheError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(ScriptCodeCache {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, script_id: &ScriptId, extension: &str) -> PathBuf {
+        let hash_type = match script_id.hash_type {
+            ScriptHashType::Data => "data",
+            ScriptHashType::Type => "type",
+            ScriptHashType::Data1 => "data1",
+            ScriptHashType::Data2 => "data2",
+        };
+        self.dir.join(format!(
+            "{:x}-{}.{}",
+            script_id.code_hash, hash_type, extension
+        ))
+    }
+
+    pub fn load(&self, script_id: &ScriptId) -> Result<Option<ScriptCode>, ScriptCacheError> {
+        let data = match fs::read(self.path(script_id, "bin")) {
+            Ok(data) => Bytes::from(data),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let cached: CachedCellDep =
+            serde_json::from_slice(&fs::read(self.path(script_id, "json"))?)?;
+        let code = ScriptCode {
+            script_id: script_id.clone(),
+            cell_dep: cached.cell_dep.into(),
+            data,
+        };
+        if !code.verify() {
+            return Err(ScriptCacheError::CodeMismatch(script_id.clone()));
+        }
+        Ok(Some(code))
+    }
+
+    pub fn save(&self, code: &ScriptCode) -> Result<(), ScriptCacheError> {
+        let cached = CachedCellDep {
+            cell_dep: code.cell_dep.clone().into(),
+        };
+        fs::write(
+            self.path(&code.script_id, "json"),
+            serde_json::to_vec_pretty(&cached)?,
+        )?;
+        fs::write(self.path(&code.script_id, "bin"), &code.data)?;
+        Ok(())
+    }
+
+    /// Remove the cached code, e.g. after a type id script is upgraded
+    pub fn remove(&self, script_id: &ScriptId) -> Result<(), ScriptCacheError> {
+        for extension in ["bin", "json"] {
+            if let Err(err) = fs::remove_file(self.path(script_id, extension)) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the code from the cache, or locate it on chain and cache it
+    pub fn fetch(
+        &self,
+        script: &Script,
+        script_type: ScriptType,
+        ckb_client: &CkbRpcClient,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        max_txs: u32,
+    ) -> Result<ScriptCode, ScriptCacheError> {
+        let script_id = ScriptId::from(script);
+        if let Some(code) = self.load(&script_id)? {
+            return Ok(code);
+        }
+        let code = locate_script_code(script, script_type, ckb_client, tx_dep_provider, max_txs)?
+            .ok_or(ScriptCacheError::NotFound(script_id))?;
+        self.save(&code)?;
+        Ok(code)
+    }
+}
+
+/// Register the cell dep of the code, so the builders can resolve the script
+pub fn register_script_code(
+    cell_dep_resolver: &mut DefaultCellDepResolver,
+    code: &ScriptCode,
+    name: String,
+) -> Option<(CellDep, String)> {
+    cell_dep_resolver.insert(code.script_id.clone(), code.cell_dep.clone(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{packed::OutPoint, H256};
+
+    #[test]
+    fn test_script_code_cache() {
+        let dir = std::env::temp_dir().join(format!("ckb-sdk-script-cache-{}", std::process::id()));
+        let cache = ScriptCodeCache::new(&dir).unwrap();
+        let data = Bytes::from(vec![1u8, 2, 3]);
+        let code = ScriptCode {
+            script_id: ScriptId::new(H256(blake2b_256(&data)), ScriptHashType::Data1),
+            cell_dep: CellDep::new_builder()
+                .out_point(OutPoint::new(Default::default(), 1))
+                .dep_type(DepType::Code.into())
+                .build(),
+            data,
+        };
+        assert!(cache.load(&code.script_id).unwrap().is_none());
+        cache.save(&code).unwrap();
+        assert_eq!(cache.load(&code.script_id).unwrap(), Some(code.clone()));
+
+        let mut tampered = code.clone();
+        tampered.data = Bytes::from(vec![4u8]);
+        cache.save(&tampered).unwrap();
+        assert!(matches!(
+            cache.load(&code.script_id),
+            Err(ScriptCacheError::CodeMismatch(_))
+        ));
+        cache.remove(&code.script_id).unwrap();
+        assert!(cache.load(&code.script_id).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+//! A facade bundling the RPC clients, providers, resolvers and unlockers
+//! needed by the transaction builders.
+//!
+//! ```no_run
+//! # use ckb_sdk::{sdk::CkbSdk, NetworkInfo};
+//! # use ckb_types::packed::Script;
+//! # fn run(key: secp256k1::SecretKey, sender: Script, receiver: Script) -> Result<(), ckb_sdk::sdk::SdkError> {
+//! let mut sdk = CkbSdk::new(NetworkInfo::testnet())?;
+//! sdk.add_sighash_keys(vec![key]);
+//! let tx = sdk.transfer(&sender, receiver, 100_0000_0000)?;
+//! let tx_hash = sdk.send_transaction(&tx)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! All the underlying components are reachable through accessors, so a
+//! transaction built by any other `TxBuilder` can still reuse them. The cell
+//! deps of the scripts not deployed in the genesis block, e.g. sUDT or cheque,
+//! are registered with [`CkbSdk::cell_dep_resolver_mut`].
+
+use std::collections::{HashMap, HashSet};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockView, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use crate::constants::SIGHASH_TYPE_HASH;
+use crate::registry::{global_registry, RegistryError, ScriptDescription, ScriptRegistry};
+use crate::rpc::ckb_indexer::SearchKey;
+use crate::traits::default_impls::ParseGenesisInfoError;
+use crate::traits::{
+    CellCollector, CellQueryOptions, DefaultCellCollector, DefaultCellDepResolver,
+    DefaultHeaderDepResolver, DefaultTransactionDependencyProvider, HeaderDepResolver,
+    SecpCkbRawKeySigner, Signer, TransactionDependencyError, TransactionDependencyProvider,
+};
+use crate::tx_builder::{
+    cheque::ChequeClaimBuilder,
+    dao::{DaoDepositBuilder, DaoDepositReceiver},
+    transfer::CapacityTransferBuilder,
+    udt::{UdtTargetReceiver, UdtTransferBuilder},
+    CapacityBalancer, TransferAction, TxBuilder, TxBuilderError,
+};
+use crate::types::{NetworkInfo, ScriptId};
+use crate::unlock::{ChequeAction, ChequeUnlocker, ScriptUnlocker, SecpSighashUnlocker};
+use crate::{CkbRpcClient, IndexerRpcClient, RpcError};
+
+/// The default fee rate of `CkbSdk`, in shannons per KB
+pub const DEFAULT_FEE_RATE: u64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum SdkError {
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+
+    #[error("genesis block not found")]
+    GenesisNotFound,
+
+    #[error("parse genesis info error: `{0}`")]
+    ParseGenesisInfo(#[from] ParseGenesisInfoError),
+
+    #[error("no placeholder witness registered for lock script: `{0}`")]
+    NoPlaceholderWitness(ScriptId),
+
+    #[error("transaction builder error: `{0}`")]
+    TxBuilder(#[from] TxBuilderError),
+
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("script registry error: `{0}`")]
+    Registry(#[from] RegistryError),
+
+    #[error("{0} script groups are still locked")]
+    StillLocked(usize),
+}
+
+/// The components shared by the high level transaction methods
+pub struct CkbSdk {
+    network_info: NetworkInfo,
+    ckb_client: CkbRpcClient,
+    indexer_client: IndexerRpcClient,
+    cell_collector: DefaultCellCollector,
+    cell_dep_resolver: DefaultCellDepResolver,
+    header_dep_resolver: DefaultHeaderDepResolver,
+    tx_dep_provider: DefaultTransactionDependencyProvider,
+    unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    placeholder_witnesses: HashMap<ScriptId, WitnessArgs>,
+    /// Scripts whose unlocker was added by `add_unlocker`
+    custom_unlockers: HashSet<ScriptId>,
+    sighash_keys: Vec<secp256k1::SecretKey>,
+    fee_rate: u64,
+}
+
+impl CkbSdk {
+    /// Connect to the node of `network_info`, the cell deps of the system
+    /// scripts are loaded from its genesis block.
+    pub fn new(network_info: NetworkInfo) -> Result<CkbSdk, SdkError> {
+        let genesis_block = CkbRpcClient::new(network_info.url.as_str())
+            .get_block_by_number(0.into())?
+            .ok_or(SdkError::GenesisNotFound)?;
+        CkbSdk::from_genesis(network_info, &BlockView::from(genesis_block))
+    }
+
+    /// Same as [`CkbSdk::new`] with an already known genesis block, the node
+    /// is not requested.
+    pub fn from_genesis(
+        network_info: NetworkInfo,
+        genesis_block: &BlockView,
+    ) -> Result<CkbSdk, SdkError> {
+        let url = network_info.url.as_str();
+        let cell_dep_resolver = DefaultCellDepResolver::from_genesis(genesis_block)?;
+        let mut placeholder_witnesses = HashMap::default();
+        placeholder_witnesses.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            WitnessArgs::new_builder()
+                .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+                .build(),
+        );
+        let mut sdk = CkbSdk {
+            indexer_client: IndexerRpcClient::new(url),
+            cell_collector: DefaultCellCollector::new(url),
+            cell_dep_resolver,
+            header_dep_resolver: DefaultHeaderDepResolver::new(url),
+            tx_dep_provider: DefaultTransactionDependencyProvider::new(url, 10),
+            unlockers: HashMap::default(),
+            placeholder_witnesses,
+            custom_unlockers: HashSet::default(),
+            sighash_keys: Vec::new(),
+            fee_rate: DEFAULT_FEE_RATE,
+            ckb_client: CkbRpcClient::new(url),
+            network_info,
+        };
+        sdk.install_plugins(&global_registry());
+        Ok(sdk)
+    }
+
+    pub fn with_fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Add secret keys able to unlock sighash cells, a sighash unlocker added
+    /// by `add_unlocker` is kept.
+    pub fn add_sighash_keys(&mut self, keys: Vec<secp256k1::SecretKey>) {
+        self.sighash_keys.extend(keys);
+        let script_id = ScriptId::new_type(SIGHASH_TYPE_HASH.clone());
+        if !self.custom_unlockers.contains(&script_id) {
+            let signer = SecpCkbRawKeySigner::new_with_secret_keys(self.sighash_keys.clone());
+            self.unlockers.insert(
+                script_id,
+                Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+            );
+        }
+        self.install_plugins(&global_registry());
+    }
+
+    /// Register the unlocker of a lock script and the placeholder witness
+    /// used to estimate the fee of its inputs.
+    pub fn add_unlocker(
+        &mut self,
+        script_id: ScriptId,
+        unlocker: Box<dyn ScriptUnlocker>,
+        placeholder_witness: WitnessArgs,
+    ) {
+        self.unlockers.insert(script_id.clone(), unlocker);
+        self.placeholder_witnesses
+            .insert(script_id.clone(), placeholder_witness);
+        self.custom_unlockers.insert(script_id);
+    }
+
+    /// Register the unlocker of the cheque lock `script_id`, it signs with the
+    /// sighash keys. An unlocker added by `add_unlocker` is kept.
+    pub fn add_cheque_unlocker(&mut self, script_id: ScriptId, action: ChequeAction) {
+        if self.custom_unlockers.contains(&script_id) {
+            return;
+        }
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(self.sighash_keys.clone());
+        self.unlockers.insert(
+            script_id,
+            Box::new(ChequeUnlocker::from((
+                Box::new(signer) as Box<dyn Signer>,
+                action,
+            ))),
+        );
+    }
+
+    /// Install the unlockers of the plugins in `registry`, they sign with the
+    /// sighash keys. Unlockers added by `add_unlocker` are kept. Plugins of the
+    /// global registry are installed when the sighash keys change.
+    pub fn install_plugins(&mut self, registry: &ScriptRegistry) {
+        let keys = self.sighash_keys.clone();
+        let new_signer = move || {
+            Box::new(SecpCkbRawKeySigner::new_with_secret_keys(keys.clone())) as Box<dyn Signer>
+        };
+        for (script_id, unlocker, placeholder_witness) in registry.unlockers(&new_signer) {
+            if self.custom_unlockers.contains(&script_id) {
+                continue;
+            }
+            self.unlockers.insert(script_id.clone(), unlocker);
+            self.placeholder_witnesses
+                .insert(script_id, placeholder_witness);
+        }
+    }
+
+    /// Describe a script with the plugins of the global registry
+    pub fn describe_script(&self, script: &Script) -> Option<ScriptDescription> {
+        global_registry().describe(script)
+    }
+
+    pub fn network_info(&self) -> &NetworkInfo {
+        &self.network_info
+    }
+    pub fn fee_rate(&self) -> u64 {
+        self.fee_rate
+    }
+    pub fn ckb_client(&self) -> &CkbRpcClient {
+        &self.ckb_client
+    }
+    pub fn indexer_client(&self) -> &IndexerRpcClient {
+        &self.indexer_client
+    }
+    pub fn cell_collector_mut(&mut self) -> &mut DefaultCellCollector {
+        &mut self.cell_collector
+    }
+    pub fn cell_dep_resolver(&self) -> &DefaultCellDepResolver {
+        &self.cell_dep_resolver
+    }
+    pub fn cell_dep_resolver_mut(&mut self) -> &mut DefaultCellDepResolver {
+        &mut self.cell_dep_resolver
+    }
+    pub fn header_dep_resolver(&self) -> &DefaultHeaderDepResolver {
+        &self.header_dep_resolver
+    }
+    pub fn tx_dep_provider(&self) -> &DefaultTransactionDependencyProvider {
+        &self.tx_dep_provider
+    }
+    pub fn unlockers(&self) -> &HashMap<ScriptId, Box<dyn ScriptUnlocker>> {
+        &self.unlockers
+    }
+
+    /// A balancer paying the fee and receiving the change with `fee_payer`
+    pub fn balancer(&self, fee_payer: &Script) -> Result<CapacityBalancer, SdkError> {
+        let script_id = ScriptId::from(fee_payer);
+        let placeholder_witness = self
+            .placeholder_witnesses
+            .get(&script_id)
+            .cloned()
+            .ok_or(SdkError::NoPlaceholderWitness(script_id))?;
+        Ok(CapacityBalancer::new_simple(
+            fee_payer.clone(),
+            placeholder_witness,
+            self.fee_rate,
+        ))
+    }
+
+    /// Balance and unlock the transaction built by `builder`, the fee is paid
+    /// by `fee_payer`. All script groups must be unlocked.
+    pub fn build(
+        &mut self,
+        builder: &dyn TxBuilder,
+        fee_payer: &Script,
+    ) -> Result<TransactionView, SdkError> {
+        let balancer = self.balancer(fee_payer)?;
+        build_unlocked(
+            builder,
+            &balancer,
+            &mut self.cell_collector,
+            &self.cell_dep_resolver,
+            &self.header_dep_resolver,
+            &self.tx_dep_provider,
+            &self.unlockers,
+        )
+    }
+
+    /// Same as [`CkbSdk::build`] with other providers than the ones of the
+    /// node, e.g. to build offline. The cell deps, unlockers and placeholder
+    /// witnesses of the SDK are used.
+    pub fn build_with(
+        &self,
+        builder: &dyn TxBuilder,
+        fee_payer: &Script,
+        cell_collector: &mut dyn CellCollector,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, SdkError> {
+        let balancer = self.balancer(fee_payer)?;
+        build_unlocked(
+            builder,
+            &balancer,
+            cell_collector,
+            &self.cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+            &self.unlockers,
+        )
+    }
+
+    /// Build with the builder `builder` registered by the plugin of
+    /// `script_id` in the global registry.
+    pub fn build_plugin(
+        &mut self,
+        script_id: &ScriptId,
+        builder: &str,
+        params: &serde_json::Value,
+        fee_payer: &Script,
+    ) -> Result<TransactionView, SdkError> {
+        let builder = global_registry().build_builder(script_id, builder, params)?;
+        self.build(builder.as_ref(), fee_payer)
+    }
+
+    /// Transfer `capacity` shannons from `sender` to `receiver`
+    pub fn transfer(
+        &mut self,
+        sender: &Script,
+        receiver: Script,
+        capacity: u64,
+    ) -> Result<TransactionView, SdkError> {
+        let output = CellOutput::new_builder()
+            .lock(receiver)
+            .capacity(capacity.pack())
+            .build();
+        let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+        self.build(&builder, sender)
+    }
+
+    /// Transfer `amount` of the UDT `type_script` from the single UDT cell of
+    /// `sender` to a new cell of `receiver`. The cell dep of the UDT type
+    /// script must be registered in [`CkbSdk::cell_dep_resolver_mut`].
+    pub fn transfer_udt(
+        &mut self,
+        sender: &Script,
+        type_script: Script,
+        receiver: Script,
+        amount: u128,
+    ) -> Result<TransactionView, SdkError> {
+        let builder = udt_transfer_builder(sender, type_script, receiver, amount);
+        self.build(&builder, sender)
+    }
+
+    /// Deposit `capacity` shannons of `owner` into Nervos DAO
+    pub fn deposit_dao(
+        &mut self,
+        owner: &Script,
+        capacity: u64,
+    ) -> Result<TransactionView, SdkError> {
+        let builder =
+            DaoDepositBuilder::new(vec![DaoDepositReceiver::new(owner.clone(), capacity)]);
+        self.build(&builder, owner)
+    }
+
+    /// Claim the cheque cells to the UDT cell `receiver_cell` of `receiver`,
+    /// the fee is paid by `receiver`. The cheque cells are unlocked with the
+    /// sighash keys, see [`CkbSdk::add_cheque_unlocker`]. The cell deps of the
+    /// cheque lock and of the UDT type script must be registered in
+    /// [`CkbSdk::cell_dep_resolver_mut`].
+    pub fn claim_cheque(
+        &mut self,
+        receiver: &Script,
+        cheque_cells: Vec<OutPoint>,
+        receiver_cell: OutPoint,
+        sender: Script,
+    ) -> Result<TransactionView, SdkError> {
+        if let Some(out_point) = cheque_cells.first() {
+            let cheque_lock = self.tx_dep_provider.get_cell(out_point)?.lock();
+            self.add_cheque_unlocker(ScriptId::from(&cheque_lock), ChequeAction::Claim);
+        }
+        let builder = cheque_claim_builder(cheque_cells, receiver_cell, sender);
+        self.build(&builder, receiver)
+    }
+
+    /// The total capacity of the live cells locked by `lock_script`
+    pub fn get_balance(&self, lock_script: &Script) -> Result<u64, SdkError> {
+        let search_key = SearchKey::from(CellQueryOptions::new_lock(lock_script.clone()));
+        Ok(self
+            .indexer_client
+            .get_cells_capacity(search_key)?
+            .map(|cells_capacity| cells_capacity.capacity.value())
+            .unwrap_or(0))
+    }
+
+    /// Send the transaction to the node, outputs are passed through without
+    /// the well-known script checks.
+    pub fn send_transaction(&self, tx: &TransactionView) -> Result<H256, SdkError> {
+        let json_tx = json_types::TransactionView::from(tx.clone());
+        Ok(self.ckb_client.send_transaction(
+            json_tx.inner,
+            Some(json_types::OutputsValidator::Passthrough),
+        )?)
+    }
+}
+
+fn build_unlocked(
+    builder: &dyn TxBuilder,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    cell_dep_resolver: &DefaultCellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<TransactionView, SdkError> {
+    let (tx, still_locked_groups) = builder.build_unlocked(
+        cell_collector,
+        cell_dep_resolver,
+        header_dep_resolver,
+        tx_dep_provider,
+        balancer,
+        unlockers,
+    )?;
+    if !still_locked_groups.is_empty() {
+        return Err(SdkError::StillLocked(still_locked_groups.len()));
+    }
+    Ok(tx)
+}
+
+/// The builder of [`CkbSdk::transfer_udt`]
+pub fn udt_transfer_builder(
+    sender: &Script,
+    type_script: Script,
+    receiver: Script,
+    amount: u128,
+) -> UdtTransferBuilder {
+    UdtTransferBuilder {
+        type_script,
+        sender: sender.clone(),
+        receivers: vec![UdtTargetReceiver::new(
+            TransferAction::Create,
+            receiver,
+            amount,
+        )],
+        change_lock: None,
+    }
+}
+
+/// The builder of [`CkbSdk::claim_cheque`]
+pub fn cheque_claim_builder(
+    cheque_cells: Vec<OutPoint>,
+    receiver_cell: OutPoint,
+    sender: Script,
+) -> ChequeClaimBuilder {
+    let inputs = cheque_cells
+        .into_iter()
+        .map(|out_point| CellInput::new(out_point, 0))
+        .collect();
+    ChequeClaimBuilder::new(inputs, CellInput::new(receiver_cell, 0), sender)
+}
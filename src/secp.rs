@@ -0,0 +1,187 @@
+//! The secp256k1 backend of the signatures.
+//!
+//! By default the signatures are made and recovered by libsecp256k1 (the
+//! `secp256k1` C bindings). With the `k256-signing` feature they are made by
+//! the pure-Rust [k256](https://docs.rs/k256) implementation instead. Both
+//! backends use RFC6979 nonces and low-S normalization, so the signatures are
+//! byte identical.
+//!
+//! The feature only swaps the signing backend, it doesn't remove the C
+//! dependency: libsecp256k1 is still built, since the key types of the public
+//! API are the `secp256k1` ones, and [`SECP256K1`](crate::SECP256K1) and
+//! `ckb-crypto` keep using it. Cross-compiling still needs a C toolchain for
+//! the target.
+
+use thiserror::Error;
+
+/// The length of a recoverable signature: `r | s | recovery_id`
+pub const RECOVERABLE_SIGNATURE_LEN: usize = 65;
+/// The length of a non-recoverable signature: `r | s`
+pub const COMPACT_SIGNATURE_LEN: usize = 64;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum SecpError {
+    #[error("invalid secret key")]
+    InvalidSecretKey,
+
+    #[error("invalid signature: `{0}`")]
+    InvalidSignature(String),
+}
+
+/// The name of the backend in use
+pub fn backend_name() -> &'static str {
+    if cfg!(feature = "k256-signing") {
+        "k256"
+    } else {
+        "libsecp256k1"
+    }
+}
+
+/// Sign the 32 bytes `message` with `secret_key`
+#[cfg(not(feature = "k256-signing"))]
+pub fn sign_recoverable(
+    secret_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Result<[u8; RECOVERABLE_SIGNATURE_LEN], SecpError> {
+    let key =
+        secp256k1::SecretKey::from_slice(secret_key).map_err(|_| SecpError::InvalidSecretKey)?;
+    let message = secp256k1::Message::from_digest(*message);
+    let signature = crate::SECP256K1.sign_ecdsa_recoverable(&message, &key);
+    Ok(crate::util::serialize_signature(&signature))
+}
+
+/// Sign the 32 bytes `message` with `secret_key`
+#[cfg(feature = "k256-signing")]
+pub fn sign_recoverable(
+    secret_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Result<[u8; RECOVERABLE_SIGNATURE_LEN], SecpError> {
+    let key = k256::ecdsa::SigningKey::from_bytes(secret_key.into())
+        .map_err(|_| SecpError::InvalidSecretKey)?;
+    let (signature, recovery_id) = key
+        .sign_prehash_recoverable(message)
+        .map_err(|err| SecpError::InvalidSignature(err.to_string()))?;
+    let mut signature_bytes = [0u8; RECOVERABLE_SIGNATURE_LEN];
+    signature_bytes[0..64].copy_from_slice(&signature.to_bytes());
+    signature_bytes[64] = recovery_id.to_byte();
+    Ok(signature_bytes)
+}
+
+/// Sign the 32 bytes `message` with `secret_key`, without the recovery id
+#[cfg(not(feature = "k256-signing"))]
+pub fn sign_compact(
+    secret_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Result<[u8; COMPACT_SIGNATURE_LEN], SecpError> {
+    let key =
+        secp256k1::SecretKey::from_slice(secret_key).map_err(|_| SecpError::InvalidSecretKey)?;
+    let message = secp256k1::Message::from_digest(*message);
+    Ok(crate::SECP256K1
+        .sign_ecdsa(&message, &key)
+        .serialize_compact())
+}
+
+/// Sign the 32 bytes `message` with `secret_key`, without the recovery id
+#[cfg(feature = "k256-signing")]
+pub fn sign_compact(
+    secret_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Result<[u8; COMPACT_SIGNATURE_LEN], SecpError> {
+    use k256::ecdsa::signature::hazmat::PrehashSigner;
+
+    let key = k256::ecdsa::SigningKey::from_bytes(secret_key.into())
+        .map_err(|_| SecpError::InvalidSecretKey)?;
+    let signature: k256::ecdsa::Signature = key
+        .sign_prehash(message)
+        .map_err(|err| SecpError::InvalidSignature(err.to_string()))?;
+    let mut signature_bytes = [0u8; COMPACT_SIGNATURE_LEN];
+    signature_bytes.copy_from_slice(&signature.to_bytes());
+    Ok(signature_bytes)
+}
+
+/// Recover the compressed public key which made `signature` over `message`
+#[cfg(not(feature = "k256-signing"))]
+pub fn recover_pubkey(message: &[u8; 32], signature: &[u8]) -> Result<[u8; 33], SecpError> {
+    if signature.len() != RECOVERABLE_SIGNATURE_LEN {
+        return Err(SecpError::InvalidSignature(format!(
+            "expected length: {}, got: {}",
+            RECOVERABLE_SIGNATURE_LEN,
+            signature.len()
+        )));
+    }
+    let invalid = |err: secp256k1::Error| SecpError::InvalidSignature(err.to_string());
+    let recovery_id =
+        secp256k1::ecdsa::RecoveryId::from_i32(i32::from(signature[64])).map_err(invalid)?;
+    let recoverable =
+        secp256k1::ecdsa::RecoverableSignature::from_compact(&signature[0..64], recovery_id)
+            .map_err(invalid)?;
+    let message = secp256k1::Message::from_digest(*message);
+    let pubkey = crate::SECP256K1
+        .recover_ecdsa(&message, &recoverable)
+        .map_err(invalid)?;
+    Ok(pubkey.serialize())
+}
+
+/// Recover the compressed public key which made `signature` over `message`
+#[cfg(feature = "k256-signing")]
+pub fn recover_pubkey(message: &[u8; 32], signature: &[u8]) -> Result<[u8; 33], SecpError> {
+    if signature.len() != RECOVERABLE_SIGNATURE_LEN {
+        return Err(SecpError::InvalidSignature(format!(
+            "expected length: {}, got: {}",
+            RECOVERABLE_SIGNATURE_LEN,
+            signature.len()
+        )));
+    }
+    let invalid = |err: k256::ecdsa::Error| SecpError::InvalidSignature(err.to_string());
+    let recovery_id = k256::ecdsa::RecoveryId::from_byte(signature[64])
+        .ok_or_else(|| SecpError::InvalidSignature("invalid recovery id".to_string()))?;
+    let recoverable = k256::ecdsa::Signature::from_slice(&signature[0..64]).map_err(invalid)?;
+    let pubkey =
+        k256::ecdsa::VerifyingKey::recover_from_prehash(message, &recoverable, recovery_id)
+            .map_err(invalid)?;
+    let mut pubkey_bytes = [0u8; 33];
+    pubkey_bytes.copy_from_slice(pubkey.to_encoded_point(true).as_bytes());
+    Ok(pubkey_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::serialize_signature;
+    use crate::SECP256K1;
+
+    const SECRET_KEY: [u8; 32] = [
+        0x8d, 0x92, 0x95, 0x99, 0x35, 0x6b, 0x8c, 0x6a, 0xcc, 0x8b, 0x4c, 0x8d, 0x4a, 0x2c, 0x8c,
+        0x9b, 0x87, 0x5c, 0x68, 0x51, 0x6f, 0x0c, 0x3b, 0x2a, 0x47, 0xfc, 0xa5, 0x70, 0x4a, 0x67,
+        0x92, 0x11,
+    ];
+
+    #[test]
+    fn test_sign_and_recover() {
+        let key = secp256k1::SecretKey::from_slice(&SECRET_KEY).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        for seed in 0u8..16 {
+            let message = ckb_hash::blake2b_256([seed]);
+            let signature = sign_recoverable(&SECRET_KEY, &message).unwrap();
+            // the reference signature of libsecp256k1
+            let expected =
+                SECP256K1.sign_ecdsa_recoverable(&secp256k1::Message::from_digest(message), &key);
+            assert_eq!(signature, serialize_signature(&expected));
+            assert_eq!(
+                recover_pubkey(&message, &signature).unwrap(),
+                pubkey.serialize()
+            );
+        }
+        let message = ckb_hash::blake2b_256([0u8]);
+        let expected = SECP256K1.sign_ecdsa(&secp256k1::Message::from_digest(message), &key);
+        assert_eq!(
+            sign_compact(&SECRET_KEY, &message).unwrap(),
+            expected.serialize_compact()
+        );
+        assert_eq!(
+            sign_recoverable(&[0u8; 32], &[1u8; 32]),
+            Err(SecpError::InvalidSecretKey)
+        );
+        assert!(recover_pubkey(&[1u8; 32], &[0u8; 64]).is_err());
+    }
+}
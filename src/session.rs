@@ -0,0 +1,400 @@
+//! Persistent storage for in-progress signing sessions.
+//!
+//! A multisig or open transaction ceremony may take hours or days to collect
+//! all the signatures. A [`SigningSession`] records the transaction, the
+//! inputs reserved for it and the signatures collected so far, and a
+//! [`SessionStore`] persists it so the ceremony can resume after the process
+//! restarts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{OutPoint, Transaction},
+    prelude::*,
+    H256,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::traits::CellCollector;
+use crate::tx_stream::{read_json_bounded, TxStreamError, DEFAULT_MAX_READ_SIZE};
+use crate::unlock::MultisigConfig;
+
+#[derive(Error, Debug)]
+pub enum SessionStoreError {
+    #[error("invalid session id: `{0}`")]
+    InvalidId(String),
+
+    #[error("io error: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("read session error: `{0}`")]
+    Read(#[from] TxStreamError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    Multisig,
+    Otx,
+}
+
+/// The state of a signing ceremony
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SigningSession {
+    pub id: String,
+    pub kind: SessionKind,
+    /// The transaction to sign
+    pub tx: json_types::Transaction,
+    /// Only for multisig sessions
+    pub multisig_config: Option<MultisigConfig>,
+    /// The inputs assigned to this session, they must not be spent by other
+    /// transactions before the session finishes.
+    pub reserved_inputs: Vec<json_types::OutPoint>,
+    /// The signatures collected so far, keyed by the lock script hash of the
+    /// script group they belong to.
+    pub signatures: BTreeMap<H256, Vec<json_types::JsonBytes>>,
+    /// Unix timestamp in milliseconds of the last update
+    pub updated_at: u64,
+}
+
+impl SigningSession {
+    /// Create a session, all inputs of `tx` are reserved.
+    pub fn new(id: impl Into<String>, kind: SessionKind, tx: &TransactionView) -> SigningSession {
+        let reserved_inputs = tx
+            .input_pts_iter()
+            .map(json_types::OutPoint::from)
+            .collect();
+        SigningSession {
+            id: id.into(),
+            kind,
+            tx: tx.data().into(),
+            multisig_config: None,
+            reserved_inputs,
+            signatures: BTreeMap::new(),
+            updated_at: now_millis(),
+        }
+    }
+
+    pub fn with_multisig_config(mut self, config: MultisigConfig) -> Self {
+        self.multisig_config = Some(config);
+        self
+    }
+
+    pub fn tx_view(&self) -> TransactionView {
+        Transaction::from(self.tx.clone()).into_view()
+    }
+
+    /// Replace the transaction, e.g. after a signature is put into the witnesses
+    pub fn set_tx(&mut self, tx: &TransactionView) {
+        self.tx = tx.data().into();
+        self.touch();
+    }
+
+    pub fn reserved_out_points(&self) -> Vec<OutPoint> {
+        self.reserved_inputs
+            .iter()
+            .cloned()
+            .map(OutPoint::from)
+            .collect()
+    }
+
+    /// Record a signature of the script group, return false if the signature
+    /// is already collected.
+    pub fn add_signature(&mut self, lock_hash: H256, signature: Bytes) -> bool {
+        let signature = json_types::JsonBytes::from_bytes(signature);
+        let signatures = self.signatures.entry(lock_hash).or_default();
+        if signatures.contains(&signature) {
+            return false;
+        }
+        signatures.push(signature);
+        self.touch();
+        true
+    }
+
+    pub fn signatures_of(&self, lock_hash: &H256) -> Vec<Bytes> {
+        self.signatures
+            .get(lock_hash)
+            .map(|signatures| {
+                signatures
+                    .iter()
+                    .map(|signature| signature.clone().into_bytes())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn touch(&mut self) {
+        self.updated_at = now_millis();
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Persist signing sessions.
+///
+/// `save` must be atomic: after a crash the store contains either the old or
+/// the new version of the session, never a partially written one.
+pub trait SessionStore: Send + Sync {
+    /// Insert or replace the session
+    fn save(&self, session: &SigningSession) -> Result<(), SessionStoreError>;
+
+    fn load(&self, id: &str) -> Result<Option<SigningSession>, SessionStoreError>;
+
+    /// Remove the session, removing a missing session is not an error.
+    fn remove(&self, id: &str) -> Result<(), SessionStoreError>;
+
+    /// All the stored sessions, ordered by id
+    fn list(&self) -> Result<Vec<SigningSession>, SessionStoreError>;
+
+    /// The inputs reserved by all the stored sessions
+    fn reserved_inputs(&self) -> Result<Vec<OutPoint>, SessionStoreError> {
+        Ok(self
+            .list()?
+            .iter()
+            .flat_map(SigningSession::reserved_out_points)
+            .collect())
+    }
+}
+
+/// Lock the inputs reserved by the stored sessions in the cell collector, call
+/// this after a restart so new transactions will not spend them.
+pub fn restore_reservations(
+    store: &dyn SessionStore,
+    cell_collector: &mut dyn CellCollector,
+    tip_block_number: u64,
+) -> Result<usize, SessionStoreError> {
+    let out_points = store.reserved_inputs()?;
+    let count = out_points.len();
+    for out_point in out_points {
+        cell_collector
+            .lock_cell(out_point, tip_block_number)
+            .map_err(|err| SessionStoreError::Other(err.into()))?;
+    }
+    Ok(count)
+}
+
+fn check_session_id(id: &str) -> Result<(), SessionStoreError> {
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(SessionStoreError::InvalidId(id.to_string()))
+    }
+}
+
+const SESSION_FILE_EXT: &str = "json";
+const SESSION_TMP_FILE_EXT: &str = "json.tmp";
+
+/// Store each session as a json file in a directory.
+///
+/// A session is first written to a temporary file then renamed, temporary
+/// files left by a crash are ignored. Session files larger than
+/// `max_file_size` are rejected, the directory may be shared with other
+/// participants.
+#[derive(Debug, Clone)]
+pub struct FileSessionStore {
+    dir: PathBuf,
+    max_file_size: u64,
+}
+
+impl FileSessionStore {
+    /// The directory is created if not exists
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<FileSessionStore, SessionStoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(FileSessionStore {
+            dir: dir.as_ref().to_path_buf(),
+            max_file_size: DEFAULT_MAX_READ_SIZE,
+        })
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn session_path(&self, id: &str, ext: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, ext))
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn save(&self, session: &SigningSession) -> Result<(), SessionStoreError> {
+        check_session_id(&session.id)?;
+        let tmp_path = self.session_path(&session.id, SESSION_TMP_FILE_EXT);
+        {
+            let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+            serde_json::to_writer_pretty(&mut writer, session)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, self.session_path(&session.id, SESSION_FILE_EXT))?;
+        Ok(())
+    }
+
+    fn load(&self, id: &str) -> Result<Option<SigningSession>, SessionStoreError> {
+        check_session_id(id)?;
+        match fs::File::open(self.session_path(id, SESSION_FILE_EXT)) {
+            Ok(file) => Ok(Some(read_json_bounded(file, self.max_file_size)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn remove(&self, id: &str) -> Result<(), SessionStoreError> {
+        check_session_id(id)?;
+        match fs::remove_file(self.session_path(id, SESSION_FILE_EXT)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<SigningSession>, SessionStoreError> {
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SESSION_FILE_EXT) {
+                continue;
+            }
+            let file = fs::File::open(&path)?;
+            sessions.push(read_json_bounded(file, self.max_file_size)?);
+        }
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sessions)
+    }
+}
+
+/// Keep the sessions in memory, for tests or short-lived processes.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, SigningSession>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn save(&self, session: &SigningSession) -> Result<(), SessionStoreError> {
+        check_session_id(&session.id)?;
+        self.sessions
+            .lock()
+            .insert(session.id.clone(), session.clone());
+        Ok(())
+    }
+
+    fn load(&self, id: &str) -> Result<Option<SigningSession>, SessionStoreError> {
+        Ok(self.sessions.lock().get(id).cloned())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), SessionStoreError> {
+        self.sessions.lock().remove(id);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<SigningSession>, SessionStoreError> {
+        let mut sessions = self.sessions.lock().values().cloned().collect::<Vec<_>>();
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::TransactionBuilder, packed::CellInput};
+
+    fn test_tx() -> TransactionView {
+        TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256([1u8; 32]).pack(), 0), 0))
+            .input(CellInput::new(OutPoint::new(H256([2u8; 32]).pack(), 1), 0))
+            .build()
+    }
+
+    fn temp_store_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ckb-sdk-session-{}-{}-{}",
+            name,
+            std::process::id(),
+            now_millis()
+        ))
+    }
+
+    #[test]
+    fn test_file_session_store() {
+        let dir = temp_store_dir("file");
+        let store = FileSessionStore::new(&dir).unwrap();
+        let tx = test_tx();
+        let mut session = SigningSession::new("session-1", SessionKind::Multisig, &tx);
+        assert!(session.add_signature(H256([3u8; 32]), Bytes::from(vec![4u8; 65])));
+        assert!(!session.add_signature(H256([3u8; 32]), Bytes::from(vec![4u8; 65])));
+        store.save(&session).unwrap();
+
+        // a crashed write leaves only the temporary file
+        fs::write(dir.join("session-2.json.tmp"), b"{").unwrap();
+
+        // reopen the store as after a restart
+        let store = FileSessionStore::new(&dir).unwrap();
+        let loaded = store.load("session-1").unwrap().unwrap();
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.tx_view().hash(), tx.hash());
+        assert_eq!(
+            loaded.signatures_of(&H256([3u8; 32])),
+            vec![Bytes::from(vec![4u8; 65])]
+        );
+        assert_eq!(store.list().unwrap(), vec![session]);
+        assert_eq!(
+            store.reserved_inputs().unwrap(),
+            tx.input_pts_iter().collect::<Vec<_>>()
+        );
+        assert!(store.load("session-2").unwrap().is_none());
+        assert!(matches!(
+            store.load("../session-1"),
+            Err(SessionStoreError::InvalidId(_))
+        ));
+
+        store.remove("session-1").unwrap();
+        store.remove("session-1").unwrap();
+        assert!(store.list().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_session_store() {
+        let store = MemorySessionStore::new();
+        let session = SigningSession::new("otx", SessionKind::Otx, &test_tx());
+        store.save(&session).unwrap();
+        assert_eq!(store.load("otx").unwrap(), Some(session));
+        assert_eq!(store.reserved_inputs().unwrap().len(), 2);
+        store.remove("otx").unwrap();
+        assert!(store.load("otx").unwrap().is_none());
+    }
+}
@@ -0,0 +1,232 @@
+//! Check a [`ScriptSigner`] implementation against the real lock scripts.
+//!
+//! The compiled system lock scripts (secp256k1 sighash and multisig from the
+//! testnet genesis block, anyone-can-pay and cheque) are bundled as fixtures.
+//! [`SignerTestkit::check_signer`] spends a cell of a lock with a transaction
+//! signed by the signer and runs all the scripts in ckb-vm, so a third-party
+//! signer can be validated without a node.
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{BlockView, Cycle, ScriptHashType, TransactionBuilder},
+    packed::{CellInput, CellOutput, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use crate::constants::{MULTISIG_TYPE_HASH, ONE_CKB, SECP_SIGNATURE_SIZE, SIGHASH_TYPE_HASH};
+use crate::test_util::{random_out_point, Context};
+use crate::traits::CellDepResolver;
+use crate::unlock::{ScriptSignError, ScriptSigner};
+use crate::ScriptGroup;
+
+/// The testnet genesis block with the secp256k1 sighash and multisig scripts
+pub const GENESIS_BLOCK_JSON: &str = include_str!("test-data/genesis_block.json");
+/// The compiled anyone-can-pay lock script
+pub const ACP_BIN: &[u8] = include_bytes!("test-data/anyone_can_pay");
+/// The compiled cheque lock script
+pub const CHEQUE_BIN: &[u8] = include_bytes!("test-data/ckb-cheque-script");
+
+/// The capacity of the cell spent by [`SignerTestkit::check_signer`]
+const INPUT_CAPACITY: u64 = 1000 * ONE_CKB;
+/// The fee of the transaction built by [`SignerTestkit::check_signer`]
+const TX_FEE: u64 = ONE_CKB;
+
+#[derive(Error, Debug)]
+pub enum SignerTestkitError {
+    #[error("signer does not match the lock script args: `{0}`")]
+    ArgsNotMatched(Script),
+
+    #[error("sign transaction error: `{0}`")]
+    Sign(#[from] ScriptSignError),
+
+    #[error("resolve cell dep failed: `{0}`")]
+    ResolveCellDepFailed(Script),
+
+    #[error("verify script error: `{0}`")]
+    VerifyScript(String),
+}
+
+/// The system lock scripts bundled in the testkit
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SystemLock {
+    Sighash,
+    Multisig,
+    Acp,
+    Cheque,
+}
+
+impl SystemLock {
+    /// The lock script with `args`
+    pub fn script(self, args: Bytes) -> Script {
+        let (code_hash, hash_type) = match self {
+            SystemLock::Sighash => (SIGHASH_TYPE_HASH, ScriptHashType::Type),
+            SystemLock::Multisig => (MULTISIG_TYPE_HASH, ScriptHashType::Type),
+            SystemLock::Acp => (H256::from(blake2b_256(ACP_BIN)), ScriptHashType::Data1),
+            SystemLock::Cheque => (H256::from(blake2b_256(CHEQUE_BIN)), ScriptHashType::Data1),
+        };
+        Script::new_builder()
+            .code_hash(code_hash.pack())
+            .hash_type(hash_type.into())
+            .args(args.pack())
+            .build()
+    }
+}
+
+/// A test context with all the [`SystemLock`]s deployed
+pub struct SignerTestkit {
+    pub ctx: Context,
+}
+
+impl Default for SignerTestkit {
+    fn default() -> SignerTestkit {
+        SignerTestkit::new()
+    }
+}
+
+impl SignerTestkit {
+    pub fn new() -> SignerTestkit {
+        let genesis_block: json_types::BlockView =
+            serde_json::from_str(GENESIS_BLOCK_JSON).expect("genesis block json");
+        let genesis_block: BlockView = genesis_block.into();
+        let ctx = Context::new(&genesis_block, vec![(ACP_BIN, true), (CHEQUE_BIN, true)]);
+        SignerTestkit { ctx }
+    }
+
+    /// Spend a cell of `lock` with `since`, sign the transaction with
+    /// `signer` and run all the scripts. The cycles are returned on success.
+    ///
+    /// The input cell and its change output are both locked by `lock`, the
+    /// witness lock placeholder has the length from
+    /// [`ScriptSigner::placeholder_lock_len`] (a secp256k1 signature when
+    /// unknown).
+    pub fn check_signer(
+        &mut self,
+        signer: &dyn ScriptSigner,
+        lock: &Script,
+        since: u64,
+    ) -> Result<Cycle, SignerTestkitError> {
+        let args = lock.args().raw_data();
+        if !signer.match_args(args.as_ref()) {
+            return Err(SignerTestkitError::ArgsNotMatched(lock.clone()));
+        }
+        let cell_dep = self
+            .ctx
+            .resolve(lock)
+            .ok_or_else(|| SignerTestkitError::ResolveCellDepFailed(lock.clone()))?;
+        let out_point = random_out_point();
+        self.ctx
+            .add_simple_live_cell(out_point.clone(), lock.clone(), Some(INPUT_CAPACITY));
+
+        let lock_len = signer
+            .placeholder_lock_len(args.as_ref())
+            .unwrap_or(SECP_SIGNATURE_SIZE);
+        let placeholder_witness = WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; lock_len])).pack())
+            .build();
+        let output = CellOutput::new_builder()
+            .capacity((INPUT_CAPACITY - TX_FEE).pack())
+            .lock(lock.clone())
+            .build();
+        let tx = TransactionBuilder::default()
+            .cell_dep(cell_dep)
+            .input(CellInput::new(out_point, since))
+            .output(output)
+            .output_data(Bytes::new().pack())
+            .witness(placeholder_witness.as_bytes().pack())
+            .build();
+        let mut script_group = ScriptGroup::from_lock_script(lock);
+        script_group.input_indices.push(0);
+        let tx = signer.sign_tx(&tx, &script_group)?;
+        self.ctx
+            .verify_scripts(tx)
+            .map_err(|err| SignerTestkitError::VerifyScript(err.to_string()))
+    }
+}
+
+/// Panic when `signer` fails [`SignerTestkit::check_signer`]
+pub fn assert_signer_passes(signer: &dyn ScriptSigner, lock: &Script, since: u64) -> Cycle {
+    SignerTestkit::new()
+        .check_signer(signer, lock, since)
+        .unwrap_or_else(|err| panic!("signer failed on lock {}: {}", lock, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::SecpCkbRawKeySigner;
+    use crate::unlock::{
+        AcpScriptSigner, MultisigConfig, SecpMultisigScriptSigner, SecpSighashScriptSigner,
+    };
+    use crate::SECP256K1;
+    use ckb_types::{h256, H160};
+
+    const KEY: H256 = h256!("0x8d929e962f940f4ab0c4a6ba0d4a1a9c3a2b7e0b0c9a1c3b2e2ae1d8f3a1b2c3");
+
+    fn build_signer() -> (Box<SecpCkbRawKeySigner>, H160) {
+        let key = secp256k1::SecretKey::from_slice(KEY.as_bytes()).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let id = H160::from_slice(&blake2b_256(pubkey.serialize())[0..20]).unwrap();
+        (
+            Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![key])),
+            id,
+        )
+    }
+
+    #[test]
+    fn test_system_lock_signers() {
+        let mut testkit = SignerTestkit::new();
+        let (key_signer, id) = build_signer();
+        let sighash_signer = SecpSighashScriptSigner::new(key_signer);
+        let lock = SystemLock::Sighash.script(Bytes::from(id.as_bytes().to_vec()));
+        assert!(testkit.check_signer(&sighash_signer, &lock, 0).is_ok());
+
+        let (key_signer, id) = build_signer();
+        let acp_signer = AcpScriptSigner::new(key_signer);
+        let lock = SystemLock::Acp.script(Bytes::from(id.as_bytes().to_vec()));
+        assert!(testkit.check_signer(&acp_signer, &lock, 0).is_ok());
+
+        let (key_signer, id) = build_signer();
+        let config = MultisigConfig::new_with(vec![id], 0, 1).unwrap();
+        let lock = SystemLock::Multisig.script(Bytes::from(config.hash160().as_bytes().to_vec()));
+        let multisig_signer = SecpMultisigScriptSigner::new(key_signer, config);
+        assert!(testkit.check_signer(&multisig_signer, &lock, 0).is_ok());
+    }
+
+    /// Signs with a wrong message, the real script must reject it
+    struct BrokenSigner(SecpSighashScriptSigner);
+
+    impl ScriptSigner for BrokenSigner {
+        fn match_args(&self, args: &[u8]) -> bool {
+            self.0.match_args(args)
+        }
+
+        fn sign_tx(
+            &self,
+            tx: &ckb_types::core::TransactionView,
+            script_group: &ScriptGroup,
+        ) -> Result<ckb_types::core::TransactionView, ScriptSignError> {
+            let tx = self.0.sign_tx(tx, script_group)?;
+            // change the output after signing
+            let output = tx.output(0).unwrap().as_builder().capacity(0u64.pack());
+            Ok(tx
+                .as_advanced_builder()
+                .set_outputs(vec![output.build()])
+                .build())
+        }
+    }
+
+    #[test]
+    fn test_broken_signer() {
+        let (key_signer, id) = build_signer();
+        let signer = BrokenSigner(SecpSighashScriptSigner::new(key_signer));
+        let lock = SystemLock::Sighash.script(Bytes::from(id.as_bytes().to_vec()));
+        let err = SignerTestkit::new()
+            .check_signer(&signer, &lock, 0)
+            .unwrap_err();
+        assert!(matches!(err, SignerTestkitError::VerifyScript(_)));
+    }
+}
@@ -1,4 +1,3 @@
-use ckb_chain_spec::consensus::ConsensusBuilder;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
@@ -10,6 +9,7 @@ use rand::{thread_rng, Rng};
 use thiserror::Error;
 
 use crate::{
+    compat::{dev_consensus, resolve_tx, verify_tx_scripts},
     constants::{
         MULTISIG_GROUP_OUTPUT_LOC, MULTISIG_TYPE_HASH, ONE_CKB, SIGHASH_GROUP_OUTPUT_LOC,
         SIGHASH_TYPE_HASH,
@@ -26,13 +26,10 @@ use ckb_hash::blake2b_256;
 use ckb_mock_tx_types::{
     MockCellDep, MockInfo, MockInput, MockResourceLoader, MockTransaction, Resource,
 };
-use ckb_script::{TransactionScriptsVerifier, TxVerifyEnv};
-use ckb_types::core::hardfork::{HardForks, CKB2021, CKB2023};
 use ckb_types::{
     bytes::Bytes,
     core::{
-        cell::resolve_transaction, BlockView, Capacity, Cycle, DepType, FeeRate, HeaderView,
-        ScriptHashType, TransactionView,
+        BlockView, Capacity, Cycle, DepType, FeeRate, HeaderView, ScriptHashType, TransactionView,
     },
     packed::{Byte32, CellDep, CellInput, CellOutput, OutPoint, OutPointVec, Script, Transaction},
     prelude::*,
@@ -65,6 +62,8 @@ pub struct Context {
     pub dep_type_hashes: Vec<Option<H256>>,
     /// For resolve dep group cell dep
     pub cell_dep_map: HashMap<ScriptId, CellDep>,
+    /// Full transactions returned by `get_transaction`
+    pub transactions: Vec<TransactionView>,
 }
 
 #[derive(Clone)]
@@ -269,6 +268,12 @@ impl Context {
         }
         None
     }
+    /// Add a full transaction, e.g. to look up the inputs of the transaction
+    /// creating a live cell
+    pub fn add_transaction(&mut self, tx: TransactionView) {
+        self.transactions.push(tx);
+    }
+
     pub fn get_live_cell_with_tx_hash(&self, tx_hash: &Byte32) -> Option<Vec<(CellOutput, Bytes)>> {
         if let Some(result) = self.get_input_with_tx_hash(tx_hash) {
             return Some(vec![result]);
@@ -357,29 +362,17 @@ impl Context {
         let mock_tx = self.to_mock_tx(tx.data());
         let resource =
             Resource::from_both(&mock_tx, &mut DummyLoader).map_err(Error::VerifyScript)?;
-        let rtx = resolve_transaction(tx, &mut HashSet::new(), &resource, &resource)
-            .map_err(|err| Error::VerifyScript(format!("Resolve transaction error: {:?}", err)))?;
-        let consensus = ConsensusBuilder::default()
-            .hardfork_switch(HardForks {
-                ckb2021: CKB2021::new_dev_default(),
-                ckb2023: CKB2023::new_dev_default(),
-            })
-            .build();
+        let rtx = resolve_tx(tx, &resource, &resource).map_err(Error::VerifyScript)?;
         let tip = HeaderBuilder::default().number(0.pack()).build();
-        let tx_verify_env = TxVerifyEnv::new_submit(&tip);
-
-        let mut verifier = TransactionScriptsVerifier::new(
-            Arc::new(rtx),
+        verify_tx_scripts(
+            rtx,
             resource,
-            Arc::new(consensus),
-            Arc::new(tx_verify_env),
-        );
-        verifier.set_debug_printer(|script_hash, message| {
-            println!("script: {:x}, debug: {}", script_hash, message);
-        });
-        verifier
-            .verify(u64::max_value())
-            .map_err(|err| Error::VerifyScript(format!("Verify script error: {:?}", err)))
+            Arc::new(dev_consensus()),
+            &tip,
+            u64::max_value(),
+            true,
+        )
+        .map_err(Error::VerifyScript)
     }
 
     /// Verify:
@@ -397,6 +390,9 @@ impl TransactionDependencyProvider for Context {
         &self,
         tx_hash: &Byte32,
     ) -> Result<TransactionView, TransactionDependencyError> {
+        if let Some(tx) = self.transactions.iter().find(|tx| &tx.hash() == tx_hash) {
+            return Ok(tx.clone());
+        }
         self.get_live_cell_with_tx_hash(tx_hash)
             .map(|data| {
                 let (outputs, outputs_data): (Vec<_>, Vec<_>) = data
@@ -547,17 +543,36 @@ impl CellCollector for LiveCellsContext {
 
     fn lock_cell(
         &mut self,
-        _out_point: OutPoint,
+        out_point: OutPoint,
         _tip_block_number: u64,
     ) -> Result<(), CellCollectorError> {
-        unimplemented!()
+        if let Some(idx) = self
+            .inputs
+            .iter()
+            .position(|item| item.input.previous_output() == out_point)
+        {
+            self.used_inputs.insert(idx);
+        }
+        Ok(())
     }
     fn apply_tx(
         &mut self,
-        _tx: Transaction,
-        _tip_block_number: u64,
+        tx: Transaction,
+        tip_block_number: u64,
     ) -> Result<(), CellCollectorError> {
-        unimplemented!()
+        let tx_view = tx.into_view();
+        for out_point in tx_view.input_pts_iter() {
+            self.lock_cell(out_point, tip_block_number)?;
+        }
+        for (idx, (output, data)) in tx_view.outputs_with_data_iter().enumerate() {
+            self.inputs.push(MockInput {
+                input: CellInput::new(OutPoint::new(tx_view.hash(), idx as u32), 0),
+                output,
+                data,
+                header: None,
+            });
+        }
+        Ok(())
     }
     fn reset(&mut self) {
         self.used_inputs.clear();
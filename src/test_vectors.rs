@@ -0,0 +1,311 @@
+//! Sighash message test vectors shared with the other CKB SDKs.
+//!
+//! [`SighashVectorSet::generate`] builds a fixed set of transactions and
+//! script groups together with the message [`generate_message`] computes for
+//! them. The set is exported as JSON for the JS, Go and Java SDK test suites,
+//! and [`SighashVectorSet::check`] validates this crate against the vectors
+//! produced by another SDK, see the `sighash_test_vectors` example.
+
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types::{self as json_types, JsonBytes};
+use ckb_types::{
+    bytes::Bytes,
+    core::{DepType, ScriptHashType, TransactionBuilder, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script, Transaction, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::constants::{ONE_CKB, SECP_SIGNATURE_SIZE, SIGHASH_TYPE_HASH};
+use crate::types::{ScriptGroup, ScriptGroupType};
+use crate::unlock::{generate_message, ScriptSignError};
+
+/// The version of the vectors format, bumped on incompatible changes
+pub const SIGHASH_VECTORS_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum TestVectorError {
+    #[error("generate message error: `{0}`")]
+    Sign(#[from] ScriptSignError),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("unsupported test vectors version: `{0}`")]
+    UnsupportedVersion(u32),
+
+    #[error("vector `{0}` has no input index `{1}`")]
+    InvalidInputIndex(String, usize),
+
+    #[error("vector `{name}` expects message `{expected:#x}`, got `{actual:#x}`")]
+    Mismatch {
+        name: String,
+        expected: H256,
+        actual: H256,
+    },
+}
+
+/// The sighash message of a script group of a transaction
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SighashVector {
+    pub name: String,
+    pub tx: json_types::Transaction,
+    /// The input indices of the script group, the witness of the first one
+    /// holds the signature
+    pub group_input_indices: Vec<usize>,
+    /// The zero filled witness lock the message is computed with
+    pub zero_lock: JsonBytes,
+    pub message: H256,
+}
+
+impl SighashVector {
+    /// A vector with the message computed by this crate
+    pub fn new<S: Into<String>>(
+        name: S,
+        tx: &TransactionView,
+        group_input_indices: Vec<usize>,
+        zero_lock: Bytes,
+    ) -> Result<SighashVector, TestVectorError> {
+        let mut vector = SighashVector {
+            name: name.into(),
+            tx: tx.data().into(),
+            group_input_indices,
+            zero_lock: JsonBytes::from_bytes(zero_lock),
+            message: H256::default(),
+        };
+        vector.message = vector.compute_message()?;
+        Ok(vector)
+    }
+
+    /// The message of the vector computed by this crate
+    pub fn compute_message(&self) -> Result<H256, TestVectorError> {
+        let tx = Transaction::from(self.tx.clone()).into_view();
+        if let Some(idx) = self
+            .group_input_indices
+            .iter()
+            .find(|idx| **idx >= tx.inputs().len())
+        {
+            return Err(TestVectorError::InvalidInputIndex(self.name.clone(), *idx));
+        }
+        let mut script_group = ScriptGroup::new(&Script::default(), ScriptGroupType::Lock);
+        script_group.input_indices = self.group_input_indices.clone();
+        let message = generate_message(&tx, &script_group, self.zero_lock.clone().into_bytes())?;
+        Ok(H256::from_slice(&message).expect("blake2b 256 message"))
+    }
+
+    /// Check the expected message against the one computed by this crate
+    pub fn check(&self) -> Result<(), TestVectorError> {
+        let actual = self.compute_message()?;
+        if actual != self.message {
+            return Err(TestVectorError::Mismatch {
+                name: self.name.clone(),
+                expected: self.message.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The exported vectors file
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SighashVectorSet {
+    pub version: u32,
+    pub vectors: Vec<SighashVector>,
+}
+
+impl SighashVectorSet {
+    /// The vectors of this crate, the output is the same on every run
+    pub fn generate() -> Result<SighashVectorSet, TestVectorError> {
+        let lock = sighash_lock(0x11);
+        let other_lock = sighash_lock(0x22);
+        let placeholder = placeholder_witness(SECP_SIGNATURE_SIZE);
+        let zero_lock = Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]);
+        let typed_witness = WitnessArgs::new_builder()
+            .lock(Some(zero_lock.clone()).pack())
+            .input_type(Some(Bytes::from(vec![0xaa, 0xbb])).pack())
+            .output_type(Some(Bytes::from(vec![0xcc])).pack())
+            .build()
+            .as_bytes();
+        // a 2 of 2 multisig witness lock: the 4 bytes header, 2 blake160 and
+        // 2 signatures
+        let multisig_zero_lock = Bytes::from(vec![0u8; 4 + 20 * 2 + SECP_SIGNATURE_SIZE * 2]);
+
+        let vectors = vec![
+            SighashVector::new(
+                "single_input",
+                &vector_tx("single_input", &[&lock], &[placeholder.clone()]),
+                vec![0],
+                zero_lock.clone(),
+            )?,
+            SighashVector::new(
+                "group_with_gap",
+                &vector_tx(
+                    "group_with_gap",
+                    &[&lock, &other_lock, &lock],
+                    &[placeholder.clone(), placeholder.clone(), Bytes::new()],
+                ),
+                vec![0, 2],
+                zero_lock.clone(),
+            )?,
+            SighashVector::new(
+                "missing_witnesses",
+                &vector_tx("missing_witnesses", &[&lock, &lock], &[]),
+                vec![0, 1],
+                zero_lock.clone(),
+            )?,
+            SighashVector::new(
+                "outer_witnesses",
+                &vector_tx(
+                    "outer_witnesses",
+                    &[&lock],
+                    &[placeholder, Bytes::from(vec![0x12, 0x34]), Bytes::new()],
+                ),
+                vec![0],
+                zero_lock.clone(),
+            )?,
+            SighashVector::new(
+                "witness_type_fields",
+                &vector_tx("witness_type_fields", &[&lock], &[typed_witness]),
+                vec![0],
+                zero_lock,
+            )?,
+            SighashVector::new(
+                "multisig_zero_lock",
+                &vector_tx("multisig_zero_lock", &[&lock], &[Bytes::new()]),
+                vec![0],
+                multisig_zero_lock,
+            )?,
+        ];
+        Ok(SighashVectorSet {
+            version: SIGHASH_VECTORS_VERSION,
+            vectors,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, TestVectorError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parse vectors exported by any SDK
+    pub fn from_json(json: &str) -> Result<SighashVectorSet, TestVectorError> {
+        let set: SighashVectorSet = serde_json::from_str(json)?;
+        if set.version != SIGHASH_VECTORS_VERSION {
+            return Err(TestVectorError::UnsupportedVersion(set.version));
+        }
+        Ok(set)
+    }
+
+    /// Check every vector, stop at the first failure
+    pub fn check(&self) -> Result<(), TestVectorError> {
+        self.vectors.iter().try_for_each(SighashVector::check)
+    }
+}
+
+fn sighash_lock(args_byte: u8) -> Script {
+    Script::new_builder()
+        .code_hash(SIGHASH_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![args_byte; 20]).pack())
+        .build()
+}
+
+fn placeholder_witness(lock_len: usize) -> Bytes {
+    WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; lock_len])).pack())
+        .build()
+        .as_bytes()
+}
+
+/// A transaction spending a cell of each of `input_locks` to a single
+/// 100 CKB output, the out points are derived from `name`
+fn vector_tx(name: &str, input_locks: &[&Script], witnesses: &[Bytes]) -> TransactionView {
+    let cell_dep = CellDep::new_builder()
+        .out_point(OutPoint::new(blake2b_256(b"cell_dep").pack(), 0))
+        .dep_type(DepType::DepGroup.into())
+        .build();
+    let inputs = (0..input_locks.len())
+        .map(|idx| CellInput::new(OutPoint::new(blake2b_256(name).pack(), idx as u32), 0));
+    let output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(sighash_lock(0x33))
+        .build();
+    TransactionBuilder::default()
+        .cell_dep(cell_dep)
+        .inputs(inputs)
+        .output(output)
+        .output_data(Bytes::new().pack())
+        .witnesses(witnesses.iter().map(|witness| witness.pack()))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h256;
+
+    #[test]
+    fn test_sighash_vectors() {
+        let set = SighashVectorSet::generate().unwrap();
+        // the messages are computed independently of this crate
+        let expected = [
+            (
+                "single_input",
+                h256!("0x0d3264eb5d6e66b341ba1dca6da0e0e2bace3dc2fa8015ec9870e0b0a0d4bba5"),
+            ),
+            (
+                "group_with_gap",
+                h256!("0x8267de8731c86d34abca34db13a4531d1a5d839202f20c2b57e56c58818b9a90"),
+            ),
+            (
+                "missing_witnesses",
+                h256!("0x66958f2bba4025bb8aacbffab750c09732cb8e3a459d4888bc3c0d09d5cbb5d4"),
+            ),
+            (
+                "outer_witnesses",
+                h256!("0xc471eb0eaa2c529c2162a74b7d7daa96cdb95547fc0eeb84f61bd565e2af770f"),
+            ),
+            (
+                "witness_type_fields",
+                h256!("0xb76f28512b48f95dfc4632d4fcbd350a5f7af17113b84fbc5be83b1253e91ee4"),
+            ),
+            (
+                "multisig_zero_lock",
+                h256!("0x2c4d187b6aa5d162149e6c3837517a1a798e1ee4e69e7033465c8783bbb5578e"),
+            ),
+        ];
+        assert_eq!(
+            set.vectors
+                .iter()
+                .map(|vector| (vector.name.as_str(), vector.message.clone()))
+                .collect::<Vec<_>>(),
+            expected.to_vec()
+        );
+
+        let imported = SighashVectorSet::from_json(&set.to_json().unwrap()).unwrap();
+        assert_eq!(imported, set);
+        assert!(imported.check().is_ok());
+
+        let mut tampered = imported.clone();
+        tampered.vectors[1].tx.outputs[0].capacity = (99 * ONE_CKB).into();
+        assert!(matches!(
+            tampered.check(),
+            Err(TestVectorError::Mismatch { name, .. }) if name == "group_with_gap"
+        ));
+        let mut tampered = imported.clone();
+        tampered.vectors[0].group_input_indices = vec![0, 1];
+        assert!(matches!(
+            tampered.check(),
+            Err(TestVectorError::InvalidInputIndex(_, 1))
+        ));
+
+        let mut future = set;
+        future.version += 1;
+        assert!(matches!(
+            SighashVectorSet::from_json(&future.to_json().unwrap()),
+            Err(TestVectorError::UnsupportedVersion(2))
+        ));
+    }
+}
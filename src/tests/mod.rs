@@ -1,40 +1,97 @@
-use std::{collections::HashMap, u64};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+    u64,
+};
 
 use ckb_dao_utils::pack_dao_data;
 use ckb_hash::blake2b_256;
 use ckb_jsonrpc_types as json_types;
 use ckb_types::{
     bytes::Bytes,
-    core::{BlockView, Capacity, EpochNumberWithFraction, HeaderBuilder, ScriptHashType},
+    core::{
+        BlockView, Capacity, DepType, EpochNumberWithFraction, FeeRate, HeaderBuilder,
+        ScriptHashType, TransactionBuilder, TransactionView,
+    },
     h160, h256,
-    packed::{CellInput, CellOutput, Script, ScriptOpt, WitnessArgs},
+    packed::{
+        CellDep, CellInput, CellOutput, OutPoint, Script, ScriptOpt, Transaction, WitnessArgs,
+    },
     prelude::*,
     H160, H256,
 };
 
+use crate::cell_snapshot::{export_cells, CellSnapshot, CellSnapshotError, CELL_SNAPSHOT_VERSION};
 use crate::constants::{
     CHEQUE_CELL_SINCE, DAO_TYPE_HASH, MULTISIG_TYPE_HASH, ONE_CKB, SIGHASH_TYPE_HASH,
+    TYPE_ID_CODE_HASH,
+};
+use crate::deadline::{Deadline, DeadlineExceeded};
+use crate::deposit::{DepositKeyDeriver, DepositLockKind, DepositRegistry};
+use crate::hash::Blake2bBackend;
+use crate::ledger::{classify_transaction, Asset, LedgerExporter, TokenInfo, TokenRegistry};
+use crate::sdk::{cheque_claim_builder, udt_transfer_builder, CkbSdk};
+use crate::traits::{
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, CompositeSigner,
+    LiveCell, OffchainCellCollector, SecpCkbRawKeySigner, Signer, SignerError, TenantReservations,
+    TenantScope, TransactionDependencyError, TransactionDependencyProvider,
 };
-use crate::traits::SecpCkbRawKeySigner;
 use crate::tx_builder::{
-    acp::{AcpTransferBuilder, AcpTransferReceiver},
+    acp::{find_acp_cell, AcpCellSelectStrategy, AcpTransferBuilder, AcpTransferReceiver},
+    balance_tx_capacity, balance_tx_capacity_with_breakdown, check_since_reached,
     cheque::{ChequeClaimBuilder, ChequeWithdrawBuilder},
+    coin_selection::{compare_selection_strategies, SelectionStrategy},
+    cycles::{dummy_sign_tx, estimate_cycles_with_dummy_signatures},
     dao::{
-        DaoDepositBuilder, DaoDepositReceiver, DaoPrepareBuilder, DaoWithdrawBuilder,
-        DaoWithdrawItem, DaoWithdrawReceiver,
+        withdraw_since, DaoCompoundBuilder, DaoDepositBuilder, DaoDepositReceiver,
+        DaoPrepareBuilder, DaoWithdrawBuilder, DaoWithdrawItem, DaoWithdrawReceiver,
     },
+    donation::{DonationError, DonationGuard},
+    escrow::{EscrowClaimBuilder, EscrowScript},
+    extra_deps::ExtraDepsBuilder,
+    funding::{fund_transaction, FundingRequest},
+    gen_script_groups,
+    htlc::HtlcBuilder,
+    migration::{
+        canonical_address, is_deprecated_address, scan_deprecated_locks, MigrationBuilder,
+        ScriptMigration,
+    },
+    multisig_rotation::MultisigRotationBuilder,
+    negotiate_tx_witness_layouts,
+    policy::{OutputPolicy, OutputPolicyError, PolicyViolation},
+    refund::{RefundBuilder, RefundFeePolicy},
+    resolve_cell_deps,
+    swap::{SwapBuilder, SwapProposal, SwapStage, SwapTerms},
+    sweep::{MultiLockSweepBuilder, SweepLock},
+    template::{CachedTxBuilder, TemplateCache, TemplateKey},
+    trace::{BuildTrace, TraceEvent},
     transfer::CapacityTransferBuilder,
+    tx_fee,
     udt::{UdtIssueBuilder, UdtTargetReceiver, UdtTransferBuilder, UdtType},
-    unlock_tx, CapacityBalancer, TransferAction, TxBuilder,
+    unlock_tx, unlock_tx_fully,
+    vesting::{vesting_lock, VestingBuilder, VestingClaimBuilder},
+    vote::{tally_votes, VoteAmendBuilder, VoteBuilder, VoteLayout, VoteScheme},
+    BalanceLimits, BalanceTxCapacityError, CapacityBalancer, CapacityProvider, ChangeDecision,
+    LockedReason, SmallChangePolicy, TransferAction, TxBuilder, TxBuilderError,
+    REPORTED_SKIPPED_CELLS,
 };
+use crate::types::{Address, AddressPayload, HtlcAction, HtlcArgs, NetworkInfo, NetworkType};
 use crate::unlock::{
-    AcpUnlocker, ChequeAction, ChequeUnlocker, MultisigConfig, ScriptUnlocker,
-    SecpMultisigUnlocker, SecpSighashUnlocker,
+    cobuild_signing_message, detect_witness_layout, ensure_witness_capacity, fill_signature_slot,
+    fill_witness_type, generate_message, generate_message_with_strategy, pad_group_witnesses,
+    verify_all_signatures, verify_domain_separated_signature, witness_index_for_group, AcpUnlocker,
+    ChequeAction, ChequeUnlocker, CobuildSighashWitness, CoverAllOuterWitnesses, HtlcUnlocker,
+    MigrationStatus, MultisigConfig, OuterWitnessStrategy, ScriptSignError, ScriptSigner,
+    ScriptUnlocker, SecpMultisigScriptSigner, SecpMultisigUnlocker, SecpSighashScriptSigner,
+    SecpSighashUnlocker, SigningAuthorizer, SigningEntry, SkipOuterWitnesses, SlotStatus,
+    TypeWitnessField, UnlockError, WitnessLayout, WitnessLayoutKind, WitnessLayoutMode,
+    WitnessMigrationError, WitnessMigrator,
 };
 use crate::util::{calculate_dao_maximum_withdraw4, minimal_unlock_point};
-use crate::{ScriptId, Since, SinceType};
+use crate::{ScriptGroup, ScriptGroupType, ScriptId, Since, SinceType};
 
-use crate::test_util::{random_out_point, Context};
+use crate::test_util::{random_out_point, Context, LiveCellsContext};
 
 // ckt1qyq86vaa6e8tsruv5ngcd5tp7lcvcewxy7cquuksvj
 const ACCOUNT0_KEY: H256 =
@@ -183,458 +240,778 @@ fn test_transfer_from_sighash() {
 }
 
 #[test]
-fn test_transfer_capacity_overflow() {
+fn test_transfer_min_capacity_top_up() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(100 * ONE_CKB))]);
-
-    let large_amount: u64 = u64::MAX;
+    // a lock with long args, e.g. an omnilock
+    let receiver = Script::new_builder()
+        .code_hash(SIGHASH_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Bytes::from(vec![1u8; 22]).pack())
+        .build();
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
     let output = CellOutput::new_builder()
-        .capacity((large_amount).pack())
+        .capacity((10 * ONE_CKB).pack())
         .lock(receiver)
         .build();
-    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let data = Bytes::from(vec![0u8; 4]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let build = |builder: CapacityTransferBuilder| {
+        builder
+            .build_balanced(
+                &mut ctx.to_live_cells_context(),
+                &ctx,
+                &ctx,
+                &ctx,
+                &balancer,
+                &HashMap::default(),
+            )
+            .unwrap()
+    };
 
-    let unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    let mut cell_collector = ctx.to_live_cells_context();
-    let res = builder.build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers);
-    assert!(res.is_err());
-    assert!(res.unwrap_err().to_string().contains("capacity not enough"));
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), data.clone())]);
+    let tx = build(builder.clone());
+    assert_eq!(tx.output(0).unwrap(), output);
+
+    let tx = build(builder.with_min_capacity_top_up());
+    // 8 bytes capacity, 33 bytes code hash and hash type, 22 bytes args, 4
+    // bytes data
+    let capacity: u64 = tx.output(0).unwrap().capacity().unpack();
+    assert_eq!(capacity, (8 + 33 + 22 + 4) * ONE_CKB);
+    let change: u64 = tx.output(1).unwrap().capacity().unpack();
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    assert!(change < (300 - 67) * ONE_CKB);
+
+    // outputs above the minimum are kept
+    let output = output.as_builder().capacity((100 * ONE_CKB).pack()).build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), data)]);
+    assert_eq!(
+        build(builder.with_min_capacity_top_up()).output(0).unwrap(),
+        output
+    );
 }
 
+#[cfg(feature = "hd")]
 #[test]
-fn test_transfer_from_multisig() {
-    let lock_args = vec![
-        ACCOUNT0_ARG.clone(),
-        ACCOUNT1_ARG.clone(),
-        ACCOUNT2_ARG.clone(),
-    ];
-    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
-
-    let sender = build_multisig_script(&cfg);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
+fn test_hd_account_privacy_mode() {
+    use crate::address_rotation::HdAccount;
+    use crate::address_scan::{AddressScanError, KeyChain};
 
+    let derive = |chain: KeyChain, index: u32| -> Result<Script, AddressScanError> {
+        let mut args = [0u8; 20];
+        args[0] = (chain == KeyChain::Change) as u8;
+        args[1..5].copy_from_slice(&index.to_le_bytes());
+        Ok(build_sighash_script(H160(args)))
+    };
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let receiving = derive(KeyChain::External, 0).unwrap();
+    let old_change = derive(KeyChain::Change, 0).unwrap();
     let ctx = init_context(
         Vec::new(),
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (receiving.clone(), Some(100 * ONE_CKB)),
+            (old_change.clone(), Some(200 * ONE_CKB)),
         ],
     );
 
+    // without the privacy mode the change goes to the first lock script
+    let mut account = HdAccount::new(derive, placeholder_witness.clone());
+    let balancer = account.balancer(FEE_RATE).unwrap();
+    assert_eq!(balancer.change_lock_script, None);
+    assert_eq!(account.lock_scripts(), vec![receiving.clone()]);
+    assert_eq!(
+        account.balance(&mut ctx.to_live_cells_context()).unwrap(),
+        100 * ONE_CKB
+    );
+
+    let mut account = account.with_privacy_mode(true);
+    assert_eq!(account.next_change_lock().unwrap(), old_change);
+    assert_eq!(
+        account.balance(&mut ctx.to_live_cells_context()).unwrap(),
+        300 * ONE_CKB
+    );
+    let output = CellOutput::new_builder()
+        .capacity((250 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT2_ARG))
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut change_locks = Vec::new();
+    for _ in 0..2 {
+        let balancer = account.balancer(FEE_RATE).unwrap();
+        let tx = builder
+            .build_balanced(
+                &mut ctx.to_live_cells_context(),
+                &ctx,
+                &ctx,
+                &ctx,
+                &balancer,
+                &HashMap::default(),
+            )
+            .unwrap();
+        // both lock scripts of the account are spent
+        assert_eq!(tx.inputs().len(), 2);
+        let change_lock = tx.output(1).unwrap().lock();
+        assert_eq!(Some(change_lock.clone()), balancer.change_lock_script);
+        assert!(account.contains(&change_lock));
+        change_locks.push(change_lock);
+    }
+    assert_eq!(
+        change_locks,
+        vec![
+            derive(KeyChain::Change, 1).unwrap(),
+            derive(KeyChain::Change, 2).unwrap()
+        ]
+    );
+    assert_eq!(account.locks().len(), 4);
+}
+
+#[test]
+fn test_extra_deps_builder() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
     let output = CellOutput::new_builder()
         .capacity((120 * ONE_CKB).pack())
-        .lock(receiver)
+        .lock(build_sighash_script(ACCOUNT2_ARG))
         .build();
-    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
-    let placeholder_witness = cfg.placeholder_witness();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let sighash_dep = ctx.resolve(&sender).unwrap();
+    let custom_dep = CellDep::new_builder()
+        .out_point(random_out_point())
+        .dep_type(DepType::Code.into())
+        .build();
+    let block_hash = h256!("0x1234").pack();
 
-    let mut cell_collector = ctx.to_live_cells_context();
-    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
-    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
-    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
-    let mut tx = builder
-        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+    let transfer = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let builder = ExtraDepsBuilder::new(transfer.clone())
+        .cell_dep(custom_dep.clone())
+        .cell_dep(custom_dep.clone())
+        .header_dep(block_hash.clone());
+    let tx = builder
+        .build_balanced(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
         .unwrap();
-
-    let mut locked_groups = None;
-    for key in [account0_key, account2_key] {
-        let unlockers = build_multisig_unlockers(key, cfg.clone());
-        let (new_tx, new_locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
-        tx = new_tx;
-        locked_groups = Some(new_locked_groups);
-    }
-
-    assert_eq!(locked_groups, Some(Vec::new()));
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 1);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 2);
-    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[1].len(), 0);
-    ctx.verify(tx, FEE_RATE).unwrap();
+    // the sighash dep is added by the balancer, after the extra ones
+    assert_eq!(
+        tx.cell_deps().into_iter().collect::<Vec<_>>(),
+        vec![custom_dep, sighash_dep]
+    );
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![block_hash]
+    );
+    assert_ne!(builder.template_key(), transfer.template_key());
 }
 
 #[test]
-fn test_transfer_from_acp() {
-    let data_hash = H256::from(blake2b_256(ACP_BIN));
-    let sender = Script::new_builder()
-        .code_hash(data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(ACCOUNT1_ARG.0.to_vec()).pack())
-        .build();
+fn test_cell_snapshot() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
     let receiver = build_sighash_script(ACCOUNT2_ARG);
     let ctx = init_context(
-        vec![(ACP_BIN, true)],
+        Vec::new(),
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (receiver.clone(), Some(50 * ONE_CKB)),
         ],
     );
+    let snapshot = export_cells(
+        &mut ctx.to_live_cells_context(),
+        &[sender.clone(), sender.clone()],
+        100,
+        90,
+    )
+    .unwrap();
+    assert_eq!(snapshot.lock_scripts.len(), 1);
+    assert_eq!(snapshot.cells.len(), 2);
+    assert_eq!(snapshot.total_capacity(), 300 * ONE_CKB);
+
+    let json = snapshot.to_json().unwrap();
+    let imported = CellSnapshot::from_json(&json).unwrap();
+    assert_eq!(imported, snapshot);
+    let mut future = snapshot.clone();
+    future.version = CELL_SNAPSHOT_VERSION + 1;
+    assert!(matches!(
+        CellSnapshot::from_json(&future.to_json().unwrap()),
+        Err(CellSnapshotError::UnsupportedVersion(_))
+    ));
 
+    // build with the imported cells only
     let output = CellOutput::new_builder()
-        .capacity((120 * ONE_CKB).pack())
+        .capacity((200 * ONE_CKB).pack())
         .lock(receiver)
         .build();
-    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
-
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = AcpUnlocker::from(Box::new(signer) as Box<_>);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(script_unlocker));
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
 
-    let mut cell_collector = ctx.to_live_cells_context();
+    let mut cell_collector = imported.cell_collector();
+    let tx_dep_provider = imported.tx_dep_provider();
     let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &tx_dep_provider,
+            &balancer,
+            &unlockers,
+        )
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 1);
     assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 2);
-    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[1].len(), 0);
     ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the cells are spent by the balancer until the collector is reset
+    let query = CellQueryOptions::new_lock(sender);
+    assert!(cell_collector
+        .collect_live_cells(&query, false)
+        .unwrap()
+        .0
+        .is_empty());
+    cell_collector.reset();
+    assert_eq!(
+        cell_collector
+            .collect_live_cells(&query, false)
+            .unwrap()
+            .0
+            .len(),
+        2
+    );
 }
 
 #[test]
-fn test_transfer_to_acp() {
-    let data_hash = H256::from(blake2b_256(ACP_BIN));
+fn test_build_unlocked_with_policy() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = Script::new_builder()
-        .code_hash(data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
-        .build();
-    let ctx = init_context(
-        vec![(ACP_BIN, true)],
-        vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
-            (receiver.clone(), Some(99 * ONE_CKB)),
-        ],
-    );
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT2_ARG, ACCOUNT3_ARG], 0, 2).unwrap();
+    let receiver = build_multisig_script(&cfg);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
 
-    let acp_receiver = AcpTransferReceiver::new(receiver.clone(), 150 * ONE_CKB);
-    let builder = AcpTransferBuilder::new(vec![acp_receiver]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::from(vec![0u8; 100]))]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
-
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer1 = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let sighash_unlocker = AcpUnlocker::from(Box::new(signer1) as Box<_>);
-    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
     );
-    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(acp_unlocker));
 
-    let mut cell_collector = ctx.to_live_cells_context();
+    // the change output is checked too
+    let policy = OutputPolicy::new()
+        .max_data_size(64)
+        .allow_lock(ScriptId::new_type(SIGHASH_TYPE_HASH.clone()));
+    let err = builder
+        .build_unlocked_with_policy(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+            &policy,
+        )
+        .unwrap_err();
+    match err {
+        TxBuilderError::OutputPolicy(OutputPolicyError(violations)) => assert_eq!(
+            violations,
+            vec![
+                PolicyViolation::DataTooLarge {
+                    output_index: 0,
+                    size: 100,
+                    max_size: 64
+                },
+                PolicyViolation::LockNotAllowed {
+                    output_index: 0,
+                    lock: receiver
+                },
+            ]
+        ),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    let policy = OutputPolicy::new()
+        .max_data_size(100)
+        .allow_lock(ScriptId::new_type(SIGHASH_TYPE_HASH.clone()))
+        .allow_lock(ScriptId::new_type(MULTISIG_TYPE_HASH.clone()));
     let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .build_unlocked_with_policy(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+            &policy,
+        )
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 3);
-    let input_cells = vec![
-        CellOutput::new_builder()
-            .capacity((99 * ONE_CKB).pack())
-            .lock(receiver.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity((200 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let acp_output = CellOutput::new_builder()
-        .capacity(((99 + 150) * ONE_CKB).pack())
-        .lock(receiver)
-        .build();
-    assert_eq!(tx.output(0).unwrap(), acp_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses.len(), 3);
-    assert_eq!(witnesses[0].len(), 0);
-    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[2].len(), 0);
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_cheque_claim() {
-    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+fn test_donation_guard() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
     let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(vec![9u8; 32]).pack())
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
         .build();
-    let mut ctx = init_context(
-        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
-        vec![
-            (receiver.clone(), Some(100 * ONE_CKB)),
-            (receiver.clone(), Some(200 * ONE_CKB)),
-        ],
+    // the change output is forgotten, 180 CKB go to the miners
+    let tx = TransactionBuilder::default()
+        .input(ctx.inputs[0].input.clone())
+        .output(output.clone())
+        .output_data(Bytes::new().pack())
+        .build();
+    match DonationGuard::default().check(&tx, &ctx) {
+        Err(DonationError::FeeTooLarge { fee, max_fee }) => {
+            assert_eq!(fee, 180 * ONE_CKB);
+            assert_eq!(max_fee, 10 * ONE_CKB);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        DonationGuard::warn_only(ONE_CKB).check(&tx, &ctx).unwrap(),
+        180 * ONE_CKB
     );
 
-    let receiver_input = CellInput::new(random_out_point(), 0);
-    let receiver_output = CellOutput::new_builder()
-        .capacity((200 * ONE_CKB).pack())
-        .lock(receiver.clone())
-        .type_(Some(type_script.clone()).pack())
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let receiver_data = Bytes::from(1000u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(
-        receiver_input.clone(),
-        receiver_output.clone(),
-        receiver_data,
-        None,
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
     );
+    let (tx, locked_groups) = builder
+        .build_unlocked_with_donation_guard(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+            &DonationGuard::default(),
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
 
-    let cheque_input = CellInput::new(random_out_point(), 0);
-    let cheque_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(cheque_script)
-        .type_(Some(type_script).pack())
-        .build();
-    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(
-        cheque_input.clone(),
-        cheque_output.clone(),
-        cheque_data,
-        None,
-    );
+#[test]
+fn test_sign_cobuild_witness() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
 
-    let builder = ChequeClaimBuilder::new(vec![cheque_input], receiver_input, sender.clone());
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(receiver.clone(), placeholder_witness.clone(), FEE_RATE);
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
+        .unwrap();
+    let cobuild_witness = CobuildSighashWitness::new_sighash_all_only(Bytes::from(vec![0u8; 65]));
+    let mut witnesses = tx.witnesses().into_iter().collect::<Vec<_>>();
+    witnesses[0] = cobuild_witness.to_bytes().pack();
+    let tx = tx.as_advanced_builder().set_witnesses(witnesses).build();
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_signer = SecpSighashScriptSigner::new(Box::new(signer.clone()));
+    let mut script_group = ScriptGroup::from_lock_script(&build_sighash_script(ACCOUNT1_ARG));
+    script_group.input_indices.push(0);
+    assert!(matches!(
+        script_signer.sign_tx(&tx, &script_group),
+        Err(ScriptSignError::UnsupportedWitnessLayout(
+            WitnessLayoutKind::SighashAllOnly
+        ))
+    ));
 
-    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
-    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
-    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>)),
     );
+    let (signed_tx, locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    let witness = signed_tx.witnesses().get(0).unwrap().raw_data();
+    assert_eq!(
+        detect_witness_layout(&witness),
+        WitnessLayoutKind::SighashAllOnly
+    );
+    let input_cells = ctx
+        .get_cells_with_data(&tx.input_pts_iter().collect::<Vec<_>>())
+        .unwrap();
+    let message = cobuild_signing_message(&tx, None, &input_cells);
+    let expected_seal = signer
+        .sign(ACCOUNT1_ARG.as_bytes(), &message, true, &tx)
+        .unwrap();
+    assert_eq!(
+        CobuildSighashWitness::parse(&witness).unwrap().seal,
+        expected_seal
+    );
+
+    // forcing the WitnessArgs layout reports the cobuild witness
     unlockers.insert(
-        ScriptId::new_data1(cheque_data_hash),
-        Box::new(cheque_unlocker),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(
+            SecpSighashUnlocker::from(Box::new(signer) as Box<_>)
+                .with_witness_layout(WitnessLayoutMode::WitnessArgs),
+        ),
     );
+    assert!(unlock_tx(tx, &ctx, &unlockers).is_err());
+}
+
+#[test]
+fn test_sign_with_domain_separator() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
 
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
     let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
         .unwrap();
 
-    assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 3);
-    assert_eq!(tx.inputs().len(), 3);
-    let input_cells = vec![
-        cheque_output,
-        receiver_output.clone(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(receiver.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
-    }
-    assert_eq!(tx.outputs().len(), 3);
-    assert_eq!(tx.output(0).unwrap(), receiver_output);
-    let sender_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(sender)
+    let mainnet = h256!("0x92b197aa1fba0f63633922c61c92375c9c074a93e85963554f5499fe1450d0e5");
+    let testnet = h256!("0x10639e0895502b5688a6be8cf69460d76541bfa4821629d86d62ba0aae3f9606");
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_signer =
+        SecpSighashScriptSigner::new(Box::new(signer.clone())).with_domain_separator(&testnet);
+    let mut script_group = ScriptGroup::from_lock_script(&sender);
+    script_group.input_indices.push(0);
+    let signed_tx = script_signer.sign_tx(&tx, &script_group).unwrap();
+    let signature = WitnessArgs::from_slice(&signed_tx.witnesses().get(0).unwrap().raw_data())
+        .unwrap()
+        .lock()
+        .to_opt()
+        .unwrap()
+        .raw_data();
+
+    let zero_lock = Bytes::from(vec![0u8; 65]);
+    let verify = |genesis_hash: &H256| {
+        verify_domain_separated_signature(
+            &tx,
+            &script_group,
+            zero_lock.clone(),
+            genesis_hash,
+            &signature,
+            &ACCOUNT1_ARG,
+        )
+        .unwrap()
+    };
+    assert!(verify(&testnet));
+    assert!(!verify(&mainnet));
+
+    // the plain signature is not bound to any chain
+    let plain_tx = SecpSighashScriptSigner::new(Box::new(signer))
+        .sign_tx(&tx, &script_group)
+        .unwrap();
+    assert_ne!(plain_tx.witnesses(), signed_tx.witnesses());
+}
+
+#[test]
+fn test_sign_groups_with_missing_witnesses() {
+    let locks = [ACCOUNT1_ARG, ACCOUNT2_ARG, ACCOUNT1_ARG, ACCOUNT3_ARG]
+        .iter()
+        .map(|arg| build_sighash_script(arg.clone()))
+        .collect::<Vec<_>>();
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let inputs = locks
+        .iter()
+        .map(|lock| {
+            let out_point = random_out_point();
+            ctx.add_simple_live_cell(out_point.clone(), lock.clone(), Some(100 * ONE_CKB));
+            CellInput::new(out_point, 0)
+        })
+        .collect::<Vec<_>>();
+    let output = CellOutput::new_builder()
+        .capacity((390 * ONE_CKB).pack())
+        .lock(locks[0].clone())
         .build();
-    assert_eq!(tx.output(1).unwrap(), sender_output);
-    assert_eq!(tx.output(2).unwrap().lock(), receiver);
-    let expected_outputs_data = vec![
-        Bytes::from((1000u128 + 500u128).to_le_bytes().to_vec()),
-        Bytes::default(),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
+    let base_tx = TransactionBuilder::default()
+        .cell_dep(ctx.resolve(&locks[0]).unwrap())
+        .inputs(inputs)
+        .output(output)
+        .output_data(Bytes::default().pack())
+        .build();
+
+    let groups = vec![vec![0, 2], vec![1], vec![3]]
         .into_iter()
-        .map(|d| d.raw_data())
+        .map(|input_indices| {
+            let mut group = ScriptGroup::from_lock_script(&locks[input_indices[0]]);
+            group.input_indices = input_indices;
+            group
+        })
         .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data())
+    let signers = [ACCOUNT1_KEY, ACCOUNT2_KEY, ACCOUNT3_KEY]
+        .iter()
+        .map(|key| {
+            let key = secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap();
+            SecpSighashScriptSigner::new(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+                key,
+            ])))
+        })
         .collect::<Vec<_>>();
+
+    // the missing witnesses are hashed as empty ones, same as padded
+    let zero_lock = Bytes::from(vec![0u8; 65]);
+    let mut witnesses = Vec::new();
+    pad_group_witnesses(&mut witnesses, &groups[0]);
     assert_eq!(witnesses.len(), 3);
-    assert_eq!(witnesses[0].len(), 0);
-    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
-    assert_eq!(witnesses[2].len(), 0);
+    let padded_tx = base_tx
+        .as_advanced_builder()
+        .set_witnesses(witnesses)
+        .build();
+    assert_eq!(
+        generate_message(&base_tx, &groups[0], zero_lock.clone()).unwrap(),
+        generate_message(&padded_tx, &groups[0], zero_lock).unwrap()
+    );
+
+    // the public helpers map the groups the same way as the built-in signers
+    assert_eq!(ensure_witness_capacity(&base_tx, &groups[0]).len(), 3);
+    assert_eq!(ensure_witness_capacity(&padded_tx, &groups[1]).len(), 3);
+    assert_eq!(witness_index_for_group(&base_tx, &groups[2]).unwrap(), 3);
+    let mut out_of_bound = groups[2].clone();
+    out_of_bound.input_indices = vec![4];
+    assert!(matches!(
+        witness_index_for_group(&base_tx, &out_of_bound),
+        Err(ScriptSignError::InputIndexOutOfBound(4, 4))
+    ));
+    out_of_bound.input_indices.clear();
+    assert!(matches!(
+        witness_index_for_group(&base_tx, &out_of_bound),
+        Err(ScriptSignError::EmptyScriptGroup)
+    ));
+
+    // inputs outnumber witnesses: signing the group of input 3 pads the
+    // witness of input 2 after the group of inputs 0 and 2 is signed
+    let mut tx = base_tx.clone();
+    for idx in [0, 2, 1] {
+        tx = signers[idx].sign_tx(&tx, &groups[idx]).unwrap();
+    }
+    assert_eq!(tx.witnesses().len(), 4);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // witnesses outnumber inputs: the witness not covered by any input is
+    // signed by every group
+    let tx = base_tx
+        .as_advanced_builder()
+        .set_witnesses(vec![Bytes::default().pack(); 5])
+        .build();
+    let mut witnesses = tx.witnesses().into_iter().collect::<Vec<_>>();
+    witnesses[4] = Bytes::from(vec![1u8; 10]).pack();
+    let mut tx = tx.as_advanced_builder().set_witnesses(witnesses).build();
+    for idx in [2, 1, 0] {
+        tx = signers[idx].sign_tx(&tx, &groups[idx]).unwrap();
+    }
+    assert_eq!(tx.witnesses().len(), 5);
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
+/// Covers the 4 bytes header of the outter witnesses, not the proof after it
+struct ProofHeaderOnly;
+
+impl OuterWitnessStrategy for ProofHeaderOnly {
+    fn covered_bytes<'w>(
+        &self,
+        _tx: &TransactionView,
+        _index: usize,
+        witness: &'w [u8],
+    ) -> Option<&'w [u8]> {
+        Some(&witness[..witness.len().min(4)])
+    }
+}
+
 #[test]
-fn test_cheque_withdraw() {
-    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
-    let sender = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
-    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(vec![9u8; 32]).pack())
+fn test_outer_witness_strategy() {
+    let lock = build_sighash_script(ACCOUNT1_ARG);
+    let mut script_group = ScriptGroup::from_lock_script(&lock);
+    script_group.input_indices.push(0);
+    let with_outer_witness = |proof: &[u8]| {
+        TransactionBuilder::default()
+            .input(CellInput::new(random_out_point(), 0))
+            .witness(Bytes::default().pack())
+            .witness(Bytes::from(proof.to_vec()).pack())
+            .build()
+    };
+    let tx = with_outer_witness(&[1, 2, 3, 4, 5, 6]);
+    let zero_lock = Bytes::from(vec![0u8; 65]);
+    let message = |tx: &TransactionView, strategy: &dyn OuterWitnessStrategy| {
+        generate_message_with_strategy(
+            tx,
+            &script_group,
+            zero_lock.clone(),
+            &Blake2bBackend::default(),
+            strategy,
+        )
+        .unwrap()
+    };
+
+    // covering all of them is the default
+    assert_eq!(
+        message(&tx, &CoverAllOuterWitnesses),
+        generate_message(&tx, &script_group, zero_lock.clone()).unwrap()
+    );
+    let without_outer_witness = tx
+        .as_advanced_builder()
+        .set_witnesses(vec![Bytes::default().pack()])
         .build();
-    let mut ctx = init_context(
-        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
-        vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-        ],
+    assert_eq!(
+        message(&tx, &SkipOuterWitnesses),
+        message(&without_outer_witness, &CoverAllOuterWitnesses)
     );
 
-    let cheque_out_point = random_out_point();
-    let cheque_input = CellInput::new(cheque_out_point.clone(), CHEQUE_CELL_SINCE);
-    let cheque_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(cheque_script)
-        .type_(Some(type_script).pack())
+    // only the header is covered, the proof can be replaced after signing
+    let other_proof = tx
+        .as_advanced_builder()
+        .set_witnesses(vec![
+            Bytes::default().pack(),
+            Bytes::from(vec![1, 2, 3, 4, 9]).pack(),
+        ])
         .build();
-    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(cheque_input, cheque_output.clone(), cheque_data, None);
-
-    let builder = ChequeWithdrawBuilder::new(vec![cheque_out_point], sender.clone(), None);
-    let placeholder_witness = WitnessArgs::new_builder()
-        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+    let other_header = tx
+        .as_advanced_builder()
+        .set_witnesses(vec![
+            Bytes::default().pack(),
+            Bytes::from(vec![9, 2, 3, 4, 5, 6]).pack(),
+        ])
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    assert_eq!(
+        message(&tx, &ProofHeaderOnly),
+        message(&other_proof, &ProofHeaderOnly)
+    );
+    assert_ne!(
+        message(&tx, &ProofHeaderOnly),
+        message(&other_header, &ProofHeaderOnly)
+    );
+    assert_ne!(
+        message(&tx, &CoverAllOuterWitnesses),
+        message(&other_proof, &CoverAllOuterWitnesses)
+    );
 
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer =
+        SecpSighashScriptSigner::new(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+            key,
+        ])))
+        .with_outer_witness_strategy(Arc::new(ProofHeaderOnly));
+    let signed_tx = signer.sign_tx(&tx, &script_group).unwrap();
+    let signed_other_proof = signer.sign_tx(&other_proof, &script_group).unwrap();
+    assert_eq!(
+        signed_tx.witnesses().get(0),
+        signed_other_proof.witnesses().get(0)
+    );
+}
+
+#[test]
+fn test_placeholder_lock_len() {
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
     let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
-    let cheque_unlocker =
-        ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Withdraw));
-    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
-    unlockers.insert(
-        ScriptId::new_type(SIGHASH_TYPE_HASH),
-        Box::new(sighash_unlocker),
-    );
-    unlockers.insert(
-        ScriptId::new_data1(cheque_data_hash),
-        Box::new(cheque_unlocker),
+    assert_eq!(
+        sighash_unlocker.placeholder_lock_len(ACCOUNT1_ARG.as_bytes()),
+        Some(65)
     );
 
-    let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
-        .unwrap();
+    let cfg =
+        MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG, ACCOUNT3_ARG], 0, 2).unwrap();
+    let multisig_unlocker =
+        SecpMultisigUnlocker::from((Box::new(signer.clone()) as Box<_>, cfg.clone()));
+    let lock_len = cfg
+        .placeholder_witness()
+        .lock()
+        .to_opt()
+        .unwrap()
+        .raw_data()
+        .len();
+    assert_eq!(lock_len, 4 + 20 * 3 + 65 * 2);
+    assert_eq!(
+        multisig_unlocker.placeholder_lock_len(cfg.hash160().as_bytes()),
+        Some(lock_len)
+    );
 
-    assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 3);
-    assert_eq!(tx.inputs().len(), 2);
-    let input_cells = vec![
-        cheque_output.clone(),
-        CellOutput::new_builder()
-            .capacity((100 * ONE_CKB).pack())
-            .lock(sender.clone())
-            .build(),
-    ];
-    for (idx, out_point) in tx.input_pts_iter().enumerate() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let sender_output = cheque_output.as_builder().lock(sender.clone()).build();
-    assert_eq!(tx.output(0).unwrap(), sender_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![
-        Bytes::from(500u128.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![0, placeholder_witness.as_slice().len()]);
-    ctx.verify(tx, FEE_RATE).unwrap();
+    let preimage = Bytes::from_static(b"htlc-secret");
+    let claim = HtlcAction::Claim {
+        preimage: preimage.clone(),
+    };
+    let htlc_unlocker = HtlcUnlocker::from((Box::new(signer) as Box<_>, claim));
+    let htlc_args = HtlcArgs::new(&preimage, ACCOUNT1_ARG, ACCOUNT2_ARG, 0);
+    assert_eq!(
+        htlc_unlocker.placeholder_lock_len(&htlc_args.to_bytes()),
+        Some(65 + preimage.len())
+    );
 }
 
 #[test]
-fn test_dao_deposit() {
+fn test_plan_transfer() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
     let ctx = init_context(
         Vec::new(),
         vec![
@@ -644,226 +1021,263 @@ fn test_dao_deposit() {
         ],
     );
 
-    let deposit_receiver = DaoDepositReceiver::new(sender.clone(), 120 * ONE_CKB);
-    let builder = DaoDepositBuilder::new(vec![deposit_receiver]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
-
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
     let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
     let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
         ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
-        Box::new(script_unlocker),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
     );
 
     let mut cell_collector = ctx.to_live_cells_context();
+    let plan = builder
+        .plan(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert_eq!(plan.inputs.len(), 2);
+    assert_eq!(plan.outputs, vec![(output, Bytes::default())]);
+    assert_eq!(plan.change.len(), 1);
+    assert_eq!(plan.change[0].0.lock(), sender);
+    assert_eq!(plan.required_signers.len(), 1);
+    assert_eq!(plan.required_signers[0].input_indices, vec![0, 1]);
+    assert_eq!(
+        plan.change_capacity() + plan.fee,
+        300 * ONE_CKB - 120 * ONE_CKB
+    );
+
+    // planning doesn't reserve the cells, the real build selects the same ones
     let (tx, locked_groups) = builder
         .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let deposit_output = CellOutput::new_builder()
-        .capacity((120 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
-        .build();
-    assert_eq!(tx.output(0).unwrap(), deposit_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![Bytes::from(vec![0u8; 8]), Bytes::default()];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    assert_eq!(
+        tx.input_pts_iter().collect::<Vec<_>>(),
+        plan.inputs
+            .iter()
+            .map(|(out_point, _, _)| out_point.clone())
+            .collect::<Vec<_>>()
+    );
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_dao_prepare() {
+fn test_build_balanced_traced() {
     let sender = build_sighash_script(ACCOUNT1_ARG);
-    let mut ctx = init_context(
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
         Vec::new(),
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let deposit_point = (5, 5, 1000);
-    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
-    let deposit_point =
-        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
-
-    let deposit_input = CellInput::new(random_out_point(), 0);
-    let deposit_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
         .build();
-    let deposit_header = HeaderBuilder::default()
-        .epoch(deposit_point.full_value().pack())
-        .number(deposit_number.pack())
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let deposit_block_hash = deposit_header.hash();
-    ctx.add_live_cell(
-        deposit_input.clone(),
-        deposit_output.clone(),
-        Bytes::from(vec![0u8; 8]),
-        Some(deposit_block_hash.clone()),
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+
+    let mut cell_collector = OffchainLockCellCollector {
+        inner: ctx.to_live_cells_context(),
+        offchain: OffchainCellCollector::default(),
+    };
+    let (result, trace) = builder.build_balanced_traced(
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        &balancer,
+        &unlockers,
+        1000,
     );
-    ctx.add_header(deposit_header);
+    let tx = result.unwrap();
+    assert_eq!(trace.balanced_tx(), Some(tx.clone()));
+    assert!(trace.events.iter().any(|event| matches!(
+        event,
+        TraceEvent::CellsOffered {
+            apply_changes: true,
+            ..
+        }
+    )));
+    // the caller's collector keeps the changes of the traced build
+    let query = CellQueryOptions::new_lock(build_sighash_script(ACCOUNT1_ARG));
+    let (cells, _) = cell_collector.collect_live_cells(&query, false).unwrap();
+    assert!(cells.iter().all(|cell| tx
+        .input_pts_iter()
+        .all(|out_point| out_point != cell.out_point)));
+    // only the inputs of the built transaction are locked, at the tip
+    let inputs = tx
+        .input_pts_iter()
+        .map(|out_point| {
+            (
+                (out_point.tx_hash().unpack(), out_point.index().unpack()),
+                1000,
+            )
+        })
+        .collect::<HashMap<(H256, u32), u64>>();
+    assert_eq!(cell_collector.offchain.locked_cells, inputs);
 
-    let builder = DaoPrepareBuilder::from(vec![deposit_input]);
-    let placeholder_witness = WitnessArgs::new_builder()
-        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+    // replay the serialized trace without the original providers
+    let trace: BuildTrace = serde_json::from_str(&serde_json::to_string(&trace).unwrap()).unwrap();
+    let replay_context = trace.replay_context();
+    let mut replay_collector = trace.replay_cell_collector();
+    let replayed_tx = builder
+        .build_balanced(
+            &mut replay_collector,
+            &replay_context,
+            &replay_context,
+            &replay_context,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert_eq!(replayed_tx, tx);
+
+    // a failed build keeps the error in the trace
+    let mut cell_collector = ctx.to_live_cells_context();
+    let poor_balancer = CapacityBalancer::new_simple(
+        build_sighash_script(ACCOUNT3_ARG),
+        WitnessArgs::default(),
+        FEE_RATE,
+    );
+    let (result, trace) = builder.build_balanced_traced(
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+        &poor_balancer,
+        &unlockers,
+        1000,
+    );
+    assert!(result.is_err());
+    assert!(trace.error().is_some());
+}
+
+#[test]
+fn test_multi_lock_sweep() {
+    let sighash_lock = build_sighash_script(ACCOUNT1_ARG);
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let acp_lock = Script::new_builder()
+        .code_hash(acp_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
         .build();
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 2).unwrap();
+    let multisig_lock = build_multisig_script(&cfg);
+    let destination = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sighash_lock.clone(), Some(100 * ONE_CKB)),
+            (acp_lock.clone(), Some(200 * ONE_CKB)),
+            (multisig_lock.clone(), Some(300 * ONE_CKB)),
+            (multisig_lock.clone(), Some(400 * ONE_CKB)),
+        ],
+    );
 
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let builder = MultiLockSweepBuilder::new(vec![
+        SweepLock::Sighash(sighash_lock),
+        SweepLock::Acp(acp_lock),
+        SweepLock::Multisig(multisig_lock, cfg.clone()),
+    ]);
+    let balancer = builder.balancer(destination.clone(), FEE_RATE);
+    let keys = [ACCOUNT1_KEY, ACCOUNT2_KEY]
+        .iter()
+        .map(|key| secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap())
+        .collect::<Vec<_>>();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
         ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
-        Box::new(script_unlocker),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>)),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(acp_data_hash),
+        Box::new(AcpUnlocker::from(Box::new(signer.clone()) as Box<_>)),
+    );
+    unlockers.insert(
+        ScriptId::new_type(MULTISIG_TYPE_HASH.clone()),
+        Box::new(SecpMultisigUnlocker::from((
+            Box::new(signer) as Box<_>,
+            cfg,
+        ))),
     );
 
     let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+    let balanced_tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
         .unwrap();
+    assert_eq!(balanced_tx.inputs().len(), 4);
+    assert_eq!(balanced_tx.outputs().len(), 1);
+    assert_eq!(balanced_tx.output(0).unwrap().lock(), destination);
 
-    assert!(locked_groups.is_empty());
+    let report = builder.signer_requirements(&balanced_tx, &ctx).unwrap();
+    let requirements = report
+        .requirements
+        .iter()
+        .map(|req| (req.input_indices.clone(), req.capacity, req.threshold()))
+        .collect::<Vec<_>>();
     assert_eq!(
-        tx.header_deps().into_iter().collect::<Vec<_>>(),
-        vec![deposit_block_hash]
+        requirements,
+        vec![
+            (vec![0], 100 * ONE_CKB, 1),
+            (vec![1], 200 * ONE_CKB, 1),
+            (vec![2, 3], 700 * ONE_CKB, 2),
+        ]
     );
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    assert_eq!(tx.output(0).unwrap(), deposit_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![
-        Bytes::from(deposit_number.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    assert_eq!(report.signers(), vec![ACCOUNT1_ARG, ACCOUNT2_ARG]);
+    assert_eq!(report.total_capacity(), 1000 * ONE_CKB);
+
+    let (tx, locked_groups) = unlock_tx(balanced_tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_dao_withdraw() {
-    let sender = build_sighash_script(ACCOUNT1_ARG);
-    let mut ctx = init_context(
+fn test_deposit_sweep() {
+    let account_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let mut registry = DepositRegistry::new(
+        DepositKeyDeriver::new(account_key),
+        DepositLockKind::Sighash,
+    );
+    let invoice1_lock = registry.register("invoice-1").unwrap();
+    let invoice2_lock = registry.register("invoice-2").unwrap();
+    let treasury = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
         Vec::new(),
         vec![
-            (sender.clone(), Some(100 * ONE_CKB)),
-            (sender.clone(), Some(200 * ONE_CKB)),
-            (sender.clone(), Some(300 * ONE_CKB)),
+            (invoice1_lock.clone(), Some(100 * ONE_CKB)),
+            (invoice1_lock.clone(), Some(200 * ONE_CKB)),
+            (invoice2_lock.clone(), Some(300 * ONE_CKB)),
         ],
     );
 
-    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
-    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
-    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
-    let deposit_point =
-        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
-    let prepare_point =
-        EpochNumberWithFraction::new(prepare_point.0, prepare_point.1, prepare_point.2);
-    let deposit_header = HeaderBuilder::default()
-        .epoch(deposit_point.full_value().pack())
-        .number(deposit_number.pack())
-        .dao(pack_dao_data(
-            10_000_000_000_123_456,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-        ))
-        .build();
-    let prepare_header = HeaderBuilder::default()
-        .epoch(prepare_point.full_value().pack())
-        .number(prepare_number.pack())
-        .dao(pack_dao_data(
-            10_000_000_001_123_456,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-        ))
-        .build();
-    let deposit_block_hash = deposit_header.hash();
-    let prepare_block_hash = prepare_header.hash();
-
-    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
-    let since = Since::new(
-        SinceType::EpochNumberWithFraction,
-        unlock_point.full_value(),
-        false,
-    );
-    let prepare_out_point = random_out_point();
-    let prepare_input = CellInput::new(prepare_out_point.clone(), since.value());
-    let prepare_output = CellOutput::new_builder()
-        .capacity((220 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(build_dao_script()).pack())
-        .build();
-    ctx.add_live_cell(
-        prepare_input,
-        prepare_output.clone(),
-        Bytes::from(deposit_number.to_le_bytes().to_vec()),
-        Some(prepare_block_hash.clone()),
-    );
-    ctx.add_header(deposit_header.clone());
-    ctx.add_header(prepare_header.clone());
-
-    let placeholder_witness = WitnessArgs::new_builder()
-        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
-        .build();
-    let withdraw_item = DaoWithdrawItem::new(prepare_out_point, Some(placeholder_witness.clone()));
-    let withdraw_receiver = DaoWithdrawReceiver::LockScript {
-        script: sender.clone(),
-        fee_rate: None,
-    };
-    let builder = DaoWithdrawBuilder::new(vec![withdraw_item], withdraw_receiver);
-    let balancer =
-        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let deposits = registry.scan(&mut cell_collector).unwrap();
+    let invoice_ids = deposits
+        .iter()
+        .map(|deposit| deposit.invoice_id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(invoice_ids, vec!["invoice-1", "invoice-1", "invoice-2"]);
 
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let (builder, balancer) = registry
+        .sweep(&["invoice-1", "invoice-2"], treasury.clone(), FEE_RATE)
+        .unwrap();
+    let signer = registry.signer(&["invoice-1", "invoice-2"]).unwrap();
     let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
@@ -871,249 +1285,4352 @@ fn test_dao_withdraw() {
         Box::new(script_unlocker),
     );
 
-    let mut cell_collector = ctx.to_live_cells_context();
     let (tx, locked_groups) = builder
         .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(
-        tx.header_deps().into_iter().collect::<Vec<_>>(),
-        vec![deposit_block_hash, prepare_block_hash]
-    );
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    assert_eq!(tx.inputs().len(), 3);
+    assert_eq!(tx.outputs().len(), 1);
+    let output = tx.output(0).unwrap();
+    assert_eq!(output.lock(), treasury);
+    let output_capacity: u64 = output.capacity().unpack();
+    assert!(output_capacity > 599 * ONE_CKB && output_capacity < 600 * ONE_CKB);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+/// Count the collections applying changes, an overstating collector offers a
+/// single cell while reporting an unlimited capacity
+#[derive(Clone)]
+struct CountingCellCollector {
+    inner: LiveCellsContext,
+    collections: usize,
+    overstate: bool,
+}
+
+impl CountingCellCollector {
+    fn new(inner: LiveCellsContext, overstate: bool) -> CountingCellCollector {
+        CountingCellCollector {
+            inner,
+            collections: 0,
+            overstate,
+        }
     }
-    assert_eq!(tx.outputs().len(), 2);
-    let occupied_capacity = prepare_output
-        .occupied_capacity(Capacity::bytes(8).unwrap())
-        .unwrap()
-        .as_u64();
-    let expected_capacity = calculate_dao_maximum_withdraw4(
-        &deposit_header,
-        &prepare_header,
-        &prepare_output,
-        occupied_capacity,
-    );
-    let expected_output = prepare_output
-        .as_builder()
-        .capacity(expected_capacity.pack())
-        .type_(ScriptOpt::default())
-        .build();
-    assert_eq!(tx.output(0).unwrap(), expected_output);
-    assert_eq!(tx.output(1).unwrap().lock(), sender);
-    let expected_outputs_data = vec![Bytes::default(), Bytes::default()];
-    let outputs_data = tx
-        .outputs_data()
+}
+
+impl CellCollector for CountingCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        if apply_changes {
+            self.collections += 1;
+        }
+        if !self.overstate {
+            return self.inner.collect_live_cells(query, apply_changes);
+        }
+        let (mut cells, _) = self.inner.collect_live_cells(query, false)?;
+        cells.truncate(1);
+        if apply_changes {
+            for cell in &cells {
+                self.inner.lock_cell(cell.out_point.clone(), 0)?;
+            }
+        }
+        Ok((cells, u64::MAX))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+/// A `LiveCellsContext` also recording the locked cells in an
+/// `OffchainCellCollector`, the way the rpc backed collectors expire them
+#[derive(Clone)]
+struct OffchainLockCellCollector {
+    inner: LiveCellsContext,
+    offchain: OffchainCellCollector,
+}
+
+impl CellCollector for OffchainLockCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        self.inner.collect_live_cells(query, apply_changes)
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.offchain
+            .lock_cell(out_point.clone(), tip_block_number)?;
+        self.inner.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+#[test]
+fn test_balance_fee_bounds() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let provider_cells: Vec<Vec<u64>> = vec![
+        vec![100, 200, 300],
+        vec![1000],
+        vec![62; 10],
+        vec![181, 1, 1, 1],
+    ];
+    for fee_rate in [1000, 1001, 2345, 10000] {
+        for capacities in &provider_cells {
+            let live_cells = capacities
+                .iter()
+                .map(|capacity| (sender.clone(), Some(capacity * ONE_CKB)))
+                .collect();
+            let ctx = init_context(Vec::new(), live_cells);
+            let output = CellOutput::new_builder()
+                .capacity((120 * ONE_CKB).pack())
+                .lock(receiver.clone())
+                .build();
+            let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+            let balancer =
+                CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), fee_rate);
+
+            let mut cell_collector = ctx.to_live_cells_context();
+            let base_tx = builder
+                .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+                .unwrap();
+            let mut cell_collector = CountingCellCollector::new(cell_collector, false);
+            let tx =
+                balance_tx_capacity(&base_tx, &balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+                    .unwrap();
+            assert!(
+                cell_collector.collections <= 2,
+                "fee_rate: {}, capacities: {:?}, collections: {}",
+                fee_rate,
+                capacities,
+                cell_collector.collections
+            );
+
+            let tx_size = tx.data().as_reader().serialized_size_in_block() as u64;
+            let fee = tx_fee(tx.clone(), &ctx, &ctx).unwrap();
+            let min_fee = FeeRate::from_u64(fee_rate).fee(tx_size).as_u64();
+            let max_fee = FeeRate::from_u64(fee_rate + 1).fee(tx_size).as_u64();
+            assert!(
+                min_fee <= fee && fee <= max_fee,
+                "fee_rate: {}, capacities: {:?}, fee: {}, expected: [{}, {}]",
+                fee_rate,
+                capacities,
+                fee,
+                min_fee,
+                max_fee
+            );
+            ctx.verify(tx, fee_rate).unwrap();
+        }
+    }
+
+    // a collector serving less than it reports, one cell per collection
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(ONE_CKB)); 200]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let build = |balancer: &CapacityBalancer| {
+        let mut cell_collector = CountingCellCollector::new(ctx.to_live_cells_context(), true);
+        let base_tx = builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        let result = balance_tx_capacity(&base_tx, balancer, &mut cell_collector, &ctx, &ctx, &ctx);
+        (result, cell_collector.collections)
+    };
+    // unlimited by default, the small cells still balance
+    let (result, collections) = build(&balancer);
+    assert!(result.is_ok());
+    assert!(collections > 120);
+
+    // the limit is reached on the collection after the last allowed one
+    for max_served_rounds in [1, 2, collections - 1] {
+        balancer.set_limits(BalanceLimits {
+            max_served_rounds: Some(max_served_rounds),
+            ..Default::default()
+        });
+        let (result, served) = build(&balancer);
+        assert!(matches!(
+            result,
+            Err(BalanceTxCapacityError::NotConverged(n)) if n == max_served_rounds
+        ));
+        assert_eq!(served, max_served_rounds);
+    }
+    balancer.set_limits(BalanceLimits {
+        max_served_rounds: Some(collections),
+        ..Default::default()
+    });
+    assert!(build(&balancer).0.is_ok());
+}
+
+#[test]
+fn test_small_change_policy() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(150 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let build = |balancer: &CapacityBalancer| {
+        let mut cell_collector = ctx.to_live_cells_context();
+        let base_tx = builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        balance_tx_capacity_with_breakdown(
+            &base_tx,
+            balancer,
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+        )
+    };
+
+    // 30 CKB is left, which can not hold a change cell
+    assert!(matches!(
+        build(&balancer),
+        Err(BalanceTxCapacityError::CapacityNotEnough(_))
+    ));
+
+    balancer.set_small_change_policy(SmallChangePolicy::AddToFee);
+    let (tx, fee_breakdown) = build(&balancer).unwrap();
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(fee_breakdown.fee, 30 * ONE_CKB);
+    assert_eq!(
+        fee_breakdown.change,
+        ChangeDecision::AddedToFee(30 * ONE_CKB - fee_breakdown.min_fee)
+    );
+    assert_eq!(tx_fee(tx.clone(), &ctx, &ctx).unwrap(), fee_breakdown.fee);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    balancer.set_max_fee(Some(ONE_CKB));
+    assert!(matches!(
+        build(&balancer),
+        Err(BalanceTxCapacityError::ForceSmallChangeAsFeeFailed(_))
+    ));
+
+    balancer.set_small_change_policy(SmallChangePolicy::AddToOutput(0));
+    let (tx, fee_breakdown) = build(&balancer).unwrap();
+    let receiver_capacity: u64 = tx.output(0).unwrap().capacity().unpack();
+    assert_eq!(receiver_capacity, 150 * ONE_CKB - fee_breakdown.min_fee);
+    assert_eq!(fee_breakdown.fee, fee_breakdown.min_fee);
+    assert_eq!(
+        fee_breakdown.change,
+        ChangeDecision::AddedToOutput {
+            index: 0,
+            capacity: 30 * ONE_CKB - fee_breakdown.min_fee,
+        }
+    );
+    assert_eq!(tx_fee(tx.clone(), &ctx, &ctx).unwrap(), fee_breakdown.fee);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    balancer.set_small_change_policy(SmallChangePolicy::AddToOutput(1));
+    assert!(matches!(
+        build(&balancer),
+        Err(BalanceTxCapacityError::SmallChangeOutputNotFound(1))
+    ));
+}
+
+#[test]
+fn test_balance_limits() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let ctx = init_context(
+        Vec::new(),
+        (0..10)
+            .map(|_| (sender.clone(), Some(100 * ONE_CKB)))
+            .collect(),
+    );
+    let output = CellOutput::new_builder()
+        .capacity((450 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT2_ARG))
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let build = |balancer: &CapacityBalancer| {
+        let mut cell_collector = ctx.to_live_cells_context();
+        let base_tx = builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        balance_tx_capacity(&base_tx, balancer, &mut cell_collector, &ctx, &ctx, &ctx)
+    };
+    let tx = build(&balancer).unwrap();
+    assert_eq!(tx.inputs().len(), 6);
+
+    balancer.set_limits(BalanceLimits {
+        max_iterations: None,
+        max_inputs: Some(3),
+        ..Default::default()
+    });
+    match build(&balancer) {
+        Err(BalanceTxCapacityError::CannotBalance {
+            collected,
+            shortfall,
+            largest_skipped,
+        }) => {
+            assert_eq!(collected, 3);
+            assert!(shortfall > 150 * ONE_CKB && shortfall < 250 * ONE_CKB);
+            assert_eq!(largest_skipped.len(), 3);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    balancer.set_limits(BalanceLimits {
+        max_iterations: Some(0),
+        ..Default::default()
+    });
+    match build(&balancer) {
+        Err(BalanceTxCapacityError::CannotBalance {
+            collected,
+            shortfall,
+            largest_skipped,
+        }) => {
+            assert_eq!(collected, 0);
+            assert!(shortfall > 450 * ONE_CKB);
+            assert_eq!(largest_skipped.len(), REPORTED_SKIPPED_CELLS);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    balancer.set_limits(BalanceLimits {
+        max_iterations: Some(1),
+        max_inputs: Some(6),
+        ..Default::default()
+    });
+    assert!(build(&balancer).is_ok());
+}
+
+#[test]
+fn test_fee_payer() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let sponsor = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sponsor.clone(), Some(100 * ONE_CKB)),
+        ],
+    );
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let mut balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+    balancer.set_fee_payer(Some(CapacityProvider::new_simple(vec![(
+        sponsor.clone(),
+        placeholder_witness,
+    )])));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, fee_breakdown) = balance_tx_capacity_with_breakdown(
+        &builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap(),
+        &balancer,
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+    let outputs = tx.outputs().into_iter().collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 3);
+    // the sender only pays the transferred capacity
+    assert_eq!(outputs[1].lock(), sender);
+    assert_eq!(Unpack::<u64>::unpack(&outputs[1].capacity()), 80 * ONE_CKB);
+    assert_eq!(outputs[2].lock(), sponsor);
+    assert_eq!(
+        Unpack::<u64>::unpack(&outputs[2].capacity()),
+        100 * ONE_CKB - fee_breakdown.fee
+    );
+
+    // the sender and the sponsor sign their own script groups
+    let unlockers_of = |key: &H256| {
+        let key = secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap();
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+        );
+        unlockers
+    };
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers_of(&ACCOUNT1_KEY)).unwrap();
+    assert_eq!(locked_groups.len(), 1);
+    assert_eq!(locked_groups[0].script, sponsor);
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers_of(&ACCOUNT2_KEY)).unwrap();
+    assert_eq!(locked_groups.len(), 1);
+    assert_eq!(locked_groups[0].script, sender);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_missing_signer_report() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
+        .unwrap();
+
+    let err = unlock_tx_fully(tx.clone(), &ctx, &HashMap::default()).unwrap_err();
+    let report = match err {
+        TxBuilderError::MissingSigner(report) => report,
+        err => panic!("unexpected error: {}", err),
+    };
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].reason, LockedReason::NoMatchingSigner);
+    assert_eq!(report.groups[0].input_indices, vec![0]);
+    let args = ACCOUNT1_ARG.as_bytes();
+    assert_eq!(
+        report.groups[0].args_preview(),
+        format!(
+            "0x{}..{}",
+            hex::encode(&args[..4]),
+            hex::encode(&args[18..])
+        )
+    );
+    assert!(report.to_string().contains("SecpSighashUnlocker"));
+
+    // a sighash unlocker of another key
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    match unlock_tx_fully(tx, &ctx, &unlockers).unwrap_err() {
+        TxBuilderError::MissingSigner(report) => {
+            assert_eq!(report.groups.len(), 1);
+            assert_eq!(report.groups[0].reason, LockedReason::ArgsNotMatched);
+            assert_eq!(report.groups[0].script_id, ScriptId::from(&sender));
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn test_cached_template() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let other_builder = CapacityTransferBuilder::new(vec![(
+        output.as_builder().lock(sender.clone()).build(),
+        Bytes::default(),
+    )]);
+    assert_eq!(
+        builder.template_key(),
+        CapacityTransferBuilder::new(builder.outputs.clone()).template_key()
+    );
+    assert_ne!(builder.template_key(), other_builder.template_key());
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+
+    let cache = TemplateCache::default();
+    let cached_builder = CachedTxBuilder::new(&builder, &cache);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let mut inputs = Vec::new();
+    for _ in 0..2 {
+        let (tx, locked_groups) = cached_builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        assert_eq!(tx.output(0).unwrap().lock(), receiver);
+        inputs.push(tx.inputs().get(0).unwrap());
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+    assert_ne!(inputs[0], inputs[1]);
+    assert_eq!(cache.len(), 1);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+}
+
+#[test]
+fn test_fill_witness_type() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(vec![(ALWAYS_SUCCESS_BIN, false)], Vec::new());
+    let type_script = Script::new_builder()
+        .code_hash(H256::from(blake2b_256(ALWAYS_SUCCESS_BIN)).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .build();
+    let typed_output = CellOutput::new_builder()
+        .capacity((300 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let input = CellInput::new(random_out_point(), 0);
+    ctx.add_live_cell(input.clone(), typed_output.clone(), Bytes::new(), None);
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let tx = TransactionBuilder::default()
+        .cell_dep(ctx.resolve(&sender).unwrap())
+        .cell_dep(ctx.resolve(&type_script).unwrap())
+        .input(input)
+        .output(
+            CellOutput::new_builder()
+                .capacity((150 * ONE_CKB).pack())
+                .lock(sender.clone())
+                .build(),
+        )
+        .output(
+            typed_output
+                .as_builder()
+                .capacity((149 * ONE_CKB).pack())
+                .build(),
+        )
+        .outputs_data(vec![Bytes::new().pack(); 2])
+        .witness(placeholder_witness.as_bytes().pack())
+        .build();
+    let type_group = gen_script_groups(&tx, &ctx)
+        .unwrap()
+        .type_groups
+        .into_values()
+        .next()
+        .unwrap();
+    assert_eq!(type_group.input_indices, vec![0]);
+    assert_eq!(type_group.output_indices, vec![1]);
+
+    let input_type = Bytes::from(vec![1u8; 4]);
+    let output_type = Bytes::from(vec![2u8; 4]);
+    let tx = fill_witness_type(
+        &tx,
+        &type_group,
+        TypeWitnessField::InputType,
+        input_type.clone(),
+    )
+    .unwrap();
+    let tx = fill_witness_type(
+        &tx,
+        &type_group,
+        TypeWitnessField::OutputType,
+        output_type.clone(),
+    )
+    .unwrap();
+    // the lock placeholder sharing the first witness is kept
+    let witness = WitnessArgs::from_slice(&tx.witnesses().get(0).unwrap().raw_data()).unwrap();
+    assert_eq!(witness.lock(), placeholder_witness.lock());
+    assert_eq!(
+        witness.input_type().to_opt().unwrap().raw_data(),
+        input_type
+    );
+    let witness = WitnessArgs::from_slice(&tx.witnesses().get(1).unwrap().raw_data()).unwrap();
+    assert!(witness.lock().is_none());
+    assert_eq!(
+        witness.output_type().to_opt().unwrap().raw_data(),
+        output_type
+    );
+    // setting the same data again is fine, other data conflicts
+    assert!(fill_witness_type(&tx, &type_group, TypeWitnessField::InputType, input_type).is_ok());
+    assert!(matches!(
+        fill_witness_type(&tx, &type_group, TypeWitnessField::InputType, output_type),
+        Err(UnlockError::WitnessFieldConflict(0))
+    ));
+
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_funding_request() {
+    let payer = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(payer.clone(), Some(200 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let mut cell_collector = ctx.to_live_cells_context();
+
+    // the receiver builds the outputs
+    let request = FundingRequest::build(&builder, &mut cell_collector, &ctx, &ctx, &ctx).unwrap();
+    assert_eq!(request.required_capacity(), 120 * ONE_CKB);
+    let json = serde_json::to_string(&request).unwrap();
+    let request: FundingRequest = serde_json::from_str(&json).unwrap();
+
+    // the payer funds it
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(payer.clone(), placeholder_witness, FEE_RATE);
+    let mut tampered = request.clone();
+    tampered.tx = request
+        .tx_view()
+        .as_advanced_builder()
+        .set_outputs(vec![output
+            .clone()
+            .as_builder()
+            .capacity((150 * ONE_CKB).pack())
+            .build()])
+        .build()
+        .data()
+        .into();
+    assert!(fund_transaction(&tampered, &balancer, &mut cell_collector, &ctx, &ctx, &ctx).is_err());
+
+    let tx = fund_transaction(&request, &balancer, &mut cell_collector, &ctx, &ctx, &ctx).unwrap();
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), payer);
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // a request with inputs is rejected
+    let funded = FundingRequest::new(
+        &TransactionBuilder::default()
+            .input(CellInput::new(random_out_point(), 0))
+            .build(),
+    );
+    assert!(funded.is_err());
+}
+
+#[test]
+fn test_transfer_capacity_overflow() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(100 * ONE_CKB))]);
+
+    let large_amount: u64 = u64::MAX;
+    let output = CellOutput::new_builder()
+        .capacity((large_amount).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    let mut cell_collector = ctx.to_live_cells_context();
+    let res = builder.build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers);
+    assert!(res.is_err());
+    assert!(res.unwrap_err().to_string().contains("capacity not enough"));
+}
+
+#[test]
+fn test_transfer_type_id_collision() {
+    let owner = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(Vec::new(), vec![(owner.clone(), Some(100 * ONE_CKB))]);
+    let build_type_id = |args: [u8; 32]| {
+        Script::new_builder()
+            .code_hash(TYPE_ID_CODE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(args.to_vec()).pack())
+            .build()
+    };
+    let existing_out_point = random_out_point();
+    let existing_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(owner.clone())
+        .type_(Some(build_type_id([1u8; 32])).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(existing_out_point.clone(), 0),
+        existing_output,
+        Bytes::from(vec![0u8; 8]),
+        None,
+    );
+
+    let build_output = |args: [u8; 32]| {
+        CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(owner.clone())
+            .type_(Some(build_type_id(args)).pack())
+            .build()
+    };
+    let mut cell_collector = ctx.to_live_cells_context();
+    let builder = CapacityTransferBuilder::new(vec![(build_output([1u8; 32]), Bytes::default())]);
+    match builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx) {
+        Err(TxBuilderError::TypeIdCollision(script, out_point)) => {
+            assert_eq!(script, build_type_id([1u8; 32]));
+            assert_eq!(out_point, existing_out_point);
+        }
+        other => panic!("unexpected result: {:?}", other.map(|tx| tx.hash())),
+    }
+    for args in [[2u8; 32], [0u8; 32]] {
+        let builder = CapacityTransferBuilder::new(vec![(build_output(args), Bytes::default())]);
+        assert!(builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .is_ok());
+    }
+}
+
+#[test]
+fn test_transfer_from_multisig() {
+    let lock_args = vec![
+        ACCOUNT0_ARG.clone(),
+        ACCOUNT1_ARG.clone(),
+        ACCOUNT2_ARG.clone(),
+    ];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
+
+    let sender = build_multisig_script(&cfg);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = cfg.placeholder_witness();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(account0_key, cfg.clone());
+    let mut tx = builder
+        .build_balanced(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    let mut locked_groups = None;
+    for key in [account0_key, account2_key] {
+        let unlockers = build_multisig_unlockers(key, cfg.clone());
+        let (new_tx, new_locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
+        tx = new_tx;
+        locked_groups = Some(new_locked_groups);
+    }
+
+    assert_eq!(locked_groups, Some(Vec::new()));
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 1);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 2);
+    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[1].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_estimate_cycles_with_dummy_signatures() {
+    let lock_args = vec![
+        ACCOUNT0_ARG.clone(),
+        ACCOUNT1_ARG.clone(),
+        ACCOUNT2_ARG.clone(),
+    ];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
+    let multisig_sender = build_multisig_script(&cfg);
+    let sighash_sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (multisig_sender.clone(), Some(100 * ONE_CKB)),
+            (sighash_sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let sighash_placeholder = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_with_provider(
+        FEE_RATE,
+        CapacityProvider::new_simple(vec![
+            (multisig_sender, cfg.placeholder_witness()),
+            (sighash_sender, sighash_placeholder),
+        ]),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &HashMap::default(),
+        )
+        .unwrap();
+    assert_eq!(tx.inputs().len(), 2);
+
+    // the multisig group is partially signed
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let (tx, _) = unlock_tx(
+        tx,
+        &ctx,
+        &build_multisig_unlockers(account0_key, cfg.clone()),
+    )
+    .unwrap();
+    let estimate = estimate_cycles_with_dummy_signatures(&tx, &ctx).unwrap();
+    assert_eq!(estimate.dummy_signed_groups.len(), 2);
+    let dummy_signed = dummy_sign_tx(&tx, &ctx).unwrap();
+
+    // sign for real
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let (tx, _) = unlock_tx(tx, &ctx, &build_multisig_unlockers(account2_key, cfg)).unwrap();
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(
+            Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+                account1_key,
+            ])) as Box<_>,
+        )),
+    );
+    let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+
+    // the dummy signatures have the same length as the real ones
+    let witnesses_len = |tx: &TransactionView| {
+        tx.witnesses()
+            .into_iter()
+            .map(|witness| witness.raw_data().len())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(witnesses_len(&dummy_signed.tx), witnesses_len(&tx));
+    let cycles = ctx.verify_scripts(tx.clone()).unwrap();
+    let diff = cycles.max(estimate.cycles) - cycles.min(estimate.cycles);
+    assert!(
+        diff * 100 < cycles,
+        "cycles: {}, estimated: {}",
+        cycles,
+        estimate.cycles
+    );
+
+    // a fully signed transaction is verified as it is
+    let estimate = estimate_cycles_with_dummy_signatures(&tx, &ctx).unwrap();
+    assert!(estimate.dummy_signed_groups.is_empty());
+    assert_eq!(estimate.cycles, cycles);
+}
+
+#[test]
+fn test_multisig_rotation() {
+    let old_cfg = MultisigConfig::new_with(
+        vec![
+            ACCOUNT0_ARG.clone(),
+            ACCOUNT1_ARG.clone(),
+            ACCOUNT2_ARG.clone(),
+        ],
+        0,
+        2,
+    )
+    .unwrap();
+    let new_cfg =
+        MultisigConfig::new_with(vec![ACCOUNT2_ARG.clone(), ACCOUNT3_ARG.clone()], 0, 1).unwrap();
+    let old_lock = build_multisig_script(&old_cfg);
+    let new_lock = build_multisig_script(&new_cfg);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (old_lock.clone(), Some(100 * ONE_CKB)),
+            (old_lock.clone(), Some(200 * ONE_CKB)),
+            (old_lock.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let mut builder = MultisigRotationBuilder::new(old_cfg.clone(), new_cfg.clone(), FEE_RATE);
+    builder.max_inputs = 2;
+    assert_eq!(builder.old_lock_script(), old_lock);
+    assert_eq!(builder.new_lock_script(), new_lock);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let rotation_txs = builder
+        .build("rekey", &mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(rotation_txs.len(), 2);
+    assert_eq!(
+        rotation_txs
+            .iter()
+            .map(|rotation_tx| rotation_tx.tx.inputs().len())
+            .collect::<Vec<_>>(),
+        vec![2, 1]
+    );
+
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    for rotation_tx in rotation_txs {
+        assert!(rotation_tx.old_signers_session.id.ends_with("-old"));
+        assert_eq!(
+            rotation_tx.old_signers_session.multisig_config,
+            Some(old_cfg.clone())
+        );
+        assert_eq!(
+            rotation_tx.new_signers_session.multisig_config,
+            Some(new_cfg.clone())
+        );
+        assert!(rotation_tx.new_signers_session.reserved_inputs.is_empty());
+
+        let mut tx = rotation_tx.old_signers_session.tx_view();
+        assert_eq!(tx.outputs().len(), 1);
+        assert_eq!(tx.output(0).unwrap().lock(), new_lock);
+        for key in [account0_key, account1_key] {
+            let unlockers = build_multisig_unlockers(key, old_cfg.clone());
+            let (new_tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+            tx = new_tx;
+        }
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+}
+
+#[test]
+fn test_vote_builders() {
+    let always_success_data_hash = H256::from(blake2b_256(ALWAYS_SUCCESS_BIN));
+    let scheme = VoteScheme::new(
+        ScriptId::new_data1(always_success_data_hash.clone()),
+        VoteLayout::Molecule,
+    );
+    let proposal = Script::new_builder()
+        .code_hash(always_success_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from("proposal-1").pack())
+        .build();
+    let voter = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, true)],
+        vec![(voter.clone(), Some(300 * ONE_CKB))],
+    );
+    let proposal_cell = random_out_point();
+    ctx.add_cell_dep(
+        CellDep::new_builder()
+            .out_point(proposal_cell.clone())
+            .dep_type(DepType::Code.into())
+            .build(),
+        CellOutput::new_builder()
+            .type_(Some(proposal.clone()).pack())
+            .build(),
+        Bytes::from("proposal body"),
+        None,
+    );
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(voter.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+
+    let builder = VoteBuilder::new(scheme.clone(), proposal.clone(), voter.clone(), 1)
+        .proposal_cell(proposal_cell.clone())
+        .memo(Bytes::from("aye"))
+        .capacity(200 * ONE_CKB);
+    let (tx, locked_groups) = builder
+        .build_unlocked(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let (vote_output, vote_data) = tx.output_with_data(0).unwrap();
+    assert_eq!(
+        scheme.parse_vote(&vote_output, &vote_data),
+        Some(builder.vote())
+    );
+    ctx.verify(tx.clone(), FEE_RATE).unwrap();
+
+    // another voter picks choice 2 with less capacity
+    let vote_cell = OutPoint::new(tx.hash(), 0);
+    ctx.add_live_cell(
+        CellInput::new(vote_cell.clone(), 0),
+        vote_output,
+        vote_data,
+        None,
+    );
+    let other_vote = VoteBuilder::new(
+        scheme.clone(),
+        proposal.clone(),
+        build_sighash_script(ACCOUNT2_ARG),
+        2,
+    );
+    let other_output = CellOutput::new_builder()
+        .capacity((100 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT2_ARG))
+        .type_(Some(scheme.vote_type_script(&proposal)).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        other_output,
+        scheme.layout.encode(&other_vote.vote()),
+        None,
+    );
+    let tally = tally_votes(&mut ctx.to_live_cells_context(), &scheme, &proposal).unwrap();
+    assert_eq!(tally.choices[&1].capacity, 200 * ONE_CKB);
+    assert_eq!(tally.choices[&2].votes, 1);
+    assert_eq!(tally.winner(), Some(1));
+
+    let amend = VoteAmendBuilder::new(scheme.clone(), vote_cell, 2).proposal_cell(proposal_cell);
+    let (tx, locked_groups) = amend
+        .build_unlocked(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let (amended_output, amended_data) = tx.output_with_data(0).unwrap();
+    let amended = scheme.parse_vote(&amended_output, &amended_data).unwrap();
+    assert_eq!(amended.choice, 2);
+    assert!(amended.memo.is_empty());
+    assert_eq!(
+        Unpack::<u64>::unpack(&amended_output.capacity()),
+        200 * ONE_CKB
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_script_migration() {
+    let always_success_data_hash = H256::from(blake2b_256(ALWAYS_SUCCESS_BIN));
+    let migration = ScriptMigration::new(
+        ScriptId::new_data1(always_success_data_hash),
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+    );
+    let old_lock = migration.deprecated_script(Bytes::from(ACCOUNT1_ARG.as_bytes().to_vec()));
+    let new_lock = build_sighash_script(ACCOUNT1_ARG);
+    assert_eq!(migration.migrate(&old_lock), Some(new_lock.clone()));
+    assert_eq!(migration.migrate(&new_lock), None);
+
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, true)],
+        vec![
+            (old_lock.clone(), Some(100 * ONE_CKB)),
+            (old_lock.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let data_output = CellOutput::new_builder()
+        .capacity((150 * ONE_CKB).pack())
+        .lock(old_lock.clone())
+        .build();
+    let data = Bytes::from_static(b"data");
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        data_output,
+        data.clone(),
+        None,
+    );
+
+    let mut builder = MigrationBuilder::new(
+        vec![migration],
+        vec![(old_lock, WitnessArgs::default())],
+        FEE_RATE,
+    );
+    builder.max_inputs = 2;
+    let mut cell_collector = ctx.to_live_cells_context();
+    let report = builder
+        .dry_run(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(report.cells.len(), 3);
+    assert_eq!(report.fees.len(), 2);
+    assert!(report.total_fee() > 0);
+
+    // the dry run does not lock the cells
+    let txs = builder
+        .build(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(txs.len(), 2);
+    let fees = txs
+        .iter()
+        .map(|tx| tx_fee(tx.clone(), &ctx, &ctx).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(fees, report.fees);
+    // the data cell is migrated with the largest plain cell paying the fee
+    let outputs = txs[0].outputs_with_data_iter().collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[0].0.lock(), new_lock);
+    assert_eq!(outputs[0].1, data);
+    for tx in txs {
+        assert!(tx
+            .outputs()
+            .into_iter()
+            .all(|output| output.lock() == new_lock));
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+}
+
+#[test]
+fn test_migrate_deprecated_address_locks() {
+    let always_success_data_hash = H256::from(blake2b_256(ALWAYS_SUCCESS_BIN));
+    let old_address = Address::new(
+        NetworkType::Testnet,
+        AddressPayload::new_full(
+            ScriptHashType::Data,
+            always_success_data_hash.pack(),
+            Bytes::from(ACCOUNT1_ARG.as_bytes().to_vec()),
+        ),
+        false,
+    );
+    let new_address = canonical_address(&old_address);
+    assert!(is_deprecated_address(&old_address));
+    assert!(!is_deprecated_address(&new_address));
+    assert!(new_address.is_new());
+    assert_eq!(new_address.payload().hash_type(), ScriptHashType::Data1);
+    // the short format is deprecated but its lock is canonical
+    let short_address = Address::new(
+        NetworkType::Testnet,
+        AddressPayload::from_pubkey_hash(ACCOUNT1_ARG),
+        false,
+    );
+    assert!(is_deprecated_address(&short_address));
+    assert_eq!(
+        Script::from(&canonical_address(&short_address)),
+        Script::from(&short_address)
+    );
+
+    let old_lock = Script::from(&old_address);
+    let new_lock = Script::from(&new_address);
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, true)],
+        vec![
+            (old_lock.clone(), Some(100 * ONE_CKB)),
+            (old_lock.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let cell_dep = ctx
+        .cell_dep_map
+        .get(&ScriptId::new_data1(always_success_data_hash.clone()))
+        .cloned()
+        .unwrap();
+    ctx.add_cell_dep_map(ScriptId::new_data(always_success_data_hash), cell_dep);
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let deprecated = scan_deprecated_locks(
+        &mut cell_collector,
+        &[old_address, new_address, short_address],
+    )
+    .unwrap();
+    assert_eq!(deprecated.len(), 1);
+    assert_eq!(deprecated[0].lock, old_lock);
+    assert_eq!(deprecated[0].canonical_lock, new_lock);
+    assert_eq!(deprecated[0].capacity(), 300 * ONE_CKB);
+
+    let builder =
+        MigrationBuilder::from_deprecated_locks(&deprecated, WitnessArgs::default(), FEE_RATE);
+    let txs = builder
+        .build(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].inputs().len(), 2);
+    assert!(txs[0]
+        .outputs()
+        .into_iter()
+        .all(|output| output.lock() == new_lock));
+    ctx.verify(txs[0].clone(), FEE_RATE).unwrap();
+}
+
+/// A hardware wallet which is not connected
+struct UnavailableSigner(H160);
+
+impl Signer for UnavailableSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        id == self.0.as_bytes()
+    }
+
+    fn sign(
+        &self,
+        _id: &[u8],
+        _message: &[u8],
+        _recoverable: bool,
+        _tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        Err(SignerError::Other(anyhow::anyhow!("device not connected")))
+    }
+}
+
+#[test]
+fn test_multisig_composite_signer() {
+    let lock_args = vec![
+        ACCOUNT0_ARG.clone(),
+        ACCOUNT1_ARG.clone(),
+        ACCOUNT2_ARG.clone(),
+    ];
+    let cfg = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
+    let sender = build_multisig_script(&cfg);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT3_ARG))
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), cfg.placeholder_witness(), FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let tx = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &build_multisig_unlockers(account0_key, cfg.clone()),
+        )
+        .unwrap();
+
+    // key 0 is on an unavailable hardware wallet, key 2 is a raw key
+    let composite = CompositeSigner::new(vec![
+        Box::new(UnavailableSigner(ACCOUNT0_ARG.clone())),
+        Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+            account2_key,
+        ])),
+    ]);
+    let script_signer = SecpMultisigScriptSigner::new(Box::new(composite), cfg.clone());
+    let mut script_group = ScriptGroup::new(&sender, ScriptGroupType::Lock);
+    script_group.input_indices = vec![0];
+    let (tx, failures) = script_signer.sign_tx_partial(&tx, &script_group).unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, ACCOUNT0_ARG);
+    // the plain signing fails on any backend error
+    assert!(ScriptSigner::sign_tx(&script_signer, &tx, &script_group).is_err());
+
+    // all matched backends failed
+    let unavailable = SecpMultisigScriptSigner::new(
+        Box::new(CompositeSigner::new(vec![Box::new(UnavailableSigner(
+            ACCOUNT0_ARG.clone(),
+        ))])),
+        cfg.clone(),
+    );
+    assert!(unavailable.sign_tx_partial(&tx, &script_group).is_err());
+
+    // collect the missing signature when the wallet is back
+    let (tx, locked_groups) =
+        unlock_tx(tx, &ctx, &build_multisig_unlockers(account0_key, cfg)).unwrap();
+    assert!(locked_groups.is_empty());
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_from_acp() {
+    let data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sender = Script::new_builder()
+        .code_hash(data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT1_ARG.0.to_vec()).pack())
+        .build();
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output.clone(), Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = AcpUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(script_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 1);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 2);
+    assert_eq!(witnesses[0].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[1].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_transfer_to_acp() {
+    let data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = Script::new_builder()
+        .code_hash(data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let ctx = init_context(
+        vec![(ACP_BIN, true)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+            (receiver.clone(), Some(99 * ONE_CKB)),
+        ],
+    );
+
+    let acp_receiver = AcpTransferReceiver::new(receiver.clone(), 150 * ONE_CKB);
+    let builder = AcpTransferBuilder::new(vec![acp_receiver]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer1 = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let sighash_unlocker = AcpUnlocker::from(Box::new(signer1) as Box<_>);
+    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(ScriptId::new_data1(data_hash), Box::new(acp_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 3);
+    let input_cells = vec![
+        CellOutput::new_builder()
+            .capacity((99 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let acp_output = CellOutput::new_builder()
+        .capacity(((99 + 150) * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    assert_eq!(tx.output(0).unwrap(), acp_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 3);
+    assert_eq!(witnesses[0].len(), 0);
+    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[2].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_cheque_claim() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (receiver.clone(), Some(100 * ONE_CKB)),
+            (receiver.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let receiver_data = Bytes::from(1000u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(
+        receiver_input.clone(),
+        receiver_output.clone(),
+        receiver_data,
+        None,
+    );
+
+    let cheque_input = CellInput::new(random_out_point(), 0);
+    let cheque_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(cheque_script)
+        .type_(Some(type_script).pack())
+        .build();
+    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(
+        cheque_input.clone(),
+        cheque_output.clone(),
+        cheque_data,
+        None,
+    );
+
+    let builder = ChequeClaimBuilder::new(vec![cheque_input], receiver_input, sender.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(receiver.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.inputs().len(), 3);
+    let input_cells = vec![
+        cheque_output,
+        receiver_output.clone(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 3);
+    assert_eq!(tx.output(0).unwrap(), receiver_output);
+    let sender_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender)
+        .build();
+    assert_eq!(tx.output(1).unwrap(), sender_output);
+    assert_eq!(tx.output(2).unwrap().lock(), receiver);
+    let expected_outputs_data = vec![
+        Bytes::from((1000u128 + 500u128).to_le_bytes().to_vec()),
+        Bytes::default(),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses.len(), 3);
+    assert_eq!(witnesses[0].len(), 0);
+    assert_eq!(witnesses[1].len(), placeholder_witness.as_slice().len());
+    assert_eq!(witnesses[2].len(), 0);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_sdk_claim_cheque_and_transfer_udt() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (receiver.clone(), Some(100 * ONE_CKB)),
+            (receiver.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+    let receiver_out_point = random_out_point();
+    ctx.add_live_cell(
+        CellInput::new(receiver_out_point.clone(), 0),
+        CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build(),
+        Bytes::from(1000u128.to_le_bytes().to_vec()),
+        None,
+    );
+    let cheque_out_point = random_out_point();
+    ctx.add_live_cell(
+        CellInput::new(cheque_out_point.clone(), 0),
+        CellOutput::new_builder()
+            .capacity((220 * ONE_CKB).pack())
+            .lock(cheque_script.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build(),
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
+    );
+
+    let genesis_block: json_types::BlockView = serde_json::from_str(GENESIS_JSON).unwrap();
+    let mut sdk = CkbSdk::from_genesis(NetworkInfo::testnet(), &genesis_block.into())
+        .unwrap()
+        .with_fee_rate(FEE_RATE);
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    sdk.add_sighash_keys(vec![account2_key]);
+    for script in vec![&cheque_script, &type_script] {
+        sdk.cell_dep_resolver_mut().insert(
+            ScriptId::from(script),
+            ctx.resolve(script).unwrap(),
+            String::new(),
+        );
+    }
+    sdk.add_cheque_unlocker(ScriptId::from(&cheque_script), ChequeAction::Claim);
+
+    let builder = cheque_claim_builder(
+        vec![cheque_out_point],
+        receiver_out_point.clone(),
+        sender.clone(),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = sdk
+        .build_with(&builder, &receiver, &mut cell_collector, &ctx, &ctx)
+        .unwrap();
+    assert_eq!(tx.witnesses().len(), 3);
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    let builder = udt_transfer_builder(&receiver, type_script, sender, 300);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = sdk
+        .build_with(&builder, &receiver, &mut cell_collector, &ctx, &ctx)
+        .unwrap();
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|data| data.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data[0], Bytes::from(700u128.to_le_bytes().to_vec()));
+    assert_eq!(outputs_data[1], Bytes::from(300u128.to_le_bytes().to_vec()));
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_sdk_keeps_custom_sighash_unlocker() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(300 * ONE_CKB))]);
+
+    let genesis_block: json_types::BlockView = serde_json::from_str(GENESIS_JSON).unwrap();
+    let mut sdk = CkbSdk::from_genesis(NetworkInfo::testnet(), &genesis_block.into())
+        .unwrap()
+        .with_fee_rate(FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    sdk.add_unlocker(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+        WitnessArgs::new_builder()
+            .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+            .build(),
+    );
+    // the keys of another account don't replace the custom unlocker
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    sdk.add_sighash_keys(vec![account2_key]);
+
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let tx = sdk
+        .build_with(&builder, &sender, &mut cell_collector, &ctx, &ctx)
+        .unwrap();
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_escrow_claim_with_cheque_script() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let escrow = EscrowScript::new_cheque(ScriptId::new_data1(cheque_data_hash.clone()));
+    let escrow_lock = escrow.lock_script(&receiver, &sender, None).unwrap();
+    assert_eq!(
+        escrow_lock,
+        build_cheque_script(&sender, &receiver, cheque_data_hash.clone())
+    );
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![(receiver.clone(), Some(100 * ONE_CKB))],
+    );
+
+    let receiver_input = random_out_point();
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(receiver_input.clone(), 0),
+        receiver_output.clone(),
+        Bytes::from(1000u128.to_le_bytes().to_vec()),
+        None,
+    );
+    let mut escrow_inputs = Vec::new();
+    for amount in [300u128, 200u128] {
+        let escrow_input = random_out_point();
+        let escrow_output = CellOutput::new_builder()
+            .capacity((162 * ONE_CKB).pack())
+            .lock(escrow_lock.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        ctx.add_live_cell(
+            CellInput::new(escrow_input.clone(), 0),
+            escrow_output,
+            Bytes::from(amount.to_le_bytes().to_vec()),
+            None,
+        );
+        escrow_inputs.push(escrow_input);
+    }
+
+    // the sender must match the escrow args
+    let builder = EscrowClaimBuilder::new(
+        escrow.clone(),
+        escrow_inputs.clone(),
+        receiver_input.clone(),
+        receiver.clone(),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    let builder = EscrowClaimBuilder::new(escrow, escrow_inputs, receiver_input, sender.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(receiver.clone(), placeholder_witness, FEE_RATE);
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker = ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Claim));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.output(0).unwrap(), receiver_output);
+    assert_eq!(
+        tx.outputs_data().get(0).unwrap().raw_data(),
+        Bytes::from(1500u128.to_le_bytes().to_vec())
+    );
+    let sender_output = CellOutput::new_builder()
+        .capacity((324 * ONE_CKB).pack())
+        .lock(sender)
+        .build();
+    assert_eq!(tx.output(1).unwrap(), sender_output);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_htlc_claim_and_refund() {
+    let always_success_data_hash = H256::from(blake2b_256(ALWAYS_SUCCESS_BIN));
+    let htlc_script_id = ScriptId::new_data1(always_success_data_hash);
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let preimage = Bytes::from_static(b"htlc-secret");
+    let timeout_since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        EpochNumberWithFraction::new(6, 0, 1).full_value(),
+        true,
+    )
+    .value();
+    let htlc_args = HtlcArgs::new(&preimage, ACCOUNT2_ARG, ACCOUNT1_ARG, timeout_since);
+    let mut ctx = init_context(
+        vec![(ALWAYS_SUCCESS_BIN, true)],
+        vec![(receiver.clone(), Some(100 * ONE_CKB))],
+    );
+    let htlc_input = random_out_point();
+    let htlc_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(htlc_args.lock_script(&htlc_script_id))
+        .build();
+    ctx.add_live_cell(
+        CellInput::new(htlc_input.clone(), 0),
+        htlc_output,
+        Bytes::default(),
+        None,
+    );
+
+    let wrong_claim = HtlcAction::Claim {
+        preimage: Bytes::from_static(b"guess"),
+    };
+    let builder = HtlcBuilder::new(
+        htlc_script_id.clone(),
+        vec![htlc_input.clone()],
+        wrong_claim,
+        receiver.clone(),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    assert!(matches!(
+        builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx),
+        Err(TxBuilderError::InvalidParameter(_))
+    ));
+
+    let claim = HtlcAction::Claim {
+        preimage: preimage.clone(),
+    };
+    let builder = HtlcBuilder::new(
+        htlc_script_id.clone(),
+        vec![htlc_input.clone()],
+        claim.clone(),
+        receiver.clone(),
+    );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(receiver.clone(), placeholder_witness, FEE_RATE);
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>)),
+    );
+    unlockers.insert(
+        htlc_script_id.clone(),
+        Box::new(HtlcUnlocker::from((
+            Box::new(signer.clone()) as Box<_>,
+            claim.clone(),
+        ))),
+    );
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let claim_since: u64 = tx.inputs().get(0).unwrap().since().unpack();
+    assert_eq!(claim_since, 0);
+    assert_eq!(tx.output(0).unwrap().lock(), receiver);
+
+    let witness_lock = WitnessArgs::from_slice(&tx.witnesses().get(0).unwrap().raw_data())
+        .unwrap()
+        .lock()
+        .to_opt()
+        .unwrap()
+        .raw_data();
+    assert_eq!(witness_lock.len(), 65 + preimage.len());
+    assert_eq!(&witness_lock[65..], preimage.as_ref());
+    let mut script_group = ScriptGroup::from_lock_script(&htlc_args.lock_script(&htlc_script_id));
+    script_group.input_indices.push(0);
+    let message = generate_message(&tx, &script_group, claim.zero_lock()).unwrap();
+    let expected_signature = signer
+        .sign(ACCOUNT2_ARG.as_bytes(), &message, true, &tx)
+        .unwrap();
+    assert_eq!(&witness_lock[0..65], expected_signature.as_ref());
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // the refund inputs wait for the timeout
+    let builder = HtlcBuilder::new(htlc_script_id, vec![htlc_input], HtlcAction::Refund, sender);
+    let base_tx = builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    let refund_since: u64 = base_tx.inputs().get(0).unwrap().since().unpack();
+    assert_eq!(refund_since, timeout_since);
+}
+
+#[test]
+fn test_cheque_claim_extreme_values() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+
+    let extreme_capacities = [0, 1, u64::MAX / 2 + 1, u64::MAX];
+    let extreme_amounts = [0, 1, u128::MAX / 2 + 1, u128::MAX];
+    let mut cases = Vec::new();
+    for capacity0 in extreme_capacities {
+        for capacity1 in extreme_capacities {
+            cases.push(([capacity0, capacity1], [1, 1]));
+        }
+    }
+    for amount0 in extreme_amounts {
+        for amount1 in extreme_amounts {
+            cases.push(([ONE_CKB, ONE_CKB], [amount0, amount1]));
+        }
+    }
+    let receiver_amount = 1u128;
+    for (capacities, amounts) in cases {
+        let mut ctx = init_context(vec![(CHEQUE_BIN, true), (SUDT_BIN, false)], Vec::new());
+        let receiver_input = CellInput::new(random_out_point(), 0);
+        let receiver_output = CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(receiver.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let receiver_data = Bytes::from(receiver_amount.to_le_bytes().to_vec());
+        ctx.add_live_cell(receiver_input.clone(), receiver_output, receiver_data, None);
+        let mut cheque_inputs = Vec::new();
+        for (capacity, amount) in capacities.iter().zip(amounts.iter()) {
+            let cheque_input = CellInput::new(random_out_point(), 0);
+            let cheque_output = CellOutput::new_builder()
+                .capacity(capacity.pack())
+                .lock(cheque_script.clone())
+                .type_(Some(type_script.clone()).pack())
+                .build();
+            let cheque_data = Bytes::from(amount.to_le_bytes().to_vec());
+            ctx.add_live_cell(cheque_input.clone(), cheque_output, cheque_data, None);
+            cheque_inputs.push(cheque_input);
+        }
+
+        let builder = ChequeClaimBuilder::new(cheque_inputs, receiver_input, sender.clone());
+        let mut cell_collector = ctx.to_live_cells_context();
+        let result = builder.build_base(&mut cell_collector, &ctx, &ctx, &ctx);
+        let capacity_overflow = capacities[0].checked_add(capacities[1]).is_none();
+        let amount_overflow = amounts[0]
+            .checked_add(amounts[1])
+            .and_then(|total| total.checked_add(receiver_amount))
+            .is_none();
+        match result {
+            Ok(tx) => {
+                assert!(!capacity_overflow && !amount_overflow);
+                let sender_capacity: u64 = tx.outputs().get(1).unwrap().capacity().unpack();
+                assert_eq!(sender_capacity, capacities[0] + capacities[1]);
+            }
+            Err(TxBuilderError::Capacity(_)) => assert!(capacity_overflow),
+            Err(TxBuilderError::AmountOverflow(..)) => {
+                assert!(!capacity_overflow && amount_overflow)
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn test_resolve_cell_deps() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let ctx = init_context(vec![(CHEQUE_BIN, true), (SUDT_BIN, false)], Vec::new());
+
+    let scripts = vec![
+        type_script.clone(),
+        sender.clone(),
+        cheque_script.clone(),
+        receiver,
+        type_script.clone(),
+    ];
+    assert_eq!(
+        ctx.resolve_batch(&scripts),
+        scripts
+            .iter()
+            .map(|script| ctx.resolve(script))
+            .collect::<Vec<_>>()
+    );
+    // sender and receiver share the sighash cell dep
+    let expected_cell_deps = vec![
+        ctx.resolve(&type_script).unwrap(),
+        ctx.resolve(&sender).unwrap(),
+        ctx.resolve(&cheque_script).unwrap(),
+    ];
+    assert_eq!(
+        resolve_cell_deps(&ctx, &scripts).unwrap(),
+        expected_cell_deps
+    );
+
+    let unknown_script = Script::new_builder()
+        .code_hash(H256([7u8; 32]).pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .build();
+    match resolve_cell_deps(&ctx, &[sender, unknown_script.clone()]) {
+        Err(TxBuilderError::ResolveCellDepFailed(script)) => assert_eq!(script, unknown_script),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_cheque_withdraw() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let cheque_data_hash = H256::from(blake2b_256(CHEQUE_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let cheque_script = build_cheque_script(&sender, &receiver, cheque_data_hash.clone());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(CHEQUE_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let cheque_out_point = random_out_point();
+    let cheque_input = CellInput::new(cheque_out_point.clone(), CHEQUE_CELL_SINCE);
+    let cheque_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(cheque_script)
+        .type_(Some(type_script).pack())
+        .build();
+    let cheque_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(cheque_input, cheque_output.clone(), cheque_data, None);
+
+    let builder = ChequeWithdrawBuilder::new(vec![cheque_out_point], sender.clone(), None);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>);
+    let cheque_unlocker =
+        ChequeUnlocker::from((Box::new(signer) as Box<_>, ChequeAction::Withdraw));
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(
+        ScriptId::new_data1(cheque_data_hash),
+        Box::new(cheque_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.inputs().len(), 2);
+    let input_cells = vec![
+        cheque_output.clone(),
+        CellOutput::new_builder()
+            .capacity((100 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+    ];
+    for (idx, out_point) in tx.input_pts_iter().enumerate() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0, input_cells[idx]);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let sender_output = cheque_output.as_builder().lock(sender.clone()).build();
+    assert_eq!(tx.output(0).unwrap(), sender_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![0, placeholder_witness.as_slice().len()]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_deposit() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_receiver = DaoDepositReceiver::new(sender.clone(), 120 * ONE_CKB);
+    let builder = DaoDepositBuilder::new(vec![deposit_receiver]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let deposit_output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), deposit_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![Bytes::from(vec![0u8; 8]), Bytes::default()];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_prepare() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let deposit_point = (5, 5, 1000);
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let deposit_point =
+        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
+
+    let deposit_input = CellInput::new(random_out_point(), 0);
+    let deposit_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .build();
+    let deposit_block_hash = deposit_header.hash();
+    ctx.add_live_cell(
+        deposit_input.clone(),
+        deposit_output.clone(),
+        Bytes::from(vec![0u8; 8]),
+        Some(deposit_block_hash.clone()),
+    );
+    ctx.add_header(deposit_header);
+
+    let builder = DaoPrepareBuilder::from(vec![deposit_input]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_block_hash]
+    );
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    assert_eq!(tx.output(0).unwrap(), deposit_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![
+        Bytes::from(deposit_number.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_withdraw() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let mut ctx = init_context(
+        Vec::new(),
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
+    let deposit_point =
+        EpochNumberWithFraction::new(deposit_point.0, deposit_point.1, deposit_point.2);
+    let prepare_point =
+        EpochNumberWithFraction::new(prepare_point.0, prepare_point.1, prepare_point.2);
+    let deposit_header = HeaderBuilder::default()
+        .epoch(deposit_point.full_value().pack())
+        .number(deposit_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_000_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_header = HeaderBuilder::default()
+        .epoch(prepare_point.full_value().pack())
+        .number(prepare_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_001_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let deposit_block_hash = deposit_header.hash();
+    let prepare_block_hash = prepare_header.hash();
+
+    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+    let since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        unlock_point.full_value(),
+        false,
+    );
+    let prepare_out_point = random_out_point();
+    let prepare_input = CellInput::new(prepare_out_point.clone(), since.value());
+    let prepare_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    ctx.add_live_cell(
+        prepare_input,
+        prepare_output.clone(),
+        Bytes::from(deposit_number.to_le_bytes().to_vec()),
+        Some(prepare_block_hash.clone()),
+    );
+    ctx.add_header(deposit_header.clone());
+    ctx.add_header(prepare_header.clone());
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let withdraw_item = DaoWithdrawItem::new(prepare_out_point, Some(placeholder_witness.clone()));
+    let withdraw_receiver = DaoWithdrawReceiver::LockScript {
+        script: sender.clone(),
+        fee_rate: None,
+    };
+    let builder = DaoWithdrawBuilder::new(vec![withdraw_item], withdraw_receiver);
+    let balancer =
+        CapacityBalancer::new_simple(sender.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(
+        tx.header_deps().into_iter().collect::<Vec<_>>(),
+        vec![deposit_block_hash, prepare_block_hash]
+    );
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), sender);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let occupied_capacity = prepare_output
+        .occupied_capacity(Capacity::bytes(8).unwrap())
+        .unwrap()
+        .as_u64();
+    let expected_capacity = calculate_dao_maximum_withdraw4(
+        &deposit_header,
+        &prepare_header,
+        &prepare_output,
+        occupied_capacity,
+    );
+    let expected_output = prepare_output
+        .as_builder()
+        .capacity(expected_capacity.pack())
+        .type_(ScriptOpt::default())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), expected_output);
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let expected_outputs_data = vec![Bytes::default(), Bytes::default()];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    let witness = placeholder_witness
+        .as_builder()
+        .input_type(Some(Bytes::from(vec![0u8; 8])).pack())
+        .build();
+    assert_eq!(witnesses_len, vec![witness.as_slice().len(), 0]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_dao_withdraw_from_multisig_with_since() {
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT1_ARG.clone()], 0, 1).unwrap();
+    let build_lock = |since: Since| {
+        let mut args = cfg.hash160().0.to_vec();
+        args.extend_from_slice(&since.value().to_le_bytes());
+        build_multisig_script(&cfg)
+            .as_builder()
+            .args(Bytes::from(args).pack())
+            .build()
+    };
+    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
+    let deposit_header = HeaderBuilder::default()
+        .epoch(EpochNumberWithFraction::new(5, 5, 1000).full_value().pack())
+        .number(deposit_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_000_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_header = HeaderBuilder::default()
+        .epoch(
+            EpochNumberWithFraction::new(184, 4, 1000)
+                .full_value()
+                .pack(),
+        )
+        .number(prepare_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_001_123_456,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let dao_since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        minimal_unlock_point(&deposit_header, &prepare_header).full_value(),
+        false,
+    );
+    let later_since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        EpochNumberWithFraction::new(200, 1, 2).full_value(),
+        false,
+    );
+    let earlier_since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        EpochNumberWithFraction::new(10, 0, 1).full_value(),
+        false,
+    );
+    let key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(key, cfg.clone());
+
+    for (lock_since, expected_since) in [(later_since, later_since), (earlier_since, dao_since)] {
+        let owner = build_lock(lock_since);
+        let mut ctx = init_context(Vec::new(), Vec::new());
+        let prepare_out_point = random_out_point();
+        ctx.add_live_cell(
+            CellInput::new(prepare_out_point.clone(), 0),
+            CellOutput::new_builder()
+                .capacity((220 * ONE_CKB).pack())
+                .lock(owner.clone())
+                .type_(Some(build_dao_script()).pack())
+                .build(),
+            Bytes::from(deposit_number.to_le_bytes().to_vec()),
+            Some(prepare_header.hash()),
+        );
+        ctx.add_header(deposit_header.clone());
+        ctx.add_header(prepare_header.clone());
+
+        let builder = DaoWithdrawBuilder::new(
+            vec![DaoWithdrawItem::new(
+                prepare_out_point,
+                Some(cfg.placeholder_witness()),
+            )],
+            DaoWithdrawReceiver::LockScript {
+                script: owner,
+                fee_rate: Some(FeeRate::from_u64(FEE_RATE)),
+            },
+        );
+        let mut cell_collector = ctx.to_live_cells_context();
+        let tx = builder
+            .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+            .unwrap();
+        let since: u64 = tx.inputs().get(0).unwrap().since().unpack();
+        assert_eq!(since, expected_since.value());
+        let (tx, locked_groups) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+
+    // a block number since can not be combined with the dao epoch since
+    let owner = build_lock(Since::new(SinceType::BlockNumber, 100, false));
+    let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
+    assert!(withdraw_since(unlock_point, &owner).is_err());
+    assert_eq!(
+        withdraw_since(unlock_point, &build_multisig_script(&cfg)).unwrap(),
+        dao_since
+    );
+}
+
+#[test]
+fn test_dao_compound() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let (deposit_point, prepare_point) = ((5, 5, 1000), (184, 4, 1000));
+    let deposit_number = deposit_point.0 * deposit_point.2 + deposit_point.1;
+    let prepare_number = prepare_point.0 * prepare_point.2 + prepare_point.1;
+    let deposit_header = HeaderBuilder::default()
+        .epoch(EpochNumberWithFraction::new(5, 5, 1000).full_value().pack())
+        .number(deposit_number.pack())
+        .dao(pack_dao_data(
+            10_000_000_000_000_000,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_header = HeaderBuilder::default()
+        .epoch(
+            EpochNumberWithFraction::new(184, 4, 1000)
+                .full_value()
+                .pack(),
+        )
+        .number(prepare_number.pack())
+        .dao(pack_dao_data(
+            20_000_000_000_000_000,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+        .build();
+    let prepare_output = CellOutput::new_builder()
+        .capacity((220 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(build_dao_script()).pack())
+        .build();
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+
+    for compound_compensation in [false, true] {
+        let mut ctx = init_context(Vec::new(), Vec::new());
+        let prepare_out_point = random_out_point();
+        ctx.add_live_cell(
+            CellInput::new(prepare_out_point.clone(), 0),
+            prepare_output.clone(),
+            Bytes::from(deposit_number.to_le_bytes().to_vec()),
+            Some(prepare_header.hash()),
+        );
+        ctx.add_header(deposit_header.clone());
+        ctx.add_header(prepare_header.clone());
+
+        let mut builder = DaoCompoundBuilder::new(
+            vec![DaoWithdrawItem::new(
+                prepare_out_point,
+                Some(placeholder_witness.clone()),
+            )],
+            sender.clone(),
+            placeholder_witness.clone(),
+            FEE_RATE,
+        );
+        builder.compound_compensation = compound_compensation;
+        let mut cell_collector = ctx.to_live_cells_context();
+        let (withdraw_tx, deposit_tx) = builder
+            .build(&mut cell_collector, &ctx, &ctx, &ctx, 0)
+            .unwrap();
+        let withdrawn = withdraw_tx.output(0).unwrap();
+        assert_eq!(
+            deposit_tx.inputs().get(0).unwrap().previous_output(),
+            OutPoint::new(withdraw_tx.hash(), 0)
+        );
+
+        let (withdraw_tx, locked_groups) = unlock_tx(withdraw_tx, &ctx, &unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        ctx.verify(withdraw_tx.clone(), FEE_RATE).unwrap();
+
+        ctx.add_live_cell(
+            deposit_tx.inputs().get(0).unwrap(),
+            withdrawn.clone(),
+            Bytes::new(),
+            None,
+        );
+        let (deposit_tx, locked_groups) = unlock_tx(deposit_tx, &ctx, &unlockers).unwrap();
+        assert!(locked_groups.is_empty());
+        let deposit = deposit_tx.output(0).unwrap();
+        assert_eq!(deposit.type_().to_opt(), Some(build_dao_script()));
+        assert_eq!(deposit.lock(), sender);
+        if compound_compensation {
+            assert_eq!(deposit_tx.outputs().len(), 1);
+        } else {
+            assert_eq!(deposit_tx.outputs().len(), 2);
+            assert_eq!(Unpack::<u64>::unpack(&deposit.capacity()), 220 * ONE_CKB);
+            assert_eq!(deposit_tx.output(1).unwrap().lock(), sender);
+        }
+        ctx.verify(deposit_tx.clone(), FEE_RATE).unwrap();
+
+        // the new deposit is visible to the following builds
+        let query = CellQueryOptions::new_type(build_dao_script());
+        let (cells, _) = cell_collector.collect_live_cells(&query, false).unwrap();
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0].out_point, OutPoint::new(deposit_tx.hash(), 0));
+    }
+}
+
+#[test]
+fn test_udt_issue() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let owner = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![
+            (owner.clone(), Some(100 * ONE_CKB)),
+            (owner.clone(), Some(200 * ONE_CKB)),
+            (owner.clone(), Some(300 * ONE_CKB)),
+        ],
+    );
+
+    let sudt_script_id = ScriptId::new_data1(sudt_data_hash.clone());
+    let udt_receiver = UdtTargetReceiver::new(TransferAction::Create, receiver.clone(), 500);
+    let builder = UdtIssueBuilder {
+        udt_type: UdtType::Sudt,
+        script_id: sudt_script_id,
+        owner: owner.clone(),
+        receivers: vec![udt_receiver],
+    };
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer =
+        CapacityBalancer::new_simple(owner.clone(), placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 2);
+    assert_eq!(tx.inputs().len(), 2);
+    for out_point in tx.input_pts_iter() {
+        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), owner);
+    }
+    assert_eq!(tx.outputs().len(), 2);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let output = CellOutput::new_builder()
+        .lock(receiver)
+        .type_(Some(type_script).pack())
+        .build();
+    let occupied_capacity = output
+        .occupied_capacity(Capacity::bytes(16).unwrap())
+        .unwrap()
+        .as_u64();
+    let output = output
+        .as_builder()
+        .capacity(occupied_capacity.pack())
+        .build();
+    assert_eq!(tx.output(0).unwrap(), output);
+    assert_eq!(tx.output(1).unwrap().lock(), owner);
+    let expected_outputs_data = vec![
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_udt_transfer() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(ACP_BIN, true), (SUDT_BIN, false)],
+        vec![
+            (sender.clone(), Some(100 * ONE_CKB)),
+            (sender.clone(), Some(200 * ONE_CKB)),
+        ],
+    );
+
+    let sender_input = CellInput::new(random_out_point(), 0);
+    let sender_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let sender_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(sender_input, sender_output.clone(), sender_data, None);
+
+    let receiver_acp_lock = Script::new_builder()
+        .code_hash(acp_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let receiver_input = CellInput::new(random_out_point(), 0);
+    let receiver_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(receiver_acp_lock.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let receiver_data = Bytes::from(100u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(receiver_input, receiver_output.clone(), receiver_data, None);
+
+    let udt_receiver = UdtTargetReceiver::new(TransferAction::Update, receiver_acp_lock, 300);
+    let builder = UdtTransferBuilder {
+        type_script,
+        sender: sender.clone(),
+        receivers: vec![udt_receiver],
+        change_lock: None,
+    };
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness.clone(), FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    unlockers.insert(ScriptId::new_data1(acp_data_hash), Box::new(acp_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.header_deps().len(), 0);
+    assert_eq!(tx.cell_deps().len(), 3);
+    assert_eq!(tx.inputs().len(), 3);
+    let outputs = tx.outputs().into_iter().collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0..2], vec![sender_output, receiver_output]);
+    let expected_outputs_data = vec![
+        Bytes::from(200u128.to_le_bytes().to_vec()),
+        Bytes::from(400u128.to_le_bytes().to_vec()),
+        Bytes::default(),
+    ];
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs_data, expected_outputs_data);
+    let witnesses_len = tx
+        .witnesses()
+        .into_iter()
+        .map(|w| w.raw_data().len())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        witnesses_len,
+        vec![placeholder_witness.as_slice().len(), 0, 0]
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_udt_transfer_with_udt_change() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![(sender.clone(), Some(100 * ONE_CKB))],
+    );
+    for amount in [200u128, 300] {
+        let output = CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let data = Bytes::from(amount.to_le_bytes().to_vec());
+        ctx.add_live_cell(CellInput::new(random_out_point(), 0), output, data, None);
+    }
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender.clone(), placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(script_unlocker),
+    );
+    let build = |amount: u128| {
+        let builder = UdtTransferBuilder {
+            type_script: type_script.clone(),
+            sender: sender.clone(),
+            receivers: vec![UdtTargetReceiver::new(
+                TransferAction::Create,
+                receiver.clone(),
+                amount,
+            )],
+            change_lock: None,
+        }
+        .with_udt_change(sender.clone());
+        builder.build_unlocked(
+            &mut ctx.to_live_cells_context(),
+            &ctx,
+            &ctx,
+            &ctx,
+            &balancer,
+            &unlockers,
+        )
+    };
+
+    // both udt cells are needed, the udt change goes to a new minimal cell
+    let (tx, locked_groups) = build(400).unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    let outputs = tx.outputs().into_iter().collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].lock(), sender);
+    assert_eq!(outputs[0].type_(), Some(type_script.clone()).pack());
+    // 8 bytes capacity, 53 bytes lock, 65 bytes type script, 16 bytes data
+    let change_capacity: u64 = outputs[0].capacity().unpack();
+    assert_eq!(change_capacity, (8 + 53 + 65 + 16) * ONE_CKB);
+    // the capacity released from the udt cells goes to the capacity change
+    assert_eq!(outputs[2].lock(), sender);
+    assert!(outputs[2].type_().is_none());
+    let outputs_data = tx
+        .outputs_data()
+        .into_iter()
+        .map(|d| d.raw_data())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outputs_data,
+        vec![
+            Bytes::from(100u128.to_le_bytes().to_vec()),
+            Bytes::from(400u128.to_le_bytes().to_vec()),
+            Bytes::default(),
+        ]
+    );
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    // no udt change cell for a zero change
+    let (tx, _) = build(500).unwrap();
+    assert_eq!(tx.outputs().len(), 2);
+    assert!(tx.output(1).unwrap().type_().is_none());
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    assert!(build(501).is_err());
+}
+
+#[test]
+fn test_udt_change_keeps_extension_data() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![(sender.clone(), Some(100 * ONE_CKB))],
+    );
+    let extension_data = vec![7u8; 4];
+    for amount in [200u128, 300] {
+        let output = CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let data = [amount.to_le_bytes().to_vec(), extension_data.clone()].concat();
+        ctx.add_live_cell(
+            CellInput::new(random_out_point(), 0),
+            output,
+            Bytes::from(data),
+            None,
+        );
+    }
+
+    let builder = UdtTransferBuilder {
+        type_script,
+        sender: sender.clone(),
+        receivers: vec![UdtTargetReceiver::new(
+            TransferAction::Create,
+            receiver,
+            400,
+        )],
+        change_lock: None,
+    }
+    .with_udt_change(sender);
+    let tx = builder
+        .build_base(&mut ctx.to_live_cells_context(), &ctx, &ctx, &ctx)
+        .unwrap();
+    let change_data = tx.outputs_data().get(0).unwrap().raw_data();
+    assert_eq!(
+        change_data,
+        Bytes::from([100u128.to_le_bytes().to_vec(), extension_data].concat())
+    );
+    // 8 bytes capacity, 53 bytes lock, 65 bytes type script, 20 bytes data
+    let change_capacity: u64 = tx.output(0).unwrap().capacity().unpack();
+    assert_eq!(change_capacity, (8 + 53 + 65 + 20) * ONE_CKB);
+}
+
+#[test]
+fn test_udt_swap() {
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let party_a = build_sighash_script(ACCOUNT1_ARG);
+    let party_b = build_sighash_script(ACCOUNT2_ARG);
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(SUDT_BIN, false)],
+        vec![(party_b.clone(), Some(1000 * ONE_CKB))],
+    );
+    let party_a_output = CellOutput::new_builder()
+        .capacity((200 * ONE_CKB).pack())
+        .lock(party_a.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    let party_a_data = Bytes::from(500u128.to_le_bytes().to_vec());
+    ctx.add_live_cell(
+        CellInput::new(random_out_point(), 0),
+        party_a_output,
+        party_a_data,
+        None,
+    );
+
+    let unlockers_of = |key: &H256| {
+        let key = secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap();
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+        unlockers.insert(
+            ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+            Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+        );
+        unlockers
+    };
+    let unlockers_a = unlockers_of(&ACCOUNT1_KEY);
+    let unlockers_b = unlockers_of(&ACCOUNT2_KEY);
+    let terms = SwapTerms::new(
+        type_script.clone(),
+        300,
+        100 * ONE_CKB,
+        party_a.clone(),
+        party_b.clone(),
+    );
+    let builder = SwapBuilder::new(terms.clone());
+
+    // party A
+    let mut cell_collector_a = ctx.to_live_cells_context();
+    let proposal = builder
+        .propose(&mut cell_collector_a, &ctx, &ctx, &unlockers_a)
+        .unwrap();
+    assert_eq!(proposal.stage, SwapStage::Proposed);
+    let proposal: SwapProposal =
+        serde_json::from_str(&serde_json::to_string(&proposal).unwrap()).unwrap();
+
+    // party B, the terms must match its own
+    let other_terms = SwapTerms::new(type_script, 400, 100 * ONE_CKB, party_a, party_b.clone());
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(party_b, placeholder_witness, FEE_RATE);
+    let mut cell_collector_b = ctx.to_live_cells_context();
+    assert!(SwapBuilder::new(other_terms)
+        .accept(
+            &proposal,
+            &balancer,
+            &mut cell_collector_b,
+            &ctx,
+            &ctx,
+            &ctx
+        )
+        .is_err());
+    let mut accepted = builder
+        .accept(
+            &proposal,
+            &balancer,
+            &mut cell_collector_b,
+            &ctx,
+            &ctx,
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(accepted.stage, SwapStage::Accepted);
+
+    // each party only signs its own inputs
+    let locked_groups = accepted.sign(&ctx, &unlockers_b).unwrap();
+    assert_eq!(locked_groups.len(), 1);
+    assert_eq!(locked_groups[0].input_indices, vec![0]);
+    let locked_groups = accepted.sign(&ctx, &unlockers_a).unwrap();
+    assert_eq!(locked_groups.len(), 1);
+    assert_eq!(locked_groups[0].input_indices, vec![1]);
+
+    let tx = accepted.tx_view();
+    let outputs_data = tx
+        .outputs_data()
         .into_iter()
-        .map(|d| d.raw_data())
+        .map(|data| data.raw_data())
         .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
+    assert_eq!(outputs_data[0], Bytes::from(300u128.to_le_bytes().to_vec()));
+    assert_eq!(outputs_data[2], Bytes::from(200u128.to_le_bytes().to_vec()));
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_find_acp_cell() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let owner = build_sighash_script(H160::default());
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(owner.calc_script_hash().as_bytes().pack())
+        .build();
+    let other_type_script = type_script
+        .clone()
+        .as_builder()
+        .args(Bytes::from(vec![1u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(vec![(ACP_BIN, true), (SUDT_BIN, false)], vec![]);
+
+    let acp_id = ScriptId::new_data1(acp_data_hash.clone());
+    let build_acp_lock = |args: Vec<u8>| {
+        Script::new_builder()
+            .code_hash(acp_data_hash.pack())
+            .hash_type(ScriptHashType::Data1.into())
+            .args(Bytes::from(args).pack())
+            .build()
+    };
+    let receiver_lock = build_acp_lock(ACCOUNT2_ARG.0.to_vec());
+    // acp lock with minimal ckb/udt amount args
+    let receiver_lock_with_min = build_acp_lock([ACCOUNT2_ARG.0.to_vec(), vec![1, 2]].concat());
+    let mut add_cell = |lock: &Script, type_script: &Script, capacity: u64, amount: u128| {
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let input = CellInput::new(random_out_point(), 0);
+        let data = Bytes::from(amount.to_le_bytes().to_vec());
+        ctx.add_live_cell(input.clone(), output, data, None);
+        input.previous_output()
+    };
+    let small_cell = add_cell(&receiver_lock, &type_script, 150 * ONE_CKB, 900);
+    let large_cell = add_cell(&receiver_lock_with_min, &type_script, 300 * ONE_CKB, 100);
+    add_cell(&receiver_lock, &other_type_script, 500 * ONE_CKB, 1000);
+    add_cell(
+        &build_acp_lock(ACCOUNT1_ARG.0.to_vec()),
+        &type_script,
+        500 * ONE_CKB,
+        1000,
+    );
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let prefix = ACCOUNT2_ARG.0.to_vec();
+    for (strategy, expected) in [
+        (AcpCellSelectStrategy::LargestCapacity, &large_cell),
+        (AcpCellSelectStrategy::LargestAmount, &small_cell),
+        (AcpCellSelectStrategy::First, &small_cell),
+    ] {
+        let cell = find_acp_cell(
+            &mut cell_collector,
+            &acp_id,
+            &prefix,
+            &type_script,
+            strategy,
+        )
+        .unwrap();
+        assert_eq!(&cell.out_point, expected);
+    }
+    assert!(find_acp_cell(
+        &mut cell_collector,
+        &acp_id,
+        &prefix,
+        &type_script,
+        AcpCellSelectStrategy::Unique,
+    )
+    .is_err());
+    // nothing is locked by the lookup
+    assert!(cell_collector.used_inputs.is_empty());
+
+    let cell = find_acp_cell(
+        &mut cell_collector,
+        &acp_id,
+        &prefix,
+        &other_type_script,
+        AcpCellSelectStrategy::Unique,
+    )
+    .unwrap();
+    assert_eq!(cell.output.type_().to_opt(), Some(other_type_script));
+    assert!(find_acp_cell(
+        &mut cell_collector,
+        &acp_id,
+        ACCOUNT3_ARG.as_bytes(),
+        &type_script,
+        AcpCellSelectStrategy::default(),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_udt_transfer_to_acp_receivers() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
+        .build();
+    let mut ctx = init_context(
+        vec![(ACP_BIN, true), (SUDT_BIN, false)],
+        vec![(sender.clone(), Some(200 * ONE_CKB))],
+    );
+    let receiver_acp_lock = Script::new_builder()
+        .code_hash(acp_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let mut add_cell = |lock: &Script, capacity: u64, amount: u128| {
+        let output = CellOutput::new_builder()
+            .capacity(capacity.pack())
+            .lock(lock.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let input = CellInput::new(random_out_point(), 0);
+        let data = Bytes::from(amount.to_le_bytes().to_vec());
+        ctx.add_live_cell(input.clone(), output, data, None);
+        input.previous_output()
+    };
+    add_cell(&sender, 200 * ONE_CKB, 500);
+    let acp_cells = vec![
+        add_cell(&receiver_acp_lock, 300 * ONE_CKB, 100),
+        add_cell(&receiver_acp_lock, 150 * ONE_CKB, 100),
+    ];
+
+    // both receivers look up the acp cells of the same lock
+    let receivers = [100, 200]
+        .iter()
+        .map(|amount| {
+            UdtTargetReceiver::new(TransferAction::Update, receiver_acp_lock.clone(), *amount)
+                .with_acp_cell(AcpCellSelectStrategy::LargestCapacity, 0)
+        })
+        .collect();
+    let builder = UdtTransferBuilder {
+        type_script,
+        sender: sender.clone(),
+        receivers,
+        change_lock: None,
+    };
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let sighash_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(sighash_unlocker),
+    );
+    unlockers.insert(ScriptId::new_data1(acp_data_hash), Box::new(acp_unlocker));
+
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let input_out_points = tx.input_pts_iter().collect::<Vec<_>>();
+    assert_eq!(input_out_points[1..3], acp_cells[..]);
+    let outputs_data = tx
+        .outputs_data()
         .into_iter()
-        .map(|w| w.raw_data().len())
+        .map(|data| data.raw_data())
         .collect::<Vec<_>>();
-    let witness = placeholder_witness
-        .as_builder()
-        .input_type(Some(Bytes::from(vec![0u8; 8])).pack())
+    assert_eq!(outputs_data[1], Bytes::from(200u128.to_le_bytes().to_vec()));
+    assert_eq!(outputs_data[2], Bytes::from(300u128.to_le_bytes().to_vec()));
+    ctx.verify(tx, FEE_RATE).unwrap();
+}
+
+#[test]
+fn test_udt_acp_cell_lock_expires() {
+    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let type_script = Script::new_builder()
+        .code_hash(sudt_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(vec![9u8; 32]).pack())
         .build();
-    assert_eq!(witnesses_len, vec![witness.as_slice().len(), 0]);
+    let mut ctx = init_context(vec![(ACP_BIN, true), (SUDT_BIN, false)], Vec::new());
+    let receiver_acp_lock = Script::new_builder()
+        .code_hash(acp_data_hash.pack())
+        .hash_type(ScriptHashType::Data1.into())
+        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+        .build();
+    let mut add_cell = |lock: &Script, amount: u128| {
+        let output = CellOutput::new_builder()
+            .capacity((200 * ONE_CKB).pack())
+            .lock(lock.clone())
+            .type_(Some(type_script.clone()).pack())
+            .build();
+        let input = CellInput::new(random_out_point(), 0);
+        let data = Bytes::from(amount.to_le_bytes().to_vec());
+        ctx.add_live_cell(input.clone(), output, data, None);
+        input.previous_output()
+    };
+    add_cell(&sender, 500);
+    let acp_cell = add_cell(&receiver_acp_lock, 100);
+
+    let tip_block_number = 1000;
+    let receiver = UdtTargetReceiver::new(TransferAction::Update, receiver_acp_lock, 100)
+        .with_acp_cell(AcpCellSelectStrategy::First, tip_block_number);
+    let builder = UdtTransferBuilder {
+        type_script,
+        sender: sender.clone(),
+        receivers: vec![receiver],
+        change_lock: None,
+    };
+    let mut cell_collector = OffchainLockCellCollector {
+        inner: ctx.to_live_cells_context(),
+        offchain: OffchainCellCollector::default(),
+    };
+    builder
+        .build_base(&mut cell_collector, &ctx, &ctx, &ctx)
+        .unwrap();
+    let acp_cell_key: (H256, u32) = (acp_cell.tx_hash().unpack(), acp_cell.index().unpack());
+    assert_eq!(
+        cell_collector.offchain.locked_cells.get(&acp_cell_key),
+        Some(&tip_block_number)
+    );
+
+    // the lock is kept for a few blocks, then dropped with the stale cells
+    let query = CellQueryOptions::new_lock(sender);
+    cell_collector
+        .offchain
+        .collect(&query, tip_block_number + 13);
+    assert!(cell_collector
+        .offchain
+        .locked_cells
+        .contains_key(&acp_cell_key));
+    cell_collector
+        .offchain
+        .collect(&query, tip_block_number + 14);
+    assert!(cell_collector.offchain.locked_cells.is_empty());
+}
+
+#[test]
+fn test_tenant_scope_isolation() {
+    let omnibus = build_sighash_script(ACCOUNT0_ARG);
+    let ctx = init_context(
+        Vec::new(),
+        vec![
+            (omnibus.clone(), Some(100 * ONE_CKB)),
+            (omnibus.clone(), Some(200 * ONE_CKB)),
+            (omnibus.clone(), Some(300 * ONE_CKB)),
+            (omnibus.clone(), Some(400 * ONE_CKB)),
+        ],
+    );
+    let reservations = TenantReservations::new();
+    let mut tenant_a = TenantScope::new("a", ctx.to_live_cells_context(), reservations.clone());
+    // tenant b can only see cells with capacity >= 200 CKB
+    let mut tenant_b = TenantScope::new("b", ctx.to_live_cells_context(), reservations.clone())
+        .with_visibility(Arc::new(|cell: &LiveCell| {
+            let capacity: u64 = cell.output.capacity().unpack();
+            capacity >= 200 * ONE_CKB
+        }));
+
+    let mut query = CellQueryOptions::new_lock(omnibus);
+    query.min_total_capacity = 250 * ONE_CKB;
+    let (cells_a, capacity_a) = tenant_a.collect_live_cells(&query, true).unwrap();
+    assert_eq!(cells_a.len(), 2);
+    assert_eq!(capacity_a, 300 * ONE_CKB);
+    for cell in &cells_a {
+        assert_eq!(reservations.owner_of(&cell.out_point).as_deref(), Some("a"));
+    }
+
+    query.min_total_capacity = u64::MAX;
+    let (cells_b, capacity_b) = tenant_b.collect_live_cells(&query, true).unwrap();
+    assert_eq!(cells_b.len(), 2);
+    assert_eq!(capacity_b, 700 * ONE_CKB);
+    let (cells_a, _) = tenant_a.collect_live_cells(&query, false).unwrap();
+    assert!(cells_a.is_empty());
+    assert!(tenant_a.lock_cell(cells_b[0].out_point.clone(), 0).is_err());
+
+    tenant_b.reset();
+    let (cells_a, capacity_a) = tenant_a.collect_live_cells(&query, false).unwrap();
+    assert_eq!(cells_a.len(), 2);
+    assert_eq!(capacity_a, 700 * ONE_CKB);
+}
+
+pub mod ckb_indexer_rpc;
+pub mod ckb_rpc;
+pub mod cycle;
+pub mod omni_lock;
+pub mod omni_lock_util;
+pub mod transaction;
+
+#[test]
+fn test_check_since_reached() {
+    let since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        EpochNumberWithFraction::new(10, 1, 2).full_value(),
+        false,
+    );
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(random_out_point(), since.value()))
+        .input(CellInput::new(random_out_point(), 0))
+        .build();
+    assert!(check_since_reached(&tx, EpochNumberWithFraction::new(10, 2, 4)).is_ok());
+    assert!(check_since_reached(&tx, EpochNumberWithFraction::new(11, 0, 1)).is_ok());
+    let err = check_since_reached(&tx, EpochNumberWithFraction::new(10, 1, 4)).unwrap_err();
+    match err {
+        TxBuilderError::SinceNotReached(idx, msg) => {
+            assert_eq!(idx, 0);
+            assert!(msg.contains("epoch 10.1/2"), "{}", msg);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn test_multisig_config_display() {
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG], 1, 2).unwrap();
+    let text = cfg.to_string();
+    assert!(
+        text.starts_with(&format!(
+            "2-of-2 multisig {:#x} (require first 1)",
+            cfg.hash160()
+        )),
+        "{}",
+        text
+    );
+    assert!(text.ends_with(&format!("[{:#x}, {:#x}]", ACCOUNT1_ARG, ACCOUNT2_ARG)));
+}
+
+#[test]
+fn test_signing_preview() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let sender_input = CellInput::new(random_out_point(), 0);
+    let other_input = CellInput::new(random_out_point(), 0);
+    for (input, lock) in [(&sender_input, &sender), (&other_input, &receiver)] {
+        let output = CellOutput::new_builder()
+            .capacity((300 * ONE_CKB).pack())
+            .lock(lock.clone())
+            .build();
+        ctx.add_live_cell(input.clone(), output, Bytes::new(), None);
+    }
+    let tx = TransactionBuilder::default()
+        .input(sender_input)
+        .input(other_input)
+        .output(
+            CellOutput::new_builder()
+                .capacity((500 * ONE_CKB).pack())
+                .lock(receiver.clone())
+                .build(),
+        )
+        .output(
+            CellOutput::new_builder()
+                .capacity((98 * ONE_CKB).pack())
+                .lock(sender.clone())
+                .build(),
+        )
+        .outputs_data(vec![Bytes::new().pack(); 2])
+        .build();
+    let mut script_group = ScriptGroup::from_lock_script(&sender);
+    script_group.input_indices.push(0);
+
+    let entry = SigningEntry::new(&tx, &script_group, Bytes::from(vec![0u8; 65]), &ctx).unwrap();
+    assert_eq!(entry.message.len(), 32);
+    let preview = entry.preview;
+    assert_eq!(preview.spent_capacity.value(), 300 * ONE_CKB);
+    assert_eq!(preview.fee.value(), 2 * ONE_CKB);
+    assert_eq!(preview.fee_share.value(), ONE_CKB);
+    assert_eq!(preview.sent_capacity(), 500 * ONE_CKB);
+    assert!(!preview.destinations[0].is_change);
+    assert!(preview.destinations[1].is_change);
+}
+
+#[test]
+fn test_vesting_and_claim() {
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT2_ARG], 0, 1).unwrap();
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(1000 * ONE_CKB))]);
+
+    let builder = VestingBuilder::new_even(cfg.clone(), 700 * ONE_CKB, 10, 5, 3).unwrap();
+    assert_eq!(builder.epochs(), vec![10, 15, 20]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let capacities = tx
+        .outputs()
+        .into_iter()
+        .take(3)
+        .map(|output| Unpack::<u64>::unpack(&output.capacity()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        capacities,
+        vec![233_3333_3333, 233_3333_3333, 233_3333_3334]
+    );
+    for (output, epoch) in tx.outputs().into_iter().zip([10, 15, 20]) {
+        assert_eq!(output.lock(), vesting_lock(&cfg, epoch));
+    }
+    ctx.verify(tx, FEE_RATE).unwrap();
+
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    for tranche in &builder.tranches {
+        ctx.add_simple_live_cell(
+            random_out_point(),
+            vesting_lock(&cfg, tranche.epoch_number),
+            Some(tranche.capacity),
+        );
+    }
+    let claim = VestingClaimBuilder::new(
+        cfg.clone(),
+        builder.epochs(),
+        EpochNumberWithFraction::new(16, 0, 1),
+        receiver.clone(),
+    );
+    assert_eq!(claim.matured_epochs(), vec![10, 15]);
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let unlockers = build_multisig_unlockers(account2_key, cfg.clone());
+    let mut cell_collector = ctx.to_live_cells_context();
+    let (tx, locked_groups) = claim
+        .build_unlocked(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &ctx,
+            &claim.balancer(FEE_RATE),
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.inputs().len(), 2);
+    for (input, epoch) in tx.inputs().into_iter().zip([10, 15]) {
+        let since: u64 = input.since().unpack();
+        assert_eq!(since, Since::new_absolute_epoch(epoch).value());
+    }
+    assert_eq!(tx.outputs().len(), 1);
+    assert_eq!(tx.output(0).unwrap().lock(), receiver);
     ctx.verify(tx, FEE_RATE).unwrap();
 }
 
 #[test]
-fn test_udt_issue() {
-    let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
-    let owner = build_sighash_script(ACCOUNT1_ARG);
-    let receiver = build_sighash_script(ACCOUNT2_ARG);
+fn test_refund_mistaken_payment() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let sender_input = CellInput::new(random_out_point(), 0);
+    ctx.add_live_cell(
+        sender_input.clone(),
+        CellOutput::new_builder()
+            .capacity((500 * ONE_CKB).pack())
+            .lock(sender.clone())
+            .build(),
+        Bytes::new(),
+        None,
+    );
+    let payment_tx = TransactionBuilder::default()
+        .input(sender_input)
+        .output(
+            CellOutput::new_builder()
+                .capacity((300 * ONE_CKB).pack())
+                .lock(receiver.clone())
+                .build(),
+        )
+        .output_data(Bytes::new().pack())
+        .build();
+    ctx.add_transaction(payment_tx.clone());
+    let (output, data) = payment_tx.output_with_data(0).unwrap();
+    ctx.add_live_cell(
+        CellInput::new(OutPoint::new(payment_tx.hash(), 0), 0),
+        output,
+        data,
+        None,
+    );
+    ctx.add_simple_live_cell(random_out_point(), receiver.clone(), Some(200 * ONE_CKB));
+
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let account2_key = secp256k1::SecretKey::from_slice(ACCOUNT2_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account2_key]);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+
+    for fee_policy in [
+        RefundFeePolicy::DeductFromRefund,
+        RefundFeePolicy::PaidByRefunder,
+    ] {
+        let builder = RefundBuilder::new(
+            payment_tx.hash().unpack(),
+            0,
+            placeholder_witness.clone(),
+            fee_policy,
+            1000,
+        );
+        assert_eq!(builder.sender_lock(&ctx).unwrap(), sender);
+        let balancer = builder.balancer(&ctx, FEE_RATE).unwrap();
+        let mut cell_collector = OffchainLockCellCollector {
+            inner: ctx.to_live_cells_context(),
+            offchain: OffchainCellCollector::default(),
+        };
+        let (tx, locked_groups) = builder
+            .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+            .unwrap();
+        assert!(locked_groups.is_empty());
+        // the received cell is locked at the tip, not forever
+        let refund_key: (H256, u32) = (payment_tx.hash().unpack(), 0);
+        assert_eq!(
+            cell_collector.offchain.locked_cells.get(&refund_key),
+            Some(&1000)
+        );
+        let refund = tx.output(0).unwrap();
+        assert_eq!(refund.lock(), sender);
+        let refund_capacity: u64 = refund.capacity().unpack();
+        match fee_policy {
+            RefundFeePolicy::DeductFromRefund => {
+                assert_eq!(tx.inputs().len(), 1);
+                assert_eq!(tx.outputs().len(), 1);
+                assert!(refund_capacity < 300 * ONE_CKB);
+            }
+            RefundFeePolicy::PaidByRefunder => {
+                assert_eq!(tx.inputs().len(), 2);
+                assert_eq!(refund_capacity, 300 * ONE_CKB);
+                assert_eq!(tx.output(1).unwrap().lock(), receiver);
+            }
+        }
+        ctx.verify(tx, FEE_RATE).unwrap();
+    }
+}
+
+#[test]
+fn test_build_with_deadline() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(Vec::new(), vec![(sender.clone(), Some(200 * ONE_CKB))]);
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
+    let unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+
+    let deadline = Deadline::after(Duration::from_secs(60));
+    let mut cell_collector = deadline.cell_collector(Box::new(ctx.to_live_cells_context()));
+    let tx_dep_provider = deadline.tx_dep_provider(&ctx);
+    let header_dep_resolver = deadline.header_dep_resolver(&ctx);
+    builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &header_dep_resolver,
+            &tx_dep_provider,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+
+    let expired = Deadline::at(Instant::now());
+    assert_eq!(expired.check(), Err(DeadlineExceeded));
+    let mut cell_collector = expired.cell_collector(Box::new(ctx.to_live_cells_context()));
+    let tx_dep_provider = expired.tx_dep_provider(&ctx);
+    let err = builder
+        .build_balanced(
+            &mut cell_collector,
+            &ctx,
+            &ctx,
+            &tx_dep_provider,
+            &balancer,
+            &unlockers,
+        )
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            TxBuilderError::BalanceCapacity(
+                BalanceTxCapacityError::CellCollector(CellCollectorError::DeadlineExceeded(_))
+                    | BalanceTxCapacityError::TxDep(TransactionDependencyError::DeadlineExceeded(
+                        _
+                    ))
+            )
+        ),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn test_verify_all_signatures() {
+    let sighash_lock = build_sighash_script(ACCOUNT1_ARG);
+    let cfg = MultisigConfig::new_with(
+        vec![
+            ACCOUNT0_ARG.clone(),
+            ACCOUNT1_ARG.clone(),
+            ACCOUNT2_ARG.clone(),
+        ],
+        0,
+        2,
+    )
+    .unwrap();
+    let multisig_lock = build_multisig_script(&cfg);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let sighash_input = random_out_point();
+    let multisig_input = random_out_point();
+    ctx.add_simple_live_cell(
+        sighash_input.clone(),
+        sighash_lock.clone(),
+        Some(100 * ONE_CKB),
+    );
+    ctx.add_simple_live_cell(
+        multisig_input.clone(),
+        multisig_lock.clone(),
+        Some(200 * ONE_CKB),
+    );
+
+    let sighash_placeholder = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let output = CellOutput::new_builder()
+        .capacity((299 * ONE_CKB).pack())
+        .lock(build_sighash_script(ACCOUNT3_ARG))
+        .build();
+    let cell_deps =
+        resolve_cell_deps(&ctx, &[sighash_lock.clone(), multisig_lock.clone()]).unwrap();
+    let tx = TransactionBuilder::default()
+        .set_cell_deps(cell_deps)
+        .input(CellInput::new(sighash_input, 0))
+        .input(CellInput::new(multisig_input, 0))
+        .output(output)
+        .output_data(Bytes::new().pack())
+        .witness(sighash_placeholder.as_bytes().pack())
+        .witness(cfg.placeholder_witness().as_bytes().pack())
+        .build();
+    assert!(verify_all_signatures(&tx, &ctx).unwrap().is_empty());
+
+    let account0_key = secp256k1::SecretKey::from_slice(ACCOUNT0_KEY.as_bytes()).unwrap();
+    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
+    let mut unlockers = build_multisig_unlockers(account0_key, cfg);
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    let (tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+
+    let slots = verify_all_signatures(&tx, &ctx).unwrap();
+    assert_eq!(slots.len(), 2);
+    assert!(slots.iter().all(|slot| slot.is_valid()));
+    assert_eq!(slots[0].lock_script, sighash_lock);
+    assert_eq!(slots[0].status, SlotStatus::Valid(ACCOUNT1_ARG));
+    assert_eq!(slots[1].lock_script, multisig_lock);
+    assert_eq!((slots[1].witness_index, slots[1].slot), (1, 0));
+    assert_eq!(slots[1].status, SlotStatus::Valid(ACCOUNT0_ARG));
+
+    // the signatures don't cover another output
+    let tampered = tx
+        .as_advanced_builder()
+        .set_outputs(vec![CellOutput::new_builder()
+            .capacity((299 * ONE_CKB).pack())
+            .lock(build_sighash_script(ACCOUNT2_ARG))
+            .build()])
+        .build();
+    let slots = verify_all_signatures(&tampered, &ctx).unwrap();
+    assert_eq!(slots.len(), 2);
+    assert!(slots
+        .iter()
+        .all(|slot| matches!(slot.status, SlotStatus::WrongSigner(_))));
+
+    let malformed = tx
+        .as_advanced_builder()
+        .set_witnesses(vec![
+            Bytes::from(vec![1u8; 10]).pack(),
+            tx.witnesses().get(1).unwrap(),
+        ])
+        .build();
+    let slots = verify_all_signatures(&malformed, &ctx).unwrap();
+    assert!(matches!(slots[0].status, SlotStatus::Invalid(_)));
+    assert!(slots[1].is_valid());
+}
+
+#[test]
+fn test_signing_authorizer() {
+    let customer1 = build_sighash_script(ACCOUNT1_ARG);
+    let customer2 = build_sighash_script(ACCOUNT2_ARG);
+    let receiver = build_sighash_script(ACCOUNT3_ARG);
     let ctx = init_context(
-        vec![(SUDT_BIN, false)],
+        Vec::new(),
         vec![
-            (owner.clone(), Some(100 * ONE_CKB)),
-            (owner.clone(), Some(200 * ONE_CKB)),
-            (owner.clone(), Some(300 * ONE_CKB)),
+            (customer1.clone(), Some(200 * ONE_CKB)),
+            (customer2.clone(), Some(200 * ONE_CKB)),
         ],
     );
-
-    let sudt_script_id = ScriptId::new_data1(sudt_data_hash.clone());
-    let udt_receiver = UdtTargetReceiver::new(TransferAction::Create, receiver.clone(), 500);
-    let builder = UdtIssueBuilder {
-        udt_type: UdtType::Sudt,
-        script_id: sudt_script_id,
-        owner: owner.clone(),
-        receivers: vec![udt_receiver],
-    };
-    let placeholder_witness = WitnessArgs::new_builder()
-        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
-        .build();
-    let balancer =
-        CapacityBalancer::new_simple(owner.clone(), placeholder_witness.clone(), FEE_RATE);
-
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
+    // the service holds the keys of both customers, the request is scoped to
+    // the first one
+    let keys = [ACCOUNT1_KEY, ACCOUNT2_KEY]
+        .iter()
+        .map(|key| secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap())
+        .collect::<Vec<_>>();
+    let authorizer: Arc<dyn SigningAuthorizer> = Arc::new(
+        |owner_id: &[u8], _group: &ScriptGroup, _tx: &TransactionView| {
+            if owner_id == ACCOUNT1_ARG.as_bytes() {
+                Ok(())
+            } else {
+                Err("not the key of the current customer".to_string())
+            }
+        },
+    );
+    let signer = SecpSighashScriptSigner::new(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(
+        keys.clone(),
+    )))
+    .with_authorizer(authorizer.clone());
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
         ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
-        Box::new(script_unlocker),
+        Box::new(SecpSighashUnlocker::new(signer)),
     );
+    let placeholder_witness = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .build();
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
 
+    let balancer = CapacityBalancer::new_simple(customer1, placeholder_witness.clone(), FEE_RATE);
     let mut cell_collector = ctx.to_live_cells_context();
     let (tx, locked_groups) = builder
         .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
         .unwrap();
-
     assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 2);
-    assert_eq!(tx.inputs().len(), 2);
-    for out_point in tx.input_pts_iter() {
-        assert_eq!(ctx.get_input(&out_point).unwrap().0.lock(), owner);
-    }
-    assert_eq!(tx.outputs().len(), 2);
-    let type_script = Script::new_builder()
-        .code_hash(sudt_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(owner.calc_script_hash().as_bytes().pack())
-        .build();
-    let output = CellOutput::new_builder()
-        .lock(receiver)
-        .type_(Some(type_script).pack())
-        .build();
-    let occupied_capacity = output
-        .occupied_capacity(Capacity::bytes(16).unwrap())
-        .unwrap()
-        .as_u64();
-    let output = output
-        .as_builder()
-        .capacity(occupied_capacity.pack())
-        .build();
-    assert_eq!(tx.output(0).unwrap(), output);
-    assert_eq!(tx.output(1).unwrap().lock(), owner);
-    let expected_outputs_data = vec![
-        Bytes::from(500u128.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
-        .collect::<Vec<_>>();
-    assert_eq!(witnesses_len, vec![placeholder_witness.as_slice().len(), 0]);
     ctx.verify(tx, FEE_RATE).unwrap();
+
+    // a build bug spending the cells of the other customer is not signed
+    let balancer = CapacityBalancer::new_simple(customer2, placeholder_witness, FEE_RATE);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let err = builder
+        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        TxBuilderError::Unlock(UnlockError::ScriptSigner(
+            ScriptSignError::Unauthorized { .. }
+        ))
+    ));
+
+    // the multisig signer fails when any key of the config is denied
+    let cfg = MultisigConfig::new_with(vec![ACCOUNT1_ARG, ACCOUNT2_ARG], 0, 1).unwrap();
+    let multisig_lock = build_multisig_script(&cfg);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let input = random_out_point();
+    ctx.add_simple_live_cell(input.clone(), multisig_lock.clone(), Some(200 * ONE_CKB));
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(input, 0))
+        .witness(cfg.placeholder_witness().as_bytes().pack())
+        .build();
+    let signer = SecpMultisigScriptSigner::new(
+        Box::new(SecpCkbRawKeySigner::new_with_secret_keys(keys)),
+        cfg,
+    )
+    .with_authorizer(authorizer);
+    let mut group = ScriptGroup::from_lock_script(&multisig_lock);
+    group.input_indices.push(0);
+    assert!(matches!(
+        signer.sign_tx(&tx, &group),
+        Err(ScriptSignError::Unauthorized { .. })
+    ));
 }
 
 #[test]
-fn test_udt_transfer() {
-    let acp_data_hash = H256::from(blake2b_256(ACP_BIN));
+fn test_fill_signature_slot() {
+    let config_len = 24;
+    let mut lock_field = vec![0u8; config_len + 65 * 2];
+    let sig1 = [1u8; 65];
+    let sig2 = [2u8; 65];
+    fill_signature_slot(&mut lock_field, config_len, &sig1).unwrap();
+    // already in a slot
+    fill_signature_slot(&mut lock_field, config_len, &sig1).unwrap();
+    fill_signature_slot(&mut lock_field, config_len, &sig2).unwrap();
+    assert_eq!(&lock_field[config_len..config_len + 65], &sig1[..]);
+    assert_eq!(&lock_field[config_len + 65..], &sig2[..]);
+    assert!(matches!(
+        fill_signature_slot(&mut lock_field, config_len, &[3u8; 65]),
+        Err(ScriptSignError::TooManySignatures)
+    ));
+    // a truncated slot or an offset out of the field is never sliced
+    let mut truncated = vec![0u8; config_len + 64];
+    assert!(fill_signature_slot(&mut truncated, config_len, &sig1).is_err());
+    assert!(fill_signature_slot(&mut truncated, 1000, &sig1).is_err());
+    assert!(fill_signature_slot(&mut lock_field, config_len, &[1u8; 64]).is_err());
+
+    // a script group without inputs has no witness to sign
+    let signer =
+        SecpSighashScriptSigner::new(Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![
+            secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap(),
+        ])));
+    let group = ScriptGroup::from_lock_script(&build_sighash_script(ACCOUNT1_ARG));
+    let tx = TransactionBuilder::default().build();
+    assert!(matches!(
+        signer.sign_tx(&tx, &group),
+        Err(ScriptSignError::EmptyScriptGroup)
+    ));
+}
+
+#[test]
+fn test_export_ledger() {
     let sudt_data_hash = H256::from(blake2b_256(SUDT_BIN));
     let sender = build_sighash_script(ACCOUNT1_ARG);
-    let owner = build_sighash_script(H160::default());
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
     let type_script = Script::new_builder()
         .code_hash(sudt_data_hash.pack())
         .hash_type(ScriptHashType::Data1.into())
-        .args(owner.calc_script_hash().as_bytes().pack())
+        .args(
+            build_sighash_script(H160::default())
+                .calc_script_hash()
+                .as_bytes()
+                .pack(),
+        )
         .build();
-    let mut ctx = init_context(
-        vec![(ACP_BIN, true), (SUDT_BIN, false)],
+    let mut ctx = init_context(vec![(SUDT_BIN, false)], Vec::new());
+    let udt_input = CellInput::new(random_out_point(), 0);
+    let udt_cell = CellOutput::new_builder()
+        .capacity((300 * ONE_CKB).pack())
+        .lock(sender.clone())
+        .type_(Some(type_script.clone()).pack())
+        .build();
+    ctx.add_live_cell(
+        udt_input.clone(),
+        udt_cell.clone(),
+        Bytes::from(500u128.to_le_bytes().to_vec()),
+        None,
+    );
+    let ckb_input = random_out_point();
+    ctx.add_simple_live_cell(ckb_input.clone(), sender.clone(), Some(100 * ONE_CKB));
+
+    let tx = TransactionBuilder::default()
+        .input(udt_input)
+        .input(CellInput::new(ckb_input, 0))
+        .output(
+            udt_cell
+                .clone()
+                .as_builder()
+                .capacity((150 * ONE_CKB).pack())
+                .lock(receiver.clone())
+                .build(),
+        )
+        .output_data(Bytes::from(200u128.to_le_bytes().to_vec()).pack())
+        .output(
+            udt_cell
+                .as_builder()
+                .capacity((249 * ONE_CKB).pack())
+                .build(),
+        )
+        .output_data(Bytes::from(300u128.to_le_bytes().to_vec()).pack())
+        .build();
+    let mut registry = TokenRegistry::new();
+    let udt = Asset::Udt(type_script.calc_script_hash().unpack());
+
+    // the type script is not registered, only the capacity is counted
+    let events = classify_transaction(&tx, 1000, &[sender.clone()], &registry, &ctx).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].asset, Asset::Ckb);
+    assert_eq!(events[0].amount, -150 * ONE_CKB as i128);
+    assert_eq!(events[0].fee, ONE_CKB);
+    assert_eq!(events[0].counterparty_lock, Some(receiver.clone()));
+
+    registry.register(
+        &type_script,
+        TokenInfo {
+            symbol: "TUDT".to_string(),
+            decimals: 2,
+        },
+    );
+    let events = classify_transaction(&tx, 1000, &[sender.clone()], &registry, &ctx).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].asset, udt.clone());
+    assert_eq!(events[1].amount, -200);
+    assert_eq!(events[1].fee, 0);
+    let received = classify_transaction(&tx, 1000, &[receiver], &registry, &ctx).unwrap();
+    assert_eq!(
+        received
+            .iter()
+            .map(|event| (event.asset.clone(), event.amount, event.fee))
+            .collect::<Vec<_>>(),
+        vec![(Asset::Ckb, 150 * ONE_CKB as i128, 0), (udt, 200, 0)]
+    );
+    assert_eq!(received[1].counterparty_lock, Some(sender.clone()));
+
+    let exporter = LedgerExporter::new(&registry, NetworkType::Testnet);
+    let mut csv = Vec::new();
+    exporter.write_csv(&events, &mut csv).unwrap();
+    let receiver_address = "ckt1qzda0cr08m85hc8jlnfp3zer7xulejywt49kt2rr0vthywaa50xwsq2swumd37vvw70wgu556hgxrk025rdls4s2twyxq";
+    let tx_hash = format!("{:#x}", tx.hash());
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        format!(
+            "timestamp,asset,amount,counterparty,tx_hash,fee\n\
+             1000,CKB,-150,{addr},{hash},1\n\
+             1000,TUDT,-2,{addr},{hash},0\n",
+            addr = receiver_address,
+            hash = tx_hash
+        )
+    );
+    let mut json = Vec::new();
+    exporter.write_json(&received, &mut json).unwrap();
+    let entries: Vec<crate::ledger::LedgerEntry> = serde_json::from_slice(&json).unwrap();
+    assert_eq!(entries, exporter.entries(&received));
+    assert_eq!(entries[1].amount, "2");
+}
+
+#[test]
+fn test_compare_selection_strategies() {
+    let sender = build_sighash_script(ACCOUNT1_ARG);
+    let receiver = build_sighash_script(ACCOUNT2_ARG);
+    let ctx = init_context(
+        Vec::new(),
         vec![
             (sender.clone(), Some(100 * ONE_CKB)),
             (sender.clone(), Some(200 * ONE_CKB)),
+            (sender.clone(), Some(300 * ONE_CKB)),
         ],
     );
-
-    let sender_input = CellInput::new(random_out_point(), 0);
-    let sender_output = CellOutput::new_builder()
-        .capacity((200 * ONE_CKB).pack())
-        .lock(sender.clone())
-        .type_(Some(type_script.clone()).pack())
-        .build();
-    let sender_data = Bytes::from(500u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(sender_input, sender_output.clone(), sender_data, None);
-
-    let receiver_acp_lock = Script::new_builder()
-        .code_hash(acp_data_hash.pack())
-        .hash_type(ScriptHashType::Data1.into())
-        .args(Bytes::from(ACCOUNT2_ARG.0.to_vec()).pack())
+    let output = CellOutput::new_builder()
+        .capacity((120 * ONE_CKB).pack())
+        .lock(receiver)
         .build();
-    let receiver_input = CellInput::new(random_out_point(), 0);
-    let receiver_output = CellOutput::new_builder()
-        .capacity((200 * ONE_CKB).pack())
-        .lock(receiver_acp_lock.clone())
-        .type_(Some(type_script.clone()).pack())
+    let tx = TransactionBuilder::default()
+        .output(output)
+        .output_data(Bytes::new().pack())
         .build();
-    let receiver_data = Bytes::from(100u128.to_le_bytes().to_vec());
-    ctx.add_live_cell(receiver_input, receiver_output.clone(), receiver_data, None);
-
-    let udt_receiver = UdtTargetReceiver::new(TransferAction::Update, receiver_acp_lock, 300);
-    let builder = UdtTransferBuilder {
-        type_script,
-        sender: sender.clone(),
-        receivers: vec![udt_receiver],
-    };
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
         .build();
-    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness.clone(), FEE_RATE);
+    let balancer = CapacityBalancer::new_simple(sender, placeholder_witness, FEE_RATE);
 
-    let account1_key = secp256k1::SecretKey::from_slice(ACCOUNT1_KEY.as_bytes()).unwrap();
-    let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![account1_key]);
-    let script_unlocker = SecpSighashUnlocker::from(Box::new(signer) as Box<_>);
-    let acp_unlocker = AcpUnlocker::from(Box::<SecpCkbRawKeySigner>::default() as Box<_>);
+    let mut cell_collector = ctx.to_live_cells_context();
+    let reports = compare_selection_strategies(
+        &tx,
+        &balancer,
+        &SelectionStrategy::ALL,
+        &mut cell_collector,
+        &ctx,
+        &ctx,
+        &ctx,
+    );
+    let outcomes = reports
+        .iter()
+        .map(|report| (report.strategy, report.outcome.as_ref().unwrap()))
+        .collect::<Vec<_>>();
+    let input_capacities = outcomes
+        .iter()
+        .map(|(_, outcome)| {
+            outcome
+                .tx
+                .input_pts_iter()
+                .map(|out_point| {
+                    let capacity: u64 = ctx.get_input(&out_point).unwrap().0.capacity().unpack();
+                    capacity / ONE_CKB
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        input_capacities,
+        vec![vec![100, 200], vec![300], vec![100, 200], vec![200]]
+    );
+    for ((_, outcome), capacities) in outcomes.iter().zip(&input_capacities) {
+        let inputs_capacity = capacities.iter().sum::<u64>() * ONE_CKB;
+        assert_eq!(outcome.input_count, capacities.len());
+        assert_eq!(
+            outcome.change_capacity,
+            inputs_capacity - 120 * ONE_CKB - outcome.fee
+        );
+        assert_eq!(
+            outcome.fee,
+            FeeRate::from_u64(FEE_RATE)
+                .fee(outcome.tx_size as u64)
+                .as_u64()
+        );
+    }
+    // one input less, a smaller transaction and a lower fee
+    assert!(outcomes[1].1.tx_size < outcomes[0].1.tx_size);
+    assert!(outcomes[1].1.fee < outcomes[0].1.fee);
+
+    // the cells are not locked in the collector
+    let (cells, _) = cell_collector
+        .collect_live_cells(
+            &CellQueryOptions::new_lock(build_sighash_script(ACCOUNT1_ARG)),
+            false,
+        )
+        .unwrap();
+    assert_eq!(cells.len(), 3);
+}
+
+#[test]
+fn test_sign_mixed_witness_layouts() {
+    let cobuild_lock = build_sighash_script(ACCOUNT1_ARG);
+    let legacy_lock = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let cobuild_input = random_out_point();
+    let legacy_input = random_out_point();
+    ctx.add_simple_live_cell(
+        cobuild_input.clone(),
+        cobuild_lock.clone(),
+        Some(300 * ONE_CKB),
+    );
+    ctx.add_simple_live_cell(
+        legacy_input.clone(),
+        legacy_lock.clone(),
+        Some(200 * ONE_CKB),
+    );
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(cobuild_input, 0))
+        .input(CellInput::new(legacy_input, 0))
+        .output(
+            CellOutput::new_builder()
+                .capacity((499 * ONE_CKB).pack())
+                .lock(build_sighash_script(ACCOUNT3_ARG))
+                .build(),
+        )
+        .output_data(Bytes::new().pack())
+        .witness(WitnessLayout::Cobuild.placeholder_witness(65).pack())
+        .witness(WitnessLayout::WitnessArgs.placeholder_witness(65).pack())
+        .build();
+
+    let keys = [ACCOUNT1_KEY, ACCOUNT2_KEY]
+        .iter()
+        .map(|key| secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap())
+        .collect::<Vec<_>>();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys);
     let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
     unlockers.insert(
         ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
-        Box::new(script_unlocker),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer.clone()) as Box<_>)),
     );
-    unlockers.insert(ScriptId::new_data1(acp_data_hash), Box::new(acp_unlocker));
+    let layouts =
+        negotiate_tx_witness_layouts(&tx, &ctx, &unlockers, WitnessLayout::Cobuild).unwrap();
+    assert_eq!(
+        layouts
+            .iter()
+            .map(|(group, layout)| (group.script.clone(), *layout))
+            .collect::<Vec<_>>(),
+        vec![
+            (cobuild_lock.clone(), WitnessLayout::Cobuild),
+            (legacy_lock.clone(), WitnessLayout::WitnessArgs),
+        ]
+    );
+    let entries = layouts
+        .iter()
+        .map(|(group, _)| SigningEntry::new(&tx, group, Bytes::from(vec![0u8; 65]), &ctx).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(entries[0].layout, WitnessLayout::Cobuild);
+    assert_eq!(entries[1].layout, WitnessLayout::WitnessArgs);
 
-    let mut cell_collector = ctx.to_live_cells_context();
-    let (tx, locked_groups) = builder
-        .build_unlocked(&mut cell_collector, &ctx, &ctx, &ctx, &balancer, &unlockers)
+    let (signed_tx, locked_groups) = unlock_tx(tx.clone(), &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    let cobuild_witness = signed_tx.witnesses().get(0).unwrap().raw_data();
+    let seal = CobuildSighashWitness::parse(&cobuild_witness).unwrap().seal;
+    assert_eq!(
+        seal,
+        signer
+            .sign(ACCOUNT1_ARG.as_bytes(), &entries[0].message, true, &tx)
+            .unwrap()
+    );
+    let slots = verify_all_signatures(&signed_tx, &ctx).unwrap();
+    let legacy_slot = slots
+        .iter()
+        .find(|slot| slot.lock_script == legacy_lock)
         .unwrap();
+    assert_eq!(legacy_slot.status, SlotStatus::Valid(ACCOUNT2_ARG));
+    let legacy_witness =
+        WitnessArgs::from_slice(&signed_tx.witnesses().get(1).unwrap().raw_data()).unwrap();
+    assert_eq!(
+        legacy_witness.lock().to_opt().unwrap().raw_data(),
+        signer
+            .sign(ACCOUNT2_ARG.as_bytes(), &entries[1].message, true, &tx)
+            .unwrap()
+    );
 
-    assert!(locked_groups.is_empty());
-    assert_eq!(tx.header_deps().len(), 0);
-    assert_eq!(tx.cell_deps().len(), 3);
-    assert_eq!(tx.inputs().len(), 3);
-    let outputs = tx.outputs().into_iter().collect::<Vec<_>>();
-    assert_eq!(outputs.len(), 3);
-    assert_eq!(outputs[0..2], vec![sender_output, receiver_output]);
-    let expected_outputs_data = vec![
-        Bytes::from(200u128.to_le_bytes().to_vec()),
-        Bytes::from(400u128.to_le_bytes().to_vec()),
-        Bytes::default(),
-    ];
-    let outputs_data = tx
-        .outputs_data()
-        .into_iter()
-        .map(|d| d.raw_data())
-        .collect::<Vec<_>>();
-    assert_eq!(outputs_data, expected_outputs_data);
-    let witnesses_len = tx
-        .witnesses()
-        .into_iter()
-        .map(|w| w.raw_data().len())
+    // an unlocker restricted to WitnessArgs can't sign the cobuild group
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(
+            SecpSighashUnlocker::from(Box::new(signer) as Box<_>)
+                .with_witness_layout(WitnessLayoutMode::WitnessArgs),
+        ),
+    );
+    assert!(matches!(
+        negotiate_tx_witness_layouts(&tx, &ctx, &unlockers, WitnessLayout::Cobuild),
+        Err(UnlockError::ScriptSigner(
+            ScriptSignError::UnsupportedWitnessLayout(WitnessLayoutKind::SighashAllOnly)
+        ))
+    ));
+    assert!(unlock_tx(tx, &ctx, &unlockers).is_err());
+}
+
+#[test]
+fn test_migrate_witness_layout() {
+    let lock1 = build_sighash_script(ACCOUNT1_ARG);
+    let lock2 = build_sighash_script(ACCOUNT2_ARG);
+    let mut ctx = init_context(Vec::new(), Vec::new());
+    let input1 = random_out_point();
+    let input2 = random_out_point();
+    ctx.add_simple_live_cell(input1.clone(), lock1.clone(), Some(300 * ONE_CKB));
+    ctx.add_simple_live_cell(input2.clone(), lock2.clone(), Some(200 * ONE_CKB));
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(input1, 0))
+        .input(CellInput::new(input2, 0))
+        .output(
+            CellOutput::new_builder()
+                .capacity((499 * ONE_CKB).pack())
+                .lock(build_sighash_script(ACCOUNT3_ARG))
+                .build(),
+        )
+        .output_data(Bytes::new().pack())
+        .witness(WitnessLayout::WitnessArgs.placeholder_witness(65).pack())
+        .witness(WitnessLayout::WitnessArgs.placeholder_witness(65).pack())
+        .build();
+
+    // an unsigned transaction is converted without signatures to drop
+    let (cobuild_tx, report) = WitnessMigrator::new(WitnessLayout::Cobuild)
+        .migrate(&tx, &ctx)
+        .unwrap();
+    assert!(report
+        .groups
+        .iter()
+        .all(|group| group.status == MigrationStatus::Converted));
+    for witness in cobuild_tx.witnesses() {
+        assert_eq!(
+            detect_witness_layout(&witness.raw_data()),
+            WitnessLayoutKind::SighashAllOnly
+        );
+    }
+
+    let keys = [ACCOUNT1_KEY, ACCOUNT2_KEY]
+        .iter()
+        .map(|key| secp256k1::SecretKey::from_slice(key.as_bytes()).unwrap())
         .collect::<Vec<_>>();
+    let signer = SecpCkbRawKeySigner::new_with_secret_keys(keys);
+    let mut unlockers: HashMap<ScriptId, Box<dyn ScriptUnlocker>> = HashMap::default();
+    unlockers.insert(
+        ScriptId::new_type(SIGHASH_TYPE_HASH.clone()),
+        Box::new(SecpSighashUnlocker::from(Box::new(signer) as Box<_>)),
+    );
+    let (signed_tx, _) = unlock_tx(tx, &ctx, &unlockers).unwrap();
+
+    // only the group of lock2 moves to cobuild, the signature of lock1 is kept
+    let (migrated_tx, report) = WitnessMigrator::new(WitnessLayout::Cobuild)
+        .only(vec![lock2.clone()])
+        .migrate(&signed_tx, &ctx)
+        .unwrap();
+    assert_eq!(report.groups[0].status, MigrationStatus::Unchanged);
+    assert_eq!(report.groups[1].status, MigrationStatus::ResignRequired);
     assert_eq!(
-        witnesses_len,
-        vec![placeholder_witness.as_slice().len(), 0, 0]
+        report.resign_required(),
+        vec![&report.groups[1].script_group]
+    );
+    assert!(!report.signatures_preserved());
+    assert_eq!(
+        migrated_tx.witnesses().get(0).unwrap().raw_data(),
+        signed_tx.witnesses().get(0).unwrap().raw_data()
+    );
+    let witness = migrated_tx.witnesses().get(1).unwrap().raw_data();
+    assert_eq!(
+        CobuildSighashWitness::parse(&witness).unwrap().seal,
+        Bytes::from(vec![0u8; 65])
     );
-    ctx.verify(tx, FEE_RATE).unwrap();
-}
 
-pub mod ckb_indexer_rpc;
-pub mod ckb_rpc;
-pub mod cycle;
-pub mod omni_lock;
-pub mod omni_lock_util;
-pub mod transaction;
+    // sign the migrated group again, then move it back to WitnessArgs
+    let (resigned_tx, locked_groups) = unlock_tx(migrated_tx, &ctx, &unlockers).unwrap();
+    assert!(locked_groups.is_empty());
+    let slots = verify_all_signatures(&resigned_tx, &ctx).unwrap();
+    assert!(slots
+        .iter()
+        .any(|slot| slot.lock_script == lock1 && slot.status == SlotStatus::Valid(ACCOUNT1_ARG)));
+    let (legacy_tx, report) = WitnessMigrator::new(WitnessLayout::WitnessArgs)
+        .migrate(&resigned_tx, &ctx)
+        .unwrap();
+    assert_eq!(
+        report.resign_required(),
+        vec![&report.groups[1].script_group]
+    );
+    assert_eq!(
+        detect_witness_layout(&legacy_tx.witnesses().get(1).unwrap().raw_data()),
+        WitnessLayoutKind::WitnessArgs
+    );
+
+    // the fields without a place in the target layout are reported
+    let mut witnesses = legacy_tx.witnesses().into_iter().collect::<Vec<_>>();
+    witnesses[0] = WitnessArgs::new_builder()
+        .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+        .input_type(Some(Bytes::from(vec![1u8])).pack())
+        .build()
+        .as_bytes()
+        .pack();
+    witnesses[1] = CobuildSighashWitness {
+        message: Some(Bytes::from(vec![4, 0, 0, 0])),
+        seal: Bytes::new(),
+    }
+    .to_bytes()
+    .pack();
+    let tx = legacy_tx
+        .as_advanced_builder()
+        .set_witnesses(witnesses)
+        .build();
+    assert!(matches!(
+        WitnessMigrator::new(WitnessLayout::Cobuild).migrate(&tx, &ctx),
+        Err(WitnessMigrationError::TypeFieldsNotMigratable(0))
+    ));
+    assert!(matches!(
+        WitnessMigrator::new(WitnessLayout::WitnessArgs).migrate(&tx, &ctx),
+        Err(WitnessMigrationError::MessageNotMigratable(1))
+    ));
+}
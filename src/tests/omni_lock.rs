@@ -800,6 +800,9 @@ fn test_omnilock_transfer_from_ownerlock() {
         ]),
         change_lock_script: None,
         force_small_change_as_fee: Some(ONE_CKB),
+        small_change_policy: Default::default(),
+        fee_payer: None,
+        limits: Default::default(),
     };
 
     let mut cell_collector = ctx.to_live_cells_context();
@@ -900,6 +903,9 @@ fn test_omnilock_transfer_from_ownerlock_wl_admin() {
         ]),
         change_lock_script: None,
         force_small_change_as_fee: Some(ONE_CKB),
+        small_change_policy: Default::default(),
+        fee_payer: None,
+        limits: Default::default(),
     };
 
     let mut cell_collector = ctx.to_live_cells_context();
@@ -1175,6 +1181,7 @@ fn test_omnilock_udt_transfer() {
         type_script,
         sender: sender.clone(),
         receivers: vec![udt_receiver],
+        change_lock: None,
     };
     let placeholder_witness = WitnessArgs::new_builder()
         .lock(Some(Bytes::from(vec![0u8; 65])).pack())
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use ckb_types::{bytes::Bytes, core::TransactionView};
+
+use crate::traits::{Signer, SignerError};
+
+/// How `CompositeSigner` dispatches `sign` to the backends matching the id
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum CompositePolicy {
+    /// Only the first matching backend signs
+    #[default]
+    FirstMatch,
+    /// Try the matching backends by priority until one succeeds
+    Fallback,
+    /// All the matching backends must sign and produce the same signature,
+    /// e.g. to cross check a HSM with a backup keystore.
+    AllMatch,
+}
+
+/// The errors of all the backends tried by `CompositeSigner`, wrapped in
+/// `SignerError::Other` so it can be downcast.
+#[derive(Debug)]
+pub struct CompositeSignerError {
+    /// The index of the backend and its error
+    pub errors: Vec<(usize, SignerError)>,
+}
+
+impl fmt::Display for CompositeSignerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all signer backends failed:")?;
+        for (idx, err) in &self.errors {
+            write!(f, " [{}] {};", idx, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CompositeSignerError {}
+
+/// A signer aggregating multiple signer backends ordered by priority, e.g. a
+/// hardware wallet holding one key and a raw key signer holding another.
+#[derive(Default)]
+pub struct CompositeSigner {
+    signers: Vec<Box<dyn Signer>>,
+    policy: CompositePolicy,
+}
+
+impl CompositeSigner {
+    pub fn new(signers: Vec<Box<dyn Signer>>) -> CompositeSigner {
+        CompositeSigner {
+            signers,
+            policy: CompositePolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: CompositePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Append a backend, it has lower priority than the existing backends
+    pub fn add_signer(&mut self, signer: Box<dyn Signer>) {
+        self.signers.push(signer);
+    }
+
+    pub fn signers(&self) -> &[Box<dyn Signer>] {
+        &self.signers
+    }
+
+    pub fn policy(&self) -> CompositePolicy {
+        self.policy
+    }
+}
+
+impl Signer for CompositeSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        self.signers.iter().any(|signer| signer.match_id(id))
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        let mut matched = self
+            .signers
+            .iter()
+            .enumerate()
+            .filter(|(_, signer)| signer.match_id(id))
+            .peekable();
+        if matched.peek().is_none() {
+            return Err(SignerError::IdNotFound);
+        }
+        match self.policy {
+            CompositePolicy::FirstMatch => {
+                let (_, signer) = matched.next().expect("peeked");
+                signer.sign(id, message, recoverable, tx)
+            }
+            CompositePolicy::Fallback => {
+                let mut errors = Vec::new();
+                for (idx, signer) in matched {
+                    match signer.sign(id, message, recoverable, tx) {
+                        Ok(signature) => return Ok(signature),
+                        Err(err) => errors.push((idx, err)),
+                    }
+                }
+                Err(SignerError::Other(CompositeSignerError { errors }.into()))
+            }
+            CompositePolicy::AllMatch => {
+                let mut signature: Option<Bytes> = None;
+                for (idx, signer) in matched {
+                    let current = signer.sign(id, message, recoverable, tx).map_err(|err| {
+                        SignerError::Other(
+                            CompositeSignerError {
+                                errors: vec![(idx, err)],
+                            }
+                            .into(),
+                        )
+                    })?;
+                    match signature.as_ref() {
+                        Some(first) if first != &current => {
+                            return Err(SignerError::Other(anyhow!(
+                                "signer backend {} produced a different signature",
+                                idx
+                            )));
+                        }
+                        Some(_) => {}
+                        None => signature = Some(current),
+                    }
+                }
+                Ok(signature.expect("peeked"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::SecpCkbRawKeySigner;
+    use crate::util::blake160;
+    use crate::SECP256K1;
+
+    struct FailingSigner(Vec<u8>);
+
+    impl Signer for FailingSigner {
+        fn match_id(&self, id: &[u8]) -> bool {
+            id == self.0.as_slice()
+        }
+
+        fn sign(
+            &self,
+            _id: &[u8],
+            _message: &[u8],
+            _recoverable: bool,
+            _tx: &TransactionView,
+        ) -> Result<Bytes, SignerError> {
+            Err(SignerError::Other(anyhow!("backend unavailable")))
+        }
+    }
+
+    #[test]
+    fn test_composite_policies() {
+        let key = secp256k1::SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let id = blake160(&pubkey.serialize()).as_bytes().to_vec();
+        let message = [7u8; 32];
+        let tx = TransactionView::new_advanced_builder().build();
+        let build = |policy| {
+            CompositeSigner::new(vec![
+                Box::new(FailingSigner(id.clone())),
+                Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![key])),
+                Box::new(SecpCkbRawKeySigner::new_with_secret_keys(vec![key])),
+            ])
+            .with_policy(policy)
+        };
+
+        assert!(build(CompositePolicy::FirstMatch)
+            .sign(&id, &message, true, &tx)
+            .is_err());
+        let signature = build(CompositePolicy::Fallback)
+            .sign(&id, &message, true, &tx)
+            .unwrap();
+        assert_eq!(signature.len(), 65);
+        let err = build(CompositePolicy::AllMatch)
+            .sign(&id, &message, true, &tx)
+            .unwrap_err();
+        match err {
+            SignerError::Other(err) => {
+                let err = err.downcast_ref::<CompositeSignerError>().unwrap();
+                assert_eq!(err.errors.len(), 1);
+                assert_eq!(err.errors[0].0, 0);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let mut signer = build(CompositePolicy::AllMatch);
+        signer.signers.remove(0);
+        assert_eq!(signer.sign(&id, &message, true, &tx).unwrap(), signature);
+        assert!(matches!(
+            signer.sign(&[0u8; 20], &message, true, &tx),
+            Err(SignerError::IdNotFound)
+        ));
+    }
+}
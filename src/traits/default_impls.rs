@@ -1,37 +1,67 @@
 use std::collections::HashMap;
+#[cfg(feature = "rpc")]
 use std::sync::Arc;
+#[cfg(feature = "rpc")]
 use std::thread;
+#[cfg(feature = "indexer")]
 use std::time::Duration;
 
 use anyhow::anyhow;
 use ckb_crypto::secp::Pubkey;
+#[cfg(feature = "indexer")]
+use jsonrpc_core::ErrorCode;
+#[cfg(feature = "rpc")]
 use lru::LruCache;
+#[cfg(feature = "rpc")]
 use parking_lot::Mutex;
 use thiserror::Error;
 
 use ckb_hash::blake2b_256;
+#[cfg(feature = "rpc")]
 use ckb_jsonrpc_types::{self as json_types, Either};
 use ckb_types::{
     bytes::Bytes,
-    core::{BlockView, DepType, HeaderView, TransactionView},
-    packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction, TransactionReader},
+    core::{BlockView, DepType, TransactionView},
+    packed::{CellDep, CellOutput, OutPoint, Script},
     prelude::*,
     H160,
 };
-
-use super::{
-    offchain_impls::CollectResult, OffchainCellCollector, OffchainCellDepResolver,
-    OffchainTransactionDependencyProvider,
+#[cfg(feature = "rpc")]
+use ckb_types::{
+    core::HeaderView,
+    packed::{Byte32, Transaction, TransactionReader},
 };
-use crate::rpc::ckb_indexer::{Order, SearchKey, Tip};
-use crate::rpc::{CkbRpcClient, IndexerRpcClient};
+
+use super::OffchainCellDepResolver;
+#[cfg(feature = "rpc")]
+use super::{OffchainTransactionDependencyProvider, TransactionDependencyError};
+#[cfg(feature = "rpc")]
+use crate::deadline::Deadline;
+#[cfg(feature = "indexer")]
+use crate::health::check_health;
+#[cfg(feature = "rpc")]
+use crate::health::{check_node_health, HealthReport};
+#[cfg(feature = "indexer")]
+use crate::rpc::ckb_indexer::{IndexerFilterSupport, Order, SearchKey, Tip};
+#[cfg(feature = "rpc")]
+use crate::rpc::CkbRpcClient;
+#[cfg(feature = "indexer")]
+use crate::rpc::IndexerRpcClient;
+#[cfg(feature = "indexer")]
+use crate::rpc::RpcError;
+use crate::secp;
+#[cfg(feature = "indexer")]
 use crate::traits::{
-    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
-    LiveCell, QueryOrder, Signer, SignerError, TransactionDependencyError,
-    TransactionDependencyProvider,
+    offchain_impls::CollectResult, CellCollector, CellCollectorError, CellQueryOptions, LiveCell,
+    OffchainCellCollector, QueryOrder,
 };
+use crate::traits::{CellDepResolver, Signer, SignerError};
+#[cfg(feature = "rpc")]
+use crate::traits::{HeaderDepResolver, TransactionDependencyProvider};
 use crate::types::ScriptId;
-use crate::util::{get_max_mature_number, serialize_signature, zeroize_privkey};
+#[cfg(feature = "indexer")]
+use crate::util::get_max_mature_number;
+use crate::util::zeroize_privkey;
 use crate::SECP256K1;
 use crate::{
     constants::{
@@ -203,15 +233,28 @@ impl CellDepResolver for DefaultCellDepResolver {
 }
 
 /// A header_dep resolver use ckb jsonrpc client as backend
+#[cfg(feature = "rpc")]
 pub struct DefaultHeaderDepResolver {
     ckb_client: CkbRpcClient,
 }
+#[cfg(feature = "rpc")]
 impl DefaultHeaderDepResolver {
     pub fn new(ckb_client: &str) -> DefaultHeaderDepResolver {
         let ckb_client = CkbRpcClient::new(ckb_client);
         DefaultHeaderDepResolver { ckb_client }
     }
+
+    /// Bound the rpc calls by `deadline`, see [`crate::deadline`]
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.ckb_client.set_deadline(deadline);
+    }
+
+    /// Check the node, see [`crate::health`]
+    pub fn health_check(&self) -> HealthReport {
+        check_node_health(&self.ckb_client)
+    }
 }
+#[cfg(feature = "rpc")]
 impl HeaderDepResolver for DefaultHeaderDepResolver {
     fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
         if let Some(block_hash) = self
@@ -239,14 +282,17 @@ impl HeaderDepResolver for DefaultHeaderDepResolver {
 }
 
 /// A cell collector use ckb-indexer as backend
+#[cfg(feature = "indexer")]
 #[derive(Clone)]
 pub struct DefaultCellCollector {
     indexer_client: IndexerRpcClient,
     ckb_client: CkbRpcClient,
     offchain: OffchainCellCollector,
     acceptable_indexer_leftbehind: u64,
+    filter_support: Option<IndexerFilterSupport>,
 }
 
+#[cfg(feature = "indexer")]
 impl DefaultCellCollector {
     pub fn new(ckb_client: &str) -> DefaultCellCollector {
         let indexer_client = IndexerRpcClient::new(ckb_client);
@@ -256,9 +302,34 @@ impl DefaultCellCollector {
             ckb_client,
             offchain: OffchainCellCollector::default(),
             acceptable_indexer_leftbehind: 1,
+            filter_support: None,
         }
     }
 
+    /// Bound the rpc calls by `deadline`, see [`crate::deadline`]
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.indexer_client.set_deadline(deadline);
+        self.ckb_client.set_deadline(deadline);
+    }
+
+    /// Check the node and the indexer, see [`crate::health`]
+    pub fn health_check(&self) -> HealthReport {
+        check_health(&self.ckb_client, &self.indexer_client)
+    }
+
+    /// Forget a cell spent out of band (e.g. by a transaction submitted by
+    /// other means), it's never returned again until it expires like the
+    /// cells locked by `lock_cell`.
+    pub fn invalidate_out_point(&mut self, out_point: &OutPoint, tip_block_number: u64) {
+        self.offchain
+            .invalidate_out_point(out_point, tip_block_number);
+    }
+
+    /// Forget the cells spent by the transactions of `block`
+    pub fn invalidate_block(&mut self, block: &BlockView) {
+        self.offchain.invalidate_block(block);
+    }
+
     /// THe acceptable ckb-indexer leftbehind block number (default = 1)
     pub fn acceptable_indexer_leftbehind(&self) -> u64 {
         self.acceptable_indexer_leftbehind
@@ -268,6 +339,36 @@ impl DefaultCellCollector {
         self.acceptable_indexer_leftbehind = value;
     }
 
+    /// The search key filters the indexer supports, probed from the node
+    /// version on first use. The unsupported filters are applied by the
+    /// collector instead. When the probe fails none of them is used, the
+    /// result is kept either way until [`Self::invalidate_filter_support`].
+    pub fn filter_support(&mut self) -> IndexerFilterSupport {
+        if let Some(support) = self.filter_support {
+            return support;
+        }
+        let support = match self.ckb_client.local_node_info() {
+            Ok(node) => IndexerFilterSupport::from_node_version(&node.version),
+            Err(err) => {
+                log::debug!("probe the node version failed: {}", err);
+                IndexerFilterSupport::NONE
+            }
+        };
+        self.filter_support = Some(support);
+        support
+    }
+
+    /// Override the probed filter support, `None` to probe again
+    pub fn set_filter_support(&mut self, support: Option<IndexerFilterSupport>) {
+        self.filter_support = support;
+    }
+
+    /// Probe the filter support again on the next collection, e.g. after the
+    /// node is upgraded or was unreachable
+    pub fn invalidate_filter_support(&mut self) {
+        self.filter_support = None;
+    }
+
     /// Check if ckb-indexer synced with ckb node. This will check every 50ms for 100 times (more than 5s in total, since ckb-indexer's poll interval is 2.0s).
     pub fn check_ckb_chain(&mut self) -> Result<(), CellCollectorError> {
         let tip_number = self
@@ -303,6 +404,7 @@ impl DefaultCellCollector {
     }
 }
 
+#[cfg(feature = "indexer")]
 impl CellCollector for DefaultCellCollector {
     fn collect_live_cells(
         &mut self,
@@ -336,15 +438,33 @@ impl CellCollector for DefaultCellCollector {
                 .map(|c| (c.out_point.clone(), c))
                 .collect();
             let locked_cells = self.offchain.locked_cells.clone();
-            let search_key = SearchKey::from(query.clone());
+            let mut search_key = self
+                .filter_support()
+                .degrade(SearchKey::from(query.clone()));
             const MAX_LIMIT: u32 = 4096;
             let mut limit: u32 = query.limit.unwrap_or(16);
             let mut last_cursor: Option<json_types::JsonBytes> = None;
             while total_capacity < query.min_total_capacity {
-                let page = self
-                    .indexer_client
-                    .get_cells(search_key.clone(), order.clone(), limit.into(), last_cursor)
-                    .map_err(|err| CellCollectorError::Internal(err.into()))?;
+                let page = match self.indexer_client.get_cells(
+                    search_key.clone(),
+                    order.clone(),
+                    limit.into(),
+                    last_cursor.clone(),
+                ) {
+                    Ok(page) => page,
+                    // an indexer older than its version tells rejects the
+                    // newer filters, retry without them
+                    Err(RpcError::Rpc(err))
+                        if err.code == ErrorCode::InvalidParams
+                            && !IndexerFilterSupport::NONE.supports(&search_key) =>
+                    {
+                        log::debug!("the indexer rejected the search key filters: {}", err);
+                        self.filter_support = Some(IndexerFilterSupport::NONE);
+                        search_key = IndexerFilterSupport::NONE.degrade(search_key);
+                        continue;
+                    }
+                    Err(err) => return Err(CellCollectorError::Internal(err.into())),
+                };
                 if page.objects.is_empty() {
                     break;
                 }
@@ -405,6 +525,7 @@ impl CellCollector for DefaultCellCollector {
     }
 }
 
+#[cfg(feature = "rpc")]
 struct DefaultTxDepProviderInner {
     rpc_client: CkbRpcClient,
     tx_cache: LruCache<Byte32, TransactionView>,
@@ -414,11 +535,13 @@ struct DefaultTxDepProviderInner {
 }
 
 /// A transaction dependency provider use ckb rpc client as backend, and with LRU cache supported
+#[cfg(feature = "rpc")]
 pub struct DefaultTransactionDependencyProvider {
     // since we will mainly deal with LruCache, so use Mutex here
     inner: Arc<Mutex<DefaultTxDepProviderInner>>,
 }
 
+#[cfg(feature = "rpc")]
 impl Clone for DefaultTransactionDependencyProvider {
     fn clone(&self) -> DefaultTransactionDependencyProvider {
         let inner = Arc::clone(&self.inner);
@@ -426,6 +549,7 @@ impl Clone for DefaultTransactionDependencyProvider {
     }
 }
 
+#[cfg(feature = "rpc")]
 impl DefaultTransactionDependencyProvider {
     /// Arguments:
     ///   * `url` is the ckb http jsonrpc server url
@@ -444,6 +568,18 @@ impl DefaultTransactionDependencyProvider {
         }
     }
 
+    /// Bound the rpc calls by `deadline`, shared by the clones of the
+    /// provider
+    pub fn set_deadline(&self, deadline: Option<Deadline>) {
+        self.inner.lock().rpc_client.set_deadline(deadline);
+    }
+
+    /// Check the node, see [`crate::health`]
+    pub fn health_check(&self) -> HealthReport {
+        let rpc_client = self.inner.lock().rpc_client.clone();
+        check_node_health(&rpc_client)
+    }
+
     pub fn apply_tx(
         &mut self,
         tx: Transaction,
@@ -454,6 +590,33 @@ impl DefaultTransactionDependencyProvider {
         Ok(())
     }
 
+    /// Drop the cached cell, the next query asks the node again
+    pub fn invalidate_out_point(&self, out_point: &OutPoint) {
+        let mut inner = self.inner.lock();
+        inner.cell_cache.pop(out_point);
+        inner.offchain_cache.invalidate_out_point(out_point);
+    }
+
+    /// Drop everything cached about `block`: its header, its transactions and
+    /// the cells they created or spent. Use it when the block is committed or
+    /// rolled back by a reorg.
+    pub fn invalidate_block(&self, block: &BlockView) {
+        let mut inner = self.inner.lock();
+        inner.header_cache.pop(&block.hash());
+        for tx in block.transactions() {
+            inner.tx_cache.pop(&tx.hash());
+            for out_point in tx.input_pts_iter() {
+                inner.cell_cache.pop(&out_point);
+            }
+            for index in 0..tx.outputs().len() {
+                inner
+                    .cell_cache
+                    .pop(&OutPoint::new(tx.hash(), index as u32));
+            }
+        }
+        inner.offchain_cache.invalidate_block(block);
+    }
+
     pub fn get_cell_with_data(
         &self,
         out_point: &OutPoint,
@@ -463,19 +626,7 @@ impl DefaultTransactionDependencyProvider {
             return Ok(pair.clone());
         }
 
-        let cell_with_status = inner
-            .rpc_client
-            .get_live_cell(out_point.clone().into(), true)
-            .map_err(|err| TransactionDependencyError::Other(err.into()))?;
-        if cell_with_status.status != "live" {
-            return Err(TransactionDependencyError::Other(anyhow!(
-                "invalid cell status: {:?}",
-                cell_with_status.status
-            )));
-        }
-        let cell = cell_with_status.cell.unwrap();
-        let output = CellOutput::from(cell.output);
-        let output_data = cell.data.unwrap().content.into_bytes();
+        let (output, output_data) = fetch_live_cell(&inner.rpc_client, out_point)?;
         inner
             .cell_cache
             .put(out_point.clone(), (output.clone(), output_data.clone()));
@@ -483,6 +634,32 @@ impl DefaultTransactionDependencyProvider {
     }
 }
 
+/// Max number of `get_live_cell` requests sent concurrently by
+/// `DefaultTransactionDependencyProvider::get_cells_with_data`
+#[cfg(feature = "rpc")]
+const MAX_CONCURRENT_CELL_REQUESTS: usize = 8;
+
+#[cfg(feature = "rpc")]
+fn fetch_live_cell(
+    rpc_client: &CkbRpcClient,
+    out_point: &OutPoint,
+) -> Result<(CellOutput, Bytes), TransactionDependencyError> {
+    let cell_with_status = rpc_client
+        .get_live_cell(out_point.clone().into(), true)
+        .map_err(|err| TransactionDependencyError::Other(err.into()))?;
+    if cell_with_status.status != "live" {
+        return Err(TransactionDependencyError::Other(anyhow!(
+            "invalid cell status: {:?}",
+            cell_with_status.status
+        )));
+    }
+    let cell = cell_with_status.cell.unwrap();
+    let output = CellOutput::from(cell.output);
+    let output_data = cell.data.unwrap().content.into_bytes();
+    Ok((output, output_data))
+}
+
+#[cfg(feature = "rpc")]
 impl TransactionDependencyProvider for DefaultTransactionDependencyProvider {
     fn get_transaction(
         &self,
@@ -537,6 +714,59 @@ impl TransactionDependencyProvider for DefaultTransactionDependencyProvider {
         self.get_cell_with_data(out_point)
             .map(|(_, output_data)| output_data)
     }
+    /// Cells missing from the caches are fetched concurrently, and the lock
+    /// of the caches is not held while waiting for the rpc responses.
+    fn get_cells_with_data(
+        &self,
+        out_points: &[OutPoint],
+    ) -> Result<Vec<(CellOutput, Bytes)>, TransactionDependencyError> {
+        let mut results = Vec::with_capacity(out_points.len());
+        let rpc_client = {
+            let mut inner = self.inner.lock();
+            for out_point in out_points {
+                let offchain = inner
+                    .offchain_cache
+                    .get_cell(out_point)
+                    .and_then(|output| {
+                        let data = inner.offchain_cache.get_cell_data(out_point)?;
+                        Ok((output, data))
+                    })
+                    .ok();
+                results.push(offchain.or_else(|| inner.cell_cache.get(out_point).cloned()));
+            }
+            inner.rpc_client.clone()
+        };
+
+        let missing = results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.is_none())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        for chunk in missing.chunks(MAX_CONCURRENT_CELL_REQUESTS) {
+            let fetched = thread::scope(|scope| {
+                let handles = chunk
+                    .iter()
+                    .map(|idx| {
+                        let rpc_client = &rpc_client;
+                        let out_point = &out_points[*idx];
+                        scope.spawn(move || fetch_live_cell(rpc_client, out_point))
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("fetch live cell thread panicked"))
+                    .collect::<Vec<_>>()
+            });
+            let mut inner = self.inner.lock();
+            for (idx, pair) in chunk.iter().zip(fetched) {
+                let pair = pair?;
+                inner.cell_cache.put(out_points[*idx].clone(), pair.clone());
+                results[*idx] = Some(pair);
+            }
+        }
+        Ok(results.into_iter().map(|pair| pair.unwrap()).collect())
+    }
     fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
         let mut inner = self.inner.lock();
         if let Some(header) = inner.header_cache.get(block_hash) {
@@ -630,16 +860,16 @@ impl Signer for SecpCkbRawKeySigner {
                 message.len()
             )));
         }
-        let msg =
-            secp256k1::Message::from_digest_slice(message).expect("Convert to message failed");
         let key = self.keys.get(&H160::from_slice(id).unwrap()).unwrap();
-        if recoverable {
-            let sig = SECP256K1.sign_ecdsa_recoverable(&msg, key);
-            Ok(Bytes::from(serialize_signature(&sig).to_vec()))
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(message);
+        let sig = if recoverable {
+            secp::sign_recoverable(&key.secret_bytes(), &digest).map(|sig| sig.to_vec())
         } else {
-            let sig = SECP256K1.sign_ecdsa(&msg, key);
-            Ok(Bytes::from(sig.serialize_compact().to_vec()))
+            secp::sign_compact(&key.secret_bytes(), &digest).map(|sig| sig.to_vec())
         }
+        .map_err(|err| SignerError::Other(anyhow!(err)))?;
+        Ok(Bytes::from(sig))
     }
 }
 
@@ -650,6 +880,42 @@ impl Drop for SecpCkbRawKeySigner {
         }
     }
 }
+#[cfg(all(test, feature = "indexer"))]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_filter_support_probe() {
+        let server = MockServer::start();
+        let mut probe = server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("local_node_info");
+            then.status(500);
+        });
+        let mut cell_collector = DefaultCellCollector::new(server.base_url().as_str());
+        // the failed probe falls back to the collector filters, and is kept
+        assert_eq!(cell_collector.filter_support(), IndexerFilterSupport::NONE);
+        assert_eq!(cell_collector.filter_support(), IndexerFilterSupport::NONE);
+        probe.assert_hits(1);
+
+        probe.delete();
+        let probe = server.mock(|when, then| {
+            when.method(POST).path("/").body_contains("local_node_info");
+            then.status(200).body(
+                r#"{"id":1,"jsonrpc":"2.0","result":{"version":"0.110.2 (6d7d3a6 2023-09-20)","node_id":"QmNode","active":true,"addresses":[],"protocols":[],"connections":"0x0"}}"#,
+            );
+        });
+        assert_eq!(cell_collector.filter_support(), IndexerFilterSupport::NONE);
+        probe.assert_hits(0);
+        cell_collector.invalidate_filter_support();
+        assert_eq!(
+            cell_collector.filter_support(),
+            IndexerFilterSupport::from_node_version("0.110.2")
+        );
+        probe.assert_hits(1);
+    }
+}
+
 #[cfg(test)]
 mod anyhow_tests {
     use anyhow::anyhow;
@@ -1,15 +1,21 @@
 //! The traits defined here is intent to describe the requirements of current
 //!  library code and only implemented the trait in upper level code.
 
+pub mod composite_impls;
 pub mod default_impls;
 pub mod dummy_impls;
+#[cfg(feature = "rpc")]
 pub mod light_client_impls;
 pub mod offchain_impls;
-
-pub use default_impls::{
-    DefaultCellCollector, DefaultCellDepResolver, DefaultHeaderDepResolver,
-    DefaultTransactionDependencyProvider, SecpCkbRawKeySigner,
-};
+pub mod tenant_impls;
+
+pub use composite_impls::{CompositePolicy, CompositeSigner, CompositeSignerError};
+#[cfg(feature = "indexer")]
+pub use default_impls::DefaultCellCollector;
+pub use default_impls::{DefaultCellDepResolver, SecpCkbRawKeySigner};
+#[cfg(feature = "rpc")]
+pub use default_impls::{DefaultHeaderDepResolver, DefaultTransactionDependencyProvider};
+#[cfg(feature = "rpc")]
 pub use light_client_impls::{
     LightClientCellCollector, LightClientHeaderDepResolver,
     LightClientTransactionDependencyProvider,
@@ -18,6 +24,7 @@ pub use offchain_impls::{
     OffchainCellCollector, OffchainCellDepResolver, OffchainHeaderDepResolver,
     OffchainTransactionDependencyProvider,
 };
+pub use tenant_impls::{CellVisibility, TenantReservations, TenantScope};
 
 use dyn_clone::DynClone;
 use thiserror::Error;
@@ -35,6 +42,7 @@ use ckb_types::{
     prelude::*,
 };
 
+use crate::deadline::DeadlineExceeded;
 use crate::{rpc::ckb_indexer::SearchMode, util::is_mature};
 
 /// Signer errors
@@ -82,6 +90,9 @@ pub enum TransactionDependencyError {
     #[error("the resource is not found in the provider: `{0}`")]
     NotFound(String),
 
+    #[error(transparent)]
+    DeadlineExceeded(#[from] DeadlineExceeded),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -108,6 +119,23 @@ pub trait TransactionDependencyProvider: Sync + Send {
         &self,
         block_hash: &Byte32,
     ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError>;
+
+    /// Get the outputs and data of many cells, the result is in the same order
+    /// as `out_points`. Implementations backed by a remote node should fetch
+    /// the cells concurrently.
+    fn get_cells_with_data(
+        &self,
+        out_points: &[OutPoint],
+    ) -> Result<Vec<(CellOutput, Bytes)>, TransactionDependencyError> {
+        out_points
+            .iter()
+            .map(|out_point| {
+                let output = self.get_cell(out_point)?;
+                let data = self.get_cell_data(out_point)?;
+                Ok((output, data))
+            })
+            .collect()
+    }
 }
 
 // Implement CellDataProvider trait is currently for `DaoCalculator`
@@ -174,6 +202,9 @@ pub enum CellCollectorError {
     #[error(transparent)]
     Internal(anyhow::Error),
 
+    #[error(transparent)]
+    DeadlineExceeded(#[from] DeadlineExceeded),
+
     #[error(transparent)]
     Other(anyhow::Error),
 }
@@ -247,6 +278,8 @@ pub struct CellQueryOptions {
     pub data_len_range: Option<ValueRangeOption>,
     pub capacity_range: Option<ValueRangeOption>,
     pub block_range: Option<ValueRangeOption>,
+    /// Filter cell by the prefix of its data
+    pub data_prefix: Option<Bytes>,
 
     pub order: QueryOrder,
     pub limit: Option<u32>,
@@ -268,6 +301,7 @@ impl CellQueryOptions {
             data_len_range: None,
             capacity_range: None,
             block_range: None,
+            data_prefix: None,
             with_data: None,
             order: QueryOrder::Asc,
             limit: None,
@@ -282,6 +316,23 @@ impl CellQueryOptions {
     pub fn new_type(primary_script: Script) -> CellQueryOptions {
         CellQueryOptions::new(primary_script, PrimaryScriptType::Type)
     }
+    /// Check the primary script, the args is matched by prefix when
+    /// `script_search_mode` is `SearchMode::Prefix`, otherwise the script must
+    /// be exactly the same.
+    fn match_primary_script(&self, script: &Script) -> bool {
+        match self.script_search_mode {
+            Some(SearchMode::Prefix) => {
+                script.code_hash() == self.primary_script.code_hash()
+                    && script.hash_type() == self.primary_script.hash_type()
+                    && script
+                        .args()
+                        .raw_data()
+                        .starts_with(&self.primary_script.args().raw_data())
+            }
+            _ => script == &self.primary_script,
+        }
+    }
+
     pub fn match_cell(&self, cell: &LiveCell, max_mature_number: u64) -> bool {
         fn extract_raw_data(script: &Script) -> Vec<u8> {
             [
@@ -301,7 +352,7 @@ impl CellQueryOptions {
         match self.primary_type {
             PrimaryScriptType::Lock => {
                 // check primary script
-                if cell.output.lock() != self.primary_script {
+                if !self.match_primary_script(&cell.output.lock()) {
                     return false;
                 }
 
@@ -326,8 +377,9 @@ impl CellQueryOptions {
             }
             PrimaryScriptType::Type => {
                 // check primary script
-                if cell.output.type_().to_opt().as_ref() != Some(&self.primary_script) {
-                    return false;
+                match cell.output.type_().to_opt() {
+                    Some(script) if self.match_primary_script(&script) => {}
+                    _ => return false,
                 }
 
                 // if primary is `type`, secondary is `lock`
@@ -365,6 +417,11 @@ impl CellQueryOptions {
                 return false;
             }
         }
+        if let Some(prefix) = self.data_prefix.as_ref() {
+            if !cell.output_data.starts_with(prefix) {
+                return false;
+            }
+        }
         if let Some(range) = self.capacity_range {
             let capacity: u64 = cell.output.capacity().unpack();
             if !range.match_value(capacity) {
@@ -410,11 +467,18 @@ pub trait CellCollector: DynClone {
     fn reset(&mut self);
 }
 
+dyn_clone::clone_trait_object!(CellCollector);
+
 pub trait CellDepResolver {
     /// Resolve cell dep by script.
     ///
     /// When a new script is added, transaction builders use CellDepResolver to find the corresponding cell deps and add them to the transaction.
     fn resolve(&self, script: &Script) -> Option<CellDep>;
+
+    /// Resolve cell deps of many scripts, the result is in the same order as `scripts`.
+    fn resolve_batch(&self, scripts: &[Script]) -> Vec<Option<CellDep>> {
+        scripts.iter().map(|script| self.resolve(script)).collect()
+    }
 }
 pub trait HeaderDepResolver {
     /// Resolve header dep by trancation hash
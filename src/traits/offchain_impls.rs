@@ -4,7 +4,7 @@ use std::collections::HashMap;
 
 use ckb_types::{
     bytes::Bytes,
-    core::{HeaderView, TransactionView},
+    core::{BlockView, HeaderView, TransactionView},
     packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction},
     prelude::*,
     H256,
@@ -153,6 +153,26 @@ impl OffchainCellCollector {
         self.locked_cells.clear();
         self.live_cells.clear();
     }
+
+    /// Forget a cell spent out of band: it's dropped from the offchain live
+    /// cells and locked, so a lagging indexer can not return it either.
+    pub fn invalidate_out_point(&mut self, out_point: &OutPoint, tip_block_number: u64) {
+        self.live_cells
+            .retain(|(cell, _)| &cell.out_point != out_point);
+        self.locked_cells.insert(
+            (out_point.tx_hash().unpack(), out_point.index().unpack()),
+            tip_block_number,
+        );
+    }
+
+    /// Invalidate the cells spent by the transactions of `block`
+    pub fn invalidate_block(&mut self, block: &BlockView) {
+        for tx in block.transactions() {
+            for out_point in tx.input_pts_iter() {
+                self.invalidate_out_point(&out_point, block.number());
+            }
+        }
+    }
 }
 
 /// offchain transaction dependency provider
@@ -191,6 +211,26 @@ impl OffchainTransactionDependencyProvider {
         Ok(())
     }
 
+    /// Forget a cell spent out of band
+    pub fn invalidate_out_point(&mut self, out_point: &OutPoint) {
+        self.cells
+            .remove(&(out_point.tx_hash().unpack(), out_point.index().unpack()));
+    }
+
+    /// Forget the transactions of `block` and the cells they created or
+    /// spent, the node is the source of truth for them from now on.
+    pub fn invalidate_block(&mut self, block: &BlockView) {
+        for tx in block.transactions() {
+            let tx_hash: H256 = tx.hash().unpack();
+            self.tx_tip_num_map.remove(&tx_hash);
+            self.txs.remove(&tx_hash);
+            self.cells.retain(|(hash, _), _| hash != &tx_hash);
+            for out_point in tx.input_pts_iter() {
+                self.invalidate_out_point(&out_point);
+            }
+        }
+    }
+
     /// Remove offchain data
     pub(crate) fn truncate(&mut self, current_tip_block_number: u64) {
         let (keep, removed) = self
@@ -264,3 +304,51 @@ impl TransactionDependencyProvider for OffchainTransactionDependencyProvider {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        core::{BlockBuilder, TransactionBuilder},
+        packed::CellInput,
+    };
+
+    fn build_tx(input: OutPoint) -> TransactionView {
+        TransactionBuilder::default()
+            .input(CellInput::new(input, 0))
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Bytes::new().pack())
+            .build()
+    }
+
+    #[test]
+    fn test_invalidate_offchain_cells() {
+        let parent_tx = build_tx(OutPoint::default());
+        let mut provider = OffchainTransactionDependencyProvider::new();
+        let mut collector = OffchainCellCollector::default();
+        provider.apply_tx(parent_tx.data(), 1).unwrap();
+        collector.apply_tx(parent_tx.data(), 1).unwrap();
+        let out_point = OutPoint::new(parent_tx.hash(), 0);
+        assert!(provider.get_cell(&out_point).is_ok());
+
+        // spent by a transaction submitted out of band
+        provider.invalidate_out_point(&out_point);
+        collector.invalidate_out_point(&out_point, 1);
+        assert!(provider.get_cell(&out_point).is_err());
+        assert!(provider.get_transaction(&parent_tx.hash()).is_ok());
+        assert!(collector.live_cells.is_empty());
+        assert!(collector
+            .locked_cells
+            .contains_key(&(parent_tx.hash().unpack(), 0)));
+
+        // the parent transaction is committed
+        let child_tx = build_tx(out_point);
+        provider.apply_tx(child_tx.data(), 1).unwrap();
+        let block = BlockBuilder::default()
+            .transaction(parent_tx.clone())
+            .build();
+        provider.invalidate_block(&block);
+        assert!(provider.get_transaction(&parent_tx.hash()).is_err());
+        assert!(provider.get_transaction(&child_tx.hash()).is_ok());
+    }
+}
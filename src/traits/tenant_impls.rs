@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ckb_types::{
+    packed::{OutPoint, Transaction},
+    prelude::*,
+    H256,
+};
+use parking_lot::Mutex;
+
+use crate::traits::{CellCollector, CellCollectorError, CellQueryOptions, LiveCell};
+
+/// Decide whether a cell is visible to a tenant.
+pub type CellVisibility = Arc<dyn Fn(&LiveCell) -> bool + Send + Sync>;
+
+/// Cell reservations shared by all the tenants of one backend.
+///
+/// A reserved cell is hidden from every `TenantScope` until the owner tenant
+/// releases it (by `TenantScope::reset` or `TenantReservations::release`).
+#[derive(Clone, Default)]
+pub struct TenantReservations {
+    inner: Arc<Mutex<HashMap<(H256, u32), String>>>,
+}
+
+impl TenantReservations {
+    pub fn new() -> TenantReservations {
+        TenantReservations::default()
+    }
+
+    /// The tenant which reserved the cell
+    pub fn owner_of(&self, out_point: &OutPoint) -> Option<String> {
+        self.inner.lock().get(&out_point_key(out_point)).cloned()
+    }
+
+    /// Release all cells reserved by the tenant
+    pub fn release(&self, tenant_id: &str) {
+        self.inner.lock().retain(|_, owner| owner != tenant_id);
+    }
+
+    /// Reserve the cell for the tenant, return false if it is already
+    /// reserved by another tenant.
+    pub fn reserve(&self, tenant_id: &str, out_point: &OutPoint) -> bool {
+        let mut reservations = self.inner.lock();
+        match reservations.get(&out_point_key(out_point)) {
+            Some(owner) => owner == tenant_id,
+            None => {
+                reservations.insert(out_point_key(out_point), tenant_id.to_string());
+                true
+            }
+        }
+    }
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+/// A CellCollector wrapper which isolate cells between tenants sharing the
+/// same lock script (an omnibus account).
+///
+/// Only the cells accepted by `visibility` are returned, and collected cells
+/// are reserved in the shared `TenantReservations` so concurrent builds of
+/// other tenants will not consume them.
+#[derive(Clone)]
+pub struct TenantScope<C> {
+    tenant_id: String,
+    collector: C,
+    reservations: TenantReservations,
+    visibility: Option<CellVisibility>,
+}
+
+impl<C: CellCollector + Clone> TenantScope<C> {
+    pub fn new(
+        tenant_id: impl Into<String>,
+        collector: C,
+        reservations: TenantReservations,
+    ) -> TenantScope<C> {
+        TenantScope {
+            tenant_id: tenant_id.into(),
+            collector,
+            reservations,
+            visibility: None,
+        }
+    }
+
+    /// Only cells accepted by `visibility` will be collected by this tenant
+    pub fn with_visibility(mut self, visibility: CellVisibility) -> Self {
+        self.visibility = Some(visibility);
+        self
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn reservations(&self) -> &TenantReservations {
+        &self.reservations
+    }
+
+    fn is_visible(&self, cell: &LiveCell) -> bool {
+        self.visibility
+            .as_ref()
+            .map(|visibility| visibility(cell))
+            .unwrap_or(true)
+    }
+}
+
+impl<C: CellCollector + Clone> CellCollector for TenantScope<C> {
+    /// All the matched cells are fetched from the inner collector, since cells
+    /// hidden from this tenant must be skipped before counting
+    /// `min_total_capacity`.
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let mut all_query = query.clone();
+        all_query.min_total_capacity = u64::MAX;
+        let (cells, _) = self.collector.collect_live_cells(&all_query, false)?;
+
+        // hold the lock so other tenants can not reserve the same cells meanwhile
+        let mut reservations = self.reservations.inner.lock();
+        let mut total_capacity = 0;
+        let mut collected = Vec::new();
+        for cell in cells {
+            if total_capacity >= query.min_total_capacity {
+                break;
+            }
+            let key = out_point_key(&cell.out_point);
+            if reservations.contains_key(&key) || !self.is_visible(&cell) {
+                continue;
+            }
+            let capacity: u64 = cell.output.capacity().unpack();
+            total_capacity += capacity;
+            if apply_changes {
+                reservations.insert(key, self.tenant_id.clone());
+            }
+            collected.push(cell);
+        }
+        Ok((collected, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        if !self.reservations.reserve(&self.tenant_id, &out_point) {
+            return Err(CellCollectorError::Other(anyhow!(
+                "cell {} is reserved by another tenant",
+                out_point
+            )));
+        }
+        self.collector.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        for input in tx.raw().inputs() {
+            self.reservations
+                .reserve(&self.tenant_id, &input.previous_output());
+        }
+        self.collector.apply_tx(tx, tip_block_number)
+    }
+
+    /// Also release all the cells reserved by this tenant
+    fn reset(&mut self) {
+        self.reservations.release(&self.tenant_id);
+        self.collector.reset();
+    }
+}
@@ -81,28 +81,31 @@ impl<'a> ChangeBuilder for DefaultChangeBuilder<'a> {
     fn check_balance(&mut self, input: TransactionInput, tx: &mut TransactionBuilder) -> bool {
         self.inputs.push(input);
 
-        let outputs_capacity: u64 = tx
-            .get_outputs()
-            .iter()
-            .map(|o| Unpack::<u64>::unpack(&o.capacity()))
-            .sum();
         let (change_output, change_output_data) = self.get_change();
         let occupied_capacity = change_output
             .occupied_capacity(Capacity::bytes(change_output_data.len()).unwrap())
-            .unwrap()
-            .as_u64();
-
+            .unwrap();
         let fee_calculator = self.configuration.fee_calculator();
-        let required_capacity = outputs_capacity
-            + occupied_capacity
-            + fee_calculator.fee(self.configuration.estimate_tx_size);
-
-        let inputs_capacity: u64 = self
+        let fee = Capacity::shannons(fee_calculator.fee(self.configuration.estimate_tx_size));
+
+        // an overflowed capacity can never be balanced
+        let required_capacity = occupied_capacity.safe_add(fee).and_then(|base| {
+            tx.get_outputs().iter().try_fold(base, |total, output| {
+                total.safe_add(Capacity::shannons(output.capacity().unpack()))
+            })
+        });
+        let inputs_capacity = self
             .inputs
             .iter()
-            .map(|i| Unpack::<u64>::unpack(&i.previous_output().capacity()))
-            .sum();
-        inputs_capacity >= required_capacity
+            .try_fold(Capacity::zero(), |total, input| {
+                total.safe_add(Capacity::shannons(
+                    input.previous_output().capacity().unpack(),
+                ))
+            });
+        match (inputs_capacity, required_capacity) {
+            (Ok(inputs_capacity), Ok(required_capacity)) => inputs_capacity >= required_capacity,
+            _ => false,
+        }
     }
 
     fn finalize(&self, mut tx: TransactionBuilder) -> TransactionView {
@@ -100,6 +100,7 @@ impl SudtTransactionBuilder {
     }
 }
 
+#[cfg(feature = "indexer")]
 #[test]
 fn test_parse_u128_from_sudt_tx_output_data() {
     use crate::Address;
@@ -179,19 +180,21 @@ impl CkbTransactionBuilder for SudtTransactionBuilder {
             let mut sudt_input_iter = input_iter.clone();
             sudt_input_iter.set_type_script(Some(sudt_type_script));
 
-            let outputs_sudt_amount: u128 = tx
-                .outputs_data
-                .iter()
-                .map(|data| parse_u128(data.raw_data().as_ref()))
-                .collect::<Result<Vec<u128>, TxBuilderError>>()
-                .map(|u128_vec| u128_vec.iter().sum())?;
+            let outputs_sudt_amount = tx.outputs_data.iter().try_fold(0u128, |total, data| {
+                let amount = parse_u128(data.raw_data().as_ref())?;
+                total
+                    .checked_add(amount)
+                    .ok_or(TxBuilderError::AmountOverflow(total, amount))
+            })?;
 
-            let mut inputs_sudt_amount = 0;
+            let mut inputs_sudt_amount: u128 = 0;
 
             for input in sudt_input_iter {
                 let input = input?;
                 let input_amount = parse_u128(input.live_cell.output_data.as_ref())?;
-                inputs_sudt_amount += input_amount;
+                inputs_sudt_amount = inputs_sudt_amount.checked_add(input_amount).ok_or(
+                    TxBuilderError::AmountOverflow(inputs_sudt_amount, input_amount),
+                )?;
                 input_iter.push_input(input);
                 if inputs_sudt_amount >= outputs_sudt_amount {
                     let change_output_data: Bytes = (inputs_sudt_amount - outputs_sudt_amount)
@@ -80,8 +80,7 @@ impl ScriptHandler for SudtHandler {
         script_group: &mut ScriptGroup,
         context: &dyn HandlerContext,
     ) -> Result<bool, TxBuilderError> {
-        if context.as_any().is::<SudtContext>()
-            && ScriptId::from(&script_group.script) == self.sudt_script_id
+        if context.as_any().is::<SudtContext>() && self.sudt_script_id.matches(&script_group.script)
         {
             tx_builder.dedup_cell_deps(self.cell_deps.clone());
             if script_group.input_indices.is_empty() {
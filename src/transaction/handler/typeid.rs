@@ -3,9 +3,13 @@ use ckb_types::{
     packed::{Bytes, CellInput},
     prelude::*,
 };
+use parking_lot::Mutex;
 
 use crate::{
-    core::TransactionBuilder, tx_builder::TxBuilderError, NetworkInfo, ScriptGroup, ScriptId,
+    core::TransactionBuilder,
+    traits::CellCollector,
+    tx_builder::{check_type_id_collision, TxBuilderError},
+    NetworkInfo, ScriptGroup, ScriptId,
 };
 
 use super::{HandlerContext, ScriptHandler};
@@ -17,6 +21,22 @@ pub struct TypeIdContext;
 
 impl HandlerContext for TypeIdContext {}
 
+/// Same as `TypeIdContext`, and check no live cell already has the computed
+/// type id before setting it.
+pub struct TypeIdGuardContext {
+    cell_collector: Mutex<Box<dyn CellCollector + Send>>,
+}
+
+impl TypeIdGuardContext {
+    pub fn new(cell_collector: Box<dyn CellCollector + Send>) -> Self {
+        Self {
+            cell_collector: Mutex::new(cell_collector),
+        }
+    }
+}
+
+impl HandlerContext for TypeIdGuardContext {}
+
 impl ScriptHandler for TypeIdHandler {
     fn build_transaction(
         &self,
@@ -24,7 +44,8 @@ impl ScriptHandler for TypeIdHandler {
         script_group: &mut ScriptGroup,
         context: &dyn HandlerContext,
     ) -> Result<bool, TxBuilderError> {
-        if context.as_any().is::<TypeIdContext>()
+        let guard = context.as_any().downcast_ref::<TypeIdGuardContext>();
+        if (context.as_any().is::<TypeIdContext>() || guard.is_some())
             && ScriptId::from(&script_group.script).is_type_id()
             && script_group.input_indices.is_empty()
             && script_group.output_indices.len() == 1
@@ -40,6 +61,9 @@ impl ScriptHandler for TypeIdHandler {
                 .as_builder()
                 .args(args)
                 .build();
+            if let Some(guard) = guard {
+                check_type_id_collision(&output_type_script, guard.cell_collector.lock().as_mut())?;
+            }
             let updated_output = output
                 .as_builder()
                 .type_(Some(output_type_script.clone()).pack())
@@ -1,7 +1,11 @@
-use ckb_types::core;
+use std::sync::Arc;
+
+use ckb_types::{bytes::Bytes, core};
 
 use crate::{
-    traits::{dummy_impls::DummyTransactionDependencyProvider, SecpCkbRawKeySigner},
+    traits::{
+        dummy_impls::DummyTransactionDependencyProvider, SecpCkbRawKeySigner, Signer, SignerError,
+    },
     unlock::{
         MultisigConfig, ScriptUnlocker, SecpMultisigScriptSigner, SecpMultisigUnlocker, UnlockError,
     },
@@ -13,6 +17,7 @@ pub struct Secp256k1Blake160MultisigAllSigner {}
 
 pub struct Secp256k1Blake160MultisigAllSignerContext {
     keys: Vec<secp256k1::SecretKey>,
+    signer: Option<Arc<dyn Signer>>,
     multisig_config: MultisigConfig,
 }
 
@@ -20,18 +25,49 @@ impl Secp256k1Blake160MultisigAllSignerContext {
     pub fn new(keys: Vec<secp256k1::SecretKey>, multisig_config: MultisigConfig) -> Self {
         Self {
             keys,
+            signer: None,
+            multisig_config,
+        }
+    }
+
+    /// Sign with a signer backend instead of raw keys, e.g. a
+    /// `CompositeSigner` mixing hardware wallets and raw keys.
+    pub fn new_with_signer(signer: Arc<dyn Signer>, multisig_config: MultisigConfig) -> Self {
+        Self {
+            keys: Vec::new(),
+            signer: Some(signer),
             multisig_config,
         }
     }
 
     pub fn build_multisig_unlocker(&self) -> SecpMultisigUnlocker {
-        let signer = SecpCkbRawKeySigner::new_with_secret_keys(self.keys.clone());
-        let multisig_signer =
-            SecpMultisigScriptSigner::new(Box::new(signer), self.multisig_config.clone());
+        let signer: Box<dyn Signer> = match self.signer.as_ref() {
+            Some(signer) => Box::new(SharedSigner(Arc::clone(signer))),
+            None => Box::new(SecpCkbRawKeySigner::new_with_secret_keys(self.keys.clone())),
+        };
+        let multisig_signer = SecpMultisigScriptSigner::new(signer, self.multisig_config.clone());
         SecpMultisigUnlocker::new(multisig_signer)
     }
 }
 
+struct SharedSigner(Arc<dyn Signer>);
+
+impl Signer for SharedSigner {
+    fn match_id(&self, id: &[u8]) -> bool {
+        self.0.match_id(id)
+    }
+
+    fn sign(
+        &self,
+        id: &[u8],
+        message: &[u8],
+        recoverable: bool,
+        tx: &core::TransactionView,
+    ) -> Result<Bytes, SignerError> {
+        self.0.sign(id, message, recoverable, tx)
+    }
+}
+
 impl SignContext for Secp256k1Blake160MultisigAllSignerContext {}
 
 impl CKBScriptSigner for Secp256k1Blake160MultisigAllSigner {
@@ -1,17 +1,106 @@
-use std::collections::HashSet;
-
 use anyhow::anyhow;
 use ckb_types::{
-    core::{TransactionBuilder, TransactionView},
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
     packed::{CellInput, Script},
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{resolve_cell_deps, TxBuilder, TxBuilderError};
+use crate::rpc::ckb_indexer::SearchMode;
 use crate::traits::{
-    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
-    TransactionDependencyProvider,
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider, ValueRangeOption,
 };
+use crate::types::ScriptId;
+use crate::util::parse_udt_amount;
+
+/// How to pick the receiver's acp cell when more than one cell matches.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum AcpCellSelectStrategy {
+    /// Pick the cell with the largest capacity
+    #[default]
+    LargestCapacity,
+    /// Pick the cell holding the largest udt amount
+    LargestAmount,
+    /// Pick the first cell returned by the cell collector
+    First,
+    /// Return an error if more than one cell matches
+    Unique,
+}
+
+/// Find the acp cell of `udt_type` token whose lock args starts with
+/// `lock_args_prefix`. When multiple cells exist, `strategy` decides which one
+/// is returned, ties are resolved by the order of the cell collector.
+///
+/// The cells are collected without applying changes, the caller should lock
+/// the returned cell if it will be used as an input.
+pub fn find_acp_cell(
+    cell_collector: &mut dyn CellCollector,
+    acp_script_id: &ScriptId,
+    lock_args_prefix: &[u8],
+    udt_type: &Script,
+    strategy: AcpCellSelectStrategy,
+) -> Result<LiveCell, TxBuilderError> {
+    let acp_lock = Script::new_builder()
+        .code_hash(acp_script_id.code_hash.pack())
+        .hash_type(acp_script_id.hash_type.into())
+        .args(Bytes::from(lock_args_prefix.to_vec()).pack())
+        .build();
+    let mut query = CellQueryOptions::new_lock(acp_lock.clone());
+    query.script_search_mode = Some(SearchMode::Prefix);
+    query.secondary_script = Some(udt_type.clone());
+    query.data_len_range = Some(ValueRangeOption::new_min(16));
+    query.min_total_capacity = u64::MAX;
+    let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+    // secondary script is matched by prefix, filter out the tokens with longer args
+    let mut candidates = cells
+        .into_iter()
+        .filter(|cell| cell.output.type_().to_opt().as_ref() == Some(udt_type))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Err(TxBuilderError::Other(anyhow!(
+            "acp cell not found, lock={:?}, type={:?}",
+            acp_lock,
+            udt_type
+        )));
+    }
+
+    let select_max = |candidates: Vec<LiveCell>, key: fn(&LiveCell) -> u128| {
+        let mut selected: Option<(u128, LiveCell)> = None;
+        for cell in candidates {
+            let value = key(&cell);
+            if selected.as_ref().map_or(true, |(max, _)| value > *max) {
+                selected = Some((value, cell));
+            }
+        }
+        selected
+            .map(|(_, cell)| cell)
+            .expect("candidates not empty")
+    };
+    let cell = match strategy {
+        AcpCellSelectStrategy::LargestCapacity => select_max(candidates, |cell| {
+            let capacity: u64 = cell.output.capacity().unpack();
+            capacity as u128
+        }),
+        AcpCellSelectStrategy::LargestAmount => select_max(candidates, |cell| {
+            parse_udt_amount(cell.output_data.as_ref()).unwrap_or(0)
+        }),
+        AcpCellSelectStrategy::First => candidates.remove(0),
+        AcpCellSelectStrategy::Unique => {
+            if candidates.len() > 1 {
+                return Err(TxBuilderError::Other(anyhow!(
+                    "found {} acp cells, lock={:?}, type={:?}",
+                    candidates.len(),
+                    acp_lock,
+                    udt_type
+                )));
+            }
+            candidates.remove(0)
+        }
+    };
+    Ok(cell)
+}
 
 #[derive(Clone, Debug)]
 pub struct AcpTransferReceiver {
@@ -28,6 +117,7 @@ impl AcpTransferReceiver {
 }
 /// Transfer capacity to already exists acp cell, the type script and cell data
 /// will be copied.
+#[derive(Debug, Clone)]
 pub struct AcpTransferBuilder {
     pub receivers: Vec<AcpTransferReceiver>,
 }
@@ -45,8 +135,7 @@ impl TxBuilder for AcpTransferBuilder {
         _header_dep_resolver: &dyn HeaderDepResolver,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, TxBuilderError> {
-        #[allow(clippy::mutable_key_type)]
-        let mut cell_deps = HashSet::new();
+        let mut cell_dep_scripts = Vec::new();
         let mut inputs = Vec::new();
         let mut outputs = Vec::new();
         let mut outputs_data = Vec::new();
@@ -61,7 +150,8 @@ impl TxBuilder for AcpTransferBuilder {
             }
             let input_cell = &cells[0];
             let input = CellInput::new(input_cell.out_point.clone(), 0);
-            let output_capacity = input_capacity + receiver.capacity;
+            let output_capacity = Capacity::shannons(input_capacity)
+                .safe_add(Capacity::shannons(receiver.capacity))?;
             let output = input_cell
                 .output
                 .clone()
@@ -70,25 +160,18 @@ impl TxBuilder for AcpTransferBuilder {
                 .build();
             let output_data = input_cell.output_data.clone();
 
-            let lock_cell_dep = cell_dep_resolver
-                .resolve(&receiver.lock_script)
-                .ok_or_else(|| {
-                    TxBuilderError::ResolveCellDepFailed(receiver.lock_script.clone())
-                })?;
-            cell_deps.insert(lock_cell_dep);
+            cell_dep_scripts.push(receiver.lock_script.clone());
             if let Some(type_script) = input_cell.output.type_().to_opt() {
-                let cell_dep = cell_dep_resolver
-                    .resolve(&type_script)
-                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
-                cell_deps.insert(cell_dep);
+                cell_dep_scripts.push(type_script);
             }
 
             inputs.push(input);
             outputs.push(output);
             outputs_data.push(output_data.pack());
         }
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
         Ok(TransactionBuilder::default()
-            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_cell_deps(cell_deps)
             .set_inputs(inputs)
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
@@ -1,5 +1,3 @@
-use std::collections::HashSet;
-
 use anyhow::anyhow;
 use ckb_types::{
     bytes::Bytes,
@@ -8,14 +6,16 @@ use ckb_types::{
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{resolve_cell_deps, TxBuilder, TxBuilderError};
 use crate::constants::{CHEQUE_CELL_SINCE, SIGHASH_TYPE_HASH};
 use crate::traits::{
     CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
     TransactionDependencyProvider, ValueRangeOption,
 };
-use crate::types::ScriptId;
+use crate::types::{LockHashPrefix, ScriptId};
+use crate::util::parse_udt_amount;
 
+#[derive(Debug, Clone)]
 pub struct ChequeClaimBuilder {
     /// The cheque cells to claim, all cells must have same lock script and same
     /// type script and cell data length is equals to 16.
@@ -57,23 +57,18 @@ impl TxBuilder for ChequeClaimBuilder {
             )));
         }
 
-        #[allow(clippy::mutable_key_type)]
-        let mut cell_deps = HashSet::new();
         let mut inputs = self.inputs.clone();
         inputs.push(self.receiver_input.clone());
 
-        let receiver_input_cell =
-            tx_dep_provider.get_cell(&self.receiver_input.previous_output())?;
-        let receiver_input_data =
-            tx_dep_provider.get_cell_data(&self.receiver_input.previous_output())?;
+        let out_points = inputs
+            .iter()
+            .map(|input| input.previous_output())
+            .collect::<Vec<_>>();
+        let mut input_cells = tx_dep_provider.get_cells_with_data(&out_points)?;
+        let (receiver_input_cell, receiver_input_data) = input_cells.pop().unwrap();
         let receiver_type_script = receiver_input_cell.type_().to_opt().ok_or_else(|| {
             TxBuilderError::InvalidParameter(anyhow!("receiver input missing type script"))
         })?;
-        let receiver_input_lock_cell_dep =
-            cell_dep_resolver
-                .resolve(&receiver_input_cell.lock())
-                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(receiver_input_cell.lock()))?;
-        cell_deps.insert(receiver_input_lock_cell_dep);
 
         if receiver_input_data.len() != 16 {
             return Err(TxBuilderError::InvalidParameter(anyhow!(
@@ -87,18 +82,10 @@ impl TxBuilder for ChequeClaimBuilder {
             u128::from_le_bytes(amount_bytes)
         };
 
-        let receiver_type_cell_dep = cell_dep_resolver
-            .resolve(&receiver_type_script)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(receiver_type_script.clone()))?;
-        cell_deps.insert(receiver_type_cell_dep);
-
-        let mut cheque_total_amount = 0;
-        let mut cheque_total_capacity = 0;
+        let mut cheque_total_amount: u128 = 0;
+        let mut cheque_total_capacity = Capacity::zero();
         let mut last_lock_script = None;
-        for input in &self.inputs {
-            let out_point = input.previous_output();
-            let input_cell = tx_dep_provider.get_cell(&out_point)?;
-            let input_data = tx_dep_provider.get_cell_data(&out_point)?;
+        for (input, (input_cell, input_data)) in self.inputs.iter().zip(input_cells) {
             let type_script = receiver_input_cell.type_().to_opt().ok_or_else(|| {
                 TxBuilderError::InvalidParameter(anyhow!(
                     "cheque input missing type script: {}",
@@ -133,13 +120,11 @@ impl TxBuilder for ChequeClaimBuilder {
                     "all cheque input lock script must be the same"
                 )));
             }
-            let lock_cell_dep = cell_dep_resolver
-                .resolve(&lock_script)
-                .ok_or(TxBuilderError::ResolveCellDepFailed(lock_script))?;
-
-            cell_deps.insert(lock_cell_dep);
-            cheque_total_amount += input_amount;
-            cheque_total_capacity += input_capacity;
+            cheque_total_amount = cheque_total_amount.checked_add(input_amount).ok_or(
+                TxBuilderError::AmountOverflow(cheque_total_amount, input_amount),
+            )?;
+            cheque_total_capacity =
+                cheque_total_capacity.safe_add(Capacity::shannons(input_capacity))?;
         }
 
         let cheque_lock_script = last_lock_script.unwrap();
@@ -150,8 +135,9 @@ impl TxBuilder for ChequeClaimBuilder {
                 cheque_lock_args.len()
             )));
         }
-        let sender_lock_hash = self.sender_lock_script.calc_script_hash();
-        if sender_lock_hash.as_slice()[0..20] != cheque_lock_args.as_ref()[20..40] {
+        if !LockHashPrefix::from_script(&self.sender_lock_script)
+            .matches_args(&cheque_lock_args, 20)
+        {
             return Err(TxBuilderError::InvalidParameter(anyhow!(
                 "sender lock script is not match with cheque lock script args"
             )));
@@ -159,7 +145,12 @@ impl TxBuilder for ChequeClaimBuilder {
 
         let receiver_output = receiver_input_cell;
         let receiver_output_data = {
-            let receiver_output_amount = receiver_input_amount + cheque_total_amount;
+            let receiver_output_amount = receiver_input_amount
+                .checked_add(cheque_total_amount)
+                .ok_or(TxBuilderError::AmountOverflow(
+                    receiver_input_amount,
+                    cheque_total_amount,
+                ))?;
             Bytes::from(receiver_output_amount.to_le_bytes().to_vec())
         };
         let sender_output = CellOutput::new_builder()
@@ -168,11 +159,19 @@ impl TxBuilder for ChequeClaimBuilder {
             .build();
         let sender_output_data = Bytes::new();
 
+        let cell_deps = resolve_cell_deps(
+            cell_dep_resolver,
+            &[
+                receiver_output.lock(),
+                receiver_type_script,
+                cheque_lock_script,
+            ],
+        )?;
         let outputs = vec![receiver_output, sender_output];
         let outputs_data = vec![receiver_output_data.pack(), sender_output_data.pack()];
 
         Ok(TransactionBuilder::default()
-            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_cell_deps(cell_deps)
             .set_inputs(inputs)
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
@@ -180,6 +179,7 @@ impl TxBuilder for ChequeClaimBuilder {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct ChequeWithdrawBuilder {
     /// The cheque cells to withdraw, all cells must have same lock script and same
     /// type script and cell data length is equals to 16.
@@ -225,10 +225,9 @@ impl TxBuilder for ChequeWithdrawBuilder {
         let mut last_lock_script = None;
         let mut last_type_script = None;
         let mut cheque_total_amount: u128 = 0;
-        let mut cheque_total_capacity: u64 = 0;
-        for out_point in &self.out_points {
-            let input_cell = tx_dep_provider.get_cell(out_point)?;
-            let input_data = tx_dep_provider.get_cell_data(out_point)?;
+        let mut cheque_total_capacity = Capacity::zero();
+        let input_cells = tx_dep_provider.get_cells_with_data(&self.out_points)?;
+        for (out_point, (input_cell, input_data)) in self.out_points.iter().zip(input_cells) {
             let lock_script = input_cell.lock();
             let type_script = input_cell.type_().to_opt().ok_or_else(|| {
                 TxBuilderError::InvalidParameter(anyhow!(
@@ -252,6 +251,12 @@ impl TxBuilder for ChequeWithdrawBuilder {
                 )));
             }
 
+            if input_data.len() != 16 {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "invalid cheque input cell data length, expected: 16, got: {}",
+                    input_data.len()
+                )));
+            }
             let input_amount = {
                 let mut amount_bytes = [0u8; 16];
                 amount_bytes.copy_from_slice(input_data.as_ref());
@@ -260,21 +265,17 @@ impl TxBuilder for ChequeWithdrawBuilder {
             let input_capacity: u64 = input_cell.capacity().unpack();
             let input = CellInput::new(out_point.clone(), CHEQUE_CELL_SINCE);
 
-            cheque_total_capacity += input_capacity;
-            cheque_total_amount += input_amount;
+            cheque_total_capacity =
+                cheque_total_capacity.safe_add(Capacity::shannons(input_capacity))?;
+            cheque_total_amount = cheque_total_amount.checked_add(input_amount).ok_or(
+                TxBuilderError::AmountOverflow(cheque_total_amount, input_amount),
+            )?;
             inputs.push(input);
         }
 
         let cheque_lock_script = last_lock_script.unwrap();
         let type_script = last_type_script.unwrap();
 
-        let cheque_cell_dep = cell_dep_resolver
-            .resolve(&cheque_lock_script)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(cheque_lock_script.clone()))?;
-        let type_cell_dep = cell_dep_resolver
-            .resolve(&type_script)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
-
         let cheque_lock_args = cheque_lock_script.args().raw_data();
         if cheque_lock_args.len() != 40 {
             return Err(TxBuilderError::InvalidParameter(anyhow!(
@@ -291,14 +292,15 @@ impl TxBuilder for ChequeWithdrawBuilder {
                 self.sender_lock_script
             )));
         }
-        let sender_lock_hash = self.sender_lock_script.calc_script_hash();
-        if sender_lock_hash.as_slice()[0..20] != cheque_lock_args.as_ref()[20..40] {
+        if !LockHashPrefix::from_script(&self.sender_lock_script)
+            .matches_args(&cheque_lock_args, 20)
+        {
             return Err(TxBuilderError::InvalidParameter(anyhow!(
                 "sender lock script is match with cheque lock script args"
             )));
         }
 
-        let mut cell_deps = vec![cheque_cell_dep, type_cell_dep];
+        let mut cell_dep_scripts = vec![cheque_lock_script.clone(), type_script.clone()];
         let (sender_lock, total_capacity, total_amount) =
             if let Some(script_id) = self.acp_script_id.as_ref() {
                 let acp_lock = Script::new_builder()
@@ -308,7 +310,7 @@ impl TxBuilder for ChequeWithdrawBuilder {
                     .build();
                 let mut query = CellQueryOptions::new_lock(acp_lock.clone());
                 query.secondary_script = Some(type_script.clone());
-                query.data_len_range = Some(ValueRangeOption::new_min(16));
+                query.data_len_range = Some(ValueRangeOption::new_exact(16));
                 let (acp_cells, _) = cell_collector.collect_live_cells(&query, true)?;
                 if acp_cells.is_empty() {
                     return Err(TxBuilderError::Other(anyhow!(
@@ -317,23 +319,21 @@ impl TxBuilder for ChequeWithdrawBuilder {
                     )));
                 }
                 let acp_cell = &acp_cells[0];
-                let mut amount_bytes = [0u8; 16];
-                amount_bytes.copy_from_slice(acp_cell.output_data.as_ref());
-                let acp_amount = u128::from_le_bytes(amount_bytes);
+                let acp_amount = parse_udt_amount(acp_cell.output_data.as_ref())
+                    .map_err(|err| TxBuilderError::InvalidParameter(err.into()))?;
                 let acp_capacity = acp_cell
                     .output
                     .occupied_capacity(Capacity::bytes(acp_cell.output_data.len()).unwrap())
-                    .expect("occupied_capacity")
-                    .as_u64();
-                let acp_cell_dep = cell_dep_resolver
-                    .resolve(&acp_lock)
-                    .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(acp_lock.clone()))?;
-                cell_deps.push(acp_cell_dep);
+                    .expect("occupied_capacity");
+                cell_dep_scripts.push(acp_lock.clone());
                 inputs.push(CellInput::new(acp_cell.out_point.clone(), 0));
+                let total_amount = cheque_total_amount.checked_add(acp_amount).ok_or(
+                    TxBuilderError::AmountOverflow(cheque_total_amount, acp_amount),
+                )?;
                 (
                     acp_lock,
-                    cheque_total_capacity + acp_capacity,
-                    cheque_total_amount + acp_amount,
+                    cheque_total_capacity.safe_add(acp_capacity)?,
+                    total_amount,
                 )
             } else {
                 (
@@ -353,8 +353,9 @@ impl TxBuilder for ChequeWithdrawBuilder {
         let outputs = vec![sender_output];
         let outputs_data = vec![sender_output_data.pack()];
 
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
         Ok(TransactionBuilder::default()
-            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_cell_deps(cell_deps)
             .set_inputs(inputs)
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
@@ -0,0 +1,199 @@
+//! Compare the coin selection strategies on the same transaction.
+//!
+//! The capacity balancer takes the cells in the order of the cell collector.
+//! [`compare_selection_strategies`] balances the same base transaction once
+//! per [`SelectionStrategy`], with the cells of the collector reordered by the
+//! strategy, and reports the size, fee, change and input count of each
+//! result. Nothing is locked in the collector.
+
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    packed::{OutPoint, Transaction},
+    prelude::*,
+};
+
+use super::{
+    balance_tx_capacity_with_breakdown, plan::DryRunCellCollector, BalanceTxCapacityError,
+    CapacityBalancer, ChangeDecision,
+};
+use crate::traits::{
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    LiveCell, TransactionDependencyProvider,
+};
+
+/// The order in which the cells are offered to the balancer
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SelectionStrategy {
+    /// The order of the cell collector, what the balancer does by default
+    CollectorOrder,
+    /// The largest cells first, the fewest inputs
+    LargestFirst,
+    /// The smallest cells first, consolidates the small cells
+    SmallestFirst,
+    /// The smallest single cell covering the required capacity, or the
+    /// largest cells first when no cell covers it alone
+    BestFit,
+}
+
+impl SelectionStrategy {
+    pub const ALL: [SelectionStrategy; 4] = [
+        SelectionStrategy::CollectorOrder,
+        SelectionStrategy::LargestFirst,
+        SelectionStrategy::SmallestFirst,
+        SelectionStrategy::BestFit,
+    ];
+
+    /// Pick the cells to reach `min_total_capacity` from `cells`
+    fn select(&self, mut cells: Vec<LiveCell>, min_total_capacity: u64) -> Vec<LiveCell> {
+        let capacity = |cell: &LiveCell| -> u64 { cell.output.capacity().unpack() };
+        match self {
+            SelectionStrategy::CollectorOrder => {}
+            SelectionStrategy::LargestFirst => {
+                cells.sort_by_key(|cell| std::cmp::Reverse(capacity(cell)))
+            }
+            SelectionStrategy::SmallestFirst => cells.sort_by_key(capacity),
+            SelectionStrategy::BestFit => {
+                let best = cells
+                    .iter()
+                    .filter(|cell| capacity(cell) >= min_total_capacity)
+                    .min_by_key(|cell| capacity(cell))
+                    .cloned();
+                if let Some(cell) = best {
+                    return vec![cell];
+                }
+                cells.sort_by_key(|cell| std::cmp::Reverse(capacity(cell)));
+            }
+        }
+        let mut total = 0u64;
+        cells
+            .into_iter()
+            .take_while(|cell| {
+                let reached = total >= min_total_capacity;
+                total = total.saturating_add(capacity(cell));
+                !reached
+            })
+            .collect()
+    }
+}
+
+/// The balanced transaction of a strategy
+#[derive(Debug, Clone)]
+pub struct SelectionOutcome {
+    pub tx: TransactionView,
+    pub input_count: usize,
+    /// The serialized size of the transaction in a block, in bytes
+    pub tx_size: usize,
+    pub fee: u64,
+    /// The capacity of the change cell, 0 if there is none
+    pub change_capacity: u64,
+    pub change: ChangeDecision,
+}
+
+#[derive(Debug)]
+pub struct StrategyReport {
+    pub strategy: SelectionStrategy,
+    pub outcome: Result<SelectionOutcome, BalanceTxCapacityError>,
+}
+
+/// Balance `tx` with each of `strategies`, see the [module documentation](self).
+///
+/// The witnesses are the placeholders of the balancer, the fee is the one
+/// of the signed transaction.
+pub fn compare_selection_strategies(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    strategies: &[SelectionStrategy],
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Vec<StrategyReport> {
+    let mut reports = Vec::with_capacity(strategies.len());
+    for strategy in strategies {
+        // every strategy starts from its own clone of `cell_collector`
+        let mut collector = StrategyCellCollector {
+            inner: DryRunCellCollector::new(&*cell_collector),
+            strategy: *strategy,
+        };
+        let outcome = balance_tx_capacity_with_breakdown(
+            tx,
+            balancer,
+            &mut collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )
+        .map(|(tx, fee_breakdown)| {
+            let change_capacity = match fee_breakdown.change {
+                ChangeDecision::ChangeCell(index) => tx
+                    .output(index)
+                    .map(|output| Unpack::<Capacity>::unpack(&output.capacity()).as_u64())
+                    .unwrap_or_default(),
+                _ => 0,
+            };
+            SelectionOutcome {
+                input_count: tx.inputs().len(),
+                tx_size: tx.data().as_reader().serialized_size_in_block(),
+                fee: fee_breakdown.fee,
+                change_capacity,
+                change: fee_breakdown.change,
+                tx,
+            }
+        });
+        reports.push(StrategyReport {
+            strategy: *strategy,
+            outcome,
+        });
+    }
+    reports
+}
+
+/// Offer the cells of the wrapped dry run collector in the strategy order
+#[derive(Clone)]
+struct StrategyCellCollector<'a> {
+    inner: DryRunCellCollector<'a>,
+    strategy: SelectionStrategy,
+}
+
+impl<'a> CellCollector for StrategyCellCollector<'a> {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let mut all_query = query.clone();
+        all_query.min_total_capacity = u64::MAX;
+        let (cells, _) = self.inner.collect_live_cells(&all_query, false)?;
+        let cells = self.strategy.select(cells, query.min_total_capacity);
+        let total_capacity = cells
+            .iter()
+            .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .fold(0u64, u64::saturating_add);
+        if apply_changes {
+            for cell in &cells {
+                self.inner.lock_cell(cell.out_point.clone(), 0)?;
+            }
+        }
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.lock_cell(out_point, tip_block_number)
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.inner.apply_tx(tx, tip_block_number)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
@@ -0,0 +1,562 @@
+//! Instantiate the shipped transaction builders from a JSON or TOML document.
+//!
+//! A document describes one builder, the `builder` field selects it:
+//!
+//! ```json
+//! {
+//!     "builder": "capacity_transfer",
+//!     "outputs": [
+//!         { "address": "ckt1qyq86vaa6e8tsruv5ngcd5tp7lcvcewxy7cquuksvj", "capacity": "120.5" }
+//!     ]
+//! }
+//! ```
+//!
+//! Lock scripts are given as addresses, capacities in CKB (e.g. `"120.5"`) and
+//! udt amounts as decimal strings. The format is defined by the serde types of
+//! this module, there is no JSON Schema of it: unknown fields are rejected
+//! when parsing, and [`BuilderConfig::build`] validates the values against the
+//! network, the error names the offending field. The omni-lock builder is not supported,
+//! its lock config can't be described by an address.
+
+use std::str::FromStr;
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    packed::{CellInput, CellOutput, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+#[cfg(feature = "builders-dao")]
+use ckb_types::{core::FeeRate, packed::OutPoint};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[cfg(feature = "builders-dao")]
+use super::dao::{
+    DaoDepositBuilder, DaoDepositReceiver, DaoPrepareBuilder, DaoPrepareItem, DaoWithdrawBuilder,
+    DaoWithdrawItem, DaoWithdrawReceiver,
+};
+use super::{
+    acp::{AcpTransferBuilder, AcpTransferReceiver},
+    cheque::{ChequeClaimBuilder, ChequeWithdrawBuilder},
+    sweep::SweepBuilder,
+    transfer::CapacityTransferBuilder,
+    TxBuilder,
+};
+#[cfg(feature = "builders-udt")]
+use super::{
+    udt::{UdtIssueBuilder, UdtTargetReceiver, UdtTransferBuilder, UdtType},
+    TransferAction,
+};
+use crate::types::{Address, HumanCapacity, NetworkType, ScriptId};
+
+/// The placeholder lock length of a sweep lock when it's not configured (a
+/// secp256k1 signature)
+const DEFAULT_PLACEHOLDER_LEN: usize = 65;
+
+#[derive(Error, Debug)]
+pub enum BuilderConfigError {
+    #[error("parse builder config error: `{0}`")]
+    Parse(String),
+
+    #[error("invalid field `{field}`: {reason}")]
+    InvalidField { field: String, reason: String },
+}
+
+impl BuilderConfigError {
+    fn invalid<F: Into<String>, R: ToString>(field: F, reason: R) -> BuilderConfigError {
+        BuilderConfigError::InvalidField {
+            field: field.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    pub address: String,
+    pub capacity: String,
+    pub type_script: Option<json_types::Script>,
+    pub data: Option<json_types::JsonBytes>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReceiverConfig {
+    pub address: String,
+    pub capacity: String,
+}
+
+#[cfg(feature = "builders-dao")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaoPrepareItemConfig {
+    pub out_point: json_types::OutPoint,
+    /// Move the prepared cell to this address, keep the lock if not set
+    pub address: Option<String>,
+}
+
+#[cfg(feature = "builders-udt")]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferActionConfig {
+    #[default]
+    Create,
+    Update,
+}
+
+#[cfg(feature = "builders-udt")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdtReceiverConfig {
+    pub address: String,
+    pub amount: String,
+    #[serde(default)]
+    pub action: TransferActionConfig,
+    /// The capacity of the created cell, only for the `create` action
+    pub capacity: Option<String>,
+}
+
+#[cfg(feature = "builders-udt")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UdtTypeConfig {
+    Sudt,
+    Xudt(json_types::JsonBytes),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptIdConfig {
+    pub code_hash: H256,
+    pub hash_type: json_types::ScriptHashType,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepLockConfig {
+    pub address: String,
+    /// The length of the placeholder lock field, 65 by default
+    pub placeholder_len: Option<usize>,
+}
+
+/// The config document of a builder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "builder", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BuilderConfig {
+    CapacityTransfer {
+        outputs: Vec<OutputConfig>,
+    },
+    AcpTransfer {
+        receivers: Vec<ReceiverConfig>,
+    },
+    #[cfg(feature = "builders-dao")]
+    DaoDeposit {
+        receivers: Vec<ReceiverConfig>,
+    },
+    #[cfg(feature = "builders-dao")]
+    DaoPrepare {
+        items: Vec<DaoPrepareItemConfig>,
+    },
+    #[cfg(feature = "builders-dao")]
+    DaoWithdraw {
+        out_points: Vec<json_types::OutPoint>,
+        receiver: String,
+        /// Pay the fee from the withdrawn capacity at this fee rate
+        fee_rate: Option<u64>,
+    },
+    #[cfg(feature = "builders-udt")]
+    UdtIssue {
+        udt_type: UdtTypeConfig,
+        script_id: ScriptIdConfig,
+        owner: String,
+        receivers: Vec<UdtReceiverConfig>,
+    },
+    #[cfg(feature = "builders-udt")]
+    UdtTransfer {
+        type_script: json_types::Script,
+        sender: String,
+        receivers: Vec<UdtReceiverConfig>,
+    },
+    ChequeClaim {
+        inputs: Vec<json_types::OutPoint>,
+        receiver_input: json_types::OutPoint,
+        sender: String,
+    },
+    ChequeWithdraw {
+        out_points: Vec<json_types::OutPoint>,
+        sender: String,
+        acp_script_id: Option<ScriptIdConfig>,
+    },
+    Sweep {
+        locks: Vec<SweepLockConfig>,
+    },
+}
+
+impl BuilderConfig {
+    pub fn from_json(content: &str) -> Result<BuilderConfig, BuilderConfigError> {
+        serde_json::from_str(content).map_err(|err| BuilderConfigError::Parse(err.to_string()))
+    }
+
+    #[cfg(feature = "toml-config")]
+    pub fn from_toml(content: &str) -> Result<BuilderConfig, BuilderConfigError> {
+        toml::from_str(content).map_err(|err| BuilderConfigError::Parse(err.to_string()))
+    }
+
+    /// The value of the `builder` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuilderConfig::CapacityTransfer { .. } => "capacity_transfer",
+            BuilderConfig::AcpTransfer { .. } => "acp_transfer",
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoDeposit { .. } => "dao_deposit",
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoPrepare { .. } => "dao_prepare",
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoWithdraw { .. } => "dao_withdraw",
+            #[cfg(feature = "builders-udt")]
+            BuilderConfig::UdtIssue { .. } => "udt_issue",
+            #[cfg(feature = "builders-udt")]
+            BuilderConfig::UdtTransfer { .. } => "udt_transfer",
+            BuilderConfig::ChequeClaim { .. } => "cheque_claim",
+            BuilderConfig::ChequeWithdraw { .. } => "cheque_withdraw",
+            BuilderConfig::Sweep { .. } => "sweep",
+        }
+    }
+
+    /// Validate the config and instantiate the builder, all the addresses must
+    /// belong to `network`.
+    pub fn build(&self, network: NetworkType) -> Result<Box<dyn TxBuilder>, BuilderConfigError> {
+        let builder: Box<dyn TxBuilder> = match self {
+            BuilderConfig::CapacityTransfer { outputs } => {
+                check_not_empty("outputs", outputs.len())?;
+                let outputs = outputs
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, output)| {
+                        let field = format!("outputs[{}]", idx);
+                        let cell_output = CellOutput::new_builder()
+                            .lock(parse_address(
+                                &format!("{}.address", field),
+                                &output.address,
+                                network,
+                            )?)
+                            .type_(output.type_script.clone().map(Script::from).pack())
+                            .capacity(
+                                parse_capacity(&format!("{}.capacity", field), &output.capacity)?
+                                    .pack(),
+                            )
+                            .build();
+                        let data = output
+                            .data
+                            .clone()
+                            .map(|data| data.into_bytes())
+                            .unwrap_or_default();
+                        Ok((cell_output, data))
+                    })
+                    .collect::<Result<Vec<_>, BuilderConfigError>>()?;
+                Box::new(CapacityTransferBuilder::new(outputs))
+            }
+            BuilderConfig::AcpTransfer { receivers } => {
+                let receivers = parse_receivers(receivers, network)?
+                    .into_iter()
+                    .map(|(lock_script, capacity)| AcpTransferReceiver::new(lock_script, capacity))
+                    .collect();
+                Box::new(AcpTransferBuilder::new(receivers))
+            }
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoDeposit { receivers } => {
+                let receivers = parse_receivers(receivers, network)?
+                    .into_iter()
+                    .map(|(lock_script, capacity)| DaoDepositReceiver::new(lock_script, capacity))
+                    .collect();
+                Box::new(DaoDepositBuilder::new(receivers))
+            }
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoPrepare { items } => {
+                check_not_empty("items", items.len())?;
+                let items = items
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, item)| {
+                        let lock_script = item
+                            .address
+                            .as_ref()
+                            .map(|address| {
+                                parse_address(&format!("items[{}].address", idx), address, network)
+                            })
+                            .transpose()?;
+                        Ok(DaoPrepareItem {
+                            input: CellInput::new(OutPoint::from(item.out_point.clone()), 0),
+                            lock_script,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, BuilderConfigError>>()?;
+                Box::new(DaoPrepareBuilder::new(items))
+            }
+            #[cfg(feature = "builders-dao")]
+            BuilderConfig::DaoWithdraw {
+                out_points,
+                receiver,
+                fee_rate,
+            } => {
+                check_not_empty("out_points", out_points.len())?;
+                let items = out_points
+                    .iter()
+                    .map(|out_point| DaoWithdrawItem::new(out_point.clone().into(), None))
+                    .collect();
+                let receiver = DaoWithdrawReceiver::LockScript {
+                    script: parse_address("receiver", receiver, network)?,
+                    fee_rate: fee_rate.map(FeeRate::from_u64),
+                };
+                Box::new(DaoWithdrawBuilder::new(items, receiver))
+            }
+            #[cfg(feature = "builders-udt")]
+            BuilderConfig::UdtIssue {
+                udt_type,
+                script_id,
+                owner,
+                receivers,
+            } => Box::new(UdtIssueBuilder {
+                udt_type: match udt_type {
+                    UdtTypeConfig::Sudt => UdtType::Sudt,
+                    UdtTypeConfig::Xudt(args) => UdtType::Xudt(args.clone().into_bytes()),
+                },
+                script_id: ScriptId::new(script_id.code_hash.clone(), script_id.hash_type.into()),
+                owner: parse_address("owner", owner, network)?,
+                receivers: parse_udt_receivers(receivers, network)?,
+            }),
+            #[cfg(feature = "builders-udt")]
+            BuilderConfig::UdtTransfer {
+                type_script,
+                sender,
+                receivers,
+            } => Box::new(UdtTransferBuilder {
+                type_script: type_script.clone().into(),
+                sender: parse_address("sender", sender, network)?,
+                receivers: parse_udt_receivers(receivers, network)?,
+                change_lock: None,
+            }),
+            BuilderConfig::ChequeClaim {
+                inputs,
+                receiver_input,
+                sender,
+            } => {
+                check_not_empty("inputs", inputs.len())?;
+                let inputs = inputs
+                    .iter()
+                    .map(|out_point| CellInput::new(out_point.clone().into(), 0))
+                    .collect();
+                Box::new(ChequeClaimBuilder::new(
+                    inputs,
+                    CellInput::new(receiver_input.clone().into(), 0),
+                    parse_address("sender", sender, network)?,
+                ))
+            }
+            BuilderConfig::ChequeWithdraw {
+                out_points,
+                sender,
+                acp_script_id,
+            } => {
+                check_not_empty("out_points", out_points.len())?;
+                Box::new(ChequeWithdrawBuilder::new(
+                    out_points.iter().cloned().map(OutPoint::from).collect(),
+                    parse_address("sender", sender, network)?,
+                    acp_script_id
+                        .as_ref()
+                        .map(|id| ScriptId::new(id.code_hash.clone(), id.hash_type.into())),
+                ))
+            }
+            BuilderConfig::Sweep { locks } => {
+                check_not_empty("locks", locks.len())?;
+                let lock_scripts = locks
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, lock)| {
+                        let script = parse_address(
+                            &format!("locks[{}].address", idx),
+                            &lock.address,
+                            network,
+                        )?;
+                        let placeholder_len =
+                            lock.placeholder_len.unwrap_or(DEFAULT_PLACEHOLDER_LEN);
+                        let placeholder_witness = WitnessArgs::new_builder()
+                            .lock(Some(Bytes::from(vec![0u8; placeholder_len])).pack())
+                            .build();
+                        Ok((script, placeholder_witness))
+                    })
+                    .collect::<Result<Vec<_>, BuilderConfigError>>()?;
+                Box::new(SweepBuilder::new(lock_scripts))
+            }
+        };
+        Ok(builder)
+    }
+}
+
+fn check_not_empty(field: &str, len: usize) -> Result<(), BuilderConfigError> {
+    if len == 0 {
+        return Err(BuilderConfigError::invalid(field, "must not be empty"));
+    }
+    Ok(())
+}
+
+fn parse_address(
+    field: &str,
+    value: &str,
+    network: NetworkType,
+) -> Result<Script, BuilderConfigError> {
+    let address =
+        Address::from_str(value).map_err(|err| BuilderConfigError::invalid(field, err))?;
+    if address.network() != network {
+        return Err(BuilderConfigError::invalid(
+            field,
+            format!(
+                "address of {:?} network, expected {:?}",
+                address.network(),
+                network
+            ),
+        ));
+    }
+    Ok(Script::from(&address))
+}
+
+fn parse_capacity(field: &str, value: &str) -> Result<u64, BuilderConfigError> {
+    let capacity = HumanCapacity::from_str(value)
+        .map_err(|err| BuilderConfigError::invalid(field, err))?
+        .0;
+    if capacity == 0 {
+        return Err(BuilderConfigError::invalid(field, "must not be zero"));
+    }
+    Ok(capacity)
+}
+
+fn parse_receivers(
+    receivers: &[ReceiverConfig],
+    network: NetworkType,
+) -> Result<Vec<(Script, u64)>, BuilderConfigError> {
+    check_not_empty("receivers", receivers.len())?;
+    receivers
+        .iter()
+        .enumerate()
+        .map(|(idx, receiver)| {
+            Ok((
+                parse_address(
+                    &format!("receivers[{}].address", idx),
+                    &receiver.address,
+                    network,
+                )?,
+                parse_capacity(&format!("receivers[{}].capacity", idx), &receiver.capacity)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(feature = "builders-udt")]
+fn parse_udt_receivers(
+    receivers: &[UdtReceiverConfig],
+    network: NetworkType,
+) -> Result<Vec<UdtTargetReceiver>, BuilderConfigError> {
+    check_not_empty("receivers", receivers.len())?;
+    receivers
+        .iter()
+        .enumerate()
+        .map(|(idx, receiver)| {
+            let lock_script = parse_address(
+                &format!("receivers[{}].address", idx),
+                &receiver.address,
+                network,
+            )?;
+            let amount = receiver.amount.parse::<u128>().map_err(|err| {
+                BuilderConfigError::invalid(format!("receivers[{}].amount", idx), err)
+            })?;
+            let action = match receiver.action {
+                TransferActionConfig::Create => TransferAction::Create,
+                TransferActionConfig::Update => TransferAction::Update,
+            };
+            let capacity = match (&receiver.capacity, &action) {
+                (Some(_), TransferAction::Update) => {
+                    return Err(BuilderConfigError::invalid(
+                        format!("receivers[{}].capacity", idx),
+                        "only allowed for the create action",
+                    ))
+                }
+                (Some(capacity), _) => Some(parse_capacity(
+                    &format!("receivers[{}].capacity", idx),
+                    capacity,
+                )?),
+                (None, _) => None,
+            };
+            let mut target = UdtTargetReceiver::new(action, lock_script, amount);
+            target.capacity = capacity;
+            Ok(target)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "ckt1qyq86vaa6e8tsruv5ngcd5tp7lcvcewxy7cquuksvj";
+
+    #[cfg(feature = "builders-dao")]
+    #[test]
+    fn test_builder_config() {
+        let config = BuilderConfig::from_json(&format!(
+            r#"{{"builder": "dao_deposit", "receivers": [{{"address": "{}", "capacity": "102.5"}}]}}"#,
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(config.name(), "dao_deposit");
+        assert!(config.build(NetworkType::Testnet).is_ok());
+
+        let err = config.build(NetworkType::Mainnet).err().unwrap();
+        assert!(err.to_string().contains("receivers[0].address"));
+
+        let config = BuilderConfig::from_json(&format!(
+            r#"{{"builder": "capacity_transfer", "outputs": [{{"address": "{}", "capacity": "1.123456789"}}]}}"#,
+            ADDRESS
+        ))
+        .unwrap();
+        let err = config.build(NetworkType::Testnet).err().unwrap();
+        assert!(err.to_string().contains("outputs[0].capacity"));
+
+        assert!(matches!(
+            BuilderConfig::from_json(r#"{"builder": "sweep", "locks": [], "extra": 1}"#),
+            Err(BuilderConfigError::Parse(_))
+        ));
+        let config = BuilderConfig::from_json(r#"{"builder": "sweep", "locks": []}"#).unwrap();
+        assert!(config.build(NetworkType::Testnet).is_err());
+    }
+
+    #[cfg(feature = "toml-config")]
+    #[test]
+    fn test_builder_config_from_toml() {
+        let toml_config = BuilderConfig::from_toml(&format!(
+            r#"
+builder = "capacity_transfer"
+
+[[outputs]]
+address = "{}"
+capacity = "120.5"
+"#,
+            ADDRESS
+        ))
+        .unwrap();
+        let json_config = BuilderConfig::from_json(&format!(
+            r#"{{"builder": "capacity_transfer", "outputs": [{{"address": "{}", "capacity": "120.5"}}]}}"#,
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(toml_config.name(), "capacity_transfer");
+        assert_eq!(format!("{:?}", toml_config), format!("{:?}", json_config));
+        assert!(toml_config.build(NetworkType::Testnet).is_ok());
+
+        assert!(matches!(
+            BuilderConfig::from_toml("builder = \"sweep\"\nlocks = []\nextra = 1\n"),
+            Err(BuilderConfigError::Parse(_))
+        ));
+    }
+}
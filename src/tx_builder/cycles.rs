@@ -0,0 +1,326 @@
+//! Estimate the cycles of a transaction before all the signatures exist.
+//!
+//! The unsigned lock groups of the standard secp256k1 sighash and multisig
+//! locks are signed with dummy keys, and their input cells are rewritten to
+//! the locks of the dummy keys, so the lock scripts run the whole verification
+//! path with a signature of the real length. The groups already signed keep
+//! their signatures and their cells. The scripts are then run locally.
+
+use std::sync::Arc;
+
+use ckb_hash::blake2b_256;
+use ckb_mock_tx_types::{MockInfo, MockInput, MockResourceLoader, MockTransaction, Resource};
+use ckb_types::{
+    bytes::Bytes,
+    core::{Cycle, HeaderBuilder, HeaderView, ScriptHashType, TransactionView},
+    packed::{Byte32, CellOutput, OutPoint, Script, WitnessArgs},
+    prelude::*,
+    H160, H256,
+};
+use thiserror::Error;
+
+use super::gen_script_groups;
+use crate::compat::{dev_consensus, resolve_tx, verify_tx_scripts};
+use crate::constants::{MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH};
+use crate::traits::{
+    SecpCkbRawKeySigner, TransactionDependencyError, TransactionDependencyProvider,
+};
+use crate::types::ScriptGroup;
+use crate::unlock::{
+    ensure_witness_capacity, MultisigConfig, ScriptSignError, ScriptSigner,
+    SecpMultisigScriptSigner, SecpSighashScriptSigner,
+};
+use crate::SECP256K1;
+
+const SIGNATURE_LEN: usize = 65;
+
+#[derive(Error, Debug)]
+pub enum CycleEstimateError {
+    #[error("transaction dependency error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("sign script error: `{0}`")]
+    ScriptSign(#[from] ScriptSignError),
+
+    #[error("can not fill dummy signatures for the unsigned lock script: `{0}`")]
+    UnsupportedLock(Script),
+
+    #[error("verify script error: {0}")]
+    VerifyScript(String),
+}
+
+/// A transaction whose unsigned lock groups are signed with dummy keys
+pub struct DummySignedTx {
+    pub tx: TransactionView,
+    /// The input cells, the cells of the dummy signed groups are locked by
+    /// the dummy keys.
+    pub inputs: Vec<(CellOutput, Bytes)>,
+    /// The lock groups signed with dummy keys, with the original lock scripts
+    pub dummy_signed_groups: Vec<ScriptGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleEstimate {
+    pub cycles: Cycle,
+    /// The lock groups signed with dummy keys, with the original lock scripts
+    pub dummy_signed_groups: Vec<ScriptGroup>,
+}
+
+fn dummy_key(idx: usize) -> secp256k1::SecretKey {
+    let mut key = [0u8; 32];
+    key[24..].copy_from_slice(&(idx as u64 + 1).to_be_bytes());
+    secp256k1::SecretKey::from_slice(&key).expect("dummy secret key")
+}
+
+fn dummy_key_hash(idx: usize) -> H160 {
+    let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &dummy_key(idx));
+    H160::from_slice(&blake2b_256(&pubkey.serialize()[..])[0..20]).expect("dummy key hash")
+}
+
+/// The witness lock field of the group, `None` if it's empty
+fn witness_lock(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Result<Option<Bytes>, CycleEstimateError> {
+    let witness_data = match tx.witnesses().get(script_group.input_indices[0]) {
+        Some(witness) => witness.raw_data(),
+        None => return Ok(None),
+    };
+    if witness_data.is_empty() {
+        return Ok(None);
+    }
+    let witness = WitnessArgs::from_slice(witness_data.as_ref())
+        .map_err(ScriptSignError::InvalidWitnessArgs)?;
+    Ok(witness.lock().to_opt().map(|data| data.raw_data()))
+}
+
+/// A signature area is signed when none of its signatures is left zero
+fn is_signed(signatures: &[u8]) -> bool {
+    !signatures.is_empty()
+        && signatures.len() % SIGNATURE_LEN == 0
+        && signatures
+            .chunks(SIGNATURE_LEN)
+            .all(|signature| signature.iter().any(|byte| *byte != 0))
+}
+
+fn parse_multisig_config(lock: &[u8]) -> Option<(u8, u8, usize)> {
+    if lock.len() < 4 || lock[0] != 0 {
+        return None;
+    }
+    let (require_first_n, threshold, keys_len) = (lock[1], lock[2], lock[3] as usize);
+    if lock.len() != 4 + 20 * keys_len + SIGNATURE_LEN * threshold as usize {
+        return None;
+    }
+    Some((require_first_n, threshold, keys_len))
+}
+
+fn set_witness_lock(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    lock: Bytes,
+) -> TransactionView {
+    let witness_idx = script_group.input_indices[0];
+    let mut witnesses = ensure_witness_capacity(tx, script_group);
+    let witness_data = witnesses[witness_idx].raw_data();
+    let witness = if witness_data.is_empty() {
+        WitnessArgs::default()
+    } else {
+        WitnessArgs::from_slice(witness_data.as_ref()).unwrap_or_default()
+    };
+    witnesses[witness_idx] = witness
+        .as_builder()
+        .lock(Some(lock).pack())
+        .build()
+        .as_bytes()
+        .pack();
+    tx.as_advanced_builder().set_witnesses(witnesses).build()
+}
+
+/// Sign the unsigned secp256k1 sighash and multisig lock groups with dummy
+/// keys. The witness length of a group is the same as a real signature's.
+pub fn dummy_sign_tx(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<DummySignedTx, CycleEstimateError> {
+    let mut inputs =
+        tx_dep_provider.get_cells_with_data(&tx.input_pts_iter().collect::<Vec<_>>())?;
+    let mut lock_groups = gen_script_groups(tx, tx_dep_provider)?
+        .lock_groups
+        .into_values()
+        .collect::<Vec<_>>();
+    lock_groups.sort_by_key(|group| group.input_indices[0]);
+
+    let mut tx = tx.clone();
+    let mut dummy_signed_groups = Vec::new();
+    for group in lock_groups {
+        let lock_script = &group.script;
+        let lock = witness_lock(&tx, &group)?;
+        let code_hash: H256 = lock_script.code_hash().unpack();
+        let is_type_hash = lock_script.hash_type() == ScriptHashType::Type.into();
+        let args = lock_script.args().raw_data();
+        let (dummy_args, signed_tx) = if is_type_hash && code_hash == SIGHASH_TYPE_HASH {
+            if matches!(&lock, Some(lock) if is_signed(lock)) {
+                continue;
+            }
+            let dummy_args = Bytes::from(dummy_key_hash(0).as_bytes().to_vec());
+            let dummy_group = dummy_group(&group, dummy_args.clone());
+            let unsigned_tx = set_witness_lock(&tx, &group, Bytes::from(vec![0u8; SIGNATURE_LEN]));
+            let signer = SecpSighashScriptSigner::new(Box::new(
+                SecpCkbRawKeySigner::new_with_secret_keys(vec![dummy_key(0)]),
+            ));
+            (dummy_args, signer.sign_tx(&unsigned_tx, &dummy_group)?)
+        } else if is_type_hash && code_hash == MULTISIG_TYPE_HASH && args.len() >= 20 {
+            let lock =
+                lock.ok_or_else(|| CycleEstimateError::UnsupportedLock(group.script.clone()))?;
+            let (require_first_n, threshold, keys_len) = parse_multisig_config(&lock)
+                .ok_or_else(|| CycleEstimateError::UnsupportedLock(group.script.clone()))?;
+            if is_signed(&lock[4 + 20 * keys_len..]) {
+                continue;
+            }
+            let config = MultisigConfig::new_with(
+                (0..keys_len).map(dummy_key_hash).collect(),
+                require_first_n,
+                threshold,
+            )?;
+            // keep the since part of the args
+            let mut dummy_args = config.hash160().as_bytes().to_vec();
+            dummy_args.extend_from_slice(&args[20..]);
+            let dummy_args = Bytes::from(dummy_args);
+            let dummy_group = dummy_group(&group, dummy_args.clone());
+            let placeholder = config
+                .placeholder_witness()
+                .lock()
+                .to_opt()
+                .expect("placeholder lock")
+                .raw_data();
+            let unsigned_tx = set_witness_lock(&tx, &group, placeholder);
+            let keys = (0..threshold as usize).map(dummy_key).collect();
+            let signer = SecpMultisigScriptSigner::new(
+                Box::new(SecpCkbRawKeySigner::new_with_secret_keys(keys)),
+                config,
+            );
+            (dummy_args, signer.sign_tx(&unsigned_tx, &dummy_group)?)
+        } else {
+            match lock {
+                Some(lock) if lock.iter().any(|byte| *byte != 0) => continue,
+                _ => return Err(CycleEstimateError::UnsupportedLock(group.script.clone())),
+            }
+        };
+        tx = signed_tx;
+        let dummy_lock = lock_script
+            .clone()
+            .as_builder()
+            .args(dummy_args.pack())
+            .build();
+        for idx in &group.input_indices {
+            let (output, _) = &mut inputs[*idx];
+            *output = output.clone().as_builder().lock(dummy_lock.clone()).build();
+        }
+        dummy_signed_groups.push(group);
+    }
+    Ok(DummySignedTx {
+        tx,
+        inputs,
+        dummy_signed_groups,
+    })
+}
+
+fn dummy_group(group: &ScriptGroup, dummy_args: Bytes) -> ScriptGroup {
+    let mut dummy_group = group.clone();
+    dummy_group.script = group
+        .script
+        .clone()
+        .as_builder()
+        .args(dummy_args.pack())
+        .build();
+    dummy_group
+}
+
+struct ProviderLoader<'a> {
+    tx_dep_provider: &'a dyn TransactionDependencyProvider,
+}
+
+impl<'a> MockResourceLoader for ProviderLoader<'a> {
+    fn get_header(&mut self, hash: H256) -> Result<Option<HeaderView>, String> {
+        match self.tx_dep_provider.get_header(&hash.pack()) {
+            Ok(header) => Ok(Some(header)),
+            Err(TransactionDependencyError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn get_live_cell(
+        &mut self,
+        out_point: OutPoint,
+    ) -> Result<Option<(CellOutput, Bytes, Option<Byte32>)>, String> {
+        let (output, data) = match self.tx_dep_provider.get_cells_with_data(&[out_point]) {
+            Ok(mut cells) => cells.remove(0),
+            Err(TransactionDependencyError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err.to_string()),
+        };
+        Ok(Some((output, data, None)))
+    }
+}
+
+/// Run the scripts of `tx` with the input cells `inputs`, the cell deps and
+/// header deps are loaded from `tx_dep_provider`. All the hardforks are
+/// activated.
+pub fn verify_scripts_with_inputs(
+    tx: &TransactionView,
+    inputs: &[(CellOutput, Bytes)],
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Cycle, CycleEstimateError> {
+    let mock_info = MockInfo {
+        inputs: tx
+            .inputs()
+            .into_iter()
+            .zip(inputs.iter().cloned())
+            .map(|(input, (output, data))| MockInput {
+                input,
+                output,
+                data,
+                header: None,
+            })
+            .collect(),
+        cell_deps: Vec::new(),
+        header_deps: Vec::new(),
+        extensions: Vec::new(),
+    };
+    let mock_tx = MockTransaction {
+        mock_info,
+        tx: tx.data(),
+    };
+    let mut loader = ProviderLoader { tx_dep_provider };
+    let resource =
+        Resource::from_both(&mock_tx, &mut loader).map_err(CycleEstimateError::VerifyScript)?;
+    let rtx =
+        resolve_tx(tx.clone(), &resource, &resource).map_err(CycleEstimateError::VerifyScript)?;
+    let tip = HeaderBuilder::default().number(0.pack()).build();
+    verify_tx_scripts(
+        rtx,
+        resource,
+        Arc::new(dev_consensus()),
+        &tip,
+        u64::MAX,
+        false,
+    )
+    .map_err(CycleEstimateError::VerifyScript)
+}
+
+/// Estimate the cycles of a partially signed transaction, see the module
+/// document.
+pub fn estimate_cycles_with_dummy_signatures(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<CycleEstimate, CycleEstimateError> {
+    let DummySignedTx {
+        tx,
+        inputs,
+        dummy_signed_groups,
+    } = dummy_sign_tx(tx, tx_dep_provider)?;
+    let cycles = verify_scripts_with_inputs(&tx, &inputs, tx_dep_provider)?;
+    Ok(CycleEstimate {
+        cycles,
+        dummy_signed_groups,
+    })
+}
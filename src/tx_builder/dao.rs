@@ -3,17 +3,20 @@ use std::collections::HashSet;
 use anyhow::anyhow;
 use ckb_types::{
     bytes::Bytes,
-    core::{Capacity, FeeRate, ScriptHashType, TransactionBuilder, TransactionView},
+    core::{
+        Capacity, EpochNumberWithFraction, FeeRate, ScriptHashType, TransactionBuilder,
+        TransactionView,
+    },
     packed::{CellInput, CellOutput, OutPoint, Script, WitnessArgs},
     prelude::*,
 };
 
 use super::{TxBuilder, TxBuilderError};
-use crate::constants::DAO_TYPE_HASH;
+use crate::constants::{DAO_TYPE_HASH, MULTISIG_TYPE_HASH};
 use crate::traits::{
     CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
 };
-use crate::types::{Since, SinceType};
+use crate::types::{format_epoch, Since, SinceType};
 use crate::util::{calculate_dao_maximum_withdraw4, minimal_unlock_point};
 
 /// Deposit target
@@ -190,6 +193,73 @@ impl TxBuilder for DaoPrepareBuilder {
     }
 }
 
+/// The since of a multisig lock script with since args, the lock requires
+/// its inputs to have the same since flags and a since not less than it.
+pub fn lock_since(lock: &Script) -> Option<Since> {
+    let args = lock.args().raw_data();
+    if lock.code_hash().as_slice() != MULTISIG_TYPE_HASH.as_bytes()
+        || lock.hash_type() != ScriptHashType::Type.into()
+        || args.len() != 28
+    {
+        return None;
+    }
+    let mut since_bytes = [0u8; 8];
+    since_bytes.copy_from_slice(&args[20..28]);
+    Some(Since::from_raw_value(u64::from_le_bytes(since_bytes)))
+}
+
+/// The since of a withdraw (phase 2) input locked by `lock`: the later one of
+/// the DAO unlock point and the since required by a multisig lock.
+///
+/// Both must be absolute epochs (with fraction) since the input can only have
+/// one since.
+pub fn withdraw_since(
+    unlock_point: EpochNumberWithFraction,
+    lock: &Script,
+) -> Result<Since, TxBuilderError> {
+    let dao_since = Since::new(
+        SinceType::EpochNumberWithFraction,
+        unlock_point.full_value(),
+        false,
+    );
+    let lock_since = match lock_since(lock) {
+        Some(since) => since,
+        None => return Ok(dao_since),
+    };
+    let lock_epoch = match lock_since.extract_metric() {
+        Some((SinceType::EpochNumberWithFraction, value))
+            if lock_since.is_absolute() && lock_since.flags_is_valid() =>
+        {
+            EpochNumberWithFraction::from_full_value(value)
+        }
+        _ => {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "multisig since ({}) is not an absolute epoch, can not withdraw from dao",
+                lock_since
+            )))
+        }
+    };
+    if lock_epoch.length() == 0 || lock_epoch.index() >= lock_epoch.length() {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "multisig since epoch {} is not well formed",
+            format_epoch(lock_epoch)
+        )));
+    }
+    // compare index / length as fractions
+    let lock_is_later = (
+        lock_epoch.number(),
+        lock_epoch.index() * unlock_point.length(),
+    ) > (
+        unlock_point.number(),
+        unlock_point.index() * lock_epoch.length(),
+    );
+    if lock_is_later {
+        Ok(lock_since)
+    } else {
+        Ok(dao_since)
+    }
+}
+
 /// The dao withdraw receiver
 ///
 #[derive(Debug, Clone)]
@@ -268,7 +338,7 @@ impl TxBuilder for DaoWithdrawBuilder {
         let mut prepare_block_hashes = Vec::new();
         let mut inputs = Vec::new();
         let mut witnesses = Vec::new();
-        let mut input_total = 0;
+        let mut input_total = Capacity::zero();
         for DaoWithdrawItem {
             out_point,
             init_witness,
@@ -318,11 +388,7 @@ impl TxBuilder for DaoWithdrawBuilder {
                 ))?;
             let input = {
                 let unlock_point = minimal_unlock_point(&deposit_header, &prepare_header);
-                let since = Since::new(
-                    SinceType::EpochNumberWithFraction,
-                    unlock_point.full_value(),
-                    false,
-                );
+                let since = withdraw_since(unlock_point, &input_cell.lock())?;
                 CellInput::new(out_point.clone(), since.value())
             };
             let deposit_block_hash = deposit_header.hash();
@@ -349,7 +415,7 @@ impl TxBuilder for DaoWithdrawBuilder {
                 &input_cell,
                 occupied_capacity.as_u64(),
             );
-            input_total += input_capacity;
+            input_total = input_total.safe_add(Capacity::shannons(input_capacity))?;
 
             cell_deps.insert(input_lock_cell_dep);
             if header_idx == header_deps.len() {
@@ -378,9 +444,9 @@ impl TxBuilder for DaoWithdrawBuilder {
                         .build();
                     let tx_size = tmp_tx.data().as_reader().serialized_size_in_block();
                     let tx_fee = fee_rate.fee(tx_size as u64).as_u64();
-                    input_total - tx_fee
+                    input_total.safe_sub(Capacity::shannons(tx_fee))?.as_u64()
                 } else {
-                    input_total
+                    input_total.as_u64()
                 };
                 let final_capacity = std::cmp::max(occupied_capacity, capacity);
                 let output = tmp_output
@@ -417,3 +483,183 @@ impl TxBuilder for DaoWithdrawBuilder {
             .build())
     }
 }
+
+/// Withdraw prepared cells and deposit the withdrawn capacity again.
+///
+/// Two chained transactions are built: the phase 2 withdraw transaction
+/// paying its fee from the withdrawn capacity, and a deposit transaction
+/// spending the withdraw output. The deposit keeps the principal, the
+/// compensation is deposited too when `compound_compensation` is set, or
+/// else returned as a change cell (merged into the deposit when it's too
+/// small for a cell). Both transactions are applied to the cell collector at
+/// `tip_block_number`, so the following builds see the new deposit.
+#[derive(Debug, Clone)]
+pub struct DaoCompoundBuilder {
+    /// The prepared cells to withdraw
+    pub items: Vec<DaoWithdrawItem>,
+    /// The lock script of the withdrawn capacity and the new deposit
+    pub lock_script: Script,
+    /// The placeholder witness of `lock_script` in the deposit transaction
+    pub placeholder_witness: WitnessArgs,
+    pub compound_compensation: bool,
+    pub fee_rate: u64,
+}
+
+impl DaoCompoundBuilder {
+    pub fn new(
+        items: Vec<DaoWithdrawItem>,
+        lock_script: Script,
+        placeholder_witness: WitnessArgs,
+        fee_rate: u64,
+    ) -> DaoCompoundBuilder {
+        DaoCompoundBuilder {
+            items,
+            lock_script,
+            placeholder_witness,
+            compound_compensation: false,
+            fee_rate,
+        }
+    }
+
+    /// Build the unsigned withdraw and deposit transactions
+    pub fn build(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        tip_block_number: u64,
+    ) -> Result<(TransactionView, TransactionView), TxBuilderError> {
+        let fee_rate = FeeRate::from_u64(self.fee_rate);
+        let withdraw_tx = DaoWithdrawBuilder::new(
+            self.items.clone(),
+            DaoWithdrawReceiver::LockScript {
+                script: self.lock_script.clone(),
+                fee_rate: Some(fee_rate),
+            },
+        )
+        .build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        let withdrawn: u64 = withdraw_tx
+            .output(0)
+            .expect("withdraw output")
+            .capacity()
+            .unpack();
+        let mut principal = 0u64;
+        for item in &self.items {
+            let capacity: u64 = tx_dep_provider
+                .get_cell(&item.out_point)?
+                .capacity()
+                .unpack();
+            principal += capacity;
+        }
+
+        let dao_type_script = Script::new_builder()
+            .code_hash(DAO_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .build();
+        let mut cell_deps = Vec::new();
+        for script in [&dao_type_script, &self.lock_script] {
+            let cell_dep = cell_dep_resolver
+                .resolve(script)
+                .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))?;
+            if !cell_deps.contains(&cell_dep) {
+                cell_deps.push(cell_dep);
+            }
+        }
+        let deposit_input_since = lock_since(&self.lock_script).map(Since::value).unwrap_or(0);
+        let deposit_output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .type_(Some(dao_type_script).pack())
+            .build();
+        let change_output = CellOutput::new_builder()
+            .lock(self.lock_script.clone())
+            .build();
+        let build_deposit_tx = |deposit: u64, change: Option<u64>| {
+            let mut outputs = vec![deposit_output
+                .clone()
+                .as_builder()
+                .capacity(deposit.pack())
+                .build()];
+            let mut outputs_data = vec![Bytes::from(vec![0u8; 8]).pack()];
+            if let Some(change) = change {
+                outputs.push(
+                    change_output
+                        .clone()
+                        .as_builder()
+                        .capacity(change.pack())
+                        .build(),
+                );
+                outputs_data.push(Bytes::new().pack());
+            }
+            TransactionBuilder::default()
+                .set_cell_deps(cell_deps.clone())
+                .input(CellInput::new(
+                    OutPoint::new(withdraw_tx.hash(), 0),
+                    deposit_input_since,
+                ))
+                .set_outputs(outputs)
+                .set_outputs_data(outputs_data)
+                .witness(self.placeholder_witness.as_bytes().pack())
+                .build()
+        };
+        let tx_fee = |tx: &TransactionView| {
+            fee_rate
+                .fee(tx.data().as_reader().serialized_size_in_block() as u64)
+                .as_u64()
+        };
+
+        let change_occupied = change_output
+            .occupied_capacity(Capacity::zero())
+            .expect("change occupied capacity")
+            .as_u64();
+        let mut deposit_tx = None;
+        if !self.compound_compensation {
+            let fee = tx_fee(&build_deposit_tx(principal, Some(0)));
+            let change = withdrawn.checked_sub(principal + fee).unwrap_or(0);
+            if change >= change_occupied {
+                deposit_tx = Some(build_deposit_tx(principal, Some(change)));
+            }
+        }
+        let deposit_tx = match deposit_tx {
+            Some(tx) => tx,
+            None => {
+                let fee = tx_fee(&build_deposit_tx(withdrawn, None));
+                let deposit = withdrawn.checked_sub(fee).ok_or_else(|| {
+                    TxBuilderError::InvalidParameter(anyhow!(
+                        "withdrawn capacity {} is not enough for the fee {}",
+                        withdrawn,
+                        fee
+                    ))
+                })?;
+                build_deposit_tx(deposit, None)
+            }
+        };
+        let deposit_occupied = deposit_tx
+            .output(0)
+            .expect("deposit output")
+            .occupied_capacity(Capacity::bytes(8).expect("dao data capacity"))
+            .expect("deposit occupied capacity")
+            .as_u64();
+        let deposit: u64 = deposit_tx
+            .output(0)
+            .expect("deposit output")
+            .capacity()
+            .unpack();
+        if deposit < deposit_occupied {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "deposit capacity {} is less than the occupied capacity {}",
+                deposit,
+                deposit_occupied
+            )));
+        }
+
+        cell_collector.apply_tx(withdraw_tx.data(), tip_block_number)?;
+        cell_collector.apply_tx(deposit_tx.data(), tip_block_number)?;
+        Ok((withdraw_tx, deposit_tx))
+    }
+}
@@ -0,0 +1,107 @@
+//! Catch the transactions donating a cell to the miners.
+//!
+//! The fee of a transaction is whatever capacity the inputs have and the
+//! outputs don't, a forgotten change output silently turns the rest of an
+//! input cell into the fee. A [`DonationGuard`] computes the implied fee of a
+//! balanced transaction and warns, or fails above a limit, when it is
+//! absurdly large. The
+//! [`TxBuilder::build_unlocked_with_donation_guard`](super::TxBuilder::build_unlocked_with_donation_guard)
+//! checks the balanced transaction before signing.
+
+use ckb_types::{core::TransactionView, prelude::*};
+use thiserror::Error;
+
+use crate::redact;
+use crate::traits::{TransactionDependencyError, TransactionDependencyProvider};
+use crate::types::HumanCapacity;
+
+/// Warn above 1 CKB of fee, a normal transfer pays less than 0.001 CKB
+pub const DEFAULT_WARN_FEE: u64 = 100_000_000;
+/// Fail above 10 CKB of fee
+pub const DEFAULT_MAX_FEE: u64 = 1_000_000_000;
+
+#[derive(Error, Debug)]
+pub enum DonationError {
+    #[error("transaction dependency provider error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("the transaction pays `{}` CKB of fee, more than `{}` CKB, is a change output missing?", redact::amount(HumanCapacity(*.fee)), redact::amount(HumanCapacity(*.max_fee)))]
+    FeeTooLarge { fee: u64, max_fee: u64 },
+}
+
+/// The fee thresholds of a balanced transaction
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DonationGuard {
+    /// Log a warning when the fee is larger
+    pub warn_fee: u64,
+    /// Fail when the fee is larger, `None` only warns
+    pub max_fee: Option<u64>,
+}
+
+impl Default for DonationGuard {
+    fn default() -> DonationGuard {
+        DonationGuard {
+            warn_fee: DEFAULT_WARN_FEE,
+            max_fee: Some(DEFAULT_MAX_FEE),
+        }
+    }
+}
+
+impl DonationGuard {
+    /// Warn above `warn_fee`, never fail
+    pub fn warn_only(warn_fee: u64) -> DonationGuard {
+        DonationGuard {
+            warn_fee,
+            max_fee: None,
+        }
+    }
+
+    pub fn max_fee(mut self, max_fee: u64) -> Self {
+        self.max_fee = Some(max_fee);
+        self
+    }
+
+    /// Check the implied fee of `tx`, returns the fee
+    pub fn check(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<u64, DonationError> {
+        let fee = implied_fee(tx, tx_dep_provider)?;
+        if let Some(max_fee) = self.max_fee {
+            if fee > max_fee {
+                return Err(DonationError::FeeTooLarge { fee, max_fee });
+            }
+        }
+        if fee > self.warn_fee {
+            log::warn!(
+                "transaction {} pays {} CKB of fee, is a change output missing?",
+                tx.hash(),
+                redact::amount(HumanCapacity(fee))
+            );
+        }
+        Ok(fee)
+    }
+}
+
+/// The input capacity minus the output capacity of `tx`, 0 when the outputs
+/// have more.
+///
+/// The DAO withdraw inputs count with their deposited capacity, the
+/// compensation makes the implied fee smaller than the real one.
+pub fn implied_fee(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<u64, TransactionDependencyError> {
+    let mut input_capacity = 0u64;
+    for out_point in tx.input_pts_iter() {
+        let capacity: u64 = tx_dep_provider.get_cell(&out_point)?.capacity().unpack();
+        input_capacity = input_capacity.saturating_add(capacity);
+    }
+    let output_capacity = tx
+        .outputs()
+        .into_iter()
+        .map(|output| Unpack::<u64>::unpack(&output.capacity()))
+        .fold(0u64, u64::saturating_add);
+    Ok(input_capacity.saturating_sub(output_capacity))
+}
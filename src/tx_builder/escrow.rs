@@ -0,0 +1,439 @@
+//! Builders for cheque-like escrow scripts.
+//!
+//! An escrow cell is locked by a script whose args hold a prefix of the
+//! receiver's lock hash, a prefix of the sender's lock hash and optionally the
+//! since the sender must wait before refunding. The receiver claims the cell
+//! by spending one of its own cells in the same transaction, the sender
+//! refunds it after the timeout. The cheque script is one such script, see
+//! [`EscrowScript::new_cheque`].
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{resolve_cell_deps, TxBuilder, TxBuilderError};
+use crate::constants::CHEQUE_CELL_SINCE;
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+
+/// Where the refund since of an escrow cell comes from
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EscrowTimeout {
+    /// The same since for every cell of the script
+    Fixed(u64),
+    /// An 8 bytes little endian since appended to the lock args
+    InArgs,
+}
+
+/// The layout of an escrow lock script
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EscrowScript {
+    pub script_id: ScriptId,
+    /// Length of the lock hash prefix of each party
+    pub hash_len: usize,
+    pub timeout: EscrowTimeout,
+}
+
+/// The parsed lock args of an escrow cell
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EscrowArgs {
+    pub receiver_hash: Bytes,
+    pub sender_hash: Bytes,
+    /// The since of the refund inputs
+    pub timeout_since: u64,
+}
+
+impl EscrowArgs {
+    pub fn is_receiver(&self, lock_script: &Script) -> bool {
+        lock_script.calc_script_hash().as_slice()[0..self.receiver_hash.len()]
+            == self.receiver_hash[..]
+    }
+
+    pub fn is_sender(&self, lock_script: &Script) -> bool {
+        lock_script.calc_script_hash().as_slice()[0..self.sender_hash.len()] == self.sender_hash[..]
+    }
+}
+
+impl EscrowScript {
+    /// `hash_len` is capped at 32, the length of a lock hash
+    pub fn new(script_id: ScriptId, hash_len: usize, timeout: EscrowTimeout) -> EscrowScript {
+        EscrowScript {
+            script_id,
+            hash_len: hash_len.min(32),
+            timeout,
+        }
+    }
+
+    /// The cheque script deployed with `script_id`
+    pub fn new_cheque(script_id: ScriptId) -> EscrowScript {
+        EscrowScript::new(script_id, 20, EscrowTimeout::Fixed(CHEQUE_CELL_SINCE))
+    }
+
+    fn args_len(&self) -> usize {
+        match self.timeout {
+            EscrowTimeout::Fixed(_) => self.hash_len * 2,
+            EscrowTimeout::InArgs => self.hash_len * 2 + 8,
+        }
+    }
+
+    /// Build the lock script of an escrow cell, `timeout_since` is required
+    /// when the timeout is stored in the args and ignored otherwise.
+    pub fn lock_script(
+        &self,
+        receiver_lock: &Script,
+        sender_lock: &Script,
+        timeout_since: Option<u64>,
+    ) -> Result<Script, TxBuilderError> {
+        let mut args = Vec::with_capacity(self.args_len());
+        args.extend_from_slice(&receiver_lock.calc_script_hash().as_slice()[0..self.hash_len]);
+        args.extend_from_slice(&sender_lock.calc_script_hash().as_slice()[0..self.hash_len]);
+        if self.timeout == EscrowTimeout::InArgs {
+            let since = timeout_since.ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!("missing escrow timeout since"))
+            })?;
+            args.extend_from_slice(&since.to_le_bytes());
+        }
+        Ok(Script::new_builder()
+            .code_hash(self.script_id.code_hash.pack())
+            .hash_type(self.script_id.hash_type.into())
+            .args(Bytes::from(args).pack())
+            .build())
+    }
+
+    /// Parse the args of an escrow lock script
+    pub fn parse_args(&self, lock_script: &Script) -> Result<EscrowArgs, TxBuilderError> {
+        if !self.script_id.matches(lock_script) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "lock script is not an escrow script: {:?}",
+                lock_script
+            )));
+        }
+        let args = lock_script.args().raw_data();
+        if args.len() != self.args_len() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "invalid escrow lock args length, expected: {}, got: {}",
+                self.args_len(),
+                args.len()
+            )));
+        }
+        let timeout_since = match self.timeout {
+            EscrowTimeout::Fixed(since) => since,
+            EscrowTimeout::InArgs => {
+                let mut since_bytes = [0u8; 8];
+                since_bytes.copy_from_slice(&args[self.hash_len * 2..]);
+                u64::from_le_bytes(since_bytes)
+            }
+        };
+        Ok(EscrowArgs {
+            receiver_hash: args.slice(0..self.hash_len),
+            sender_hash: args.slice(self.hash_len..self.hash_len * 2),
+            timeout_since,
+        })
+    }
+}
+
+/// The escrow cells spent by a claim or a refund
+struct EscrowCells {
+    lock_script: Script,
+    type_script: Option<Script>,
+    args: EscrowArgs,
+    total_capacity: Capacity,
+    /// The total UDT amount, always zero for cells without type script
+    total_amount: u128,
+}
+
+fn parse_amount(data: &[u8]) -> Result<u128, TxBuilderError> {
+    if data.len() != 16 {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "invalid escrow cell data length, expected: 16, got: {}",
+            data.len()
+        )));
+    }
+    let mut amount_bytes = [0u8; 16];
+    amount_bytes.copy_from_slice(data);
+    Ok(u128::from_le_bytes(amount_bytes))
+}
+
+/// Load and check the escrow cells: all cells must have the same lock script
+/// and the same type script. Cells with a type script hold a 16 bytes UDT
+/// amount, cells without type script must have empty data.
+fn load_escrow_cells(
+    escrow: &EscrowScript,
+    out_points: &[OutPoint],
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<EscrowCells, TxBuilderError> {
+    if out_points.is_empty() {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "empty escrow inputs"
+        )));
+    }
+    let input_cells = tx_dep_provider.get_cells_with_data(out_points)?;
+    let (first_cell, _) = &input_cells[0];
+    let lock_script = first_cell.lock();
+    let type_script = first_cell.type_().to_opt();
+    let args = escrow.parse_args(&lock_script)?;
+
+    let mut total_amount: u128 = 0;
+    let mut total_capacity = Capacity::zero();
+    for (out_point, (input_cell, input_data)) in out_points.iter().zip(input_cells.iter()) {
+        if input_cell.lock() != lock_script {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "all escrow input lock script must be the same: {}",
+                out_point
+            )));
+        }
+        if input_cell.type_().to_opt() != type_script {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "all escrow input type script must be the same: {}",
+                out_point
+            )));
+        }
+        if type_script.is_some() {
+            let input_amount = parse_amount(input_data)?;
+            total_amount = total_amount
+                .checked_add(input_amount)
+                .ok_or(TxBuilderError::AmountOverflow(total_amount, input_amount))?;
+        } else if !input_data.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "escrow input without type script must have empty data: {}",
+                out_point
+            )));
+        }
+        let input_capacity: u64 = input_cell.capacity().unpack();
+        total_capacity = total_capacity.safe_add(Capacity::shannons(input_capacity))?;
+    }
+    Ok(EscrowCells {
+        lock_script,
+        type_script,
+        args,
+        total_capacity,
+        total_amount,
+    })
+}
+
+/// Claim escrow cells as the receiver.
+///
+/// The receiver input proves the ownership and receives the escrowed assets.
+/// For UDT escrow cells the amount is added to the receiver input (which must
+/// be a cell of the same UDT) and the escrow capacity goes back to the sender,
+/// for plain escrow cells the capacity is added to the receiver input.
+pub struct EscrowClaimBuilder {
+    pub escrow: EscrowScript,
+    pub out_points: Vec<OutPoint>,
+    /// A cell locked by the receiver, its lock hash must match the escrow args
+    pub receiver_input: OutPoint,
+    /// Only used by UDT escrow cells, its lock hash must match the escrow args
+    pub sender_lock_script: Script,
+}
+
+impl EscrowClaimBuilder {
+    pub fn new(
+        escrow: EscrowScript,
+        out_points: Vec<OutPoint>,
+        receiver_input: OutPoint,
+        sender_lock_script: Script,
+    ) -> EscrowClaimBuilder {
+        EscrowClaimBuilder {
+            escrow,
+            out_points,
+            receiver_input,
+            sender_lock_script,
+        }
+    }
+}
+
+impl TxBuilder for EscrowClaimBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let escrow_cells = load_escrow_cells(&self.escrow, &self.out_points, tx_dep_provider)?;
+        let (receiver_cell, receiver_data) = tx_dep_provider
+            .get_cells_with_data(&[self.receiver_input.clone()])?
+            .remove(0);
+        let receiver_lock = receiver_cell.lock();
+        if !escrow_cells.args.is_receiver(&receiver_lock) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "receiver input lock script is not match with escrow lock script args"
+            )));
+        }
+
+        let mut cell_dep_scripts = vec![escrow_cells.lock_script.clone(), receiver_lock];
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        if let Some(type_script) = escrow_cells.type_script.clone() {
+            if receiver_cell.type_().to_opt().as_ref() != Some(&type_script) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "receiver input's type script not same with escrow input's type script"
+                )));
+            }
+            if !escrow_cells.args.is_sender(&self.sender_lock_script) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "sender lock script is not match with escrow lock script args"
+                )));
+            }
+            let receiver_amount = parse_amount(&receiver_data)?;
+            let total_amount = receiver_amount
+                .checked_add(escrow_cells.total_amount)
+                .ok_or(TxBuilderError::AmountOverflow(
+                    receiver_amount,
+                    escrow_cells.total_amount,
+                ))?;
+            outputs.push(receiver_cell);
+            outputs_data.push(Bytes::from(total_amount.to_le_bytes().to_vec()));
+            outputs.push(
+                CellOutput::new_builder()
+                    .lock(self.sender_lock_script.clone())
+                    .capacity(escrow_cells.total_capacity.pack())
+                    .build(),
+            );
+            outputs_data.push(Bytes::new());
+            cell_dep_scripts.push(type_script);
+        } else {
+            let receiver_capacity: u64 = receiver_cell.capacity().unpack();
+            let total_capacity =
+                Capacity::shannons(receiver_capacity).safe_add(escrow_cells.total_capacity)?;
+            if let Some(type_script) = receiver_cell.type_().to_opt() {
+                cell_dep_scripts.push(type_script);
+            }
+            outputs.push(
+                receiver_cell
+                    .as_builder()
+                    .capacity(total_capacity.pack())
+                    .build(),
+            );
+            outputs_data.push(receiver_data);
+        }
+
+        let mut inputs = self
+            .out_points
+            .iter()
+            .map(|out_point| CellInput::new(out_point.clone(), 0))
+            .collect::<Vec<_>>();
+        inputs.push(CellInput::new(self.receiver_input.clone(), 0));
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data.into_iter().map(|data| data.pack()).collect())
+            .build())
+    }
+}
+
+/// Refund escrow cells to the sender after the timeout, the inputs carry the
+/// timeout since of the escrow args.
+pub struct EscrowRefundBuilder {
+    pub escrow: EscrowScript,
+    pub out_points: Vec<OutPoint>,
+    /// The lock hash must match the escrow args
+    pub sender_lock_script: Script,
+}
+
+impl EscrowRefundBuilder {
+    pub fn new(
+        escrow: EscrowScript,
+        out_points: Vec<OutPoint>,
+        sender_lock_script: Script,
+    ) -> EscrowRefundBuilder {
+        EscrowRefundBuilder {
+            escrow,
+            out_points,
+            sender_lock_script,
+        }
+    }
+}
+
+impl TxBuilder for EscrowRefundBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let escrow_cells = load_escrow_cells(&self.escrow, &self.out_points, tx_dep_provider)?;
+        if !escrow_cells.args.is_sender(&self.sender_lock_script) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "sender lock script is not match with escrow lock script args"
+            )));
+        }
+
+        let mut cell_dep_scripts = vec![escrow_cells.lock_script.clone()];
+        let output_data = if let Some(type_script) = escrow_cells.type_script.as_ref() {
+            cell_dep_scripts.push(type_script.clone());
+            Bytes::from(escrow_cells.total_amount.to_le_bytes().to_vec())
+        } else {
+            Bytes::new()
+        };
+        let sender_output = CellOutput::new_builder()
+            .lock(self.sender_lock_script.clone())
+            .type_(escrow_cells.type_script.pack())
+            .capacity(escrow_cells.total_capacity.pack())
+            .build();
+
+        let inputs = self
+            .out_points
+            .iter()
+            .map(|out_point| CellInput::new(out_point.clone(), escrow_cells.args.timeout_since))
+            .collect::<Vec<_>>();
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_outputs(vec![sender_output])
+            .set_outputs_data(vec![output_data.pack()])
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::ScriptHashType, h256};
+
+    #[test]
+    fn test_escrow_args() {
+        let script_id = ScriptId::new_data1(h256!("0x1234"));
+        let receiver = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let sender = Script::new_builder()
+            .args(Bytes::from(vec![2u8; 20]).pack())
+            .build();
+
+        let escrow = EscrowScript::new(script_id.clone(), 20, EscrowTimeout::InArgs);
+        assert!(escrow.lock_script(&receiver, &sender, None).is_err());
+        let lock_script = escrow.lock_script(&receiver, &sender, Some(42)).unwrap();
+        assert_eq!(lock_script.args().raw_data().len(), 48);
+        let args = escrow.parse_args(&lock_script).unwrap();
+        assert_eq!(args.timeout_since, 42);
+        assert!(args.is_receiver(&receiver));
+        assert!(args.is_sender(&sender));
+        assert!(!args.is_sender(&receiver));
+
+        let cheque = EscrowScript::new_cheque(script_id);
+        let lock_script = cheque.lock_script(&receiver, &sender, Some(42)).unwrap();
+        assert_eq!(lock_script.args().raw_data().len(), 40);
+        assert_eq!(
+            cheque.parse_args(&lock_script).unwrap().timeout_since,
+            CHEQUE_CELL_SINCE
+        );
+        // a cheque lock is not an escrow of the in-args layout
+        assert!(escrow.parse_args(&lock_script).is_err());
+        let other_lock = lock_script
+            .as_builder()
+            .hash_type(ScriptHashType::Type.into())
+            .build();
+        assert!(cheque.parse_args(&other_lock).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! Add cell deps and header deps the resolvers don't know to any builder.
+//!
+//! [`ExtraDepsBuilder`] wraps a builder and appends its extra deps to the
+//! base transaction, e.g. the code cell of a custom type script in the
+//! outputs. The deps already in the base transaction are not added again
+//! and the existing ones keep their index, which the DAO withdraw witnesses
+//! refer to.
+
+use ckb_hash::new_blake2b;
+use ckb_types::{
+    core::TransactionView,
+    packed::{Byte32, CellDep},
+    prelude::*,
+    H256,
+};
+
+use super::{template::TemplateKey, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+
+/// A builder adding `extra_cell_deps` and `extra_header_deps` to the base
+/// transaction of `builder`.
+#[derive(Debug, Clone)]
+pub struct ExtraDepsBuilder<B> {
+    pub builder: B,
+    pub extra_cell_deps: Vec<CellDep>,
+    pub extra_header_deps: Vec<Byte32>,
+}
+
+impl<B: TxBuilder> ExtraDepsBuilder<B> {
+    pub fn new(builder: B) -> ExtraDepsBuilder<B> {
+        ExtraDepsBuilder {
+            builder,
+            extra_cell_deps: Vec::new(),
+            extra_header_deps: Vec::new(),
+        }
+    }
+
+    pub fn cell_dep(mut self, cell_dep: CellDep) -> Self {
+        self.extra_cell_deps.push(cell_dep);
+        self
+    }
+
+    /// Add the block hash of a header dep
+    pub fn header_dep(mut self, block_hash: Byte32) -> Self {
+        self.extra_header_deps.push(block_hash);
+        self
+    }
+}
+
+impl<B: TxBuilder> TxBuilder for ExtraDepsBuilder<B> {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let tx = self.builder.build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        let mut cell_deps: Vec<CellDep> = tx.cell_deps().into_iter().collect();
+        for cell_dep in &self.extra_cell_deps {
+            if !cell_deps.contains(cell_dep) {
+                cell_deps.push(cell_dep.clone());
+            }
+        }
+        let mut header_deps: Vec<Byte32> = tx.header_deps().into_iter().collect();
+        for block_hash in &self.extra_header_deps {
+            if !header_deps.contains(block_hash) {
+                header_deps.push(block_hash.clone());
+            }
+        }
+        Ok(tx
+            .as_advanced_builder()
+            .set_cell_deps(cell_deps)
+            .set_header_deps(header_deps)
+            .build())
+    }
+}
+
+impl<B: TemplateKey> TemplateKey for ExtraDepsBuilder<B> {
+    fn template_key(&self) -> H256 {
+        let mut hasher = new_blake2b();
+        hasher.update(b"ExtraDepsBuilder");
+        hasher.update(self.builder.template_key().as_bytes());
+        for cell_dep in &self.extra_cell_deps {
+            hasher.update(cell_dep.as_slice());
+        }
+        hasher.update(&(self.extra_cell_deps.len() as u64).to_le_bytes());
+        for block_hash in &self.extra_header_deps {
+            hasher.update(block_hash.as_slice());
+        }
+        let mut key = [0u8; 32];
+        hasher.finalize(&mut key);
+        H256(key)
+    }
+}
@@ -0,0 +1,112 @@
+//! Funding requests: transactions with outputs only, funded later by a payer.
+//!
+//! The receiver of a payment builds the output side with any [`TxBuilder`]
+//! whose base transaction has no input and sends the [`FundingRequest`] (it can
+//! be serialized as JSON) to the payer. The payer calls [`fund_transaction`]
+//! to add its cells and the change, then signs the transaction.
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    packed::Transaction,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{balance_tx_capacity, CapacityBalancer, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+
+/// A transaction without inputs and the capacity it requires
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FundingRequest {
+    pub tx: json_types::Transaction,
+    /// The total capacity of the outputs in shannons, the fee is not included
+    pub required_capacity: json_types::Capacity,
+}
+
+impl FundingRequest {
+    /// Declare the required capacity of `tx`, which must have no inputs
+    pub fn new(tx: &TransactionView) -> Result<FundingRequest, TxBuilderError> {
+        if !tx.inputs().is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "funding request transaction must have no inputs"
+            )));
+        }
+        Ok(FundingRequest {
+            tx: tx.data().into(),
+            required_capacity: outputs_capacity(tx)?.into(),
+        })
+    }
+
+    /// Build the base transaction of `builder` as a funding request
+    pub fn build(
+        builder: &dyn TxBuilder,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<FundingRequest, TxBuilderError> {
+        let tx = builder.build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        FundingRequest::new(&tx)
+    }
+
+    pub fn tx_view(&self) -> TransactionView {
+        Transaction::from(self.tx.clone()).into_view()
+    }
+
+    pub fn required_capacity(&self) -> u64 {
+        self.required_capacity.value()
+    }
+}
+
+/// Add the inputs and the change of `balancer` to a funding request.
+///
+/// Fails when the outputs of the request do not match its declared required
+/// capacity, so the payer never pays more than it was asked for.
+pub fn fund_transaction(
+    request: &FundingRequest,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<TransactionView, TxBuilderError> {
+    let tx = request.tx_view();
+    if !tx.inputs().is_empty() {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "funding request transaction must have no inputs"
+        )));
+    }
+    let capacity = outputs_capacity(&tx)?;
+    if capacity != request.required_capacity() {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "outputs capacity {} does not match the required capacity {}",
+            capacity,
+            request.required_capacity()
+        )));
+    }
+    Ok(balance_tx_capacity(
+        &tx,
+        balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
+    )?)
+}
+
+fn outputs_capacity(tx: &TransactionView) -> Result<u64, TxBuilderError> {
+    let mut total = Capacity::zero();
+    for output in tx.outputs() {
+        total = total.safe_add(Capacity::shannons(output.capacity().unpack()))?;
+    }
+    Ok(total.as_u64())
+}
@@ -0,0 +1,106 @@
+use anyhow::anyhow;
+use ckb_types::{
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, OutPoint, Script},
+    prelude::*,
+};
+
+use super::{resolve_cell_deps, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+use crate::types::{HtlcAction, HtlcArgs, ScriptId};
+
+/// Spend hash time locked cells, by claiming them with the preimage or by
+/// refunding them after the timeout.
+///
+/// Every cell is moved to `to_lock` keeping its capacity, type script and
+/// data, the fee is paid by the balancer. To lock a cell, transfer it to
+/// [`HtlcArgs::lock_script`].
+pub struct HtlcBuilder {
+    /// The HTLC script, all cells must be locked by it
+    pub script_id: ScriptId,
+    pub out_points: Vec<OutPoint>,
+    pub action: HtlcAction,
+    pub to_lock: Script,
+}
+
+impl HtlcBuilder {
+    pub fn new(
+        script_id: ScriptId,
+        out_points: Vec<OutPoint>,
+        action: HtlcAction,
+        to_lock: Script,
+    ) -> HtlcBuilder {
+        HtlcBuilder {
+            script_id,
+            out_points,
+            action,
+            to_lock,
+        }
+    }
+}
+
+impl TxBuilder for HtlcBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.out_points.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty htlc inputs"
+            )));
+        }
+
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        let mut cell_dep_scripts = vec![self.to_lock.clone()];
+        let input_cells = tx_dep_provider.get_cells_with_data(&self.out_points)?;
+        for (out_point, (input_cell, input_data)) in self.out_points.iter().zip(input_cells) {
+            let lock_script = input_cell.lock();
+            if !self.script_id.matches(&lock_script) {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "input is not locked by the htlc script: {}",
+                    out_point
+                )));
+            }
+            let args = HtlcArgs::from_slice(&lock_script.args().raw_data()).ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "invalid htlc lock args of input: {}",
+                    out_point
+                ))
+            })?;
+            let since = match &self.action {
+                HtlcAction::Claim { preimage } => {
+                    if !args.match_preimage(preimage) {
+                        return Err(TxBuilderError::InvalidParameter(anyhow!(
+                            "preimage not match with the hash of input: {}",
+                            out_point
+                        )));
+                    }
+                    0
+                }
+                HtlcAction::Refund => args.timeout_since,
+            };
+            inputs.push(CellInput::new(out_point.clone(), since));
+            cell_dep_scripts.push(lock_script);
+            if let Some(type_script) = input_cell.type_().to_opt() {
+                cell_dep_scripts.push(type_script);
+            }
+            outputs.push(input_cell.as_builder().lock(self.to_lock.clone()).build());
+            outputs_data.push(input_data.pack());
+        }
+
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build())
+    }
+}
@@ -0,0 +1,369 @@
+//! Migrate cells from deprecated script deployments.
+//!
+//! When a system script is redeployed (e.g. a new ACP or omnilock release),
+//! the cells of the old deployment only stay spendable while its code cell is
+//! live. A [`ScriptMigration`] maps a deprecated `ScriptId` to the current
+//! one, [`MigrationBuilder`] moves all the cells of some deprecated lock
+//! scripts to the current version, keeping the args.
+//!
+//! Wallets moving users to the CKB2021 address format can find the cells
+//! still locked by the `data` hash type of old full format addresses with
+//! [`scan_deprecated_locks`], and move them to the lock of the
+//! [`canonical_address`] with [`MigrationBuilder::from_deprecated_locks`].
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{ScriptHashType, TransactionBuilder, TransactionView},
+    packed::{CellInput, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+
+use super::{
+    balance_tx_capacity, multisig_rotation::split_fee_chunks, plan::DryRunCellCollector,
+    resolve_cell_deps, tx_fee, BalanceTxCapacityError, CapacityBalancer, CapacityProvider,
+    TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider,
+};
+use crate::types::{Address, AddressPayload, AddressType, ScriptId};
+
+/// The default maximum inputs of a migration transaction
+pub const DEFAULT_MAX_MIGRATION_INPUTS: usize = 500;
+
+/// A deprecated script version and the version replacing it
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ScriptMigration {
+    pub deprecated: ScriptId,
+    pub current: ScriptId,
+}
+
+impl ScriptMigration {
+    pub fn new(deprecated: ScriptId, current: ScriptId) -> ScriptMigration {
+        ScriptMigration {
+            deprecated,
+            current,
+        }
+    }
+
+    pub fn is_deprecated(&self, script: &Script) -> bool {
+        self.deprecated.matches(script)
+    }
+
+    /// The deprecated script with `args`, e.g. to look for the old cells of
+    /// an account
+    pub fn deprecated_script(&self, args: Bytes) -> Script {
+        Script::new_builder()
+            .code_hash(self.deprecated.code_hash.pack())
+            .hash_type(self.deprecated.hash_type.into())
+            .args(args.pack())
+            .build()
+    }
+
+    /// The current version of `script` with the same args, `None` if the
+    /// script is not of the deprecated version.
+    pub fn migrate(&self, script: &Script) -> Option<Script> {
+        if !self.is_deprecated(script) {
+            return None;
+        }
+        Some(
+            script
+                .clone()
+                .as_builder()
+                .code_hash(self.current.code_hash.pack())
+                .hash_type(self.current.hash_type.into())
+                .build(),
+        )
+    }
+}
+
+/// The affected cells and the fees of a migration, see
+/// [`MigrationBuilder::dry_run`]
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    pub cells: Vec<LiveCell>,
+    /// The fee of each transaction in shannons
+    pub fees: Vec<u64>,
+}
+
+impl MigrationReport {
+    pub fn total_fee(&self) -> u64 {
+        self.fees.iter().sum()
+    }
+}
+
+/// Move all the cells of deprecated lock scripts to the current version.
+///
+/// The type scripts of the cells are migrated too when they are deprecated,
+/// the data is kept. Like [`MultisigRotationBuilder`](super::multisig_rotation::MultisigRotationBuilder),
+/// the plain cells of a lock are merged into one change cell of the migrated
+/// lock which pays the fee, there are more transactions when the cells exceed
+/// `max_inputs`.
+pub struct MigrationBuilder {
+    pub migrations: Vec<ScriptMigration>,
+    /// The deprecated lock scripts and the placeholder witness of each lock
+    pub lock_scripts: Vec<(Script, WitnessArgs)>,
+    /// Maximum inputs of a transaction
+    pub max_inputs: usize,
+    pub fee_rate: u64,
+}
+
+impl MigrationBuilder {
+    pub fn new(
+        migrations: Vec<ScriptMigration>,
+        lock_scripts: Vec<(Script, WitnessArgs)>,
+        fee_rate: u64,
+    ) -> MigrationBuilder {
+        MigrationBuilder {
+            migrations,
+            lock_scripts,
+            max_inputs: DEFAULT_MAX_MIGRATION_INPUTS,
+            fee_rate,
+        }
+    }
+
+    /// Migrate the cells found by [`scan_deprecated_locks`] to the `data1`
+    /// hash type, all the locks share `placeholder_witness`.
+    pub fn from_deprecated_locks(
+        deprecated_locks: &[DeprecatedLockCells],
+        placeholder_witness: WitnessArgs,
+        fee_rate: u64,
+    ) -> MigrationBuilder {
+        let mut migrations: Vec<ScriptMigration> = Vec::new();
+        let mut lock_scripts = Vec::new();
+        for deprecated in deprecated_locks {
+            let code_hash: H256 = deprecated.lock.code_hash().unpack();
+            let migration = ScriptMigration::new(
+                ScriptId::new_data(code_hash.clone()),
+                ScriptId::new_data1(code_hash),
+            );
+            if !migrations.contains(&migration) {
+                migrations.push(migration);
+            }
+            lock_scripts.push((deprecated.lock.clone(), placeholder_witness.clone()));
+        }
+        MigrationBuilder::new(migrations, lock_scripts, fee_rate)
+    }
+
+    /// The current version of `script`, `None` if it's not deprecated
+    pub fn migrate_script(&self, script: &Script) -> Option<Script> {
+        self.migrations
+            .iter()
+            .find_map(|migration| migration.migrate(script))
+    }
+
+    /// Build the balanced (unsigned) migration transactions, all the cells
+    /// of the deprecated lock scripts are locked in `cell_collector`.
+    pub fn build(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<TransactionView>, TxBuilderError> {
+        self.build_with_cells(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+        .map(|(txs, _)| txs)
+    }
+
+    /// Report the affected cells and the fees without reserving any cell in
+    /// `cell_collector`.
+    pub fn dry_run(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<MigrationReport, TxBuilderError> {
+        let mut dry_run_collector = DryRunCellCollector::new(cell_collector);
+        let (txs, cells) = self.build_with_cells(
+            &mut dry_run_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        let fees = txs
+            .into_iter()
+            .map(|tx| tx_fee(tx, tx_dep_provider, header_dep_resolver))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(BalanceTxCapacityError::from)?;
+        Ok(MigrationReport { cells, fees })
+    }
+
+    fn build_with_cells(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<(Vec<TransactionView>, Vec<LiveCell>), TxBuilderError> {
+        if self.max_inputs < 2 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "max_inputs must be at least 2"
+            )));
+        }
+        let mut txs = Vec::new();
+        let mut migrated_cells = Vec::new();
+        for (old_lock, placeholder_witness) in &self.lock_scripts {
+            let new_lock = self.migrate_script(old_lock).ok_or_else(|| {
+                TxBuilderError::InvalidParameter(anyhow!(
+                    "lock script is not deprecated: {:?}",
+                    old_lock
+                ))
+            })?;
+            let mut query = CellQueryOptions::new_lock(old_lock.clone());
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+            if cells.is_empty() {
+                continue;
+            }
+            migrated_cells.extend(cells.iter().cloned());
+            let chunks = split_fee_chunks(cells, self.max_inputs, "deprecated lock")?;
+
+            let mut balancer = CapacityBalancer::new_with_provider(
+                self.fee_rate,
+                CapacityProvider::new_simple(vec![(old_lock.clone(), placeholder_witness.clone())]),
+            );
+            balancer.change_lock_script = Some(new_lock.clone());
+            for chunk in chunks {
+                let mut cell_dep_scripts = vec![old_lock.clone()];
+                let mut inputs = Vec::new();
+                let mut outputs = Vec::new();
+                let mut outputs_data = Vec::new();
+                for cell in chunk {
+                    inputs.push(CellInput::new(cell.out_point.clone(), 0));
+                    let type_script = cell.output.type_().to_opt();
+                    if type_script.is_none() && cell.output_data.is_empty() {
+                        // merged into the change cell
+                        continue;
+                    }
+                    let mut output = cell.output.as_builder().lock(new_lock.clone());
+                    if let Some(type_script) = type_script {
+                        let new_type_script = self
+                            .migrate_script(&type_script)
+                            .unwrap_or_else(|| type_script.clone());
+                        cell_dep_scripts.push(type_script);
+                        cell_dep_scripts.push(new_type_script.clone());
+                        output = output.type_(Some(new_type_script).pack());
+                    }
+                    outputs.push(output.build());
+                    outputs_data.push(cell.output_data.pack());
+                }
+                let mut witnesses = vec![Bytes::new().pack(); inputs.len()];
+                witnesses[0] = placeholder_witness.as_bytes().pack();
+                let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+                let base_tx = TransactionBuilder::default()
+                    .set_cell_deps(cell_deps)
+                    .set_inputs(inputs)
+                    .set_outputs(outputs)
+                    .set_outputs_data(outputs_data)
+                    .set_witnesses(witnesses)
+                    .build();
+                txs.push(balance_tx_capacity(
+                    &base_tx,
+                    &balancer,
+                    cell_collector,
+                    tx_dep_provider,
+                    cell_dep_resolver,
+                    header_dep_resolver,
+                )?);
+            }
+        }
+        Ok((txs, migrated_cells))
+    }
+}
+
+/// The CKB2021 full format of `address`.
+///
+/// Short and old full format addresses are encoded in the full format, and
+/// the pre-CKB2021 `data` hash type becomes `data1`, which runs the same code
+/// with the CKB2021 VM. The lock of the canonical address only differs from
+/// the lock of `address` in the latter case.
+///
+/// # Panics
+///
+/// Same as [`AddressPayload::code_hash`], for short format anyone-can-pay
+/// addresses of the networks other than mainnet and testnet.
+pub fn canonical_address(address: &Address) -> Address {
+    let payload = address.payload();
+    let hash_type = match payload.hash_type() {
+        ScriptHashType::Data => ScriptHashType::Data1,
+        hash_type => hash_type,
+    };
+    let payload = AddressPayload::new_full(
+        hash_type,
+        payload.code_hash(Some(address.network())),
+        payload.args(),
+    );
+    Address::new(address.network(), payload, true)
+}
+
+/// The address uses a format deprecated by CKB2021 (short or old full
+/// format) or the `data` hash type
+pub fn is_deprecated_address(address: &Address) -> bool {
+    address.payload().ty(address.is_new()) != AddressType::Full
+        || address.payload().hash_type() == ScriptHashType::Data
+}
+
+/// The cells of a lock created by the deprecated `data` hash type
+#[derive(Debug, Clone)]
+pub struct DeprecatedLockCells {
+    pub lock: Script,
+    /// The lock of the [`canonical_address`]
+    pub canonical_lock: Script,
+    pub cells: Vec<LiveCell>,
+}
+
+impl DeprecatedLockCells {
+    pub fn capacity(&self) -> u64 {
+        self.cells
+            .iter()
+            .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .sum()
+    }
+}
+
+/// Look for the cells of the accounts of `addresses` locked with the `data`
+/// hash type instead of `data1`, whatever the format of the addresses. The
+/// cells are not locked in `cell_collector`, locks without cells are not
+/// reported.
+pub fn scan_deprecated_locks(
+    cell_collector: &mut dyn CellCollector,
+    addresses: &[Address],
+) -> Result<Vec<DeprecatedLockCells>, TxBuilderError> {
+    let mut reports: Vec<DeprecatedLockCells> = Vec::new();
+    for address in addresses {
+        let canonical = canonical_address(address);
+        if canonical.payload().hash_type() != ScriptHashType::Data1 {
+            // type hash locks have no deprecated variant
+            continue;
+        }
+        let canonical_lock = Script::from(&canonical);
+        let lock = canonical_lock
+            .clone()
+            .as_builder()
+            .hash_type(ScriptHashType::Data.into())
+            .build();
+        if reports.iter().any(|report| report.lock == lock) {
+            continue;
+        }
+        let mut query = CellQueryOptions::new_lock(lock.clone());
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+        if !cells.is_empty() {
+            reports.push(DeprecatedLockCells {
+                lock,
+                canonical_lock,
+                cells,
+            });
+        }
+    }
+    Ok(reports)
+}
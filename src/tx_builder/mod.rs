@@ -1,42 +1,87 @@
 pub mod acp;
 pub mod cheque;
+pub mod coin_selection;
+pub mod config;
+#[cfg(feature = "verify")]
+pub mod cycles;
+#[cfg(feature = "builders-dao")]
 pub mod dao;
+pub mod donation;
+pub mod escrow;
+pub mod extra_deps;
+pub mod funding;
+pub mod htlc;
+pub mod migration;
+pub mod multisig_rotation;
 pub mod omni_lock;
+pub mod plan;
+pub mod policy;
+pub mod receipt;
+pub mod refund;
+pub mod swap;
+pub mod sweep;
+pub mod template;
+pub mod trace;
 pub mod transfer;
+#[cfg(feature = "builders-udt")]
 pub mod udt;
+pub mod vesting;
+pub mod vote;
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+#[cfg(feature = "verify")]
 use std::sync::Arc;
 
 use anyhow::anyhow;
+#[cfg(feature = "verify")]
 use ckb_chain_spec::consensus::Consensus;
-use ckb_script::{TransactionScriptsVerifier, TxVerifyEnv};
+#[cfg(feature = "verify")]
 use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
 use thiserror::Error;
 
+#[cfg(feature = "verify")]
 use ckb_types::core::cell::{CellProvider, HeaderChecker};
+#[cfg(feature = "verify")]
 use ckb_types::core::HeaderView;
 use ckb_types::{
+    bytes::Bytes,
     core::{
-        cell::resolve_transaction, error::OutPointError, Capacity, CapacityError, FeeRate,
-        TransactionView,
+        error::OutPointError, Capacity, CapacityError, EpochNumberWithFraction, FeeRate,
+        ScriptHashType, TransactionView,
     },
-    packed::{Byte32, CellInput, CellOutput, Script, WitnessArgs},
+    packed::{Byte32, CellDep, CellInput, CellOutput, OutPoint, Script, WitnessArgs},
     prelude::*,
 };
 
+#[cfg(feature = "verify")]
+use crate::compat::{resolve_tx, verify_tx_scripts};
+use crate::redact;
 use crate::types::ScriptGroup;
-use crate::types::{HumanCapacity, ScriptId};
-use crate::unlock::{ScriptUnlocker, UnlockError};
-use crate::util::calculate_dao_maximum_withdraw4;
-use crate::{constants::DAO_TYPE_HASH, NetworkType};
+use crate::types::{HumanCapacity, ScriptId, Since, SinceType};
+use crate::unlock::{
+    negotiate_witness_layout, ScriptSignError, ScriptUnlocker, UnlockError, WitnessLayout,
+};
+use crate::util::{calculate_dao_maximum_withdraw4, parse_dao_deposit_number, CellDataError};
+use crate::{
+    constants::{
+        ACP_TYPE_HASH_AGGRON, ACP_TYPE_HASH_LINA, DAO_TYPE_HASH, MULTISIG_TYPE_HASH,
+        SIGHASH_TYPE_HASH,
+    },
+    NetworkType,
+};
 use crate::{
     traits::{
         CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
-        TransactionDependencyError, TransactionDependencyProvider, ValueRangeOption,
+        LiveCell, MaturityOption, TransactionDependencyError, TransactionDependencyProvider,
+        ValueRangeOption,
     },
     RpcError,
 };
+use donation::{DonationError, DonationGuard};
+use plan::{DryRunCellCollector, TxPlan};
+use policy::{OutputPolicy, OutputPolicyError};
+use trace::{BuildTrace, BuildTracer};
 
 /// Transaction builder errors
 #[derive(Error, Debug)]
@@ -55,7 +100,7 @@ pub enum TxBuilderError {
     #[error("balance capacity error: `{0}`")]
     BalanceCapacity(#[from] BalanceTxCapacityError),
 
-    #[error("resolve cell dep failed: `{0}`")]
+    #[error("resolve cell dep failed: `{}`", redact::script(.0))]
     ResolveCellDepFailed(Script),
 
     #[error("resolve header dep by transaction hash failed: `{0}`")]
@@ -76,6 +121,26 @@ pub enum TxBuilderError {
     #[error("can not find specifed output to put small change")]
     NoOutputForSmallChange,
 
+    #[error("capacity error: `{0}`")]
+    Capacity(#[from] CapacityError),
+    #[error("udt amount overflow: `{}` + `{}`", redact::amount(.0), redact::amount(.1))]
+    AmountOverflow(u128, u128),
+
+    #[error("type id `{}` is already used by live cell `{1}`", redact::script(.0))]
+    TypeIdCollision(Script, OutPoint),
+
+    #[error("missing signer: `{0}`")]
+    MissingSigner(MissingSignerReport),
+
+    #[error("input `{0}` is not spendable yet: {1}")]
+    SinceNotReached(usize, String),
+
+    #[error("{0}")]
+    OutputPolicy(#[from] OutputPolicyError),
+
+    #[error("{0}")]
+    Donation(#[from] DonationError),
+
     #[error("other error: `{0}`")]
     Other(anyhow::Error),
 }
@@ -122,6 +187,101 @@ pub trait TxBuilder {
         )?)
     }
 
+    /// Dry run `build_balanced` and describe the result: the selected inputs,
+    /// the change, the fee and the lock script groups to sign.
+    ///
+    /// Nothing is signed and the cells are not reserved in `cell_collector`,
+    /// so it's cheap to show a confirmation before the real build.
+    fn plan(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<TxPlan, TxBuilderError> {
+        let mut dry_run_collector = DryRunCellCollector::new(cell_collector);
+        let base_tx = self.build_base(
+            &mut dry_run_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        let base_outputs_len = base_tx.outputs().len();
+        let (tx_filled_witnesses, _) =
+            fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        let (balanced_tx, fee_breakdown) = balance_tx_capacity_with_breakdown(
+            &tx_filled_witnesses,
+            balancer,
+            &mut dry_run_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        let mut plan = TxPlan::from_balanced_tx(
+            &balanced_tx,
+            base_outputs_len,
+            tx_dep_provider,
+            header_dep_resolver,
+        )?;
+        plan.fee_breakdown = Some(fee_breakdown);
+        Ok(plan)
+    }
+
+    /// Same as `build_balanced`, and record every answer of the providers into
+    /// a `BuildTrace`, which is returned even if the build failed. The build
+    /// runs on a clone of `cell_collector`, whose changes are only made to
+    /// `cell_collector` when the build succeeded, the inputs of the built
+    /// transaction are locked at `tip_block_number`.
+    #[allow(clippy::too_many_arguments)]
+    fn build_balanced_traced(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+        tip_block_number: u64,
+    ) -> (Result<TransactionView, TxBuilderError>, BuildTrace) {
+        let tracer = BuildTracer::new();
+        let mut tracing_collector = tracer.cell_collector(cell_collector);
+        let result = {
+            let cell_dep_resolver = tracer.cell_dep_resolver(cell_dep_resolver);
+            let header_dep_resolver = tracer.header_dep_resolver(header_dep_resolver);
+            let tx_dep_provider = tracer.tx_dep_provider(tx_dep_provider);
+            self.build_base(
+                &mut tracing_collector,
+                &cell_dep_resolver,
+                &header_dep_resolver,
+                &tx_dep_provider,
+            )
+            .and_then(|base_tx| {
+                tracer.record_base_tx(&base_tx);
+                let (tx_filled_witnesses, _) =
+                    fill_placeholder_witnesses(base_tx, &tx_dep_provider, unlockers)?;
+                Ok(balance_tx_capacity(
+                    &tx_filled_witnesses,
+                    balancer,
+                    &mut tracing_collector,
+                    &tx_dep_provider,
+                    &cell_dep_resolver,
+                    &header_dep_resolver,
+                )?)
+            })
+        };
+        let result = result.and_then(|tx| {
+            tracing_collector.apply_changes_to(&tx, tip_block_number, cell_collector)?;
+            Ok(tx)
+        });
+        match &result {
+            Ok(tx) => tracer.record_balanced(tx),
+            Err(err) => tracer.record_error(err),
+        }
+        (result, tracer.into_trace())
+    }
+
     /// Build unlocked transaction that ready to send or for further unlock:
     ///   * build base transaction
     ///   * balance the capacity
@@ -150,6 +310,56 @@ pub trait TxBuilder {
         Ok(unlock_tx(balanced_tx, tx_dep_provider, unlockers)?)
     }
 
+    /// Same as `build_unlocked`, but check the outputs of the balanced
+    /// transaction against `policy` before signing.
+    #[allow(clippy::too_many_arguments)]
+    fn build_unlocked_with_policy(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+        policy: &OutputPolicy,
+    ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+        let balanced_tx = self.build_balanced(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+            balancer,
+            unlockers,
+        )?;
+        policy.check(&balanced_tx)?;
+        Ok(unlock_tx(balanced_tx, tx_dep_provider, unlockers)?)
+    }
+
+    /// Same as `build_unlocked`, but check the implied fee of the balanced
+    /// transaction against `guard` before signing.
+    #[allow(clippy::too_many_arguments)]
+    fn build_unlocked_with_donation_guard(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        balancer: &CapacityBalancer,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+        guard: &DonationGuard,
+    ) -> Result<(TransactionView, Vec<ScriptGroup>), TxBuilderError> {
+        let balanced_tx = self.build_balanced(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+            balancer,
+            unlockers,
+        )?;
+        guard.check(&balanced_tx, tx_dep_provider)?;
+        Ok(unlock_tx(balanced_tx, tx_dep_provider, unlockers)?)
+    }
+
     /// Build unlocked transaction that ready to send or for further unlock, it's similar to `build_unlocked`,
     /// except it will try to check the consumed cycles limitation:
     /// If all input unlocked, and transaction fee can not meet the required transaction fee rate because of a big estimated cycles,
@@ -158,6 +368,7 @@ pub trait TxBuilder {
     /// Return value:
     ///   * The built transaction
     ///   * The script groups that not unlocked by given `unlockers`
+    #[cfg(feature = "verify")]
     fn build_balance_unlocked(
         &self,
         cell_collector: &mut dyn CellCollector,
@@ -175,7 +386,7 @@ pub trait TxBuilder {
         )?;
         let (tx_filled_witnesses, _) =
             fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
-        let (balanced_tx, mut change_idx) = rebalance_tx_capacity(
+        let (balanced_tx, mut change_idx, _) = rebalance_tx_capacity(
             &tx_filled_witnesses,
             balancer,
             cell_collector,
@@ -244,6 +455,64 @@ pub enum TransactionFeeError {
 
     #[error("capacity sub overflow, delta: `{0}`")]
     CapacityOverflow(u64),
+
+    #[error("invalid cell data: `{0}`")]
+    CellData(#[from] CellDataError),
+}
+
+/// Resolve the cell deps of `scripts` in one batch.
+///
+/// Duplicated scripts (and scripts sharing the same cell dep) only produce one
+/// cell dep, the cell deps are returned in the order their scripts first
+/// appear so the built transaction is deterministic.
+pub fn resolve_cell_deps(
+    cell_dep_resolver: &dyn CellDepResolver,
+    scripts: &[Script],
+) -> Result<Vec<CellDep>, TxBuilderError> {
+    #[allow(clippy::mutable_key_type)]
+    let mut seen_scripts = HashSet::new();
+    let scripts = scripts
+        .iter()
+        .filter(|script| seen_scripts.insert(*script))
+        .cloned()
+        .collect::<Vec<_>>();
+    #[allow(clippy::mutable_key_type)]
+    let mut seen_cell_deps = HashSet::new();
+    let mut cell_deps = Vec::new();
+    for (script, cell_dep) in scripts
+        .iter()
+        .zip(cell_dep_resolver.resolve_batch(&scripts))
+    {
+        let cell_dep =
+            cell_dep.ok_or_else(|| TxBuilderError::ResolveCellDepFailed(script.clone()))?;
+        if seen_cell_deps.insert(cell_dep.clone()) {
+            cell_deps.push(cell_dep);
+        }
+    }
+    Ok(cell_deps)
+}
+
+/// Check no live cell already has the type id script, so creating it fails
+/// locally instead of on chain, e.g. when two deployments race for the same
+/// first input. Scripts other than type id and placeholder args are ignored.
+pub fn check_type_id_collision(
+    type_script: &Script,
+    cell_collector: &mut dyn CellCollector,
+) -> Result<(), TxBuilderError> {
+    let args = type_script.args().raw_data();
+    if !ScriptId::from(type_script).is_type_id() || args.iter().all(|byte| *byte == 0) {
+        return Ok(());
+    }
+    let mut query = CellQueryOptions::new_type(type_script.clone());
+    query.maturity = MaturityOption::Both;
+    let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+    match cells.into_iter().next() {
+        Some(cell) => Err(TxBuilderError::TypeIdCollision(
+            type_script.clone(),
+            cell.out_point,
+        )),
+        None => Ok(()),
+    }
 }
 
 /// Calculate the actual transaction fee of the transaction, include dao
@@ -254,7 +523,7 @@ pub fn tx_fee(
     tx_dep_provider: &dyn TransactionDependencyProvider,
     header_dep_resolver: &dyn HeaderDepResolver,
 ) -> Result<u64, TransactionFeeError> {
-    let mut input_total: u64 = 0;
+    let mut input_total = Capacity::zero();
     for input in tx.inputs() {
         let mut is_withdraw = false;
         let since: u64 = input.since().unpack();
@@ -278,12 +547,7 @@ pub fn tx_fee(
                     ))
                 })?;
             let data = tx_dep_provider.get_cell_data(&input.previous_output())?;
-            assert_eq!(data.len(), 8);
-            let deposit_number = {
-                let mut number_bytes = [0u8; 8];
-                number_bytes.copy_from_slice(data.as_ref());
-                u64::from_le_bytes(number_bytes)
-            };
+            let deposit_number = parse_dao_deposit_number(data.as_ref())?;
             let deposit_header = header_dep_resolver
                 .resolve_by_number(deposit_number)
                 .map_err(TransactionFeeError::HeaderDep)?
@@ -305,8 +569,9 @@ pub fn tx_fee(
         } else {
             cell.capacity().unpack()
         };
-        input_total += capacity;
+        input_total = input_total.safe_add(Capacity::shannons(capacity))?;
     }
+    let input_total = input_total.as_u64();
     let output_total = tx.outputs_capacity()?.as_u64();
     #[allow(clippy::unnecessary_lazy_evaluations)]
     input_total
@@ -366,7 +631,7 @@ pub enum BalanceTxCapacityError {
     #[error("capacity not enough: `{0}`")]
     CapacityNotEnough(String),
 
-    #[error("Force small change as fee failed, fee: `{0}`")]
+    #[error("Force small change as fee failed, fee: `{}`", redact::amount(.0))]
     ForceSmallChangeAsFeeFailed(u64),
 
     #[error("empty capacity provider")]
@@ -375,7 +640,7 @@ pub enum BalanceTxCapacityError {
     #[error("cell collector error: `{0}`")]
     CellCollector(#[from] CellCollectorError),
 
-    #[error("resolve cell dep failed: `{0}`")]
+    #[error("resolve cell dep failed: `{}`", redact::script(.0))]
     ResolveCellDepFailed(Script),
 
     #[error("invalid witness args: `{0}`")]
@@ -393,8 +658,98 @@ pub enum BalanceTxCapacityError {
     #[error("verify script error: {0}")]
     VerifyScript(String),
 
-    #[error("should not try to rebalance, orignal fee {0}, required fee: {1},")]
+    #[error("should not try to rebalance, orignal fee {}, required fee: {},", redact::amount(.0), redact::amount(.1))]
     AlreadyBalance(u64, u64),
+
+    #[error("output to put small change not found at given index: `{0}`")]
+    SmallChangeOutputNotFound(usize),
+
+    #[error("balancing did not converge after `{0}` fully served collections")]
+    NotConverged(usize),
+
+    #[error("cannot balance within the limits, collected `{collected}` inputs, shortfall: `{}` shannons", redact::amount(.shortfall))]
+    CannotBalance {
+        /// The number of inputs added by the balancer
+        collected: usize,
+        /// The capacity still missing when the balancer gave up
+        shortfall: u64,
+        /// The largest cells of the capacity provider which were not added,
+        /// at most [`REPORTED_SKIPPED_CELLS`]
+        largest_skipped: Vec<LiveCell>,
+    },
+}
+
+/// The number of skipped cells reported by [`BalanceTxCapacityError::CannotBalance`]
+pub const REPORTED_SKIPPED_CELLS: usize = 5;
+
+/// The limits of a balancing, a pathological account (e.g. thousands of
+/// dust cells) fails with [`BalanceTxCapacityError::CannotBalance`] once a
+/// limit is reached. `None` is unlimited.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct BalanceLimits {
+    /// The maximum number of rounds collecting more cells
+    pub max_iterations: Option<usize>,
+    /// The maximum number of inputs added by the balancer, e.g. 2000
+    pub max_inputs: Option<usize>,
+    /// The maximum number of collections the cell collector reported as
+    /// served in full, the balancing fails with
+    /// [`BalanceTxCapacityError::NotConverged`] when it needs one more. A
+    /// collector serving less than it reports is stopped early with e.g. 2.
+    pub max_served_rounds: Option<usize>,
+}
+
+/// What the balancer does when the left capacity is too small to create a
+/// change cell.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum SmallChangePolicy {
+    /// Collect more inputs to hold the change cell, when there is no more
+    /// cell the left capacity is paid as fee if `force_small_change_as_fee`
+    /// is set.
+    #[default]
+    CollectMore,
+    /// Pay the left capacity as fee without collecting more inputs, the fee
+    /// is still bounded by `force_small_change_as_fee` when it is set.
+    AddToFee,
+    /// Add the left capacity to the output at the index of the base
+    /// transaction.
+    AddToOutput(usize),
+}
+
+/// Where the capacity left after paying the minimal fee went
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ChangeDecision {
+    /// Nothing left, the inputs cover the outputs and the fee exactly
+    Exact,
+    /// Put into the change cell at the output index
+    ChangeCell(usize),
+    /// Paid as fee, the value is the capacity added to the minimal fee
+    AddedToFee(u64),
+    /// Added to an existing output
+    AddedToOutput { index: usize, capacity: u64 },
+}
+
+/// The fee of a balanced transaction and how the change was handled
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct FeeBreakdown {
+    /// The fee paid by the transaction
+    pub fee: u64,
+    /// The minimal fee required by the fee rate
+    pub min_fee: u64,
+    pub change: ChangeDecision,
+}
+
+impl FeeBreakdown {
+    fn new(min_fee: u64, change: ChangeDecision) -> FeeBreakdown {
+        let fee = match change {
+            ChangeDecision::AddedToFee(capacity) => min_fee + capacity,
+            _ => min_fee,
+        };
+        FeeBreakdown {
+            fee,
+            min_fee,
+            change,
+        }
+    }
 }
 
 /// Transaction capacity balancer config.
@@ -415,6 +770,18 @@ pub struct CapacityBalancer {
     /// transaction capacity, force the addition capacity as fee, the value is
     /// actual maximum transaction fee.
     pub force_small_change_as_fee: Option<u64>,
+
+    /// How to handle the left capacity when it can not hold a change cell
+    pub small_change_policy: SmallChangePolicy,
+
+    /// A sponsor paying the transaction fee. When set, `capacity_provider`
+    /// only balances the capacity of the outputs, the fee payer adds its own
+    /// cells for the fee and takes its change with its first lock script.
+    /// The script groups of both must be unlocked.
+    pub fee_payer: Option<CapacityProvider>,
+
+    /// Abort the balancing when one of the limits is reached
+    pub limits: BalanceLimits,
 }
 
 impl CapacityBalancer {
@@ -440,6 +807,9 @@ impl CapacityBalancer {
             )]),
             change_lock_script: None,
             force_small_change_as_fee: None,
+            small_change_policy: SmallChangePolicy::default(),
+            fee_payer: None,
+            limits: BalanceLimits::default(),
         }
     }
 
@@ -459,6 +829,9 @@ impl CapacityBalancer {
             )]),
             change_lock_script: None,
             force_small_change_as_fee: None,
+            small_change_policy: SmallChangePolicy::default(),
+            fee_payer: None,
+            limits: BalanceLimits::default(),
         }
     }
 
@@ -468,6 +841,9 @@ impl CapacityBalancer {
             capacity_provider,
             change_lock_script: None,
             force_small_change_as_fee: None,
+            small_change_policy: SmallChangePolicy::default(),
+            fee_payer: None,
+            limits: BalanceLimits::default(),
         }
     }
 
@@ -476,6 +852,19 @@ impl CapacityBalancer {
         self.force_small_change_as_fee = max_fee;
     }
 
+    pub fn set_small_change_policy(&mut self, policy: SmallChangePolicy) {
+        self.small_change_policy = policy;
+    }
+
+    /// Set or clear the sponsor paying the transaction fee
+    pub fn set_fee_payer(&mut self, fee_payer: Option<CapacityProvider>) {
+        self.fee_payer = fee_payer;
+    }
+
+    pub fn set_limits(&mut self, limits: BalanceLimits) {
+        self.limits = limits;
+    }
+
     pub fn balance_tx_capacity(
         &mut self,
         tx: &TransactionView,
@@ -543,7 +932,7 @@ impl CapacityBalancer {
             };
         }
 
-        rebalance_tx_capacity(
+        let (tx, change_index, _) = rebalance_tx_capacity(
             tx,
             self,
             cell_collector,
@@ -552,9 +941,11 @@ impl CapacityBalancer {
             header_dep_resolver,
             accepted_min_fee,
             change_index,
-        )
+        )?;
+        Ok((tx, change_index))
     }
 
+    #[cfg(feature = "verify")]
     pub fn check_cycle_fee(
         &self,
         tx: TransactionView,
@@ -596,12 +987,14 @@ pub const fn bytes_per_cycle() -> f64 {
     DEFAULT_BYTES_PER_CYCLE
 }
 
+#[cfg(feature = "verify")]
 pub struct CycleResolver<DL> {
     tx_dep_provider: DL,
     tip_header: HeaderView,
     consensus: Arc<Consensus>,
 }
 
+#[cfg(feature = "verify")]
 impl<
         DL: CellDataProvider
             + HeaderProvider
@@ -623,28 +1016,17 @@ impl<
     }
 
     fn estimate_cycles(&self, tx: &TransactionView) -> Result<u64, BalanceTxCapacityError> {
-        let rtx = resolve_transaction(
-            tx.clone(),
-            &mut HashSet::new(),
-            &self.tx_dep_provider,
-            &self.tx_dep_provider,
-        )
-        .map_err(|err| {
-            BalanceTxCapacityError::VerifyScript(format!("Resolve transaction error: {:?}", err))
-        })?;
-
-        let mut verifier = TransactionScriptsVerifier::new(
-            Arc::new(rtx),
+        let rtx = resolve_tx(tx.clone(), &self.tx_dep_provider, &self.tx_dep_provider)
+            .map_err(BalanceTxCapacityError::VerifyScript)?;
+        verify_tx_scripts(
+            rtx,
             self.tx_dep_provider.clone(),
             Arc::clone(&self.consensus),
-            Arc::new(TxVerifyEnv::new_submit(&self.tip_header)),
-        );
-        verifier.set_debug_printer(|script_hash, message| {
-            println!("script: {:x}, debug: {}", script_hash, message);
-        });
-        verifier.verify(u64::max_value()).map_err(|err| {
-            BalanceTxCapacityError::VerifyScript(format!("Verify script error : {:?}", err))
-        })
+            &self.tip_header,
+            u64::max_value(),
+            true,
+        )
+        .map_err(BalanceTxCapacityError::VerifyScript)
     }
 }
 
@@ -657,7 +1039,28 @@ pub fn balance_tx_capacity(
     cell_dep_resolver: &dyn CellDepResolver,
     header_dep_resolver: &dyn HeaderDepResolver,
 ) -> Result<TransactionView, BalanceTxCapacityError> {
-    let (tx, _change_idx) = rebalance_tx_capacity(
+    let (tx, _fee_breakdown) = balance_tx_capacity_with_breakdown(
+        tx,
+        balancer,
+        cell_collector,
+        tx_dep_provider,
+        cell_dep_resolver,
+        header_dep_resolver,
+    )?;
+    Ok(tx)
+}
+
+/// Same as `balance_tx_capacity`, also returns how the fee and the change
+/// were decided.
+pub fn balance_tx_capacity_with_breakdown(
+    tx: &TransactionView,
+    balancer: &CapacityBalancer,
+    cell_collector: &mut dyn CellCollector,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    cell_dep_resolver: &dyn CellDepResolver,
+    header_dep_resolver: &dyn HeaderDepResolver,
+) -> Result<(TransactionView, FeeBreakdown), BalanceTxCapacityError> {
+    let (tx, _change_idx, fee_breakdown) = rebalance_tx_capacity(
         tx,
         balancer,
         cell_collector,
@@ -667,7 +1070,7 @@ pub fn balance_tx_capacity(
         0,
         None,
     )?;
-    Ok(tx)
+    Ok((tx, fee_breakdown))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -680,7 +1083,42 @@ fn rebalance_tx_capacity(
     header_dep_resolver: &dyn HeaderDepResolver,
     accepted_min_fee: u64,
     change_index: Option<usize>,
-) -> Result<(TransactionView, Option<usize>), BalanceTxCapacityError> {
+) -> Result<(TransactionView, Option<usize>, FeeBreakdown), BalanceTxCapacityError> {
+    if let Some(fee_payer) = balancer.fee_payer.as_ref() {
+        let mut tx = tx.clone();
+        // When rebalancing for a higher fee the outputs are already covered,
+        // only the fee payer adds more capacity.
+        if change_index.is_none() {
+            let mut outputs_balancer = balancer.clone();
+            outputs_balancer.fee_rate = FeeRate::from_u64(0);
+            outputs_balancer.fee_payer = None;
+            tx = rebalance_tx_capacity(
+                &tx,
+                &outputs_balancer,
+                cell_collector,
+                tx_dep_provider,
+                cell_dep_resolver,
+                header_dep_resolver,
+                0,
+                None,
+            )?
+            .0;
+        }
+        let mut fee_balancer = balancer.clone();
+        fee_balancer.capacity_provider = fee_payer.clone();
+        fee_balancer.change_lock_script = None;
+        fee_balancer.fee_payer = None;
+        return rebalance_tx_capacity(
+            &tx,
+            &fee_balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+            accepted_min_fee,
+            change_index,
+        );
+    }
     let capacity_provider = &balancer.capacity_provider;
     if capacity_provider.lock_scripts.is_empty() {
         return Err(BalanceTxCapacityError::EmptyCapacityProvider);
@@ -740,6 +1178,13 @@ fn rebalance_tx_capacity(
     };
     let mut changed_witnesses: HashMap<usize, WitnessArgs> = HashMap::default();
     let mut witnesses = Vec::new();
+    let mut iteration = 0;
+    let mut collect_rounds = 0;
+    // The collections the cell collector served in full. The first one also
+    // collects the fee of an input and the change cell, the next round only
+    // misses the fee of the other collected inputs, so a second one is always
+    // enough.
+    let mut served_rounds = 0;
     loop {
         let (lock_script, placeholder_witness, since_source) = &lock_scripts[lock_script_idx];
         let base_query = {
@@ -748,6 +1193,14 @@ fn rebalance_tx_capacity(
             query.data_len_range = Some(ValueRangeOption::new_exact(0));
             query
         };
+        iteration += 1;
+        log::debug!(
+            "balance iteration {}: provider {}/{}, {} inputs collected",
+            iteration,
+            lock_script_idx + 1,
+            lock_scripts.len(),
+            inputs.len()
+        );
         // check if capacity provider lock script already in inputs
         let mut has_provider = false;
         for input in tx.inputs().into_iter().chain(inputs.clone().into_iter()) {
@@ -761,8 +1214,7 @@ fn rebalance_tx_capacity(
         {
             witnesses.push(Default::default());
         }
-        let mut ret_change_index = None;
-        let new_tx = {
+        let build_tx = |change_output: Option<CellOutput>| {
             let mut all_witnesses = tx.witnesses().into_iter().collect::<Vec<_>>();
             for (idx, witness_args) in &changed_witnesses {
                 all_witnesses[*idx] = witness_args.as_bytes().pack();
@@ -775,57 +1227,103 @@ fn rebalance_tx_capacity(
                 .cell_deps(cell_deps.clone())
                 .inputs(inputs.clone())
                 .set_witnesses(all_witnesses);
-            if let Some(output) = change_output.clone() {
-                ret_change_index = Some(output_len);
+            let mut change_index = None;
+            if let Some(output) = change_output {
+                change_index = Some(output_len);
                 builder = builder.output(output).output_data(Default::default());
             }
-            builder.build()
+            (builder.build(), change_index)
+        };
+        let change_decision = |change_index: Option<usize>| {
+            change_index
+                .map(ChangeDecision::ChangeCell)
+                .unwrap_or(ChangeDecision::Exact)
         };
+        let (new_tx, ret_change_index) = build_tx(change_output.clone());
         let tx_size = new_tx.data().as_reader().serialized_size_in_block();
         let min_fee = accepted_min_fee.max(balancer.fee_rate.fee(tx_size as u64).as_u64());
+        // The exact size of the change cell in the transaction:
+        //   * first 4 bytes is for output data header (the length)
+        //   * second 4 bytes if for output data offset
+        //   * third 4 bytes is for output offset
+        let change_size = base_change_output.as_slice().len() + 4 + 4 + 4;
+        let change_min_fee = accepted_min_fee.max(
+            balancer
+                .fee_rate
+                .fee((tx_size + change_size) as u64)
+                .as_u64(),
+        );
         let mut need_more_capacity = 1;
         let fee_result: Result<u64, TransactionFeeError> =
             tx_fee(new_tx.clone(), tx_dep_provider, header_dep_resolver);
         match fee_result {
             Ok(fee) if fee == min_fee => {
-                return Ok((new_tx, ret_change_index));
+                let fee_breakdown = FeeBreakdown::new(min_fee, change_decision(ret_change_index));
+                return Ok((new_tx, ret_change_index, fee_breakdown));
             }
             Ok(fee) if fee > min_fee => {
                 let delta = fee - min_fee;
                 if let Some(output) = change_output.take() {
-                    // If change cell already exits, just change the capacity field
+                    // If change cell already exits, just change the capacity field, the
+                    // transaction size is not changed so the fee will be exactly `min_fee`.
                     let old_capacity: u64 = output.capacity().unpack();
                     let new_capacity = old_capacity
                         .checked_add(delta)
                         .expect("change cell capacity add overflow");
-                    // next loop round must return new_tx;
-                    change_output = Some(output.as_builder().capacity(new_capacity.pack()).build());
-                    need_more_capacity = 0;
+                    let output = output.as_builder().capacity(new_capacity.pack()).build();
+                    let (tx, change_index) = build_tx(Some(output));
+                    let fee_breakdown = FeeBreakdown::new(min_fee, change_decision(change_index));
+                    return Ok((tx, change_index, fee_breakdown));
                 } else {
-                    // If change cell not exists, add a change cell.
-
-                    // The output extra header size is for:
-                    //   * first 4 bytes is for output data header (the length)
-                    //   * second 4 bytes if for output data offset
-                    //   * third 4 bytes is for output offset
-                    let output_header_extra = 4 + 4 + 4;
-                    // NOTE: extra_min_fee +1 is for `FeeRate::fee` round
-                    let extra_min_fee = balancer
-                        .fee_rate
-                        .fee(base_change_output.as_slice().len() as u64 + output_header_extra)
-                        .as_u64()
-                        + 1;
-                    // The extra capacity (delta - extra_min_fee) is enough to hold the change cell.
-                    if delta >= base_change_occupied_capacity + extra_min_fee {
-                        // next loop round must return new_tx;
-                        change_output = Some(
-                            base_change_output
-                                .clone()
-                                .as_builder()
-                                .capacity((delta - extra_min_fee).pack())
-                                .build(),
+                    // If change cell not exists, add a change cell. The size of the change
+                    // cell is known, so the change capacity can be calculated exactly.
+                    if fee >= change_min_fee + base_change_occupied_capacity {
+                        let change = base_change_output
+                            .clone()
+                            .as_builder()
+                            .capacity((fee - change_min_fee).pack())
+                            .build();
+                        let (tx, change_index) = build_tx(Some(change));
+                        let fee_breakdown =
+                            FeeBreakdown::new(change_min_fee, change_decision(change_index));
+                        return Ok((tx, change_index, fee_breakdown));
+                    } else if balancer.small_change_policy == SmallChangePolicy::AddToFee {
+                        if let Some(max_fee) = balancer.force_small_change_as_fee {
+                            if fee > max_fee {
+                                return Err(BalanceTxCapacityError::ForceSmallChangeAsFeeFailed(
+                                    fee,
+                                ));
+                            }
+                        }
+                        let fee_breakdown =
+                            FeeBreakdown::new(min_fee, ChangeDecision::AddedToFee(delta));
+                        return Ok((new_tx, ret_change_index, fee_breakdown));
+                    } else if let SmallChangePolicy::AddToOutput(index) =
+                        balancer.small_change_policy
+                    {
+                        if index >= tx.outputs().len() {
+                            return Err(BalanceTxCapacityError::SmallChangeOutputNotFound(index));
+                        }
+                        // The transaction size is not changed, so the fee will be exactly `min_fee`.
+                        let mut outputs = new_tx.outputs().into_iter().collect::<Vec<_>>();
+                        let old_capacity: u64 = outputs[index].capacity().unpack();
+                        let new_capacity = old_capacity
+                            .checked_add(delta)
+                            .expect("small change output capacity add overflow");
+                        outputs[index] = outputs[index]
+                            .clone()
+                            .as_builder()
+                            .capacity(new_capacity.pack())
+                            .build();
+                        let tx = new_tx.as_advanced_builder().set_outputs(outputs).build();
+                        let fee_breakdown = FeeBreakdown::new(
+                            min_fee,
+                            ChangeDecision::AddedToOutput {
+                                index,
+                                capacity: delta,
+                            },
                         );
-                        need_more_capacity = 0;
+                        return Ok((tx, ret_change_index, fee_breakdown));
                     } else {
                         // peek if there is more live cell owned by this capacity provider
                         let (more_cells, _more_capacity) =
@@ -837,26 +1335,25 @@ fn rebalance_tx_capacity(
                                         BalanceTxCapacityError::ForceSmallChangeAsFeeFailed(fee),
                                     );
                                 } else {
-                                    return Ok((new_tx, ret_change_index));
+                                    let fee_breakdown = FeeBreakdown::new(
+                                        min_fee,
+                                        ChangeDecision::AddedToFee(delta),
+                                    );
+                                    return Ok((new_tx, ret_change_index, fee_breakdown));
                                 }
                             } else if lock_script_idx + 1 == lock_scripts.len() {
                                 return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
                                     "can not create change cell, left capacity={}",
-                                    HumanCapacity(delta)
+                                    redact::amount(HumanCapacity(delta))
                                 )));
                             } else {
                                 lock_script_idx += 1;
                                 continue;
                             }
                         } else {
-                            // need more input to balance the capacity
-                            change_output = Some(
-                                base_change_output
-                                    .clone()
-                                    .as_builder()
-                                    .capacity(base_change_occupied_capacity.pack())
-                                    .build(),
-                            );
+                            // need more input to hold the change cell
+                            need_more_capacity =
+                                change_min_fee + base_change_occupied_capacity - fee;
                         }
                     }
                 }
@@ -869,7 +1366,7 @@ fn rebalance_tx_capacity(
                 need_more_capacity = delta.checked_add(min_fee).ok_or_else(|| {
                     BalanceTxCapacityError::CapacityNotEnough(format!(
                         "need more capacity, value={}",
-                        HumanCapacity(delta)
+                        redact::amount(HumanCapacity(delta))
                     ))
                 })?;
             }
@@ -878,17 +1375,69 @@ fn rebalance_tx_capacity(
             }
         }
         if need_more_capacity > 0 {
+            // Also collect the capacity for the fee of the new input and the change cell, so
+            // the next round can put the left capacity into the change cell directly.
+            // the input and its empty witness: the bytes header and the offset
+            let mut input_size = CellInput::TOTAL_SIZE + 4 + 4;
+            if !has_provider {
+                input_size += placeholder_witness.as_slice().len();
+            }
+            let mut extra_capacity = balancer.fee_rate.fee(input_size as u64).as_u64() + 1;
+            if change_output.is_none() {
+                extra_capacity += base_change_occupied_capacity
+                    + balancer.fee_rate.fee(change_size as u64).as_u64()
+                    + 1;
+            }
+            need_more_capacity = need_more_capacity.saturating_add(extra_capacity);
             let query = {
                 let mut query = base_query.clone();
                 query.min_total_capacity = need_more_capacity;
                 query
             };
-            let (more_cells, _more_capacity) = cell_collector.collect_live_cells(&query, true)?;
+            if matches!(balancer.limits.max_served_rounds, Some(max) if served_rounds >= max) {
+                return Err(BalanceTxCapacityError::NotConverged(served_rounds));
+            }
+            collect_rounds += 1;
+            if matches!(balancer.limits.max_iterations, Some(max) if collect_rounds > max) {
+                let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+                return Err(BalanceTxCapacityError::CannotBalance {
+                    collected: inputs.len(),
+                    shortfall: need_more_capacity,
+                    largest_skipped: largest_cells(cells),
+                });
+            }
+            let (mut more_cells, more_capacity) =
+                cell_collector.collect_live_cells(&query, true)?;
+            if more_capacity >= need_more_capacity {
+                served_rounds += 1;
+            }
+            log::trace!(
+                "balance iteration {}: need {} shannons, collected {} cells of {} shannons",
+                iteration,
+                need_more_capacity,
+                more_cells.len(),
+                more_capacity
+            );
+            if let Some(max_inputs) = balancer.limits.max_inputs {
+                let allowed = max_inputs.saturating_sub(inputs.len());
+                if more_cells.len() > allowed {
+                    let skipped = more_cells.split_off(allowed);
+                    let allowed_capacity = more_cells
+                        .iter()
+                        .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+                        .fold(0u64, u64::saturating_add);
+                    return Err(BalanceTxCapacityError::CannotBalance {
+                        collected: inputs.len() + allowed,
+                        shortfall: need_more_capacity.saturating_sub(allowed_capacity),
+                        largest_skipped: largest_cells(skipped),
+                    });
+                }
+            }
             if more_cells.is_empty() {
                 if lock_script_idx + 1 == lock_scripts.len() {
                     return Err(BalanceTxCapacityError::CapacityNotEnough(format!(
                         "need more capacity, value={}",
-                        HumanCapacity(need_more_capacity)
+                        redact::amount(HumanCapacity(need_more_capacity))
                     )));
                 } else {
                     lock_script_idx += 1;
@@ -967,6 +1516,14 @@ fn rebalance_tx_capacity(
     }
 }
 
+/// The [`REPORTED_SKIPPED_CELLS`] largest cells, largest first
+fn largest_cells(mut cells: Vec<LiveCell>) -> Vec<LiveCell> {
+    cells.sort_by_key(|cell| std::cmp::Reverse(Unpack::<u64>::unpack(&cell.output.capacity())));
+    cells.truncate(REPORTED_SKIPPED_CELLS);
+    cells
+}
+
+#[derive(Debug, Clone)]
 pub struct ScriptGroups {
     pub lock_groups: HashMap<Byte32, ScriptGroup>,
     pub type_groups: HashMap<Byte32, ScriptGroup>,
@@ -1058,6 +1615,12 @@ pub fn unlock_tx(
             if unlocker.is_unlocked(&tx, script_group, tx_dep_provider)? {
                 tx = unlocker.clear_placeholder_witness(&tx, script_group)?;
             } else if unlocker.match_args(script_args.as_ref()) {
+                group_witness_layout(
+                    &tx,
+                    script_group,
+                    unlocker.as_ref(),
+                    WitnessLayout::WitnessArgs,
+                )?;
                 tx = unlocker.unlock(&tx, script_group, tx_dep_provider)?;
             } else {
                 not_unlocked.push(script_group.clone());
@@ -1069,6 +1632,210 @@ pub fn unlock_tx(
     Ok((tx, not_unlocked))
 }
 
+/// The layout `unlocker` signs the witness of the group with, see
+/// [`negotiate_witness_layout`]
+fn group_witness_layout(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    unlocker: &dyn ScriptUnlocker,
+    preferred: WitnessLayout,
+) -> Result<WitnessLayout, UnlockError> {
+    let witness = tx
+        .witnesses()
+        .get(script_group.input_indices[0])
+        .map(|witness| witness.raw_data())
+        .unwrap_or_default();
+    negotiate_witness_layout(&witness, unlocker.witness_layouts(), preferred)
+        .map_err(|kind| ScriptSignError::UnsupportedWitnessLayout(kind).into())
+}
+
+/// The witness layout of every lock script group of `tx` the unlockers can
+/// sign, sorted by the first input of the group. A transaction may mix
+/// `WitnessArgs` and cobuild groups, `preferred` is picked for the empty
+/// witnesses when the unlocker supports it.
+///
+/// Fails when a filled witness has a layout its unlocker can't sign, the
+/// groups without a matching unlocker are skipped.
+pub fn negotiate_tx_witness_layouts(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    preferred: WitnessLayout,
+) -> Result<Vec<(ScriptGroup, WitnessLayout)>, UnlockError> {
+    let mut groups = gen_script_groups(tx, tx_dep_provider)?
+        .lock_groups
+        .into_values()
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|group| group.input_indices[0]);
+    let mut layouts = Vec::new();
+    for script_group in groups {
+        let unlocker = match unlockers.get(&ScriptId::from(&script_group.script)) {
+            Some(unlocker) if unlocker.match_args(&script_group.script.args().raw_data()) => {
+                unlocker
+            }
+            _ => continue,
+        };
+        let layout = group_witness_layout(tx, &script_group, unlocker.as_ref(), preferred)?;
+        layouts.push((script_group, layout));
+    }
+    Ok(layouts)
+}
+
+/// Check the absolute epoch since of every input is reached at `tip_epoch`
+/// (e.g. a matured DAO withdraw), the error describes when the input is
+/// available.
+pub fn check_since_reached(
+    tx: &TransactionView,
+    tip_epoch: EpochNumberWithFraction,
+) -> Result<(), TxBuilderError> {
+    for (idx, input) in tx.inputs().into_iter().enumerate() {
+        let since = Since::from_raw_value(input.since().unpack());
+        if since.is_relative() || !since.flags_is_valid() {
+            continue;
+        }
+        if let Some((SinceType::EpochNumberWithFraction, value)) = since.extract_metric() {
+            let epoch = EpochNumberWithFraction::from_full_value(value);
+            let reached = (tip_epoch.number(), tip_epoch.index() * epoch.length())
+                >= (epoch.number(), epoch.index() * tip_epoch.length());
+            if !reached {
+                return Err(TxBuilderError::SinceNotReached(
+                    idx,
+                    since.describe_availability(tip_epoch),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build an unlocked transaction, fail with [`TxBuilderError::MissingSigner`]
+/// when any lock script group is still locked.
+pub fn unlock_tx_fully(
+    balanced_tx: TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+    unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+) -> Result<TransactionView, TxBuilderError> {
+    let (tx, not_unlocked) = unlock_tx(balanced_tx, tx_dep_provider, unlockers)?;
+    if not_unlocked.is_empty() {
+        Ok(tx)
+    } else {
+        Err(TxBuilderError::MissingSigner(MissingSignerReport::new(
+            &not_unlocked,
+            unlockers,
+        )))
+    }
+}
+
+/// Why a lock script group is still locked after [`unlock_tx`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LockedReason {
+    /// There is no unlocker for the script id
+    NoMatchingSigner,
+    /// The unlocker of the script id does not accept the args, usually the
+    /// key or config of the args is missing
+    ArgsNotMatched,
+}
+
+/// A lock script group not unlocked by the given unlockers
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LockedGroup {
+    pub script_id: ScriptId,
+    pub args: Bytes,
+    pub input_indices: Vec<usize>,
+    pub reason: LockedReason,
+}
+
+impl LockedGroup {
+    /// The hex args, truncated by the redaction mode, see [`redact::bytes`]
+    pub fn args_preview(&self) -> String {
+        redact::bytes(&self.args).to_string()
+    }
+
+    /// The kind of signer to register for this group
+    pub fn suggested_signer(&self) -> &'static str {
+        let code_hash = &self.script_id.code_hash;
+        if self.script_id.hash_type != ScriptHashType::Type {
+            "an unlocker for the script id"
+        } else if code_hash == &SIGHASH_TYPE_HASH {
+            "SecpSighashUnlocker with the secp256k1 key of the args"
+        } else if code_hash == &MULTISIG_TYPE_HASH {
+            "SecpMultisigUnlocker with the multisig config of the args"
+        } else if code_hash == &ACP_TYPE_HASH_LINA || code_hash == &ACP_TYPE_HASH_AGGRON {
+            "AcpUnlocker with the secp256k1 key of the args"
+        } else {
+            "an unlocker for the script id"
+        }
+    }
+}
+
+impl fmt::Display for LockedGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            LockedReason::NoMatchingSigner => "no matching signer",
+            LockedReason::ArgsNotMatched => "signer does not match the args",
+        };
+        write!(
+            f,
+            "inputs {:?} locked by {:#x} ({:?}) with args {}: {}, try {}",
+            self.input_indices,
+            self.script_id.code_hash,
+            self.script_id.hash_type,
+            redact::bytes(&self.args),
+            reason,
+            self.suggested_signer()
+        )
+    }
+}
+
+/// The lock script groups left locked by [`unlock_tx`]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MissingSignerReport {
+    pub groups: Vec<LockedGroup>,
+}
+
+impl MissingSignerReport {
+    /// Explain the `not_unlocked` groups returned by [`unlock_tx`]
+    pub fn new(
+        not_unlocked: &[ScriptGroup],
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> MissingSignerReport {
+        let groups = not_unlocked
+            .iter()
+            .map(|group| {
+                let script_id = ScriptId::from(&group.script);
+                let reason = if unlockers.contains_key(&script_id) {
+                    LockedReason::ArgsNotMatched
+                } else {
+                    LockedReason::NoMatchingSigner
+                };
+                LockedGroup {
+                    script_id,
+                    args: group.script.args().raw_data(),
+                    input_indices: group.input_indices.clone(),
+                    reason,
+                }
+            })
+            .collect();
+        MissingSignerReport { groups }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+impl fmt::Display for MissingSignerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, group) in self.groups.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", group)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod anyhow_tests {
     use anyhow::anyhow;
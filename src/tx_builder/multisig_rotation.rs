@@ -0,0 +1,202 @@
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, Script},
+    prelude::*,
+};
+
+use super::{
+    balance_tx_capacity, resolve_cell_deps, CapacityBalancer, CapacityProvider, TxBuilderError,
+};
+use crate::session::{SessionKind, SigningSession};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider,
+};
+use crate::unlock::MultisigConfig;
+
+/// The default maximum inputs of a rotation transaction
+pub const DEFAULT_MAX_ROTATION_INPUTS: usize = 500;
+
+/// Move all the cells of a multisig lock to the lock of a new `MultisigConfig`,
+/// e.g. after a member leaves or the threshold changes.
+///
+/// The cells keep their type script and data. The plain cells (no type script
+/// and empty data) are merged into one change cell of the new lock which also
+/// pays the fee, so every transaction needs at least one plain cell. When there
+/// are more cells than `max_inputs`, they are split into several transactions.
+pub struct MultisigRotationBuilder {
+    pub old_config: MultisigConfig,
+    pub new_config: MultisigConfig,
+    /// Maximum inputs of a transaction
+    pub max_inputs: usize,
+    pub fee_rate: u64,
+}
+
+/// A transaction of the rotation
+pub struct RotationTx {
+    pub tx: TransactionView,
+    /// Collects the signatures of the old members, the transaction is only
+    /// unlocked by the old lock.
+    pub old_signers_session: SigningSession,
+    /// Lets the new members review the transaction and check the cells arrive
+    /// at their lock before the old members sign, no signature is expected.
+    pub new_signers_session: SigningSession,
+}
+
+impl MultisigRotationBuilder {
+    pub fn new(
+        old_config: MultisigConfig,
+        new_config: MultisigConfig,
+        fee_rate: u64,
+    ) -> MultisigRotationBuilder {
+        MultisigRotationBuilder {
+            old_config,
+            new_config,
+            max_inputs: DEFAULT_MAX_ROTATION_INPUTS,
+            fee_rate,
+        }
+    }
+
+    pub fn old_lock_script(&self) -> Script {
+        Script::from(&self.old_config.to_address_payload(None))
+    }
+
+    pub fn new_lock_script(&self) -> Script {
+        Script::from(&self.new_config.to_address_payload(None))
+    }
+
+    /// Build the balanced (unsigned) rotation transactions, all the cells of
+    /// the old lock are locked in `cell_collector`. The session ids are
+    /// `{session_prefix}-{index}-old` and `{session_prefix}-{index}-new`.
+    pub fn build(
+        &self,
+        session_prefix: &str,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Vec<RotationTx>, TxBuilderError> {
+        if self.max_inputs < 2 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "max_inputs must be at least 2"
+            )));
+        }
+        let old_lock = self.old_lock_script();
+        let new_lock = self.new_lock_script();
+        if old_lock == new_lock {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the new multisig config is the same as the old one"
+            )));
+        }
+        let mut query = CellQueryOptions::new_lock(old_lock.clone());
+        query.min_total_capacity = u64::MAX;
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        let chunks = split_fee_chunks(cells, self.max_inputs, "old multisig lock")?;
+
+        let placeholder_witness = self.old_config.placeholder_witness();
+        let mut balancer = CapacityBalancer::new_with_provider(
+            self.fee_rate,
+            CapacityProvider::new_simple(vec![(old_lock.clone(), placeholder_witness.clone())]),
+        );
+        balancer.change_lock_script = Some(new_lock.clone());
+
+        let mut rotation_txs = Vec::with_capacity(chunks.len());
+        for (idx, chunk) in chunks.into_iter().enumerate() {
+            let mut cell_dep_scripts = vec![old_lock.clone()];
+            let mut inputs = Vec::new();
+            let mut outputs = Vec::new();
+            let mut outputs_data = Vec::new();
+            for cell in chunk {
+                inputs.push(CellInput::new(cell.out_point.clone(), 0));
+                if let Some(type_script) = cell.output.type_().to_opt() {
+                    cell_dep_scripts.push(type_script);
+                } else if cell.output_data.is_empty() {
+                    // merged into the change cell
+                    continue;
+                }
+                outputs.push(cell.output.as_builder().lock(new_lock.clone()).build());
+                outputs_data.push(cell.output_data.pack());
+            }
+            let mut witnesses = vec![Bytes::new().pack(); inputs.len()];
+            witnesses[0] = placeholder_witness.as_bytes().pack();
+            let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+            let base_tx = TransactionBuilder::default()
+                .set_cell_deps(cell_deps)
+                .set_inputs(inputs)
+                .set_outputs(outputs)
+                .set_outputs_data(outputs_data)
+                .set_witnesses(witnesses)
+                .build();
+            let tx = balance_tx_capacity(
+                &base_tx,
+                &balancer,
+                cell_collector,
+                tx_dep_provider,
+                cell_dep_resolver,
+                header_dep_resolver,
+            )?;
+            let old_signers_session = SigningSession::new(
+                format!("{}-{}-old", session_prefix, idx),
+                SessionKind::Multisig,
+                &tx,
+            )
+            .with_multisig_config(self.old_config.clone());
+            let mut new_signers_session = SigningSession::new(
+                format!("{}-{}-new", session_prefix, idx),
+                SessionKind::Multisig,
+                &tx,
+            )
+            .with_multisig_config(self.new_config.clone());
+            // the inputs are reserved by the old signers session
+            new_signers_session.reserved_inputs.clear();
+            rotation_txs.push(RotationTx {
+                tx,
+                old_signers_session,
+                new_signers_session,
+            });
+        }
+        Ok(rotation_txs)
+    }
+}
+
+/// Split the cells into chunks of at most `max_inputs` cells, every chunk has
+/// a plain cell (no type script and empty data) to pay the fee. `owner`
+/// describes the lock of the cells in the error messages.
+pub(crate) fn split_fee_chunks(
+    cells: Vec<LiveCell>,
+    max_inputs: usize,
+    owner: &str,
+) -> Result<Vec<Vec<LiveCell>>, TxBuilderError> {
+    let (mut plain_cells, other_cells): (Vec<_>, Vec<_>) = cells
+        .into_iter()
+        .partition(|cell| cell.output.type_().is_none() && cell.output_data.is_empty());
+    if plain_cells.is_empty() {
+        return Err(TxBuilderError::Other(anyhow!(
+            "no plain cell of the {} to pay the fee",
+            owner
+        )));
+    }
+    // the largest plain cells go first, one per chunk of other cells
+    plain_cells
+        .sort_by_key(|cell| std::cmp::Reverse(Unpack::<u64>::unpack(&cell.output.capacity())));
+    let mut plain_cells = plain_cells.into_iter();
+    let mut chunks = Vec::new();
+    for other_chunk in other_cells.chunks(max_inputs - 1) {
+        let fee_cell = plain_cells.next().ok_or_else(|| {
+            TxBuilderError::Other(anyhow!(
+                "not enough plain cells of the {} to pay the fees",
+                owner
+            ))
+        })?;
+        let mut chunk = vec![fee_cell];
+        chunk.extend(other_chunk.iter().cloned());
+        chunks.push(chunk);
+    }
+    let rest = plain_cells.collect::<Vec<_>>();
+    for plain_chunk in rest.chunks(max_inputs) {
+        chunks.push(plain_chunk.to_vec());
+    }
+    Ok(chunks)
+}
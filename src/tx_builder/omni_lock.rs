@@ -7,7 +7,7 @@ use ckb_types::{
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{check_type_id_collision, TxBuilder, TxBuilderError};
 use crate::types::ScriptId;
 use crate::{
     traits::{CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider},
@@ -38,7 +38,7 @@ impl OmniLockTransferBuilder {
 impl TxBuilder for OmniLockTransferBuilder {
     fn build_base(
         &self,
-        _cell_collector: &mut dyn CellCollector,
+        cell_collector: &mut dyn CellCollector,
         cell_dep_resolver: &dyn CellDepResolver,
         _header_dep_resolver: &dyn HeaderDepResolver,
         tx_dep_provider: &dyn TransactionDependencyProvider,
@@ -54,7 +54,9 @@ impl TxBuilder for OmniLockTransferBuilder {
             outputs_data.push(output_data.pack());
             if let Some(type_script) = output.type_().to_opt() {
                 let script_id = ScriptId::from(&type_script);
-                if !script_id.is_type_id() {
+                if script_id.is_type_id() {
+                    check_type_id_collision(&type_script, cell_collector)?;
+                } else {
                     let cell_dep = cell_dep_resolver
                         .resolve(&type_script)
                         .ok_or(TxBuilderError::ResolveCellDepFailed(type_script))?;
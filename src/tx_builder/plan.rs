@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{CellOutput, OutPoint, Transaction},
+    prelude::*,
+    H256,
+};
+
+use super::{gen_script_groups, tx_fee, BalanceTxCapacityError, FeeBreakdown, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellCollectorError, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider,
+};
+use crate::types::ScriptGroup;
+
+/// The outcome of a transaction build without unlocking it or reserving any
+/// cell, returned by `TxBuilder::plan`.
+#[derive(Debug, Clone)]
+pub struct TxPlan {
+    /// The selected input cells, in transaction order
+    pub inputs: Vec<(OutPoint, CellOutput, Bytes)>,
+    /// The outputs requested by the builder
+    pub outputs: Vec<(CellOutput, Bytes)>,
+    /// The change outputs appended by the balancer
+    pub change: Vec<(CellOutput, Bytes)>,
+    /// The transaction fee in shannons
+    pub fee: u64,
+    /// How the balancer decided the fee and the change, `None` when the plan
+    /// is not produced by the balancer
+    pub fee_breakdown: Option<FeeBreakdown>,
+    /// The lock script groups which must be signed, sorted by their first input
+    pub required_signers: Vec<ScriptGroup>,
+}
+
+impl TxPlan {
+    /// Describe a balanced transaction, the outputs after the first
+    /// `base_outputs_len` ones are change outputs.
+    pub fn from_balanced_tx(
+        tx: &TransactionView,
+        base_outputs_len: usize,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<TxPlan, TxBuilderError> {
+        let out_points = tx.input_pts_iter().collect::<Vec<_>>();
+        let inputs = out_points
+            .iter()
+            .cloned()
+            .zip(tx_dep_provider.get_cells_with_data(&out_points)?)
+            .map(|(out_point, (output, data))| (out_point, output, data))
+            .collect();
+        let mut outputs = tx
+            .outputs_with_data_iter()
+            .collect::<Vec<(CellOutput, Bytes)>>();
+        let change = outputs.split_off(base_outputs_len.min(outputs.len()));
+        let fee = tx_fee(tx.clone(), tx_dep_provider, header_dep_resolver)
+            .map_err(BalanceTxCapacityError::from)?;
+        let mut required_signers = gen_script_groups(tx, tx_dep_provider)?
+            .lock_groups
+            .into_values()
+            .collect::<Vec<_>>();
+        required_signers.sort_by_key(|group| group.input_indices[0]);
+        Ok(TxPlan {
+            inputs,
+            outputs,
+            change,
+            fee,
+            fee_breakdown: None,
+            required_signers,
+        })
+    }
+
+    pub fn change_capacity(&self) -> u64 {
+        self.change
+            .iter()
+            .map(|(output, _)| Unpack::<u64>::unpack(&output.capacity()))
+            .sum()
+    }
+}
+
+/// A cell collector which never changes the wrapped collector.
+///
+/// The cells collected with `apply_changes` are only locked inside this
+/// collector, so planning a transaction leaves the wrapped collector as it was.
+/// The wrapped collector is cloned, the clone is only queried.
+#[derive(Clone)]
+pub struct DryRunCellCollector<'a> {
+    inner: Box<dyn CellCollector + 'a>,
+    locked_cells: HashSet<(H256, u32)>,
+}
+
+impl<'a> DryRunCellCollector<'a> {
+    pub fn new(inner: &(dyn CellCollector + 'a)) -> DryRunCellCollector<'a> {
+        DryRunCellCollector {
+            inner: dyn_clone::clone_box(inner),
+            locked_cells: HashSet::default(),
+        }
+    }
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+impl<'a> CellCollector for DryRunCellCollector<'a> {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let mut inner_query = query.clone();
+        let mut last_len = None;
+        let (cells, total_capacity) = loop {
+            let (cells, _) = self.inner.collect_live_cells(&inner_query, false)?;
+            let mut skipped_capacity = 0u64;
+            let mut total_capacity = 0u64;
+            let len = cells.len();
+            let cells = cells
+                .into_iter()
+                .filter(|cell| {
+                    let capacity: u64 = cell.output.capacity().unpack();
+                    if self.locked_cells.contains(&out_point_key(&cell.out_point)) {
+                        skipped_capacity = skipped_capacity.saturating_add(capacity);
+                        false
+                    } else {
+                        total_capacity = total_capacity.saturating_add(capacity);
+                        true
+                    }
+                })
+                .collect::<Vec<_>>();
+            // The cells locked here are still live in the inner collector, ask
+            // for more until the locked ones are compensated or nothing is left.
+            if total_capacity >= query.min_total_capacity
+                || skipped_capacity == 0
+                || last_len == Some(len)
+            {
+                break (cells, total_capacity);
+            }
+            last_len = Some(len);
+            inner_query.min_total_capacity =
+                query.min_total_capacity.saturating_add(skipped_capacity);
+        };
+        if apply_changes {
+            self.locked_cells
+                .extend(cells.iter().map(|cell| out_point_key(&cell.out_point)));
+        }
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.locked_cells.insert(out_point_key(&out_point));
+        Ok(())
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.locked_cells.extend(
+            tx.raw()
+                .inputs()
+                .into_iter()
+                .map(|input| out_point_key(&input.previous_output())),
+        );
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.locked_cells.clear();
+    }
+}
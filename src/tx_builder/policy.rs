@@ -0,0 +1,226 @@
+//! Check the outputs of a transaction against operational rules.
+//!
+//! An [`OutputPolicy`] limits the data size of every output, forbids some
+//! type scripts and restricts the lock scripts to an allowlist. The
+//! [`TxBuilder::build_unlocked_with_policy`](super::TxBuilder::build_unlocked_with_policy)
+//! checks the balanced transaction, change output included, before signing.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use ckb_types::{core::TransactionView, packed::Script, prelude::*};
+use thiserror::Error;
+
+use crate::types::ScriptId;
+
+/// An output breaking an [`OutputPolicy`] rule
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PolicyViolation {
+    DataTooLarge {
+        output_index: usize,
+        size: usize,
+        max_size: usize,
+    },
+    ForbiddenTypeScript {
+        output_index: usize,
+        type_script: Script,
+    },
+    LockNotAllowed {
+        output_index: usize,
+        lock: Script,
+    },
+}
+
+impl PolicyViolation {
+    pub fn output_index(&self) -> usize {
+        match self {
+            PolicyViolation::DataTooLarge { output_index, .. }
+            | PolicyViolation::ForbiddenTypeScript { output_index, .. }
+            | PolicyViolation::LockNotAllowed { output_index, .. } => *output_index,
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::DataTooLarge {
+                output_index,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "output {} has {} bytes of data, more than {}",
+                output_index, size, max_size
+            ),
+            PolicyViolation::ForbiddenTypeScript {
+                output_index,
+                type_script,
+            } => write!(
+                f,
+                "output {} has forbidden type script {}",
+                output_index,
+                ScriptId::from(type_script)
+            ),
+            PolicyViolation::LockNotAllowed { output_index, lock } => write!(
+                f,
+                "output {} has lock script {} not in the allowlist",
+                output_index,
+                ScriptId::from(lock)
+            ),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+#[error("output policy violated: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct OutputPolicyError(pub Vec<PolicyViolation>);
+
+/// The rules every output of a transaction must follow, nothing is checked
+/// by default
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OutputPolicy {
+    /// The maximum data size of an output in bytes
+    pub max_data_size: Option<usize>,
+    /// The type script ids no output may have
+    pub forbidden_type_scripts: HashSet<ScriptId>,
+    /// The lock script ids the outputs must have, `None` allows all of them.
+    /// The change lock must be allowed too.
+    pub allowed_locks: Option<HashSet<ScriptId>>,
+}
+
+impl OutputPolicy {
+    pub fn new() -> OutputPolicy {
+        OutputPolicy::default()
+    }
+
+    pub fn max_data_size(mut self, max_data_size: usize) -> Self {
+        self.max_data_size = Some(max_data_size);
+        self
+    }
+
+    pub fn forbid_type_script(mut self, script_id: ScriptId) -> Self {
+        self.forbidden_type_scripts.insert(script_id);
+        self
+    }
+
+    /// Add a lock script id to the allowlist, which restricts the locks from
+    /// then on
+    pub fn allow_lock(mut self, script_id: ScriptId) -> Self {
+        self.allowed_locks
+            .get_or_insert_with(HashSet::new)
+            .insert(script_id);
+        self
+    }
+
+    /// All the violations of `tx`, in output order
+    pub fn violations(&self, tx: &TransactionView) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        for (output_index, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+            if let Some(max_size) = self.max_data_size {
+                if data.len() > max_size {
+                    violations.push(PolicyViolation::DataTooLarge {
+                        output_index,
+                        size: data.len(),
+                        max_size,
+                    });
+                }
+            }
+            if let Some(type_script) = output.type_().to_opt() {
+                if self
+                    .forbidden_type_scripts
+                    .contains(&ScriptId::from(&type_script))
+                {
+                    violations.push(PolicyViolation::ForbiddenTypeScript {
+                        output_index,
+                        type_script,
+                    });
+                }
+            }
+            let lock = output.lock();
+            if let Some(allowed_locks) = &self.allowed_locks {
+                if !allowed_locks.contains(&ScriptId::from(&lock)) {
+                    violations.push(PolicyViolation::LockNotAllowed { output_index, lock });
+                }
+            }
+        }
+        violations
+    }
+
+    /// Fail with all the violations of `tx`, if any
+    pub fn check(&self, tx: &TransactionView) -> Result<(), OutputPolicyError> {
+        let violations = self.violations(tx);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(OutputPolicyError(violations))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        bytes::Bytes,
+        core::{ScriptHashType, TransactionBuilder},
+        h256,
+        packed::CellOutput,
+        H256,
+    };
+
+    fn script(code_hash: H256) -> Script {
+        Script::new_builder()
+            .code_hash(code_hash.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .build()
+    }
+
+    #[test]
+    fn test_output_policy() {
+        let sighash = script(h256!("0x1"));
+        let acp = script(h256!("0x2"));
+        let udt = script(h256!("0x3"));
+        let output = |lock: &Script, type_script: Option<&Script>| {
+            CellOutput::new_builder()
+                .lock(lock.clone())
+                .type_(type_script.cloned().pack())
+                .build()
+        };
+        let tx = TransactionBuilder::default()
+            .output(output(&sighash, None))
+            .output_data(Bytes::from(vec![0u8; 16]).pack())
+            .output(output(&acp, Some(&udt)))
+            .output_data(Bytes::from(vec![0u8; 8]).pack())
+            .build();
+
+        assert!(OutputPolicy::new().check(&tx).is_ok());
+        let policy = OutputPolicy::new()
+            .max_data_size(8)
+            .forbid_type_script(ScriptId::from(&udt))
+            .allow_lock(ScriptId::from(&sighash));
+        assert_eq!(
+            policy.check(&tx),
+            Err(OutputPolicyError(vec![
+                PolicyViolation::DataTooLarge {
+                    output_index: 0,
+                    size: 16,
+                    max_size: 8
+                },
+                PolicyViolation::ForbiddenTypeScript {
+                    output_index: 1,
+                    type_script: udt.clone()
+                },
+                PolicyViolation::LockNotAllowed {
+                    output_index: 1,
+                    lock: acp.clone()
+                },
+            ]))
+        );
+        let policy = OutputPolicy::new()
+            .max_data_size(16)
+            .allow_lock(ScriptId::from(&sighash))
+            .allow_lock(ScriptId::from(&acp));
+        assert!(policy.check(&tx).is_ok());
+    }
+}
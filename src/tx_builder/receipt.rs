@@ -0,0 +1,268 @@
+//! Receipts binding a builder configuration to the transaction it produced.
+//!
+//! A [`BuildReceipt`] records the hash of the builder configuration, the
+//! inputs chosen by the builder and the final transaction hash. The
+//! transaction hash doesn't cover the witnesses, so the receipt can be made
+//! before or after unlocking. The builder may sign the receipt with its own
+//! key, anyone can then check with [`BuildReceipt::verify`] that an on-chain
+//! transaction was produced by the recorded configuration.
+
+use ckb_hash::{blake2b_256, new_blake2b};
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{core::TransactionView, packed::OutPoint, prelude::*, H160, H256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::secp;
+use crate::traits::{Signer, SignerError};
+use crate::util::blake160;
+
+#[derive(Error, Debug)]
+pub enum ReceiptError {
+    #[error("serialize builder config error: `{0}`")]
+    SerializeConfig(String),
+
+    #[error("config hash mismatch, expected: {expected:#x}, got: {actual:#x}")]
+    ConfigMismatch { expected: H256, actual: H256 },
+
+    #[error("transaction hash mismatch, expected: {expected:#x}, got: {actual:#x}")]
+    TxHashMismatch { expected: H256, actual: H256 },
+
+    #[error("the inputs of the transaction differ from the receipt")]
+    InputsMismatch,
+
+    #[error("invalid receipt signature: `{0}`")]
+    InvalidSignature(String),
+
+    #[error(transparent)]
+    Signer(#[from] SignerError),
+}
+
+/// The hash of a serializable builder configuration.
+///
+/// The configuration is converted to a JSON value first and written with the
+/// object keys sorted, so the hash doesn't depend on the field order of the
+/// document.
+pub fn config_hash<T: Serialize>(config: &T) -> Result<H256, ReceiptError> {
+    let value = serde_json::to_value(config)
+        .map_err(|err| ReceiptError::SerializeConfig(err.to_string()))?;
+    let mut data = Vec::new();
+    write_canonical_json(&value, &mut data);
+    Ok(H256(blake2b_256(data)))
+}
+
+fn write_canonical_json(value: &serde_json::Value, buf: &mut Vec<u8>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            buf.push(b'[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    buf.push(b',');
+                }
+                write_canonical_json(item, buf);
+            }
+            buf.push(b']');
+        }
+        serde_json::Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            buf.push(b'{');
+            for (idx, key) in keys.into_iter().enumerate() {
+                if idx > 0 {
+                    buf.push(b',');
+                }
+                buf.extend_from_slice(serde_json::Value::from(key.as_str()).to_string().as_bytes());
+                buf.push(b':');
+                write_canonical_json(&map[key], buf);
+            }
+            buf.push(b'}');
+        }
+        scalar => buf.extend_from_slice(scalar.to_string().as_bytes()),
+    }
+}
+
+/// The signature of the builder over [`BuildReceipt::message`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptSignature {
+    /// blake160 of the public key
+    pub signer: H160,
+    /// 65 bytes recoverable secp256k1 signature
+    pub signature: json_types::JsonBytes,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BuildReceipt {
+    pub config_hash: H256,
+    pub inputs: Vec<json_types::OutPoint>,
+    pub tx_hash: H256,
+    pub signature: Option<ReceiptSignature>,
+}
+
+impl BuildReceipt {
+    pub fn new(config_hash: H256, tx: &TransactionView) -> BuildReceipt {
+        BuildReceipt {
+            config_hash,
+            inputs: tx.input_pts_iter().map(Into::into).collect(),
+            tx_hash: tx.hash().unpack(),
+            signature: None,
+        }
+    }
+
+    /// Create the receipt with the [`config_hash`] of `config`
+    pub fn from_config<T: Serialize>(
+        config: &T,
+        tx: &TransactionView,
+    ) -> Result<BuildReceipt, ReceiptError> {
+        Ok(BuildReceipt::new(config_hash(config)?, tx))
+    }
+
+    /// The hash signed by the builder:
+    /// `blake2b(config_hash | tx_hash | inputs_len (u32 LE) | inputs)`
+    pub fn message(&self) -> H256 {
+        let mut hasher = new_blake2b();
+        hasher.update(self.config_hash.as_bytes());
+        hasher.update(self.tx_hash.as_bytes());
+        hasher.update(&(self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            hasher.update(OutPoint::from(input.clone()).as_slice());
+        }
+        let mut message = [0u8; 32];
+        hasher.finalize(&mut message);
+        H256(message)
+    }
+
+    /// Sign the receipt with the key of `signer` whose blake160 public key
+    /// hash is `pubkey_hash`, `tx` is the transaction of the receipt.
+    pub fn sign(
+        &mut self,
+        signer: &dyn Signer,
+        pubkey_hash: &H160,
+        tx: &TransactionView,
+    ) -> Result<(), ReceiptError> {
+        let signature = signer.sign(pubkey_hash.as_bytes(), self.message().as_bytes(), true, tx)?;
+        self.signature = Some(ReceiptSignature {
+            signer: pubkey_hash.clone(),
+            signature: json_types::JsonBytes::from_bytes(signature),
+        });
+        Ok(())
+    }
+
+    /// Check the config hash against `config`
+    pub fn verify_config<T: Serialize>(&self, config: &T) -> Result<(), ReceiptError> {
+        let actual = config_hash(config)?;
+        if actual != self.config_hash {
+            return Err(ReceiptError::ConfigMismatch {
+                expected: self.config_hash.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that `tx` is the transaction of the receipt and the signature,
+    /// if any, was made by the recorded signer.
+    pub fn verify(&self, tx: &TransactionView) -> Result<(), ReceiptError> {
+        let tx_hash: H256 = tx.hash().unpack();
+        if tx_hash != self.tx_hash {
+            return Err(ReceiptError::TxHashMismatch {
+                expected: self.tx_hash.clone(),
+                actual: tx_hash,
+            });
+        }
+        let inputs: Vec<json_types::OutPoint> = tx.input_pts_iter().map(Into::into).collect();
+        if inputs != self.inputs {
+            return Err(ReceiptError::InputsMismatch);
+        }
+        if let Some(signature) = self.signature.as_ref() {
+            let signer = recover_signer(&self.message(), signature.signature.as_bytes())?;
+            if signer != signature.signer {
+                return Err(ReceiptError::InvalidSignature(format!(
+                    "signed by {:#x}, expected {:#x}",
+                    signer, signature.signer
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// [`BuildReceipt::verify`] and require the receipt to be signed by
+    /// `pubkey_hash`
+    pub fn verify_signed_by(
+        &self,
+        tx: &TransactionView,
+        pubkey_hash: &H160,
+    ) -> Result<(), ReceiptError> {
+        match self.signature.as_ref() {
+            Some(signature) if &signature.signer == pubkey_hash => self.verify(tx),
+            Some(signature) => Err(ReceiptError::InvalidSignature(format!(
+                "signed by {:#x}, expected {:#x}",
+                signature.signer, pubkey_hash
+            ))),
+            None => Err(ReceiptError::InvalidSignature(
+                "the receipt is not signed".to_string(),
+            )),
+        }
+    }
+}
+
+fn recover_signer(message: &H256, signature: &[u8]) -> Result<H160, ReceiptError> {
+    let pubkey = secp::recover_pubkey(&message.0, signature)
+        .map_err(|err| ReceiptError::InvalidSignature(err.to_string()))?;
+    Ok(blake160(&pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::SecpCkbRawKeySigner;
+    use crate::SECP256K1;
+    use ckb_types::{core::TransactionBuilder, h160, h256, packed::CellInput};
+    use serde_json::json;
+
+    #[test]
+    fn test_build_receipt() {
+        let config = json!({ "builder": "capacity_transfer", "fee_rate": 1000 });
+        let reordered = json!({ "fee_rate": 1000, "builder": "capacity_transfer" });
+        assert_eq!(
+            config_hash(&config).unwrap(),
+            config_hash(&reordered).unwrap()
+        );
+
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(h256!("0x1").pack(), 0), 0))
+            .input(CellInput::new(OutPoint::new(h256!("0x2").pack(), 1), 0))
+            .build();
+        let mut receipt = BuildReceipt::from_config(&config, &tx).unwrap();
+        receipt.verify(&tx).unwrap();
+        receipt.verify_config(&reordered).unwrap();
+        assert!(receipt
+            .verify_config(&json!({ "builder": "sweep" }))
+            .is_err());
+        assert!(receipt.verify_signed_by(&tx, &H160::default()).is_err());
+
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, &key);
+        let pubkey_hash = blake160(&pubkey.serialize());
+        let signer = SecpCkbRawKeySigner::new_with_secret_keys(vec![key]);
+        receipt.sign(&signer, &pubkey_hash, &tx).unwrap();
+        receipt.verify_signed_by(&tx, &pubkey_hash).unwrap();
+        assert!(receipt.verify_signed_by(&tx, &h160!("0x1")).is_err());
+
+        // the receipt survives a JSON round trip
+        let json = serde_json::to_string(&receipt).unwrap();
+        let decoded: BuildReceipt = serde_json::from_str(&json).unwrap();
+        decoded.verify(&tx).unwrap();
+
+        let mut forged = decoded.clone();
+        forged.config_hash = H256::default();
+        assert!(matches!(
+            forged.verify(&tx),
+            Err(ReceiptError::InvalidSignature(_))
+        ));
+        let other_tx = tx.as_advanced_builder().set_inputs(vec![]).build();
+        assert!(matches!(
+            decoded.verify(&other_tx),
+            Err(ReceiptError::TxHashMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::anyhow;
+use ckb_types::{
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, OutPoint, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+
+use super::{resolve_cell_deps, CapacityBalancer, CapacityProvider, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+
+/// Who pays the fee of a refund
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RefundFeePolicy {
+    /// The sender gets the received capacity minus the fee, only for plain
+    /// cells (no type script and empty data)
+    DeductFromRefund,
+    /// The sender gets the received cell as is, the refunder adds inputs for
+    /// the fee
+    PaidByRefunder,
+}
+
+/// Send a received cell back to the lock of the first input of the
+/// transaction which created it, e.g. after a payment to a wrong account.
+#[derive(Debug, Clone)]
+pub struct RefundBuilder {
+    /// The transaction of the received payment
+    pub tx_hash: H256,
+    pub index: u32,
+    /// The placeholder witness of the lock of the received cell
+    pub placeholder_witness: WitnessArgs,
+    pub fee_policy: RefundFeePolicy,
+    /// The current tip block number, the received cell is locked in the cell
+    /// collector at it while the balancer collects the cells of its lock
+    pub tip_block_number: u64,
+}
+
+impl RefundBuilder {
+    pub fn new(
+        tx_hash: H256,
+        index: u32,
+        placeholder_witness: WitnessArgs,
+        fee_policy: RefundFeePolicy,
+        tip_block_number: u64,
+    ) -> RefundBuilder {
+        RefundBuilder {
+            tx_hash,
+            index,
+            placeholder_witness,
+            fee_policy,
+            tip_block_number,
+        }
+    }
+
+    pub fn out_point(&self) -> OutPoint {
+        OutPoint::new(self.tx_hash.pack(), self.index)
+    }
+
+    /// The lock of the first input of the payment transaction
+    pub fn sender_lock(
+        &self,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<Script, TxBuilderError> {
+        let tx = tx_dep_provider.get_transaction(&self.tx_hash.pack())?;
+        let input = tx.inputs().get(0).ok_or_else(|| {
+            TxBuilderError::InvalidParameter(anyhow!(
+                "transaction {:#x} has no inputs",
+                self.tx_hash
+            ))
+        })?;
+        Ok(tx_dep_provider.get_cell(&input.previous_output())?.lock())
+    }
+
+    /// The balancer of the refund transaction, the fee is paid by the lock of
+    /// the received cell. With [`RefundFeePolicy::DeductFromRefund`] the
+    /// change goes to the sender.
+    pub fn balancer(
+        &self,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        fee_rate: u64,
+    ) -> Result<CapacityBalancer, TxBuilderError> {
+        let lock = tx_dep_provider.get_cell(&self.out_point())?.lock();
+        let mut balancer = CapacityBalancer::new_with_provider(
+            fee_rate,
+            CapacityProvider::new_simple(vec![(lock, self.placeholder_witness.clone())]),
+        );
+        if self.fee_policy == RefundFeePolicy::DeductFromRefund {
+            balancer.change_lock_script = Some(self.sender_lock(tx_dep_provider)?);
+        }
+        Ok(balancer)
+    }
+}
+
+impl TxBuilder for RefundBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let out_point = self.out_point();
+        let output = tx_dep_provider.get_cell(&out_point)?;
+        let data = tx_dep_provider.get_cell_data(&out_point)?;
+        let mut cell_dep_scripts = vec![output.lock()];
+        if let Some(type_script) = output.type_().to_opt() {
+            cell_dep_scripts.push(type_script);
+        }
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+        // the balancer collects cells of the same lock, don't pick this one twice
+        cell_collector.lock_cell(out_point.clone(), self.tip_block_number)?;
+        let builder = TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .input(CellInput::new(out_point, 0))
+            .witness(self.placeholder_witness.as_bytes().pack());
+        match self.fee_policy {
+            RefundFeePolicy::DeductFromRefund => {
+                if output.type_().to_opt().is_some() || !data.is_empty() {
+                    return Err(TxBuilderError::InvalidParameter(anyhow!(
+                        "the fee can only be deducted from a plain cell"
+                    )));
+                }
+                // the balancer sends everything minus the fee to the sender
+                Ok(builder.build())
+            }
+            RefundFeePolicy::PaidByRefunder => {
+                let sender_lock = self.sender_lock(tx_dep_provider)?;
+                Ok(builder
+                    .output(output.as_builder().lock(sender_lock).build())
+                    .output_data(data.pack())
+                    .build())
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Two-party swap of UDT against CKB in one atomic transaction.
+//!
+//! The swap is negotiated in order, the [`SwapProposal`] is passed between the
+//! parties (it can be serialized as JSON):
+//!   1. Party A (the UDT seller) calls [`SwapBuilder::propose`], its UDT cells
+//!      become inputs and all the outputs of the swap are created.
+//!   2. Party B (the UDT buyer) calls [`SwapBuilder::accept`], its capacity
+//!      cells are added to pay the CKB amount and the fee.
+//!   3. Each party calls [`SwapProposal::sign`] with its own unlockers, only
+//!      the script groups of that party are signed.
+//!
+//! Every step checks the transaction against the [`SwapTerms`], so a party
+//! never signs a transaction that moves more than it agreed to.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, Script, Transaction},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    balance_tx_capacity, fill_placeholder_witnesses, resolve_cell_deps, unlock_tx,
+    CapacityBalancer, TxBuilderError,
+};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::{ScriptGroup, ScriptId};
+use crate::unlock::ScriptUnlocker;
+use crate::util::parse_udt_amount;
+
+/// What both parties of a swap agree on
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SwapTerms {
+    pub udt_type_script: json_types::Script,
+    /// The UDT amount party A sends to party B
+    pub udt_amount: json_types::Uint128,
+    /// The capacity in shannons party B sends to party A
+    pub ckb_amount: json_types::Uint64,
+    pub party_a_lock: json_types::Script,
+    pub party_b_lock: json_types::Script,
+}
+
+impl SwapTerms {
+    pub fn new(
+        udt_type_script: Script,
+        udt_amount: u128,
+        ckb_amount: u64,
+        party_a_lock: Script,
+        party_b_lock: Script,
+    ) -> SwapTerms {
+        SwapTerms {
+            udt_type_script: udt_type_script.into(),
+            udt_amount: udt_amount.into(),
+            ckb_amount: ckb_amount.into(),
+            party_a_lock: party_a_lock.into(),
+            party_b_lock: party_b_lock.into(),
+        }
+    }
+
+    pub fn udt_type_script(&self) -> Script {
+        self.udt_type_script.clone().into()
+    }
+    pub fn party_a_lock(&self) -> Script {
+        self.party_a_lock.clone().into()
+    }
+    pub fn party_b_lock(&self) -> Script {
+        self.party_b_lock.clone().into()
+    }
+}
+
+/// The stage of a swap negotiation
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapStage {
+    /// Party A added its UDT inputs and the outputs
+    Proposed,
+    /// Party B added its capacity inputs, the transaction can be signed
+    Accepted,
+}
+
+/// The negotiation object passed between the parties of a swap
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SwapProposal {
+    pub terms: SwapTerms,
+    pub stage: SwapStage,
+    pub tx: json_types::Transaction,
+}
+
+impl SwapProposal {
+    pub fn tx_view(&self) -> TransactionView {
+        Transaction::from(self.tx.clone()).into_view()
+    }
+
+    /// Check the transaction moves exactly the amounts of the terms between
+    /// the parties, and no output goes to someone else.
+    pub fn verify(
+        &self,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<(), TxBuilderError> {
+        let tx = self.tx_view();
+        let type_script = self.terms.udt_type_script();
+        let party_a_lock = self.terms.party_a_lock();
+        let party_b_lock = self.terms.party_b_lock();
+        let mut balances = SwapBalances::default();
+        for out_point in tx.input_pts_iter() {
+            let output = tx_dep_provider.get_cell(&out_point)?;
+            let data = tx_dep_provider.get_cell_data(&out_point)?;
+            if output.lock() == party_a_lock {
+                balances.add_cell(PARTY_A, true, &output, &data, &type_script)?;
+            } else if output.lock() == party_b_lock {
+                balances.add_cell(PARTY_B, true, &output, &data, &type_script)?;
+            }
+        }
+        for (idx, (output, data)) in tx.outputs_with_data_iter().enumerate() {
+            if output.lock() == party_a_lock {
+                balances.add_cell(PARTY_A, false, &output, &data, &type_script)?;
+            } else if output.lock() == party_b_lock {
+                balances.add_cell(PARTY_B, false, &output, &data, &type_script)?;
+            } else {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "output #{} is not locked by any party of the swap",
+                    idx
+                )));
+            }
+        }
+
+        let udt_amount = self.terms.udt_amount.value();
+        let ckb_amount = self.terms.ckb_amount.value();
+        let udt_sent = balances.udt_in[PARTY_A].checked_sub(balances.udt_out[PARTY_A]);
+        let udt_received = balances.udt_out[PARTY_B].checked_sub(balances.udt_in[PARTY_B]);
+        if udt_sent != Some(udt_amount) || udt_received != Some(udt_amount) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the swap does not move {} udt from party A to party B",
+                udt_amount
+            )));
+        }
+        let ckb_received =
+            balances.capacity_out[PARTY_A].checked_sub(balances.capacity_in[PARTY_A]);
+        if ckb_received != Some(ckb_amount) {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "party A does not receive exactly {} shannons",
+                ckb_amount
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sign the script groups matched by `unlockers` after checking the terms.
+    ///
+    /// Return the script groups not unlocked by `unlockers`, usually the ones
+    /// of the other party.
+    pub fn sign(
+        &mut self,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<Vec<ScriptGroup>, TxBuilderError> {
+        if self.stage != SwapStage::Accepted {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the swap is not accepted by party B yet"
+            )));
+        }
+        self.verify(tx_dep_provider)?;
+        let (tx, not_unlocked) = unlock_tx(self.tx_view(), tx_dep_provider, unlockers)?;
+        self.tx = tx.data().into();
+        Ok(not_unlocked)
+    }
+}
+
+const PARTY_A: usize = 0;
+const PARTY_B: usize = 1;
+
+/// The UDT and capacity sums of each party
+#[derive(Default)]
+struct SwapBalances {
+    udt_in: [u128; 2],
+    udt_out: [u128; 2],
+    capacity_in: [u64; 2],
+    capacity_out: [u64; 2],
+}
+
+impl SwapBalances {
+    fn add_cell(
+        &mut self,
+        party: usize,
+        is_input: bool,
+        output: &CellOutput,
+        data: &Bytes,
+        type_script: &Script,
+    ) -> Result<(), TxBuilderError> {
+        let udt = if output.type_().to_opt().as_ref() == Some(type_script) {
+            parse_udt_amount(data).map_err(|err| TxBuilderError::InvalidParameter(err.into()))?
+        } else {
+            0
+        };
+        let capacity: u64 = output.capacity().unpack();
+        let (udt_sum, capacity_sum) = if is_input {
+            (&mut self.udt_in[party], &mut self.capacity_in[party])
+        } else {
+            (&mut self.udt_out[party], &mut self.capacity_out[party])
+        };
+        *udt_sum = udt_sum
+            .checked_add(udt)
+            .ok_or(TxBuilderError::AmountOverflow(*udt_sum, udt))?;
+        *capacity_sum = capacity_sum
+            .checked_add(capacity)
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("capacity overflow")))?;
+        Ok(())
+    }
+}
+
+/// Builds the transaction of a swap, party A and party B each run their own
+/// step with their own cell collector.
+pub struct SwapBuilder {
+    pub terms: SwapTerms,
+}
+
+impl SwapBuilder {
+    pub fn new(terms: SwapTerms) -> SwapBuilder {
+        SwapBuilder { terms }
+    }
+
+    /// Step 1, run by party A.
+    ///
+    /// All the UDT cells of party A are merged into the inputs, the outputs
+    /// are the UDT cell of party B, the capacity cell of party A and the UDT
+    /// change cell of party A if any. The capacity of party A's inputs
+    /// exceeding the change cell goes back to its capacity cell. Placeholder
+    /// witnesses are filled by `unlockers` so party B can estimate the fee.
+    pub fn propose(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+        unlockers: &HashMap<ScriptId, Box<dyn ScriptUnlocker>>,
+    ) -> Result<SwapProposal, TxBuilderError> {
+        let type_script = self.terms.udt_type_script();
+        let party_a_lock = self.terms.party_a_lock();
+        let party_b_lock = self.terms.party_b_lock();
+        let udt_amount = self.terms.udt_amount.value();
+        let ckb_amount = self.terms.ckb_amount.value();
+
+        let query = {
+            let mut query = CellQueryOptions::new_lock(party_a_lock.clone());
+            query.secondary_script = Some(type_script.clone());
+            query.data_len_range = Some(ValueRangeOption::new_min(16));
+            query.min_total_capacity = u64::MAX;
+            query
+        };
+        let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+        let mut inputs = Vec::with_capacity(cells.len());
+        let mut udt_total = 0u128;
+        let mut capacity_total = 0u64;
+        for cell in &cells {
+            let amount = parse_udt_amount(&cell.output_data)
+                .map_err(|err| TxBuilderError::InvalidParameter(err.into()))?;
+            udt_total = udt_total
+                .checked_add(amount)
+                .ok_or(TxBuilderError::AmountOverflow(udt_total, amount))?;
+            capacity_total = capacity_total
+                .checked_add(cell.output.capacity().unpack())
+                .ok_or_else(|| TxBuilderError::Other(anyhow!("input capacity overflow")))?;
+            inputs.push(CellInput::new(cell.out_point.clone(), 0));
+        }
+        if udt_total < udt_amount {
+            return Err(TxBuilderError::Other(anyhow!(
+                "party A udt amount not enough, expected at least: {}, actual: {}",
+                udt_amount,
+                udt_total
+            )));
+        }
+
+        let udt_output = |lock: Script, amount: u128| {
+            let output = CellOutput::new_builder()
+                .lock(lock)
+                .type_(Some(type_script.clone()).pack())
+                .build();
+            let capacity = output
+                .occupied_capacity(Capacity::bytes(16).unwrap())
+                .unwrap()
+                .as_u64();
+            (
+                output.as_builder().capacity(capacity.pack()).build(),
+                Bytes::from(amount.to_le_bytes().to_vec()),
+            )
+        };
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        let (party_b_output, party_b_data) = udt_output(party_b_lock, udt_amount);
+        outputs.push(party_b_output);
+        outputs_data.push(party_b_data.pack());
+
+        let change_amount = udt_total - udt_amount;
+        let mut returned_capacity = capacity_total;
+        let change = if change_amount > 0 {
+            let (change_output, change_data) = udt_output(party_a_lock.clone(), change_amount);
+            let change_capacity: u64 = change_output.capacity().unpack();
+            returned_capacity =
+                returned_capacity
+                    .checked_sub(change_capacity)
+                    .ok_or_else(|| {
+                        TxBuilderError::Other(anyhow!(
+                            "party A udt cells can not hold the udt change cell"
+                        ))
+                    })?;
+            Some((change_output, change_data))
+        } else {
+            None
+        };
+
+        let capacity_output = CellOutput::new_builder().lock(party_a_lock.clone()).build();
+        let capacity = ckb_amount
+            .checked_add(returned_capacity)
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("output capacity overflow")))?;
+        let occupied_capacity = capacity_output
+            .occupied_capacity(Capacity::zero())
+            .unwrap()
+            .as_u64();
+        if capacity < occupied_capacity {
+            return Err(TxBuilderError::Other(anyhow!(
+                "Not enough capacity to hold party A cell, min: {}, actual: {}",
+                occupied_capacity,
+                capacity,
+            )));
+        }
+        outputs.push(
+            capacity_output
+                .as_builder()
+                .capacity(capacity.pack())
+                .build(),
+        );
+        outputs_data.push(Bytes::new().pack());
+        if let Some((change_output, change_data)) = change {
+            outputs.push(change_output);
+            outputs_data.push(change_data.pack());
+        }
+
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &[party_a_lock, type_script])?;
+        let base_tx = TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_outputs(outputs)
+            .set_outputs_data(outputs_data)
+            .build();
+        let (tx, not_matched) = fill_placeholder_witnesses(base_tx, tx_dep_provider, unlockers)?;
+        if !not_matched.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "no unlocker for the lock script of party A"
+            )));
+        }
+        Ok(SwapProposal {
+            terms: self.terms.clone(),
+            stage: SwapStage::Proposed,
+            tx: tx.data().into(),
+        })
+    }
+
+    /// Step 2, run by party B.
+    ///
+    /// The proposal must be made for the same terms as this builder. The
+    /// capacity paid to party A, the UDT cell capacity and the fee are
+    /// collected by `balancer`, which should use the lock of party B.
+    pub fn accept(
+        &self,
+        proposal: &SwapProposal,
+        balancer: &CapacityBalancer,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<SwapProposal, TxBuilderError> {
+        if proposal.stage != SwapStage::Proposed {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the swap is already accepted"
+            )));
+        }
+        if proposal.terms != self.terms {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "the proposal is made for different swap terms"
+            )));
+        }
+        proposal.verify(tx_dep_provider)?;
+        let tx = balance_tx_capacity(
+            &proposal.tx_view(),
+            balancer,
+            cell_collector,
+            tx_dep_provider,
+            cell_dep_resolver,
+            header_dep_resolver,
+        )?;
+        let accepted = SwapProposal {
+            terms: self.terms.clone(),
+            stage: SwapStage::Accepted,
+            tx: tx.data().into(),
+        };
+        // the balancer must not touch the cells of party A
+        accepted.verify(tx_dep_provider)?;
+        Ok(accepted)
+    }
+}
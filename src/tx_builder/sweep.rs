@@ -0,0 +1,312 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{TransactionBuilder, TransactionView},
+    packed::{CellInput, Script, WitnessArgs},
+    prelude::*,
+    H160,
+};
+
+use super::{
+    gen_script_groups, resolve_cell_deps, CapacityBalancer, CapacityProvider, TxBuilder,
+    TxBuilderError,
+};
+use crate::redact;
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::HumanCapacity;
+use crate::unlock::MultisigConfig;
+
+/// Sweep all the plain cells (no type script and empty data) of some lock
+/// scripts.
+///
+/// The base transaction has no outputs, balance it with a `CapacityBalancer`
+/// whose `change_lock_script` is the destination lock script, the whole
+/// capacity minus the transaction fee goes to the change cell.
+pub struct SweepBuilder {
+    /// The lock scripts to sweep and the placeholder witness of each lock
+    pub lock_scripts: Vec<(Script, WitnessArgs)>,
+}
+
+impl SweepBuilder {
+    pub fn new(lock_scripts: Vec<(Script, WitnessArgs)>) -> SweepBuilder {
+        SweepBuilder { lock_scripts }
+    }
+}
+
+impl TxBuilder for SweepBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let mut inputs = Vec::new();
+        let mut witnesses = Vec::new();
+        let mut cell_dep_scripts = Vec::new();
+        for (lock_script, placeholder_witness) in &self.lock_scripts {
+            let mut query = CellQueryOptions::new_lock(lock_script.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+            for (idx, cell) in cells.iter().enumerate() {
+                inputs.push(CellInput::new(cell.out_point.clone(), 0));
+                // only the first witness of the script group holds the signature
+                let witness = if idx == 0 {
+                    placeholder_witness.as_bytes()
+                } else {
+                    Bytes::new()
+                };
+                witnesses.push(witness.pack());
+            }
+            if !cells.is_empty() {
+                cell_dep_scripts.push(lock_script.clone());
+            }
+        }
+        if inputs.is_empty() {
+            return Err(TxBuilderError::Other(anyhow!("no cells to sweep")));
+        }
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_witnesses(witnesses)
+            .build())
+    }
+}
+
+/// A lock script the user controls and how its inputs are signed
+#[derive(Debug, Clone)]
+pub enum SweepLock {
+    /// A secp256k1 sighash lock
+    Sighash(Script),
+    /// An anyone-can-pay lock, only its plain cells are swept
+    Acp(Script),
+    /// A legacy secp256k1 multisig lock and its config
+    Multisig(Script, MultisigConfig),
+}
+
+impl SweepLock {
+    pub fn lock_script(&self) -> &Script {
+        match self {
+            SweepLock::Sighash(script)
+            | SweepLock::Acp(script)
+            | SweepLock::Multisig(script, _) => script,
+        }
+    }
+
+    pub fn placeholder_witness(&self) -> WitnessArgs {
+        match self {
+            SweepLock::Sighash(_) | SweepLock::Acp(_) => WitnessArgs::new_builder()
+                .lock(Some(Bytes::from(vec![0u8; 65])).pack())
+                .build(),
+            SweepLock::Multisig(_, config) => config.placeholder_witness(),
+        }
+    }
+
+    /// The key hashes which may sign, the first 20 bytes of the args for the
+    /// sighash and the ACP locks
+    pub fn signers(&self) -> Vec<H160> {
+        match self {
+            SweepLock::Sighash(script) | SweepLock::Acp(script) => {
+                let args = script.args().raw_data();
+                H160::from_slice(&args[..args.len().min(20)])
+                    .map(|key_hash| vec![key_hash])
+                    .unwrap_or_default()
+            }
+            SweepLock::Multisig(_, config) => config.sighash_addresses().clone(),
+        }
+    }
+
+    /// The number of signatures needed
+    pub fn threshold(&self) -> u8 {
+        match self {
+            SweepLock::Sighash(_) | SweepLock::Acp(_) => 1,
+            SweepLock::Multisig(_, config) => config.threshold(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            SweepLock::Sighash(_) => "sighash",
+            SweepLock::Acp(_) => "anyone-can-pay",
+            SweepLock::Multisig(_, _) => "multisig",
+        }
+    }
+}
+
+/// Sweep the plain cells of several kinds of lock scripts (e.g. sighash, ACP
+/// and legacy multisig) into one destination.
+///
+/// The inputs are grouped by lock script, the cell deps of every lock are
+/// added. After balancing, [`MultiLockSweepBuilder::signer_requirements`]
+/// reports which keys must sign each group.
+pub struct MultiLockSweepBuilder {
+    pub locks: Vec<SweepLock>,
+}
+
+impl MultiLockSweepBuilder {
+    pub fn new(locks: Vec<SweepLock>) -> MultiLockSweepBuilder {
+        MultiLockSweepBuilder { locks }
+    }
+
+    fn lock_scripts(&self) -> Vec<(Script, WitnessArgs)> {
+        self.locks
+            .iter()
+            .map(|lock| (lock.lock_script().clone(), lock.placeholder_witness()))
+            .collect()
+    }
+
+    /// The balancer sending the whole capacity minus the fee to `destination`
+    pub fn balancer(&self, destination: Script, fee_rate: u64) -> CapacityBalancer {
+        let mut balancer = CapacityBalancer::new_with_provider(
+            fee_rate,
+            CapacityProvider::new_simple(self.lock_scripts()),
+        );
+        balancer.change_lock_script = Some(destination);
+        balancer
+    }
+
+    /// The signers of every lock script group of the sweep transaction `tx`,
+    /// sorted by the first input of the group
+    pub fn signer_requirements(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<SweepSignerReport, TxBuilderError> {
+        let mut groups = gen_script_groups(tx, tx_dep_provider)?
+            .lock_groups
+            .into_values()
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|group| group.input_indices[0]);
+        let out_points = tx.input_pts_iter().collect::<Vec<_>>();
+        let mut requirements = Vec::new();
+        for group in groups {
+            let lock = self
+                .locks
+                .iter()
+                .find(|lock| lock.lock_script() == &group.script)
+                .ok_or_else(|| {
+                    TxBuilderError::Other(anyhow!(
+                        "input locked by {} is not swept",
+                        redact::script(&group.script)
+                    ))
+                })?;
+            let mut capacity = 0u64;
+            for idx in &group.input_indices {
+                let cell_capacity: u64 = tx_dep_provider
+                    .get_cell(&out_points[*idx])?
+                    .capacity()
+                    .unpack();
+                capacity = capacity.saturating_add(cell_capacity);
+            }
+            requirements.push(SignerRequirement {
+                lock: lock.clone(),
+                input_indices: group.input_indices,
+                capacity,
+            });
+        }
+        Ok(SweepSignerReport { requirements })
+    }
+}
+
+impl TxBuilder for MultiLockSweepBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        SweepBuilder::new(self.lock_scripts()).build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )
+    }
+}
+
+/// The inputs of one lock script of a sweep and who must sign them
+#[derive(Debug, Clone)]
+pub struct SignerRequirement {
+    pub lock: SweepLock,
+    pub input_indices: Vec<usize>,
+    /// The swept capacity of the inputs
+    pub capacity: u64,
+}
+
+impl SignerRequirement {
+    pub fn signers(&self) -> Vec<H160> {
+        self.lock.signers()
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.lock.threshold()
+    }
+}
+
+impl fmt::Display for SignerRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let signers = self
+            .signers()
+            .iter()
+            .map(|key_hash| redact::bytes(key_hash.as_bytes()).to_string())
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "inputs {:?} ({} CKB) locked by {} {}: {} of [{}]",
+            self.input_indices,
+            redact::amount(HumanCapacity(self.capacity)),
+            self.lock.kind(),
+            redact::bytes(&self.lock.lock_script().args().raw_data()),
+            self.threshold(),
+            signers.join(", ")
+        )
+    }
+}
+
+/// The signers needed by a multi lock sweep, see
+/// [`MultiLockSweepBuilder::signer_requirements`]
+#[derive(Debug, Clone, Default)]
+pub struct SweepSignerReport {
+    pub requirements: Vec<SignerRequirement>,
+}
+
+impl SweepSignerReport {
+    /// All the keys which may sign, without duplicates
+    pub fn signers(&self) -> Vec<H160> {
+        let mut signers = Vec::new();
+        for key_hash in self.requirements.iter().flat_map(|req| req.signers()) {
+            if !signers.contains(&key_hash) {
+                signers.push(key_hash);
+            }
+        }
+        signers
+    }
+
+    pub fn total_capacity(&self) -> u64 {
+        self.requirements
+            .iter()
+            .map(|req| req.capacity)
+            .fold(0u64, u64::saturating_add)
+    }
+}
+
+impl fmt::Display for SweepSignerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, requirement) in self.requirements.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", requirement)?;
+        }
+        Ok(())
+    }
+}
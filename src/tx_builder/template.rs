@@ -0,0 +1,142 @@
+//! Cache the base transaction of builders building the same outputs again and
+//! again.
+//!
+//! A [`TemplateCache`] keeps the `build_base` result of builders keyed by the
+//! hash of their config ([`TemplateKey`]), [`CachedTxBuilder`] wraps a builder
+//! so only the cell collection of the balancer and the unlocking are done for
+//! a cached template.
+//!
+//! Only cache builders whose base transaction is fully decided by the config,
+//! the checks `build_base` does against live cells (e.g. type id collisions)
+//! are skipped for a cached template.
+
+use ckb_hash::new_blake2b;
+use ckb_types::{core::TransactionView, prelude::*, H256};
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use super::{transfer::CapacityTransferBuilder, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
+};
+
+/// The default maximum templates of a [`TemplateCache`]
+pub const DEFAULT_TEMPLATE_CACHE_SIZE: usize = 256;
+
+/// The hash of a builder config, builders with the same key must build the
+/// same base transaction.
+pub trait TemplateKey {
+    fn template_key(&self) -> H256;
+}
+
+impl TemplateKey for CapacityTransferBuilder {
+    fn template_key(&self) -> H256 {
+        let mut hasher = new_blake2b();
+        hasher.update(b"CapacityTransferBuilder");
+        for (output, data) in &self.outputs {
+            hasher.update(output.as_slice());
+            hasher.update(&(data.len() as u64).to_le_bytes());
+            hasher.update(data.as_ref());
+        }
+        let mut key = [0u8; 32];
+        hasher.finalize(&mut key);
+        H256(key)
+    }
+}
+
+/// Hit and miss counters of a [`TemplateCache`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TemplateCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A LRU cache of base transactions, safe to share between threads.
+pub struct TemplateCache {
+    inner: Mutex<(LruCache<H256, TransactionView>, TemplateCacheStats)>,
+}
+
+impl Default for TemplateCache {
+    fn default() -> TemplateCache {
+        TemplateCache::new(DEFAULT_TEMPLATE_CACHE_SIZE)
+    }
+}
+
+impl TemplateCache {
+    pub fn new(capacity: usize) -> TemplateCache {
+        TemplateCache {
+            inner: Mutex::new((LruCache::new(capacity), TemplateCacheStats::default())),
+        }
+    }
+
+    pub fn get(&self, key: &H256) -> Option<TransactionView> {
+        let mut inner = self.inner.lock();
+        let template = inner.0.get(key).cloned();
+        if template.is_some() {
+            inner.1.hits += 1;
+        } else {
+            inner.1.misses += 1;
+        }
+        template
+    }
+
+    pub fn insert(&self, key: H256, tx: TransactionView) {
+        self.inner.lock().0.put(key, tx);
+    }
+
+    /// Drop the template of `key`, e.g. after the resolved cell deps changed
+    pub fn invalidate(&self, key: &H256) {
+        self.inner.lock().0.pop(key);
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().0.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> TemplateCacheStats {
+        self.inner.lock().1
+    }
+}
+
+/// A builder reusing the cached base transaction of `builder`.
+pub struct CachedTxBuilder<'a, B> {
+    pub builder: &'a B,
+    pub cache: &'a TemplateCache,
+}
+
+impl<'a, B: TxBuilder + TemplateKey> CachedTxBuilder<'a, B> {
+    pub fn new(builder: &'a B, cache: &'a TemplateCache) -> CachedTxBuilder<'a, B> {
+        CachedTxBuilder { builder, cache }
+    }
+}
+
+impl<'a, B: TxBuilder + TemplateKey> TxBuilder for CachedTxBuilder<'a, B> {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let key = self.builder.template_key();
+        if let Some(tx) = self.cache.get(&key) {
+            return Ok(tx);
+        }
+        let tx = self.builder.build_base(
+            cell_collector,
+            cell_dep_resolver,
+            header_dep_resolver,
+            tx_dep_provider,
+        )?;
+        self.cache.insert(key, tx.clone());
+        Ok(tx)
+    }
+}
@@ -0,0 +1,667 @@
+//! Record the decisions of a transaction build so a failed build can be
+//! replayed offline.
+//!
+//! [`BuildTracer`] wraps the cell collector, the resolvers and the transaction
+//! dependency provider, every answer they give is appended to a [`BuildTrace`].
+//! The balancer queries the cell collector once per fee iteration with the
+//! capacity still missing as `min_total_capacity`, so the `CellsOffered`
+//! events also show how the fee converged.
+//!
+//! A trace is serializable, [`BuildTrace::replay_cell_collector`] and
+//! [`BuildTrace::replay_context`] serve the recorded answers to run the same
+//! builder again without a node.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{HeaderView, TransactionView},
+    packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction},
+    prelude::*,
+    H256,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::traits::{
+    CellCollector, CellCollectorError, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    LiveCell, TransactionDependencyError, TransactionDependencyProvider,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TracedCell {
+    pub out_point: json_types::OutPoint,
+    pub output: json_types::CellOutput,
+    pub output_data: json_types::JsonBytes,
+    pub block_number: u64,
+    pub tx_index: u32,
+}
+
+impl From<&LiveCell> for TracedCell {
+    fn from(cell: &LiveCell) -> TracedCell {
+        TracedCell {
+            out_point: cell.out_point.clone().into(),
+            output: cell.output.clone().into(),
+            output_data: json_types::JsonBytes::from_bytes(cell.output_data.clone()),
+            block_number: cell.block_number,
+            tx_index: cell.tx_index,
+        }
+    }
+}
+
+impl From<TracedCell> for LiveCell {
+    fn from(cell: TracedCell) -> LiveCell {
+        LiveCell {
+            output: cell.output.into(),
+            output_data: cell.output_data.into_bytes(),
+            out_point: cell.out_point.into(),
+            block_number: cell.block_number,
+            tx_index: cell.tx_index,
+        }
+    }
+}
+
+/// A decision or an answer recorded during a build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// The transaction returned by `build_base`
+    BaseTx {
+        tx: json_types::TransactionView,
+    },
+    /// The cells returned by the cell collector for a query, they are chosen
+    /// (locked) when `apply_changes` is true.
+    CellsOffered {
+        primary_script: json_types::Script,
+        min_total_capacity: u64,
+        apply_changes: bool,
+        cells: Vec<TracedCell>,
+    },
+    CellLocked {
+        out_point: json_types::OutPoint,
+    },
+    TxApplied {
+        tx_hash: H256,
+    },
+    CellDepResolved {
+        script: json_types::Script,
+        cell_dep: Option<json_types::CellDep>,
+    },
+    HeaderResolvedByTx {
+        tx_hash: H256,
+        header: Option<json_types::HeaderView>,
+    },
+    HeaderResolvedByNumber {
+        number: u64,
+        header: Option<json_types::HeaderView>,
+    },
+    TransactionFetched {
+        tx: json_types::TransactionView,
+    },
+    CellFetched {
+        out_point: json_types::OutPoint,
+        output: json_types::CellOutput,
+    },
+    CellDataFetched {
+        out_point: json_types::OutPoint,
+        data: json_types::JsonBytes,
+    },
+    HeaderFetched {
+        header: json_types::HeaderView,
+    },
+    BlockExtensionFetched {
+        block_hash: H256,
+        extension: Option<json_types::JsonBytes>,
+    },
+    /// The balanced transaction
+    Balanced {
+        tx: json_types::TransactionView,
+    },
+    /// The build failed with this error
+    Failed {
+        error: String,
+    },
+}
+
+/// The events of a build in the order they happened
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildTrace {
+    pub events: Vec<TraceEvent>,
+}
+
+impl BuildTrace {
+    /// The balanced transaction, `None` if the build failed
+    pub fn balanced_tx(&self) -> Option<TransactionView> {
+        self.events.iter().rev().find_map(|event| match event {
+            TraceEvent::Balanced { tx } => Some(Transaction::from(tx.inner.clone()).into_view()),
+            _ => None,
+        })
+    }
+
+    /// The error of the failed build
+    pub fn error(&self) -> Option<&str> {
+        self.events.iter().rev().find_map(|event| match event {
+            TraceEvent::Failed { error } => Some(error.as_str()),
+            _ => None,
+        })
+    }
+
+    /// A cell collector answering the queries with the recorded cells, in the
+    /// recorded order.
+    pub fn replay_cell_collector(&self) -> ReplayCellCollector {
+        let offered = self
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                TraceEvent::CellsOffered {
+                    primary_script,
+                    apply_changes,
+                    cells,
+                    ..
+                } => Some((
+                    Script::from(primary_script.clone()),
+                    *apply_changes,
+                    cells.iter().cloned().map(LiveCell::from).collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+        ReplayCellCollector { offered, next: 0 }
+    }
+
+    /// The recorded cell deps, headers, transactions and cells
+    pub fn replay_context(&self) -> ReplayContext {
+        let mut context = ReplayContext::default();
+        for event in &self.events {
+            match event.clone() {
+                TraceEvent::CellDepResolved { script, cell_dep } => {
+                    context.cell_deps.insert(
+                        Script::from(script).calc_script_hash().unpack(),
+                        cell_dep.map(CellDep::from),
+                    );
+                }
+                TraceEvent::HeaderResolvedByTx { tx_hash, header } => {
+                    context
+                        .headers_by_tx
+                        .insert(tx_hash, header.map(HeaderView::from));
+                }
+                TraceEvent::HeaderResolvedByNumber { number, header } => {
+                    context
+                        .headers_by_number
+                        .insert(number, header.map(HeaderView::from));
+                }
+                TraceEvent::TransactionFetched { tx } => {
+                    let tx = Transaction::from(tx.inner).into_view();
+                    context.transactions.insert(tx.hash().unpack(), tx);
+                }
+                TraceEvent::CellFetched { out_point, output } => {
+                    context
+                        .cells
+                        .insert(out_point_key(&out_point.into()), output.into());
+                }
+                TraceEvent::CellDataFetched { out_point, data } => {
+                    context
+                        .cells_data
+                        .insert(out_point_key(&out_point.into()), data.into_bytes());
+                }
+                TraceEvent::HeaderFetched { header } => {
+                    let header = HeaderView::from(header);
+                    context.headers.insert(header.hash().unpack(), header);
+                }
+                TraceEvent::BlockExtensionFetched {
+                    block_hash,
+                    extension,
+                } => {
+                    context
+                        .extensions
+                        .insert(block_hash, extension.map(|data| data.into_bytes().pack()));
+                }
+                _ => {}
+            }
+        }
+        context
+    }
+}
+
+fn out_point_key(out_point: &OutPoint) -> (H256, u32) {
+    (out_point.tx_hash().unpack(), out_point.index().unpack())
+}
+
+fn json_tx(tx: &TransactionView) -> json_types::TransactionView {
+    json_types::TransactionView::from(tx.clone())
+}
+
+/// Collects the events of a build, see the module document.
+#[derive(Default)]
+pub struct BuildTracer {
+    trace: Arc<Mutex<BuildTrace>>,
+}
+
+impl BuildTracer {
+    pub fn new() -> BuildTracer {
+        BuildTracer::default()
+    }
+
+    pub fn record(&self, event: TraceEvent) {
+        self.trace.lock().events.push(event);
+    }
+
+    pub fn record_base_tx(&self, tx: &TransactionView) {
+        self.record(TraceEvent::BaseTx { tx: json_tx(tx) });
+    }
+
+    pub fn record_balanced(&self, tx: &TransactionView) {
+        self.record(TraceEvent::Balanced { tx: json_tx(tx) });
+    }
+
+    pub fn record_error<E: ToString>(&self, error: &E) {
+        self.record(TraceEvent::Failed {
+            error: error.to_string(),
+        });
+    }
+
+    pub fn into_trace(self) -> BuildTrace {
+        Arc::try_unwrap(self.trace)
+            .map(Mutex::into_inner)
+            .unwrap_or_else(|trace| trace.lock().clone())
+    }
+
+    /// Trace a clone of `inner`, see [`TracingCellCollector::apply_changes_to`]
+    /// to keep the changes of the build.
+    pub fn cell_collector<'a>(&self, inner: &(dyn CellCollector + 'a)) -> TracingCellCollector<'a> {
+        TracingCellCollector {
+            inner: dyn_clone::clone_box(inner),
+            trace: Arc::clone(&self.trace),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn cell_dep_resolver<'a>(
+        &'a self,
+        inner: &'a dyn CellDepResolver,
+    ) -> TracingCellDepResolver<'a> {
+        TracingCellDepResolver {
+            inner,
+            tracer: self,
+        }
+    }
+
+    pub fn header_dep_resolver<'a>(
+        &'a self,
+        inner: &'a dyn HeaderDepResolver,
+    ) -> TracingHeaderDepResolver<'a> {
+        TracingHeaderDepResolver {
+            inner,
+            tracer: self,
+        }
+    }
+
+    pub fn tx_dep_provider<'a>(
+        &'a self,
+        inner: &'a dyn TransactionDependencyProvider,
+    ) -> TracingTxDepProvider<'a> {
+        TracingTxDepProvider {
+            inner,
+            tracer: self,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum CollectorChange {
+    Locked(OutPoint, u64),
+    Applied(Transaction, u64),
+    Reset,
+}
+
+/// Record the answers of a cloned cell collector, the clones of the tracing
+/// collector share the trace.
+#[derive(Clone)]
+pub struct TracingCellCollector<'a> {
+    inner: Box<dyn CellCollector + 'a>,
+    trace: Arc<Mutex<BuildTrace>>,
+    changes: Vec<CollectorChange>,
+}
+
+impl<'a> TracingCellCollector<'a> {
+    fn record(&self, event: TraceEvent) {
+        self.trace.lock().events.push(event);
+    }
+
+    /// Make the changes of the build to `target`, the collector the traced
+    /// one was cloned from: the locked cells, the applied transactions and
+    /// the resets are made again, then the inputs of `tx`, the built
+    /// transaction, are locked at `tip_block_number`. The queries are not run
+    /// again, `target` may not offer the same cells as the traced clone.
+    pub fn apply_changes_to(
+        &self,
+        tx: &TransactionView,
+        tip_block_number: u64,
+        target: &mut dyn CellCollector,
+    ) -> Result<(), CellCollectorError> {
+        for change in &self.changes {
+            match change.clone() {
+                CollectorChange::Locked(out_point, tip_block_number) => {
+                    target.lock_cell(out_point, tip_block_number)?
+                }
+                CollectorChange::Applied(tx, tip_block_number) => {
+                    target.apply_tx(tx, tip_block_number)?
+                }
+                CollectorChange::Reset => target.reset(),
+            }
+        }
+        for out_point in tx.input_pts_iter() {
+            target.lock_cell(out_point, tip_block_number)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CellCollector for TracingCellCollector<'a> {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let (cells, total_capacity) = self.inner.collect_live_cells(query, apply_changes)?;
+        self.record(TraceEvent::CellsOffered {
+            primary_script: query.primary_script.clone().into(),
+            min_total_capacity: query.min_total_capacity,
+            apply_changes,
+            cells: cells.iter().map(TracedCell::from).collect(),
+        });
+        Ok((cells, total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        out_point: OutPoint,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.record(TraceEvent::CellLocked {
+            out_point: out_point.clone().into(),
+        });
+        self.inner.lock_cell(out_point.clone(), tip_block_number)?;
+        self.changes
+            .push(CollectorChange::Locked(out_point, tip_block_number));
+        Ok(())
+    }
+
+    fn apply_tx(
+        &mut self,
+        tx: Transaction,
+        tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        self.record(TraceEvent::TxApplied {
+            tx_hash: tx.calc_tx_hash().unpack(),
+        });
+        self.inner.apply_tx(tx.clone(), tip_block_number)?;
+        self.changes
+            .push(CollectorChange::Applied(tx, tip_block_number));
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.changes.push(CollectorChange::Reset);
+    }
+}
+
+pub struct TracingCellDepResolver<'a> {
+    inner: &'a dyn CellDepResolver,
+    tracer: &'a BuildTracer,
+}
+
+impl<'a> CellDepResolver for TracingCellDepResolver<'a> {
+    fn resolve(&self, script: &Script) -> Option<CellDep> {
+        let cell_dep = self.inner.resolve(script);
+        self.tracer.record(TraceEvent::CellDepResolved {
+            script: script.clone().into(),
+            cell_dep: cell_dep.clone().map(Into::into),
+        });
+        cell_dep
+    }
+}
+
+pub struct TracingHeaderDepResolver<'a> {
+    inner: &'a dyn HeaderDepResolver,
+    tracer: &'a BuildTracer,
+}
+
+impl<'a> HeaderDepResolver for TracingHeaderDepResolver<'a> {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        let header = self.inner.resolve_by_tx(tx_hash)?;
+        self.tracer.record(TraceEvent::HeaderResolvedByTx {
+            tx_hash: tx_hash.unpack(),
+            header: header.clone().map(Into::into),
+        });
+        Ok(header)
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        let header = self.inner.resolve_by_number(number)?;
+        self.tracer.record(TraceEvent::HeaderResolvedByNumber {
+            number,
+            header: header.clone().map(Into::into),
+        });
+        Ok(header)
+    }
+}
+
+pub struct TracingTxDepProvider<'a> {
+    inner: &'a dyn TransactionDependencyProvider,
+    tracer: &'a BuildTracer,
+}
+
+impl<'a> TransactionDependencyProvider for TracingTxDepProvider<'a> {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        let tx = self.inner.get_transaction(tx_hash)?;
+        self.tracer
+            .record(TraceEvent::TransactionFetched { tx: json_tx(&tx) });
+        Ok(tx)
+    }
+
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        let output = self.inner.get_cell(out_point)?;
+        self.tracer.record(TraceEvent::CellFetched {
+            out_point: out_point.clone().into(),
+            output: output.clone().into(),
+        });
+        Ok(output)
+    }
+
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        let data = self.inner.get_cell_data(out_point)?;
+        self.tracer.record(TraceEvent::CellDataFetched {
+            out_point: out_point.clone().into(),
+            data: json_types::JsonBytes::from_bytes(data.clone()),
+        });
+        Ok(data)
+    }
+
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        let header = self.inner.get_header(block_hash)?;
+        self.tracer.record(TraceEvent::HeaderFetched {
+            header: header.clone().into(),
+        });
+        Ok(header)
+    }
+
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        let extension = self.inner.get_block_extension(block_hash)?;
+        self.tracer.record(TraceEvent::BlockExtensionFetched {
+            block_hash: block_hash.unpack(),
+            extension: extension
+                .clone()
+                .map(|data| json_types::JsonBytes::from_bytes(data.raw_data())),
+        });
+        Ok(extension)
+    }
+
+    fn get_cells_with_data(
+        &self,
+        out_points: &[OutPoint],
+    ) -> Result<Vec<(CellOutput, Bytes)>, TransactionDependencyError> {
+        let cells = self.inner.get_cells_with_data(out_points)?;
+        for (out_point, (output, data)) in out_points.iter().zip(cells.iter()) {
+            self.tracer.record(TraceEvent::CellFetched {
+                out_point: out_point.clone().into(),
+                output: output.clone().into(),
+            });
+            self.tracer.record(TraceEvent::CellDataFetched {
+                out_point: out_point.clone().into(),
+                data: json_types::JsonBytes::from_bytes(data.clone()),
+            });
+        }
+        Ok(cells)
+    }
+}
+
+/// Answer the cell queries of a replayed build with the recorded cells
+#[derive(Clone)]
+pub struct ReplayCellCollector {
+    offered: Vec<(Script, bool, Vec<LiveCell>)>,
+    next: usize,
+}
+
+impl CellCollector for ReplayCellCollector {
+    fn collect_live_cells(
+        &mut self,
+        query: &CellQueryOptions,
+        apply_changes: bool,
+    ) -> Result<(Vec<LiveCell>, u64), CellCollectorError> {
+        let (primary_script, recorded_apply_changes, cells) =
+            self.offered.get(self.next).ok_or_else(|| {
+                CellCollectorError::Other(anyhow!(
+                    "replay diverged: query #{} was not recorded",
+                    self.next
+                ))
+            })?;
+        if primary_script != &query.primary_script || *recorded_apply_changes != apply_changes {
+            return Err(CellCollectorError::Other(anyhow!(
+                "replay diverged: query #{} differs from the recorded one",
+                self.next
+            )));
+        }
+        self.next += 1;
+        let total_capacity = cells
+            .iter()
+            .map(|cell| Unpack::<u64>::unpack(&cell.output.capacity()))
+            .sum();
+        Ok((cells.clone(), total_capacity))
+    }
+
+    fn lock_cell(
+        &mut self,
+        _out_point: OutPoint,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        Ok(())
+    }
+
+    fn apply_tx(
+        &mut self,
+        _tx: Transaction,
+        _tip_block_number: u64,
+    ) -> Result<(), CellCollectorError> {
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.next = 0;
+    }
+}
+
+/// Serve the recorded cell deps, headers, transactions and cells of a trace
+#[derive(Default, Clone)]
+pub struct ReplayContext {
+    cell_deps: HashMap<H256, Option<CellDep>>,
+    headers_by_tx: HashMap<H256, Option<HeaderView>>,
+    headers_by_number: HashMap<u64, Option<HeaderView>>,
+    transactions: HashMap<H256, TransactionView>,
+    cells: HashMap<(H256, u32), CellOutput>,
+    cells_data: HashMap<(H256, u32), Bytes>,
+    headers: HashMap<H256, HeaderView>,
+    extensions: HashMap<H256, Option<ckb_types::packed::Bytes>>,
+}
+
+fn not_recorded(what: String) -> TransactionDependencyError {
+    TransactionDependencyError::NotFound(format!("{} was not recorded", what))
+}
+
+impl CellDepResolver for ReplayContext {
+    fn resolve(&self, script: &Script) -> Option<CellDep> {
+        self.cell_deps
+            .get(&script.calc_script_hash().unpack())
+            .cloned()
+            .flatten()
+    }
+}
+
+impl HeaderDepResolver for ReplayContext {
+    fn resolve_by_tx(&self, tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.headers_by_tx
+            .get(&tx_hash.unpack())
+            .cloned()
+            .ok_or_else(|| anyhow!("header of transaction {} was not recorded", tx_hash))
+    }
+
+    fn resolve_by_number(&self, number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+        self.headers_by_number
+            .get(&number)
+            .cloned()
+            .ok_or_else(|| anyhow!("header of block {} was not recorded", number))
+    }
+}
+
+impl TransactionDependencyProvider for ReplayContext {
+    fn get_transaction(
+        &self,
+        tx_hash: &Byte32,
+    ) -> Result<TransactionView, TransactionDependencyError> {
+        self.transactions
+            .get(&tx_hash.unpack())
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("transaction {}", tx_hash)))
+    }
+
+    fn get_cell(&self, out_point: &OutPoint) -> Result<CellOutput, TransactionDependencyError> {
+        self.cells
+            .get(&out_point_key(out_point))
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("cell {}", out_point)))
+    }
+
+    fn get_cell_data(&self, out_point: &OutPoint) -> Result<Bytes, TransactionDependencyError> {
+        self.cells_data
+            .get(&out_point_key(out_point))
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("cell data {}", out_point)))
+    }
+
+    fn get_header(&self, block_hash: &Byte32) -> Result<HeaderView, TransactionDependencyError> {
+        self.headers
+            .get(&block_hash.unpack())
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("header {}", block_hash)))
+    }
+
+    fn get_block_extension(
+        &self,
+        block_hash: &Byte32,
+    ) -> Result<Option<ckb_types::packed::Bytes>, TransactionDependencyError> {
+        self.extensions
+            .get(&block_hash.unpack())
+            .cloned()
+            .ok_or_else(|| not_recorded(format!("block extension {}", block_hash)))
+    }
+}
@@ -2,12 +2,12 @@ use std::collections::HashSet;
 
 use ckb_types::{
     bytes::Bytes,
-    core::{TransactionBuilder, TransactionView},
+    core::{Capacity, TransactionBuilder, TransactionView},
     packed::CellOutput,
     prelude::*,
 };
 
-use super::{TxBuilder, TxBuilderError};
+use super::{check_type_id_collision, TxBuilder, TxBuilderError};
 use crate::traits::{
     CellCollector, CellDepResolver, HeaderDepResolver, TransactionDependencyProvider,
 };
@@ -15,20 +15,34 @@ use crate::types::ScriptId;
 
 /// A builder to build a transaction simply transfer capcity to an address. It
 /// will resolve the type script's cell_dep if given.
+#[derive(Debug, Clone)]
 pub struct CapacityTransferBuilder {
     pub outputs: Vec<(CellOutput, Bytes)>,
+    /// Raise the capacity of the outputs below their occupied capacity to
+    /// the minimum, the sender pays the difference
+    pub top_up_min_capacity: bool,
 }
 
 impl CapacityTransferBuilder {
     pub fn new(outputs: Vec<(CellOutput, Bytes)>) -> CapacityTransferBuilder {
-        CapacityTransferBuilder { outputs }
+        CapacityTransferBuilder {
+            outputs,
+            top_up_min_capacity: false,
+        }
+    }
+
+    /// Top up the outputs to their occupied capacity, e.g. a first transfer
+    /// to an omnilock with long args
+    pub fn with_min_capacity_top_up(mut self) -> Self {
+        self.top_up_min_capacity = true;
+        self
     }
 }
 
 impl TxBuilder for CapacityTransferBuilder {
     fn build_base(
         &self,
-        _cell_collector: &mut dyn CellCollector,
+        cell_collector: &mut dyn CellCollector,
         cell_dep_resolver: &dyn CellDepResolver,
         _header_dep_resolver: &dyn HeaderDepResolver,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
@@ -38,11 +52,21 @@ impl TxBuilder for CapacityTransferBuilder {
         let mut outputs = Vec::new();
         let mut outputs_data = Vec::new();
         for (output, output_data) in &self.outputs {
+            let mut output = output.clone();
+            if self.top_up_min_capacity {
+                let occupied = output.occupied_capacity(Capacity::bytes(output_data.len())?)?;
+                let capacity: Capacity = output.capacity().unpack();
+                if capacity < occupied {
+                    output = output.as_builder().capacity(occupied.pack()).build();
+                }
+            }
             outputs.push(output.clone());
             outputs_data.push(output_data.pack());
             if let Some(type_script) = output.type_().to_opt() {
                 let script_id = ScriptId::from(&type_script);
-                if !script_id.is_type_id() {
+                if script_id.is_type_id() {
+                    check_type_id_collision(&type_script, cell_collector)?;
+                } else {
                     let cell_dep = cell_dep_resolver
                         .resolve(&type_script)
                         .ok_or(TxBuilderError::ResolveCellDepFailed(type_script))?;
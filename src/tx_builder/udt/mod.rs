@@ -7,14 +7,17 @@ use ckb_types::{
     packed::{Byte32, CellDep, CellInput, CellOutput, Script},
     prelude::*,
 };
-use std::collections::HashSet;
 
-use super::{TransferAction, TxBuilder, TxBuilderError};
+use super::{
+    acp::{find_acp_cell, AcpCellSelectStrategy},
+    resolve_cell_deps, TransferAction, TxBuilder, TxBuilderError,
+};
 use crate::traits::{
     CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
     TransactionDependencyProvider, ValueRangeOption,
 };
 use crate::types::ScriptId;
+use crate::util::parse_udt_amount;
 
 /// The udt type
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
@@ -60,8 +63,19 @@ pub struct UdtTargetReceiver {
 
     /// Only for <xudt data> and only used when action == TransferAction::Create
     pub extra_data: Option<Bytes>,
+
+    /// Only used when action == TransferAction::Update. If set, the receiver
+    /// cell is located by [`find_acp_cell`] with `lock_script.args` as the args
+    /// prefix, otherwise the first cell matching `lock_script` is used.
+    pub acp_cell_strategy: Option<AcpCellSelectStrategy>,
+
+    /// The current tip block number, the cell located by `acp_cell_strategy`
+    /// is locked in the cell collector at it, so the lock expires like the
+    /// locks of the collected cells.
+    pub tip_block_number: u64,
 }
 
+#[derive(Debug, Clone)]
 pub struct ReceiverBuildOutput {
     pub input: Option<(CellInput, CellDep)>,
     pub output: CellOutput,
@@ -76,15 +90,49 @@ impl UdtTargetReceiver {
             capacity: None,
             amount,
             extra_data: None,
+            acp_cell_strategy: None,
+            tip_block_number: 0,
         }
     }
 
+    /// Locate the receiver cell by [`find_acp_cell`] with `strategy`, the
+    /// picked cell is locked at `tip_block_number`
+    pub fn with_acp_cell(mut self, strategy: AcpCellSelectStrategy, tip_block_number: u64) -> Self {
+        self.acp_cell_strategy = Some(strategy);
+        self.tip_block_number = tip_block_number;
+        self
+    }
+
     pub fn build(
         &self,
         type_script: &Script,
         cell_collector: &mut dyn CellCollector,
         cell_dep_resolver: &dyn CellDepResolver,
     ) -> Result<ReceiverBuildOutput, TxBuilderError> {
+        let (input, output, output_data) = self.build_unresolved(type_script, cell_collector)?;
+        let input = input
+            .map(|(input, lock)| {
+                cell_dep_resolver
+                    .resolve(&lock)
+                    .map(|cell_dep| (input, cell_dep))
+                    .ok_or(TxBuilderError::ResolveCellDepFailed(lock))
+            })
+            .transpose()?;
+        Ok(ReceiverBuildOutput {
+            input,
+            output,
+            output_data,
+        })
+    }
+
+    /// Build the receiver without resolving the cell dep, the lock script of
+    /// the input (if any) is returned instead so the builders can resolve all
+    /// the cell deps in one batch.
+    fn build_unresolved(
+        &self,
+        type_script: &Script,
+        cell_collector: &mut dyn CellCollector,
+    ) -> Result<(Option<(CellInput, Script)>, CellOutput, Bytes), TxBuilderError> {
         match self.action {
             TransferAction::Create => {
                 let data_len = self
@@ -124,56 +172,60 @@ impl UdtTargetReceiver {
                     .as_builder()
                     .capacity(final_capacity.pack())
                     .build();
-                Ok(ReceiverBuildOutput {
-                    input: None,
-                    output,
-                    output_data: data.freeze(),
-                })
+                Ok((None, output, data.freeze()))
             }
             TransferAction::Update => {
-                let receiver_query = {
-                    let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
-                    query.secondary_script = Some(type_script.clone());
-                    query.data_len_range = Some(ValueRangeOption::new_min(16));
-                    query
+                let receiver_cell = if let Some(strategy) = self.acp_cell_strategy {
+                    let cell = find_acp_cell(
+                        cell_collector,
+                        &ScriptId::from(&self.lock_script),
+                        &self.lock_script.args().raw_data(),
+                        type_script,
+                        strategy,
+                    )?;
+                    // found without applying changes, don't pick it for another receiver
+                    cell_collector.lock_cell(cell.out_point.clone(), self.tip_block_number)?;
+                    cell
+                } else {
+                    let receiver_query = {
+                        let mut query = CellQueryOptions::new_lock(self.lock_script.clone());
+                        query.secondary_script = Some(type_script.clone());
+                        query.data_len_range = Some(ValueRangeOption::new_min(16));
+                        query
+                    };
+                    let (mut receiver_cells, _) =
+                        cell_collector.collect_live_cells(&receiver_query, true)?;
+                    if receiver_cells.is_empty() {
+                        return Err(TxBuilderError::Other(anyhow!(
+                            "update receiver cell failed, cell not found, lock={:?}",
+                            self.lock_script
+                        )));
+                    }
+                    receiver_cells.remove(0)
                 };
-                let (receiver_cells, _) =
-                    cell_collector.collect_live_cells(&receiver_query, true)?;
-                if receiver_cells.is_empty() {
-                    return Err(TxBuilderError::Other(anyhow!(
-                        "update receiver cell failed, cell not found, lock={:?}",
-                        self.lock_script
-                    )));
-                }
 
-                let receiver_cell_dep =
-                    cell_dep_resolver
-                        .resolve(&self.lock_script)
-                        .ok_or_else(|| {
-                            TxBuilderError::ResolveCellDepFailed(self.lock_script.clone())
-                        })?;
-
-                let mut amount_bytes = [0u8; 16];
-                let receiver_cell = &receiver_cells[0];
-                amount_bytes.copy_from_slice(&receiver_cell.output_data.as_ref()[0..16]);
-                let old_amount = u128::from_le_bytes(amount_bytes);
-                let new_amount = old_amount + self.amount;
+                let old_amount = parse_udt_amount(receiver_cell.output_data.as_ref())
+                    .map_err(|err| TxBuilderError::InvalidParameter(err.into()))?;
+                let new_amount = old_amount
+                    .checked_add(self.amount)
+                    .ok_or(TxBuilderError::AmountOverflow(old_amount, self.amount))?;
                 let mut new_data = receiver_cell.output_data.as_ref().to_vec();
                 new_data[0..16].copy_from_slice(&new_amount.to_le_bytes()[..]);
                 let output_data = Bytes::from(new_data);
 
                 let input = CellInput::new(receiver_cell.out_point.clone(), 0);
-                Ok(ReceiverBuildOutput {
-                    input: Some((input, receiver_cell_dep)),
-                    output: receiver_cell.output.clone(),
+                Ok((
+                    Some((input, receiver_cell.output.lock())),
+                    receiver_cell.output.clone(),
                     output_data,
-                })
+                ))
             }
         }
     }
 }
 
 /// The udt issue transaction builder
+#[derive(Debug, Clone)]
 pub struct UdtIssueBuilder {
     /// The udt type (sudt/xudt)
     pub udt_type: UdtType,
@@ -219,35 +271,24 @@ impl TxBuilder for UdtIssueBuilder {
             .udt_type
             .build_script(&self.script_id, &owner_lock_hash);
 
-        let owner_cell_dep = cell_dep_resolver
-            .resolve(&self.owner)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.owner.clone()))?;
-        let udt_cell_dep = cell_dep_resolver
-            .resolve(&type_script)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(type_script.clone()))?;
-        #[allow(clippy::mutable_key_type)]
-        let mut cell_deps = HashSet::new();
-        cell_deps.insert(owner_cell_dep);
-        cell_deps.insert(udt_cell_dep);
+        let mut cell_dep_scripts = vec![self.owner.clone(), type_script.clone()];
 
         // Build outputs, outputs_data, cell_deps
         let mut outputs = Vec::new();
         let mut outputs_data = Vec::new();
         for receiver in &self.receivers {
-            let ReceiverBuildOutput {
-                input,
-                output,
-                output_data,
-            } = receiver.build(&type_script, cell_collector, cell_dep_resolver)?;
-            if let Some((input, input_lock_cell_dep)) = input {
+            let (input, output, output_data) =
+                receiver.build_unresolved(&type_script, cell_collector)?;
+            if let Some((input, input_lock)) = input {
                 inputs.push(input);
-                cell_deps.insert(input_lock_cell_dep);
+                cell_dep_scripts.push(input_lock);
             }
             outputs.push(output);
             outputs_data.push(output_data.pack());
         }
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
         Ok(TransactionBuilder::default()
-            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_cell_deps(cell_deps)
             .set_inputs(inputs)
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
@@ -255,6 +296,7 @@ impl TxBuilder for UdtIssueBuilder {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct UdtTransferBuilder {
     /// The udt type script
     pub type_script: Script,
@@ -265,6 +307,46 @@ pub struct UdtTransferBuilder {
 
     /// The transfer receivers
     pub receivers: Vec<UdtTargetReceiver>,
+
+    /// If set, collect as many sender udt cells as needed and put the udt
+    /// change into a new cell locked by this script, with the minimal
+    /// capacity and the data of the first sender cell holding the change
+    /// amount, so the xUDT extension data is kept. The capacity of the sender
+    /// cells is left to the capacity balancer. Otherwise the only sender cell
+    /// keeps the change.
+    pub change_lock: Option<Script>,
+}
+
+impl UdtTransferBuilder {
+    /// Put the udt change into a new cell locked by `change_lock`
+    pub fn with_udt_change(mut self, change_lock: Script) -> Self {
+        self.change_lock = Some(change_lock);
+        self
+    }
+
+    fn build_udt_change(
+        &self,
+        change_lock: &Script,
+        amount: u128,
+        extension_data: &[u8],
+    ) -> (CellOutput, Bytes) {
+        let mut data = BytesMut::with_capacity(16 + extension_data.len());
+        data.put(&amount.to_le_bytes()[..]);
+        data.put(extension_data);
+        let data = data.freeze();
+        let output = CellOutput::new_builder()
+            .lock(change_lock.clone())
+            .type_(Some(self.type_script.clone()).pack())
+            .build();
+        let occupied_capacity = output
+            .occupied_capacity(Capacity::bytes(data.len()).unwrap())
+            .unwrap();
+        let output = output
+            .as_builder()
+            .capacity(occupied_capacity.pack())
+            .build();
+        (output, data)
+    }
 }
 
 impl TxBuilder for UdtTransferBuilder {
@@ -281,27 +363,42 @@ impl TxBuilder for UdtTransferBuilder {
             query.data_len_range = Some(ValueRangeOption::new_min(16));
             query
         };
-        let (sender_cells, _) = cell_collector.collect_live_cells(&sender_query, true)?;
+        let output_total = self.receivers.iter().try_fold(0u128, |total, receiver| {
+            total
+                .checked_add(receiver.amount)
+                .ok_or(TxBuilderError::AmountOverflow(total, receiver.amount))
+        })?;
+        // Without a change lock only one sender cell is used
+        let max_cells = if self.change_lock.is_some() {
+            usize::MAX
+        } else {
+            1
+        };
+        let mut sender_cells = Vec::new();
+        let mut input_total = 0u128;
+        loop {
+            let (cells, _) = cell_collector.collect_live_cells(&sender_query, true)?;
+            if cells.is_empty() {
+                break;
+            }
+            for cell in cells.into_iter().take(max_cells - sender_cells.len()) {
+                let amount = parse_udt_amount(cell.output_data.as_ref())
+                    .map_err(|err| TxBuilderError::InvalidParameter(err.into()))?;
+                input_total = input_total
+                    .checked_add(amount)
+                    .ok_or(TxBuilderError::AmountOverflow(input_total, amount))?;
+                sender_cells.push(cell);
+            }
+            if sender_cells.len() >= max_cells || input_total >= output_total {
+                break;
+            }
+        }
         if sender_cells.is_empty() {
             return Err(TxBuilderError::Other(anyhow!("sender cell not found")));
         }
-        let sender_cell = &sender_cells[0];
-
-        let sender_cell_dep = cell_dep_resolver
-            .resolve(&self.sender)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.sender.clone()))?;
-        let udt_cell_dep = cell_dep_resolver
-            .resolve(&self.type_script)
-            .ok_or_else(|| TxBuilderError::ResolveCellDepFailed(self.type_script.clone()))?;
-        #[allow(clippy::mutable_key_type)]
-        let mut cell_deps = HashSet::new();
-        cell_deps.insert(sender_cell_dep);
-        cell_deps.insert(udt_cell_dep);
-
-        let mut amount_bytes = [0u8; 16];
-        amount_bytes.copy_from_slice(&sender_cell.output_data.as_ref()[0..16]);
-        let input_total = u128::from_le_bytes(amount_bytes);
-        let output_total: u128 = self.receivers.iter().map(|receiver| receiver.amount).sum();
+
+        let mut cell_dep_scripts = vec![self.sender.clone(), self.type_script.clone()];
+
         if input_total < output_total {
             return Err(TxBuilderError::Other(anyhow!(
                 "sender udt amount not enough, expected at least: {}, actual: {}",
@@ -310,33 +407,46 @@ impl TxBuilder for UdtTransferBuilder {
             )));
         }
 
-        let sender_output_data = {
-            let new_amount = input_total - output_total;
+        let change_amount = input_total - output_total;
+        let mut inputs = sender_cells
+            .iter()
+            .map(|cell| CellInput::new(cell.out_point.clone(), 0))
+            .collect::<Vec<_>>();
+        let mut outputs = Vec::new();
+        let mut outputs_data = Vec::new();
+        if let Some(change_lock) = self.change_lock.as_ref() {
+            // No change cell for a zero change, nothing is burned
+            if change_amount > 0 {
+                let (output, output_data) = self.build_udt_change(
+                    change_lock,
+                    change_amount,
+                    &sender_cells[0].output_data[16..],
+                );
+                outputs.push(output);
+                outputs_data.push(output_data.pack());
+            }
+        } else {
+            let sender_cell = &sender_cells[0];
             let mut new_data = sender_cell.output_data.as_ref().to_vec();
-            new_data[0..16].copy_from_slice(&new_amount.to_le_bytes()[..]);
-            Bytes::from(new_data)
-        };
-
-        let mut inputs = vec![CellInput::new(sender_cell.out_point.clone(), 0)];
-        let mut outputs = vec![sender_cell.output.clone()];
-        let mut outputs_data = vec![sender_output_data.pack()];
+            new_data[0..16].copy_from_slice(&change_amount.to_le_bytes()[..]);
+            outputs.push(sender_cell.output.clone());
+            outputs_data.push(Bytes::from(new_data).pack());
+        }
 
         for receiver in &self.receivers {
-            let ReceiverBuildOutput {
-                input,
-                output,
-                output_data,
-            } = receiver.build(&self.type_script, cell_collector, cell_dep_resolver)?;
-            if let Some((input, input_lock_cell_dep)) = input {
+            let (input, output, output_data) =
+                receiver.build_unresolved(&self.type_script, cell_collector)?;
+            if let Some((input, input_lock)) = input {
                 inputs.push(input);
-                cell_deps.insert(input_lock_cell_dep);
+                cell_dep_scripts.push(input_lock);
             }
             outputs.push(output);
             outputs_data.push(output_data.pack());
         }
 
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &cell_dep_scripts)?;
         Ok(TransactionBuilder::default()
-            .set_cell_deps(cell_deps.into_iter().collect())
+            .set_cell_deps(cell_deps)
             .set_inputs(inputs)
             .set_outputs(outputs)
             .set_outputs_data(outputs_data)
@@ -0,0 +1,239 @@
+//! Vesting schedules locked by multisig-with-since args.
+//!
+//! A multisig lock with 28 bytes args (`hash160 | since`) can only be spent
+//! after the since in its args. [`VestingBuilder`] splits a balance into one
+//! output per tranche, each locked until the epoch of the tranche, and
+//! [`VestingClaimBuilder`] moves the matured tranches to a receiver.
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, EpochNumberWithFraction, TransactionBuilder, TransactionView},
+    packed::{CellInput, CellOutput, Script},
+    prelude::*,
+};
+
+use super::{resolve_cell_deps, CapacityBalancer, CapacityProvider, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver,
+    TransactionDependencyProvider, ValueRangeOption,
+};
+use crate::types::Since;
+use crate::unlock::MultisigConfig;
+
+/// The lock of a tranche which matures at `epoch_number`
+pub fn vesting_lock(config: &MultisigConfig, epoch_number: u64) -> Script {
+    Script::from(&config.to_address_payload(Some(epoch_number)))
+}
+
+/// Capacity released at an epoch
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VestingTranche {
+    pub epoch_number: u64,
+    pub capacity: u64,
+}
+
+impl VestingTranche {
+    pub fn new(epoch_number: u64, capacity: u64) -> VestingTranche {
+        VestingTranche {
+            epoch_number,
+            capacity,
+        }
+    }
+}
+
+/// Lock a balance into tranches, the inputs and the fee come from the
+/// balancer.
+#[derive(Debug, Clone)]
+pub struct VestingBuilder {
+    pub config: MultisigConfig,
+    pub tranches: Vec<VestingTranche>,
+}
+
+impl VestingBuilder {
+    pub fn new(config: MultisigConfig, tranches: Vec<VestingTranche>) -> VestingBuilder {
+        VestingBuilder { config, tranches }
+    }
+
+    /// Split `total_capacity` into `count` tranches released every
+    /// `interval_epochs` from `start_epoch`, the last tranche gets the
+    /// remainder.
+    pub fn new_even(
+        config: MultisigConfig,
+        total_capacity: u64,
+        start_epoch: u64,
+        interval_epochs: u64,
+        count: u64,
+    ) -> Result<VestingBuilder, TxBuilderError> {
+        if count == 0 {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "vesting tranches count must be greater than 0"
+            )));
+        }
+        let capacity = total_capacity / count;
+        let tranches = (0..count)
+            .map(|idx| {
+                let epoch_number = start_epoch + idx * interval_epochs;
+                if idx + 1 == count {
+                    VestingTranche::new(epoch_number, total_capacity - capacity * idx)
+                } else {
+                    VestingTranche::new(epoch_number, capacity)
+                }
+            })
+            .collect();
+        Ok(VestingBuilder::new(config, tranches))
+    }
+
+    /// The epochs of the tranches, needed to claim them later
+    pub fn epochs(&self) -> Vec<u64> {
+        self.tranches
+            .iter()
+            .map(|tranche| tranche.epoch_number)
+            .collect()
+    }
+}
+
+impl TxBuilder for VestingBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        _cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        if self.tranches.is_empty() {
+            return Err(TxBuilderError::InvalidParameter(anyhow!(
+                "empty vesting tranches"
+            )));
+        }
+        let mut outputs = Vec::new();
+        for tranche in &self.tranches {
+            let output = CellOutput::new_builder()
+                .lock(vesting_lock(&self.config, tranche.epoch_number))
+                .capacity(tranche.capacity.pack())
+                .build();
+            let occupied = output.occupied_capacity(Capacity::zero())?;
+            if tranche.capacity < occupied.as_u64() {
+                return Err(TxBuilderError::InvalidParameter(anyhow!(
+                    "the capacity of the tranche at epoch {} is less than {}",
+                    tranche.epoch_number,
+                    occupied
+                )));
+            }
+            outputs.push(output);
+        }
+        Ok(TransactionBuilder::default()
+            .set_outputs_data(vec![Bytes::new().pack(); outputs.len()])
+            .set_outputs(outputs)
+            .build())
+    }
+}
+
+/// Claim the tranches matured at `tip_epoch`.
+///
+/// Like [`SweepBuilder`](super::sweep::SweepBuilder), the base transaction has
+/// no outputs, balance it with [`VestingClaimBuilder::balancer`] so everything
+/// minus the fee goes to `receiver`.
+#[derive(Debug, Clone)]
+pub struct VestingClaimBuilder {
+    pub config: MultisigConfig,
+    /// The epochs of the tranches, see [`VestingBuilder::epochs`]
+    pub epochs: Vec<u64>,
+    pub tip_epoch: EpochNumberWithFraction,
+    pub receiver: Script,
+}
+
+impl VestingClaimBuilder {
+    pub fn new(
+        config: MultisigConfig,
+        epochs: Vec<u64>,
+        tip_epoch: EpochNumberWithFraction,
+        receiver: Script,
+    ) -> VestingClaimBuilder {
+        VestingClaimBuilder {
+            config,
+            epochs,
+            tip_epoch,
+            receiver,
+        }
+    }
+
+    /// The epochs of the tranches spendable at `tip_epoch`
+    pub fn matured_epochs(&self) -> Vec<u64> {
+        let mut epochs = self
+            .epochs
+            .iter()
+            .copied()
+            .filter(|epoch_number| *epoch_number <= self.tip_epoch.number())
+            .collect::<Vec<_>>();
+        epochs.sort_unstable();
+        epochs.dedup();
+        epochs
+    }
+
+    pub fn balancer(&self, fee_rate: u64) -> CapacityBalancer {
+        let placeholder_witness = self.config.placeholder_witness();
+        let lock_scripts = self
+            .matured_epochs()
+            .into_iter()
+            .map(|epoch_number| {
+                (
+                    vesting_lock(&self.config, epoch_number),
+                    placeholder_witness.clone(),
+                )
+            })
+            .collect();
+        let mut balancer = CapacityBalancer::new_with_provider(
+            fee_rate,
+            CapacityProvider::new_simple(lock_scripts),
+        );
+        balancer.change_lock_script = Some(self.receiver.clone());
+        balancer
+    }
+}
+
+impl TxBuilder for VestingClaimBuilder {
+    fn build_base(
+        &self,
+        cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let placeholder_witness = self.config.placeholder_witness();
+        let mut inputs = Vec::new();
+        let mut witnesses = Vec::new();
+        let mut lock_script = None;
+        for epoch_number in self.matured_epochs() {
+            let lock = vesting_lock(&self.config, epoch_number);
+            let mut query = CellQueryOptions::new_lock(lock.clone());
+            query.secondary_script_len_range = Some(ValueRangeOption::new_exact(0));
+            query.data_len_range = Some(ValueRangeOption::new_exact(0));
+            query.min_total_capacity = u64::MAX;
+            let (cells, _) = cell_collector.collect_live_cells(&query, true)?;
+            let since = Since::new_absolute_epoch(epoch_number).value();
+            for (idx, cell) in cells.iter().enumerate() {
+                inputs.push(CellInput::new(cell.out_point.clone(), since));
+                // each tranche lock is a script group
+                let witness = if idx == 0 {
+                    placeholder_witness.as_bytes()
+                } else {
+                    Bytes::new()
+                };
+                witnesses.push(witness.pack());
+            }
+            if !cells.is_empty() {
+                lock_script = Some(lock);
+            }
+        }
+        let lock_script = lock_script
+            .ok_or_else(|| TxBuilderError::Other(anyhow!("no matured vesting tranches")))?;
+        // all the tranche locks share the multisig cell dep
+        let cell_deps = resolve_cell_deps(cell_dep_resolver, &[lock_script])?;
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .set_inputs(inputs)
+            .set_witnesses(witnesses)
+            .build())
+    }
+}
@@ -0,0 +1,472 @@
+//! Cell based voting for DAOs.
+//!
+//! A vote is a cell typed by the vote script of a [`VoteScheme`] whose args
+//! is the type script hash of the proposal cell, its data is the encoded
+//! [`Vote`] in the [`VoteLayout`] of the scheme. [`VoteBuilder`] casts a
+//! vote, [`VoteAmendBuilder`] changes it and [`tally_votes`] counts the live
+//! vote cells of a proposal, weighted by their capacity.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::{BufMut, Bytes, BytesMut},
+    core::{Capacity, DepType, TransactionBuilder, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+    H256,
+};
+
+use super::{resolve_cell_deps, TxBuilder, TxBuilderError};
+use crate::traits::{
+    CellCollector, CellDepResolver, CellQueryOptions, HeaderDepResolver, LiveCell,
+    TransactionDependencyProvider,
+};
+use crate::types::ScriptId;
+
+/// A vote for a proposal
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Vote {
+    /// The type script hash of the proposal cell
+    pub proposal: H256,
+    pub choice: u32,
+    pub memo: Bytes,
+}
+
+/// How a [`Vote`] is encoded in the cell data
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum VoteLayout {
+    /// `proposal (32 bytes) | choice (u32 le) | memo`
+    Compact,
+    /// The molecule table
+    /// `table Vote { proposal: Byte32, choice: Uint32, memo: Bytes }`,
+    /// extra fields are ignored when decoding
+    Molecule,
+}
+
+const COMPACT_HEADER_LEN: usize = 32 + 4;
+const MOLECULE_FIELDS_COUNT: usize = 3;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+impl VoteLayout {
+    pub fn encode(&self, vote: &Vote) -> Bytes {
+        match self {
+            VoteLayout::Compact => {
+                let mut data = BytesMut::with_capacity(COMPACT_HEADER_LEN + vote.memo.len());
+                data.put_slice(vote.proposal.as_bytes());
+                data.put_u32_le(vote.choice);
+                data.put_slice(&vote.memo);
+                data.freeze()
+            }
+            VoteLayout::Molecule => {
+                let header_len = 4 * (1 + MOLECULE_FIELDS_COUNT);
+                let fields_len = [32, 4, 4 + vote.memo.len()];
+                let total_len = header_len + fields_len.iter().sum::<usize>();
+                let mut data = BytesMut::with_capacity(total_len);
+                data.put_u32_le(total_len as u32);
+                let mut offset = header_len;
+                for field_len in fields_len {
+                    data.put_u32_le(offset as u32);
+                    offset += field_len;
+                }
+                data.put_slice(vote.proposal.as_bytes());
+                data.put_u32_le(vote.choice);
+                data.put_u32_le(vote.memo.len() as u32);
+                data.put_slice(&vote.memo);
+                data.freeze()
+            }
+        }
+    }
+
+    /// `None` when `data` is not a vote in this layout
+    pub fn decode(&self, data: &[u8]) -> Option<Vote> {
+        match self {
+            VoteLayout::Compact => {
+                if data.len() < COMPACT_HEADER_LEN {
+                    return None;
+                }
+                Some(Vote {
+                    proposal: H256::from_slice(&data[..32]).ok()?,
+                    choice: read_u32(data, 32)?,
+                    memo: Bytes::from(data[COMPACT_HEADER_LEN..].to_vec()),
+                })
+            }
+            VoteLayout::Molecule => {
+                if read_u32(data, 0)? as usize != data.len() {
+                    return None;
+                }
+                let header_len = read_u32(data, 4)? as usize;
+                if header_len % 4 != 0 || header_len < 4 * (1 + MOLECULE_FIELDS_COUNT) {
+                    return None;
+                }
+                let mut offsets = (1..header_len / 4)
+                    .map(|idx| read_u32(data, idx * 4).map(|offset| offset as usize))
+                    .collect::<Option<Vec<_>>>()?;
+                offsets.push(data.len());
+                if offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+                    return None;
+                }
+                let field = |idx: usize| &data[offsets[idx]..offsets[idx + 1]];
+                let (proposal, choice, memo) = (field(0), field(1), field(2));
+                if proposal.len() != 32
+                    || choice.len() != 4
+                    || read_u32(memo, 0)? as usize + 4 != memo.len()
+                {
+                    return None;
+                }
+                Some(Vote {
+                    proposal: H256::from_slice(proposal).ok()?,
+                    choice: read_u32(choice, 0)?,
+                    memo: Bytes::from(memo[4..].to_vec()),
+                })
+            }
+        }
+    }
+}
+
+/// A voting scheme: the vote type script and the layout of the votes
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct VoteScheme {
+    pub vote_script_id: ScriptId,
+    pub layout: VoteLayout,
+}
+
+impl VoteScheme {
+    pub fn new(vote_script_id: ScriptId, layout: VoteLayout) -> VoteScheme {
+        VoteScheme {
+            vote_script_id,
+            layout,
+        }
+    }
+
+    /// The type script of the vote cells of the proposal typed by
+    /// `proposal_type_script`
+    pub fn vote_type_script(&self, proposal_type_script: &Script) -> Script {
+        let proposal_hash = proposal_type_script.calc_script_hash();
+        Script::new_builder()
+            .code_hash(self.vote_script_id.code_hash.pack())
+            .hash_type(self.vote_script_id.hash_type.into())
+            .args(proposal_hash.as_bytes().pack())
+            .build()
+    }
+
+    /// The vote of a vote cell of this scheme, `None` for other cells
+    pub fn parse_vote(&self, output: &CellOutput, data: &[u8]) -> Option<Vote> {
+        let type_script = output.type_().to_opt()?;
+        if ScriptId::from(&type_script) != self.vote_script_id {
+            return None;
+        }
+        let vote = self.layout.decode(data)?;
+        if type_script.args().raw_data().as_ref() != vote.proposal.as_bytes() {
+            return None;
+        }
+        Some(vote)
+    }
+}
+
+fn proposal_cell_dep(out_point: &OutPoint) -> CellDep {
+    CellDep::new_builder()
+        .out_point(out_point.clone())
+        .dep_type(DepType::Code.into())
+        .build()
+}
+
+/// The vote cell with the minimal capacity if `capacity` is `None`
+fn vote_output(
+    lock: Script,
+    type_script: Script,
+    data: &Bytes,
+    capacity: Option<u64>,
+) -> Result<CellOutput, TxBuilderError> {
+    let output = CellOutput::new_builder()
+        .lock(lock)
+        .type_(Some(type_script).pack())
+        .build();
+    let occupied = output
+        .occupied_capacity(Capacity::bytes(data.len())?)?
+        .as_u64();
+    let capacity = capacity.unwrap_or(occupied);
+    if capacity < occupied {
+        return Err(TxBuilderError::InvalidParameter(anyhow!(
+            "vote cell capacity {} is less than {}",
+            capacity,
+            occupied
+        )));
+    }
+    Ok(output.as_builder().capacity(capacity.pack()).build())
+}
+
+/// Cast a vote, the voter pays the vote cell and the fee with the balancer.
+///
+/// The proposal cell, if any, is added as a cell dep for the vote script to
+/// check the proposal.
+#[derive(Debug, Clone)]
+pub struct VoteBuilder {
+    pub scheme: VoteScheme,
+    pub proposal_type_script: Script,
+    pub proposal_cell: Option<OutPoint>,
+    /// The lock of the vote cell
+    pub voter: Script,
+    pub choice: u32,
+    pub memo: Bytes,
+    /// The capacity of the vote cell, the vote weight in [`tally_votes`].
+    /// `None` is the minimal capacity.
+    pub capacity: Option<u64>,
+}
+
+impl VoteBuilder {
+    pub fn new(
+        scheme: VoteScheme,
+        proposal_type_script: Script,
+        voter: Script,
+        choice: u32,
+    ) -> VoteBuilder {
+        VoteBuilder {
+            scheme,
+            proposal_type_script,
+            proposal_cell: None,
+            voter,
+            choice,
+            memo: Bytes::new(),
+            capacity: None,
+        }
+    }
+
+    pub fn proposal_cell(mut self, out_point: OutPoint) -> Self {
+        self.proposal_cell = Some(out_point);
+        self
+    }
+
+    pub fn memo(mut self, memo: Bytes) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    pub fn capacity(mut self, capacity: u64) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn vote(&self) -> Vote {
+        Vote {
+            proposal: self.proposal_type_script.calc_script_hash().unpack(),
+            choice: self.choice,
+            memo: self.memo.clone(),
+        }
+    }
+}
+
+impl TxBuilder for VoteBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let type_script = self.scheme.vote_type_script(&self.proposal_type_script);
+        let data = self.scheme.layout.encode(&self.vote());
+        let output = vote_output(
+            self.voter.clone(),
+            type_script.clone(),
+            &data,
+            self.capacity,
+        )?;
+        let mut cell_deps = resolve_cell_deps(cell_dep_resolver, &[type_script])?;
+        cell_deps.extend(self.proposal_cell.as_ref().map(proposal_cell_dep));
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .output(output)
+            .output_data(data.pack())
+            .build())
+    }
+}
+
+/// Change the choice and the memo of a vote cell, the capacity and the lock
+/// are kept. Balance it with the lock of the voter.
+#[derive(Debug, Clone)]
+pub struct VoteAmendBuilder {
+    pub scheme: VoteScheme,
+    pub vote_cell: OutPoint,
+    pub proposal_cell: Option<OutPoint>,
+    pub choice: u32,
+    pub memo: Bytes,
+}
+
+impl VoteAmendBuilder {
+    pub fn new(scheme: VoteScheme, vote_cell: OutPoint, choice: u32) -> VoteAmendBuilder {
+        VoteAmendBuilder {
+            scheme,
+            vote_cell,
+            proposal_cell: None,
+            choice,
+            memo: Bytes::new(),
+        }
+    }
+
+    pub fn proposal_cell(mut self, out_point: OutPoint) -> Self {
+        self.proposal_cell = Some(out_point);
+        self
+    }
+
+    pub fn memo(mut self, memo: Bytes) -> Self {
+        self.memo = memo;
+        self
+    }
+}
+
+impl TxBuilder for VoteAmendBuilder {
+    fn build_base(
+        &self,
+        _cell_collector: &mut dyn CellCollector,
+        cell_dep_resolver: &dyn CellDepResolver,
+        _header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, TxBuilderError> {
+        let output = tx_dep_provider.get_cell(&self.vote_cell)?;
+        let data = tx_dep_provider.get_cell_data(&self.vote_cell)?;
+        let vote = self.scheme.parse_vote(&output, &data).ok_or_else(|| {
+            TxBuilderError::InvalidParameter(anyhow!(
+                "cell {} is not a vote of the scheme",
+                self.vote_cell
+            ))
+        })?;
+        let data = self.scheme.layout.encode(&Vote {
+            choice: self.choice,
+            memo: self.memo.clone(),
+            ..vote
+        });
+        let type_script = output.type_().to_opt().expect("vote type script");
+        let capacity: u64 = output.capacity().unpack();
+        let occupied = output
+            .occupied_capacity(Capacity::bytes(data.len())?)?
+            .as_u64();
+        let new_output = vote_output(
+            output.lock(),
+            type_script.clone(),
+            &data,
+            Some(capacity.max(occupied)),
+        )?;
+        let mut cell_deps = resolve_cell_deps(cell_dep_resolver, &[output.lock(), type_script])?;
+        cell_deps.extend(self.proposal_cell.as_ref().map(proposal_cell_dep));
+        Ok(TransactionBuilder::default()
+            .set_cell_deps(cell_deps)
+            .input(CellInput::new(self.vote_cell.clone(), 0))
+            .output(new_output)
+            .output_data(data.pack())
+            .build())
+    }
+}
+
+/// The votes for a choice
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ChoiceTally {
+    /// The number of vote cells
+    pub votes: u64,
+    /// The capacity of the vote cells
+    pub capacity: u64,
+}
+
+/// The counted votes of a proposal
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct VoteTally {
+    pub choices: BTreeMap<u32, ChoiceTally>,
+    /// The cells typed by the vote script of the proposal which are not
+    /// valid votes
+    pub invalid: u64,
+}
+
+impl VoteTally {
+    /// Count a vote cell, `None` counts an invalid one
+    pub fn add(&mut self, vote: Option<&Vote>, capacity: u64) {
+        match vote {
+            Some(vote) => {
+                let tally = self.choices.entry(vote.choice).or_default();
+                tally.votes += 1;
+                tally.capacity = tally.capacity.saturating_add(capacity);
+            }
+            None => self.invalid += 1,
+        }
+    }
+
+    /// The choice with the most capacity, `None` when there is no vote or
+    /// it's a tie
+    pub fn winner(&self) -> Option<u32> {
+        let mut ranked = self
+            .choices
+            .iter()
+            .map(|(choice, tally)| (tally.capacity, *choice))
+            .collect::<Vec<_>>();
+        ranked.sort_unstable_by(|a, b| b.cmp(a));
+        match ranked.as_slice() {
+            [first, second, ..] if first.0 == second.0 => None,
+            [first, ..] => Some(first.1),
+            [] => None,
+        }
+    }
+}
+
+/// Count the live vote cells of the proposal typed by
+/// `proposal_type_script`, a vote weighs its cell capacity.
+pub fn tally_votes(
+    cell_collector: &mut dyn CellCollector,
+    scheme: &VoteScheme,
+    proposal_type_script: &Script,
+) -> Result<VoteTally, TxBuilderError> {
+    let mut query = CellQueryOptions::new_type(scheme.vote_type_script(proposal_type_script));
+    query.min_total_capacity = u64::MAX;
+    let (cells, _) = cell_collector.collect_live_cells(&query, false)?;
+    let mut tally = VoteTally::default();
+    for LiveCell {
+        output,
+        output_data,
+        ..
+    } in cells
+    {
+        let vote = scheme.parse_vote(&output, &output_data);
+        tally.add(vote.as_ref(), output.capacity().unpack());
+    }
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::h256;
+
+    #[test]
+    fn test_vote_layouts() {
+        let vote = Vote {
+            proposal: h256!("0x1234"),
+            choice: 2,
+            memo: Bytes::from("yes"),
+        };
+        for layout in [VoteLayout::Compact, VoteLayout::Molecule] {
+            let data = layout.encode(&vote);
+            assert_eq!(layout.decode(&data), Some(vote.clone()));
+            assert_eq!(layout.decode(&data[..data.len() - 4]), None);
+        }
+        let data = VoteLayout::Molecule.encode(&vote);
+        assert_eq!(data.len(), 16 + 32 + 4 + 4 + 3);
+        assert_eq!(VoteLayout::Molecule.decode(&[0u8; 16]), None);
+
+        let mut tally = VoteTally::default();
+        tally.add(Some(&vote), 100);
+        assert_eq!(tally.winner(), Some(2));
+        tally.add(
+            Some(&Vote {
+                choice: 1,
+                ..vote.clone()
+            }),
+            100,
+        );
+        tally.add(None, 1000);
+        assert_eq!(tally.winner(), None);
+        assert_eq!(tally.invalid, 1);
+    }
+}
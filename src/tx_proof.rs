@@ -0,0 +1,181 @@
+//! Verify the inclusion of transactions in a block locally.
+//!
+//! A [`TransactionProof`] from the `get_transaction_proof` rpc is a CBMT
+//! merkle proof of the transaction hashes, with the witnesses root of the
+//! block. [`verify_tx_proof`] rebuilds the transactions root from it and
+//! compares it with the one of a block header, so a light integration only
+//! trusts the header (e.g. from its own header chain), not the transaction
+//! status reported by the node.
+
+use ckb_jsonrpc_types::TransactionProof;
+use ckb_types::{
+    core::HeaderView,
+    packed::Byte32,
+    prelude::*,
+    utilities::{merkle_root, MerkleProof},
+    H256,
+};
+use thiserror::Error;
+
+#[cfg(feature = "rpc")]
+use crate::CkbRpcClient;
+#[cfg(feature = "rpc")]
+use crate::RpcError;
+
+#[derive(Error, Debug)]
+pub enum TxProofError {
+    #[error("proof of block `{proof:#x}` does not match header `{header:#x}`")]
+    BlockHashMismatch { proof: H256, header: H256 },
+
+    #[error("proof has `{indices}` indices but `{tx_hashes}` transaction hashes are given")]
+    LeavesMismatch { indices: usize, tx_hashes: usize },
+
+    #[error(
+        "transactions root `{actual:#x}` of the proof does not match `{expected:#x}` of the header"
+    )]
+    RootMismatch { expected: H256, actual: H256 },
+
+    #[cfg(feature = "rpc")]
+    #[error("rpc error: `{0}`")]
+    Rpc(#[from] RpcError),
+}
+
+/// The transactions root of the block proven by `proof`, `tx_hashes` are in
+/// the order of the proof indices
+pub fn proof_transactions_root(
+    proof: &TransactionProof,
+    tx_hashes: &[H256],
+) -> Result<H256, TxProofError> {
+    let leaves_mismatch = || TxProofError::LeavesMismatch {
+        indices: proof.proof.indices.len(),
+        tx_hashes: tx_hashes.len(),
+    };
+    if proof.proof.indices.len() != tx_hashes.len() {
+        return Err(leaves_mismatch());
+    }
+    let merkle_proof = MerkleProof::new(
+        proof
+            .proof
+            .indices
+            .iter()
+            .map(|index| index.value())
+            .collect(),
+        proof
+            .proof
+            .lemmas
+            .iter()
+            .map(|lemma| lemma.pack())
+            .collect(),
+    );
+    let leaves = tx_hashes
+        .iter()
+        .map(|tx_hash| tx_hash.pack())
+        .collect::<Vec<Byte32>>();
+    let raw_transactions_root = merkle_proof.root(&leaves).ok_or_else(leaves_mismatch)?;
+    Ok(merkle_root(&[raw_transactions_root, proof.witnesses_root.pack()]).unpack())
+}
+
+/// Check that `proof` proves `tx_hashes` are in the block of `header`.
+///
+/// The transaction hashes are in the order of the proof indices, the order
+/// of the node when it proves several transactions is unspecified, so prove
+/// one transaction per proof unless the indices are known.
+pub fn verify_tx_proof(
+    proof: &TransactionProof,
+    tx_hashes: &[H256],
+    header: &HeaderView,
+) -> Result<(), TxProofError> {
+    let block_hash: H256 = header.hash().unpack();
+    if proof.block_hash != block_hash {
+        return Err(TxProofError::BlockHashMismatch {
+            proof: proof.block_hash.clone(),
+            header: block_hash,
+        });
+    }
+    let expected: H256 = header.transactions_root().unpack();
+    let actual = proof_transactions_root(proof, tx_hashes)?;
+    if actual != expected {
+        return Err(TxProofError::RootMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Fetch the proof of `tx_hash` in the block of the trusted `header` and
+/// verify it locally
+#[cfg(feature = "rpc")]
+pub fn verify_tx_inclusion(
+    ckb_client: &CkbRpcClient,
+    tx_hash: &H256,
+    header: &HeaderView,
+) -> Result<TransactionProof, TxProofError> {
+    let proof =
+        ckb_client.get_transaction_proof(vec![tx_hash.clone()], Some(header.hash().unpack()))?;
+    verify_tx_proof(&proof, &[tx_hash.clone()], header)?;
+    Ok(proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_jsonrpc_types::MerkleProof as JsonMerkleProof;
+    use ckb_types::{
+        core::{BlockBuilder, TransactionBuilder},
+        packed::CellInput,
+        utilities::CBMT,
+    };
+
+    #[test]
+    fn test_verify_tx_proof() {
+        let txs = (0..5u64)
+            .map(|since| {
+                TransactionBuilder::default()
+                    .input(CellInput::new(Default::default(), since))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let block = BlockBuilder::default().transactions(txs).build();
+        let tx_hashes = block.tx_hashes().to_vec();
+        let proof_of = |indices: &[u32]| {
+            let merkle_proof = CBMT::build_merkle_proof(&tx_hashes, indices).unwrap();
+            TransactionProof {
+                block_hash: block.hash().unpack(),
+                witnesses_root: block.calc_witnesses_root().unpack(),
+                proof: JsonMerkleProof {
+                    indices: merkle_proof.indices().iter().map(|i| (*i).into()).collect(),
+                    lemmas: merkle_proof
+                        .lemmas()
+                        .iter()
+                        .map(|lemma| lemma.unpack())
+                        .collect(),
+                },
+            }
+        };
+        let tx_hash = |idx: usize| -> H256 { tx_hashes[idx].unpack() };
+
+        let proof = proof_of(&[3]);
+        let header = block.header();
+        assert!(verify_tx_proof(&proof, &[tx_hash(3)], &header).is_ok());
+        assert!(matches!(
+            verify_tx_proof(&proof, &[tx_hash(2)], &header),
+            Err(TxProofError::RootMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_tx_proof(&proof, &[tx_hash(3), tx_hash(2)], &header),
+            Err(TxProofError::LeavesMismatch { .. })
+        ));
+        let other_header = BlockBuilder::default().build().header();
+        assert!(matches!(
+            verify_tx_proof(&proof, &[tx_hash(3)], &other_header),
+            Err(TxProofError::BlockHashMismatch { .. })
+        ));
+        let mut tampered = proof.clone();
+        tampered.witnesses_root = H256::default();
+        assert!(matches!(
+            verify_tx_proof(&tampered, &[tx_hash(3)], &header),
+            Err(TxProofError::RootMismatch { .. })
+        ));
+
+        let proof = proof_of(&[0, 4]);
+        assert!(verify_tx_proof(&proof, &[tx_hash(0), tx_hash(4)], &header).is_ok());
+    }
+}
@@ -0,0 +1,276 @@
+//! Stream large transactions to and from files or sockets.
+//!
+//! A transaction with thousands of inputs is several megabytes of molecule
+//! data and even more as JSON. The writers here serialize directly from the
+//! molecule buffer of a `TransactionView`, and the readers allocate the buffer
+//! once, refusing any input larger than the given limit so an untrusted file
+//! can not exhaust the memory.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{Transaction, TransactionReader},
+    prelude::*,
+    H256,
+};
+use serde::{de::DeserializeOwned, ser::SerializeSeq, ser::SerializeStruct, Serialize, Serializer};
+use thiserror::Error;
+
+/// The default limit of the readers, larger than any transaction a block can
+/// hold.
+pub const DEFAULT_MAX_READ_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum TxStreamError {
+    #[error("io error: `{0}`")]
+    Io(#[from] io::Error),
+
+    #[error("input too large, limit: {limit} bytes")]
+    TooLarge { limit: u64 },
+
+    #[error("invalid molecule transaction: `{0}`")]
+    InvalidMolecule(String),
+
+    #[error("serde error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// Write the molecule serialized transaction, returns the written length
+pub fn write_molecule_tx<W: Write>(
+    tx: &TransactionView,
+    writer: &mut W,
+) -> Result<usize, TxStreamError> {
+    let data = tx.data();
+    writer.write_all(data.as_slice())?;
+    Ok(data.as_slice().len())
+}
+
+/// Read a molecule serialized transaction of at most `max_size` bytes, the
+/// size is checked against the molecule header before reading the body.
+pub fn read_molecule_tx<R: Read>(
+    reader: &mut R,
+    max_size: u64,
+) -> Result<TransactionView, TxStreamError> {
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+    let total_size = u64::from(u32::from_le_bytes(header));
+    if total_size > max_size {
+        return Err(TxStreamError::TooLarge { limit: max_size });
+    }
+    if total_size < 4 {
+        return Err(TxStreamError::InvalidMolecule(format!(
+            "total size {} is less than the header",
+            total_size
+        )));
+    }
+    let mut data = vec![0u8; total_size as usize];
+    data[0..4].copy_from_slice(&header);
+    reader.read_exact(&mut data[4..])?;
+    TransactionReader::verify(&data, false)
+        .map_err(|err| TxStreamError::InvalidMolecule(err.to_string()))?;
+    Ok(Transaction::new_unchecked(Bytes::from(data)).into_view())
+}
+
+/// Serialize the items produced by the closure as a sequence, without
+/// collecting them first.
+struct LazySeq<F>(F);
+
+impl<F, I, T> Serialize for LazySeq<F>
+where
+    F: Fn() -> I,
+    I: Iterator<Item = T>,
+    T: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for item in (self.0)() {
+            seq.serialize_element(&item)?;
+        }
+        seq.end()
+    }
+}
+
+/// The JSON form of a transaction, same as `json_types::Transaction`, each
+/// item is converted when it's written.
+struct StreamingTx<'a>(&'a TransactionView);
+
+impl<'a> Serialize for StreamingTx<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tx = self.0.data();
+        let raw = tx.raw();
+        let mut state = serializer.serialize_struct("Transaction", 7)?;
+        state.serialize_field("version", &json_types::Uint32::from(self.0.version()))?;
+        state.serialize_field(
+            "cell_deps",
+            &LazySeq(|| raw.cell_deps().into_iter().map(json_types::CellDep::from)),
+        )?;
+        state.serialize_field(
+            "header_deps",
+            &LazySeq(|| {
+                raw.header_deps()
+                    .into_iter()
+                    .map(|hash| -> H256 { hash.unpack() })
+            }),
+        )?;
+        state.serialize_field(
+            "inputs",
+            &LazySeq(|| raw.inputs().into_iter().map(json_types::CellInput::from)),
+        )?;
+        state.serialize_field(
+            "outputs",
+            &LazySeq(|| raw.outputs().into_iter().map(json_types::CellOutput::from)),
+        )?;
+        state.serialize_field(
+            "outputs_data",
+            &LazySeq(|| {
+                raw.outputs_data()
+                    .into_iter()
+                    .map(|data| json_types::JsonBytes::from_bytes(data.raw_data()))
+            }),
+        )?;
+        state.serialize_field(
+            "witnesses",
+            &LazySeq(|| {
+                tx.witnesses()
+                    .into_iter()
+                    .map(|witness| json_types::JsonBytes::from_bytes(witness.raw_data()))
+            }),
+        )?;
+        state.end()
+    }
+}
+
+/// Write the transaction as `json_types::Transaction` JSON
+pub fn write_json_tx<W: Write>(tx: &TransactionView, writer: W) -> Result<(), TxStreamError> {
+    serde_json::to_writer(writer, &StreamingTx(tx))?;
+    Ok(())
+}
+
+/// A reader failing once more than `limit` bytes are read
+pub struct BoundedReader<R> {
+    inner: R,
+    limit: u64,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> BoundedReader<R> {
+    pub fn new(inner: R, limit: u64) -> BoundedReader<R> {
+        BoundedReader {
+            inner,
+            limit,
+            remaining: limit,
+            exceeded: false,
+        }
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl<R: Read> Read for BoundedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut probe = [0u8; 1];
+            if self.inner.read(&mut probe)? == 0 {
+                return Ok(0);
+            }
+            self.exceeded = true;
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("input exceeds {} bytes", self.limit),
+            ));
+        }
+        let max_len = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let read_len = self.inner.read(&mut buf[..max_len])?;
+        self.remaining -= read_len as u64;
+        Ok(read_len)
+    }
+}
+
+/// Deserialize a JSON document of at most `max_size` bytes
+pub fn read_json_bounded<T: DeserializeOwned, R: Read>(
+    reader: R,
+    max_size: u64,
+) -> Result<T, TxStreamError> {
+    let mut bounded = BoundedReader::new(io::BufReader::new(reader), max_size);
+    match serde_json::from_reader(&mut bounded) {
+        Ok(value) => Ok(value),
+        Err(_) if bounded.exceeded() => Err(TxStreamError::TooLarge { limit: max_size }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        core::TransactionBuilder,
+        packed::{CellDep, CellInput, CellOutput, OutPoint},
+    };
+
+    fn build_tx(inputs: usize) -> TransactionView {
+        TransactionBuilder::default()
+            .cell_dep(CellDep::default())
+            .header_dep(Default::default())
+            .inputs(
+                (0..inputs)
+                    .map(|idx| CellInput::new(OutPoint::new(Default::default(), idx as u32), 0)),
+            )
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Bytes::from(vec![1u8, 2]).pack())
+            .witnesses((0..inputs).map(|_| Bytes::from(vec![0u8; 85]).pack()))
+            .build()
+    }
+
+    #[test]
+    fn test_molecule_roundtrip() {
+        let tx = build_tx(1000);
+        let mut data = Vec::new();
+        let len = write_molecule_tx(&tx, &mut data).unwrap();
+        assert_eq!(len, data.len());
+        let decoded = read_molecule_tx(&mut data.as_slice(), DEFAULT_MAX_READ_SIZE).unwrap();
+        assert_eq!(decoded.hash(), tx.hash());
+
+        assert!(matches!(
+            read_molecule_tx(&mut data.as_slice(), 1024),
+            Err(TxStreamError::TooLarge { limit: 1024 })
+        ));
+        data[4] ^= 0xff;
+        assert!(matches!(
+            read_molecule_tx(&mut data.as_slice(), DEFAULT_MAX_READ_SIZE),
+            Err(TxStreamError::InvalidMolecule(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let tx = build_tx(100);
+        let mut data = Vec::new();
+        write_json_tx(&tx, &mut data).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<json_types::Transaction>(&data).unwrap(),
+            json_types::Transaction::from(tx.data())
+        );
+        let decoded: json_types::Transaction =
+            read_json_bounded(data.as_slice(), data.len() as u64).unwrap();
+        assert_eq!(decoded, json_types::Transaction::from(tx.data()));
+        assert!(matches!(
+            read_json_bounded::<json_types::Transaction, _>(data.as_slice(), 100),
+            Err(TxStreamError::TooLarge { limit: 100 })
+        ));
+    }
+}
@@ -13,6 +13,7 @@ use ckb_types::{
 };
 use serde_derive::{Deserialize, Serialize};
 
+use super::address_format::decode_custom_payload;
 use super::NetworkType;
 use crate::constants::{
     ACP_TYPE_HASH_AGGRON, ACP_TYPE_HASH_LINA, MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH,
@@ -357,8 +358,18 @@ impl FromStr for Address {
         let (hrp, data, variant) = bech32::decode(input).map_err(|err| err.to_string())?;
         let network =
             NetworkType::from_prefix(&hrp).ok_or_else(|| format!("Invalid hrp: {}", hrp))?;
-        let data = convert_bits(&data, 5, 8, false).unwrap();
-        let ty = AddressType::from_u8(data[0])?;
+        let data = convert_bits(&data, 5, 8, false).map_err(|err| err.to_string())?;
+        let ty = match AddressType::from_u8(*data.first().ok_or("Empty address payload")?) {
+            Ok(ty) => ty,
+            Err(err) => {
+                let payload = decode_custom_payload(&data).unwrap_or(Err(err))?;
+                return Ok(Address {
+                    network,
+                    payload,
+                    is_new: true,
+                });
+            }
+        };
         match ty {
             // payload = 0x01 | code_hash_index | args
             AddressType::Short => {
@@ -767,6 +778,16 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_empty_address_payload() {
+        let addr =
+            bech32::encode("ckb", Vec::<bech32::u5>::new(), bech32::Variant::Bech32m).unwrap();
+        assert_eq!(
+            Address::from_str(&addr),
+            Err("Empty address payload".to_string())
+        );
+    }
+
     #[test]
     fn test_address_debug() {
         let payload = AddressPayload::Full {
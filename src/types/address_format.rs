@@ -0,0 +1,181 @@
+//! Runtime extensions of the address codec.
+//!
+//! Chains reusing the CKB address format (e.g. consortium or side chains) can
+//! give the `Staging`, `Preview` and `Dev` network types their own bech32
+//! prefix with [`register_address_prefix`], and decode addresses with extra
+//! payload formats registered by [`register_payload_format`]. The mainnet and
+//! testnet prefixes and the RFC21 payload formats can't be changed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use super::{AddressPayload, NetworkType};
+use crate::constants::{PREFIX_MAINNET, PREFIX_TESTNET};
+
+/// Decode the payload of an address format not defined by RFC21
+pub trait PayloadFormat: Send + Sync {
+    /// `data` is the whole decoded payload, including the format byte
+    fn decode(&self, data: &[u8]) -> Result<AddressPayload, String>;
+}
+
+impl<F> PayloadFormat for F
+where
+    F: Fn(&[u8]) -> Result<AddressPayload, String> + Send + Sync,
+{
+    fn decode(&self, data: &[u8]) -> Result<AddressPayload, String> {
+        self(data)
+    }
+}
+
+/// The format bytes defined by RFC21
+const BUILTIN_FORMATS: [u8; 4] = [0x00, 0x01, 0x02, 0x04];
+
+#[derive(Default)]
+struct AddressFormats {
+    prefixes: HashMap<NetworkType, &'static str>,
+    payload_formats: HashMap<u8, Arc<dyn PayloadFormat>>,
+}
+
+lazy_static! {
+    static ref ADDRESS_FORMATS: RwLock<AddressFormats> = RwLock::new(AddressFormats::default());
+}
+
+/// Use `prefix` as the bech32 hrp of `network`, replacing the previously
+/// registered one. Only `Staging`, `Preview` and `Dev` can be customized.
+pub fn register_address_prefix(network: NetworkType, prefix: &str) -> Result<(), String> {
+    if matches!(network, NetworkType::Mainnet | NetworkType::Testnet) {
+        return Err(format!("the prefix of {} can't be changed", network));
+    }
+    if prefix.is_empty()
+        || prefix
+            .chars()
+            .any(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit()))
+    {
+        return Err(format!("invalid address prefix: {}", prefix));
+    }
+    if prefix == PREFIX_MAINNET || prefix == PREFIX_TESTNET {
+        return Err(format!("address prefix {} is reserved", prefix));
+    }
+    let mut formats = ADDRESS_FORMATS.write();
+    if let Some((other, _)) = formats
+        .prefixes
+        .iter()
+        .find(|(other, registered)| **other != network && **registered == prefix)
+    {
+        return Err(format!(
+            "address prefix {} is already used by {}",
+            prefix, other
+        ));
+    }
+    if formats.prefixes.get(&network) != Some(&prefix) {
+        // prefixes are registered once per process, the leak is bounded
+        let prefix: &'static str = Box::leak(prefix.to_string().into_boxed_str());
+        formats.prefixes.insert(network, prefix);
+    }
+    Ok(())
+}
+
+/// Restore the default (testnet) prefix of `network`
+pub fn unregister_address_prefix(network: NetworkType) {
+    ADDRESS_FORMATS.write().prefixes.remove(&network);
+}
+
+/// Decode the addresses whose payload starts with `format_byte` with `format`
+pub fn register_payload_format(
+    format_byte: u8,
+    format: Arc<dyn PayloadFormat>,
+) -> Result<(), String> {
+    if BUILTIN_FORMATS.contains(&format_byte) {
+        return Err(format!("payload format {:#04x} is reserved", format_byte));
+    }
+    let mut formats = ADDRESS_FORMATS.write();
+    if formats.payload_formats.contains_key(&format_byte) {
+        return Err(format!(
+            "payload format {:#04x} is already registered",
+            format_byte
+        ));
+    }
+    formats.payload_formats.insert(format_byte, format);
+    Ok(())
+}
+
+pub fn unregister_payload_format(format_byte: u8) {
+    ADDRESS_FORMATS.write().payload_formats.remove(&format_byte);
+}
+
+pub(crate) fn custom_prefix(network: NetworkType) -> Option<&'static str> {
+    ADDRESS_FORMATS.read().prefixes.get(&network).copied()
+}
+
+pub(crate) fn network_of_custom_prefix(prefix: &str) -> Option<NetworkType> {
+    ADDRESS_FORMATS
+        .read()
+        .prefixes
+        .iter()
+        .find(|(_, registered)| **registered == prefix)
+        .map(|(network, _)| *network)
+}
+
+pub(crate) fn decode_custom_payload(data: &[u8]) -> Option<Result<AddressPayload, String>> {
+    let format = ADDRESS_FORMATS
+        .read()
+        .payload_formats
+        .get(data.first()?)
+        .cloned()?;
+    Some(format.decode(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Address;
+    use bech32::{ToBase32, Variant};
+    use ckb_types::{bytes::Bytes, core::ScriptHashType, h160, packed::Byte32, prelude::*};
+    use std::str::FromStr;
+
+    // payload = 0x7f | code_hash, the args are always empty
+    fn decode_code_hash_only(data: &[u8]) -> Result<AddressPayload, String> {
+        let code_hash =
+            Byte32::from_slice(&data[1..]).map_err(|err| format!("invalid code hash: {}", err))?;
+        Ok(AddressPayload::new_full(
+            ScriptHashType::Type,
+            code_hash,
+            Bytes::new(),
+        ))
+    }
+
+    #[test]
+    fn test_custom_prefix_and_payload_format() {
+        assert!(register_address_prefix(NetworkType::Mainnet, "abc").is_err());
+        assert!(register_address_prefix(NetworkType::Staging, PREFIX_TESTNET).is_err());
+        register_address_prefix(NetworkType::Staging, "cks").unwrap();
+        assert!(register_address_prefix(NetworkType::Preview, "cks").is_err());
+        assert_eq!(NetworkType::Staging.to_prefix(), "cks");
+        assert_eq!(NetworkType::from_prefix("cks"), Some(NetworkType::Staging));
+
+        let payload =
+            AddressPayload::from_pubkey_hash(h160!("0xb39bbc0b3673c7d36450bc14cfcdad2d559c6c64"));
+        let address = Address::new(NetworkType::Staging, payload, false);
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("cks1"));
+        assert_eq!(Address::from_str(&encoded).unwrap(), address);
+
+        let format_byte = 0x7f;
+        assert!(register_payload_format(0x01, Arc::new(decode_code_hash_only)).is_err());
+        register_payload_format(format_byte, Arc::new(decode_code_hash_only)).unwrap();
+        let mut data = vec![format_byte];
+        data.extend_from_slice(&[3u8; 32]);
+        let encoded = bech32::encode("cks", data.to_base32(), Variant::Bech32m).unwrap();
+        let address = Address::from_str(&encoded).unwrap();
+        assert_eq!(address.network(), NetworkType::Staging);
+        assert_eq!(address.payload().code_hash(None), [3u8; 32].pack());
+
+        unregister_payload_format(format_byte);
+        assert!(Address::from_str(&encoded).is_err());
+        unregister_address_prefix(NetworkType::Staging);
+        assert_eq!(NetworkType::Staging.to_prefix(), PREFIX_TESTNET);
+    }
+}
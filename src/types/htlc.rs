@@ -0,0 +1,128 @@
+use ckb_hash::blake2b_256;
+use ckb_types::{bytes::Bytes, packed::Script, prelude::*, H160, H256};
+
+use super::ScriptId;
+
+/// The lock args of a hash time locked cell:
+///
+/// ```text
+/// blake2b_256(preimage) | receiver blake160 | sender blake160 | timeout since (u64 LE)
+/// ```
+///
+/// The receiver unlocks the cell with the preimage and a signature, the sender
+/// unlocks it with a signature once the inputs reach the timeout since.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HtlcArgs {
+    pub hash: H256,
+    pub receiver: H160,
+    pub sender: H160,
+    pub timeout_since: u64,
+}
+
+impl HtlcArgs {
+    pub const LEN: usize = 32 + 20 + 20 + 8;
+
+    pub fn new(preimage: &[u8], receiver: H160, sender: H160, timeout_since: u64) -> HtlcArgs {
+        HtlcArgs {
+            hash: H256(blake2b_256(preimage)),
+            receiver,
+            sender,
+            timeout_since,
+        }
+    }
+
+    pub fn from_slice(args: &[u8]) -> Option<HtlcArgs> {
+        if args.len() != HtlcArgs::LEN {
+            return None;
+        }
+        let mut since_bytes = [0u8; 8];
+        since_bytes.copy_from_slice(&args[72..80]);
+        Some(HtlcArgs {
+            hash: H256::from_slice(&args[0..32]).ok()?,
+            receiver: H160::from_slice(&args[32..52]).ok()?,
+            sender: H160::from_slice(&args[52..72]).ok()?,
+            timeout_since: u64::from_le_bytes(since_bytes),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut args = Vec::with_capacity(HtlcArgs::LEN);
+        args.extend_from_slice(self.hash.as_bytes());
+        args.extend_from_slice(self.receiver.as_bytes());
+        args.extend_from_slice(self.sender.as_bytes());
+        args.extend_from_slice(&self.timeout_since.to_le_bytes());
+        Bytes::from(args)
+    }
+
+    /// The lock script of the HTLC script deployed with `script_id`
+    pub fn lock_script(&self, script_id: &ScriptId) -> Script {
+        Script::new_builder()
+            .code_hash(script_id.code_hash.pack())
+            .hash_type(script_id.hash_type.into())
+            .args(self.to_bytes().pack())
+            .build()
+    }
+
+    pub fn match_preimage(&self, preimage: &[u8]) -> bool {
+        blake2b_256(preimage) == self.hash.0
+    }
+}
+
+/// How a hash time locked cell is unlocked
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum HtlcAction {
+    /// The receiver reveals the preimage, the witness lock is
+    /// `signature | preimage`
+    Claim { preimage: Bytes },
+    /// The sender takes the cell back after the timeout, the witness lock is
+    /// the signature only
+    Refund,
+}
+
+impl HtlcAction {
+    /// The witness lock with the signature zeroed, it's also the lock used to
+    /// generate the signing message so the preimage is signed too.
+    pub fn zero_lock(&self) -> Bytes {
+        self.witness_lock(&[0u8; 65])
+    }
+
+    pub fn witness_lock(&self, signature: &[u8]) -> Bytes {
+        let mut lock = signature.to_vec();
+        if let HtlcAction::Claim { preimage } = self {
+            lock.extend_from_slice(preimage);
+        }
+        Bytes::from(lock)
+    }
+
+    /// The blake160 of the key signing for the action
+    pub fn owner<'a>(&self, args: &'a HtlcArgs) -> &'a H160 {
+        match self {
+            HtlcAction::Claim { .. } => &args.receiver,
+            HtlcAction::Refund => &args.sender,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_htlc_args() {
+        let args = HtlcArgs::new(b"secret", H160([1u8; 20]), H160([2u8; 20]), 42);
+        let bytes = args.to_bytes();
+        assert_eq!(bytes.len(), HtlcArgs::LEN);
+        assert_eq!(HtlcArgs::from_slice(&bytes), Some(args.clone()));
+        assert_eq!(HtlcArgs::from_slice(&bytes[1..]), None);
+        assert!(args.match_preimage(b"secret"));
+        assert!(!args.match_preimage(b"guess"));
+
+        let claim = HtlcAction::Claim {
+            preimage: Bytes::from_static(b"secret"),
+        };
+        assert_eq!(claim.zero_lock().len(), 65 + 6);
+        assert_eq!(claim.owner(&args), &args.receiver);
+        assert_eq!(HtlcAction::Refund.zero_lock().len(), 65);
+        assert_eq!(HtlcAction::Refund.owner(&args), &args.sender);
+    }
+}
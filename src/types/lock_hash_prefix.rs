@@ -0,0 +1,120 @@
+use std::fmt;
+
+use ckb_types::{
+    packed::{Byte32, Script},
+    prelude::*,
+    H160,
+};
+
+/// The length of a [`LockHashPrefix`]
+pub const LOCK_HASH_PREFIX_LEN: usize = 20;
+
+/// The first 20 bytes of a 32 bytes hash, the way the lock args refer to a
+/// script or a config: the receiver and the sender lock hashes in the cheque
+/// args, the owner lock hash of an omnilock, the multisig config hash.
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Debug, Default)]
+pub struct LockHashPrefix([u8; LOCK_HASH_PREFIX_LEN]);
+
+impl LockHashPrefix {
+    /// The prefix of the hash of `script`
+    pub fn from_script(script: &Script) -> LockHashPrefix {
+        LockHashPrefix::from_hash(&script.calc_script_hash())
+    }
+
+    pub fn from_hash(hash: &Byte32) -> LockHashPrefix {
+        let mut prefix = [0u8; LOCK_HASH_PREFIX_LEN];
+        prefix.copy_from_slice(&hash.as_slice()[0..LOCK_HASH_PREFIX_LEN]);
+        LockHashPrefix(prefix)
+    }
+
+    /// The prefix of a 32 bytes hash, e.g. from `blake2b_256`
+    pub fn from_hash_bytes(hash: &[u8; 32]) -> LockHashPrefix {
+        let mut prefix = [0u8; LOCK_HASH_PREFIX_LEN];
+        prefix.copy_from_slice(&hash[0..LOCK_HASH_PREFIX_LEN]);
+        LockHashPrefix(prefix)
+    }
+
+    /// The prefix in `args` at `offset`, `None` if `args` is too short
+    pub fn from_args(args: &[u8], offset: usize) -> Option<LockHashPrefix> {
+        let data = args.get(offset..offset.checked_add(LOCK_HASH_PREFIX_LEN)?)?;
+        let mut prefix = [0u8; LOCK_HASH_PREFIX_LEN];
+        prefix.copy_from_slice(data);
+        Some(LockHashPrefix(prefix))
+    }
+
+    /// Whether this is the prefix of the hash of `script`
+    pub fn matches(&self, script: &Script) -> bool {
+        *self == LockHashPrefix::from_script(script)
+    }
+
+    /// Whether this is the prefix of `hash`
+    pub fn matches_hash(&self, hash: &Byte32) -> bool {
+        *self == LockHashPrefix::from_hash(hash)
+    }
+
+    /// Whether `args` has this prefix at `offset`
+    pub fn matches_args(&self, args: &[u8], offset: usize) -> bool {
+        LockHashPrefix::from_args(args, offset).as_ref() == Some(self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<H160> for LockHashPrefix {
+    fn from(hash: H160) -> LockHashPrefix {
+        LockHashPrefix(hash.0)
+    }
+}
+
+impl From<LockHashPrefix> for H160 {
+    fn from(prefix: LockHashPrefix) -> H160 {
+        H160(prefix.0)
+    }
+}
+
+impl From<&Script> for LockHashPrefix {
+    fn from(script: &Script) -> LockHashPrefix {
+        LockHashPrefix::from_script(script)
+    }
+}
+
+impl fmt::Display for LockHashPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}", H160(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::bytes::Bytes;
+
+    #[test]
+    fn test_lock_hash_prefix() {
+        let script = Script::new_builder()
+            .args(Bytes::from(vec![1u8; 20]).pack())
+            .build();
+        let hash = script.calc_script_hash();
+        let prefix = LockHashPrefix::from_script(&script);
+        assert_eq!(prefix.as_bytes(), &hash.as_slice()[0..20]);
+        assert!(prefix.matches(&script));
+        assert!(prefix.matches_hash(&hash));
+        assert!(!prefix.matches(&Script::default()));
+
+        // cheque args: receiver | sender
+        let mut args = vec![0u8; 20];
+        args.extend_from_slice(prefix.as_bytes());
+        assert_eq!(LockHashPrefix::from_args(&args, 20), Some(prefix));
+        assert!(prefix.matches_args(&args, 20));
+        assert!(!prefix.matches_args(&args, 0));
+        assert_eq!(LockHashPrefix::from_args(&args, 21), None);
+        assert_eq!(LockHashPrefix::from_args(&args, usize::MAX), None);
+        assert!(!prefix.matches_args(&args[0..39], 20));
+
+        let h160: H160 = prefix.into();
+        assert_eq!(LockHashPrefix::from(h160.clone()), prefix);
+        assert_eq!(prefix.to_string(), format!("{:#x}", h160));
+    }
+}
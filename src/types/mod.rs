@@ -1,6 +1,9 @@
 //! Basic ckb sdk types
 mod address;
+mod address_format;
+mod htlc;
 mod human_capacity;
+mod lock_hash_prefix;
 mod network_type;
 #[allow(clippy::all)]
 pub mod omni_lock;
@@ -14,9 +17,15 @@ pub mod xudt_rce_mol;
 pub use address::{
     Address, AddressPayload, AddressType, CodeHashIndex, OldAddress, OldAddressFormat,
 };
+pub use address_format::{
+    register_address_prefix, register_payload_format, unregister_address_prefix,
+    unregister_payload_format, PayloadFormat,
+};
+pub use htlc::{HtlcAction, HtlcArgs};
 pub use human_capacity::HumanCapacity;
+pub use lock_hash_prefix::{LockHashPrefix, LOCK_HASH_PREFIX_LEN};
 pub use network_type::{NetworkInfo, NetworkType};
 pub use script_group::{ScriptGroup, ScriptGroupType};
 pub use script_id::ScriptId;
-pub use since::{Since, SinceType};
+pub use since::{format_epoch, Since, SinceType, EPOCH_DURATION_SECS};
 pub use transaction_with_groups::TransactionWithScriptGroups;
@@ -2,6 +2,7 @@ use std::fmt;
 
 use serde_derive::{Deserialize, Serialize};
 
+use super::address_format::{custom_prefix, network_of_custom_prefix};
 use crate::constants::{
     NETWORK_DEV, NETWORK_MAINNET, NETWORK_PREVIEW, NETWORK_STAGING, NETWORK_TESTNET,
     PREFIX_MAINNET, PREFIX_TESTNET,
@@ -17,15 +18,20 @@ pub enum NetworkType {
 }
 
 impl NetworkType {
+    /// See [`register_address_prefix`](super::register_address_prefix) for
+    /// the custom prefixes
     pub fn from_prefix(value: &str) -> Option<NetworkType> {
         match value {
             PREFIX_MAINNET => Some(NetworkType::Mainnet),
             PREFIX_TESTNET => Some(NetworkType::Testnet),
-            _ => None,
+            _ => network_of_custom_prefix(value),
         }
     }
 
     pub fn to_prefix(self) -> &'static str {
+        if let Some(prefix) = custom_prefix(self) {
+            return prefix;
+        }
         match self {
             NetworkType::Mainnet => PREFIX_MAINNET,
             NetworkType::Testnet => PREFIX_TESTNET,
@@ -1,8 +1,11 @@
+use std::convert::TryFrom;
 use std::fmt;
 
-use ckb_types::packed::Script;
+use ckb_types::{core::ScriptHashType, packed::Script, prelude::*};
 use serde_derive::{Deserialize, Serialize};
 
+use crate::redact;
+
 /// A script group is defined as scripts that share the same hash.
 ///
 /// A script group will only be executed once per transaction, the
@@ -44,6 +47,25 @@ impl ScriptGroup {
     }
 }
 
+impl fmt::Display for ScriptGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hash_type = ScriptHashType::try_from(self.script.hash_type())
+            .map(|hash_type| format!("{:?}", hash_type))
+            .unwrap_or_else(|_| self.script.hash_type().to_string());
+        write!(
+            f,
+            "{} group {} (code_hash: {:#x}, hash_type: {}, args: {}), inputs: {:?}, outputs: {:?}",
+            self.group_type,
+            redact::bytes(self.script.calc_script_hash().as_slice()),
+            self.script.code_hash(),
+            hash_type,
+            redact::bytes(&self.script.args().raw_data()),
+            self.input_indices,
+            self.output_indices
+        )
+    }
+}
+
 /// The script group type.
 ///
 /// A cell can have a lock script and an optional type script. Even they reference the same script,
@@ -65,3 +87,24 @@ impl fmt::Display for ScriptGroupType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{bytes::Bytes, h256};
+
+    #[test]
+    fn test_script_group_display() {
+        let script = Script::new_builder()
+            .code_hash(h256!("0x1234").pack())
+            .hash_type(ScriptHashType::Data1.into())
+            .args(Bytes::from(vec![0xab, 0xcd]).pack())
+            .build();
+        let mut group = ScriptGroup::from_lock_script(&script);
+        group.input_indices = vec![0, 2];
+        let text = group.to_string();
+        assert!(text.starts_with("Lock group 0x"), "{}", text);
+        assert!(text.contains("hash_type: Data1, args: 0xabcd"), "{}", text);
+        assert!(text.ends_with("inputs: [0, 2], outputs: []"), "{}", text);
+    }
+}
@@ -1,7 +1,11 @@
 use std::convert::TryFrom;
 use std::fmt;
 
-use crate::constants::{DAO_TYPE_HASH, TYPE_ID_CODE_HASH};
+use super::Address;
+use crate::constants::{
+    ACP_TYPE_HASH_AGGRON, ACP_TYPE_HASH_LINA, DAO_TYPE_HASH, MULTISIG_TYPE_HASH, SIGHASH_TYPE_HASH,
+    TYPE_ID_CODE_HASH,
+};
 use ckb_types::{core::ScriptHashType, packed::Script, prelude::*, H256};
 
 #[derive(Clone, Hash, Eq, PartialEq, Debug, Default)]
@@ -35,6 +39,33 @@ impl ScriptId {
         self.code_hash == DAO_TYPE_HASH && self.hash_type == ScriptHashType::Type
     }
 
+    /// Whether `script` has the same code hash and hash type
+    pub fn matches(&self, script: &Script) -> bool {
+        script.code_hash().as_slice() == self.code_hash.as_bytes()
+            && ScriptHashType::try_from(script.hash_type()).ok() == Some(self.hash_type)
+    }
+
+    /// The name of a known system script
+    pub fn known_name(&self) -> Option<&'static str> {
+        if self.hash_type != ScriptHashType::Type {
+            return None;
+        }
+        let code_hash = &self.code_hash;
+        if code_hash == &SIGHASH_TYPE_HASH {
+            Some("secp256k1_blake160_sighash_all")
+        } else if code_hash == &MULTISIG_TYPE_HASH {
+            Some("secp256k1_blake160_multisig_all")
+        } else if code_hash == &DAO_TYPE_HASH {
+            Some("nervos_dao")
+        } else if code_hash == &TYPE_ID_CODE_HASH {
+            Some("type_id")
+        } else if code_hash == &ACP_TYPE_HASH_LINA || code_hash == &ACP_TYPE_HASH_AGGRON {
+            Some("anyone_can_pay")
+        } else {
+            None
+        }
+    }
+
     /// Generate a dummy TypeId script with a placeholder args
     pub fn dummy_type_id_script(&self) -> Script {
         Script::new_builder()
@@ -56,8 +87,29 @@ impl From<&Script> for ScriptId {
     }
 }
 
+impl From<&Address> for ScriptId {
+    fn from(address: &Address) -> ScriptId {
+        ScriptId::from(&Script::from(address))
+    }
+}
+
+impl From<(H256, ScriptHashType)> for ScriptId {
+    fn from((code_hash, hash_type): (H256, ScriptHashType)) -> ScriptId {
+        ScriptId::new(code_hash, hash_type)
+    }
+}
+
+impl PartialEq<Script> for ScriptId {
+    fn eq(&self, script: &Script) -> bool {
+        self.matches(script)
+    }
+}
+
 impl fmt::Display for ScriptId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = self.known_name() {
+            write!(f, "{} ", name)?;
+        }
         write!(
             f,
             "code_hash={:?}, hash_type={:?}",
@@ -65,3 +117,38 @@ impl fmt::Display for ScriptId {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NetworkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_script_id_constructors() {
+        let address = Address::from_str("ckt1qyq86vaa6e8tsruv5ngcd5tp7lcvcewxy7cquuksvj").unwrap();
+        assert_eq!(address.network(), NetworkType::Testnet);
+        let script_id = ScriptId::from(&address);
+        assert_eq!(script_id, ScriptId::new_type(SIGHASH_TYPE_HASH));
+        assert_eq!(script_id, Script::from(&address));
+        assert_eq!(
+            ScriptId::from((SIGHASH_TYPE_HASH, ScriptHashType::Type)),
+            script_id
+        );
+        assert_ne!(
+            ScriptId::new_data1(SIGHASH_TYPE_HASH),
+            Script::from(&address)
+        );
+    }
+
+    #[test]
+    fn test_script_id_display() {
+        let script_id = ScriptId::new_type(DAO_TYPE_HASH);
+        assert_eq!(script_id.known_name(), Some("nervos_dao"));
+        assert!(script_id.to_string().starts_with("nervos_dao code_hash="));
+        assert_eq!(ScriptId::new_data(DAO_TYPE_HASH).known_name(), None);
+        assert!(ScriptId::new_data(DAO_TYPE_HASH)
+            .to_string()
+            .starts_with("code_hash="));
+    }
+}
@@ -1,7 +1,12 @@
+use std::fmt;
+
 use ckb_types::core::EpochNumberWithFraction;
 
 use crate::constants::{LOCK_TYPE_FLAG, METRIC_TYPE_FLAG_MASK, REMAIN_FLAGS_BITS, VALUE_MASK};
 
+/// The expected duration of an epoch in seconds
+pub const EPOCH_DURATION_SECS: u64 = 4 * 60 * 60;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SinceType {
     BlockNumber,
@@ -69,4 +74,127 @@ impl Since {
         };
         ty_opt.map(|ty| (ty, value))
     }
+
+    /// Describe when an absolute epoch since is reached from the tip epoch,
+    /// e.g. "available in ~3 days at epoch 8123.412/1800". Other kinds of
+    /// since are only rendered.
+    pub fn describe_availability(self, tip_epoch: EpochNumberWithFraction) -> String {
+        match self.extract_metric() {
+            Some((SinceType::EpochNumberWithFraction, value))
+                if self.is_absolute() && self.flags_is_valid() =>
+            {
+                let target = EpochNumberWithFraction::from_full_value(value);
+                let remaining = epoch_to_f64(target) - epoch_to_f64(tip_epoch);
+                if remaining <= 0.0 {
+                    format!("available now, since epoch {}", format_epoch(target))
+                } else {
+                    format!(
+                        "available in {} at epoch {}",
+                        format_duration((remaining * EPOCH_DURATION_SECS as f64) as u64),
+                        format_epoch(target)
+                    )
+                }
+            }
+            _ => format!("available at {}", self),
+        }
+    }
+}
+
+impl fmt::Display for Since {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ty, value) = match self.extract_metric() {
+            Some(metric) if self.flags_is_valid() => metric,
+            _ => return write!(f, "invalid since {:#x}", self.0),
+        };
+        match (ty, self.is_relative()) {
+            (SinceType::BlockNumber, false) => write!(f, "block {}", value),
+            (SinceType::BlockNumber, true) => write!(f, "{} blocks after commit", value),
+            (SinceType::EpochNumberWithFraction, false) => write!(
+                f,
+                "epoch {}",
+                format_epoch(EpochNumberWithFraction::from_full_value(value))
+            ),
+            (SinceType::EpochNumberWithFraction, true) => {
+                let epoch = EpochNumberWithFraction::from_full_value(value);
+                if epoch.index() == 0 {
+                    write!(f, "{} epochs after commit", epoch.number())
+                } else {
+                    write!(f, "{} epochs after commit", format_epoch(epoch))
+                }
+            }
+            (SinceType::Timestamp, false) => write!(f, "median time {}s", value),
+            (SinceType::Timestamp, true) => write!(f, "{}s after commit", value),
+        }
+    }
+}
+
+/// Render an epoch as `number.index/length`, e.g. "8123.412/1800"
+pub fn format_epoch(epoch: EpochNumberWithFraction) -> String {
+    format!("{}.{}/{}", epoch.number(), epoch.index(), epoch.length())
+}
+
+fn epoch_to_f64(epoch: EpochNumberWithFraction) -> f64 {
+    if epoch.length() == 0 {
+        epoch.number() as f64
+    } else {
+        epoch.number() as f64 + epoch.index() as f64 / epoch.length() as f64
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    if secs >= DAY {
+        format!("~{} days", (secs + DAY / 2) / DAY)
+    } else if secs >= HOUR {
+        format!("~{} hours", (secs + HOUR / 2) / HOUR)
+    } else {
+        format!("~{} minutes", ((secs + 30) / 60).max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_since_display() {
+        let epoch = EpochNumberWithFraction::new(8123, 412, 1800);
+        let since = Since::new(
+            SinceType::EpochNumberWithFraction,
+            epoch.full_value(),
+            false,
+        );
+        assert_eq!(since.to_string(), "epoch 8123.412/1800");
+        let since = Since::from_raw_value(0xA000000000000006);
+        assert_eq!(since.to_string(), "6 epochs after commit");
+        let since = Since::new(SinceType::BlockNumber, 100, false);
+        assert_eq!(since.to_string(), "block 100");
+        assert_eq!(
+            Since::from_raw_value(0x6000_0000_0000_0000).to_string(),
+            "invalid since 0x6000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_describe_availability() {
+        let target = EpochNumberWithFraction::new(8123, 412, 1800);
+        let since = Since::new(
+            SinceType::EpochNumberWithFraction,
+            target.full_value(),
+            false,
+        );
+        assert_eq!(
+            since.describe_availability(EpochNumberWithFraction::new(8105, 412, 1800)),
+            "available in ~3 days at epoch 8123.412/1800"
+        );
+        assert_eq!(
+            since.describe_availability(EpochNumberWithFraction::new(8122, 0, 1)),
+            "available in ~5 hours at epoch 8123.412/1800"
+        );
+        assert_eq!(
+            since.describe_availability(EpochNumberWithFraction::new(8124, 0, 1)),
+            "available now, since epoch 8123.412/1800"
+        );
+    }
 }
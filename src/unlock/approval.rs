@@ -0,0 +1,262 @@
+//! Canonical textual encodings of a signing message for out-of-band
+//! approvals.
+//!
+//! An approval system (a chat bot, a ticket) shows an [`ApprovalRequest`] in
+//! one of the [`ApprovalFormat`]s and records the approved digest. At
+//! execution time [`ApprovalRequest::verify`] or an [`ApprovalAuthorizer`]
+//! set on the script signers recompute the digest from the transaction being
+//! signed, so a transaction changed after the approval is not signed.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use ckb_hash::blake2b_256;
+use ckb_types::{bytes::Bytes, core::TransactionView, prelude::*, H256};
+use thiserror::Error;
+
+use super::{generate_message, ScriptSignError, SigningAuthorizer};
+use crate::constants::SECP_SIGNATURE_SIZE;
+use crate::types::ScriptGroup;
+
+/// The prefix of the [`ApprovalFormat::Prefixed`] digest, the 32 bytes
+/// message follows
+pub const APPROVAL_MESSAGE_PREFIX: &[u8] = b"\x19Nervos CKB Signed Message:\n32";
+
+/// The version line of the [`ApprovalFormat::Structured`] text
+pub const APPROVAL_STRUCTURED_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum ApprovalError {
+    #[error("approved digest `{approved:#x}` does not match `{actual:#x}` of the transaction")]
+    DigestMismatch { approved: H256, actual: H256 },
+
+    #[error("generate signing message error: `{0}`")]
+    Message(#[from] ScriptSignError),
+}
+
+/// How a signing message is shown and digested
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ApprovalFormat {
+    /// The signing message itself in hex
+    Raw,
+    /// EIP-191 like, `blake2b_256(APPROVAL_MESSAGE_PREFIX || message)`, a
+    /// digest no transaction signature can be made over
+    Prefixed,
+    /// BIP-322 like, a text of the transaction hash, the lock script hash,
+    /// the group inputs and the message, the digest is its `blake2b_256`
+    Structured,
+}
+
+/// The signing message of a script group with what it signs
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ApprovalRequest {
+    pub tx_hash: H256,
+    pub lock_hash: H256,
+    pub input_indices: Vec<usize>,
+    pub message: H256,
+}
+
+impl ApprovalRequest {
+    /// The request of `message`, the signing message of `script_group` in
+    /// `tx`
+    pub fn new(tx: &TransactionView, script_group: &ScriptGroup, message: H256) -> Self {
+        ApprovalRequest {
+            tx_hash: tx.hash().unpack(),
+            lock_hash: script_group.script.calc_script_hash().unpack(),
+            input_indices: script_group.input_indices.clone(),
+            message,
+        }
+    }
+
+    /// The request of the message generated with a `zero_lock` placeholder,
+    /// e.g. 65 zero bytes for the sighash lock
+    pub fn from_zero_lock(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        zero_lock: Bytes,
+    ) -> Result<Self, ScriptSignError> {
+        let message = generate_message(tx, script_group, zero_lock)?;
+        Ok(ApprovalRequest::new(
+            tx,
+            script_group,
+            H256::from_slice(message.as_ref()).expect("32 bytes message"),
+        ))
+    }
+
+    /// The request of the sighash signing message
+    pub fn from_sighash(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<Self, ScriptSignError> {
+        let zero_lock = Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]);
+        ApprovalRequest::from_zero_lock(tx, script_group, zero_lock)
+    }
+
+    /// The text shown to the approvers
+    pub fn text(&self, format: ApprovalFormat) -> String {
+        match format {
+            ApprovalFormat::Raw => format!("{:#x}", self.message),
+            ApprovalFormat::Prefixed => format!("{:#x}", self.digest(format)),
+            ApprovalFormat::Structured => {
+                let mut text = String::new();
+                let indices = self
+                    .input_indices
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(text, "CKB signing request").unwrap();
+                writeln!(text, "Version: {}", APPROVAL_STRUCTURED_VERSION).unwrap();
+                writeln!(text, "Transaction: {:#x}", self.tx_hash).unwrap();
+                writeln!(text, "Lock script: {:#x}", self.lock_hash).unwrap();
+                writeln!(text, "Inputs: {}", indices).unwrap();
+                writeln!(text, "Message: {:#x}", self.message).unwrap();
+                text
+            }
+        }
+    }
+
+    /// The digest the approvers approve
+    pub fn digest(&self, format: ApprovalFormat) -> H256 {
+        match format {
+            ApprovalFormat::Raw => self.message.clone(),
+            ApprovalFormat::Prefixed => {
+                let mut data = APPROVAL_MESSAGE_PREFIX.to_vec();
+                data.extend_from_slice(self.message.as_bytes());
+                H256(blake2b_256(data))
+            }
+            ApprovalFormat::Structured => H256(blake2b_256(self.text(format))),
+        }
+    }
+
+    /// Check that `approved` is the digest of this request
+    pub fn verify(&self, format: ApprovalFormat, approved: &H256) -> Result<(), ApprovalError> {
+        let actual = self.digest(format);
+        if &actual != approved {
+            return Err(ApprovalError::DigestMismatch {
+                approved: approved.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A [`SigningAuthorizer`] only allowing the script groups whose signing
+/// message digest was approved. The message is generated with `zero_lock`,
+/// 65 zero bytes by default, set the placeholder lock of the others (e.g.
+/// the multisig config followed by zero signatures) with
+/// [`with_zero_lock`](Self::with_zero_lock).
+#[derive(Debug, Clone)]
+pub struct ApprovalAuthorizer {
+    format: ApprovalFormat,
+    zero_lock: Bytes,
+    approved: HashSet<H256>,
+}
+
+impl ApprovalAuthorizer {
+    pub fn new<I: IntoIterator<Item = H256>>(format: ApprovalFormat, approved: I) -> Self {
+        ApprovalAuthorizer {
+            format,
+            zero_lock: Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]),
+            approved: approved.into_iter().collect(),
+        }
+    }
+
+    pub fn with_zero_lock(mut self, zero_lock: Bytes) -> Self {
+        self.zero_lock = zero_lock;
+        self
+    }
+
+    pub fn approve(&mut self, digest: H256) {
+        self.approved.insert(digest);
+    }
+}
+
+impl SigningAuthorizer for ApprovalAuthorizer {
+    fn authorize(
+        &self,
+        _owner_id: &[u8],
+        script_group: &ScriptGroup,
+        tx: &TransactionView,
+    ) -> Result<(), String> {
+        let request = ApprovalRequest::from_zero_lock(tx, script_group, self.zero_lock.clone())
+            .map_err(|err| err.to_string())?;
+        let digest = request.digest(self.format);
+        if self.approved.contains(&digest) {
+            Ok(())
+        } else {
+            Err(format!(
+                "digest {:#x} of lock script {:#x} is not approved",
+                digest, request.lock_hash
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        core::TransactionBuilder,
+        h256,
+        packed::{CellInput, OutPoint, Script},
+    };
+
+    use crate::types::ScriptGroupType;
+
+    #[test]
+    fn test_approval_request() {
+        let request = ApprovalRequest {
+            tx_hash: h256!("0x1"),
+            lock_hash: h256!("0x2"),
+            input_indices: vec![0, 2],
+            message: h256!("0x3"),
+        };
+        assert_eq!(
+            request.text(ApprovalFormat::Raw),
+            format!("{:#x}", h256!("0x3"))
+        );
+        assert_eq!(request.digest(ApprovalFormat::Raw), h256!("0x3"));
+        assert_eq!(
+            request.digest(ApprovalFormat::Prefixed),
+            h256!("0x8bfd4431d2ffa7bc503d727f62d1a01aa8cb2d36d0d3648485b84eea1960cb2a")
+        );
+        let text = request.text(ApprovalFormat::Structured);
+        assert!(text.starts_with("CKB signing request\nVersion: 1\n"));
+        assert!(text.contains("Inputs: 0,2\n"));
+        assert_eq!(
+            request.digest(ApprovalFormat::Structured),
+            H256(blake2b_256(text))
+        );
+        let digest = request.digest(ApprovalFormat::Structured);
+        assert!(request.verify(ApprovalFormat::Structured, &digest).is_ok());
+        assert!(matches!(
+            request.verify(ApprovalFormat::Prefixed, &digest),
+            Err(ApprovalError::DigestMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_approval_authorizer() {
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(h256!("0x1").pack(), 0), 0))
+            .build();
+        let mut script_group = ScriptGroup::new(&Script::default(), ScriptGroupType::Lock);
+        script_group.input_indices = vec![0];
+        let digest = ApprovalRequest::from_sighash(&tx, &script_group)
+            .unwrap()
+            .digest(ApprovalFormat::Prefixed);
+
+        let mut authorizer = ApprovalAuthorizer::new(ApprovalFormat::Prefixed, None);
+        assert!(authorizer.authorize(&[], &script_group, &tx).is_err());
+        authorizer.approve(digest);
+        assert!(authorizer.authorize(&[], &script_group, &tx).is_ok());
+        // the transaction changed after the approval
+        let changed = tx
+            .as_advanced_builder()
+            .output_data(Bytes::new().pack())
+            .build();
+        assert!(authorizer.authorize(&[], &script_group, &changed).is_err());
+    }
+}
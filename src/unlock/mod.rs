@@ -1,16 +1,49 @@
+mod approval;
 pub(crate) mod omni_lock;
+mod preview;
 pub mod rc_data;
 mod signer;
+mod skeleton;
 mod unlocker;
+mod verify;
+mod witness_layout;
+mod witness_migration;
+mod xchain;
 
+pub use approval::{
+    ApprovalAuthorizer, ApprovalError, ApprovalFormat, ApprovalRequest, APPROVAL_MESSAGE_PREFIX,
+    APPROVAL_STRUCTURED_VERSION,
+};
+pub use preview::{PreviewDestination, SigningEntry, SigningPreview};
 pub use signer::{
-    generate_message, AcpScriptSigner, ChequeAction, ChequeScriptSigner, MultisigConfig,
-    OmniLockScriptSigner, OmniUnlockMode, ScriptSignError, ScriptSigner, SecpMultisigScriptSigner,
-    SecpSighashScriptSigner,
+    ensure_witness_capacity, fill_signature_slot, generate_message, generate_message_with_backend,
+    generate_message_with_strategy, pad_group_witnesses, verify_domain_separated_signature,
+    witness_index_for_group, AcpScriptSigner, ChequeAction, ChequeScriptSigner,
+    CoverAllOuterWitnesses, HtlcScriptSigner, MultisigConfig, OmniLockScriptSigner, OmniUnlockMode,
+    OuterWitnessStrategy, ScriptSignError, ScriptSigner, SecpMultisigScriptSigner,
+    SecpSighashScriptSigner, SigningAuthorizer, SkipOuterWitnesses,
 };
+pub use skeleton::{SighashSkeletonSigner, WitnessSkeleton};
 pub use unlocker::{
-    fill_witness_lock, reset_witness_lock, AcpUnlocker, ChequeUnlocker, OmniLockUnlocker,
-    ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker, UnlockError,
+    fill_witness_lock, fill_witness_type, reset_witness_lock, AcpUnlocker, ChequeUnlocker,
+    HtlcUnlocker, OmniLockUnlocker, ScriptUnlocker, SecpMultisigUnlocker, SecpSighashUnlocker,
+    TypeWitnessField, UnlockError,
+};
+pub use verify::{verify_all_signatures, SignatureSlot, SlotStatus};
+
+pub use witness_layout::{
+    cobuild_signing_message, detect_witness_layout, negotiate_witness_layout,
+    CobuildSighashWitness, WitnessLayout, WitnessLayoutKind, WitnessLayoutMode,
+    COBUILD_SIGHASH_ALL_ONLY_PERSONALIZATION, COBUILD_SIGHASH_ALL_PERSONALIZATION, OTX_LAYOUT_ID,
+    OTX_START_LAYOUT_ID, SIGHASH_ALL_LAYOUT_ID, SIGHASH_ALL_ONLY_LAYOUT_ID,
+};
+pub use witness_migration::{
+    GroupMigration, MigrationReport, MigrationStatus, WitnessMigrationError, WitnessMigrator,
 };
 
 pub use omni_lock::{IdentityFlag, InfoCellData, OmniLockAcpConfig, OmniLockConfig};
+pub use xchain::{
+    bitcoin_auth_content, bitcoin_auth_content_from_address, dogecoin_auth_content_from_address,
+    ethereum_auth_content, ethereum_auth_content_from_address, hash160,
+    tron_auth_content_from_address, xchain_auth_content, BitcoinPubkeyFormat, XChainAddressError,
+};
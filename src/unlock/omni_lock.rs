@@ -663,11 +663,8 @@ impl OmniLockConfig {
                         .as_ref()
                         .ok_or(ConfigError::NoMultiSigConfig)?,
                 };
-                let config_data = multisig_config.to_witness_data();
-                let multisig_len = config_data.len() + multisig_config.threshold() as usize * 65;
-                let mut omni_sig = vec![0u8; multisig_len];
-                omni_sig[..config_data.len()].copy_from_slice(&config_data);
-                OmniLockWitnessLock::new_builder().signature(Some(Bytes::from(omni_sig)).pack())
+                OmniLockWitnessLock::new_builder()
+                    .signature(Some(multisig_config.zero_lock()).pack())
             }
             IdentityFlag::OwnerLock => OmniLockWitnessLock::new_builder(),
             _ => todo!("to support other placeholder_witness_lock implementions"),
@@ -0,0 +1,153 @@
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionView},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cobuild_signing_message, generate_message, CobuildSighashWitness, ScriptSignError, UnlockError,
+    WitnessLayout,
+};
+use crate::traits::TransactionDependencyProvider;
+use crate::ScriptGroup;
+
+/// An output of the transaction as shown to the signer
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PreviewDestination {
+    pub output_index: u32,
+    pub lock: json_types::Script,
+    #[serde(rename = "type")]
+    pub type_: Option<json_types::Script>,
+    pub capacity: json_types::Capacity,
+    pub data_len: u32,
+    /// The output goes back to the lock of the signing group
+    pub is_change: bool,
+}
+
+/// What a signature of a lock script group approves, derived from the
+/// transaction for approval UIs and hardware wallets.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SigningPreview {
+    /// The capacity of the group inputs
+    pub spent_capacity: json_types::Capacity,
+    pub destinations: Vec<PreviewDestination>,
+    /// The fee of the whole transaction
+    pub fee: json_types::Capacity,
+    /// The part of the fee paid by the group, proportional to its share of
+    /// the input capacity
+    pub fee_share: json_types::Capacity,
+}
+
+impl SigningPreview {
+    pub fn new(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<SigningPreview, UnlockError> {
+        let mut inputs_capacity = Capacity::zero();
+        let mut spent_capacity = Capacity::zero();
+        for (idx, input) in tx.inputs().into_iter().enumerate() {
+            let output = tx_dep_provider.get_cell(&input.previous_output())?;
+            let capacity: Capacity = output.capacity().unpack();
+            inputs_capacity = inputs_capacity
+                .safe_add(capacity)
+                .map_err(anyhow::Error::from)?;
+            if script_group.input_indices.contains(&idx) {
+                spent_capacity = spent_capacity
+                    .safe_add(capacity)
+                    .map_err(anyhow::Error::from)?;
+            }
+        }
+        let outputs_capacity = tx.outputs_capacity().map_err(anyhow::Error::from)?;
+        let fee = inputs_capacity
+            .safe_sub(outputs_capacity)
+            .map_err(anyhow::Error::from)?;
+        let fee_share = if inputs_capacity.as_u64() == 0 {
+            0
+        } else {
+            (fee.as_u64() as u128 * spent_capacity.as_u64() as u128
+                / inputs_capacity.as_u64() as u128) as u64
+        };
+        let destinations = tx
+            .outputs_with_data_iter()
+            .enumerate()
+            .map(|(idx, (output, data))| {
+                let capacity: u64 = output.capacity().unpack();
+                PreviewDestination {
+                    output_index: idx as u32,
+                    is_change: output.lock() == script_group.script,
+                    lock: output.lock().into(),
+                    type_: output.type_().to_opt().map(Into::into),
+                    capacity: capacity.into(),
+                    data_len: data.len() as u32,
+                }
+            })
+            .collect();
+        Ok(SigningPreview {
+            spent_capacity: spent_capacity.as_u64().into(),
+            destinations,
+            fee: fee.as_u64().into(),
+            fee_share: fee_share.into(),
+        })
+    }
+
+    /// The capacity sent to other locks
+    pub fn sent_capacity(&self) -> u64 {
+        self.destinations
+            .iter()
+            .filter(|destination| !destination.is_change)
+            .map(|destination| destination.capacity.value())
+            .sum()
+    }
+}
+
+/// The message a lock script group signs with its preview
+#[derive(Debug, Clone)]
+pub struct SigningEntry {
+    pub script_group: ScriptGroup,
+    /// The layout of the group witness, an empty witness is signed as
+    /// `WitnessArgs`
+    pub layout: WitnessLayout,
+    /// The signing message, see [`generate_message`] and
+    /// [`cobuild_signing_message`]
+    pub message: Bytes,
+    pub preview: SigningPreview,
+}
+
+impl SigningEntry {
+    /// `zero_lock` is the witness lock placeholder of the group, e.g. 65 zero
+    /// bytes for sighash, it is not used for a cobuild witness
+    pub fn new(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        zero_lock: Bytes,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<SigningEntry, UnlockError> {
+        let witness = tx
+            .witnesses()
+            .get(script_group.input_indices[0])
+            .map(|witness| witness.raw_data())
+            .unwrap_or_default();
+        let layout = WitnessLayout::detect(&witness)
+            .map_err(ScriptSignError::UnsupportedWitnessLayout)?
+            .unwrap_or(WitnessLayout::WitnessArgs);
+        let message = match CobuildSighashWitness::parse(&witness) {
+            Some(cobuild_witness) if layout == WitnessLayout::Cobuild => {
+                let input_cells = tx_dep_provider
+                    .get_cells_with_data(&tx.input_pts_iter().collect::<Vec<_>>())?;
+                let message =
+                    cobuild_signing_message(tx, cobuild_witness.message.as_deref(), &input_cells);
+                Bytes::from(message.to_vec())
+            }
+            _ => generate_message(tx, script_group, zero_lock)?,
+        };
+        Ok(SigningEntry {
+            script_group: script_group.clone(),
+            layout,
+            message,
+            preview: SigningPreview::new(tx, script_group, tx_dep_provider)?,
+        })
+    }
+}
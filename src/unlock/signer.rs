@@ -1,30 +1,41 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
 
 use anyhow::anyhow;
-use ckb_hash::{blake2b_256, new_blake2b};
+use ckb_hash::blake2b_256;
 use ckb_types::{
     bytes::{Bytes, BytesMut},
     core::{ScriptHashType, TransactionView},
     error::VerificationError,
+    molecule::hex_string,
     packed::{self, BytesOpt, Script, WitnessArgs},
     prelude::*,
-    H160,
+    H160, H256,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{constants::MULTISIG_TYPE_HASH, types::omni_lock::OmniLockWitnessLock};
+use crate::hash::{default_backend, Blake2bBackend, DomainSeparatedBackend, HashBackend};
 use crate::{
+    constants::{MULTISIG_TYPE_HASH, SECP_SIGNATURE_SIZE},
+    types::omni_lock::OmniLockWitnessLock,
+};
+use crate::{
+    secp,
     traits::{Signer, SignerError},
-    util::convert_keccak256_hash,
+    util::{blake160, convert_keccak256_hash},
 };
 use crate::{
-    types::{AddressPayload, CodeHashIndex, ScriptGroup, Since},
+    types::{
+        AddressPayload, CodeHashIndex, HtlcAction, HtlcArgs, LockHashPrefix, ScriptGroup, Since,
+    },
     Address, NetworkType,
 };
 
 use super::{
     omni_lock::{ConfigError, Identity},
+    witness_layout::{detect_witness_layout, WitnessLayoutKind},
     IdentityFlag, OmniLockConfig,
 };
 
@@ -39,6 +50,9 @@ pub enum ScriptSignError {
     #[error("the witness is not empty and not WitnessArgs format: `{0}`")]
     InvalidWitnessArgs(#[from] VerificationError),
 
+    #[error("the witness is in `{0:?}` layout, which this signer does not support")]
+    UnsupportedWitnessLayout(WitnessLayoutKind),
+
     #[error("the Omni lock witness lock field is invalid: `{0}`")]
     InvalidOmniLockWitnessLock(String),
 
@@ -48,13 +62,153 @@ pub enum ScriptSignError {
     #[error("there already too many signatures in current WitnessArgs.lock field (old_count + new_count > threshold)")]
     TooManySignatures,
 
+    #[error("the script group has no input")]
+    EmptyScriptGroup,
+
+    #[error("script group input index `{0}` is out of bound `{1}`")]
+    InputIndexOutOfBound(usize, usize),
+
     #[error("there is an configuration error: `{0}`")]
     InvalidConfig(#[from] ConfigError),
 
+    #[error("signing with key `{owner_id}` is not authorized: {reason}")]
+    Unauthorized { owner_id: String, reason: String },
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// Parse a non-empty witness as `WitnessArgs`, a cobuild witness is reported
+/// by its layout instead of a molecule verification error.
+fn parse_witness_args(witness_data: &[u8]) -> Result<WitnessArgs, ScriptSignError> {
+    WitnessArgs::from_slice(witness_data).map_err(|err| match detect_witness_layout(witness_data) {
+        WitnessLayoutKind::Unknown => ScriptSignError::InvalidWitnessArgs(err),
+        kind => ScriptSignError::UnsupportedWitnessLayout(kind),
+    })
+}
+
+/// Put a 65 bytes `signature` into the first empty signature slot of a
+/// multisig `lock_field`, the slots start at `offset`. Nothing changes if
+/// the signature is already in a slot.
+pub fn fill_signature_slot(
+    lock_field: &mut [u8],
+    offset: usize,
+    signature: &[u8],
+) -> Result<(), ScriptSignError> {
+    if signature.len() != 65 {
+        return Err(ScriptSignError::Other(anyhow!(
+            "invalid signature length: {}, expected: 65",
+            signature.len()
+        )));
+    }
+    let slots = lock_field
+        .get_mut(offset..)
+        .ok_or(ScriptSignError::TooManySignatures)?;
+    for slot in slots.chunks_exact_mut(65) {
+        if &slot[..] == signature {
+            return Ok(());
+        } else if slot.iter().all(|byte| *byte == 0) {
+            slot.copy_from_slice(signature);
+            return Ok(());
+        }
+    }
+    Err(ScriptSignError::TooManySignatures)
+}
+
+/// Decide whether a key may sign for a script group.
+///
+/// Services holding the keys of many customers in one [`Signer`] set it on
+/// the script signers, it's consulted before every signature, so a build bug
+/// can't make the wallet sign with the key of another customer.
+pub trait SigningAuthorizer: Send + Sync {
+    /// `owner_id` is the key id given to the [`Signer`], the blake160 hash of
+    /// the public key for the secp256k1 locks. `Err` tells why it's denied.
+    fn authorize(
+        &self,
+        owner_id: &[u8],
+        script_group: &ScriptGroup,
+        tx: &TransactionView,
+    ) -> Result<(), String>;
+}
+
+impl<F> SigningAuthorizer for F
+where
+    F: Fn(&[u8], &ScriptGroup, &TransactionView) -> Result<(), String> + Send + Sync,
+{
+    fn authorize(
+        &self,
+        owner_id: &[u8],
+        script_group: &ScriptGroup,
+        tx: &TransactionView,
+    ) -> Result<(), String> {
+        self(owner_id, script_group, tx)
+    }
+}
+
+/// Choose the bytes of the witnesses beyond the transaction inputs (the
+/// "outter witnesses") covered by the sighash message.
+///
+/// The system lock scripts hash all of them whole, see
+/// [`CoverAllOuterWitnesses`]. A lock interpreting the extra witnesses
+/// differently, e.g. as proofs added after signing, sets its own strategy on
+/// the script signer.
+pub trait OuterWitnessStrategy: Send + Sync {
+    /// The bytes of the witness at `index` to hash, `None` skips the witness
+    fn covered_bytes<'w>(
+        &self,
+        tx: &TransactionView,
+        index: usize,
+        witness: &'w [u8],
+    ) -> Option<&'w [u8]>;
+}
+
+/// Hash every outter witness, what the system lock scripts do
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CoverAllOuterWitnesses;
+
+impl OuterWitnessStrategy for CoverAllOuterWitnesses {
+    fn covered_bytes<'w>(
+        &self,
+        _tx: &TransactionView,
+        _index: usize,
+        witness: &'w [u8],
+    ) -> Option<&'w [u8]> {
+        Some(witness)
+    }
+}
+
+/// Hash none of the outter witnesses, they can change after signing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipOuterWitnesses;
+
+impl OuterWitnessStrategy for SkipOuterWitnesses {
+    fn covered_bytes<'w>(
+        &self,
+        _tx: &TransactionView,
+        _index: usize,
+        _witness: &'w [u8],
+    ) -> Option<&'w [u8]> {
+        None
+    }
+}
+
+fn check_authorized(
+    authorizer: Option<&Arc<dyn SigningAuthorizer>>,
+    owner_id: &[u8],
+    script_group: &ScriptGroup,
+    tx: &TransactionView,
+) -> Result<(), ScriptSignError> {
+    match authorizer {
+        Some(authorizer) => authorizer
+            .authorize(owner_id, script_group, tx)
+            .map_err(|reason| ScriptSignError::Unauthorized {
+                owner_id: format!("0x{}", hex_string(owner_id)),
+                reason,
+            }),
+        None => Ok(()),
+    }
+}
+
 /// Script signer logic:
 ///   * Generate message to sign
 ///   * Sign the message by wallet
@@ -68,17 +222,60 @@ pub trait ScriptSigner {
         tx: &TransactionView,
         script_group: &ScriptGroup,
     ) -> Result<TransactionView, ScriptSignError>;
+
+    /// The length of the witness lock field filled by this signer for a lock
+    /// script with `args`, `None` if it can not be known before signing.
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        None
+    }
 }
 
 /// Signer for secp256k1 sighash all lock script
 pub struct SecpSighashScriptSigner {
     // Can be: SecpCkbRawKeySigner, HardwareWalletSigner
     signer: Box<dyn Signer>,
+    hash_backend: Arc<dyn HashBackend>,
+    outer_witnesses: Arc<dyn OuterWitnessStrategy>,
+    authorizer: Option<Arc<dyn SigningAuthorizer>>,
 }
 
 impl SecpSighashScriptSigner {
     pub fn new(signer: Box<dyn Signer>) -> SecpSighashScriptSigner {
-        SecpSighashScriptSigner { signer }
+        SecpSighashScriptSigner {
+            signer,
+            hash_backend: default_backend(),
+            outer_witnesses: Arc::new(CoverAllOuterWitnesses),
+            authorizer: None,
+        }
+    }
+
+    /// Use `hash_backend` to generate the signing message
+    pub fn with_hash_backend(mut self, hash_backend: Arc<dyn HashBackend>) -> Self {
+        self.hash_backend = hash_backend;
+        self
+    }
+
+    /// Bind the signing message to the chain of `genesis_hash`, only for
+    /// locks hashing the message with a [`DomainSeparatedBackend`].
+    pub fn with_domain_separator(self, genesis_hash: &H256) -> Self {
+        let hash_backend = DomainSeparatedBackend::new(self.hash_backend.clone(), genesis_hash.0);
+        self.with_hash_backend(Arc::new(hash_backend))
+    }
+
+    /// Cover the outter witnesses chosen by `outer_witnesses` in the signing
+    /// message
+    pub fn with_outer_witness_strategy(
+        mut self,
+        outer_witnesses: Arc<dyn OuterWitnessStrategy>,
+    ) -> Self {
+        self.outer_witnesses = outer_witnesses;
+        self
+    }
+
+    /// Ask `authorizer` before signing
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn SigningAuthorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
     }
 
     pub fn signer(&self) -> &dyn Signer {
@@ -91,19 +288,20 @@ impl SecpSighashScriptSigner {
         tx: &TransactionView,
         script_group: &ScriptGroup,
     ) -> Result<TransactionView, ScriptSignError> {
-        let witness_idx = script_group.input_indices[0];
-        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-        while witnesses.len() <= witness_idx {
-            witnesses.push(Default::default());
-        }
-        let tx_new = tx
-            .as_advanced_builder()
-            .set_witnesses(witnesses.clone())
-            .build();
-
-        let zero_lock = Bytes::from(vec![0u8; 65]);
-        let message = generate_message(&tx_new, script_group, zero_lock)?;
-
+        let witness_idx = witness_index_for_group(tx, script_group)?;
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
+
+        let zero_lock = Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]);
+        let message = generate_message_with_witnesses(
+            tx,
+            &witnesses,
+            script_group,
+            zero_lock,
+            self.hash_backend.as_ref(),
+            self.outer_witnesses.as_ref(),
+        )?;
+
+        check_authorized(self.authorizer.as_ref(), owner_id, script_group, tx)?;
         let signature = self.signer.sign(owner_id, message.as_ref(), true, tx)?;
 
         // Put signature into witness
@@ -111,7 +309,7 @@ impl SecpSighashScriptSigner {
         let mut current_witness: WitnessArgs = if witness_data.is_empty() {
             WitnessArgs::default()
         } else {
-            WitnessArgs::from_slice(witness_data.as_ref())?
+            parse_witness_args(witness_data.as_ref())?
         };
         current_witness = current_witness
             .as_builder()
@@ -135,6 +333,10 @@ impl ScriptSigner for SecpSighashScriptSigner {
         let args = script_group.script.args().raw_data();
         self.sign_tx_with_owner_id(args.as_ref(), tx, script_group)
     }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(SECP_SIGNATURE_SIZE)
+    }
 }
 
 #[derive(Eq, PartialEq, Clone, Hash, Serialize, Deserialize, Debug)]
@@ -229,12 +431,23 @@ impl MultisigConfig {
         witness_data
     }
 
-    pub fn placeholder_witness(&self) -> WitnessArgs {
+    /// The length of the witness lock: the config followed by `threshold`
+    /// signatures
+    pub fn witness_lock_len(&self) -> usize {
+        4 + 20 * self.sighash_addresses.len() + SECP_SIGNATURE_SIZE * self.threshold as usize
+    }
+
+    /// The witness lock with the config and all signatures zeroed
+    pub fn zero_lock(&self) -> Bytes {
         let config_data = self.to_witness_data();
-        let mut zero_lock = vec![0u8; config_data.len() + 65 * self.threshold() as usize];
+        let mut zero_lock = vec![0u8; self.witness_lock_len()];
         zero_lock[0..config_data.len()].copy_from_slice(config_data.as_ref());
+        Bytes::from(zero_lock)
+    }
+
+    pub fn placeholder_witness(&self) -> WitnessArgs {
         WitnessArgs::new_builder()
-            .lock(Some(Bytes::from(zero_lock)).pack())
+            .lock(Some(self.zero_lock()).pack())
             .build()
     }
 
@@ -244,6 +457,26 @@ impl MultisigConfig {
     }
 }
 
+impl fmt::Display for MultisigConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-of-{} multisig {:#x} (require first {}): [",
+            self.threshold,
+            self.sighash_addresses.len(),
+            self.hash160(),
+            self.require_first_n
+        )?;
+        for (idx, address) in self.sighash_addresses.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x}", address)?;
+        }
+        write!(f, "]")
+    }
+}
+
 impl From<&MultisigConfig> for Script {
     fn from(value: &MultisigConfig) -> Self {
         Script::new_builder()
@@ -260,6 +493,9 @@ pub struct SecpMultisigScriptSigner {
     signer: Box<dyn Signer>,
     config: MultisigConfig,
     config_hash: [u8; 32],
+    hash_backend: Arc<dyn HashBackend>,
+    outer_witnesses: Arc<dyn OuterWitnessStrategy>,
+    authorizer: Option<Arc<dyn SigningAuthorizer>>,
 }
 impl SecpMultisigScriptSigner {
     pub fn new(signer: Box<dyn Signer>, config: MultisigConfig) -> SecpMultisigScriptSigner {
@@ -268,88 +504,106 @@ impl SecpMultisigScriptSigner {
             signer,
             config,
             config_hash,
+            hash_backend: default_backend(),
+            outer_witnesses: Arc::new(CoverAllOuterWitnesses),
+            authorizer: None,
         }
     }
+    /// Use `hash_backend` to generate the signing message
+    pub fn with_hash_backend(mut self, hash_backend: Arc<dyn HashBackend>) -> Self {
+        self.hash_backend = hash_backend;
+        self
+    }
+    /// Bind the signing message to the chain of `genesis_hash`, every
+    /// participant must sign with the same domain.
+    pub fn with_domain_separator(self, genesis_hash: &H256) -> Self {
+        let hash_backend = DomainSeparatedBackend::new(self.hash_backend.clone(), genesis_hash.0);
+        self.with_hash_backend(Arc::new(hash_backend))
+    }
+    /// Cover the outter witnesses chosen by `outer_witnesses` in the signing
+    /// message, every participant must sign with the same strategy.
+    pub fn with_outer_witness_strategy(
+        mut self,
+        outer_witnesses: Arc<dyn OuterWitnessStrategy>,
+    ) -> Self {
+        self.outer_witnesses = outer_witnesses;
+        self
+    }
+    /// Ask `authorizer` before signing with each key of the config, a denied
+    /// key fails the whole signing.
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn SigningAuthorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
     pub fn signer(&self) -> &dyn Signer {
         self.signer.as_ref()
     }
     pub fn config(&self) -> &MultisigConfig {
         &self.config
     }
-}
 
-impl ScriptSigner for SecpMultisigScriptSigner {
-    fn match_args(&self, args: &[u8]) -> bool {
-        self.config_hash[0..20] == args[0..20]
-            && self
-                .config
-                .sighash_addresses
-                .iter()
-                .any(|id| self.signer.match_id(id.as_bytes()))
-    }
-
-    fn sign_tx(
+    /// Sign with every backend matching an address of the multisig config.
+    ///
+    /// Backends failed to sign (e.g. a disconnected hardware wallet) do not
+    /// fail the whole signing, their errors are returned along with the
+    /// transaction carrying the signatures of the other backends. It only
+    /// fails when no signature is produced, while `sign_tx` fails on any
+    /// backend error.
+    pub fn sign_tx_partial(
         &self,
         tx: &TransactionView,
         script_group: &ScriptGroup,
-    ) -> Result<TransactionView, ScriptSignError> {
-        let witness_idx = script_group.input_indices[0];
-        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-        while witnesses.len() <= witness_idx {
-            witnesses.push(Default::default());
-        }
-        let tx_new = tx
-            .as_advanced_builder()
-            .set_witnesses(witnesses.clone())
-            .build();
+    ) -> Result<(TransactionView, Vec<(H160, SignerError)>), ScriptSignError> {
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
 
         let config_data = self.config.to_witness_data();
-        let mut zero_lock = vec![0u8; config_data.len() + 65 * (self.config.threshold as usize)];
-        zero_lock[0..config_data.len()].copy_from_slice(&config_data);
-        let message = generate_message(&tx_new, script_group, Bytes::from(zero_lock.clone()))?;
-
-        let signatures = self
-            .config
-            .sighash_addresses
-            .iter()
-            .filter(|id| self.signer.match_id(id.as_bytes()))
-            .map(|id| self.signer.sign(id.as_bytes(), message.as_ref(), true, tx))
-            .collect::<Result<Vec<_>, SignerError>>()?;
+        let zero_lock = self.config.zero_lock();
+        let message = generate_message_with_witnesses(
+            tx,
+            &witnesses,
+            script_group,
+            zero_lock.clone(),
+            self.hash_backend.as_ref(),
+            self.outer_witnesses.as_ref(),
+        )?;
+
+        let mut signatures = Vec::new();
+        let mut failures = Vec::new();
+        for id in &self.config.sighash_addresses {
+            if !self.signer.match_id(id.as_bytes()) {
+                continue;
+            }
+            check_authorized(self.authorizer.as_ref(), id.as_bytes(), script_group, tx)?;
+            match self.signer.sign(id.as_bytes(), message.as_ref(), true, tx) {
+                Ok(signature) => signatures.push(signature),
+                Err(err) => failures.push((id.clone(), err)),
+            }
+        }
+        if signatures.is_empty() && !failures.is_empty() {
+            return Err(failures.remove(0).1.into());
+        }
         // Put signature into witness
-        let witness_idx = script_group.input_indices[0];
+        let witness_idx = witness_index_for_group(tx, script_group)?;
         let witness_data = witnesses[witness_idx].raw_data();
         let mut current_witness: WitnessArgs = if witness_data.is_empty() {
             WitnessArgs::default()
         } else {
-            WitnessArgs::from_slice(witness_data.as_ref())?
+            parse_witness_args(witness_data.as_ref())?
         };
         let mut lock_field = current_witness
             .lock()
             .to_opt()
             .map(|data| data.raw_data().as_ref().to_vec())
-            .unwrap_or(zero_lock);
-        if lock_field.len() != config_data.len() + self.config.threshold() as usize * 65 {
+            .unwrap_or_else(|| zero_lock.to_vec());
+        if lock_field.len() != self.config.witness_lock_len() {
             return Err(ScriptSignError::Other(anyhow!(
                 "invalid witness lock field length: {}, expected: {}",
                 lock_field.len(),
-                config_data.len() + self.config.threshold() as usize * 65,
+                self.config.witness_lock_len(),
             )));
         }
         for signature in signatures {
-            let mut idx = config_data.len();
-            while idx < lock_field.len() {
-                // Put signature into an empty place.
-                if lock_field[idx..idx + 65] == signature {
-                    break;
-                } else if lock_field[idx..idx + 65] == [0u8; 65] {
-                    lock_field[idx..idx + 65].copy_from_slice(signature.as_ref());
-                    break;
-                }
-                idx += 65;
-            }
-            if idx >= lock_field.len() {
-                return Err(ScriptSignError::TooManySignatures);
-            }
+            fill_signature_slot(&mut lock_field, config_data.len(), signature.as_ref())?;
         }
 
         current_witness = current_witness
@@ -357,7 +611,39 @@ impl ScriptSigner for SecpMultisigScriptSigner {
             .lock(Some(Bytes::from(lock_field)).pack())
             .build();
         witnesses[witness_idx] = current_witness.as_bytes().pack();
-        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+        Ok((
+            tx.as_advanced_builder().set_witnesses(witnesses).build(),
+            failures,
+        ))
+    }
+}
+
+impl ScriptSigner for SecpMultisigScriptSigner {
+    fn match_args(&self, args: &[u8]) -> bool {
+        LockHashPrefix::from_hash_bytes(&self.config_hash).matches_args(args, 0)
+            && self
+                .config
+                .sighash_addresses
+                .iter()
+                .any(|id| self.signer.match_id(id.as_bytes()))
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<TransactionView, ScriptSignError> {
+        // any failed backend fails the signing, see `sign_tx_partial`
+        let (tx, mut failures) = self.sign_tx_partial(tx, script_group)?;
+        if failures.is_empty() {
+            Ok(tx)
+        } else {
+            Err(failures.remove(0).1.into())
+        }
+    }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(self.config.witness_lock_len())
     }
 }
 
@@ -370,6 +656,12 @@ impl AcpScriptSigner {
         let sighash_signer = SecpSighashScriptSigner::new(signer);
         AcpScriptSigner { sighash_signer }
     }
+
+    /// Ask `authorizer` before signing
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn SigningAuthorizer>) -> Self {
+        self.sighash_signer = self.sighash_signer.with_authorizer(authorizer);
+        self
+    }
 }
 
 impl ScriptSigner for AcpScriptSigner {
@@ -390,6 +682,10 @@ impl ScriptSigner for AcpScriptSigner {
         self.sighash_signer
             .sign_tx_with_owner_id(id, tx, script_group)
     }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(SECP_SIGNATURE_SIZE)
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -421,6 +717,11 @@ impl ChequeScriptSigner {
     pub fn action(&self) -> ChequeAction {
         self.action
     }
+    /// Ask `authorizer` before signing
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn SigningAuthorizer>) -> Self {
+        self.sighash_signer = self.sighash_signer.with_authorizer(authorizer);
+        self
+    }
 }
 
 impl ScriptSigner for ChequeScriptSigner {
@@ -439,73 +740,282 @@ impl ScriptSigner for ChequeScriptSigner {
         self.sighash_signer
             .sign_tx_with_owner_id(id, tx, script_group)
     }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(SECP_SIGNATURE_SIZE)
+    }
+}
+
+/// Pad the witnesses with empty ones until every input of the script group
+/// has a witness.
+///
+/// The lock script hashes the witnesses of all the group inputs, so they must
+/// exist when the message is signed: a witness appended later (e.g. while
+/// signing a group with a larger input index) would change the message.
+pub fn pad_group_witnesses(witnesses: &mut Vec<packed::Bytes>, script_group: &ScriptGroup) {
+    if let Some(max_idx) = script_group.input_indices.iter().max() {
+        while witnesses.len() <= *max_idx {
+            witnesses.push(Default::default());
+        }
+    }
+}
+
+/// The index of the witness holding the lock of `script_group`, the index
+/// of its first input. Fails when the group has no input or the input is not
+/// in `tx`.
+pub fn witness_index_for_group(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Result<usize, ScriptSignError> {
+    let witness_idx = script_group
+        .input_indices
+        .first()
+        .copied()
+        .ok_or(ScriptSignError::EmptyScriptGroup)?;
+    let inputs_len = tx.inputs().len();
+    if witness_idx >= inputs_len {
+        return Err(ScriptSignError::InputIndexOutOfBound(
+            witness_idx,
+            inputs_len,
+        ));
+    }
+    Ok(witness_idx)
+}
+
+/// The witnesses of `tx`, padded with empty witnesses so that every input
+/// of `script_group` has one, see [`pad_group_witnesses`]. Set them back
+/// with `set_witnesses` after filling the lock.
+pub fn ensure_witness_capacity(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+) -> Vec<packed::Bytes> {
+    let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
+    pad_group_witnesses(&mut witnesses, script_group);
+    witnesses
 }
 
 /// Common logic of generate message for certain script group. Overwrite
 /// this method to support special use case.
+///
+/// The missing witnesses of the group inputs are treated as empty, the same
+/// as after [`pad_group_witnesses`].
 pub fn generate_message(
     tx: &TransactionView,
     script_group: &ScriptGroup,
     zero_lock: Bytes,
 ) -> Result<Bytes, ScriptSignError> {
-    if tx.witnesses().item_count() <= script_group.input_indices[0] {
-        return Err(ScriptSignError::WitnessNotEnough);
+    generate_message_with_backend(tx, script_group, zero_lock, &Blake2bBackend::default())
+}
+
+/// Same as [`generate_message`], but hash the message with `hash_backend`.
+pub fn generate_message_with_backend(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    hash_backend: &dyn HashBackend,
+) -> Result<Bytes, ScriptSignError> {
+    generate_message_with_strategy(
+        tx,
+        script_group,
+        zero_lock,
+        hash_backend,
+        &CoverAllOuterWitnesses,
+    )
+}
+
+/// Same as [`generate_message_with_backend`], but only cover the outter
+/// witnesses chosen by `outer_witnesses`.
+pub fn generate_message_with_strategy(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    hash_backend: &dyn HashBackend,
+    outer_witnesses: &dyn OuterWitnessStrategy,
+) -> Result<Bytes, ScriptSignError> {
+    let tx_data = tx.data();
+    let witnesses = tx_data.as_reader().witnesses();
+    hash_message(
+        tx,
+        witnesses.len(),
+        |idx| witnesses.get(idx).map(|witness| witness.raw_data()),
+        script_group,
+        zero_lock,
+        hash_backend,
+        outer_witnesses,
+    )
+}
+
+/// Signer for hash time locked cells, see [`HtlcArgs`]
+pub struct HtlcScriptSigner {
+    signer: Box<dyn Signer>,
+    action: HtlcAction,
+}
+impl HtlcScriptSigner {
+    pub fn new(signer: Box<dyn Signer>, action: HtlcAction) -> HtlcScriptSigner {
+        HtlcScriptSigner { signer, action }
+    }
+    pub fn action(&self) -> &HtlcAction {
+        &self.action
+    }
+}
+
+impl ScriptSigner for HtlcScriptSigner {
+    fn match_args(&self, args: &[u8]) -> bool {
+        match HtlcArgs::from_slice(args) {
+            Some(htlc_args) => {
+                let preimage_matched = match &self.action {
+                    HtlcAction::Claim { preimage } => htlc_args.match_preimage(preimage),
+                    HtlcAction::Refund => true,
+                };
+                preimage_matched
+                    && self
+                        .signer
+                        .match_id(self.action.owner(&htlc_args).as_bytes())
+            }
+            None => false,
+        }
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<TransactionView, ScriptSignError> {
+        let args = script_group.script.args().raw_data();
+        let htlc_args = HtlcArgs::from_slice(&args).ok_or_else(|| {
+            ScriptSignError::Other(anyhow!("invalid htlc args length: {}", args.len()))
+        })?;
+        let witness_idx = witness_index_for_group(tx, script_group)?;
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
+        let message = generate_message_with_witnesses(
+            tx,
+            &witnesses,
+            script_group,
+            self.action.zero_lock(),
+            &Blake2bBackend::default(),
+            &CoverAllOuterWitnesses,
+        )?;
+        let owner = self.action.owner(&htlc_args);
+        let signature = self
+            .signer
+            .sign(owner.as_bytes(), message.as_ref(), true, tx)?;
+
+        let witness_data = witnesses[witness_idx].raw_data();
+        let current_witness: WitnessArgs = if witness_data.is_empty() {
+            WitnessArgs::default()
+        } else {
+            parse_witness_args(witness_data.as_ref())?
+        };
+        witnesses[witness_idx] = current_witness
+            .as_builder()
+            .lock(Some(self.action.witness_lock(&signature)).pack())
+            .build()
+            .as_bytes()
+            .pack();
+        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
     }
 
-    let witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-    let witness_data = witnesses[script_group.input_indices[0]].raw_data();
-    let mut init_witness = if witness_data.is_empty() {
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(self.action.zero_lock().len())
+    }
+}
+
+/// Check `signature` is made by the key of `pubkey_hash` over the message of
+/// `script_group` bound to the chain of `genesis_hash`. `zero_lock` is the lock
+/// placeholder the message was generated with: 65 zero bytes for sighash, the
+/// multisig config followed by the zeroed signatures for multisig.
+///
+/// Returns `false` when the signature was made for another chain.
+pub fn verify_domain_separated_signature(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    genesis_hash: &H256,
+    signature: &[u8],
+    pubkey_hash: &H160,
+) -> Result<bool, ScriptSignError> {
+    let hash_backend = DomainSeparatedBackend::with_default(genesis_hash.0);
+    let message = generate_message_with_backend(tx, script_group, zero_lock, &hash_backend)?;
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(message.as_ref());
+    Ok(match secp::recover_pubkey(&digest, signature) {
+        Ok(pubkey) => &blake160(&pubkey) == pubkey_hash,
+        Err(_) => false,
+    })
+}
+
+/// Generate message with `witnesses` instead of the witnesses in `tx`, so the
+/// signers can pad the witnesses without rebuilding the transaction.
+fn generate_message_with_witnesses(
+    tx: &TransactionView,
+    witnesses: &[packed::Bytes],
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    hash_backend: &dyn HashBackend,
+    outer_witnesses: &dyn OuterWitnessStrategy,
+) -> Result<Bytes, ScriptSignError> {
+    hash_message(
+        tx,
+        witnesses.len(),
+        |idx| {
+            witnesses
+                .get(idx)
+                .map(|witness| witness.as_reader().raw_data())
+        },
+        script_group,
+        zero_lock,
+        hash_backend,
+        outer_witnesses,
+    )
+}
+
+/// Feed the witnesses to the hasher directly from the borrowed witness data.
+fn hash_message<'a, F>(
+    tx: &TransactionView,
+    witnesses_len: usize,
+    get_witness: F,
+    script_group: &ScriptGroup,
+    zero_lock: Bytes,
+    hash_backend: &dyn HashBackend,
+    outer_witnesses: &dyn OuterWitnessStrategy,
+) -> Result<Bytes, ScriptSignError>
+where
+    F: Fn(usize) -> Option<&'a [u8]>,
+{
+    let witness_data = script_group
+        .input_indices
+        .first()
+        .and_then(|idx| get_witness(*idx))
+        .unwrap_or_default();
+    let init_witness = if witness_data.is_empty() {
         WitnessArgs::default()
     } else {
-        WitnessArgs::from_slice(witness_data.as_ref())?
-    };
-    init_witness = init_witness
-        .as_builder()
-        .lock(Some(zero_lock).pack())
-        .build();
-    // Other witnesses in current script group
-    let other_witnesses: Vec<([u8; 8], Bytes)> = script_group
+        parse_witness_args(witness_data)?
+    }
+    .as_builder()
+    .lock(Some(zero_lock).pack())
+    .build();
+
+    let mut blake2b = hash_backend.new_hasher();
+    blake2b.update(tx.hash().as_slice());
+    blake2b.update(&(init_witness.as_slice().len() as u64).to_le_bytes());
+    blake2b.update(init_witness.as_slice());
+    // Other witnesses in current script group, a missing one is padded as empty
+    let other_witnesses = script_group
         .input_indices
         .iter()
         .skip(1)
-        .filter_map(|idx| witnesses.get(*idx))
-        .map(|witness| {
-            (
-                (witness.item_count() as u64).to_le_bytes(),
-                witness.raw_data(),
-            )
-        })
-        .collect();
-    // The witnesses not covered by any inputs
-    let outter_witnesses: Vec<([u8; 8], Bytes)> = if tx.inputs().len() < witnesses.len() {
-        witnesses[tx.inputs().len()..witnesses.len()]
-            .iter()
-            .map(|witness| {
-                (
-                    (witness.item_count() as u64).to_le_bytes(),
-                    witness.raw_data(),
-                )
-            })
-            .collect()
-    } else {
-        Default::default()
-    };
-
-    let mut blake2b = new_blake2b();
-    blake2b.update(tx.hash().as_slice());
-    blake2b.update(&(init_witness.as_bytes().len() as u64).to_le_bytes());
-    blake2b.update(&init_witness.as_bytes());
-    for (len_le, data) in other_witnesses {
-        blake2b.update(&len_le);
-        blake2b.update(&data);
+        .map(|idx| get_witness(*idx).unwrap_or_default());
+    // The witnesses not covered by any inputs, as chosen by the strategy
+    let outter_witnesses = (tx.inputs().len()..witnesses_len).filter_map(|idx| {
+        get_witness(idx).and_then(|data| outer_witnesses.covered_bytes(tx, idx, data))
+    });
+    for data in other_witnesses.chain(outter_witnesses) {
+        blake2b.update(&(data.len() as u64).to_le_bytes());
+        blake2b.update(data);
     }
-    for (len_le, data) in outter_witnesses {
-        blake2b.update(&len_le);
-        blake2b.update(&data);
-    }
-    let mut message = vec![0u8; 32];
-    blake2b.finalize(&mut message);
-    Ok(Bytes::from(message))
+    let message = blake2b.finalize();
+    Ok(Bytes::from(message.to_vec()))
 }
 
 /// specify the unlock mode for a omnilock transaction.
@@ -522,6 +1032,7 @@ pub struct OmniLockScriptSigner {
     signer: Box<dyn Signer>,
     config: OmniLockConfig,
     unlock_mode: OmniUnlockMode,
+    outer_witnesses: Arc<dyn OuterWitnessStrategy>,
 }
 
 impl OmniLockScriptSigner {
@@ -534,8 +1045,18 @@ impl OmniLockScriptSigner {
             signer,
             config,
             unlock_mode,
+            outer_witnesses: Arc::new(CoverAllOuterWitnesses),
         }
     }
+    /// Cover the outter witnesses chosen by `outer_witnesses` in the signing
+    /// message
+    pub fn with_outer_witness_strategy(
+        mut self,
+        outer_witnesses: Arc<dyn OuterWitnessStrategy>,
+    ) -> Self {
+        self.outer_witnesses = outer_witnesses;
+        self
+    }
     pub fn signer(&self) -> &dyn Signer {
         self.signer.as_ref()
     }
@@ -552,19 +1073,18 @@ impl OmniLockScriptSigner {
         tx: &TransactionView,
         script_group: &ScriptGroup,
     ) -> Result<TransactionView, ScriptSignError> {
-        let witness_idx = script_group.input_indices[0];
-        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-        while witnesses.len() <= witness_idx {
-            witnesses.push(Default::default());
-        }
-        let tx_new = tx
-            .as_advanced_builder()
-            .set_witnesses(witnesses.clone())
-            .build();
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
 
         let zero_lock = self.config.zero_lock(self.unlock_mode)?;
         let zero_lock_len = zero_lock.len();
-        let message = generate_message(&tx_new, script_group, zero_lock)?;
+        let message = generate_message_with_witnesses(
+            tx,
+            &witnesses,
+            script_group,
+            zero_lock,
+            &Blake2bBackend::default(),
+            self.outer_witnesses.as_ref(),
+        )?;
 
         let multisig_config = match self.unlock_mode {
             OmniUnlockMode::Admin => self
@@ -582,12 +1102,12 @@ impl OmniLockScriptSigner {
             .map(|id| self.signer.sign(id.as_bytes(), message.as_ref(), true, tx))
             .collect::<Result<Vec<_>, SignerError>>()?;
         // Put signature into witness
-        let witness_idx = script_group.input_indices[0];
+        let witness_idx = witness_index_for_group(tx, script_group)?;
         let witness_data = witnesses[witness_idx].raw_data();
         let mut current_witness: WitnessArgs = if witness_data.is_empty() {
             WitnessArgs::default()
         } else {
-            WitnessArgs::from_slice(witness_data.as_ref())?
+            parse_witness_args(witness_data.as_ref())?
         };
         let lock_field = current_witness.lock().to_opt().map(|data| data.raw_data());
         let omnilock_witnesslock = if let Some(lock_field) = lock_field {
@@ -607,27 +1127,18 @@ impl OmniLockScriptSigner {
             .signature()
             .to_opt()
             .map(|data| data.raw_data().as_ref().to_vec())
-            .unwrap_or_else(|| {
-                let mut omni_sig =
-                    vec![0u8; config_data.len() + multisig_config.threshold() as usize * 65];
-                omni_sig[..config_data.len()].copy_from_slice(&config_data);
-                omni_sig
-            });
+            .unwrap_or_else(|| multisig_config.zero_lock().to_vec());
+        if omni_sig.len() != multisig_config.witness_lock_len()
+            || omni_sig[..config_data.len()] != config_data[..]
+        {
+            return Err(ScriptSignError::InvalidOmniLockWitnessLock(format!(
+                "multisig signatures not match the config, length: {}, expected: {}",
+                omni_sig.len(),
+                multisig_config.witness_lock_len(),
+            )));
+        }
         for signature in signatures {
-            let mut idx = config_data.len();
-            while idx < omni_sig.len() {
-                // Put signature into an empty place.
-                if omni_sig[idx..idx + 65] == signature {
-                    break;
-                } else if omni_sig[idx..idx + 65] == [0u8; 65] {
-                    omni_sig[idx..idx + 65].copy_from_slice(signature.as_ref());
-                    break;
-                }
-                idx += 65;
-            }
-            if idx >= omni_sig.len() {
-                return Err(ScriptSignError::TooManySignatures);
-            }
+            fill_signature_slot(&mut omni_sig, config_data.len(), signature.as_ref())?;
         }
         let lock = omnilock_witnesslock
             .as_builder()
@@ -646,18 +1157,18 @@ impl OmniLockScriptSigner {
         script_group: &ScriptGroup,
         id: &Identity,
     ) -> Result<TransactionView, ScriptSignError> {
-        let witness_idx = script_group.input_indices[0];
-        let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-        while witnesses.len() <= witness_idx {
-            witnesses.push(Default::default());
-        }
-        let tx_new = tx
-            .as_advanced_builder()
-            .set_witnesses(witnesses.clone())
-            .build();
+        let witness_idx = witness_index_for_group(tx, script_group)?;
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
 
         let zero_lock = self.config.zero_lock(self.unlock_mode())?;
-        let message = generate_message(&tx_new, script_group, zero_lock)?;
+        let message = generate_message_with_witnesses(
+            tx,
+            &witnesses,
+            script_group,
+            zero_lock,
+            &Blake2bBackend::default(),
+            self.outer_witnesses.as_ref(),
+        )?;
         let message = convert_keccak256_hash(message.as_ref());
 
         let signature = self
@@ -669,7 +1180,7 @@ impl OmniLockScriptSigner {
         let mut current_witness: WitnessArgs = if witness_data.is_empty() {
             WitnessArgs::default()
         } else {
-            WitnessArgs::from_slice(witness_data.as_ref())?
+            parse_witness_args(witness_data.as_ref())?
         };
 
         let lock = Self::build_witness_lock(current_witness.lock(), signature)?;
@@ -768,18 +1279,18 @@ impl ScriptSigner for OmniLockScriptSigner {
         };
         match id.flag() {
             IdentityFlag::PubkeyHash => {
-                let witness_idx = script_group.input_indices[0];
-                let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
-                while witnesses.len() <= witness_idx {
-                    witnesses.push(Default::default());
-                }
-                let tx_new = tx
-                    .as_advanced_builder()
-                    .set_witnesses(witnesses.clone())
-                    .build();
+                let witness_idx = witness_index_for_group(tx, script_group)?;
+                let mut witnesses = ensure_witness_capacity(tx, script_group);
 
                 let zero_lock = self.config.zero_lock(self.unlock_mode)?;
-                let message = generate_message(&tx_new, script_group, zero_lock)?;
+                let message = generate_message_with_witnesses(
+                    tx,
+                    &witnesses,
+                    script_group,
+                    zero_lock,
+                    &Blake2bBackend::default(),
+                    self.outer_witnesses.as_ref(),
+                )?;
 
                 let signature =
                     self.signer
@@ -790,7 +1301,7 @@ impl ScriptSigner for OmniLockScriptSigner {
                 let mut current_witness: WitnessArgs = if witness_data.is_empty() {
                     WitnessArgs::default()
                 } else {
-                    WitnessArgs::from_slice(witness_data.as_ref())?
+                    parse_witness_args(witness_data.as_ref())?
                 };
 
                 let lock = Self::build_witness_lock(current_witness.lock(), signature)?;
@@ -810,6 +1321,20 @@ impl ScriptSigner for OmniLockScriptSigner {
             }
         }
     }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        match self.config.id().flag() {
+            IdentityFlag::PubkeyHash | IdentityFlag::Ethereum | IdentityFlag::Multisig => {}
+            // without the admin config, the owner lock doesn't need a witness lock
+            IdentityFlag::OwnerLock if self.config.get_admin_config().is_none() => return Some(0),
+            IdentityFlag::OwnerLock => {}
+            _ => return None,
+        }
+        self.config
+            .zero_lock(self.unlock_mode)
+            .ok()
+            .map(|lock| lock.len())
+    }
 }
 
 #[cfg(test)]
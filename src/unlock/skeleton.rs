@@ -0,0 +1,249 @@
+//! A fast path to sign many similar sighash transactions with one key.
+//!
+//! [`SecpSighashScriptSigner`](super::SecpSighashScriptSigner) parses the
+//! group witness, rebuilds it with a zero lock for the signing message, then
+//! rebuilds it again with the signature and the whole transaction through
+//! `as_advanced_builder`. A [`WitnessSkeleton`] is the zero lock witness
+//! serialized once, the signature is copied into a copy of its bytes and
+//! only the witnesses of the transaction are replaced.
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use ckb_types::{
+    bytes::{Bytes, BytesMut},
+    core::TransactionView,
+    packed::{self, WitnessArgs},
+    prelude::*,
+    H160,
+};
+
+use super::{witness_index_for_group, ScriptSignError, ScriptSigner};
+use crate::constants::SECP_SIGNATURE_SIZE;
+use crate::hash::{default_backend, HashBackend};
+use crate::traits::SignerError;
+use crate::types::ScriptGroup;
+use crate::{secp, util::blake160, SECP256K1};
+
+/// The offset of the lock bytes in a `WitnessArgs` with a lock: the total
+/// size, the three field offsets and the lock length
+const LOCK_OFFSET: usize = 4 * 4 + 4;
+
+/// A sighash `WitnessArgs` with a zero lock, serialized once
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WitnessSkeleton {
+    data: Bytes,
+}
+
+impl WitnessSkeleton {
+    pub fn new(input_type: Option<Bytes>, output_type: Option<Bytes>) -> WitnessSkeleton {
+        let witness = WitnessArgs::new_builder()
+            .input_type(input_type.pack())
+            .output_type(output_type.pack())
+            .build();
+        WitnessSkeleton::from_witness_args(&witness)
+    }
+
+    /// The skeleton of `witness`, its lock is replaced by a zero lock
+    pub fn from_witness_args(witness: &WitnessArgs) -> WitnessSkeleton {
+        let data = witness
+            .clone()
+            .as_builder()
+            .lock(Some(Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE])).pack())
+            .build()
+            .as_bytes();
+        WitnessSkeleton { data }
+    }
+
+    /// The serialized `WitnessArgs` with the zero lock
+    pub fn zero_lock_bytes(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// The serialized `WitnessArgs` with `signature` as the lock
+    pub fn signed(&self, signature: &[u8; SECP_SIGNATURE_SIZE]) -> Bytes {
+        let mut data = BytesMut::from(self.data.as_ref());
+        data[LOCK_OFFSET..LOCK_OFFSET + SECP_SIGNATURE_SIZE].copy_from_slice(signature);
+        data.freeze()
+    }
+}
+
+/// Sign sighash transactions with one secret key and one witness skeleton,
+/// e.g. for a payout service. The signatures are the same as the ones of
+/// [`SecpSighashScriptSigner`](super::SecpSighashScriptSigner) when the
+/// group witness is the skeleton and all the outter witnesses are covered.
+pub struct SighashSkeletonSigner {
+    secret_key: [u8; 32],
+    lock_arg: H160,
+    skeleton: WitnessSkeleton,
+    hash_backend: Arc<dyn HashBackend>,
+}
+
+impl SighashSkeletonSigner {
+    pub fn new(secret_key: &secp256k1::SecretKey, skeleton: WitnessSkeleton) -> Self {
+        let pubkey = secp256k1::PublicKey::from_secret_key(&SECP256K1, secret_key);
+        SighashSkeletonSigner {
+            secret_key: secret_key.secret_bytes(),
+            lock_arg: blake160(&pubkey.serialize()),
+            skeleton,
+            hash_backend: default_backend(),
+        }
+    }
+
+    /// Use `hash_backend` to generate the signing message
+    pub fn with_hash_backend(mut self, hash_backend: Arc<dyn HashBackend>) -> Self {
+        self.hash_backend = hash_backend;
+        self
+    }
+
+    /// The sighash lock args of the secret key
+    pub fn lock_arg(&self) -> &H160 {
+        &self.lock_arg
+    }
+
+    pub fn skeleton(&self) -> &WitnessSkeleton {
+        &self.skeleton
+    }
+
+    /// Sign `script_group` of `tx`, the first witness of the group is
+    /// replaced by the signed skeleton, the missing ones are padded as
+    /// empty. Call `into_view` on the result only when the view is needed.
+    pub fn sign(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<packed::Transaction, ScriptSignError> {
+        if script_group.script.args().raw_data().as_ref() != self.lock_arg.as_bytes() {
+            return Err(SignerError::IdNotFound.into());
+        }
+        let witness_idx = witness_index_for_group(tx, script_group)?;
+        let tx_data = tx.data();
+        let witnesses = tx_data.witnesses();
+        let witnesses_len = script_group
+            .input_indices
+            .iter()
+            .max()
+            .map(|idx| witnesses.len().max(idx + 1))
+            .unwrap_or_else(|| witnesses.len());
+        let witness_data = |idx: usize| {
+            witnesses
+                .as_reader()
+                .get(idx)
+                .map(|witness| witness.raw_data())
+                .unwrap_or_default()
+        };
+
+        let zero_lock_witness = self.skeleton.zero_lock_bytes();
+        let mut hasher = self.hash_backend.new_hasher();
+        hasher.update(tx.hash().as_slice());
+        hasher.update(&(zero_lock_witness.len() as u64).to_le_bytes());
+        hasher.update(zero_lock_witness);
+        let other_witnesses = script_group.input_indices.iter().skip(1).copied();
+        for idx in other_witnesses.chain(tx.inputs().len()..witnesses_len) {
+            let data = witness_data(idx);
+            hasher.update(&(data.len() as u64).to_le_bytes());
+            hasher.update(data);
+        }
+        let message = hasher.finalize();
+        let signature = secp::sign_recoverable(&self.secret_key, &message)
+            .map_err(|err| SignerError::Other(anyhow!(err)))?;
+
+        let signed_witness = self.skeleton.signed(&signature).pack();
+        let new_witnesses = (0..witnesses_len).map(|idx| {
+            if idx == witness_idx {
+                signed_witness.clone()
+            } else {
+                witnesses.get(idx).unwrap_or_default()
+            }
+        });
+        Ok(tx_data
+            .as_builder()
+            .witnesses(
+                packed::BytesVec::new_builder()
+                    .extend(new_witnesses)
+                    .build(),
+            )
+            .build())
+    }
+}
+
+impl ScriptSigner for SighashSkeletonSigner {
+    fn match_args(&self, args: &[u8]) -> bool {
+        args == self.lock_arg.as_bytes()
+    }
+
+    fn sign_tx(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<TransactionView, ScriptSignError> {
+        self.sign(tx, script_group).map(|tx| tx.into_view())
+    }
+
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        Some(SECP_SIGNATURE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::SIGHASH_TYPE_HASH, traits::SecpCkbRawKeySigner, types::ScriptGroupType,
+        unlock::SecpSighashScriptSigner,
+    };
+    use ckb_types::{
+        core::{ScriptHashType, TransactionBuilder},
+        packed::{CellInput, OutPoint, Script},
+        H256,
+    };
+
+    #[test]
+    fn test_sighash_skeleton_signer() {
+        let key = secp256k1::SecretKey::from_slice(&[7u8; 32]).unwrap();
+        let skeleton = WitnessSkeleton::new(None, Some(Bytes::from(vec![9u8; 4])));
+        let signer = SighashSkeletonSigner::new(&key, skeleton.clone());
+        let lock = Script::new_builder()
+            .code_hash(SIGHASH_TYPE_HASH.pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(Bytes::from(signer.lock_arg().as_bytes().to_vec()).pack())
+            .build();
+        let input = |idx: u8| CellInput::new(OutPoint::new(H256([idx; 32]).pack(), 0), 0);
+        // the group is inputs 0 and 2, with an outter witness
+        let tx = TransactionBuilder::default()
+            .inputs(vec![input(0), input(1), input(2)])
+            .witnesses(vec![
+                skeleton.zero_lock_bytes().pack(),
+                Bytes::from(vec![1u8; 3]).pack(),
+                Bytes::new().pack(),
+                Bytes::from(vec![2u8; 5]).pack(),
+            ])
+            .build();
+        let mut script_group = ScriptGroup::new(&lock, ScriptGroupType::Lock);
+        script_group.input_indices = vec![0, 2];
+
+        let expected = SecpSighashScriptSigner::new(Box::new(
+            SecpCkbRawKeySigner::new_with_secret_keys(vec![key]),
+        ))
+        .sign_tx(&tx, &script_group)
+        .unwrap();
+        let signed = signer.sign(&tx, &script_group).unwrap();
+        assert_eq!(signed.as_slice(), expected.data().as_slice());
+        let witness =
+            WitnessArgs::from_slice(&signed.witnesses().get(0).unwrap().raw_data()).unwrap();
+        assert_eq!(
+            witness.output_type(),
+            Some(Bytes::from(vec![9u8; 4])).pack()
+        );
+
+        let mut other_group = script_group.clone();
+        other_group.script = lock
+            .as_builder()
+            .args(Bytes::from(vec![0u8; 20]).pack())
+            .build();
+        assert!(matches!(
+            signer.sign(&tx, &other_group),
+            Err(ScriptSignError::Signer(SignerError::IdNotFound))
+        ));
+    }
+}
@@ -10,13 +10,19 @@ use thiserror::Error;
 use super::{
     omni_lock::{ConfigError, OmniLockFlags},
     signer::{
-        AcpScriptSigner, ChequeAction, ChequeScriptSigner, MultisigConfig, ScriptSignError,
-        ScriptSigner, SecpMultisigScriptSigner, SecpSighashScriptSigner,
+        ensure_witness_capacity, witness_index_for_group, AcpScriptSigner, ChequeAction,
+        ChequeScriptSigner, HtlcScriptSigner, MultisigConfig, ScriptSignError, ScriptSigner,
+        SecpMultisigScriptSigner, SecpSighashScriptSigner,
+    },
+    witness_layout::{
+        cobuild_signing_message, detect_witness_layout, negotiate_witness_layout,
+        CobuildSighashWitness, WitnessLayout, WitnessLayoutMode,
     },
     OmniLockConfig, OmniLockScriptSigner, OmniUnlockMode,
 };
+use crate::constants::SECP_SIGNATURE_SIZE;
 use crate::traits::{Signer, TransactionDependencyError, TransactionDependencyProvider};
-use crate::types::ScriptGroup;
+use crate::types::{HtlcAction, HtlcArgs, LockHashPrefix, ScriptGroup, Since};
 
 const CHEQUE_CLAIM_SINCE: u64 = 0;
 const CHEQUE_WITHDRAW_SINCE: u64 = 0xA000000000000006;
@@ -38,6 +44,9 @@ pub enum UnlockError {
     #[error("sign context is incorrect")]
     SignContextTypeIncorrect,
 
+    #[error("witness field is already set to other data: witness index=`{0}`")]
+    WitnessFieldConflict(usize),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -85,6 +94,19 @@ pub trait ScriptUnlocker {
         script_group: &ScriptGroup,
         tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError>;
+
+    /// The length of the witness lock field after unlocking a script with
+    /// `args`, see [`ScriptSigner::placeholder_lock_len`].
+    fn placeholder_lock_len(&self, _args: &[u8]) -> Option<usize> {
+        None
+    }
+
+    /// The witness layouts this unlocker can sign, see
+    /// [`negotiate_witness_layout`]. An unlocker signing cobuild witnesses
+    /// must include [`WitnessLayout::Cobuild`].
+    fn witness_layouts(&self) -> &[WitnessLayout] {
+        &[WitnessLayout::WitnessArgs]
+    }
 }
 
 pub fn fill_witness_lock(
@@ -92,21 +114,83 @@ pub fn fill_witness_lock(
     script_group: &ScriptGroup,
     lock_field: Bytes,
 ) -> Result<TransactionView, UnlockError> {
-    let witness_idx = script_group.input_indices[0];
+    let witness_idx = witness_index_for_group(tx, script_group)?;
+    let mut witnesses = ensure_witness_capacity(tx, script_group);
+    let witness_data = witnesses[witness_idx].raw_data();
+    let mut witness = if witness_data.is_empty() {
+        WitnessArgs::default()
+    } else {
+        WitnessArgs::from_slice(witness_data.as_ref())
+            .map_err(|_| UnlockError::InvalidWitnessArgs(witness_idx))?
+    };
+    if witness.lock().is_none() {
+        witness = witness.as_builder().lock(Some(lock_field).pack()).build();
+    }
+    witnesses[witness_idx] = witness.as_bytes().pack();
+    Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
+}
+
+/// A type script field of `WitnessArgs`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TypeWitnessField {
+    /// Read by the type script of an input, at the first input index of the
+    /// type script group
+    InputType,
+    /// Read by the type script of an output, at the first output index of the
+    /// type script group
+    OutputType,
+}
+
+/// Set the `input_type` or `output_type` field of the witness a type script
+/// group reads.
+///
+/// The other fields of an existing witness at the index are kept, e.g. the
+/// lock of a lock script group whose first input is the same. Setting a field
+/// already holding other data fails with
+/// [`UnlockError::WitnessFieldConflict`]. The lock signatures cover the
+/// witnesses, so fill the type fields before unlocking.
+pub fn fill_witness_type(
+    tx: &TransactionView,
+    script_group: &ScriptGroup,
+    field: TypeWitnessField,
+    data: Bytes,
+) -> Result<TransactionView, UnlockError> {
+    let indices = match field {
+        TypeWitnessField::InputType => &script_group.input_indices,
+        TypeWitnessField::OutputType => &script_group.output_indices,
+    };
+    let witness_idx = *indices.first().ok_or_else(|| {
+        UnlockError::Other(anyhow!(
+            "script group has no {:?} index: {}",
+            field,
+            script_group.script
+        ))
+    })?;
     let mut witnesses: Vec<packed::Bytes> = tx.witnesses().into_iter().collect();
     while witnesses.len() <= witness_idx {
         witnesses.push(Default::default());
     }
     let witness_data = witnesses[witness_idx].raw_data();
-    let mut witness = if witness_data.is_empty() {
+    let witness = if witness_data.is_empty() {
         WitnessArgs::default()
     } else {
         WitnessArgs::from_slice(witness_data.as_ref())
             .map_err(|_| UnlockError::InvalidWitnessArgs(witness_idx))?
     };
-    if witness.lock().is_none() {
-        witness = witness.as_builder().lock(Some(lock_field).pack()).build();
+    let current = match field {
+        TypeWitnessField::InputType => witness.input_type().to_opt(),
+        TypeWitnessField::OutputType => witness.output_type().to_opt(),
+    };
+    if let Some(current) = current {
+        if current.raw_data() != data {
+            return Err(UnlockError::WitnessFieldConflict(witness_idx));
+        }
+    }
+    let witness = match field {
+        TypeWitnessField::InputType => witness.as_builder().input_type(Some(data).pack()),
+        TypeWitnessField::OutputType => witness.as_builder().output_type(Some(data).pack()),
     }
+    .build();
     witnesses[witness_idx] = witness.as_bytes().pack();
     Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
 }
@@ -140,10 +224,60 @@ pub fn reset_witness_lock(
 
 pub struct SecpSighashUnlocker {
     signer: SecpSighashScriptSigner,
+    witness_layout: WitnessLayoutMode,
 }
 impl SecpSighashUnlocker {
     pub fn new(signer: SecpSighashScriptSigner) -> SecpSighashUnlocker {
-        SecpSighashUnlocker { signer }
+        SecpSighashUnlocker {
+            signer,
+            witness_layout: WitnessLayoutMode::default(),
+        }
+    }
+
+    /// Override the detection of the witness layout
+    pub fn with_witness_layout(mut self, witness_layout: WitnessLayoutMode) -> Self {
+        self.witness_layout = witness_layout;
+        self
+    }
+
+    /// Negotiate the layout of the group witness, an empty witness is signed
+    /// as `WitnessArgs` unless the mode is `Cobuild`.
+    fn is_cobuild(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<bool, UnlockError> {
+        let witness = tx
+            .witnesses()
+            .get(witness_index_for_group(tx, script_group)?)
+            .map(|witness| witness.raw_data())
+            .unwrap_or_default();
+        let preferred = match self.witness_layout {
+            WitnessLayoutMode::Cobuild => WitnessLayout::Cobuild,
+            _ => WitnessLayout::WitnessArgs,
+        };
+        let layout = negotiate_witness_layout(&witness, self.witness_layouts(), preferred)
+            .map_err(ScriptSignError::UnsupportedWitnessLayout)?;
+        Ok(layout == WitnessLayout::Cobuild)
+    }
+
+    /// Parse the cobuild witness of the script group, an empty witness
+    /// becomes a `SighashAllOnly` witness.
+    fn cobuild_witness(
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+    ) -> Result<(Vec<packed::Bytes>, CobuildSighashWitness), UnlockError> {
+        let witness_idx = witness_index_for_group(tx, script_group)?;
+        let mut witnesses = ensure_witness_capacity(tx, script_group);
+        let witness_data = witnesses[witness_idx].raw_data();
+        let witness = if witness_data.is_empty() {
+            CobuildSighashWitness::new_sighash_all_only(Bytes::new())
+        } else {
+            CobuildSighashWitness::parse(&witness_data).ok_or_else(|| {
+                ScriptSignError::UnsupportedWitnessLayout(detect_witness_layout(&witness_data))
+            })?
+        };
+        Ok((witnesses, witness))
     }
 }
 impl From<Box<dyn Signer>> for SecpSighashUnlocker {
@@ -156,13 +290,41 @@ impl ScriptUnlocker for SecpSighashUnlocker {
         self.signer.match_args(args)
     }
 
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
+    fn witness_layouts(&self) -> &[WitnessLayout] {
+        match self.witness_layout {
+            WitnessLayoutMode::Auto => &WitnessLayout::ALL,
+            WitnessLayoutMode::WitnessArgs => &[WitnessLayout::WitnessArgs],
+            WitnessLayoutMode::Cobuild => &[WitnessLayout::Cobuild],
+        }
+    }
+
     fn unlock(
         &self,
         tx: &TransactionView,
         script_group: &ScriptGroup,
-        _tx_dep_provider: &dyn TransactionDependencyProvider,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError> {
-        Ok(self.signer.sign_tx(tx, script_group)?)
+        if !self.is_cobuild(tx, script_group)? {
+            return Ok(self.signer.sign_tx(tx, script_group)?);
+        }
+        let (mut witnesses, mut witness) = Self::cobuild_witness(tx, script_group)?;
+        // the cobuild signing message covers all the input cells
+        let input_cells =
+            tx_dep_provider.get_cells_with_data(&tx.input_pts_iter().collect::<Vec<_>>())?;
+        let message = cobuild_signing_message(tx, witness.message.as_deref(), &input_cells);
+        let args = script_group.script.args().raw_data();
+        let signature = self
+            .signer
+            .signer()
+            .sign(args.as_ref(), &message, true, tx)
+            .map_err(ScriptSignError::from)?;
+        witness.seal = signature;
+        witnesses[script_group.input_indices[0]] = witness.to_bytes().pack();
+        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
     }
 
     fn fill_placeholder_witness(
@@ -171,7 +333,19 @@ impl ScriptUnlocker for SecpSighashUnlocker {
         script_group: &ScriptGroup,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError> {
-        fill_witness_lock(tx, script_group, Bytes::from(vec![0u8; 65]))
+        if !self.is_cobuild(tx, script_group)? {
+            return fill_witness_lock(
+                tx,
+                script_group,
+                Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]),
+            );
+        }
+        let (mut witnesses, mut witness) = Self::cobuild_witness(tx, script_group)?;
+        if witness.seal.is_empty() {
+            witness.seal = Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]);
+        }
+        witnesses[script_group.input_indices[0]] = witness.to_bytes().pack();
+        Ok(tx.as_advanced_builder().set_witnesses(witnesses).build())
     }
 }
 
@@ -193,6 +367,10 @@ impl ScriptUnlocker for SecpMultisigUnlocker {
         (args.len() == 20 || args.len() == 28) && self.signer.match_args(args)
     }
 
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
     fn unlock(
         &self,
         tx: &TransactionView,
@@ -208,11 +386,7 @@ impl ScriptUnlocker for SecpMultisigUnlocker {
         script_group: &ScriptGroup,
         _tx_dep_provider: &dyn TransactionDependencyProvider,
     ) -> Result<TransactionView, UnlockError> {
-        let config = self.signer.config();
-        let config_data = config.to_witness_data();
-        let mut zero_lock = vec![0u8; config_data.len() + 65 * (config.threshold() as usize)];
-        zero_lock[0..config_data.len()].copy_from_slice(&config_data);
-        fill_witness_lock(tx, script_group, Bytes::from(zero_lock))
+        fill_witness_lock(tx, script_group, self.signer.config().zero_lock())
     }
 }
 
@@ -407,6 +581,10 @@ impl ScriptUnlocker for AcpUnlocker {
         self.signer.match_args(args)
     }
 
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
     fn is_unlocked(
         &self,
         tx: &TransactionView,
@@ -456,11 +634,72 @@ impl ScriptUnlocker for AcpUnlocker {
         if self.is_unlocked(tx, script_group, tx_dep_provider)? {
             Ok(tx.clone())
         } else {
-            fill_witness_lock(tx, script_group, Bytes::from(vec![0u8; 65]))
+            fill_witness_lock(
+                tx,
+                script_group,
+                Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]),
+            )
         }
     }
 }
 
+pub struct HtlcUnlocker {
+    signer: HtlcScriptSigner,
+}
+impl HtlcUnlocker {
+    pub fn new(signer: HtlcScriptSigner) -> HtlcUnlocker {
+        HtlcUnlocker { signer }
+    }
+}
+impl From<(Box<dyn Signer>, HtlcAction)> for HtlcUnlocker {
+    fn from((signer, action): (Box<dyn Signer>, HtlcAction)) -> HtlcUnlocker {
+        HtlcUnlocker::new(HtlcScriptSigner::new(signer, action))
+    }
+}
+
+impl ScriptUnlocker for HtlcUnlocker {
+    fn match_args(&self, args: &[u8]) -> bool {
+        self.signer.match_args(args)
+    }
+
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
+    fn unlock(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        let args = script_group.script.args().raw_data();
+        if let (HtlcAction::Refund, Some(htlc_args)) =
+            (self.signer.action(), HtlcArgs::from_slice(&args))
+        {
+            let inputs: Vec<_> = tx.inputs().into_iter().collect();
+            if script_group.input_indices.iter().any(|idx| {
+                let since: u64 = inputs[*idx].since().unpack();
+                since != htlc_args.timeout_since
+            }) {
+                return Err(UnlockError::Other(anyhow!(
+                    "refund action must have the timeout since ({}) in htlc inputs",
+                    Since::from_raw_value(htlc_args.timeout_since)
+                )));
+            }
+        }
+        Ok(self.signer.sign_tx(tx, script_group)?)
+    }
+
+    fn fill_placeholder_witness(
+        &self,
+        tx: &TransactionView,
+        script_group: &ScriptGroup,
+        _tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<TransactionView, UnlockError> {
+        fill_witness_lock(tx, script_group, self.signer.action().zero_lock())
+    }
+}
+
 pub struct ChequeUnlocker {
     signer: ChequeScriptSigner,
 }
@@ -480,6 +719,10 @@ impl ScriptUnlocker for ChequeUnlocker {
         self.signer.match_args(args)
     }
 
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
     fn is_unlocked(
         &self,
         tx: &TransactionView,
@@ -501,14 +744,13 @@ impl ScriptUnlocker for ChequeUnlocker {
             .collect();
 
         // Check if unlocked via lock hash in inputs
-        let receiver_lock_hash = &args.as_ref()[0..20];
-        let sender_lock_hash = &args.as_ref()[20..40];
+        let receiver_lock_hash = LockHashPrefix::from_args(&args, 0).unwrap();
+        let sender_lock_hash = LockHashPrefix::from_args(&args, 20).unwrap();
         let mut receiver_lock_witness = None;
         let mut sender_lock_witness = None;
         for (input_idx, input) in inputs.into_iter().enumerate() {
             let output = tx_dep_provider.get_cell(&input.previous_output())?;
-            let lock_hash = output.lock().calc_script_hash();
-            let lock_hash_prefix = &lock_hash.as_slice()[0..20];
+            let lock_hash_prefix = LockHashPrefix::from_script(&output.lock());
             let witness = tx
                 .witnesses()
                 .get(input_idx)
@@ -554,7 +796,8 @@ impl ScriptUnlocker for ChequeUnlocker {
                 .any(|since| *since != CHEQUE_WITHDRAW_SINCE)
             {
                 return Err(UnlockError::Other(anyhow!(
-                    "withdraw action must have all relative 6 epochs since in cheque inputs"
+                    "withdraw action must have the since ({}) in all cheque inputs",
+                    Since::from_raw_value(CHEQUE_WITHDRAW_SINCE)
                 )));
             }
             let witness_args = match WitnessArgs::from_slice(witness.as_ref()) {
@@ -602,7 +845,11 @@ impl ScriptUnlocker for ChequeUnlocker {
         if self.is_unlocked(tx, script_group, tx_dep_provider)? {
             Ok(tx.clone())
         } else {
-            fill_witness_lock(tx, script_group, Bytes::from(vec![0u8; 65]))
+            fill_witness_lock(
+                tx,
+                script_group,
+                Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]),
+            )
         }
     }
 }
@@ -629,6 +876,10 @@ impl ScriptUnlocker for OmniLockUnlocker {
         self.signer.match_args(args)
     }
 
+    fn placeholder_lock_len(&self, args: &[u8]) -> Option<usize> {
+        self.signer.placeholder_lock_len(args)
+    }
+
     /// Check if the script group is already unlocked
     fn is_unlocked(
         &self,
@@ -690,9 +941,8 @@ impl ScriptUnlocker for OmniLockUnlocker {
             .filter(|(idx, _input)| !script_group.input_indices.contains(idx))
             .any(|(_idx, input)| {
                 if let Ok(output) = tx_dep_provider.get_cell(&input.previous_output()) {
-                    let lock_hash = output.calc_lock_hash();
-                    let h = &lock_hash.as_slice()[0..20];
-                    h == auth_content.as_bytes()
+                    LockHashPrefix::from(auth_content.clone())
+                        .matches_hash(&output.calc_lock_hash())
                 } else {
                     false
                 }
@@ -0,0 +1,215 @@
+use std::thread;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{Script, WitnessArgs},
+    prelude::*,
+    H160,
+};
+
+use super::{generate_message, UnlockError};
+use crate::constants::{MULTISIG_TYPE_HASH, SECP_SIGNATURE_SIZE, SIGHASH_TYPE_HASH};
+use crate::secp;
+use crate::traits::TransactionDependencyProvider;
+use crate::tx_builder::gen_script_groups;
+use crate::types::{LockHashPrefix, ScriptGroup, ScriptId};
+use crate::util::blake160;
+
+/// The result of the check of a signature slot
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SlotStatus {
+    /// Signed by an expected key
+    Valid(H160),
+    /// Signed by a key which is not allowed by the lock, or by a key which
+    /// already signed
+    WrongSigner(H160),
+    /// The witness or the signature is malformed
+    Invalid(String),
+}
+
+/// A filled signature slot of a sighash or multisig lock group
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignatureSlot {
+    pub lock_script: Script,
+    /// The witness of the first input of the group
+    pub witness_index: usize,
+    /// The index of the signature in the witness lock, always 0 for sighash
+    pub slot: usize,
+    pub status: SlotStatus,
+}
+
+impl SignatureSlot {
+    pub fn is_valid(&self) -> bool {
+        matches!(self.status, SlotStatus::Valid(_))
+    }
+}
+
+/// Check every filled signature slot of the sighash and multisig lock groups
+/// of `tx`, the groups are checked in parallel. The zeroed slots are not
+/// reported, a malformed witness is reported as an invalid slot 0.
+///
+/// The slots are sorted by witness index then slot index.
+pub fn verify_all_signatures(
+    tx: &TransactionView,
+    tx_dep_provider: &dyn TransactionDependencyProvider,
+) -> Result<Vec<SignatureSlot>, UnlockError> {
+    let sighash_id = ScriptId::new_type(SIGHASH_TYPE_HASH.clone());
+    let multisig_id = ScriptId::new_type(MULTISIG_TYPE_HASH.clone());
+    let groups = gen_script_groups(tx, tx_dep_provider)?
+        .lock_groups
+        .into_values()
+        .filter(|group| sighash_id.matches(&group.script) || multisig_id.matches(&group.script))
+        .collect::<Vec<_>>();
+    let results = thread::scope(|scope| {
+        let handles = groups
+            .iter()
+            .map(|group| {
+                let is_sighash = sighash_id.matches(&group.script);
+                scope.spawn(move || {
+                    if is_sighash {
+                        verify_sighash_group(tx, group)
+                    } else {
+                        verify_multisig_group(tx, group)
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("verify signatures thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    let mut slots = Vec::new();
+    for result in results {
+        slots.extend(result?);
+    }
+    slots.sort_by_key(|slot| (slot.witness_index, slot.slot));
+    Ok(slots)
+}
+
+/// The witness lock of the group, `Err` for a malformed witness
+fn group_witness_lock(tx: &TransactionView, group: &ScriptGroup) -> Result<Bytes, String> {
+    let witness_index = group.input_indices[0];
+    let witness = tx
+        .witnesses()
+        .get(witness_index)
+        .map(|witness| witness.raw_data())
+        .unwrap_or_default();
+    if witness.is_empty() {
+        return Ok(Bytes::new());
+    }
+    let witness_args = WitnessArgs::from_slice(&witness)
+        .map_err(|err| format!("invalid witness args: {}", err))?;
+    Ok(witness_args
+        .lock()
+        .to_opt()
+        .map(|lock| lock.raw_data())
+        .unwrap_or_default())
+}
+
+fn recover_signer(message: &[u8], signature: &[u8]) -> Result<H160, String> {
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(message);
+    secp::recover_pubkey(&digest, signature)
+        .map(|pubkey| blake160(&pubkey))
+        .map_err(|err| err.to_string())
+}
+
+fn verify_sighash_group(
+    tx: &TransactionView,
+    group: &ScriptGroup,
+) -> Result<Vec<SignatureSlot>, UnlockError> {
+    let slot = |status| SignatureSlot {
+        lock_script: group.script.clone(),
+        witness_index: group.input_indices[0],
+        slot: 0,
+        status,
+    };
+    let lock = match group_witness_lock(tx, group) {
+        Ok(lock) => lock,
+        Err(reason) => return Ok(vec![slot(SlotStatus::Invalid(reason))]),
+    };
+    if lock.is_empty() || lock.iter().all(|byte| *byte == 0) {
+        return Ok(Vec::new());
+    }
+    if lock.len() != SECP_SIGNATURE_SIZE {
+        return Ok(vec![slot(SlotStatus::Invalid(format!(
+            "invalid witness lock length: {}",
+            lock.len()
+        )))]);
+    }
+    let message = generate_message(tx, group, Bytes::from(vec![0u8; SECP_SIGNATURE_SIZE]))?;
+    let args = group.script.args().raw_data();
+    let status = match recover_signer(&message, &lock) {
+        Ok(signer) if args.len() >= 20 && signer.as_bytes() == &args[0..20] => {
+            SlotStatus::Valid(signer)
+        }
+        Ok(signer) => SlotStatus::WrongSigner(signer),
+        Err(reason) => SlotStatus::Invalid(reason),
+    };
+    Ok(vec![slot(status)])
+}
+
+fn verify_multisig_group(
+    tx: &TransactionView,
+    group: &ScriptGroup,
+) -> Result<Vec<SignatureSlot>, UnlockError> {
+    let slot = |index, status| SignatureSlot {
+        lock_script: group.script.clone(),
+        witness_index: group.input_indices[0],
+        slot: index,
+        status,
+    };
+    let lock = match group_witness_lock(tx, group) {
+        Ok(lock) => lock,
+        Err(reason) => return Ok(vec![slot(0, SlotStatus::Invalid(reason))]),
+    };
+    if lock.is_empty() {
+        return Ok(Vec::new());
+    }
+    // reserved | require_first_n | threshold | pubkeys_cnt | pubkey_hashes
+    let config_len = if lock.len() >= 4 {
+        4 + 20 * lock[3] as usize
+    } else {
+        usize::MAX
+    };
+    let args = group.script.args().raw_data();
+    if config_len > lock.len() || (lock.len() - config_len) % SECP_SIGNATURE_SIZE != 0 {
+        return Ok(vec![slot(
+            0,
+            SlotStatus::Invalid("invalid multisig witness lock".to_string()),
+        )]);
+    }
+    if !LockHashPrefix::from(blake160(&lock[0..config_len])).matches_args(&args, 0) {
+        return Ok(vec![slot(
+            0,
+            SlotStatus::Invalid("the multisig config doesn't match the lock args".to_string()),
+        )]);
+    }
+    let members = lock[4..config_len]
+        .chunks(20)
+        .map(|hash| H160::from_slice(hash).expect("20 bytes"))
+        .collect::<Vec<_>>();
+    let mut zero_lock = vec![0u8; lock.len()];
+    zero_lock[0..config_len].copy_from_slice(&lock[0..config_len]);
+    let message = generate_message(tx, group, Bytes::from(zero_lock))?;
+
+    let mut signers = Vec::new();
+    let mut slots = Vec::new();
+    for (index, signature) in lock[config_len..].chunks(SECP_SIGNATURE_SIZE).enumerate() {
+        if signature.iter().all(|byte| *byte == 0) {
+            continue;
+        }
+        let status = match recover_signer(&message, signature) {
+            Ok(signer) if members.contains(&signer) && !signers.contains(&signer) => {
+                signers.push(signer.clone());
+                SlotStatus::Valid(signer)
+            }
+            Ok(signer) => SlotStatus::WrongSigner(signer),
+            Err(reason) => SlotStatus::Invalid(reason),
+        };
+        slots.push(slot(index, status));
+    }
+    Ok(slots)
+}
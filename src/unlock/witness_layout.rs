@@ -0,0 +1,405 @@
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{CellOutput, WitnessArgs},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::hash::{Blake2bBackend, HashBackend, HASH_LEN, PERSONALIZATION_LEN};
+
+/// The union item ids of the cobuild `WitnessLayout`
+pub const SIGHASH_ALL_LAYOUT_ID: u32 = 0xFF00_0001;
+pub const SIGHASH_ALL_ONLY_LAYOUT_ID: u32 = 0xFF00_0002;
+pub const OTX_LAYOUT_ID: u32 = 0xFF00_0003;
+pub const OTX_START_LAYOUT_ID: u32 = 0xFF00_0004;
+
+/// The blake2b personalization of the cobuild signing message with a message
+pub const COBUILD_SIGHASH_ALL_PERSONALIZATION: &[u8; PERSONALIZATION_LEN] = b"ckb-tcob-sighash";
+/// The blake2b personalization of the cobuild signing message without a message
+pub const COBUILD_SIGHASH_ALL_ONLY_PERSONALIZATION: &[u8; PERSONALIZATION_LEN] =
+    b"ckb-tcob-sgohash";
+
+/// The layout of a witness
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WitnessLayoutKind {
+    Empty,
+    WitnessArgs,
+    /// Cobuild `SighashAll`, holds the message and the seal
+    SighashAll,
+    /// Cobuild `SighashAllOnly`, holds the seal only
+    SighashAllOnly,
+    /// Cobuild open transaction
+    Otx,
+    /// Cobuild open transaction start marker
+    OtxStart,
+    Unknown,
+}
+
+impl WitnessLayoutKind {
+    pub fn is_cobuild(self) -> bool {
+        matches!(
+            self,
+            WitnessLayoutKind::SighashAll
+                | WitnessLayoutKind::SighashAllOnly
+                | WitnessLayoutKind::Otx
+                | WitnessLayoutKind::OtxStart
+        )
+    }
+}
+
+/// How an unlocker reads the witness of a script group
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum WitnessLayoutMode {
+    /// Sign cobuild `SighashAll`/`SighashAllOnly` witnesses in cobuild mode,
+    /// other witnesses as `WitnessArgs`.
+    #[default]
+    Auto,
+    /// Always treat the witness as `WitnessArgs`
+    WitnessArgs,
+    /// Always sign in cobuild mode, an empty witness becomes `SighashAllOnly`
+    Cobuild,
+}
+
+/// The version of the witness protocol a script group is signed with,
+/// shared by the signers, the builders and the signing preview.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WitnessLayout {
+    /// Version 1, the signature is the lock field of `WitnessArgs`
+    WitnessArgs,
+    /// Version 2, the signature is the seal of a cobuild `SighashAll` or
+    /// `SighashAllOnly` witness
+    Cobuild,
+}
+
+impl WitnessLayout {
+    pub const ALL: [WitnessLayout; 2] = [WitnessLayout::WitnessArgs, WitnessLayout::Cobuild];
+
+    pub fn version(self) -> u8 {
+        match self {
+            WitnessLayout::WitnessArgs => 1,
+            WitnessLayout::Cobuild => 2,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Option<WitnessLayout> {
+        WitnessLayout::ALL
+            .iter()
+            .copied()
+            .find(|layout| layout.version() == version)
+    }
+
+    /// The layout of a filled witness, `Ok(None)` for an empty witness and
+    /// `Err` for a witness no signer can sign (open transactions, unknown).
+    pub fn detect(witness: &[u8]) -> Result<Option<WitnessLayout>, WitnessLayoutKind> {
+        match detect_witness_layout(witness) {
+            WitnessLayoutKind::Empty => Ok(None),
+            WitnessLayoutKind::WitnessArgs => Ok(Some(WitnessLayout::WitnessArgs)),
+            WitnessLayoutKind::SighashAll | WitnessLayoutKind::SighashAllOnly => {
+                Ok(Some(WitnessLayout::Cobuild))
+            }
+            kind => Err(kind),
+        }
+    }
+
+    /// A witness of this layout whose signature is `seal_len` zero bytes
+    pub fn placeholder_witness(self, seal_len: usize) -> Bytes {
+        let seal = Bytes::from(vec![0u8; seal_len]);
+        match self {
+            WitnessLayout::WitnessArgs => WitnessArgs::new_builder()
+                .lock(Some(seal).pack())
+                .build()
+                .as_bytes(),
+            WitnessLayout::Cobuild => CobuildSighashWitness::new_sighash_all_only(seal).to_bytes(),
+        }
+    }
+}
+
+/// Pick the layout to sign a group witness with: the layout of the witness
+/// when it is already filled, otherwise `preferred` when supported,
+/// otherwise the first supported layout.
+///
+/// Fails with the kind of the witness when none of `supported` can sign it.
+pub fn negotiate_witness_layout(
+    witness: &[u8],
+    supported: &[WitnessLayout],
+    preferred: WitnessLayout,
+) -> Result<WitnessLayout, WitnessLayoutKind> {
+    let kind = detect_witness_layout(witness);
+    match WitnessLayout::detect(witness)? {
+        Some(layout) if supported.contains(&layout) => Ok(layout),
+        Some(_) => Err(kind),
+        None if supported.contains(&preferred) => Ok(preferred),
+        None => supported.first().copied().ok_or(kind),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes);
+    Some(u32::from_le_bytes(buf))
+}
+
+/// Split a molecule table into its fields
+fn table_fields(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let total_size = read_u32(data, 0)? as usize;
+    if total_size != data.len() {
+        return None;
+    }
+    if total_size == 4 {
+        return Some(Vec::new());
+    }
+    let first_offset = read_u32(data, 4)? as usize;
+    if first_offset % 4 != 0 || first_offset < 8 || first_offset > total_size {
+        return None;
+    }
+    let mut offsets = (0..first_offset / 4 - 1)
+        .map(|idx| read_u32(data, 4 + idx * 4).map(|offset| offset as usize))
+        .collect::<Option<Vec<_>>>()?;
+    offsets.push(total_size);
+    if offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        return None;
+    }
+    Some(
+        offsets
+            .windows(2)
+            .map(|pair| &data[pair[0]..pair[1]])
+            .collect(),
+    )
+}
+
+fn encode_table(fields: &[&[u8]]) -> Vec<u8> {
+    let header_size = 4 + 4 * fields.len();
+    let total_size = header_size + fields.iter().map(|field| field.len()).sum::<usize>();
+    let mut data = Vec::with_capacity(total_size);
+    data.extend_from_slice(&(total_size as u32).to_le_bytes());
+    let mut offset = header_size;
+    for field in fields {
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    for field in fields {
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+/// Read a molecule `Bytes` (fixvec of byte)
+fn parse_bytes(data: &[u8]) -> Option<&[u8]> {
+    let len = read_u32(data, 0)? as usize;
+    if data.len() != len + 4 {
+        return None;
+    }
+    Some(&data[4..])
+}
+
+fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + 4);
+    encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+/// Detect the layout of a witness. `WitnessArgs` is a table whose first 4
+/// bytes are the witness length, cobuild layouts start with a union item id.
+pub fn detect_witness_layout(witness: &[u8]) -> WitnessLayoutKind {
+    if witness.is_empty() {
+        return WitnessLayoutKind::Empty;
+    }
+    if WitnessArgs::from_slice(witness).is_ok() {
+        return WitnessLayoutKind::WitnessArgs;
+    }
+    let kind = match read_u32(witness, 0) {
+        Some(SIGHASH_ALL_LAYOUT_ID) => WitnessLayoutKind::SighashAll,
+        Some(SIGHASH_ALL_ONLY_LAYOUT_ID) => WitnessLayoutKind::SighashAllOnly,
+        Some(OTX_LAYOUT_ID) => WitnessLayoutKind::Otx,
+        Some(OTX_START_LAYOUT_ID) => WitnessLayoutKind::OtxStart,
+        _ => return WitnessLayoutKind::Unknown,
+    };
+    match kind {
+        WitnessLayoutKind::SighashAll | WitnessLayoutKind::SighashAllOnly => {
+            if CobuildSighashWitness::parse(witness).is_some() {
+                kind
+            } else {
+                WitnessLayoutKind::Unknown
+            }
+        }
+        _ if table_fields(&witness[4..]).is_some() => kind,
+        _ => WitnessLayoutKind::Unknown,
+    }
+}
+
+/// A cobuild `SighashAll` (with message) or `SighashAllOnly` witness
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CobuildSighashWitness {
+    /// The serialized cobuild `Message`, `None` for `SighashAllOnly`
+    pub message: Option<Bytes>,
+    pub seal: Bytes,
+}
+
+impl CobuildSighashWitness {
+    pub fn new_sighash_all_only(seal: Bytes) -> CobuildSighashWitness {
+        CobuildSighashWitness {
+            message: None,
+            seal,
+        }
+    }
+
+    pub fn parse(witness: &[u8]) -> Option<CobuildSighashWitness> {
+        let id = read_u32(witness, 0)?;
+        let fields = table_fields(&witness[4..])?;
+        match (id, fields.as_slice()) {
+            (SIGHASH_ALL_LAYOUT_ID, [message, seal]) => Some(CobuildSighashWitness {
+                message: Some(Bytes::from(message.to_vec())),
+                seal: Bytes::from(parse_bytes(seal)?.to_vec()),
+            }),
+            (SIGHASH_ALL_ONLY_LAYOUT_ID, [seal]) => Some(CobuildSighashWitness {
+                message: None,
+                seal: Bytes::from(parse_bytes(seal)?.to_vec()),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let seal = encode_bytes(&self.seal);
+        let (id, table) = match self.message.as_ref() {
+            Some(message) => (
+                SIGHASH_ALL_LAYOUT_ID,
+                encode_table(&[message.as_ref(), seal.as_slice()]),
+            ),
+            None => (SIGHASH_ALL_ONLY_LAYOUT_ID, encode_table(&[seal.as_slice()])),
+        };
+        let mut data = id.to_le_bytes().to_vec();
+        data.extend_from_slice(&table);
+        Bytes::from(data)
+    }
+}
+
+/// The cobuild signing message of a transaction: the message (if any), the
+/// transaction hash, the input cells and the witnesses not covered by inputs.
+pub fn cobuild_signing_message(
+    tx: &TransactionView,
+    message: Option<&[u8]>,
+    input_cells: &[(CellOutput, Bytes)],
+) -> [u8; HASH_LEN] {
+    let personalization = if message.is_some() {
+        COBUILD_SIGHASH_ALL_PERSONALIZATION
+    } else {
+        COBUILD_SIGHASH_ALL_ONLY_PERSONALIZATION
+    };
+    let mut hasher = Blake2bBackend::new(*personalization).new_hasher();
+    if let Some(message) = message {
+        hasher.update(message);
+    }
+    hasher.update(tx.hash().as_slice());
+    for (output, data) in input_cells {
+        hasher.update(output.as_slice());
+        hasher.update(&(data.len() as u32).to_le_bytes());
+        hasher.update(data);
+    }
+    for witness in tx.witnesses().into_iter().skip(tx.inputs().len()) {
+        let witness = witness.raw_data();
+        hasher.update(&(witness.len() as u32).to_le_bytes());
+        hasher.update(&witness);
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_witness_layout() {
+        let sighash_all = CobuildSighashWitness {
+            message: Some(Bytes::from(encode_table(&[&4u32.to_le_bytes()[..]]))),
+            seal: Bytes::from(vec![1u8; 65]),
+        };
+        let witness = sighash_all.to_bytes();
+        assert_eq!(
+            detect_witness_layout(&witness),
+            WitnessLayoutKind::SighashAll
+        );
+        assert_eq!(CobuildSighashWitness::parse(&witness), Some(sighash_all));
+
+        let sighash_all_only = CobuildSighashWitness::new_sighash_all_only(Bytes::new());
+        let witness = sighash_all_only.to_bytes();
+        assert_eq!(
+            detect_witness_layout(&witness),
+            WitnessLayoutKind::SighashAllOnly
+        );
+        assert_eq!(
+            CobuildSighashWitness::parse(&witness),
+            Some(sighash_all_only)
+        );
+
+        let mut otx = OTX_LAYOUT_ID.to_le_bytes().to_vec();
+        otx.extend_from_slice(&encode_table(&[]));
+        assert_eq!(detect_witness_layout(&otx), WitnessLayoutKind::Otx);
+        assert_eq!(detect_witness_layout(&[]), WitnessLayoutKind::Empty);
+        assert_eq!(
+            detect_witness_layout(WitnessArgs::default().as_slice()),
+            WitnessLayoutKind::WitnessArgs
+        );
+        assert_eq!(
+            detect_witness_layout(&[1, 2, 3, 4, 5]),
+            WitnessLayoutKind::Unknown
+        );
+        // a truncated cobuild witness is not detected
+        assert_eq!(
+            detect_witness_layout(&witness[..witness.len() - 1]),
+            WitnessLayoutKind::Unknown
+        );
+    }
+
+    #[test]
+    fn test_negotiate_witness_layout() {
+        let legacy = WitnessLayout::WitnessArgs.placeholder_witness(65);
+        let cobuild = WitnessLayout::Cobuild.placeholder_witness(65);
+        assert_eq!(
+            detect_witness_layout(&cobuild),
+            WitnessLayoutKind::SighashAllOnly
+        );
+        let all = &WitnessLayout::ALL[..];
+        let legacy_only = &[WitnessLayout::WitnessArgs][..];
+        assert_eq!(
+            negotiate_witness_layout(&legacy, all, WitnessLayout::Cobuild),
+            Ok(WitnessLayout::WitnessArgs)
+        );
+        assert_eq!(
+            negotiate_witness_layout(&cobuild, all, WitnessLayout::WitnessArgs),
+            Ok(WitnessLayout::Cobuild)
+        );
+        assert_eq!(
+            negotiate_witness_layout(&cobuild, legacy_only, WitnessLayout::WitnessArgs),
+            Err(WitnessLayoutKind::SighashAllOnly)
+        );
+        assert_eq!(
+            negotiate_witness_layout(&[], all, WitnessLayout::Cobuild),
+            Ok(WitnessLayout::Cobuild)
+        );
+        assert_eq!(
+            negotiate_witness_layout(&[], legacy_only, WitnessLayout::Cobuild),
+            Ok(WitnessLayout::WitnessArgs)
+        );
+        let mut otx = OTX_LAYOUT_ID.to_le_bytes().to_vec();
+        otx.extend_from_slice(&encode_table(&[]));
+        assert_eq!(
+            negotiate_witness_layout(&otx, all, WitnessLayout::Cobuild),
+            Err(WitnessLayoutKind::Otx)
+        );
+        assert_eq!(WitnessLayout::from_version(2), Some(WitnessLayout::Cobuild));
+        assert_eq!(WitnessLayout::from_version(3), None);
+    }
+
+    #[test]
+    fn test_cobuild_signing_message() {
+        let tx = TransactionView::new_advanced_builder().build();
+        let with_message = cobuild_signing_message(&tx, Some(&[]), &[]);
+        let without_message = cobuild_signing_message(&tx, None, &[]);
+        assert_ne!(with_message, without_message);
+    }
+}
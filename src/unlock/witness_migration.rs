@@ -0,0 +1,350 @@
+//! Move a partially signed transaction between the witness layouts.
+//!
+//! A [`WitnessMigrator`] rewrites the witnesses of the lock script groups
+//! from `WitnessArgs` to cobuild `SighashAllOnly`, or back when the cobuild
+//! witness has no message. The signing message of every group is computed
+//! before and after the rewrite, a signature is kept when its message is
+//! unchanged, otherwise it is replaced by the placeholder of the group and
+//! the group is reported as [`MigrationStatus::ResignRequired`].
+
+use std::collections::HashMap;
+
+use ckb_types::{
+    bytes::Bytes,
+    core::TransactionView,
+    packed::{self, CellOutput, Script, WitnessArgs},
+    prelude::*,
+    H256,
+};
+use thiserror::Error;
+
+use super::{
+    cobuild_signing_message, detect_witness_layout, generate_message, CobuildSighashWitness,
+    UnlockError, WitnessLayout, WitnessLayoutKind,
+};
+use crate::traits::TransactionDependencyProvider;
+use crate::tx_builder::gen_script_groups;
+use crate::ScriptGroup;
+
+#[derive(Error, Debug)]
+pub enum WitnessMigrationError {
+    #[error("unlock error: `{0}`")]
+    Unlock(#[from] UnlockError),
+
+    #[error("the witness `{0}` has the layout `{1:?}`, it can not be migrated")]
+    UnsupportedLayout(usize, WitnessLayoutKind),
+
+    #[error("the witness `{0}` has an input_type or output_type, cobuild has no place for them")]
+    TypeFieldsNotMigratable(usize),
+
+    #[error("the witness `{0}` has a cobuild message, WitnessArgs has no place for it")]
+    MessageNotMigratable(usize),
+}
+
+/// What the migration did to a lock script group
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum MigrationStatus {
+    /// The witness is kept, its signature (if any) is still valid
+    Unchanged,
+    /// The witness is converted, the group was not signed yet
+    Converted,
+    /// The witness is converted and its signature is still valid
+    Preserved,
+    /// The signature is replaced by the placeholder, sign the group again
+    ResignRequired,
+}
+
+/// The migration of a lock script group
+#[derive(Debug, Clone)]
+pub struct GroupMigration {
+    pub script_group: ScriptGroup,
+    /// The witness of the first input of the group
+    pub witness_index: usize,
+    /// The layout before the migration, `None` for an empty or unsupported
+    /// witness
+    pub from: Option<WitnessLayout>,
+    pub to: Option<WitnessLayout>,
+    pub status: MigrationStatus,
+}
+
+/// The lock script groups of a migrated transaction, sorted by witness index
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    pub groups: Vec<GroupMigration>,
+}
+
+impl MigrationReport {
+    /// The groups to sign again
+    pub fn resign_required(&self) -> Vec<&ScriptGroup> {
+        self.groups
+            .iter()
+            .filter(|group| group.status == MigrationStatus::ResignRequired)
+            .map(|group| &group.script_group)
+            .collect()
+    }
+
+    /// Whether all the collected signatures are still valid
+    pub fn signatures_preserved(&self) -> bool {
+        self.groups
+            .iter()
+            .all(|group| group.status != MigrationStatus::ResignRequired)
+    }
+}
+
+/// Rewrite the witnesses of the lock script groups into `target` layout
+#[derive(Debug, Clone)]
+pub struct WitnessMigrator {
+    target: WitnessLayout,
+    /// Only migrate the groups of these lock scripts, all when `None`
+    scripts: Option<Vec<Script>>,
+    /// The placeholder of a signature by lock script hash
+    placeholders: HashMap<H256, Bytes>,
+}
+
+impl WitnessMigrator {
+    pub fn new(target: WitnessLayout) -> WitnessMigrator {
+        WitnessMigrator {
+            target,
+            scripts: None,
+            placeholders: HashMap::new(),
+        }
+    }
+
+    /// Only migrate the groups of `scripts`, the other groups are kept
+    pub fn only(mut self, scripts: Vec<Script>) -> Self {
+        self.scripts = Some(scripts);
+        self
+    }
+
+    /// The placeholder replacing a dropped signature of `lock`, e.g. the
+    /// multisig config followed by the zeroed signatures. Zeros of the
+    /// signature length by default.
+    pub fn with_placeholder(mut self, lock: &Script, placeholder: Bytes) -> Self {
+        self.placeholders
+            .insert(lock.calc_script_hash().unpack(), placeholder);
+        self
+    }
+
+    /// Migrate the witnesses of `tx`, the input cells are loaded from
+    /// `tx_dep_provider` for the cobuild signing messages.
+    pub fn migrate(
+        &self,
+        tx: &TransactionView,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<(TransactionView, MigrationReport), WitnessMigrationError> {
+        let mut groups = gen_script_groups(tx, tx_dep_provider)
+            .map_err(UnlockError::from)?
+            .lock_groups
+            .into_values()
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|group| group.input_indices[0]);
+        let input_cells = tx_dep_provider
+            .get_cells_with_data(&tx.input_pts_iter().collect::<Vec<_>>())
+            .map_err(UnlockError::from)?;
+
+        let mut witnesses = tx.witnesses().into_iter().collect::<Vec<_>>();
+        let mut migrations = Vec::with_capacity(groups.len());
+        let mut messages_before = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let witness_index = group.input_indices[0];
+            let witness = witness_data(&witnesses, witness_index);
+            let from = match WitnessLayout::detect(&witness) {
+                Ok(layout) => layout,
+                Err(kind) if self.is_selected(group) => {
+                    return Err(WitnessMigrationError::UnsupportedLayout(
+                        witness_index,
+                        kind,
+                    ));
+                }
+                Err(_) => None,
+            };
+            messages_before.push(self.signing_message(tx, group, &input_cells)?);
+            let to = match from {
+                Some(layout) if layout != self.target && self.is_selected(group) => {
+                    let converted = convert_witness(&witness, witness_index, self.target)?;
+                    witnesses[witness_index] = converted.pack();
+                    Some(self.target)
+                }
+                layout => layout,
+            };
+            migrations.push(GroupMigration {
+                script_group: group.clone(),
+                witness_index,
+                from,
+                to,
+                status: MigrationStatus::Unchanged,
+            });
+        }
+
+        let migrated_tx = tx
+            .as_advanced_builder()
+            .set_witnesses(witnesses.clone())
+            .build();
+        for (migration, message_before) in migrations.iter_mut().zip(messages_before) {
+            let witness_index = migration.witness_index;
+            let witness = witness_data(&witnesses, witness_index);
+            let converted = migration.from != migration.to;
+            let signed = match seal(&witness) {
+                Some(seal) => !self.is_placeholder(&migration.script_group, &seal),
+                None => false,
+            };
+            let message_after =
+                self.signing_message(&migrated_tx, &migration.script_group, &input_cells)?;
+            migration.status = match (signed, message_after == message_before) {
+                (false, _) if converted => MigrationStatus::Converted,
+                (true, true) if converted => MigrationStatus::Preserved,
+                (true, false) => {
+                    let placeholder = self.placeholder(&migration.script_group, &witness);
+                    witnesses[witness_index] = replace_seal(&witness, placeholder).pack();
+                    MigrationStatus::ResignRequired
+                }
+                _ => MigrationStatus::Unchanged,
+            };
+        }
+
+        let migrated_tx = tx.as_advanced_builder().set_witnesses(witnesses).build();
+        Ok((migrated_tx, MigrationReport { groups: migrations }))
+    }
+
+    fn is_selected(&self, group: &ScriptGroup) -> bool {
+        self.scripts
+            .as_ref()
+            .map_or(true, |scripts| scripts.contains(&group.script))
+    }
+
+    /// The configured placeholder of the group, or zeros as long as the
+    /// current seal of `witness`
+    fn placeholder(&self, group: &ScriptGroup, witness: &[u8]) -> Bytes {
+        let lock_hash: H256 = group.script.calc_script_hash().unpack();
+        match self.placeholders.get(&lock_hash) {
+            Some(placeholder) => placeholder.clone(),
+            None => Bytes::from(vec![0u8; seal(witness).map_or(0, |seal| seal.len())]),
+        }
+    }
+
+    fn is_placeholder(&self, group: &ScriptGroup, seal: &[u8]) -> bool {
+        let lock_hash: H256 = group.script.calc_script_hash().unpack();
+        seal.is_empty()
+            || seal.iter().all(|byte| *byte == 0)
+            || self
+                .placeholders
+                .get(&lock_hash)
+                .map_or(false, |placeholder| placeholder.as_ref() == seal)
+    }
+
+    /// The message the group signs in the layout of its witness, `None` for
+    /// an empty or unsupported witness
+    fn signing_message(
+        &self,
+        tx: &TransactionView,
+        group: &ScriptGroup,
+        input_cells: &[(CellOutput, Bytes)],
+    ) -> Result<Option<Bytes>, WitnessMigrationError> {
+        let witness = tx
+            .witnesses()
+            .get(group.input_indices[0])
+            .map(|witness| witness.raw_data())
+            .unwrap_or_default();
+        let message = match WitnessLayout::detect(&witness) {
+            Ok(Some(WitnessLayout::WitnessArgs)) => {
+                let zero_lock = self.placeholder(group, &witness);
+                Some(generate_message(tx, group, zero_lock).map_err(UnlockError::from)?)
+            }
+            Ok(Some(WitnessLayout::Cobuild)) => {
+                CobuildSighashWitness::parse(&witness).map(|witness| {
+                    let message =
+                        cobuild_signing_message(tx, witness.message.as_deref(), input_cells);
+                    Bytes::from(message.to_vec())
+                })
+            }
+            _ => None,
+        };
+        Ok(message)
+    }
+}
+
+fn witness_data(witnesses: &[packed::Bytes], index: usize) -> Bytes {
+    witnesses
+        .get(index)
+        .map(|witness| witness.raw_data())
+        .unwrap_or_default()
+}
+
+/// The signature field of a `WitnessArgs` or cobuild witness
+fn seal(witness: &[u8]) -> Option<Bytes> {
+    match WitnessLayout::detect(witness) {
+        Ok(Some(WitnessLayout::WitnessArgs)) => WitnessArgs::from_slice(witness)
+            .ok()?
+            .lock()
+            .to_opt()
+            .map(|lock| lock.raw_data()),
+        Ok(Some(WitnessLayout::Cobuild)) => {
+            CobuildSighashWitness::parse(witness).map(|witness| witness.seal)
+        }
+        _ => None,
+    }
+}
+
+fn replace_seal(witness: &[u8], seal: Bytes) -> Bytes {
+    match WitnessLayout::detect(witness) {
+        Ok(Some(WitnessLayout::WitnessArgs)) => WitnessArgs::from_slice(witness)
+            .map(|witness_args| {
+                witness_args
+                    .as_builder()
+                    .lock(Some(seal).pack())
+                    .build()
+                    .as_bytes()
+            })
+            .unwrap_or_else(|_| Bytes::from(witness.to_vec())),
+        Ok(Some(WitnessLayout::Cobuild)) => match CobuildSighashWitness::parse(witness) {
+            Some(cobuild_witness) => CobuildSighashWitness {
+                seal,
+                ..cobuild_witness
+            }
+            .to_bytes(),
+            None => Bytes::from(witness.to_vec()),
+        },
+        _ => Bytes::from(witness.to_vec()),
+    }
+}
+
+/// Convert a `WitnessArgs` or cobuild witness into `target`, the seal is
+/// moved as is.
+fn convert_witness(
+    witness: &[u8],
+    witness_index: usize,
+    target: WitnessLayout,
+) -> Result<Bytes, WitnessMigrationError> {
+    match target {
+        WitnessLayout::Cobuild => {
+            let witness_args = WitnessArgs::from_slice(witness)
+                .map_err(|_| UnlockError::InvalidWitnessArgs(witness_index))?;
+            if witness_args.input_type().is_some() || witness_args.output_type().is_some() {
+                return Err(WitnessMigrationError::TypeFieldsNotMigratable(
+                    witness_index,
+                ));
+            }
+            let seal = witness_args
+                .lock()
+                .to_opt()
+                .map(|lock| lock.raw_data())
+                .unwrap_or_default();
+            Ok(CobuildSighashWitness::new_sighash_all_only(seal).to_bytes())
+        }
+        WitnessLayout::WitnessArgs => {
+            let cobuild_witness = CobuildSighashWitness::parse(witness).ok_or(
+                WitnessMigrationError::UnsupportedLayout(
+                    witness_index,
+                    detect_witness_layout(witness),
+                ),
+            )?;
+            if cobuild_witness.message.is_some() {
+                return Err(WitnessMigrationError::MessageNotMigratable(witness_index));
+            }
+            Ok(WitnessArgs::new_builder()
+                .lock(Some(cobuild_witness.seal).pack())
+                .build()
+                .as_bytes())
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Derive the omnilock auth content of the foreign chain identities.
+//!
+//! The auth content of an Ethereum or Tron identity is the last 20 bytes of
+//! the keccak256 hash of the uncompressed public key, which is also the
+//! address. The one of a Bitcoin or Dogecoin identity is the hash160
+//! (`ripemd160(sha256(pubkey))`) of the compressed or uncompressed public key.
+//! The helpers derive it from a public key or parse it from an address.
+
+use std::str::FromStr;
+
+use bech32::{FromBase32, Variant};
+use ckb_types::H160;
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use thiserror::Error;
+
+use super::IdentityFlag;
+use crate::util::keccak160;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The version bytes of the Bitcoin P2PKH addresses, mainnet and testnet
+const BITCOIN_P2PKH_VERSIONS: [u8; 2] = [0x00, 0x6f];
+/// The version bytes of the Dogecoin P2PKH addresses, mainnet and testnet
+const DOGECOIN_P2PKH_VERSIONS: [u8; 2] = [0x1e, 0x71];
+/// The prefix byte of the Tron addresses
+const TRON_ADDRESS_PREFIX: u8 = 0x41;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum XChainAddressError {
+    #[error("invalid hex address: `{0}`")]
+    InvalidHex(String),
+
+    #[error("invalid base58 address: `{0}`")]
+    InvalidBase58(String),
+
+    #[error("invalid bech32 address: `{0}`")]
+    InvalidBech32(String),
+
+    #[error("checksum mismatch: `{0}`")]
+    ChecksumMismatch(String),
+
+    #[error("the address does not hold a public key hash: `{0}`")]
+    UnsupportedAddress(String),
+
+    #[error("the identity flag has no foreign chain address: `{0:?}`")]
+    UnsupportedFlag(IdentityFlag),
+}
+
+/// How a Bitcoin style public key is serialized before it is hashed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum BitcoinPubkeyFormat {
+    /// 33 bytes, used by the P2PKH addresses of the modern wallets and by
+    /// all the segwit addresses
+    Compressed,
+    /// 65 bytes, used by the legacy P2PKH addresses
+    Uncompressed,
+}
+
+/// `ripemd160(sha256(data))`
+pub fn hash160(data: &[u8]) -> H160 {
+    let sha256 = Sha256::digest(data);
+    H160::from_slice(Ripemd160::digest(sha256.as_slice()).as_slice()).unwrap()
+}
+
+/// The auth content of an Ethereum or Tron identity
+pub fn ethereum_auth_content(pubkey: &secp256k1::PublicKey) -> H160 {
+    keccak160(&pubkey.serialize_uncompressed()[1..])
+}
+
+/// The auth content of a Bitcoin or Dogecoin identity
+pub fn bitcoin_auth_content(pubkey: &secp256k1::PublicKey, format: BitcoinPubkeyFormat) -> H160 {
+    match format {
+        BitcoinPubkeyFormat::Compressed => hash160(&pubkey.serialize()),
+        BitcoinPubkeyFormat::Uncompressed => hash160(&pubkey.serialize_uncompressed()),
+    }
+}
+
+/// Parse a `0x` prefixed Ethereum address, the EIP-55 checksum of a mixed
+/// case address is checked.
+pub fn ethereum_auth_content_from_address(address: &str) -> Result<H160, XChainAddressError> {
+    let invalid = || XChainAddressError::InvalidHex(address.to_string());
+    let hex = address.strip_prefix("0x").ok_or_else(invalid)?;
+    if hex.len() != 40 {
+        return Err(invalid());
+    }
+    let lower = hex.to_ascii_lowercase();
+    let auth_content = H160::from_str(&lower).map_err(|_| invalid())?;
+    let has_upper = hex.bytes().any(|c| c.is_ascii_uppercase());
+    let has_lower = hex.bytes().any(|c| c.is_ascii_lowercase());
+    if has_upper && has_lower {
+        let hash = Keccak256::digest(lower.as_bytes());
+        for (idx, c) in hex.bytes().enumerate() {
+            let nibble = if idx % 2 == 0 {
+                hash[idx / 2] >> 4
+            } else {
+                hash[idx / 2] & 0x0f
+            };
+            if c.is_ascii_alphabetic() && c.is_ascii_uppercase() != (nibble >= 8) {
+                return Err(XChainAddressError::ChecksumMismatch(address.to_string()));
+            }
+        }
+    }
+    Ok(auth_content)
+}
+
+/// Parse a base58 Tron address (`T...`)
+pub fn tron_auth_content_from_address(address: &str) -> Result<H160, XChainAddressError> {
+    base58_pubkey_hash(address, &[TRON_ADDRESS_PREFIX])
+}
+
+/// Parse a P2PKH (`1...`, `m...`, `n...`) or P2WPKH (`bc1q...`, `tb1q...`)
+/// Bitcoin address. A P2SH or taproot address does not hold a public key
+/// hash and is rejected.
+pub fn bitcoin_auth_content_from_address(address: &str) -> Result<H160, XChainAddressError> {
+    let lower = address.to_ascii_lowercase();
+    if ["bc1", "tb1", "bcrt1"]
+        .iter()
+        .any(|hrp| lower.starts_with(hrp))
+    {
+        segwit_pubkey_hash(address)
+    } else {
+        base58_pubkey_hash(address, &BITCOIN_P2PKH_VERSIONS)
+    }
+}
+
+/// Parse a P2PKH Dogecoin address (`D...`, `n...`)
+pub fn dogecoin_auth_content_from_address(address: &str) -> Result<H160, XChainAddressError> {
+    base58_pubkey_hash(address, &DOGECOIN_P2PKH_VERSIONS)
+}
+
+/// The auth content of the identity `flag` owned by a foreign chain
+/// `address`
+pub fn xchain_auth_content(flag: IdentityFlag, address: &str) -> Result<H160, XChainAddressError> {
+    match flag {
+        IdentityFlag::Ethereum => ethereum_auth_content_from_address(address),
+        IdentityFlag::Tron => tron_auth_content_from_address(address),
+        IdentityFlag::Bitcoin => bitcoin_auth_content_from_address(address),
+        IdentityFlag::Dogecoin => dogecoin_auth_content_from_address(address),
+        flag => Err(XChainAddressError::UnsupportedFlag(flag)),
+    }
+}
+
+fn base58_pubkey_hash(address: &str, versions: &[u8]) -> Result<H160, XChainAddressError> {
+    let payload = decode_base58_check(address)?;
+    match payload.split_first() {
+        Some((version, hash)) if versions.contains(version) && hash.len() == 20 => {
+            Ok(H160::from_slice(hash).unwrap())
+        }
+        _ => Err(XChainAddressError::UnsupportedAddress(address.to_string())),
+    }
+}
+
+fn segwit_pubkey_hash(address: &str) -> Result<H160, XChainAddressError> {
+    let invalid = |err: bech32::Error| XChainAddressError::InvalidBech32(err.to_string());
+    let (_hrp, data, variant) = bech32::decode(address).map_err(invalid)?;
+    let (version, program) = data
+        .split_first()
+        .ok_or_else(|| XChainAddressError::UnsupportedAddress(address.to_string()))?;
+    let program = Vec::<u8>::from_base32(program).map_err(invalid)?;
+    // witness version 0 with a 20-byte program is P2WPKH
+    if version.to_u8() != 0 || variant != Variant::Bech32 || program.len() != 20 {
+        return Err(XChainAddressError::UnsupportedAddress(address.to_string()));
+    }
+    Ok(H160::from_slice(&program).unwrap())
+}
+
+/// Decode a base58 string and check its 4-byte double sha256 checksum
+fn decode_base58_check(address: &str) -> Result<Vec<u8>, XChainAddressError> {
+    let invalid = || XChainAddressError::InvalidBase58(address.to_string());
+    // big endian digits in base 256
+    let mut value: Vec<u8> = Vec::new();
+    for c in address.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|letter| *letter == c)
+            .ok_or_else(invalid)? as u32;
+        for byte in value.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            value.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let mut data = vec![0u8; address.bytes().take_while(|c| *c == b'1').count()];
+    data.extend(value);
+    if data.len() < 4 {
+        return Err(invalid());
+    }
+    let (payload, checksum) = data.split_at(data.len() - 4);
+    let hash = Sha256::digest(Sha256::digest(payload).as_slice());
+    if &hash[..4] != checksum {
+        return Err(XChainAddressError::ChecksumMismatch(address.to_string()));
+    }
+    Ok(payload.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SECP256K1;
+    use ckb_types::h160;
+
+    fn pubkey() -> secp256k1::PublicKey {
+        let mut key = [0u8; 32];
+        key[31] = 1;
+        let key = secp256k1::SecretKey::from_slice(&key).unwrap();
+        secp256k1::PublicKey::from_secret_key(&SECP256K1, &key)
+    }
+
+    #[test]
+    fn test_xchain_auth_content() {
+        // the addresses of the private key 1
+        let eth = h160!("0x7e5f4552091a69125d5dfcb7b8c2659029395bdf");
+        assert_eq!(ethereum_auth_content(&pubkey()), eth);
+        for address in [
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf",
+            "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf",
+        ] {
+            assert_eq!(ethereum_auth_content_from_address(address), Ok(eth.clone()));
+        }
+        assert_eq!(
+            ethereum_auth_content_from_address("0x7E5F4552091A69125d5DfCb7b8C2659029395BdF"),
+            Err(XChainAddressError::ChecksumMismatch(
+                "0x7E5F4552091A69125d5DfCb7b8C2659029395BdF".to_string()
+            ))
+        );
+        assert_eq!(
+            xchain_auth_content(IdentityFlag::Tron, "TMVQGm1qAQYVdetCeGRRkTWYYrLXuHK2HC"),
+            Ok(eth)
+        );
+
+        let compressed = h160!("0x751e76e8199196d454941c45d1b3a323f1433bd6");
+        let uncompressed = h160!("0x91b24bf9f5288532960ac687abb035127b1d28a5");
+        assert_eq!(
+            bitcoin_auth_content(&pubkey(), BitcoinPubkeyFormat::Compressed),
+            compressed
+        );
+        assert_eq!(
+            bitcoin_auth_content(&pubkey(), BitcoinPubkeyFormat::Uncompressed),
+            uncompressed
+        );
+        for (address, expected) in [
+            ("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", &compressed),
+            ("1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm", &uncompressed),
+            ("mrCDrCybB6J1vRfbwM5hemdJz73FwDBC8r", &compressed),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", &compressed),
+        ] {
+            assert_eq!(
+                xchain_auth_content(IdentityFlag::Bitcoin, address).as_ref(),
+                Ok(expected)
+            );
+        }
+        assert_eq!(
+            xchain_auth_content(IdentityFlag::Dogecoin, "DFpN6QqFfUm3gKNaxN6tNcab1FArL9cZLE"),
+            Ok(compressed)
+        );
+
+        // a Dogecoin address is not a Bitcoin address
+        assert!(matches!(
+            bitcoin_auth_content_from_address("DFpN6QqFfUm3gKNaxN6tNcab1FArL9cZLE"),
+            Err(XChainAddressError::UnsupportedAddress(_))
+        ));
+        assert!(matches!(
+            bitcoin_auth_content_from_address("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMh"),
+            Err(XChainAddressError::ChecksumMismatch(_))
+        ));
+        assert!(matches!(
+            tron_auth_content_from_address("T0VQGm1qAQYVdetCeGRRkTWYYrLXuHK2HC"),
+            Err(XChainAddressError::InvalidBase58(_))
+        ));
+        assert_eq!(
+            xchain_auth_content(IdentityFlag::OwnerLock, ""),
+            Err(XChainAddressError::UnsupportedFlag(IdentityFlag::OwnerLock))
+        );
+    }
+}
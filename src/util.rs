@@ -1,6 +1,7 @@
-use std::{convert::TryInto, ptr, sync::atomic};
+#[cfg(feature = "rpc")]
+use std::convert::TryInto;
+use std::{ptr, sync::atomic};
 
-use ckb_dao_utils::extract_dao_data;
 use ckb_types::{
     core::{Capacity, EpochNumber, EpochNumberWithFraction, HeaderView},
     packed::CellOutput,
@@ -8,7 +9,10 @@ use ckb_types::{
     H160, H256, U256,
 };
 use sha3::{Digest, Keccak256};
+use thiserror::Error;
 
+use crate::dao_stats::DaoStats;
+#[cfg(feature = "rpc")]
 use crate::rpc::CkbRpcClient;
 use crate::traits::LiveCell;
 
@@ -29,6 +33,7 @@ pub fn zeroize_slice(data: &mut [u8]) {
     }
 }
 
+#[cfg(feature = "rpc")]
 pub fn get_max_mature_number(rpc_client: &CkbRpcClient) -> Result<u64, String> {
     let cellbase_maturity = EpochNumberWithFraction::from_full_value(
         rpc_client
@@ -116,13 +121,40 @@ pub fn calculate_dao_maximum_withdraw4(
     output: &CellOutput,
     occupied_capacity: u64,
 ) -> u64 {
-    let (deposit_ar, _, _, _) = extract_dao_data(deposit_header.dao());
-    let (prepare_ar, _, _, _) = extract_dao_data(prepare_header.dao());
     let output_capacity: Capacity = output.capacity().unpack();
     let counted_capacity = output_capacity.as_u64() - occupied_capacity;
-    let withdraw_counted_capacity =
-        u128::from(counted_capacity) * u128::from(prepare_ar) / u128::from(deposit_ar);
-    occupied_capacity + withdraw_counted_capacity as u64
+    let withdraw_counted_capacity = DaoStats::from_header(deposit_header)
+        .grow(&DaoStats::from_header(prepare_header), counted_capacity);
+    occupied_capacity + withdraw_counted_capacity
+}
+
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CellDataError {
+    #[error("invalid udt cell data length, expected: >= 16, got: {0}")]
+    InvalidUdtData(usize),
+
+    #[error("invalid dao cell data length, expected: 8, got: {0}")]
+    InvalidDaoData(usize),
+}
+
+/// Read the UDT amount (u128 LE) from the first 16 bytes of the cell data
+pub fn parse_udt_amount(data: &[u8]) -> Result<u128, CellDataError> {
+    let bytes = data
+        .get(0..16)
+        .ok_or(CellDataError::InvalidUdtData(data.len()))?;
+    let mut amount_bytes = [0u8; 16];
+    amount_bytes.copy_from_slice(bytes);
+    Ok(u128::from_le_bytes(amount_bytes))
+}
+
+/// Read the deposit block number (u64 LE) from the data of a DAO prepared cell
+pub fn parse_dao_deposit_number(data: &[u8]) -> Result<u64, CellDataError> {
+    if data.len() != 8 {
+        return Err(CellDataError::InvalidDaoData(data.len()));
+    }
+    let mut number_bytes = [0u8; 8];
+    number_bytes.copy_from_slice(data);
+    Ok(u64::from_le_bytes(number_bytes))
 }
 
 pub fn serialize_signature(signature: &secp256k1::ecdsa::RecoverableSignature) -> [u8; 65] {
@@ -256,6 +288,7 @@ mod tests {
         assert_eq!(result, 100_000_000_009_999);
     }
 
+    #[cfg(feature = "rpc")]
     #[test]
     fn test_get_max_mature_number() {
         {
@@ -381,4 +414,23 @@ mod tests {
             assert_eq!(151500, get_max_mature_number(&rpc_client).unwrap());
         }
     }
+
+    #[test]
+    fn test_parse_cell_data() {
+        let mut data = 1000u128.to_le_bytes().to_vec();
+        assert_eq!(parse_udt_amount(&data), Ok(1000));
+        data.extend_from_slice(b"extra");
+        assert_eq!(parse_udt_amount(&data), Ok(1000));
+        assert_eq!(
+            parse_udt_amount(&data[0..15]),
+            Err(CellDataError::InvalidUdtData(15))
+        );
+        assert_eq!(parse_udt_amount(&[]), Err(CellDataError::InvalidUdtData(0)));
+
+        assert_eq!(parse_dao_deposit_number(&42u64.to_le_bytes()), Ok(42));
+        assert_eq!(
+            parse_dao_deposit_number(&[0u8; 9]),
+            Err(CellDataError::InvalidDaoData(9))
+        );
+    }
 }
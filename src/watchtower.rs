@@ -0,0 +1,285 @@
+//! Track cheque cells and DAO phase 1 cells, notify when they can be spent.
+//!
+//! A cheque cell can be withdrawn by its sender after the relative since
+//! [`CHEQUE_CELL_SINCE`], a DAO phase 1 (prepared) cell can be withdrawn after
+//! [`minimal_unlock_point`]. [`Watchtower`] computes those epochs once when a
+//! cell is tracked, then [`Watchtower::poll`] is called with the tip epoch,
+//! e.g. on every new block, and reports the cells whose window has opened. A
+//! [`WatchHandler`] registered for the kind of cell can build the transaction
+//! at the same time.
+
+use std::collections::HashMap;
+
+use ckb_types::{
+    core::{EpochNumberWithFraction, HeaderView, TransactionView},
+    packed::OutPoint,
+    prelude::*,
+};
+use thiserror::Error;
+
+use crate::constants::CHEQUE_CELL_SINCE;
+use crate::traits::{HeaderDepResolver, TransactionDependencyError, TransactionDependencyProvider};
+use crate::tx_builder::TxBuilderError;
+use crate::types::{Since, SinceType};
+use crate::util::minimal_unlock_point;
+
+#[derive(Error, Debug)]
+pub enum WatchtowerError {
+    #[error("header dependency resolver error: `{0}`")]
+    HeaderDep(anyhow::Error),
+
+    #[error("header not found: `{0}`")]
+    HeaderNotFound(String),
+
+    #[error("transaction dependency provider error: `{0}`")]
+    TxDep(#[from] TransactionDependencyError),
+
+    #[error("invalid dao phase 1 cell data, expected 8 bytes, got {0} bytes")]
+    InvalidDaoData(usize),
+
+    #[error("transaction builder error: `{0}`")]
+    TxBuilder(#[from] TxBuilderError),
+}
+
+/// What becomes possible when the window of a watched cell opens
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WatchKind {
+    /// The sender can withdraw the unclaimed cheque cell
+    ChequeWithdraw,
+    /// The owner can withdraw the prepared DAO cell (phase 2)
+    DaoWithdraw,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WatchedCell {
+    pub out_point: OutPoint,
+    pub kind: WatchKind,
+    /// The first epoch the cell can be spent
+    pub unlock_point: EpochNumberWithFraction,
+}
+
+impl WatchedCell {
+    pub fn is_unlocked(&self, tip_epoch: EpochNumberWithFraction) -> bool {
+        tip_epoch.to_rational() >= self.unlock_point.to_rational()
+    }
+}
+
+/// A watched cell whose window has opened
+#[derive(Debug)]
+pub struct Notification {
+    pub cell: WatchedCell,
+    /// The result of the handler registered for the kind of the cell, `None`
+    /// if there is no handler or it built nothing.
+    pub tx: Option<Result<TransactionView, WatchtowerError>>,
+}
+
+/// Build the transaction spending a watched cell once its window opens
+pub trait WatchHandler {
+    fn build(&mut self, cell: &WatchedCell) -> Result<Option<TransactionView>, WatchtowerError>;
+}
+
+impl<F> WatchHandler for F
+where
+    F: FnMut(&WatchedCell) -> Result<Option<TransactionView>, WatchtowerError>,
+{
+    fn build(&mut self, cell: &WatchedCell) -> Result<Option<TransactionView>, WatchtowerError> {
+        self(cell)
+    }
+}
+
+#[derive(Default)]
+pub struct Watchtower {
+    cells: Vec<WatchedCell>,
+    handlers: HashMap<WatchKind, Box<dyn WatchHandler>>,
+}
+
+impl Watchtower {
+    pub fn new() -> Watchtower {
+        Watchtower::default()
+    }
+
+    /// Register the handler of a kind of cell, replace the old one if any
+    pub fn register_handler(&mut self, kind: WatchKind, handler: Box<dyn WatchHandler>) {
+        self.handlers.insert(kind, handler);
+    }
+
+    /// Track a cell whose unlock point is already known
+    pub fn track(&mut self, cell: WatchedCell) {
+        self.untrack(&cell.out_point);
+        self.cells.push(cell);
+    }
+
+    /// Track a cheque cell, the unlock point is `CHEQUE_CELL_SINCE` after
+    /// the epoch of the block committing the cell.
+    pub fn track_cheque(
+        &mut self,
+        out_point: OutPoint,
+        header_dep_resolver: &dyn HeaderDepResolver,
+    ) -> Result<WatchedCell, WatchtowerError> {
+        let header = resolve_by_tx(header_dep_resolver, &out_point)?;
+        let unlock_point = relative_epoch_unlock_point(header.epoch(), CHEQUE_CELL_SINCE);
+        let cell = WatchedCell {
+            out_point,
+            kind: WatchKind::ChequeWithdraw,
+            unlock_point,
+        };
+        self.track(cell.clone());
+        Ok(cell)
+    }
+
+    /// Track a DAO phase 1 cell, the cell data is the deposit block number.
+    pub fn track_dao_prepare(
+        &mut self,
+        out_point: OutPoint,
+        header_dep_resolver: &dyn HeaderDepResolver,
+        tx_dep_provider: &dyn TransactionDependencyProvider,
+    ) -> Result<WatchedCell, WatchtowerError> {
+        let data = tx_dep_provider.get_cell_data(&out_point)?;
+        if data.len() != 8 {
+            return Err(WatchtowerError::InvalidDaoData(data.len()));
+        }
+        let mut number_bytes = [0u8; 8];
+        number_bytes.copy_from_slice(data.as_ref());
+        let deposit_number = u64::from_le_bytes(number_bytes);
+        let deposit_header = header_dep_resolver
+            .resolve_by_number(deposit_number)
+            .map_err(WatchtowerError::HeaderDep)?
+            .ok_or_else(|| {
+                WatchtowerError::HeaderNotFound(format!("block number {}", deposit_number))
+            })?;
+        let prepare_header = resolve_by_tx(header_dep_resolver, &out_point)?;
+        let cell = WatchedCell {
+            out_point,
+            kind: WatchKind::DaoWithdraw,
+            unlock_point: minimal_unlock_point(&deposit_header, &prepare_header),
+        };
+        self.track(cell.clone());
+        Ok(cell)
+    }
+
+    pub fn untrack(&mut self, out_point: &OutPoint) -> Option<WatchedCell> {
+        let idx = self
+            .cells
+            .iter()
+            .position(|cell| &cell.out_point == out_point)?;
+        Some(self.cells.remove(idx))
+    }
+
+    pub fn cells(&self) -> &[WatchedCell] {
+        &self.cells
+    }
+
+    /// The tracked cell which unlocks first
+    pub fn next_unlock(&self) -> Option<&WatchedCell> {
+        self.cells
+            .iter()
+            .min_by_key(|cell| cell.unlock_point.to_rational())
+    }
+
+    /// Report the cells unlocked at `tip_epoch` and run their handlers.
+    ///
+    /// A reported cell stops being tracked unless its handler failed, so it is
+    /// retried on the next poll.
+    pub fn poll(&mut self, tip_epoch: EpochNumberWithFraction) -> Vec<Notification> {
+        let (unlocked, locked): (Vec<_>, Vec<_>) = self
+            .cells
+            .drain(..)
+            .partition(|cell| cell.is_unlocked(tip_epoch));
+        self.cells = locked;
+        let mut notifications = Vec::with_capacity(unlocked.len());
+        for cell in unlocked {
+            let tx = self
+                .handlers
+                .get_mut(&cell.kind)
+                .and_then(|handler| handler.build(&cell).transpose());
+            if let Some(Err(_)) = tx {
+                self.cells.push(cell.clone());
+            }
+            notifications.push(Notification { cell, tx });
+        }
+        notifications
+    }
+}
+
+fn resolve_by_tx(
+    header_dep_resolver: &dyn HeaderDepResolver,
+    out_point: &OutPoint,
+) -> Result<HeaderView, WatchtowerError> {
+    let tx_hash = out_point.tx_hash();
+    header_dep_resolver
+        .resolve_by_tx(&tx_hash)
+        .map_err(WatchtowerError::HeaderDep)?
+        .ok_or_else(|| WatchtowerError::HeaderNotFound(format!("transaction {}", tx_hash)))
+}
+
+/// The unlock point of a relative epoch since, only the epoch number of the
+/// since value is taken into account.
+fn relative_epoch_unlock_point(
+    commit_point: EpochNumberWithFraction,
+    since: u64,
+) -> EpochNumberWithFraction {
+    let epochs = match Since::from_raw_value(since).extract_metric() {
+        Some((SinceType::EpochNumberWithFraction, value)) => {
+            EpochNumberWithFraction::from_full_value(value).number()
+        }
+        _ => 0,
+    };
+    EpochNumberWithFraction::new(
+        commit_point.number() + epochs,
+        commit_point.index(),
+        commit_point.length(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{core::HeaderBuilder, packed::Byte32};
+
+    struct SingleHeaderResolver(HeaderView);
+
+    impl HeaderDepResolver for SingleHeaderResolver {
+        fn resolve_by_tx(&self, _tx_hash: &Byte32) -> Result<Option<HeaderView>, anyhow::Error> {
+            Ok(Some(self.0.clone()))
+        }
+        fn resolve_by_number(&self, _number: u64) -> Result<Option<HeaderView>, anyhow::Error> {
+            Ok(Some(self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn test_watch_cheque_withdraw() {
+        let commit_point = EpochNumberWithFraction::new(10, 5, 100);
+        let resolver = SingleHeaderResolver(
+            HeaderBuilder::default()
+                .epoch(commit_point.full_value().pack())
+                .build(),
+        );
+        let out_point = OutPoint::new(Byte32::new([1u8; 32]), 0);
+        let mut watchtower = Watchtower::new();
+        let built = std::rc::Rc::new(std::cell::Cell::new(0));
+        let built_clone = built.clone();
+        watchtower.register_handler(
+            WatchKind::ChequeWithdraw,
+            Box::new(move |_cell: &WatchedCell| {
+                built_clone.set(built_clone.get() + 1);
+                Ok(Some(TransactionView::new_advanced_builder().build()))
+            }),
+        );
+        let cell = watchtower
+            .track_cheque(out_point.clone(), &resolver)
+            .unwrap();
+        assert_eq!(cell.unlock_point, EpochNumberWithFraction::new(16, 5, 100));
+        assert_eq!(watchtower.next_unlock(), Some(&cell));
+
+        assert!(watchtower
+            .poll(EpochNumberWithFraction::new(16, 4, 100))
+            .is_empty());
+        let notifications = watchtower.poll(EpochNumberWithFraction::new(16, 5, 100));
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].cell.out_point, out_point);
+        assert!(matches!(notifications[0].tx, Some(Ok(_))));
+        assert_eq!(built.get(), 1);
+        assert!(watchtower.cells().is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use ckb_sdk::{
+    devnet::sighash_unlockers,
+    tx_builder::{
+        dao::{DaoDepositBuilder, DaoDepositReceiver, DaoPrepareBuilder},
+        TxBuilder,
+    },
+};
+use ckb_types::packed::{CellInput, OutPoint};
+
+use crate::{harness, ONE_CKB, TIMEOUT};
+
+#[test]
+#[ignore = "needs a devnet, see tests/it/main.rs"]
+fn dao_deposit_and_prepare() {
+    let harness = harness();
+    let (key, lock) = harness.new_account(1000 * ONE_CKB, TIMEOUT).unwrap();
+    let unlockers = sighash_unlockers(vec![key]);
+    let balancer = harness.balancer(&key);
+
+    let builder = DaoDepositBuilder::new(vec![DaoDepositReceiver::new(lock, 200 * ONE_CKB)]);
+    let (deposit_tx, locked_groups) = builder
+        .build_unlocked(
+            &mut harness.cell_collector(),
+            &harness.scripts.cell_dep_resolver,
+            &harness.header_dep_resolver(),
+            &harness.tx_dep_provider(),
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    let deposit_hash = harness.send(&deposit_tx).unwrap();
+    harness.wait_committed(&deposit_hash, TIMEOUT).unwrap();
+
+    let deposit_input = CellInput::new(OutPoint::new(deposit_tx.hash(), 0), 0);
+    let builder = DaoPrepareBuilder::from(vec![deposit_input]);
+    let (prepare_tx, locked_groups) = builder
+        .build_unlocked(
+            &mut harness.cell_collector(),
+            &harness.scripts.cell_dep_resolver,
+            &harness.header_dep_resolver(),
+            &harness.tx_dep_provider(),
+            &balancer,
+            &unlockers,
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(prepare_tx.header_deps().len(), 1);
+    let prepare_hash = harness.send(&prepare_tx).unwrap();
+    harness.wait_committed(&prepare_hash, TIMEOUT).unwrap();
+}
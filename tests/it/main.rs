@@ -0,0 +1,26 @@
+//! End-to-end tests against a devnet.
+//!
+//! The tests are ignored by default, they need a devnet node with the
+//! `Indexer` and `IntegrationTest` rpc modules, e.g. the one of
+//! `tests/it/docker-compose.yml`:
+//!
+//! ```text
+//! docker compose -f tests/it/docker-compose.yml up -d
+//! CKB_DEVNET_RPC_URL=http://127.0.0.1:8114 make integration
+//! ```
+
+mod dao;
+mod transfer;
+
+use std::time::Duration;
+
+use ckb_sdk::devnet::{DevnetHarness, DEVNET_RPC_URL_ENV};
+
+pub const ONE_CKB: u64 = 100_000_000;
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn harness() -> DevnetHarness {
+    DevnetHarness::from_env()
+        .unwrap_or_else(|| panic!("{} is not set", DEVNET_RPC_URL_ENV))
+        .expect("attach to the devnet")
+}
@@ -0,0 +1,84 @@
+use ckb_sdk::{
+    devnet::{multisig_lock, multisig_unlockers, sighash_unlockers},
+    tx_builder::{transfer::CapacityTransferBuilder, unlock_tx, CapacityBalancer, TxBuilder},
+    unlock::MultisigConfig,
+    util::blake160,
+    SECP256K1,
+};
+use ckb_types::{bytes::Bytes, packed::CellOutput, prelude::*};
+
+use crate::{harness, ONE_CKB, TIMEOUT};
+
+#[test]
+#[ignore = "needs a devnet, see tests/it/main.rs"]
+fn transfer_from_sighash() {
+    let harness = harness();
+    let (sender_key, sender) = harness.new_account(1000 * ONE_CKB, TIMEOUT).unwrap();
+    let (_, receiver) = harness.new_account(100 * ONE_CKB, TIMEOUT).unwrap();
+
+    let output = CellOutput::new_builder()
+        .capacity((300 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let (tx, locked_groups) = builder
+        .build_unlocked(
+            &mut harness.cell_collector(),
+            &harness.scripts.cell_dep_resolver,
+            &harness.header_dep_resolver(),
+            &harness.tx_dep_provider(),
+            &harness.balancer(&sender_key),
+            &sighash_unlockers(vec![sender_key]),
+        )
+        .unwrap();
+    assert!(locked_groups.is_empty());
+    assert_eq!(tx.output(1).unwrap().lock(), sender);
+    let tx_hash = harness.send(&tx).unwrap();
+    harness.wait_committed(&tx_hash, TIMEOUT).unwrap();
+}
+
+#[test]
+#[ignore = "needs a devnet, see tests/it/main.rs"]
+fn transfer_from_multisig() {
+    let harness = harness();
+    let (key0, _) = harness.new_account(100 * ONE_CKB, TIMEOUT).unwrap();
+    let (key1, _) = harness.new_account(100 * ONE_CKB, TIMEOUT).unwrap();
+    let (key2, receiver) = harness.new_account(100 * ONE_CKB, TIMEOUT).unwrap();
+    let lock_args = [key0, key1, key2]
+        .iter()
+        .map(|key| blake160(&secp256k1::PublicKey::from_secret_key(&SECP256K1, key).serialize()))
+        .collect();
+    let config = MultisigConfig::new_with(lock_args, 0, 2).unwrap();
+    let sender = multisig_lock(&config);
+    let fund_hash = harness.fund(&sender, 1000 * ONE_CKB).unwrap();
+    harness.wait_committed(&fund_hash, TIMEOUT).unwrap();
+
+    let output = CellOutput::new_builder()
+        .capacity((300 * ONE_CKB).pack())
+        .lock(receiver)
+        .build();
+    let builder = CapacityTransferBuilder::new(vec![(output, Bytes::default())]);
+    let balancer =
+        CapacityBalancer::new_simple(sender, config.placeholder_witness(), harness.fee_rate());
+    let tx_dep_provider = harness.tx_dep_provider();
+    let mut tx = builder
+        .build_balanced(
+            &mut harness.cell_collector(),
+            &harness.scripts.cell_dep_resolver,
+            &harness.header_dep_resolver(),
+            &tx_dep_provider,
+            &balancer,
+            &multisig_unlockers(vec![key0], config.clone()),
+        )
+        .unwrap();
+    let mut locked_groups = Vec::new();
+    for key in [key0, key2] {
+        let unlockers = multisig_unlockers(vec![key], config.clone());
+        let (new_tx, new_locked_groups) = unlock_tx(tx, &tx_dep_provider, &unlockers).unwrap();
+        tx = new_tx;
+        locked_groups = new_locked_groups;
+    }
+    assert!(locked_groups.is_empty());
+    let tx_hash = harness.send(&tx).unwrap();
+    harness.wait_committed(&tx_hash, TIMEOUT).unwrap();
+}